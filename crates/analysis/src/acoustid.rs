@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use mogbox_io::http;
+
+use crate::fingerprint::Fingerprint;
+use crate::json::Json;
+
+/// AcoustID's fingerprint lookup endpoint
+const LOOKUP_URL: &str = "https://api.acoustid.org/v2/lookup";
+/// Longest a lookup may take, in seconds
+const LOOKUP_TIMEOUT: u32 = 30;
+
+/// A MusicBrainz recording whose fingerprint matches
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    /// How closely the fingerprints match, 0.0 to 1.0
+    pub score: f32,
+    /// AcoustID track id
+    pub acoustid: String,
+    /// MusicBrainz recording id
+    pub recording: String,
+    pub title: Option<String>,
+    /// Artist credit, e.g. `Artist feat. Other`
+    pub artist: Option<String>,
+    /// Title and MusicBrainz id of a release group the recording is on,
+    /// preferring albums
+    pub album: Option<(String, String)>,
+    pub duration: Option<Duration>,
+}
+
+impl Candidate {
+    /// Tag fields for the recording, as Vorbis comment names
+    pub fn tags(&self) -> Vec<(&'static str, String)> {
+        let mut tags = Vec::new();
+        if let Some(title) = &self.title {
+            tags.push(("TITLE", title.clone()));
+        }
+        if let Some(artist) = &self.artist {
+            tags.push(("ARTIST", artist.clone()));
+        }
+        if let Some((album, id)) = &self.album {
+            tags.push(("ALBUM", album.clone()));
+            tags.push(("MUSICBRAINZ_RELEASEGROUPID", id.clone()));
+        }
+        tags.push(("MUSICBRAINZ_TRACKID", self.recording.clone()));
+        tags.push(("ACOUSTID_ID", self.acoustid.clone()));
+        tags
+    }
+}
+
+/// Looks `fingerprint` up on AcoustID and returns the MusicBrainz recordings
+/// it matches, best first. Needs an AcoustID application key. The request
+/// goes through the `curl` command.
+pub fn lookup(fingerprint: &Fingerprint, api_key: &str) -> Result<Vec<Candidate>, String> {
+    let output = http::curl()?
+        .args(["--silent", "--show-error", "--compressed"])
+        .args(["--max-time", &LOOKUP_TIMEOUT.to_string()])
+        .args(["--data-urlencode", "format=json"])
+        .args(["--data-urlencode", &format!("client={}", api_key)])
+        .args([
+            "--data-urlencode",
+            &format!("duration={}", fingerprint.duration.as_secs()),
+        ])
+        .args([
+            "--data-urlencode",
+            &format!("fingerprint={}", fingerprint.encode()),
+        ])
+        .args(["--data-urlencode", "meta=recordings releasegroups"])
+        .arg(LOOKUP_URL)
+        .output()
+        .map_err(http::curl_error)?;
+    if !output.status.success() {
+        return Err(format!(
+            "AcoustID request failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let response = Json::parse(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|e| format!("failed to read AcoustID response: {}", e))?;
+    candidates(&response)
+}
+
+fn candidates(response: &Json) -> Result<Vec<Candidate>, String> {
+    if response.get("status").and_then(Json::as_str) != Some("ok") {
+        let message = response
+            .get("error")
+            .and_then(|error| error.get("message"))
+            .and_then(Json::as_str)
+            .unwrap_or("unknown error");
+        return Err(format!("AcoustID lookup failed: {}", message));
+    }
+
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for result in response.get("results").map(Json::items).unwrap_or_default() {
+        let (Some(acoustid), Some(score)) = (
+            result.get("id").and_then(Json::as_str),
+            result.get("score").and_then(Json::as_f64),
+        ) else {
+            continue;
+        };
+        for recording in result
+            .get("recordings")
+            .map(Json::items)
+            .unwrap_or_default()
+        {
+            let Some(id) = recording.get("id").and_then(Json::as_str) else {
+                continue;
+            };
+            // The same recording can come up under several AcoustIDs
+            if candidates.iter().any(|candidate| candidate.recording == id) {
+                continue;
+            }
+            let text = |key| {
+                recording
+                    .get(key)
+                    .and_then(Json::as_str)
+                    .map(str::to_string)
+            };
+            let artists = recording
+                .get("artists")
+                .map(Json::items)
+                .unwrap_or_default();
+            let artist = artists
+                .iter()
+                .filter_map(|artist| {
+                    let name = artist.get("name").and_then(Json::as_str)?;
+                    let join = artist.get("joinphrase").and_then(Json::as_str);
+                    Some(format!("{}{}", name, join.unwrap_or_default()))
+                })
+                .collect::<String>();
+            let groups = recording
+                .get("releasegroups")
+                .map(Json::items)
+                .unwrap_or_default();
+            let album = groups
+                .iter()
+                .find(|group| group.get("type").and_then(Json::as_str) == Some("Album"))
+                .or(groups.first())
+                .and_then(|group| {
+                    let title = group.get("title").and_then(Json::as_str)?;
+                    let id = group.get("id").and_then(Json::as_str)?;
+                    Some((title.to_string(), id.to_string()))
+                });
+            candidates.push(Candidate {
+                score: score as f32,
+                acoustid: acoustid.to_string(),
+                recording: id.to_string(),
+                title: text("title"),
+                artist: (!artist.is_empty()).then_some(artist),
+                album,
+                duration: recording
+                    .get("duration")
+                    .and_then(Json::as_f64)
+                    .filter(|seconds| *seconds >= 0.0)
+                    .map(Duration::from_secs_f64),
+            });
+        }
+    }
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(candidates)
+}
@@ -0,0 +1,77 @@
+use std::str::FromStr;
+
+/// Maps a level between 0.0 (quietest) and 1.0 (loudest) to a color
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Colormap {
+    /// Black through purple and orange to pale yellow
+    #[default]
+    Magma,
+    /// Dark blue through green to yellow
+    Viridis,
+    /// Black to white
+    Gray,
+}
+
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [28, 16, 68],
+    [79, 18, 123],
+    [129, 37, 129],
+    [181, 54, 122],
+    [229, 80, 100],
+    [251, 135, 97],
+    [254, 194, 135],
+    [252, 253, 191],
+];
+
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+
+impl Colormap {
+    pub fn color(&self, level: f32) -> [u8; 3] {
+        let level = if level.is_nan() {
+            0.0
+        } else {
+            level.clamp(0.0, 1.0)
+        };
+        let stops = match self {
+            Colormap::Magma => &MAGMA,
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Gray => {
+                let value = (level * 255.0).round() as u8;
+                return [value; 3];
+            }
+        };
+
+        let position = level * (stops.len() - 1) as f32;
+        let index = (position as usize).min(stops.len() - 2);
+        let t = position - index as f32;
+        let (from, to) = (stops[index], stops[index + 1]);
+        [0, 1, 2].map(|c| (from[c] as f32 + (to[c] as f32 - from[c] as f32) * t).round() as u8)
+    }
+}
+
+impl FromStr for Colormap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "magma" => Ok(Colormap::Magma),
+            "viridis" => Ok(Colormap::Viridis),
+            "gray" | "grey" => Ok(Colormap::Gray),
+            _ => Err(format!(
+                "invalid colormap: {} (expected magma, viridis or gray)",
+                s
+            )),
+        }
+    }
+}
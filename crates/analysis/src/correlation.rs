@@ -0,0 +1,109 @@
+use std::path::Path;
+use std::time::Duration;
+
+use mogbox_io::AudioFile;
+
+/// Stretch of audio each windowed correlation reading covers
+pub const CORRELATION_WINDOW: Duration = Duration::from_millis(400);
+
+/// Phase correlation between the first two channels: +1 for mono, 0 for
+/// unrelated channels and -1 for one channel inverted, which cancels out
+/// when folded to mono
+#[derive(Clone, Debug, Default)]
+pub struct CorrelationMeter {
+    /// Sums of left times right, left squared and right squared
+    lr: f64,
+    ll: f64,
+    rr: f64,
+}
+
+impl CorrelationMeter {
+    pub fn new() -> Self {
+        CorrelationMeter::default()
+    }
+
+    /// Adds interleaved `samples`; a single channel counts as both sides
+    pub fn process(&mut self, samples: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        for frame in samples.chunks_exact(channels) {
+            let left = frame[0] as f64;
+            let right = frame.get(1).copied().unwrap_or(frame[0]) as f64;
+            self.lr += left * right;
+            self.ll += left * left;
+            self.rr += right * right;
+        }
+    }
+
+    /// The correlation so far, or `None` while either side is silent
+    pub fn correlation(&self) -> Option<f32> {
+        let energy = (self.ll * self.rr).sqrt();
+        (energy > 0.0).then(|| (self.lr / energy).clamp(-1.0, 1.0) as f32)
+    }
+
+    pub fn reset(&mut self) {
+        *self = CorrelationMeter::default();
+    }
+}
+
+/// Phase correlation of a whole file
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorrelationReport {
+    /// Correlation over the whole file, `None` if either side is silent
+    pub overall: Option<f32>,
+    /// Lowest reading of any [`CORRELATION_WINDOW`]
+    pub minimum: Option<f32>,
+    /// Total length of the windows that read below zero
+    pub negative: Duration,
+    pub duration: Duration,
+}
+
+/// Measures the correlation between the first two channels of `path`
+pub fn correlation_report(path: &Path) -> Result<CorrelationReport, String> {
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let channels = file.channels.max(1) as usize;
+    if channels < 2 {
+        return Err("correlation needs at least two channels".to_string());
+    }
+    let sample_rate = file.sample_rate.max(1);
+    let window_frames = (CORRELATION_WINDOW.as_secs_f64() * sample_rate as f64).round() as usize;
+
+    let mut overall = CorrelationMeter::new();
+    let mut window = CorrelationMeter::new();
+    let mut window_len = 0;
+    let mut minimum: Option<f32> = None;
+    let mut negative_frames = 0u64;
+    let mut frames = 0u64;
+    let mut close_window = |window: &mut CorrelationMeter, len: usize| {
+        if let Some(correlation) = window.correlation() {
+            minimum = Some(minimum.map_or(correlation, |min| min.min(correlation)));
+            if correlation < 0.0 {
+                negative_frames += len as u64;
+            }
+        }
+        window.reset();
+    };
+
+    while let Some(samples) = file.next_samples()? {
+        overall.process(&samples, channels);
+        for frame in samples.chunks_exact(channels) {
+            window.process(frame, channels);
+            window_len += 1;
+            frames += 1;
+            if window_len == window_frames {
+                close_window(&mut window, window_len);
+                window_len = 0;
+            }
+        }
+    }
+    if window_len > 0 {
+        close_window(&mut window, window_len);
+    }
+
+    let seconds = |frames: u64| Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+    Ok(CorrelationReport {
+        overall: overall.correlation(),
+        minimum,
+        negative: seconds(negative_frames),
+        duration: seconds(frames),
+    })
+}
@@ -0,0 +1,462 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use mogbox_engine::{Resampler, ResamplerQuality};
+use mogbox_io::AudioFile;
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+
+use crate::window::Window;
+
+/// Sample rate, frame size and hop Chromaprint analyses audio with
+const FINGERPRINT_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 4096;
+const FRAME_HOP: usize = FRAME_SIZE / 3;
+/// Frequency range folded into the chroma, in Hz
+const CHROMA_LOW: f64 = 28.0;
+const CHROMA_HIGH: f64 = 3520.0;
+/// Smoothing of the chroma over neighbouring frames
+const CHROMA_FILTER: [f64; 5] = [0.25, 0.75, 1.0, 0.75, 0.25];
+/// Chroma vectors quieter than this are zeroed instead of normalized, on
+/// the 16-bit scale Chromaprint takes its samples at
+const NORMALIZE_THRESHOLD: f64 = 0.01;
+const SAMPLE_SCALE: f64 = i16::MAX as f64;
+/// Chromaprint's id for the algorithm below, its default
+const ALGORITHM: u8 = 1;
+/// Subfingerprint deltas from this size up are stored in the exception list
+const MAX_NORMAL_DELTA: u32 = 7;
+/// Frames of chroma each subfingerprint looks at, the widest classifier
+const CLASSIFIER_WIDTH: usize = 16;
+
+/// A Chromaprint classifier: a Haar-like filter over the chroma image,
+/// quantized to two bits
+struct Classifier {
+    /// Filter shape, 0 to 5
+    kind: u8,
+    /// First chroma band, number of bands and number of frames covered
+    y: usize,
+    height: usize,
+    width: usize,
+    thresholds: [f64; 3],
+}
+
+const fn classifier(kind: u8, y: usize, height: usize, width: usize, t: [f64; 3]) -> Classifier {
+    Classifier {
+        kind,
+        y,
+        height,
+        width,
+        thresholds: t,
+    }
+}
+
+/// Chromaprint's trained classifiers for its default algorithm
+const CLASSIFIERS: [Classifier; 16] = [
+    classifier(0, 4, 3, 15, [1.98215, 2.35817, 2.63523]),
+    classifier(4, 4, 6, 15, [-1.03809, -0.651211, -0.282167]),
+    classifier(1, 0, 4, 16, [-0.298702, 0.119262, 0.558497]),
+    classifier(3, 8, 2, 12, [-0.105439, 0.0153946, 0.135898]),
+    classifier(3, 4, 4, 8, [-0.142891, 0.0258736, 0.200632]),
+    classifier(4, 0, 3, 5, [-0.826319, -0.590612, -0.368214]),
+    classifier(1, 2, 2, 9, [-0.557409, -0.233035, 0.0534525]),
+    classifier(2, 7, 3, 4, [-0.0646826, 0.00620476, 0.0784847]),
+    classifier(2, 6, 2, 16, [-0.192387, -0.029699, 0.215855]),
+    classifier(2, 1, 3, 2, [-0.0397818, -0.00568076, 0.0292026]),
+    classifier(5, 10, 1, 15, [-0.53823, -0.369934, -0.190235]),
+    classifier(3, 6, 2, 10, [-0.124877, 0.0296483, 0.139239]),
+    classifier(2, 1, 1, 14, [-0.101475, 0.0225617, 0.231971]),
+    classifier(3, 5, 6, 4, [-0.0799915, -0.00729616, 0.063262]),
+    classifier(1, 9, 2, 12, [-0.272556, 0.019424, 0.302559]),
+    classifier(3, 4, 2, 14, [-0.164292, -0.0321188, 0.08463]),
+];
+
+/// Running sums of chroma frames, for the sum over any rectangle of frames
+/// and bands in constant time
+struct IntegralImage {
+    /// One row per frame plus a leading zero row, 13 columns
+    rows: Vec<[f64; 13]>,
+}
+
+impl IntegralImage {
+    fn new(frames: &[[f64; 12]]) -> Self {
+        let mut rows = vec![[0.0; 13]; frames.len() + 1];
+        for (i, frame) in frames.iter().enumerate() {
+            let mut sum = 0.0;
+            for band in 0..12 {
+                sum += frame[band];
+                rows[i + 1][band + 1] = rows[i][band + 1] + sum;
+            }
+        }
+        IntegralImage { rows }
+    }
+
+    /// Sum over frames `x1..x2` and bands `y1..y2`
+    fn area(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> f64 {
+        self.rows[x2][y2] - self.rows[x1][y2] - self.rows[x2][y1] + self.rows[x1][y1]
+    }
+}
+
+impl Classifier {
+    /// Two bits from the filter response at frame `x`, Gray coded
+    fn classify(&self, image: &IntegralImage, x: usize) -> u32 {
+        let (y, w, h) = (self.y, self.width, self.height);
+        let area = |x1, y1, x2, y2| image.area(x + x1, y + y1, x + x2, y + y2);
+        let (a, b) = match self.kind {
+            0 => (area(0, 0, w, h), 0.0),
+            1 => (area(0, h / 2, w, h), area(0, 0, w, h / 2)),
+            2 => (area(w / 2, 0, w, h), area(0, 0, w / 2, h)),
+            3 => (
+                area(0, h / 2, w / 2, h) + area(w / 2, 0, w, h / 2),
+                area(0, 0, w / 2, h / 2) + area(w / 2, h / 2, w, h),
+            ),
+            4 => (
+                area(0, h / 3, w, 2 * (h / 3)),
+                area(0, 0, w, h / 3) + area(0, 2 * (h / 3), w, h),
+            ),
+            _ => (
+                area(w / 3, 0, 2 * (w / 3), h),
+                area(0, 0, w / 3, h) + area(2 * (w / 3), 0, w, h),
+            ),
+        };
+        let value = (1.0 + a).ln() - (1.0 + b).ln();
+        let [t0, t1, t2] = self.thresholds;
+        match value {
+            v if v < t0 => 0,
+            v if v < t1 => 1,
+            v if v < t2 => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// Turns decoded audio into a Chromaprint fingerprint, as used by AcoustID
+pub struct Fingerprinter {
+    channels: usize,
+    resampler: Option<Resampler>,
+    fft: Arc<dyn RealToComplex<f64>>,
+    window: Vec<f64>,
+    input: Vec<f64>,
+    output: Vec<Complex<f64>>,
+    /// Chroma band of each FFT bin in range
+    bands: Vec<(usize, usize)>,
+    /// Mono samples at the fingerprint rate waiting for a full frame
+    pending: VecDeque<f32>,
+    chroma: Vec<[f64; 12]>,
+}
+
+impl Fingerprinter {
+    pub fn new(sample_rate: u32, channels: usize) -> Result<Self, String> {
+        let resampler = if sample_rate == FINGERPRINT_RATE {
+            None
+        } else {
+            Some(Resampler::with_quality(
+                sample_rate,
+                FINGERPRINT_RATE,
+                1,
+                ResamplerQuality::Fast,
+            )?)
+        };
+        let fft = RealFftPlanner::<f64>::new().plan_fft_forward(FRAME_SIZE);
+        let index = |frequency: f64| {
+            (FRAME_SIZE as f64 * frequency / FINGERPRINT_RATE as f64).round() as usize
+        };
+        let bands = (index(CHROMA_LOW).max(1)..index(CHROMA_HIGH).min(FRAME_SIZE / 2))
+            .map(|bin| {
+                let frequency = bin as f64 * FINGERPRINT_RATE as f64 / FRAME_SIZE as f64;
+                // Octaves above A0, whose fraction picks the band
+                let octave = (frequency / (440.0 / 16.0)).log2();
+                (bin, (12.0 * (octave - octave.floor())) as usize % 12)
+            })
+            .collect();
+        Ok(Fingerprinter {
+            channels: channels.max(1),
+            resampler,
+            input: fft.make_input_vec(),
+            output: fft.make_output_vec(),
+            fft,
+            window: Window::Hamming
+                .coefficients(FRAME_SIZE)
+                .into_iter()
+                .map(f64::from)
+                .collect(),
+            bands,
+            pending: VecDeque::with_capacity(FRAME_SIZE * 2),
+            chroma: Vec::new(),
+        })
+    }
+
+    /// Adds interleaved samples
+    pub fn process(&mut self, samples: &[f32]) {
+        let mono: Vec<f32> = samples
+            .chunks_exact(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() / self.channels as f32)
+            .collect();
+        let mono = match self.resampler.as_mut() {
+            Some(resampler) => resampler.process(&mono),
+            None => mono,
+        };
+        self.consume(&mono);
+    }
+
+    fn consume(&mut self, samples: &[f32]) {
+        self.pending.extend(samples);
+        while self.pending.len() >= FRAME_SIZE {
+            for ((input, sample), w) in self.input.iter_mut().zip(&self.pending).zip(&self.window) {
+                *input = *sample as f64 * SAMPLE_SCALE * w;
+            }
+            self.pending.drain(..FRAME_HOP);
+            // Buffers always come from the plan, so the sizes match
+            let _ = self.fft.process(&mut self.input, &mut self.output);
+            let mut chroma = [0.0; 12];
+            for (bin, band) in &self.bands {
+                chroma[*band] += self.output[*bin].norm_sqr();
+            }
+            self.chroma.push(chroma);
+        }
+    }
+
+    /// The fingerprint of everything added so far
+    pub fn finish(mut self) -> Vec<u32> {
+        if let Some(tail) = self.resampler.as_mut().map(Resampler::flush) {
+            self.consume(&tail);
+        }
+
+        // Smooth over neighbouring frames, then normalize each frame
+        let frames: Vec<[f64; 12]> = self
+            .chroma
+            .windows(CHROMA_FILTER.len())
+            .map(|window| {
+                let mut smoothed = [0.0; 12];
+                for (frame, weight) in window.iter().zip(CHROMA_FILTER) {
+                    for (sum, value) in smoothed.iter_mut().zip(frame) {
+                        *sum += value * weight;
+                    }
+                }
+                let norm = smoothed.iter().map(|v| v * v).sum::<f64>().sqrt();
+                if norm < NORMALIZE_THRESHOLD {
+                    [0.0; 12]
+                } else {
+                    smoothed.map(|v| v / norm)
+                }
+            })
+            .collect();
+
+        let image = IntegralImage::new(&frames);
+        (0..(frames.len() + 1).saturating_sub(CLASSIFIER_WIDTH))
+            .map(|x| {
+                CLASSIFIERS.iter().fold(0, |bits, classifier| {
+                    (bits << 2) | classifier.classify(&image, x)
+                })
+            })
+            .collect()
+    }
+}
+
+/// A Chromaprint fingerprint of a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fingerprint {
+    /// Length of the whole file, which AcoustID lookups also need
+    pub duration: Duration,
+    /// Subfingerprints, one per 1365 samples at 11025 Hz
+    pub data: Vec<u32>,
+}
+
+impl Fingerprint {
+    /// The compressed, URL-safe base64 form `fpcalc` prints and AcoustID
+    /// takes
+    pub fn encode(&self) -> String {
+        base64_url(&self.compress())
+    }
+
+    /// The algorithm id and length, then the bit changes between
+    /// subfingerprints packed as Chromaprint does
+    fn compress(&self) -> Vec<u8> {
+        let mut normal = Vec::new();
+        let mut exceptional = Vec::new();
+        let mut previous = 0;
+        for &subfingerprint in &self.data {
+            // Positions of the bits that changed, as gaps between them
+            let mut changed = subfingerprint ^ previous;
+            previous = subfingerprint;
+            let (mut bit, mut last_bit) = (1, 0);
+            while changed != 0 {
+                if changed & 1 != 0 {
+                    let delta = bit - last_bit;
+                    if delta >= MAX_NORMAL_DELTA {
+                        normal.push(MAX_NORMAL_DELTA);
+                        exceptional.push(delta - MAX_NORMAL_DELTA);
+                    } else {
+                        normal.push(delta);
+                    }
+                    last_bit = bit;
+                }
+                changed >>= 1;
+                bit += 1;
+            }
+            normal.push(0);
+        }
+
+        let size = self.data.len();
+        let mut bytes = vec![ALGORITHM, (size >> 16) as u8, (size >> 8) as u8, size as u8];
+        pack_bits(&mut bytes, &normal, 3);
+        pack_bits(&mut bytes, &exceptional, 5);
+        bytes
+    }
+}
+
+/// Fingerprints the first `length` of `path`, or all of it
+pub fn fingerprint(path: &Path, length: Option<Duration>) -> Result<Fingerprint, String> {
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let channels = file.channels.max(1) as usize;
+    let sample_rate = file.sample_rate.max(1);
+    let total = file.frames;
+    let limit = length.map(|length| (length.as_secs_f64() * sample_rate as f64) as usize);
+
+    let mut fingerprinter = Fingerprinter::new(sample_rate, channels)?;
+    let mut frames = 0u64;
+    while let Some(samples) = file.next_samples()? {
+        let available = samples.len() / channels;
+        let wanted = match limit {
+            Some(limit) => available.min(limit.saturating_sub(frames as usize)),
+            None => available,
+        };
+        fingerprinter.process(&samples[..wanted * channels]);
+        frames += available as u64;
+        // Keep counting when the container doesn't say how long it is
+        if wanted < available && total.is_some() {
+            break;
+        }
+    }
+
+    let data = fingerprinter.finish();
+    if data.is_empty() {
+        return Err("too short to fingerprint".to_string());
+    }
+    Ok(Fingerprint {
+        duration: Duration::from_secs_f64(total.unwrap_or(frames) as f64 / sample_rate as f64),
+        data,
+    })
+}
+
+/// Appends `values` of `bits` bits each, least significant bit first
+fn pack_bits(bytes: &mut Vec<u8>, values: &[u32], bits: u32) {
+    let (mut buffer, mut filled) = (0u32, 0);
+    for value in values {
+        buffer |= (value & ((1 << bits) - 1)) << filled;
+        filled += bits;
+        while filled >= 8 {
+            bytes.push(buffer as u8);
+            buffer >>= 8;
+            filled -= 8;
+        }
+    }
+    if filled > 0 {
+        bytes.push(buffer as u8);
+    }
+}
+
+/// Base64 with `-` and `_` for the last two digits and no padding
+fn base64_url(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for digit in 0..=chunk.len() {
+            text.push(DIGITS[(group >> (18 - 6 * digit) & 63) as usize] as char);
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chords over a little noise, as 16-bit samples at the fingerprint rate
+    fn music(seconds: usize) -> Vec<i16> {
+        let mut seed = 1u32;
+        (0..FINGERPRINT_RATE as usize * seconds)
+            .map(|i| {
+                let t = i as f64 / FINGERPRINT_RATE as f64;
+                let root = [220.0, 261.63, 329.63, 392.0, 293.66, 246.94][(t * 2.0) as usize % 6];
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let noise = ((seed >> 16) as f64 / 65536.0 - 0.5) * 0.05;
+                let tone = |frequency: f64| (std::f64::consts::TAU * frequency * t).sin();
+                let value = 0.3 * tone(root) + 0.2 * tone(root * 1.5) + noise;
+                (value * SAMPLE_SCALE).round() as i16
+            })
+            .collect()
+    }
+
+    fn fingerprint_of(samples: &[i16], sample_rate: u32) -> Fingerprint {
+        let mut fingerprinter = Fingerprinter::new(sample_rate, 1).unwrap();
+        for chunk in samples.chunks(1024) {
+            let chunk: Vec<f32> = chunk.iter().map(|&s| s as f32 / 32768.0).collect();
+            fingerprinter.process(&chunk);
+        }
+        Fingerprint {
+            duration: Duration::ZERO,
+            data: fingerprinter.finish(),
+        }
+    }
+
+    #[test]
+    fn silence_matches_chromaprint() {
+        // Chromaprint's own API tests, Test2SilenceRawFp and Test2SilenceFp
+        let fingerprint = fingerprint_of(&[0; 130 * 1024], 44100);
+        assert_eq!(fingerprint.data, [627_964_279; 3]);
+        assert_eq!(fingerprint.encode(), "AQAAA0mUaEkSRZEGAA");
+    }
+
+    #[test]
+    fn music_matches_rusty_chromaprint() {
+        // A port of Chromaprint checked against fpcalc
+        let samples = music(12);
+        let config = rusty_chromaprint::Configuration::preset_test2();
+        let mut reference = rusty_chromaprint::Fingerprinter::new(&config);
+        reference.start(FINGERPRINT_RATE, 1).unwrap();
+        reference.consume(&samples);
+        reference.finish();
+
+        let fingerprint = fingerprint_of(&samples, FINGERPRINT_RATE);
+        assert_eq!(fingerprint.data.len(), 75);
+        assert_eq!(fingerprint.data, reference.fingerprint());
+        let compressor = rusty_chromaprint::FingerprintCompressor::from(&config);
+        assert_eq!(
+            fingerprint.compress(),
+            compressor.compress(reference.fingerprint())
+        );
+    }
+
+    #[test]
+    fn compression_matches_chromaprint() {
+        // Chromaprint's FingerprintCompressor tests, after the algorithm id
+        let compress = |data: &[u32]| {
+            let fingerprint = Fingerprint {
+                duration: Duration::ZERO,
+                data: data.to_vec(),
+            };
+            fingerprint.compress()[1..].to_vec()
+        };
+        assert_eq!(compress(&[1]), [0, 0, 1, 1]);
+        assert_eq!(compress(&[7]), [0, 0, 1, 73, 0]);
+        assert_eq!(compress(&[1 << 6]), [0, 0, 1, 7, 0]);
+        assert_eq!(compress(&[1 << 8]), [0, 0, 1, 7, 2]);
+        assert_eq!(compress(&[1, 0]), [0, 0, 2, 65, 0]);
+        assert_eq!(compress(&[1, 1]), [0, 0, 2, 1, 0]);
+    }
+
+    #[test]
+    fn base64_matches_chromaprint() {
+        assert_eq!(base64_url(b"x"), "eA");
+        assert_eq!(base64_url(b"xx"), "eHg");
+        assert_eq!(base64_url(b"xxx"), "eHh4");
+        assert_eq!(base64_url(b"xxxx"), "eHh4eA");
+        assert_eq!(base64_url(&[0xff, 0xee]), "_-4");
+        assert_eq!(base64_url(&[]), "");
+    }
+}
@@ -0,0 +1,219 @@
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+use base64::Engine;
+
+use crate::image::Image;
+
+/// Character cells are about twice as tall as they are wide
+const CELL_ASPECT: usize = 2;
+/// Pixels per character cell width assumed for sixel output
+const SIXEL_CELL_WIDTH: usize = 10;
+/// Most base64 bytes one kitty graphics escape may carry
+const KITTY_CHUNK: usize = 4096;
+/// Characters of the text fallback, from dark to bright
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+/// Levels per channel of the sixel palette
+const SIXEL_LEVELS: usize = 6;
+
+/// A way of showing images in a terminal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Graphics {
+    /// The kitty graphics protocol, also spoken by Ghostty
+    Kitty,
+    /// iTerm2 inline images, also shown by WezTerm
+    Iterm2,
+    Sixel,
+    /// Characters by brightness, for any terminal
+    Ascii,
+}
+
+impl Graphics {
+    /// Guesses what the terminal on stdout can show from the environment it
+    /// sets, falling back to text when it is unknown or not a terminal
+    pub fn detect() -> Self {
+        if !std::io::stdout().is_terminal() {
+            return Graphics::Ascii;
+        }
+        let var = |name| std::env::var(name).unwrap_or_default();
+        let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+        if std::env::var_os("KITTY_WINDOW_ID").is_some()
+            || term == "xterm-kitty"
+            || term == "xterm-ghostty"
+        {
+            Graphics::Kitty
+        } else if program == "iTerm.app" || program == "WezTerm" || var("LC_TERMINAL") == "iTerm2" {
+            Graphics::Iterm2
+        } else if term.contains("sixel")
+            || ["foot", "mlterm", "contour", "yaft"]
+                .iter()
+                .any(|name| term.starts_with(name))
+        {
+            Graphics::Sixel
+        } else {
+            Graphics::Ascii
+        }
+    }
+
+    /// Output that draws `data`, a PNG or JPEG file, `columns` character
+    /// cells wide and as tall as its aspect ratio needs, ending on a new line
+    pub fn render(self, data: &[u8], columns: usize) -> Result<String, String> {
+        let columns = columns.max(1);
+        match self {
+            Graphics::Kitty => kitty(data, columns),
+            Graphics::Iterm2 => Ok(format!(
+                "\x1b]1337;File=inline=1;size={};width={};preserveAspectRatio=1:{}\x07\n",
+                data.len(),
+                columns,
+                base64::engine::general_purpose::STANDARD.encode(data)
+            )),
+            Graphics::Sixel => {
+                let image = Image::decode(data)?;
+                let width = columns * SIXEL_CELL_WIDTH;
+                let height = (width * image.height / image.width.max(1)).max(1);
+                Ok(sixel(&image.resize(width, height)))
+            }
+            Graphics::Ascii => {
+                let image = Image::decode(data)?;
+                let rows = (columns * image.height / image.width.max(1) / CELL_ASPECT).max(1);
+                Ok(ascii(&image.resize(columns, rows)))
+            }
+        }
+    }
+}
+
+impl FromStr for Graphics {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Graphics::detect()),
+            "kitty" => Ok(Graphics::Kitty),
+            "iterm" | "iterm2" => Ok(Graphics::Iterm2),
+            "sixel" => Ok(Graphics::Sixel),
+            "ascii" | "text" => Ok(Graphics::Ascii),
+            _ => Err(format!(
+                "invalid graphics mode: {} (expected auto, kitty, iterm, sixel or ascii)",
+                s
+            )),
+        }
+    }
+}
+
+/// Kitty takes PNG files as they are; anything else goes as decoded pixels
+fn kitty(data: &[u8], columns: usize) -> Result<String, String> {
+    let (format, payload) = if data.starts_with(b"\x89PNG") {
+        ("f=100".to_string(), data.to_vec())
+    } else {
+        let image = Image::decode(data)?;
+        let pixels: Vec<u8> = (0..image.height)
+            .flat_map(|y| (0..image.width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| image.get(x, y).unwrap_or_default())
+            .collect();
+        (format!("f=24,s={},v={}", image.width, image.height), pixels)
+    };
+    let encoded = base64::engine::general_purpose::STANDARD.encode(payload);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK).collect();
+    let mut out = String::with_capacity(encoded.len() + chunks.len() * 16 + 64);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        // Only the first escape carries the keys; q=2 keeps replies off stdin
+        let keys = if i == 0 {
+            format!("a=T,q=2,{},c={},", format, columns)
+        } else {
+            String::new()
+        };
+        out.push_str(&format!(
+            "\x1b_G{}m={};{}\x1b\\",
+            keys,
+            more,
+            String::from_utf8_lossy(chunk)
+        ));
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+/// Sixel output in a 216 color palette, six pixel rows per line of text
+fn sixel(image: &Image) -> String {
+    let level = |value: u8| (value as usize * (SIXEL_LEVELS - 1) + 127) / 255;
+    let palette_index = |color: [u8; 3]| {
+        (level(color[0]) * SIXEL_LEVELS + level(color[1])) * SIXEL_LEVELS + level(color[2])
+    };
+    let indexes: Vec<usize> = (0..image.height)
+        .flat_map(|y| (0..image.width).map(move |x| (x, y)))
+        .map(|(x, y)| palette_index(image.get(x, y).unwrap_or_default()))
+        .collect();
+
+    let mut out = format!("\x1bPq\"1;1;{};{}", image.width, image.height);
+    let mut used = vec![false; SIXEL_LEVELS.pow(3)];
+    for &index in &indexes {
+        used[index] = true;
+    }
+    for (index, _) in used.iter().enumerate().filter(|(_, used)| **used) {
+        let percent = |level: usize| level * 100 / (SIXEL_LEVELS - 1);
+        out.push_str(&format!(
+            "#{};2;{};{};{}",
+            index,
+            percent(index / (SIXEL_LEVELS * SIXEL_LEVELS)),
+            percent(index / SIXEL_LEVELS % SIXEL_LEVELS),
+            percent(index % SIXEL_LEVELS)
+        ));
+    }
+
+    for band in (0..image.height).step_by(6) {
+        let rows = band..(band + 6).min(image.height);
+        let mut colors: Vec<usize> = rows
+            .clone()
+            .flat_map(|y| {
+                indexes[y * image.width..(y + 1) * image.width]
+                    .iter()
+                    .copied()
+            })
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+        for color in colors {
+            out.push_str(&format!("#{}", color));
+            let sixels: Vec<u8> = (0..image.width)
+                .map(|x| {
+                    let bits = rows
+                        .clone()
+                        .filter(|y| indexes[y * image.width + x] == color)
+                        .fold(0, |bits, y| bits | 1 << (y - band));
+                    63 + bits
+                })
+                .collect();
+            // Runs are written as a count and the character
+            let mut x = 0;
+            while x < sixels.len() {
+                let run = sixels[x..].iter().take_while(|s| **s == sixels[x]).count();
+                if run > 3 {
+                    out.push_str(&format!("!{}{}", run, sixels[x] as char));
+                } else {
+                    out.extend(std::iter::repeat_n(sixels[x] as char, run));
+                }
+                x += run;
+            }
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\\n");
+    out
+}
+
+/// One character per cell, by brightness
+fn ascii(image: &Image) -> String {
+    let mut out = String::with_capacity((image.width + 1) * image.height);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let [r, g, b] = image.get(x, y).unwrap_or_default();
+            let luma = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+            let step = (luma / 256.0 * ASCII_RAMP.len() as f32) as usize;
+            out.push(ASCII_RAMP[step.min(ASCII_RAMP.len() - 1)] as char);
+        }
+        out.push('\n');
+    }
+    out
+}
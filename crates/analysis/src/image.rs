@@ -0,0 +1,368 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::inflate::zlib_decompress;
+use crate::jpeg::{decode_jpeg, encode_jpeg};
+
+/// Braille dots of each character cell, by column then row
+const BRAILLE_DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// An 8-bit RGB image, rows top to bottom
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    /// A `width` by `height` image filled with `color`
+    pub fn new(width: usize, height: usize, color: [u8; 3]) -> Self {
+        Image {
+            width,
+            height,
+            pixels: color.repeat(width * height),
+        }
+    }
+
+    /// Colors the pixel at column `x`, row `y`; outside the image does nothing
+    pub fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let i = (y * self.width + x) * 3;
+            self.pixels[i..i + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// Decodes a PNG or JPEG file, such as embedded cover art
+    pub fn decode(data: &[u8]) -> Result<Image, String> {
+        if data.starts_with(PNG_SIGNATURE) {
+            decode_png(data)
+        } else if data.starts_with(&[0xff, 0xd8]) {
+            decode_jpeg(data)
+        } else {
+            Err("unsupported image format (expected PNG or JPEG)".to_string())
+        }
+    }
+
+    /// The image scaled to `width` by `height`, averaging the pixels each
+    /// new pixel covers
+    pub fn resize(&self, width: usize, height: usize) -> Image {
+        let mut resized = Image::new(width, height, [0; 3]);
+        if self.width == 0 || self.height == 0 {
+            return resized;
+        }
+        let span = |i: usize, from: usize, to: usize| {
+            let start = i * from / to;
+            start..((i + 1) * from / to).max(start + 1)
+        };
+        for y in 0..height {
+            let rows = span(y, self.height, height);
+            for x in 0..width {
+                let columns = span(x, self.width, width);
+                let mut sum = [0u32; 3];
+                for row in rows.clone() {
+                    for column in columns.clone() {
+                        let i = (row * self.width + column) * 3;
+                        for (total, value) in sum.iter_mut().zip(&self.pixels[i..i + 3]) {
+                            *total += *value as u32;
+                        }
+                    }
+                }
+                let count = (rows.len() * columns.len()) as u32;
+                resized.set(x, y, sum.map(|total| ((total + count / 2) / count) as u8));
+            }
+        }
+        resized
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<[u8; 3]> {
+        if x < self.width && y < self.height {
+            let i = (y * self.width + x) * 3;
+            Some([self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]])
+        } else {
+            None
+        }
+    }
+
+    /// The image in braille characters for a terminal, each character
+    /// covering 2 by 4 pixels and showing a dot for every pixel in `color`
+    pub fn to_braille(&self, color: [u8; 3]) -> String {
+        let (columns, rows) = (self.width.div_ceil(2), self.height.div_ceil(4));
+        let mut text = String::with_capacity((columns * 3 + 1) * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let mut bits = 0;
+                for (dx, dots) in BRAILLE_DOTS.iter().enumerate() {
+                    for (dy, dot) in dots.iter().enumerate() {
+                        if self.get(column * 2 + dx, row * 4 + dy) == Some(color) {
+                            bits |= dot;
+                        }
+                    }
+                }
+                text.push(char::from_u32(0x2800 + bits).unwrap_or(' '));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Saves the image as a PNG file
+    pub fn write_png(&self, path: &Path) -> Result<(), String> {
+        let file = std::fs::File::create(path)
+            .map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+        let mut writer = std::io::BufWriter::new(file);
+        self.encode_png(&mut writer)
+            .and_then(|()| writer.flush())
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    /// The image as a PNG file
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        self.encode_png(&mut data)
+            .expect("writing to memory cannot fail");
+        data
+    }
+
+    /// The image as a baseline JPEG file at `quality`, 1 to 100
+    pub fn to_jpeg(&self, quality: u8) -> Vec<u8> {
+        encode_jpeg(self, quality)
+    }
+
+    fn encode_png(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(PNG_SIGNATURE)?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bits per channel, RGB, no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(writer, b"IHDR", &header)?;
+
+        // Every row starts with filter type 0, none
+        let row = self.width * 3;
+        let mut raw = Vec::with_capacity((row + 1) * self.height);
+        for line in self.pixels.chunks(row.max(1)).take(self.height) {
+            raw.push(0);
+            raw.extend_from_slice(line);
+        }
+        write_chunk(writer, b"IDAT", &zlib_stored(&raw))?;
+        write_chunk(writer, b"IEND", &[])
+    }
+}
+
+/// Decodes a non-interlaced PNG of any bit depth and color type, blending
+/// transparent pixels onto black
+fn decode_png(data: &[u8]) -> Result<Image, String> {
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut compressed = Vec::new();
+    let mut pos = PNG_SIGNATURE.len();
+    while let Some(length) = data.get(pos..pos + 4) {
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        let kind = data.get(pos + 4..pos + 8).ok_or("truncated PNG chunk")?;
+        let body = data
+            .get(pos + 8..pos + 8 + length)
+            .ok_or("truncated PNG chunk")?;
+        pos += length + 12;
+        match kind {
+            b"IHDR" if body.len() >= 13 => header = Some(body),
+            b"PLTE" => palette = body,
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    let header = header.ok_or("PNG file without a header")?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color_type) = (header[8] as usize, header[9]);
+    if header[12] != 0 {
+        return Err("interlaced PNGs are not supported".to_string());
+    }
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(format!("invalid PNG color type {}", color_type)),
+    };
+    if !matches!(depth, 1 | 2 | 4 | 8 | 16) {
+        return Err(format!("invalid PNG bit depth {}", depth));
+    }
+
+    let raw = zlib_decompress(&compressed)?;
+    let row_bytes = (width * channels * depth).div_ceil(8);
+    let pixel_bytes = (channels * depth / 8).max(1);
+    if raw.len() < (row_bytes + 1) * height {
+        return Err("truncated PNG image data".to_string());
+    }
+    let mut previous = vec![0u8; row_bytes];
+    let mut row = vec![0u8; row_bytes];
+    let max = (1u32 << depth.min(8)) - 1;
+    let mut image = Image::new(width, height, [0; 3]);
+    for y in 0..height {
+        let line = &raw[y * (row_bytes + 1)..(y + 1) * (row_bytes + 1)];
+        unfilter(line[0], &line[1..], &previous, &mut row, pixel_bytes)?;
+        // Samples scaled to 8 bits; 16-bit samples keep their high byte
+        let sample = |index: usize| -> u32 {
+            if depth >= 8 {
+                row[index * depth / 8] as u32
+            } else {
+                let bit = index * depth;
+                let value = (row[bit / 8] >> (8 - depth - bit % 8)) as u32 & max;
+                if color_type == 3 {
+                    value
+                } else {
+                    value * 255 / max
+                }
+            }
+        };
+        for x in 0..width {
+            let first = x * channels;
+            let (color, alpha) = match color_type {
+                0 => ([sample(first); 3], 255),
+                2 => ([sample(first), sample(first + 1), sample(first + 2)], 255),
+                3 => {
+                    let i = sample(first) as usize * 3;
+                    let color = palette
+                        .get(i..i + 3)
+                        .ok_or("PNG palette index out of range")?;
+                    ([color[0] as u32, color[1] as u32, color[2] as u32], 255)
+                }
+                4 => ([sample(first); 3], sample(first + 1)),
+                _ => (
+                    [sample(first), sample(first + 1), sample(first + 2)],
+                    sample(first + 3),
+                ),
+            };
+            image.set(x, y, color.map(|value| (value * alpha / 255) as u8));
+        }
+        std::mem::swap(&mut previous, &mut row);
+    }
+    Ok(image)
+}
+
+/// Undoes the PNG filter of one row
+fn unfilter(
+    filter: u8,
+    line: &[u8],
+    previous: &[u8],
+    row: &mut [u8],
+    pixel_bytes: usize,
+) -> Result<(), String> {
+    for i in 0..line.len() {
+        let left = if i >= pixel_bytes {
+            row[i - pixel_bytes]
+        } else {
+            0
+        };
+        let up = previous[i];
+        let up_left = if i >= pixel_bytes {
+            previous[i - pixel_bytes]
+        } else {
+            0
+        };
+        let predicted = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => {
+                let estimate = left as i16 + up as i16 - up_left as i16;
+                let (a, b, c) = (
+                    (estimate - left as i16).abs(),
+                    (estimate - up as i16).abs(),
+                    (estimate - up_left as i16).abs(),
+                );
+                if a <= b && a <= c {
+                    left
+                } else if b <= c {
+                    up
+                } else {
+                    up_left
+                }
+            }
+            _ => return Err(format!("invalid PNG filter type {}", filter)),
+        };
+        row[i] = line[i].wrapping_add(predicted);
+    }
+    Ok(())
+}
+
+/// Parses a color written as `#rrggbb` or `rrggbb`
+pub fn parse_color(value: &str) -> Result<[u8; 3], String> {
+    let hex = value.trim().trim_start_matches('#');
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+    };
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!("invalid color: {} (expected #rrggbb)", value)),
+    }
+}
+
+fn write_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(&[kind.as_slice(), data]);
+    writer.write_all(&crc.to_be_bytes())
+}
+
+/// Wraps `data` in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 65535;
+    let mut out = Vec::with_capacity(data.len() + data.len() / BLOCK * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    let mut crc = 0xffff_ffffu32;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        crc = table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc ^ 0xffff_ffff
+}
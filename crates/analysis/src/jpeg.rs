@@ -0,0 +1,995 @@
+// Baseline and progressive JPEG decoding, for showing cover art, and
+// baseline encoding, for embedding it
+
+use crate::image::Image;
+
+/// Position in an 8x8 block, by row, of each coefficient in zigzag order
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+struct Component {
+    id: u8,
+    /// Horizontal and vertical sampling factors
+    h: usize,
+    v: usize,
+    quant: usize,
+    /// Blocks per row and column, padded out to whole MCUs
+    blocks_x: usize,
+    blocks_y: usize,
+    /// Dequantized coefficients of every block, row by row
+    coefficients: Vec<[i16; 64]>,
+    dc_table: usize,
+    ac_table: usize,
+    /// Running DC value of the scan
+    prediction: i32,
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    progressive: bool,
+    components: Vec<Component>,
+    max_h: usize,
+    max_v: usize,
+    mcus_x: usize,
+    mcus_y: usize,
+}
+
+/// Decodes a baseline or progressive JPEG of 1 (gray) or 3 (YCbCr or RGB)
+/// components
+pub(crate) fn decode_jpeg(data: &[u8]) -> Result<Image, String> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return Err("not a JPEG file".to_string());
+    }
+    let mut quant = [[0u16; 64]; 4];
+    let mut dc_tables: [Option<Huffman>; 4] = Default::default();
+    let mut ac_tables: [Option<Huffman>; 4] = Default::default();
+    let mut frame: Option<Frame> = None;
+    let mut restart_interval = 0;
+    // Adobe's APP14 segment says whether 3 components are RGB
+    let mut adobe_transform = None;
+
+    let mut pos = 2;
+    loop {
+        // Markers may be padded with any number of 0xff bytes
+        while data.get(pos) == Some(&0xff) && data.get(pos + 1) == Some(&0xff) {
+            pos += 1;
+        }
+        let (Some(0xff), Some(&marker)) = (data.get(pos), data.get(pos + 1)) else {
+            return Err("truncated JPEG file".to_string());
+        };
+        pos += 2;
+        if marker == 0xd9 {
+            break;
+        }
+        if (0xd0..=0xd7).contains(&marker) || marker == 0x01 {
+            continue;
+        }
+        let length = data
+            .get(pos..pos + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+            .filter(|length| *length >= 2)
+            .ok_or("truncated JPEG segment")?;
+        let segment = data
+            .get(pos + 2..pos + length)
+            .ok_or("truncated JPEG segment")?;
+        pos += length;
+
+        match marker {
+            0xdb => read_quant_tables(segment, &mut quant)?,
+            0xc4 => read_huffman_tables(segment, &mut dc_tables, &mut ac_tables)?,
+            0xdd => {
+                restart_interval = segment
+                    .get(..2)
+                    .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+                    .ok_or("truncated JPEG restart interval")?;
+            }
+            0xee if segment.starts_with(b"Adobe") => adobe_transform = segment.get(11).copied(),
+            0xc0..=0xc2 => frame = Some(read_frame(segment, marker == 0xc2)?),
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                return Err("lossless and arithmetic-coded JPEGs are not supported".to_string());
+            }
+            0xda => {
+                let frame = frame.as_mut().ok_or("JPEG scan before the frame header")?;
+                let scan = read_scan_header(segment, frame)?;
+                let mut reader = BitReader::new(data, pos);
+                decode_scan(
+                    frame,
+                    &scan,
+                    &dc_tables,
+                    &ac_tables,
+                    restart_interval,
+                    &mut reader,
+                )?;
+                pos = reader.next_marker();
+            }
+            _ => {}
+        }
+    }
+
+    let frame = frame.ok_or("JPEG file without a frame")?;
+    let planes: Vec<Vec<u8>> = frame
+        .components
+        .iter()
+        .map(|component| component_plane(component, &quant[component.quant]))
+        .collect();
+    let rgb = match frame.components.len() {
+        1 => false,
+        3 => {
+            let ids: Vec<u8> = frame.components.iter().map(|c| c.id).collect();
+            adobe_transform == Some(0) || ids == b"RGB"
+        }
+        count => return Err(format!("JPEGs with {} components are not supported", count)),
+    };
+
+    let mut image = Image::new(frame.width, frame.height, [0; 3]);
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let sample = |index: usize| {
+                let component = &frame.components[index];
+                let cx = x * component.h / frame.max_h;
+                let cy = y * component.v / frame.max_v;
+                planes[index][cy * component.blocks_x * 8 + cx] as f32
+            };
+            let color = if frame.components.len() == 1 {
+                [sample(0) as u8; 3]
+            } else if rgb {
+                [sample(0) as u8, sample(1) as u8, sample(2) as u8]
+            } else {
+                let (luma, cb, cr) = (sample(0), sample(1) - 128.0, sample(2) - 128.0);
+                [
+                    luma + 1.402 * cr,
+                    luma - 0.344_136 * cb - 0.714_136 * cr,
+                    luma + 1.772 * cb,
+                ]
+                .map(|value| value.round().clamp(0.0, 255.0) as u8)
+            };
+            image.set(x, y, color);
+        }
+    }
+    Ok(image)
+}
+
+fn read_quant_tables(mut segment: &[u8], quant: &mut [[u16; 64]; 4]) -> Result<(), String> {
+    while let Some(&info) = segment.first() {
+        let (precision, id) = (info >> 4, (info & 15) as usize);
+        let size = if precision == 0 { 64 } else { 128 };
+        let values = segment
+            .get(1..1 + size)
+            .ok_or("truncated JPEG quantization table")?;
+        let table = quant.get_mut(id).ok_or("invalid JPEG quantization table")?;
+        for (k, position) in ZIGZAG.iter().enumerate() {
+            table[*position] = if precision == 0 {
+                values[k] as u16
+            } else {
+                u16::from_be_bytes([values[2 * k], values[2 * k + 1]])
+            };
+        }
+        segment = &segment[1 + size..];
+    }
+    Ok(())
+}
+
+fn read_huffman_tables(
+    mut segment: &[u8],
+    dc_tables: &mut [Option<Huffman>; 4],
+    ac_tables: &mut [Option<Huffman>; 4],
+) -> Result<(), String> {
+    while let Some(&info) = segment.first() {
+        let counts = segment.get(1..17).ok_or("truncated JPEG Huffman table")?;
+        let total: usize = counts.iter().map(|count| *count as usize).sum();
+        let symbols = segment
+            .get(17..17 + total)
+            .ok_or("truncated JPEG Huffman table")?;
+        let table = Huffman::new(counts, symbols);
+        let tables = if info >> 4 == 0 {
+            &mut *dc_tables
+        } else {
+            &mut *ac_tables
+        };
+        *tables
+            .get_mut((info & 15) as usize)
+            .ok_or("invalid JPEG Huffman table")? = Some(table);
+        segment = &segment[17 + total..];
+    }
+    Ok(())
+}
+
+fn read_frame(segment: &[u8], progressive: bool) -> Result<Frame, String> {
+    if segment.len() < 6 || segment[0] != 8 {
+        return Err("only 8-bit JPEGs are supported".to_string());
+    }
+    let height = u16::from_be_bytes([segment[1], segment[2]]) as usize;
+    let width = u16::from_be_bytes([segment[3], segment[4]]) as usize;
+    let count = segment[5] as usize;
+    if width == 0 || height == 0 {
+        return Err("JPEG without a size".to_string());
+    }
+    let mut components = Vec::with_capacity(count);
+    for info in segment[6..].chunks_exact(3).take(count) {
+        let (h, v) = ((info[1] >> 4) as usize, (info[1] & 15) as usize);
+        if !(1..=4).contains(&h) || !(1..=4).contains(&v) || info[2] > 3 {
+            return Err("invalid JPEG component".to_string());
+        }
+        components.push(Component {
+            id: info[0],
+            h,
+            v,
+            quant: info[2] as usize,
+            blocks_x: 0,
+            blocks_y: 0,
+            coefficients: Vec::new(),
+            dc_table: 0,
+            ac_table: 0,
+            prediction: 0,
+        });
+    }
+    if components.len() != count {
+        return Err("truncated JPEG frame header".to_string());
+    }
+
+    let max_h = components.iter().map(|c| c.h).max().unwrap_or(1);
+    let max_v = components.iter().map(|c| c.v).max().unwrap_or(1);
+    let mcus_x = width.div_ceil(8 * max_h);
+    let mcus_y = height.div_ceil(8 * max_v);
+    for component in &mut components {
+        component.blocks_x = mcus_x * component.h;
+        component.blocks_y = mcus_y * component.v;
+        component.coefficients = vec![[0; 64]; component.blocks_x * component.blocks_y];
+    }
+    Ok(Frame {
+        width,
+        height,
+        progressive,
+        components,
+        max_h,
+        max_v,
+        mcus_x,
+        mcus_y,
+    })
+}
+
+struct Scan {
+    /// Indexes into the frame's components
+    components: Vec<usize>,
+    /// First and last coefficient, in zigzag order
+    start: usize,
+    end: usize,
+    /// Successive approximation: the bit position sent before, and now
+    high: u8,
+    low: u8,
+}
+
+fn read_scan_header(segment: &[u8], frame: &mut Frame) -> Result<Scan, String> {
+    let count = *segment.first().ok_or("truncated JPEG scan header")? as usize;
+    let tail = segment
+        .get(1 + 2 * count..4 + 2 * count)
+        .ok_or("truncated JPEG scan header")?;
+    let mut components = Vec::with_capacity(count);
+    for info in segment[1..1 + 2 * count].chunks_exact(2) {
+        let index = frame
+            .components
+            .iter()
+            .position(|component| component.id == info[0])
+            .ok_or("JPEG scan of an unknown component")?;
+        let component = &mut frame.components[index];
+        component.dc_table = (info[1] >> 4) as usize & 3;
+        component.ac_table = (info[1] & 15) as usize & 3;
+        component.prediction = 0;
+        components.push(index);
+    }
+    let scan = Scan {
+        components,
+        start: tail[0] as usize,
+        end: tail[1] as usize,
+        high: tail[2] >> 4,
+        low: tail[2] & 15,
+    };
+    if scan.start > scan.end || scan.end > 63 || (!frame.progressive && scan.start != 0) {
+        return Err("invalid JPEG scan".to_string());
+    }
+    Ok(scan)
+}
+
+fn decode_scan(
+    frame: &mut Frame,
+    scan: &Scan,
+    dc_tables: &[Option<Huffman>; 4],
+    ac_tables: &[Option<Huffman>; 4],
+    restart_interval: usize,
+    reader: &mut BitReader,
+) -> Result<(), String> {
+    let mut decoder = BlockDecoder {
+        scan,
+        progressive: frame.progressive,
+        eob_run: 0,
+    };
+
+    // A scan of one component goes through its blocks one by one, without
+    // the padding that completes the last MCUs
+    let single = scan.components.len() == 1;
+    let units: Vec<(usize, usize)> = if single {
+        let component = &frame.components[scan.components[0]];
+        let x = (frame.width * component.h)
+            .div_ceil(frame.max_h)
+            .div_ceil(8);
+        let y = (frame.height * component.v)
+            .div_ceil(frame.max_v)
+            .div_ceil(8);
+        (0..y)
+            .flat_map(|row| (0..x).map(move |column| (column, row)))
+            .collect()
+    } else {
+        (0..frame.mcus_y)
+            .flat_map(|row| (0..frame.mcus_x).map(move |column| (column, row)))
+            .collect()
+    };
+
+    for (n, (column, row)) in units.into_iter().enumerate() {
+        if restart_interval > 0 && n > 0 && n % restart_interval == 0 {
+            reader.restart();
+            decoder.eob_run = 0;
+            for &index in &scan.components {
+                frame.components[index].prediction = 0;
+            }
+        }
+        for &index in &scan.components {
+            let component = &mut frame.components[index];
+            let tables = (
+                dc_tables[component.dc_table].as_ref(),
+                ac_tables[component.ac_table].as_ref(),
+            );
+            let (block_count_x, block_count_y) = if single {
+                (1, 1)
+            } else {
+                (component.h, component.v)
+            };
+            for by in 0..block_count_y {
+                for bx in 0..block_count_x {
+                    let (x, y) = if single {
+                        (column, row)
+                    } else {
+                        (column * component.h + bx, row * component.v + by)
+                    };
+                    let block = y * component.blocks_x + x;
+                    let mut prediction = component.prediction;
+                    decoder.decode(
+                        reader,
+                        tables,
+                        &mut component.coefficients[block],
+                        &mut prediction,
+                    )?;
+                    component.prediction = prediction;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+struct BlockDecoder<'a> {
+    scan: &'a Scan,
+    progressive: bool,
+    /// Blocks left whose coefficients in this band are all zero
+    eob_run: u32,
+}
+
+impl BlockDecoder<'_> {
+    fn decode(
+        &mut self,
+        reader: &mut BitReader,
+        (dc, ac): (Option<&Huffman>, Option<&Huffman>),
+        block: &mut [i16; 64],
+        prediction: &mut i32,
+    ) -> Result<(), String> {
+        let missing = || "JPEG scan uses a missing Huffman table".to_string();
+        let scan = self.scan;
+        let shift = scan.low as u32;
+
+        if scan.start == 0 {
+            if scan.high == 0 {
+                let dc = dc.ok_or_else(missing)?;
+                let size = dc.decode(reader)?;
+                *prediction += reader.receive_extend(size)?;
+                block[0] = (*prediction << shift) as i16;
+            } else if reader.bit()? == 1 {
+                block[0] |= 1 << shift;
+            }
+            if self.progressive {
+                return Ok(());
+            }
+        }
+
+        let ac = ac.ok_or_else(missing)?;
+        let start = scan.start.max(1);
+        if !self.progressive {
+            let mut k = 1;
+            while k < 64 {
+                let symbol = ac.decode(reader)?;
+                let (run, size) = ((symbol >> 4) as usize, symbol & 15);
+                if size == 0 {
+                    if run != 15 {
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += run;
+                if k > 63 {
+                    return Err("corrupt JPEG block".to_string());
+                }
+                block[ZIGZAG[k]] = reader.receive_extend(size)? as i16;
+                k += 1;
+            }
+            return Ok(());
+        }
+
+        if scan.high == 0 {
+            // First pass over this band
+            if self.eob_run > 0 {
+                self.eob_run -= 1;
+                return Ok(());
+            }
+            let mut k = start;
+            while k <= scan.end {
+                let symbol = ac.decode(reader)?;
+                let (run, size) = ((symbol >> 4) as u32, symbol & 15);
+                if size == 0 {
+                    if run < 15 {
+                        self.eob_run = (1 << run) + reader.bits(run)? - 1;
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += run as usize;
+                if k > 63 {
+                    return Err("corrupt JPEG block".to_string());
+                }
+                block[ZIGZAG[k]] = (reader.receive_extend(size)? << shift) as i16;
+                k += 1;
+            }
+            return Ok(());
+        }
+
+        // Refinement: one more bit of every coefficient already sent, and
+        // new coefficients of magnitude 1 in between
+        let bit = 1i16 << shift;
+        let refine = |reader: &mut BitReader, value: &mut i16| -> Result<(), String> {
+            if reader.bit()? == 1 && *value & bit == 0 {
+                *value += if *value > 0 { bit } else { -bit };
+            }
+            Ok(())
+        };
+        let mut k = start;
+        if self.eob_run > 0 {
+            self.eob_run -= 1;
+            while k <= scan.end {
+                let value = &mut block[ZIGZAG[k]];
+                if *value != 0 {
+                    refine(reader, value)?;
+                }
+                k += 1;
+            }
+            return Ok(());
+        }
+        while k <= scan.end {
+            let symbol = ac.decode(reader)?;
+            let (mut run, size) = ((symbol >> 4) as u32, symbol & 15);
+            let mut new_value = 0;
+            if size == 0 {
+                if run < 15 {
+                    self.eob_run = (1 << run) + reader.bits(run)? - 1;
+                    // Refine what remains of this block, then stop
+                    run = 64;
+                }
+            } else {
+                new_value = if reader.bit()? == 1 { bit } else { -bit };
+            }
+            while k <= scan.end {
+                let value = &mut block[ZIGZAG[k]];
+                k += 1;
+                if *value != 0 {
+                    refine(reader, value)?;
+                } else {
+                    if run == 0 {
+                        *value = new_value;
+                        break;
+                    }
+                    run -= 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The samples of a component, after dequantizing and the inverse DCT
+fn component_plane(component: &Component, quant: &[u16; 64]) -> Vec<u8> {
+    let stride = component.blocks_x * 8;
+    let mut plane = vec![0u8; stride * component.blocks_y * 8];
+    let cosines = idct_cosines();
+    for (index, coefficients) in component.coefficients.iter().enumerate() {
+        let mut block = [0f32; 64];
+        for (value, (coefficient, step)) in block.iter_mut().zip(coefficients.iter().zip(quant)) {
+            *value = *coefficient as f32 * *step as f32;
+        }
+        let samples = idct(&block, &cosines);
+        let (bx, by) = (index % component.blocks_x, index / component.blocks_x);
+        for y in 0..8 {
+            let row = (by * 8 + y) * stride + bx * 8;
+            for x in 0..8 {
+                plane[row + x] = (samples[y * 8 + x] + 128.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    plane
+}
+
+/// `C(u) cos((2x + 1) u pi / 16) / 2` for every sample `x` and frequency `u`
+fn idct_cosines() -> [[f32; 8]; 8] {
+    let mut cosines = [[0f32; 8]; 8];
+    for (x, row) in cosines.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            let scale = if u == 0 {
+                std::f32::consts::FRAC_1_SQRT_2
+            } else {
+                1.0
+            };
+            *value =
+                scale * ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos() / 2.0;
+        }
+    }
+    cosines
+}
+
+/// Separable inverse DCT of one block, rows then columns
+fn idct(block: &[f32; 64], cosines: &[[f32; 8]; 8]) -> [f32; 64] {
+    let mut rows = [0f32; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            rows[v * 8 + x] = (0..8).map(|u| cosines[x][u] * block[v * 8 + u]).sum();
+        }
+    }
+    let mut samples = [0f32; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            samples[y * 8 + x] = (0..8).map(|v| cosines[y][v] * rows[v * 8 + x]).sum();
+        }
+    }
+    samples
+}
+
+/// A JPEG Huffman table
+struct Huffman {
+    /// Largest code of each length, -1 when there are none
+    max_code: [i32; 17],
+    /// Index of the first symbol of each length, less its code
+    offsets: [i32; 17],
+    symbols: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8], symbols: &[u8]) -> Self {
+        let mut max_code = [-1; 17];
+        let mut offsets = [0; 17];
+        let (mut code, mut index) = (0i32, 0i32);
+        for length in 1..=16 {
+            let count = counts[length - 1] as i32;
+            offsets[length] = index - code;
+            code += count;
+            index += count;
+            if count > 0 {
+                max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        Huffman {
+            max_code,
+            offsets,
+            symbols: symbols.to_vec(),
+        }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u8, String> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = code << 1 | reader.bit()? as i32;
+            if code <= self.max_code[length] {
+                return self
+                    .symbols
+                    .get((code + self.offsets[length]) as usize)
+                    .copied()
+                    .ok_or_else(|| "invalid JPEG Huffman code".to_string());
+            }
+        }
+        Err("invalid JPEG Huffman code".to_string())
+    }
+}
+
+/// Reads entropy-coded data most significant bit first, dropping the zero
+/// byte stuffed after every 0xff
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    filled: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        BitReader {
+            data,
+            pos,
+            buffer: 0,
+            filled: 0,
+        }
+    }
+
+    fn bit(&mut self) -> Result<u32, String> {
+        self.bits(1)
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        while self.filled < count {
+            let byte = match self.data.get(self.pos) {
+                Some(0xff) if self.data.get(self.pos + 1) == Some(&0) => {
+                    self.pos += 2;
+                    0xff
+                }
+                // A marker ends the data; corrupt files run on with zeros
+                Some(0xff) | None => 0,
+                Some(&byte) => {
+                    self.pos += 1;
+                    byte
+                }
+            };
+            self.buffer = self.buffer << 8 | byte as u32;
+            self.filled += 8;
+        }
+        self.filled -= count;
+        Ok((self.buffer >> self.filled) & ((1u64 << count) - 1) as u32)
+    }
+
+    /// Reads a `size` bit value and extends it to its signed value
+    fn receive_extend(&mut self, size: u8) -> Result<i32, String> {
+        if size == 0 {
+            return Ok(0);
+        }
+        if size > 16 {
+            return Err("corrupt JPEG data".to_string());
+        }
+        let value = self.bits(size as u32)? as i32;
+        Ok(if value < 1 << (size - 1) {
+            value - (1 << size) + 1
+        } else {
+            value
+        })
+    }
+
+    /// Skips past the restart marker that comes every restart interval
+    fn restart(&mut self) {
+        self.buffer = 0;
+        self.filled = 0;
+        while let Some(&byte) = self.data.get(self.pos) {
+            self.pos += 1;
+            if byte == 0xff
+                && self
+                    .data
+                    .get(self.pos)
+                    .is_some_and(|m| (0xd0..=0xd7).contains(m))
+            {
+                self.pos += 1;
+                return;
+            }
+        }
+    }
+
+    /// Position of the marker after the entropy-coded data
+    fn next_marker(&self) -> usize {
+        let mut pos = self.pos;
+        while pos + 1 < self.data.len() {
+            let next = self.data[pos + 1];
+            if self.data[pos] == 0xff && next != 0 && next != 0xff && !(0xd0..=0xd7).contains(&next)
+            {
+                return pos;
+            }
+            pos += 1;
+        }
+        self.data.len()
+    }
+}
+
+/// Quantization tables of the JPEG standard's annex K, luminance then
+/// chrominance, by row, for quality 50
+const QUANT_TABLES: [[u16; 64]; 2] = [
+    [
+        16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69,
+        56, 14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81,
+        104, 113, 92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+    ],
+    [
+        17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99,
+        99, 47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+        99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    ],
+];
+/// Code counts by length and symbols of the annex K Huffman tables
+const DC_LUMINANCE: ([u8; 16], &[u8]) = (
+    [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+    &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+);
+const DC_CHROMINANCE: ([u8; 16], &[u8]) = (
+    [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+    &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+);
+const AC_LUMINANCE: ([u8; 16], &[u8]) = (
+    [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d],
+    &[
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61,
+        0x07, 0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52,
+        0xd1, 0xf0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25,
+        0x26, 0x27, 0x28, 0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45,
+        0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64,
+        0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83,
+        0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99,
+        0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6,
+        0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3,
+        0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8,
+        0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+    ],
+);
+const AC_CHROMINANCE: ([u8; 16], &[u8]) = (
+    [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    &[
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61,
+        0x71, 0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33,
+        0x52, 0xf0, 0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18,
+        0x19, 0x1a, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44,
+        0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63,
+        0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a,
+        0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97,
+        0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4,
+        0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca,
+        0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7,
+        0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+    ],
+);
+
+/// Encodes a baseline YCbCr JPEG with 4:2:0 chroma subsampling, `quality`
+/// running from 1 to 100 as in libjpeg
+pub(crate) fn encode_jpeg(image: &Image, quality: u8) -> Vec<u8> {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    let quant = QUANT_TABLES
+        .map(|table| table.map(|step| ((step as u32 * scale + 50) / 100).clamp(1, 255) as u16));
+
+    let mut out = vec![0xff, 0xd8];
+    let segment = |out: &mut Vec<u8>, marker: u8, body: &[u8]| {
+        out.extend_from_slice(&[0xff, marker]);
+        out.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(body);
+    };
+    // JFIF 1.01 with a 1:1 pixel aspect ratio and no thumbnail
+    segment(&mut out, 0xe0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+    for (id, table) in quant.iter().enumerate() {
+        let mut body = vec![id as u8];
+        body.extend(ZIGZAG.iter().map(|&i| table[i] as u8));
+        segment(&mut out, 0xdb, &body);
+    }
+    let mut frame = vec![8];
+    frame.extend_from_slice(&(image.height as u16).to_be_bytes());
+    frame.extend_from_slice(&(image.width as u16).to_be_bytes());
+    // Luma sampled 2x2 against each chroma component
+    frame.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    segment(&mut out, 0xc0, &frame);
+    let tables = [DC_LUMINANCE, AC_LUMINANCE, DC_CHROMINANCE, AC_CHROMINANCE];
+    for (class_id, (counts, symbols)) in [0x00, 0x10, 0x01, 0x11].into_iter().zip(tables) {
+        let mut body = vec![class_id];
+        body.extend_from_slice(&counts);
+        body.extend_from_slice(symbols);
+        segment(&mut out, 0xc4, &body);
+    }
+    segment(&mut out, 0xda, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+
+    let codes = tables.map(|(counts, symbols)| HuffmanCodes::new(&counts, symbols));
+    let mut writer = BitWriter {
+        out,
+        buffer: 0,
+        filled: 0,
+    };
+    let cosines = idct_cosines();
+    // Y, Cb and Cr of a pixel, clamping coordinates to the edges
+    let sample = |x: usize, y: usize| {
+        let [r, g, b] = image
+            .get(x.min(image.width - 1), y.min(image.height - 1))
+            .unwrap_or_default()
+            .map(|value| value as f32);
+        [
+            0.299 * r + 0.587 * g + 0.114 * b,
+            -0.168_736 * r - 0.331_264 * g + 0.5 * b + 128.0,
+            0.5 * r - 0.418_688 * g - 0.081_312 * b + 128.0,
+        ]
+    };
+    let mut predictions = [0i32; 3];
+    if image.width > 0 && image.height > 0 {
+        for mcu_y in (0..image.height).step_by(16) {
+            for mcu_x in (0..image.width).step_by(16) {
+                for (bx, by) in [(0, 0), (8, 0), (0, 8), (8, 8)] {
+                    let mut block = [0f32; 64];
+                    for (i, value) in block.iter_mut().enumerate() {
+                        *value = sample(mcu_x + bx + i % 8, mcu_y + by + i / 8)[0] - 128.0;
+                    }
+                    let coefficients = fdct(&block, &cosines);
+                    encode_block(
+                        &mut writer,
+                        &coefficients,
+                        &quant[0],
+                        &codes[0],
+                        &codes[1],
+                        &mut predictions[0],
+                    );
+                }
+                for (component, prediction) in predictions.iter_mut().enumerate().skip(1) {
+                    let mut block = [0f32; 64];
+                    for (i, value) in block.iter_mut().enumerate() {
+                        let (x, y) = (mcu_x + i % 8 * 2, mcu_y + i / 8 * 2);
+                        let sum: f32 = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                            .iter()
+                            .map(|(dx, dy)| sample(x + dx, y + dy)[component])
+                            .sum();
+                        *value = sum / 4.0 - 128.0;
+                    }
+                    let coefficients = fdct(&block, &cosines);
+                    encode_block(
+                        &mut writer,
+                        &coefficients,
+                        &quant[1],
+                        &codes[2],
+                        &codes[3],
+                        prediction,
+                    );
+                }
+            }
+        }
+    }
+    let mut out = writer.finish();
+    out.extend_from_slice(&[0xff, 0xd9]);
+    out
+}
+
+/// Forward DCT of one block, the transpose of [`idct`]
+fn fdct(samples: &[f32; 64], cosines: &[[f32; 8]; 8]) -> [f32; 64] {
+    let mut rows = [0f32; 64];
+    for y in 0..8 {
+        for u in 0..8 {
+            rows[y * 8 + u] = (0..8).map(|x| cosines[x][u] * samples[y * 8 + x]).sum();
+        }
+    }
+    let mut block = [0f32; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            block[v * 8 + u] = (0..8).map(|y| cosines[y][v] * rows[y * 8 + u]).sum();
+        }
+    }
+    block
+}
+
+/// Quantizes a block and writes its DC difference and run-length coded
+/// AC coefficients
+fn encode_block(
+    writer: &mut BitWriter,
+    block: &[f32; 64],
+    quant: &[u16; 64],
+    dc: &HuffmanCodes,
+    ac: &HuffmanCodes,
+    prediction: &mut i32,
+) {
+    let values = ZIGZAG.map(|i| (block[i] / quant[i] as f32).round() as i32);
+    let difference = values[0] - *prediction;
+    *prediction = values[0];
+    let (size, bits) = magnitude(difference);
+    dc.write(writer, size);
+    writer.write(bits, size);
+
+    let mut run = 0;
+    for &value in &values[1..] {
+        if value == 0 {
+            run += 1;
+            continue;
+        }
+        while run > 15 {
+            // ZRL, sixteen zeros
+            ac.write(writer, 0xf0);
+            run -= 16;
+        }
+        let (size, bits) = magnitude(value);
+        ac.write(writer, (run << 4) as u8 | size);
+        writer.write(bits, size);
+        run = 0;
+    }
+    if run > 0 {
+        // EOB, zeros to the end of the block
+        ac.write(writer, 0x00);
+    }
+}
+
+/// Bit count of a coefficient and the bits that code it, negative values
+/// as their ones' complement
+fn magnitude(value: i32) -> (u8, u32) {
+    let size = (32 - value.unsigned_abs().leading_zeros()) as u8;
+    let bits = if value < 0 { value - 1 } else { value } as u32;
+    (size, bits & ((1u64 << size) - 1) as u32)
+}
+
+/// A JPEG Huffman table as the code and length of every symbol
+struct HuffmanCodes {
+    codes: [(u16, u8); 256],
+}
+
+impl HuffmanCodes {
+    fn new(counts: &[u8; 16], symbols: &[u8]) -> Self {
+        let mut codes = [(0, 0); 256];
+        let (mut code, mut index) = (0u16, 0);
+        for (length, &count) in counts.iter().enumerate() {
+            for &symbol in &symbols[index..index + count as usize] {
+                codes[symbol as usize] = (code, length as u8 + 1);
+                code += 1;
+            }
+            index += count as usize;
+            code <<= 1;
+        }
+        HuffmanCodes { codes }
+    }
+
+    fn write(&self, writer: &mut BitWriter, symbol: u8) {
+        let (code, length) = self.codes[symbol as usize];
+        writer.write(code as u32, length);
+    }
+}
+
+/// Writes bits most significant first, stuffing a zero byte after every
+/// 0xff as entropy coded data needs
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    filled: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, bits: u32, count: u8) {
+        for shift in (0..count).rev() {
+            self.buffer = self.buffer << 1 | (bits >> shift) & 1;
+            self.filled += 1;
+            if self.filled == 8 {
+                self.push();
+            }
+        }
+    }
+
+    fn push(&mut self) {
+        let byte = self.buffer as u8;
+        self.out.push(byte);
+        if byte == 0xff {
+            self.out.push(0);
+        }
+        self.buffer = 0;
+        self.filled = 0;
+    }
+
+    /// Pads the last byte with one bits
+    fn finish(mut self) -> Vec<u8> {
+        if self.filled > 0 {
+            let count = 8 - self.filled;
+            self.write((1 << count) - 1, count);
+        }
+        self.out
+    }
+}
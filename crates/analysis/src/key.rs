@@ -0,0 +1,158 @@
+use std::fmt;
+use std::path::Path;
+
+use mogbox_io::AudioFile;
+
+use crate::for_each_block;
+use crate::spectrum::Fft;
+use crate::window::Window;
+
+/// FFT size and hop of the chroma analysis; large enough to tell
+/// semitones apart down to the lowest pitch counted
+const CHROMA_FFT_SIZE: usize = 8192;
+const CHROMA_HOP: usize = 4096;
+/// Pitches counted towards the chroma, in Hz
+const CHROMA_LOW: f32 = 100.0;
+const CHROMA_HIGH: f32 = 4000.0;
+
+/// Krumhansl-Kessler key profiles, from the tonic up by semitone
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+const PITCH_NAMES: [&str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+/// A musical key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    /// Pitch class of the tonic, 0 for C up to 11 for B
+    pub tonic: u8,
+    pub mode: Mode,
+}
+
+impl Key {
+    /// Short form used in tags, e.g. `Am` or `Eb`
+    pub fn short_name(&self) -> String {
+        let name = PITCH_NAMES[self.tonic as usize % 12];
+        match self.mode {
+            Mode::Major => name.to_string(),
+            Mode::Minor => format!("{}m", name),
+        }
+    }
+
+    /// Position on the Camelot wheel, e.g. `8A` for A minor, where
+    /// neighbouring numbers and letters mix harmonically
+    pub fn camelot(&self) -> String {
+        // Relative majors share a number with their minors
+        let (major, letter) = match self.mode {
+            Mode::Major => (self.tonic as usize, 'B'),
+            Mode::Minor => (self.tonic as usize + 3, 'A'),
+        };
+        // Each step up the wheel is a fifth, C major being 8B
+        let number = (major * 7 + 7) % 12 + 1;
+        format!("{}{}", number, letter)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self.mode {
+            Mode::Major => "major",
+            Mode::Minor => "minor",
+        };
+        write!(f, "{} {}", PITCH_NAMES[self.tonic as usize % 12], mode)
+    }
+}
+
+/// An estimated key
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEstimate {
+    pub key: Key,
+    /// Correlation of the file's chroma with the key's profile, 0.0 to 1.0
+    pub confidence: f32,
+}
+
+/// Estimates the key of `path` by matching how much of each pitch class it
+/// holds against the major and minor profile of every key
+pub fn key(path: &Path) -> Result<KeyEstimate, String> {
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let chroma = chroma(&mut file)?;
+    if chroma.iter().all(|energy| *energy <= 0.0) {
+        return Err("no pitched content to estimate a key from".to_string());
+    }
+
+    let (key, confidence) = (0..12u8)
+        .flat_map(|tonic| {
+            [(Mode::Major, &MAJOR_PROFILE), (Mode::Minor, &MINOR_PROFILE)].map(|(mode, profile)| {
+                let rotated: Vec<f32> = (0..12)
+                    .map(|pitch| profile[(pitch + 12 - tonic as usize) % 12])
+                    .collect();
+                (Key { tonic, mode }, correlation(&chroma, &rotated))
+            })
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .ok_or("no keys to match")?;
+    Ok(KeyEstimate {
+        key,
+        confidence: confidence.clamp(0.0, 1.0),
+    })
+}
+
+/// Total magnitude per pitch class, C first
+fn chroma(file: &mut AudioFile) -> Result<[f32; 12], String> {
+    let sample_rate = file.sample_rate as f32;
+    let mut fft = Fft::new(CHROMA_FFT_SIZE, Window::Hann);
+    let bin_width = sample_rate / CHROMA_FFT_SIZE as f32;
+    // Pitch class of each bin, for the bins in range
+    let classes: Vec<Option<usize>> = (0..fft.bins())
+        .map(|bin| {
+            let frequency = bin as f32 * bin_width;
+            (CHROMA_LOW..=CHROMA_HIGH).contains(&frequency).then(|| {
+                let semitones = (12.0 * (frequency / 440.0).log2()).round() as i32;
+                (semitones + 9).rem_euclid(12) as usize
+            })
+        })
+        .collect();
+
+    let mut power = vec![0.0f64; fft.bins()];
+    let mut chroma = [0.0f32; 12];
+    for_each_block(file, CHROMA_FFT_SIZE, CHROMA_HOP, |block| {
+        power.fill(0.0);
+        fft.accumulate(block, &mut power);
+        for (class, bin) in classes.iter().zip(&power) {
+            if let Some(class) = class {
+                chroma[*class] += (*bin as f32).sqrt();
+            }
+        }
+    })?;
+    Ok(chroma)
+}
+
+/// Pearson correlation of two equally long series
+fn correlation(a: &[f32], b: &[f32]) -> f32 {
+    let mean = |values: &[f32]| values.iter().sum::<f32>() / values.len() as f32;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let (mut covariance, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a) * (x - mean_a);
+        var_b += (y - mean_b) * (y - mean_b);
+    }
+    let spread = (var_a * var_b).sqrt();
+    if spread > 0.0 {
+        covariance / spread
+    } else {
+        0.0
+    }
+}
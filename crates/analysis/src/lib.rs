@@ -0,0 +1,89 @@
+// Analysis crate
+
+pub mod acoustid;
+pub mod colormap;
+pub mod correlation;
+pub mod fingerprint;
+pub mod graphics;
+pub mod image;
+mod inflate;
+mod jpeg;
+mod json;
+pub mod key;
+pub mod spectrogram;
+pub mod spectrum;
+pub mod tempo;
+pub mod visualize;
+pub mod waveform;
+pub mod window;
+
+pub use acoustid::{lookup, Candidate};
+pub use colormap::Colormap;
+pub use correlation::{
+    correlation_report, CorrelationMeter, CorrelationReport, CORRELATION_WINDOW,
+};
+pub use fingerprint::{fingerprint, Fingerprint, Fingerprinter};
+pub use graphics::Graphics;
+pub use image::{parse_color, Image};
+pub use key::{key, Key, KeyEstimate, Mode};
+pub use spectrogram::{spectrogram, SpectrogramOptions};
+pub use spectrum::{spectrum, Fft, Spectrum};
+pub use tempo::{tempo, Tempo};
+pub use visualize::{ChannelLevel, LevelMeter, PhaseScope, SpectrumBars, METER_FLOOR_DB};
+pub use waveform::{
+    peaks, text_waveform_peaks, waveform_image, waveform_peaks, waveform_svg, waveform_text,
+    PeakBits, Peaks, WaveformOptions,
+};
+pub use window::Window;
+
+use std::collections::VecDeque;
+
+use mogbox_io::AudioFile;
+
+/// Reads up to `frames` frames from the current position of `file`,
+/// averaging the channels down to mono
+pub(crate) fn read_mono(file: &mut AudioFile, frames: usize) -> Result<Vec<f32>, String> {
+    let channels = file.channels.max(1) as usize;
+    let mut mono = Vec::with_capacity(frames);
+    while mono.len() < frames {
+        let Some(samples) = file.next_samples()? else {
+            break;
+        };
+        mono.extend(
+            samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+    }
+    mono.truncate(frames);
+    Ok(mono)
+}
+
+/// Reads the rest of `file` mixed to mono in blocks of `size` samples,
+/// `hop` apart, calling `block` with each
+pub(crate) fn for_each_block(
+    file: &mut AudioFile,
+    size: usize,
+    hop: usize,
+    mut block: impl FnMut(&[f32]),
+) -> Result<(), String> {
+    let channels = file.channels.max(1) as usize;
+    let hop = hop.clamp(1, size.max(1));
+    let mut pending: VecDeque<f32> = VecDeque::with_capacity(size * 2);
+    let mut current = vec![0.0f32; size];
+    while let Some(samples) = file.next_samples()? {
+        pending.extend(
+            samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+        while pending.len() >= size {
+            for (slot, sample) in current.iter_mut().zip(&pending) {
+                *slot = *sample;
+            }
+            pending.drain(..hop);
+            block(&current);
+        }
+    }
+    Ok(())
+}
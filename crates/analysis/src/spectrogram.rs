@@ -0,0 +1,101 @@
+use std::path::Path;
+
+use mogbox_io::AudioFile;
+
+use crate::colormap::Colormap;
+use crate::image::Image;
+use crate::spectrum::{to_db, Fft};
+use crate::window::Window;
+
+/// How a spectrogram is computed and drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpectrogramOptions {
+    /// FFT size in samples; the image is half this high
+    pub size: usize,
+    /// Samples between the starts of neighbouring columns
+    pub hop: usize,
+    pub window: Window,
+    pub colormap: Colormap,
+    /// Level drawn as the quietest color, in dBFS
+    pub floor: f32,
+}
+
+impl Default for SpectrogramOptions {
+    fn default() -> Self {
+        SpectrogramOptions {
+            size: 2048,
+            hop: 512,
+            window: Window::Hann,
+            colormap: Colormap::Magma,
+            floor: -120.0,
+        }
+    }
+}
+
+/// Draws the short-time Fourier transform of a whole file, the channels
+/// mixed to mono: time runs left to right, frequency bottom to top up to
+/// Nyquist, and color shows the level from `floor` to full scale
+pub fn spectrogram(path: &Path, options: &SpectrogramOptions) -> Result<Image, String> {
+    if options.size < 2 || options.hop == 0 {
+        return Err("the FFT size and hop must be positive".to_string());
+    }
+    if options.floor >= 0.0 {
+        return Err("the floor must be below 0 dBFS".to_string());
+    }
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let channels = file.channels.max(1) as usize;
+    let mut fft = Fft::new(options.size, options.window);
+    let height = fft.bins() - 1;
+    let mut power = vec![0.0f64; fft.bins()];
+
+    // Colors of each column, lowest frequency first
+    let mut columns: Vec<Vec<[u8; 3]>> = Vec::new();
+    let mut column = |samples: &[f32]| {
+        power.fill(0.0);
+        fft.accumulate(samples, &mut power);
+        columns.push(
+            power[1..]
+                .iter()
+                .map(|power| {
+                    let level = 1.0 - to_db(*power) / options.floor;
+                    options.colormap.color(level)
+                })
+                .collect(),
+        );
+    };
+
+    let mut pending: Vec<f32> = Vec::new();
+    // Samples still to drop before the next column starts, when the hop
+    // is longer than the FFT
+    let mut skip = 0;
+    let mut drawn = false;
+    while let Some(samples) = file.next_samples()? {
+        let mono = samples
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32);
+        pending.extend(mono.skip(skip));
+        skip = skip.saturating_sub(samples.len() / channels);
+        while pending.len() >= options.size {
+            column(&pending[..options.size]);
+            drawn = true;
+            let dropped = options.hop.min(pending.len());
+            pending.drain(..dropped);
+            skip = options.hop - dropped;
+        }
+    }
+    // Audio shorter than one FFT still gets a column, padded with silence
+    if !drawn && !pending.is_empty() {
+        column(&pending);
+    }
+    if columns.is_empty() {
+        return Err("no audio to draw".to_string());
+    }
+
+    let mut image = Image::new(columns.len(), height, [0, 0, 0]);
+    for (x, column) in columns.iter().enumerate() {
+        for (bin, color) in column.iter().enumerate() {
+            image.set(x, height - 1 - bin, *color);
+        }
+    }
+    Ok(image)
+}
@@ -0,0 +1,221 @@
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use mogbox_io::AudioFile;
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+
+use crate::read_mono;
+use crate::window::Window;
+
+/// Lowest level reported, so silent bins don't come out as -inf
+pub const FLOOR_DB: f32 = -200.0;
+
+/// Nominal centre frequencies of the third-octave bands, per IEC 61260
+const THIRD_OCTAVES: [f32; 31] = [
+    20.0, 25.0, 31.5, 40.0, 50.0, 63.0, 80.0, 100.0, 125.0, 160.0, 200.0, 250.0, 315.0, 400.0,
+    500.0, 630.0, 800.0, 1000.0, 1250.0, 1600.0, 2000.0, 2500.0, 3150.0, 4000.0, 5000.0, 6300.0,
+    8000.0, 10000.0, 12500.0, 16000.0, 20000.0,
+];
+
+/// A windowed real FFT of a fixed size, scaled so a full-scale sine reads
+/// 1.0 in its bin
+pub struct Fft {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    /// Turns bin magnitudes into sine amplitudes
+    scale: f32,
+    input: Vec<f32>,
+    output: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl Fft {
+    pub fn new(size: usize, window: Window) -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(size);
+        let window = window.coefficients(size);
+        let scale = 2.0 / window.iter().sum::<f32>().max(f32::MIN_POSITIVE);
+        Fft {
+            input: fft.make_input_vec(),
+            output: fft.make_output_vec(),
+            scratch: fft.make_scratch_vec(),
+            fft,
+            window,
+            scale,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Bins from 0 Hz to Nyquist
+    pub fn bins(&self) -> usize {
+        self.output.len()
+    }
+
+    /// Equivalent noise bandwidth of the window in bins: how many bins one
+    /// sine's power is smeared over
+    pub fn bandwidth(&self) -> f32 {
+        let sum: f32 = self.window.iter().sum();
+        let squares: f32 = self.window.iter().map(|w| w * w).sum();
+        self.size() as f32 * squares / (sum * sum).max(f32::MIN_POSITIVE)
+    }
+
+    /// Adds the power (squared amplitude) of each bin of `samples` to
+    /// `power`. Missing samples count as silence.
+    pub fn accumulate(&mut self, samples: &[f32], power: &mut [f64]) {
+        for (i, (input, w)) in self.input.iter_mut().zip(&self.window).enumerate() {
+            *input = samples.get(i).copied().unwrap_or(0.0) * w;
+        }
+        // Buffers always come from the plan, so the sizes match
+        let _ = self
+            .fft
+            .process_with_scratch(&mut self.input, &mut self.output, &mut self.scratch);
+        for (sum, bin) in power.iter_mut().zip(&self.output) {
+            let amplitude = bin.norm() * self.scale;
+            *sum += (amplitude * amplitude) as f64;
+        }
+    }
+}
+
+/// Magnitude spectrum of a stretch of audio
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spectrum {
+    pub sample_rate: u32,
+    /// FFT size the spectrum was taken with
+    pub size: usize,
+    /// Level of each bin in dBFS, from 0 Hz to Nyquist
+    pub magnitudes: Vec<f32>,
+    /// Equivalent noise bandwidth of the window, in bins
+    pub bandwidth: f32,
+}
+
+impl Spectrum {
+    /// Centre frequency of `bin` in Hz
+    pub fn frequency(&self, bin: usize) -> f32 {
+        bin as f32 * self.sample_rate as f32 / self.size as f32
+    }
+
+    /// The `count` strongest local maxima as frequency and level, strongest first
+    pub fn peaks(&self, count: usize) -> Vec<(f32, f32)> {
+        let mut peaks: Vec<(usize, f32)> = self
+            .magnitudes
+            .windows(3)
+            .enumerate()
+            .filter(|(_, w)| w[1] > w[0] && w[1] >= w[2] && w[1] > FLOOR_DB)
+            .map(|(i, w)| (i + 1, w[1]))
+            .collect();
+        peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+        peaks
+            .into_iter()
+            .take(count)
+            .map(|(bin, level)| (self.frequency(bin), level))
+            .collect()
+    }
+
+    /// Levels of the third-octave bands from 20 Hz up to Nyquist, as
+    /// nominal centre frequency and level in dBFS
+    pub fn bands(&self) -> Vec<(f32, f32)> {
+        let nyquist = self.sample_rate as f32 / 2.0;
+        THIRD_OCTAVES
+            .iter()
+            .enumerate()
+            .map(|(n, nominal)| {
+                // Exact base-10 centres, 20 Hz being band -17 from 1 kHz
+                let centre = 1000.0 * 10f32.powf((n as f32 - 17.0) / 10.0);
+                (
+                    *nominal,
+                    centre / 10f32.powf(0.05),
+                    centre * 10f32.powf(0.05),
+                )
+            })
+            .take_while(|(_, _, high)| *high <= nyquist)
+            .map(|(nominal, low, high)| {
+                let power: f64 = self
+                    .magnitudes
+                    .iter()
+                    .enumerate()
+                    .filter(|(bin, _)| (low..high).contains(&self.frequency(*bin)))
+                    .map(|(_, level)| 10f64.powf(*level as f64 / 10.0))
+                    .sum();
+                (nominal, to_db(power / self.bandwidth as f64))
+            })
+            .collect()
+    }
+
+    /// Writes every bin as `frequency_hz,magnitude_db` lines
+    pub fn write_csv(&self, path: &Path) -> Result<(), String> {
+        let file = std::fs::File::create(path)
+            .map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+        let mut writer = std::io::BufWriter::new(file);
+        let mut write = || -> std::io::Result<()> {
+            writeln!(writer, "frequency_hz,magnitude_db")?;
+            for (bin, level) in self.magnitudes.iter().enumerate() {
+                writeln!(writer, "{:.3},{:.2}", self.frequency(bin), level)?;
+            }
+            writer.flush()
+        };
+        write().map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Spectrum of `path` from `at`, the channels mixed to mono. Over a
+/// `duration` longer than one FFT, the power of half-overlapping blocks is
+/// averaged; otherwise a single block of `size` samples is taken.
+pub fn spectrum(
+    path: &Path,
+    at: Duration,
+    duration: Option<Duration>,
+    size: usize,
+    window: Window,
+) -> Result<Spectrum, String> {
+    if size < 2 {
+        return Err(format!("FFT size {} is too small", size));
+    }
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let sample_rate = file.sample_rate;
+    let start = (at.as_secs_f64() * sample_rate as f64).round() as u64;
+    if start > 0 {
+        file.seek(start)?;
+    }
+    let frames = duration
+        .map(|duration| (duration.as_secs_f64() * sample_rate as f64).round() as usize)
+        .unwrap_or(0)
+        .max(size);
+    let samples = read_mono(&mut file, frames)?;
+    if samples.is_empty() {
+        return Err("no audio at that position".to_string());
+    }
+
+    let mut fft = Fft::new(size, window);
+    let mut power = vec![0.0f64; fft.bins()];
+    let hop = (size / 2).max(1);
+    let mut blocks = 0;
+    let mut offset = 0;
+    loop {
+        fft.accumulate(&samples[offset..], &mut power);
+        blocks += 1;
+        offset += hop;
+        if offset + size > samples.len() {
+            break;
+        }
+    }
+
+    Ok(Spectrum {
+        sample_rate,
+        size,
+        bandwidth: fft.bandwidth(),
+        magnitudes: power
+            .into_iter()
+            .map(|power| to_db(power / blocks as f64))
+            .collect(),
+    })
+}
+
+/// A power as a level in dB, no lower than [`FLOOR_DB`]
+pub(crate) fn to_db(power: f64) -> f32 {
+    (10.0 * power.max(f64::MIN_POSITIVE).log10()).max(FLOOR_DB as f64) as f32
+}
@@ -0,0 +1,134 @@
+use std::path::Path;
+
+use mogbox_io::AudioFile;
+
+use crate::for_each_block;
+use crate::spectrum::Fft;
+use crate::window::Window;
+
+/// FFT size and hop of the onset detection, at any sample rate
+const ONSET_FFT_SIZE: usize = 1024;
+const ONSET_HOP: usize = 512;
+/// Compression applied to bin magnitudes before taking the flux, so quiet
+/// onsets count as well as loud ones
+const ONSET_COMPRESSION: f32 = 1000.0;
+/// Stretch of the onset envelope whose mean is taken off, in seconds
+const ONSET_MEAN_WINDOW: f32 = 0.5;
+/// Tempo the autocorrelation leans towards when picking between a tempo
+/// and its half or double, and how strongly, in octaves
+const PREFERRED_BPM: f32 = 120.0;
+const PREFERENCE_OCTAVES: f32 = 0.7;
+/// Beats the final tempo is measured over, for a finer reading than one
+/// beat's lag allows
+const REFINE_BEATS: usize = 4;
+/// Lowest confidence taken as a steady beat
+const MIN_CONFIDENCE: f32 = 0.1;
+
+/// An estimated tempo
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tempo {
+    pub bpm: f32,
+    /// How strongly the onsets repeat at that tempo, 0.0 to 1.0
+    pub confidence: f32,
+}
+
+/// Estimates the tempo of `path` between `min_bpm` and `max_bpm` from the
+/// autocorrelation of its onset strength
+pub fn tempo(path: &Path, min_bpm: f32, max_bpm: f32) -> Result<Tempo, String> {
+    if !(min_bpm > 0.0 && min_bpm < max_bpm) {
+        return Err(format!(
+            "invalid tempo range: {} to {} BPM",
+            min_bpm, max_bpm
+        ));
+    }
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let envelope_rate = file.sample_rate as f32 / ONSET_HOP as f32;
+    let envelope = onset_envelope(&mut file)?;
+
+    // Lags of one beat at the edges of the range, in envelope frames
+    let shortest = ((60.0 * envelope_rate / max_bpm).floor() as usize).max(1);
+    let longest = (60.0 * envelope_rate / min_bpm).ceil() as usize;
+    if envelope.len() < longest * REFINE_BEATS * 2 {
+        return Err("too short to estimate a tempo".to_string());
+    }
+
+    let autocorrelation = |lag: usize| -> f32 {
+        envelope
+            .iter()
+            .zip(&envelope[lag..])
+            .map(|(a, b)| a * b)
+            .sum::<f32>()
+            / (envelope.len() - lag) as f32
+    };
+    let energy = autocorrelation(0);
+    if energy <= 0.0 {
+        return Err("no onsets to estimate a tempo from".to_string());
+    }
+    let weight = |lag: usize| {
+        let octaves =
+            (60.0 * envelope_rate / lag as f32 / PREFERRED_BPM).log2() / PREFERENCE_OCTAVES;
+        (-0.5 * octaves * octaves).exp()
+    };
+    let (beat, _) = (shortest..=longest)
+        .map(|lag| (lag, autocorrelation(lag) * weight(lag)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .ok_or("tempo range too narrow")?;
+
+    let confidence = (autocorrelation(beat) / energy).clamp(0.0, 1.0);
+    if confidence < MIN_CONFIDENCE {
+        return Err("no steady beat found".to_string());
+    }
+
+    // Find the peak over several beats, between whole lags, for a finer tempo
+    let around = beat * REFINE_BEATS;
+    let (peak, _) = (around - 2..=around + 2)
+        .map(|lag| (lag, autocorrelation(lag)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((around, 0.0));
+    let (before, at, after) = (
+        autocorrelation(peak - 1),
+        autocorrelation(peak),
+        autocorrelation(peak + 1),
+    );
+    let curve = before - 2.0 * at + after;
+    let offset = if curve < 0.0 {
+        (0.5 * (before - after) / curve).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    let lag = (peak as f32 + offset) / REFINE_BEATS as f32;
+    Ok(Tempo {
+        bpm: 60.0 * envelope_rate / lag,
+        confidence,
+    })
+}
+
+/// The spectral flux of `file` per hop, with its local mean taken off and
+/// negative values clipped so only onsets remain
+fn onset_envelope(file: &mut AudioFile) -> Result<Vec<f32>, String> {
+    let mut fft = Fft::new(ONSET_FFT_SIZE, Window::Hann);
+    let mut power = vec![0.0f64; fft.bins()];
+    let mut previous = vec![0.0f32; fft.bins()];
+    let mut flux = Vec::new();
+    for_each_block(file, ONSET_FFT_SIZE, ONSET_HOP, |block| {
+        power.fill(0.0);
+        fft.accumulate(block, &mut power);
+        let mut sum = 0.0;
+        for (last, bin) in previous.iter_mut().zip(&power) {
+            let magnitude = (1.0 + ONSET_COMPRESSION * (*bin as f32).sqrt()).ln();
+            sum += (magnitude - *last).max(0.0);
+            *last = magnitude;
+        }
+        flux.push(sum);
+    })?;
+
+    let rate = file.sample_rate as f32 / ONSET_HOP as f32;
+    let half = ((ONSET_MEAN_WINDOW * rate / 2.0) as usize).max(1);
+    Ok((0..flux.len())
+        .map(|i| {
+            let window = &flux[i.saturating_sub(half)..(i + half + 1).min(flux.len())];
+            let mean = window.iter().sum::<f32>() / window.len() as f32;
+            (flux[i] - mean).max(0.0)
+        })
+        .collect())
+}
@@ -0,0 +1,302 @@
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use mogbox_engine::linear_to_db;
+
+use crate::correlation::CorrelationMeter;
+use crate::image::Image;
+use crate::spectrum::{to_db, Fft};
+use crate::window::Window;
+
+/// FFT size of the live spectrum
+const BARS_FFT_SIZE: usize = 2048;
+/// Frequency range the bars are spread over, log spaced
+const BARS_LOW: f32 = 30.0;
+const BARS_HIGH: f32 = 16000.0;
+/// Level drawn as an empty bar, in dBFS
+const BARS_FLOOR_DB: f32 = -72.0;
+/// How far a bar may fall per update, as a fraction of its height
+const BARS_FALL: f32 = 0.05;
+
+/// Block characters from empty to a full cell, in eighths
+const EIGHTHS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// A spectrum of the latest audio as log-spaced bars, for drawing while it
+/// plays. Bars jump up at once and fall back slowly.
+pub struct SpectrumBars {
+    fft: Fft,
+    /// First and one-past-last FFT bin of each bar
+    ranges: Vec<(usize, usize)>,
+    levels: Vec<f32>,
+    power: Vec<f64>,
+}
+
+impl SpectrumBars {
+    pub fn new(sample_rate: u32, bars: usize) -> Self {
+        let fft = Fft::new(BARS_FFT_SIZE, Window::Hann);
+        let bins = fft.bins();
+        let bin_width = sample_rate as f32 / BARS_FFT_SIZE as f32;
+        let high = BARS_HIGH.min(sample_rate as f32 / 2.0);
+        let bars = bars.max(1);
+        let edge = |bar: usize| BARS_LOW * (high / BARS_LOW).powf(bar as f32 / bars as f32);
+        let ranges = (0..bars)
+            .map(|bar| {
+                let start = ((edge(bar) / bin_width).round() as usize).clamp(1, bins - 1);
+                let end = ((edge(bar + 1) / bin_width).round() as usize).clamp(start + 1, bins);
+                (start, end)
+            })
+            .collect();
+        SpectrumBars {
+            ranges,
+            levels: vec![0.0; bars],
+            power: vec![0.0; bins],
+            fft,
+        }
+    }
+
+    /// Frames of audio each update looks at
+    pub fn size(&self) -> usize {
+        self.fft.size()
+    }
+
+    /// Analyses the latest interleaved `samples`, the channels mixed to mono
+    pub fn update(&mut self, samples: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        let mono: Vec<f32> = samples
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect();
+        // Right-align short input so the newest audio is always included
+        let mut block = vec![0.0; self.size().saturating_sub(mono.len())];
+        block.extend_from_slice(&mono[mono.len().saturating_sub(self.size())..]);
+
+        self.power.fill(0.0);
+        self.fft.accumulate(&block, &mut self.power);
+        for (level, (start, end)) in self.levels.iter_mut().zip(&self.ranges) {
+            let power = self.power[*start..*end].iter().copied().fold(0.0, f64::max);
+            let target = ((to_db(power) - BARS_FLOOR_DB) / -BARS_FLOOR_DB).clamp(0.0, 1.0);
+            *level = target.max(*level - BARS_FALL);
+        }
+    }
+
+    /// Height of each bar, 0.0 to 1.0, lowest frequency first
+    pub fn levels(&self) -> &[f32] {
+        &self.levels
+    }
+
+    /// Draws the bars `rows` characters tall, one column per bar
+    pub fn render(&self, rows: usize) -> String {
+        let mut text = String::with_capacity((self.levels.len() * 3 + 1) * rows);
+        for row in 0..rows {
+            let base = (rows - 1 - row) * 8;
+            for level in &self.levels {
+                let eighths = (level * (rows * 8) as f32).round() as usize;
+                text.push(EIGHTHS[eighths.saturating_sub(base).min(8)]);
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Lowest level a meter shows, in dBFS
+pub const METER_FLOOR_DB: f32 = -60.0;
+/// How long a held peak stays before falling
+const METER_HOLD: Duration = Duration::from_millis(1500);
+/// How fast the peak and held peak fall back, in dB per second
+const METER_FALL_DB: f32 = 20.0;
+
+/// Live level of one channel, all in dBFS
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelLevel {
+    /// Recent peak, falling back at a steady rate
+    pub peak: f32,
+    pub rms: f32,
+    /// Highest recent peak, kept for a moment before it falls
+    pub hold: f32,
+    held_for: Duration,
+}
+
+impl Default for ChannelLevel {
+    fn default() -> Self {
+        ChannelLevel {
+            peak: METER_FLOOR_DB,
+            rms: METER_FLOOR_DB,
+            hold: METER_FLOOR_DB,
+            held_for: Duration::ZERO,
+        }
+    }
+}
+
+/// A per-channel peak and RMS meter with peak hold, for drawing while audio
+/// plays
+#[derive(Clone, Debug, Default)]
+pub struct LevelMeter {
+    levels: Vec<ChannelLevel>,
+}
+
+impl LevelMeter {
+    pub fn new() -> Self {
+        LevelMeter::default()
+    }
+
+    /// Updates the meter with the latest interleaved `samples`, `elapsed`
+    /// after the last update. RMS is taken over all of `samples`, the
+    /// peak over the last `elapsed` of them.
+    pub fn update(
+        &mut self,
+        samples: &[f32],
+        channels: usize,
+        sample_rate: u32,
+        elapsed: Duration,
+    ) {
+        let channels = channels.max(1);
+        self.levels.resize(channels, ChannelLevel::default());
+        let frames = samples.len() / channels;
+        let recent = ((elapsed.as_secs_f64() * sample_rate as f64).ceil() as usize).min(frames);
+        let fall = METER_FALL_DB * elapsed.as_secs_f32();
+
+        for (channel, level) in self.levels.iter_mut().enumerate() {
+            let channel_samples = samples.iter().skip(channel).step_by(channels);
+            let sum: f64 = channel_samples.clone().map(|s| (*s as f64).powi(2)).sum();
+            let peak = channel_samples
+                .skip(frames - recent)
+                .fold(0.0f32, |peak, s| peak.max(s.abs()));
+            let to_meter = |linear: f32| linear_to_db(linear).max(METER_FLOOR_DB);
+
+            level.rms = to_meter((sum / frames.max(1) as f64).sqrt() as f32);
+            level.peak = to_meter(peak).max(level.peak - fall);
+            if level.peak >= level.hold {
+                level.hold = level.peak;
+                level.held_for = Duration::ZERO;
+            } else if level.held_for < METER_HOLD {
+                level.held_for += elapsed;
+            } else {
+                level.hold = (level.hold - fall).max(level.peak);
+            }
+        }
+    }
+
+    pub fn levels(&self) -> &[ChannelLevel] {
+        &self.levels
+    }
+
+    /// Draws one line per channel: a bar `width` characters long with the
+    /// RMS solid, the peak shaded and the held peak as a marker, then the
+    /// levels in numbers
+    pub fn render(&self, width: usize) -> String {
+        let position = |db: f32| {
+            (((db - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0.0, 1.0) * width as f32).round()
+                as usize
+        };
+        let mut text = String::new();
+        for (channel, level) in self.levels.iter().enumerate() {
+            let (rms, peak, hold) = (
+                position(level.rms),
+                position(level.peak),
+                position(level.hold),
+            );
+            let _ = write!(text, "{:>2} ", channel + 1);
+            for x in 0..width {
+                text.push(if x + 1 == hold && hold > peak {
+                    '│'
+                } else if x < rms {
+                    '█'
+                } else if x < peak {
+                    '▒'
+                } else {
+                    '·'
+                });
+            }
+            let _ = writeln!(
+                text,
+                " {:>6.1} RMS {:>6.1} peak {:>6.1} hold",
+                level.rms, level.peak, level.hold
+            );
+        }
+        text
+    }
+}
+
+/// Smallest level a goniometer scales up to fill its plot, so near-silence
+/// stays a dot instead of noise blown up to full size
+const SCOPE_MIN_SCALE: f32 = 0.05;
+
+/// A goniometer of the latest audio with a correlation bar under it, for
+/// drawing while it plays. Mono shows as a vertical line, wide stereo as a
+/// round cloud and out-of-phase content leans towards horizontal.
+#[derive(Clone, Debug, Default)]
+pub struct PhaseScope {
+    /// Side and mid of each frame, the goniometer's x and y
+    points: Vec<(f32, f32)>,
+    correlation: Option<f32>,
+}
+
+impl PhaseScope {
+    pub fn new() -> Self {
+        PhaseScope::default()
+    }
+
+    /// Takes the latest interleaved `samples`; a single channel counts as
+    /// both sides
+    pub fn update(&mut self, samples: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        let mut meter = CorrelationMeter::new();
+        meter.process(samples, channels);
+        self.correlation = meter.correlation();
+        self.points = samples
+            .chunks_exact(channels)
+            .map(|frame| {
+                let (left, right) = (frame[0], frame.get(1).copied().unwrap_or(frame[0]));
+                (left - right, left + right)
+            })
+            .collect();
+    }
+
+    /// Correlation of the latest audio, `None` while either side is silent
+    pub fn correlation(&self) -> Option<f32> {
+        self.correlation
+    }
+
+    /// Draws a square goniometer `rows` characters tall, then a
+    /// correlation bar as wide from -1 to +1 with its value
+    pub fn render(&self, rows: usize) -> String {
+        let size = rows * 4;
+        let color = [0xff; 3];
+        let mut image = Image::new(size, size, [0; 3]);
+        let scale = self.points.iter().fold(SCOPE_MIN_SCALE, |scale, (x, y)| {
+            scale.max(x.abs()).max(y.abs())
+        });
+        let half = (size - 1) as f32 / 2.0;
+        for (x, y) in &self.points {
+            image.set(
+                (half + x / scale * half).round() as usize,
+                (half - y / scale * half).round() as usize,
+                color,
+            );
+        }
+        let mut text = image.to_braille(color);
+
+        let width = size / 2;
+        let centre = width / 2;
+        let marker = self
+            .correlation
+            .map(|correlation| ((correlation + 1.0) / 2.0 * (width - 1) as f32).round() as usize);
+        for x in 0..width {
+            text.push(if Some(x) == marker {
+                '█'
+            } else if x == centre {
+                '┼'
+            } else {
+                '─'
+            });
+        }
+        match self.correlation {
+            Some(correlation) => {
+                let _ = writeln!(text, " {:+.2}", correlation);
+            }
+            None => text.push_str("   --\n"),
+        }
+        text
+    }
+}
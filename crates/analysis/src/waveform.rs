@@ -0,0 +1,334 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
+use mogbox_io::AudioFile;
+
+use crate::image::Image;
+
+/// Lowest and highest sample of each stretch of a file, per channel
+#[derive(Clone, Debug, PartialEq)]
+pub struct Peaks {
+    pub sample_rate: u32,
+    /// Source frames each peak covers
+    pub frames_per_peak: usize,
+    /// Length of the source in frames
+    pub frames: u64,
+    /// `(min, max)` pairs, one list per channel
+    pub channels: Vec<Vec<(f32, f32)>>,
+}
+
+impl Peaks {
+    /// Peaks per channel
+    pub fn len(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Combines neighbouring peaks down to `count` per channel; fewer peaks
+    /// than that are kept as they are
+    pub fn merged(&self, count: usize) -> Peaks {
+        let len = self.len();
+        if count == 0 || count >= len {
+            return self.clone();
+        }
+        let channels = self
+            .channels
+            .iter()
+            .map(|peaks| {
+                (0..count)
+                    .map(|i| {
+                        let (start, end) = (
+                            i * len / count,
+                            ((i + 1) * len / count).max(i * len / count + 1),
+                        );
+                        peaks[start..end.min(len)]
+                            .iter()
+                            .fold((f32::MAX, f32::MIN), |(min, max), (lo, hi)| {
+                                (min.min(*lo), max.max(*hi))
+                            })
+                    })
+                    .collect()
+            })
+            .collect();
+        Peaks {
+            sample_rate: self.sample_rate,
+            frames_per_peak: self.frames_per_peak * len / count,
+            frames: self.frames,
+            channels,
+        }
+    }
+}
+
+/// Reads a whole file and takes the peaks of every `frames_per_peak`
+/// frames, of each channel or, without `split_channels`, of their average
+pub fn peaks(path: &Path, frames_per_peak: usize, split_channels: bool) -> Result<Peaks, String> {
+    let frames_per_peak = frames_per_peak.max(1);
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let channels = file.channels.max(1) as usize;
+    let outputs = if split_channels { channels } else { 1 };
+    let mut peaks = vec![Vec::new(); outputs];
+    let mut current = vec![(f32::MAX, f32::MIN); outputs];
+    let mut count = 0;
+    let mut frames = 0u64;
+    while let Some(samples) = file.next_samples()? {
+        for frame in samples.chunks_exact(channels) {
+            let mono = [frame.iter().sum::<f32>() / channels as f32];
+            let frame = if split_channels { frame } else { &mono[..] };
+            for (peak, sample) in current.iter_mut().zip(frame) {
+                peak.0 = peak.0.min(*sample);
+                peak.1 = peak.1.max(*sample);
+            }
+            count += 1;
+            frames += 1;
+            if count == frames_per_peak {
+                for (peaks, peak) in peaks.iter_mut().zip(current.iter_mut()) {
+                    peaks.push(*peak);
+                    *peak = (f32::MAX, f32::MIN);
+                }
+                count = 0;
+            }
+        }
+    }
+    if count > 0 {
+        for (peaks, peak) in peaks.iter_mut().zip(current) {
+            peaks.push(peak);
+        }
+    }
+
+    Ok(Peaks {
+        sample_rate: file.sample_rate,
+        frames_per_peak,
+        frames,
+        channels: peaks,
+    })
+}
+
+/// Sample resolution of a peak file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PeakBits {
+    /// Half the size, plenty for drawing
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+impl TryFrom<u8> for PeakBits {
+    type Error = String;
+
+    fn try_from(bits: u8) -> Result<Self, Self::Error> {
+        match bits {
+            8 => Ok(PeakBits::Eight),
+            16 => Ok(PeakBits::Sixteen),
+            _ => Err(format!(
+                "invalid peak resolution: {} (expected 8 or 16)",
+                bits
+            )),
+        }
+    }
+}
+
+impl PeakBits {
+    pub fn bits(&self) -> u8 {
+        match self {
+            PeakBits::Eight => 8,
+            PeakBits::Sixteen => 16,
+        }
+    }
+
+    fn quantize(&self, sample: f32) -> i16 {
+        let scale = match self {
+            PeakBits::Eight => 128.0,
+            PeakBits::Sixteen => 32768.0,
+        };
+        (sample * scale).round().clamp(-scale, scale - 1.0) as i16
+    }
+}
+
+impl Peaks {
+    /// Min/max pairs in audiowaveform order: per peak, every channel in turn
+    fn interleaved(&self, bits: PeakBits) -> impl Iterator<Item = i16> + '_ {
+        (0..self.len()).flat_map(move |i| {
+            self.channels.iter().flat_map(move |peaks| {
+                let (min, max) = peaks[i];
+                [bits.quantize(min), bits.quantize(max)]
+            })
+        })
+    }
+
+    /// The peaks in audiowaveform's binary `.dat` format, version 2
+    pub fn to_dat(&self, bits: PeakBits) -> Vec<u8> {
+        let mut dat = Vec::new();
+        dat.extend_from_slice(&2i32.to_le_bytes());
+        // Flag bit 0 marks 8-bit data
+        dat.extend_from_slice(&((bits == PeakBits::Eight) as u32).to_le_bytes());
+        dat.extend_from_slice(&(self.sample_rate as i32).to_le_bytes());
+        dat.extend_from_slice(&(self.frames_per_peak as i32).to_le_bytes());
+        dat.extend_from_slice(&(self.len() as u32).to_le_bytes());
+        dat.extend_from_slice(&(self.channels.len() as i32).to_le_bytes());
+        for value in self.interleaved(bits) {
+            match bits {
+                PeakBits::Eight => dat.push(value as i8 as u8),
+                PeakBits::Sixteen => dat.extend_from_slice(&value.to_le_bytes()),
+            }
+        }
+        dat
+    }
+
+    /// The peaks in audiowaveform's JSON format, version 2
+    pub fn to_json(&self, bits: PeakBits) -> String {
+        let mut json = format!(
+            "{{\"version\":2,\"channels\":{},\"sample_rate\":{},\"samples_per_pixel\":{},\"bits\":{},\"length\":{},\"data\":[",
+            self.channels.len(),
+            self.sample_rate,
+            self.frames_per_peak,
+            bits.bits(),
+            self.len()
+        );
+        for (i, value) in self.interleaved(bits).enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{}", value);
+        }
+        json.push_str("]}\n");
+        json
+    }
+
+    /// Writes the peaks as JSON when `path` ends in `.json`, as `.dat` otherwise
+    pub fn write(&self, path: &Path, bits: PeakBits) -> Result<(), String> {
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let data = if is_json {
+            self.to_json(bits).into_bytes()
+        } else {
+            self.to_dat(bits)
+        };
+        std::fs::write(path, data).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Size and colors of a waveform drawing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaveformOptions {
+    pub width: usize,
+    /// Height of the whole drawing, shared between the channels
+    pub height: usize,
+    pub color: [u8; 3],
+    pub background: [u8; 3],
+}
+
+impl Default for WaveformOptions {
+    fn default() -> Self {
+        WaveformOptions {
+            width: 1800,
+            height: 280,
+            color: [0x3b, 0x82, 0xf6],
+            background: [0xff, 0xff, 0xff],
+        }
+    }
+}
+
+/// Source frames per peak before merging down to the drawing's width
+const WAVEFORM_RESOLUTION: usize = 64;
+
+/// Peaks of `path` at one pair per column of a drawing `width` wide
+pub fn waveform_peaks(path: &Path, width: usize) -> Result<Peaks, String> {
+    let peaks = peaks(path, WAVEFORM_RESOLUTION, true)?;
+    if peaks.is_empty() {
+        return Err("no audio to draw".to_string());
+    }
+    Ok(peaks.merged(width))
+}
+
+/// Peaks of `path` with the channels averaged, for a terminal preview
+/// `columns` characters wide
+pub fn text_waveform_peaks(path: &Path, columns: usize) -> Result<Peaks, String> {
+    let peaks = peaks(path, WAVEFORM_RESOLUTION, false)?;
+    if peaks.is_empty() {
+        return Err("no audio to draw".to_string());
+    }
+    Ok(peaks.merged(columns * 2))
+}
+
+/// Draws the min/max envelope of every channel, stacked top to bottom
+pub fn waveform_image(peaks: &Peaks, options: &WaveformOptions) -> Image {
+    let mut image = Image::new(options.width, options.height, options.background);
+    let lane = (options.height / peaks.channels.len().max(1)).max(1);
+    for (channel, channel_peaks) in peaks.channels.iter().enumerate() {
+        let top = channel * lane;
+        let row = |sample: f32| {
+            top + ((1.0 - sample.clamp(-1.0, 1.0)) * 0.5 * (lane - 1) as f32).round() as usize
+        };
+        // Fewer peaks than columns are stretched over the width
+        let len = channel_peaks.len();
+        for x in 0..options.width {
+            let Some((min, max)) = channel_peaks.get(x * len / options.width) else {
+                break;
+            };
+            for y in row(*max)..=row(*min) {
+                image.set(x, y, options.color);
+            }
+        }
+    }
+    image
+}
+
+/// The same drawing as [`waveform_image`] in braille characters for a
+/// terminal, each character holding 2 by 4 dots
+pub fn waveform_text(peaks: &Peaks, columns: usize, rows: usize) -> String {
+    let options = WaveformOptions {
+        width: columns * 2,
+        height: rows * 4,
+        color: [0xff; 3],
+        background: [0; 3],
+    };
+    waveform_image(peaks, &options).to_braille(options.color)
+}
+
+/// The same drawing as [`waveform_image`] as SVG, one filled path per
+/// channel so it scales cleanly
+pub fn waveform_svg(peaks: &Peaks, options: &WaveformOptions) -> String {
+    let hex = |[r, g, b]: [u8; 3]| format!("#{:02x}{:02x}{:02x}", r, g, b);
+    let lane = options.height as f32 / peaks.channels.len().max(1) as f32;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+         <rect width=\"{w}\" height=\"{h}\" fill=\"{}\"/>\n",
+        hex(options.background),
+        w = options.width,
+        h = options.height,
+    );
+    for (channel, channel_peaks) in peaks.channels.iter().enumerate() {
+        let centre = (channel as f32 + 0.5) * lane;
+        let y = |sample: f32| centre - sample.clamp(-1.0, 1.0) * lane / 2.0;
+        let columns = channel_peaks.len().min(options.width);
+        let scale = options.width as f32 / columns.max(1) as f32;
+
+        // Along the tops left to right, then back along the bottoms
+        let mut path = String::new();
+        for (x, (_, max)) in channel_peaks.iter().take(columns).enumerate() {
+            let command = if x == 0 { 'M' } else { 'L' };
+            let _ = write!(
+                path,
+                "{}{:.1},{:.1} ",
+                command,
+                (x as f32 + 0.5) * scale,
+                y(*max)
+            );
+        }
+        for (x, (min, _)) in channel_peaks.iter().take(columns).enumerate().rev() {
+            let _ = write!(path, "L{:.1},{:.1} ", (x as f32 + 0.5) * scale, y(*min));
+        }
+        let _ = writeln!(
+            svg,
+            "<path d=\"{}Z\" fill=\"{}\"/>",
+            path,
+            hex(options.color)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,51 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+/// Window function applied to a block of samples before the FFT, trading
+/// frequency resolution for leakage between bins
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Window {
+    /// Good all-round choice
+    #[default]
+    Hann,
+    Hamming,
+    /// Lowest leakage, widest peaks
+    Blackman,
+    /// No window: sharpest peaks, most leakage
+    Rectangular,
+}
+
+impl Window {
+    /// The window's `size` coefficients
+    pub fn coefficients(&self, size: usize) -> Vec<f32> {
+        let span = (size.max(2) - 1) as f32;
+        (0..size)
+            .map(|n| {
+                let x = 2.0 * PI * n as f32 / span;
+                match self {
+                    Window::Hann => 0.5 - 0.5 * x.cos(),
+                    Window::Hamming => 0.54 - 0.46 * x.cos(),
+                    Window::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+                    Window::Rectangular => 1.0,
+                }
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hann" | "hanning" => Ok(Window::Hann),
+            "hamming" => Ok(Window::Hamming),
+            "blackman" => Ok(Window::Blackman),
+            "rect" | "rectangular" | "none" => Ok(Window::Rectangular),
+            _ => Err(format!(
+                "invalid window: {} (expected hann, hamming, blackman or rect)",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+// Generates the gRPC service of `proto/mogbox.proto` for the `grpc` feature.
+// protox compiles the definition, so protoc doesn't have to be installed.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/mogbox.proto");
+        let files = protox::compile(["proto/mogbox.proto"], ["proto"])
+            .unwrap_or_else(|e| panic!("failed to compile proto/mogbox.proto: {}", e));
+        // The client is only for the tests, which connect their own channel
+        tonic_prost_build::configure()
+            .build_transport(false)
+            .compile_fds(files)
+            .unwrap_or_else(|e| panic!("failed to generate the gRPC service: {}", e));
+    }
+}
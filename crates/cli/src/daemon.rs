@@ -0,0 +1,1123 @@
+// Daemon
+//
+// `mogbox ctl` sends the daemon one request per connection: a line of
+// tab-separated fields, the command and its arguments, with times in seconds
+// and the volume in percent. The reply is `ok` or `error` on a line of its
+// own, then a message, or `key<TAB>value` lines for `status` and
+// `current<TAB>path` lines for `queue`.
+
+use mogbox_io::{http, AudioFile};
+#[cfg(feature = "http")]
+use mogbox_runtime::Tap;
+use mogbox_runtime::{AudioPlayer, DeviceEvent, PlayerConfig, ReplayGainConfig};
+use symphonia::core::meta::StandardTagKey;
+
+use crate::discord::Presence;
+#[cfg(feature = "grpc")]
+use crate::grpc::{listen_grpc, GrpcServer};
+use crate::json::Json;
+use crate::mpd::MpdServer;
+#[cfg(all(feature = "mpris", unix, not(target_os = "macos")))]
+use crate::mpris::Mpris;
+#[cfg(all(feature = "now-playing", target_os = "macos"))]
+use crate::now_playing::NowPlaying;
+#[cfg(feature = "http")]
+use crate::rest::listen_http;
+use crate::scrobble::Scrobbler;
+#[cfg(all(feature = "smtc", windows))]
+use crate::smtc::Smtc;
+#[cfg(feature = "http")]
+use crate::websocket::EventClients;
+#[cfg(feature = "http")]
+use crate::METER_WINDOW;
+use crate::{
+    expand_paths, format_clock, format_time, load_config, load_queue, parse_volume,
+    print_player_errors, print_status, repeat_name, track_name, CtlAction, CtlArgs, DaemonArgs,
+    HistoryTracker, OutputFormat, DAEMON_INTERVAL,
+};
+#[cfg(unix)]
+use crate::{state_dir, DAEMON_READ_TIMEOUT};
+
+/// Where the daemon listens when no `--socket` is given
+#[cfg(unix)]
+fn default_socket() -> Option<std::path::PathBuf> {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Some(std::path::Path::new(&dir).join("mogbox.sock")),
+        None => Some(state_dir()?.join("mogbox").join("mogbox.sock")),
+    }
+}
+
+/// Where the daemon listens when no `--socket` is given
+#[cfg(windows)]
+fn default_socket() -> Option<std::path::PathBuf> {
+    Some(r"\\.\pipe\mogbox".into())
+}
+
+#[cfg(unix)]
+pub(crate) type IpcStream = std::os::unix::net::UnixStream;
+#[cfg(windows)]
+pub(crate) type IpcStream = interprocess::os::windows::named_pipe::DuplexPipeStream<
+    interprocess::os::windows::named_pipe::pipe_mode::Bytes,
+>;
+
+/// Starts accepting connections on `socket`, handed over as they come in.
+/// Fails when another daemon is listening on it already.
+#[cfg(unix)]
+fn listen_daemon(socket: &std::path::Path) -> Result<std::sync::mpsc::Receiver<IpcStream>, String> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    if UnixStream::connect(socket).is_ok() {
+        return Err(format!(
+            "a daemon is already listening on {}",
+            socket.display()
+        ));
+    }
+    // Left behind by a daemon that didn't shut down cleanly
+    let _ = std::fs::remove_file(socket);
+    if let Some(dir) = socket.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    }
+    let listener = UnixListener::bind(socket)
+        .map_err(|e| format!("failed to listen on {}: {}", socket.display(), e))?;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if sender.send(stream).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+/// Starts accepting connections on the named pipe `socket`, handed over as
+/// they come in. Fails when another daemon is listening on it already.
+#[cfg(windows)]
+fn listen_daemon(socket: &std::path::Path) -> Result<std::sync::mpsc::Receiver<IpcStream>, String> {
+    use interprocess::os::windows::named_pipe::{pipe_mode, PipeListenerOptions};
+
+    // The first instance of a pipe can only be created while there are none
+    let listener = PipeListenerOptions::new()
+        .path(socket.as_os_str())
+        .create_duplex::<pipe_mode::Bytes>()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::PermissionDenied => {
+                format!("a daemon is already listening on {}", socket.display())
+            }
+            _ => format!("failed to listen on {}: {}", socket.display(), e),
+        })?;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if sender.send(stream).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+#[cfg(unix)]
+pub(crate) fn connect_daemon(socket: &std::path::Path) -> std::io::Result<IpcStream> {
+    std::os::unix::net::UnixStream::connect(socket)
+}
+
+#[cfg(windows)]
+pub(crate) fn connect_daemon(socket: &std::path::Path) -> std::io::Result<IpcStream> {
+    // All instances of the pipe are busy while the daemon replies to another client
+    IpcStream::connect_by_path_with_wait_mode(
+        socket.as_os_str(),
+        interprocess::ConnectWaitMode::Timeout(DAEMON_INTERVAL * 20),
+    )
+}
+
+/// Makes sure the client has the whole reply before the connection is closed
+fn finish_reply(stream: &IpcStream) {
+    #[cfg(windows)]
+    let _ = stream.flush();
+    #[cfg(unix)]
+    let _ = stream.shutdown(std::net::Shutdown::Write);
+}
+
+/// Sends a request to the daemon listening on `socket` and returns the body of
+/// its reply
+fn send_daemon_request(socket: &std::path::Path, request: &[String]) -> Result<String, String> {
+    use std::io::{Read, Write};
+
+    if request
+        .iter()
+        .any(|field| field.contains(['\t', '\n', '\r']))
+    {
+        return Err("tabs and line breaks can't be sent to the daemon".to_string());
+    }
+    let mut stream = connect_daemon(socket).map_err(|e| {
+        format!(
+            "failed to connect to the daemon at {}: {} (is `mogbox daemon` running?)",
+            socket.display(),
+            e
+        )
+    })?;
+    let mut reply = String::new();
+    writeln!(stream, "{}", request.join("\t"))
+        .and_then(|_| stream.read_to_string(&mut reply))
+        .map_err(|e| format!("failed to talk to the daemon: {}", e))?;
+    match reply.split_once('\n') {
+        Some(("ok", body)) => Ok(body.to_string()),
+        Some(("error", message)) => Err(message.trim_end().to_string()),
+        _ => Err("the daemon sent no reply".to_string()),
+    }
+}
+
+/// Starts accepting TCP connections on `address`, handed over as they come in
+pub(crate) fn listen_tcp(
+    address: std::net::SocketAddr,
+) -> Result<std::sync::mpsc::Receiver<std::net::TcpStream>, String> {
+    let listener = std::net::TcpListener::bind(address)
+        .map_err(|e| format!("failed to listen on {}: {}", address, e))?;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if sender.send(stream).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+/// A media key, or a media control standing in for one
+#[cfg(any(
+    all(feature = "mpris", unix, not(target_os = "macos")),
+    all(feature = "now-playing", target_os = "macos"),
+    all(feature = "smtc", windows)
+))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MediaKey {
+    /// Windows sends Play or Pause instead, going by what the controls show
+    #[cfg(any(
+        all(feature = "mpris", unix, not(target_os = "macos")),
+        all(feature = "now-playing", target_os = "macos")
+    ))]
+    PlayPause,
+    Play,
+    Pause,
+    Next,
+    Previous,
+    Stop,
+}
+
+/// The player a daemon keeps running between requests
+pub(crate) struct Daemon {
+    pub(crate) player: AudioPlayer,
+    history: Option<HistoryTracker>,
+    scrobbler: Option<Scrobbler>,
+    pub(crate) presence: Option<Presence>,
+    /// The track last seen playing, to notice when another one starts
+    current: Option<usize>,
+    /// Tags of the track last asked about, so status isn't read from the file each time
+    track: Option<TrackTags>,
+    pub(crate) running: bool,
+    #[cfg(feature = "http")]
+    pub(crate) events: EventClients,
+    pub(crate) mpd: Option<MpdServer>,
+    #[cfg(feature = "grpc")]
+    pub(crate) grpc: Option<GrpcServer>,
+    #[cfg(all(feature = "mpris", unix, not(target_os = "macos")))]
+    pub(crate) mpris: Option<Mpris>,
+    #[cfg(all(feature = "now-playing", target_os = "macos"))]
+    pub(crate) now_playing: Option<NowPlaying>,
+    #[cfg(all(feature = "smtc", windows))]
+    pub(crate) smtc: Option<Smtc>,
+}
+
+/// Tags and length of a track
+pub(crate) struct TrackTags {
+    path: std::path::PathBuf,
+    pub(crate) artist: Option<String>,
+    pub(crate) album_artist: Option<String>,
+    pub(crate) title: Option<String>,
+    pub(crate) album: Option<String>,
+    pub(crate) track: Option<u32>,
+    pub(crate) date: Option<String>,
+    pub(crate) genre: Option<String>,
+    pub(crate) duration: Option<std::time::Duration>,
+    /// The embedded cover, for the media controls
+    #[cfg(all(feature = "smtc", windows))]
+    pub(crate) cover: Option<Box<[u8]>>,
+}
+
+/// Opens a track again for its tags and length. URLs are left alone, since
+/// opening one would start another download of it on the daemon thread.
+fn open_local(path: &std::path::PathBuf) -> Option<AudioFile> {
+    match http::is_url(path) {
+        true => None,
+        false => AudioFile::open(path).ok(),
+    }
+}
+
+impl TrackTags {
+    pub(crate) fn read(path: &std::path::PathBuf) -> Self {
+        let file = open_local(path);
+        let tag = |key| file.as_ref().and_then(|file| file.tag(key));
+        TrackTags {
+            path: path.clone(),
+            artist: tag(StandardTagKey::Artist),
+            album_artist: tag(StandardTagKey::AlbumArtist),
+            title: tag(StandardTagKey::TrackTitle),
+            album: tag(StandardTagKey::Album),
+            track: file
+                .as_ref()
+                .and_then(|file| file.tag_number(StandardTagKey::TrackNumber)),
+            date: tag(StandardTagKey::Date),
+            genre: tag(StandardTagKey::Genre),
+            duration: file.as_ref().and_then(AudioFile::duration),
+            #[cfg(all(feature = "smtc", windows))]
+            cover: file
+                .as_ref()
+                .and_then(|file| Some(file.cover()?.data.clone())),
+        }
+    }
+}
+
+impl Daemon {
+    /// Reads a request from a client and replies to it
+    fn serve(&mut self, stream: IpcStream) {
+        use std::io::{BufRead, Write};
+
+        #[cfg(unix)]
+        let _ = stream.set_read_timeout(Some(DAEMON_READ_TIMEOUT));
+        let mut line = String::new();
+        if std::io::BufReader::new(&stream)
+            .read_line(&mut line)
+            .is_err()
+        {
+            return;
+        }
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let reply = match self.handle(&fields) {
+            Ok(body) => format!("ok\n{}", body),
+            Err(e) => format!("error\n{}\n", e),
+        };
+        let mut stream = stream;
+        if stream.write_all(reply.as_bytes()).is_ok() {
+            finish_reply(&stream);
+        }
+    }
+
+    /// Carries out a request, returning the body of the reply
+    pub(crate) fn handle(&mut self, request: &[&str]) -> Result<String, String> {
+        let (command, args) = request.split_first().ok_or("empty request")?;
+        let (track, position) = (self.player.current_track(), self.player.position());
+        let reply = match *command {
+            "play" => self.play(args),
+            "pause" => self.pause(),
+            "toggle" => match self.player.is_paused() {
+                true => self.play(&[]),
+                false => self.pause(),
+            },
+            "stop" => {
+                self.player.stop();
+                Ok("Stopped\n".to_string())
+            }
+            "next" => match self.player.next_track() {
+                true => Ok(format!("Playing {}\n", self.current_name())),
+                false => Err("no next track".to_string()),
+            },
+            "previous" => match self.player.previous_track() {
+                true => Ok(format!("Playing {}\n", self.current_name())),
+                false => Err("no previous track".to_string()),
+            },
+            "seek" => {
+                let seconds: f64 = args
+                    .first()
+                    .and_then(|seconds| seconds.parse().ok())
+                    .ok_or("seek needs a position in seconds")?;
+                let position = std::time::Duration::try_from_secs_f64(seconds)
+                    .map_err(|_| format!("invalid position: {}", seconds))?;
+                let length = self
+                    .player
+                    .current_path()
+                    .and_then(|path| open_local(&path)?.duration());
+                if let Some(length) = length.filter(|length| position >= *length) {
+                    return Err(format!(
+                        "can't seek to {}: the track is {} long",
+                        format_time(position),
+                        format_time(length)
+                    ));
+                }
+                match self.player.seek(position) {
+                    true => Ok(format!("Playing from {}\n", format_time(position))),
+                    false => Err("nothing is playing".to_string()),
+                }
+            }
+            "volume" => {
+                let volume = parse_volume(args.first().copied().unwrap_or_default())?;
+                self.player.set_volume(volume);
+                Ok(format!("Volume {:.0}%\n", volume * 100.0))
+            }
+            "add" => {
+                let paths = expand_paths(args.iter().map(std::path::PathBuf::from).collect());
+                let count = paths.len();
+                self.player.queue().lock().unwrap().enqueue_all(paths);
+                Ok(format!("Added {} tracks\n", count))
+            }
+            "clear" => {
+                self.player.stop();
+                self.player.queue().lock().unwrap().clear();
+                Ok("Queue cleared\n".to_string())
+            }
+            "queue" => Ok(self.queue_lines()),
+            "status" => Ok(self.status_lines()),
+            "shutdown" => {
+                self.running = false;
+                Ok("Shutting down\n".to_string())
+            }
+            _ => Err(format!("unknown command: {}", command)),
+        };
+        self.left_track(track, position);
+        reply
+    }
+
+    /// Ends the history entry of `track` at `position` if the player moved
+    /// off it; leaving a track partway doesn't count as playing it to the end
+    pub(crate) fn left_track(&mut self, track: Option<usize>, position: std::time::Duration) {
+        if track.is_some() && self.player.current_track() != track {
+            if let Some(history) = &mut self.history {
+                history.stop_track(position);
+            }
+            if let Some(scrobbler) = &mut self.scrobbler {
+                scrobbler.stop_track(position);
+            }
+            self.current = None;
+        }
+    }
+
+    /// Resumes playback, or starts the queue after replacing it with `paths`
+    pub(crate) fn play(&mut self, paths: &[&str]) -> Result<String, String> {
+        if !paths.is_empty() {
+            let queue = load_queue(paths.iter().map(std::path::PathBuf::from).collect());
+            if queue.is_empty() {
+                return Err("no audio files found".to_string());
+            }
+            self.player.stop();
+            let shared = self.player.queue();
+            let mut shared = shared.lock().unwrap();
+            let (repeat, shuffle) = (shared.repeat(), shared.shuffle());
+            *shared = queue;
+            shared.set_repeat(repeat);
+            shared.set_shuffle(shuffle);
+        }
+        if self.player.is_paused() {
+            self.player.resume()?;
+            if self.player.is_playing() {
+                return Ok("Resumed\n".to_string());
+            }
+        }
+        if !self.player.is_playing() {
+            let start = {
+                let queue = self.player.queue();
+                let queue = queue.lock().unwrap();
+                queue.current_index().or_else(|| queue.first())
+            };
+            match start {
+                Some(index) => self.player.play_from(index),
+                None => return Err("the queue is empty".to_string()),
+            }
+        }
+        Ok(format!("Playing {}\n", self.current_name()))
+    }
+
+    fn pause(&mut self) -> Result<String, String> {
+        if !self.player.is_playing() {
+            return Err("nothing is playing".to_string());
+        }
+        self.player.pause()?;
+        Ok("Paused\n".to_string())
+    }
+
+    /// File name of the current track, or of where the queue starts
+    fn current_name(&self) -> String {
+        let queue = self.player.queue();
+        let queue = queue.lock().unwrap();
+        let index = self.player.current_track().or(queue.current_index());
+        index
+            .and_then(|index| queue.get(index))
+            .map_or_else(String::new, |path| track_name(path))
+    }
+
+    /// A `current<TAB>path` line per track, `current` being `*` for the
+    /// current one
+    pub(crate) fn queue_lines(&self) -> String {
+        let current = self.player.current_track();
+        let queue = self.player.queue();
+        let queue = queue.lock().unwrap();
+        queue
+            .items()
+            .iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let marker = if Some(index) == current { "*" } else { "" };
+                format!("{}\t{}\n", marker, path.display())
+            })
+            .collect()
+    }
+
+    /// `key<TAB>value` lines describing playback, values empty when unknown
+    pub(crate) fn status_lines(&mut self) -> String {
+        let state = match (self.player.is_playing(), self.player.is_paused()) {
+            (false, _) => "stopped",
+            (true, true) => "paused",
+            (true, false) => "playing",
+        };
+        let (repeat, shuffle, tracks) = {
+            let queue = self.player.queue();
+            let queue = queue.lock().unwrap();
+            (queue.repeat(), queue.shuffle(), queue.len())
+        };
+        let mut fields = vec![
+            ("state", state.to_string()),
+            ("tracks", tracks.to_string()),
+            ("volume", format!("{:.0}", self.player.volume() * 100.0)),
+            ("repeat", repeat_name(repeat).to_string()),
+            ("shuffle", shuffle.to_string()),
+        ];
+        let current = self
+            .player
+            .current_track()
+            .filter(|_| self.player.is_playing());
+        if let (Some(index), Some(path)) = (current, self.player.current_path()) {
+            let position = self.player.position();
+            // Radio streams say what they play as they go
+            let stream_title = self.player.stream_title();
+            let track = self.track_tags(&path);
+            let seconds = |time: std::time::Duration| format!("{:.3}", time.as_secs_f64());
+            fields.extend([
+                ("track", (index + 1).to_string()),
+                ("path", path.display().to_string()),
+                ("artist", track.artist.clone().unwrap_or_default()),
+                (
+                    "title",
+                    track.title.clone().or(stream_title).unwrap_or_default(),
+                ),
+                ("album", track.album.clone().unwrap_or_default()),
+                ("position", seconds(position)),
+                ("duration", track.duration.map(seconds).unwrap_or_default()),
+            ]);
+        }
+        fields
+            .into_iter()
+            .map(|(key, value)| {
+                let value: String = value
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .collect();
+                format!("{}\t{}\n", key, value)
+            })
+            .collect()
+    }
+
+    /// Acts on a media key: play/pause starts the queue when stopped, and
+    /// skipping past either end of it does nothing
+    #[cfg(any(
+        all(feature = "mpris", unix, not(target_os = "macos")),
+        all(feature = "now-playing", target_os = "macos"),
+        all(feature = "smtc", windows)
+    ))]
+    pub(crate) fn media_key(&mut self, key: MediaKey) -> Result<(), String> {
+        match key {
+            #[cfg(any(
+                all(feature = "mpris", unix, not(target_os = "macos")),
+                all(feature = "now-playing", target_os = "macos")
+            ))]
+            MediaKey::PlayPause => {
+                let key = match self.player.is_playing() && !self.player.is_paused() {
+                    true => MediaKey::Pause,
+                    false => MediaKey::Play,
+                };
+                return self.media_key(key);
+            }
+            MediaKey::Play => {
+                self.play(&[])?;
+            }
+            MediaKey::Pause => {
+                if self.player.is_playing() && !self.player.is_paused() {
+                    self.pause()?;
+                }
+            }
+            MediaKey::Next => {
+                self.player.next_track();
+            }
+            MediaKey::Previous => {
+                self.player.previous_track();
+            }
+            MediaKey::Stop => self.player.stop(),
+        }
+        Ok(())
+    }
+
+    /// Tags of `path`, read again only once another track is asked about
+    pub(crate) fn track_tags(&mut self, path: &std::path::PathBuf) -> &TrackTags {
+        if self.track.as_ref().is_some_and(|track| track.path != *path) {
+            self.track = None;
+        }
+        self.track.get_or_insert_with(|| TrackTags::read(path))
+    }
+
+    /// Keeps the history up to date as tracks start and end
+    fn track_history(&mut self) {
+        // The last track is kept as the current one once the queue ends
+        let playing = self
+            .player
+            .current_track()
+            .filter(|_| self.player.is_playing());
+        if playing.is_some() && playing != self.current {
+            self.current = playing;
+            if let (Some(history), Some(path)) = (&mut self.history, self.player.current_path()) {
+                let file = open_local(&path);
+                history.start_track(&path, file.as_ref());
+            }
+            if let Some(path) = self
+                .player
+                .current_path()
+                .filter(|_| self.scrobbler.is_some())
+            {
+                let tags = TrackTags::read(&path);
+                if let Some(scrobbler) = &mut self.scrobbler {
+                    scrobbler.start_track(&tags);
+                }
+            }
+        } else if self.current.is_some() && !self.player.is_playing() {
+            self.current = None;
+            if let Some(history) = &mut self.history {
+                history.finish();
+            }
+            if let Some(scrobbler) = &mut self.scrobbler {
+                scrobbler.finish();
+            }
+        }
+        if let Some(history) = &mut self.history {
+            history.update(self.player.position());
+        }
+        if let Some(scrobbler) = &mut self.scrobbler {
+            scrobbler.update(self.player.position());
+        }
+    }
+}
+
+pub(crate) fn handle_daemon(args: DaemonArgs) {
+    let Some(socket) = args.socket.or_else(default_socket) else {
+        eprintln!("Error: no socket path available, pass --socket");
+        return;
+    };
+    let connections = match listen_daemon(&socket) {
+        Ok(connections) => connections,
+        Err(e) => {
+            eprintln!("Error starting the daemon: {}", e);
+            return;
+        }
+    };
+    #[cfg(feature = "http")]
+    let http = match args.http.map(listen_http).transpose() {
+        Ok(http) => http,
+        Err(e) => {
+            eprintln!("Error starting the daemon: {}", e);
+            return;
+        }
+    };
+    #[cfg(feature = "grpc")]
+    let grpc = match args.grpc.map(listen_grpc).transpose() {
+        Ok(grpc) => grpc,
+        Err(e) => {
+            eprintln!("Error starting the daemon: {}", e);
+            return;
+        }
+    };
+    let mpd = match args.mpd.map(listen_tcp).transpose() {
+        Ok(mpd) => mpd,
+        Err(e) => {
+            eprintln!("Error starting the daemon: {}", e);
+            return;
+        }
+    };
+    let config = load_config();
+    let music_dir = match config.library.first() {
+        Some(dir) => std::path::absolute(dir).unwrap_or_else(|_| dir.clone()),
+        None => std::env::current_dir().unwrap_or_default(),
+    };
+    let player_config = PlayerConfig {
+        host: args.host.or(config.host),
+        device: args.device.or(config.device),
+        ..PlayerConfig::default()
+    };
+    let mut player = match AudioPlayer::with_config(&player_config) {
+        Ok(player) => player,
+        Err(e) => {
+            eprintln!("Error opening output device: {}", e);
+            return;
+        }
+    };
+    player.set_volume(args.volume.or(config.volume).unwrap_or(1.0));
+    player.set_replaygain(ReplayGainConfig {
+        mode: config.replaygain.unwrap_or_default(),
+        preamp: 0.0,
+    });
+    // Tapped last, so `/events` clients see the levels that reach the device
+    #[cfg(feature = "http")]
+    let meter = http.is_some().then(|| {
+        let frames = METER_WINDOW.as_secs_f64() * player.mixer().sample_rate() as f64;
+        let tap = Tap::new(frames.ceil() as usize);
+        player.mixer().chain().lock().unwrap().push(tap.clone());
+        tap
+    });
+
+    let mut daemon = Daemon {
+        player,
+        history: HistoryTracker::open(),
+        scrobbler: Scrobbler::start(config.lastfm, config.listenbrainz),
+        presence: config.discord.map(Presence::start),
+        current: None,
+        track: None,
+        running: true,
+        #[cfg(feature = "http")]
+        events: EventClients::new(meter),
+        mpd: mpd.map(|connections| MpdServer::new(connections, music_dir.clone())),
+        #[cfg(feature = "grpc")]
+        grpc,
+        #[cfg(all(feature = "mpris", unix, not(target_os = "macos")))]
+        mpris: match Mpris::connect() {
+            Ok((mpris, name)) => {
+                println!("Media controls on the session bus as {}", name);
+                Some(mpris)
+            }
+            Err(e) => {
+                eprintln!("Media controls unavailable: {}", e);
+                None
+            }
+        },
+        #[cfg(all(feature = "now-playing", target_os = "macos"))]
+        now_playing: Some(NowPlaying::start()),
+        #[cfg(all(feature = "smtc", windows))]
+        smtc: match Smtc::start() {
+            Ok(smtc) => {
+                println!("Media controls in the Windows media flyout");
+                Some(smtc)
+            }
+            Err(e) => {
+                eprintln!("Media controls unavailable: {}", e);
+                None
+            }
+        },
+    };
+    if !args.paths.is_empty() {
+        let paths: Vec<String> = args
+            .paths
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        if let Err(e) = daemon.play(&paths) {
+            eprintln!("Error: {}", e);
+        }
+    }
+    println!("Listening on {}", socket.display());
+    #[cfg(feature = "http")]
+    if let Some(address) = args.http {
+        println!("REST API on http://{}", address);
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(address) = args.grpc {
+        println!("gRPC service on {}", address);
+    }
+    if let Some(address) = args.mpd {
+        println!(
+            "MPD protocol on {}, library {}",
+            address,
+            music_dir.display()
+        );
+    }
+
+    while daemon.running {
+        while let Ok(stream) = connections.try_recv() {
+            daemon.serve(stream);
+        }
+        #[cfg(feature = "http")]
+        while let Some(connection) = http.as_ref().and_then(|http| http.try_recv().ok()) {
+            daemon.serve_http(connection);
+        }
+        #[cfg(feature = "http")]
+        daemon.push_events();
+        daemon.serve_mpd();
+        #[cfg(feature = "grpc")]
+        daemon.serve_grpc();
+        daemon.serve_presence();
+        #[cfg(all(feature = "mpris", unix, not(target_os = "macos")))]
+        daemon.serve_mpris();
+        #[cfg(all(feature = "now-playing", target_os = "macos"))]
+        daemon.serve_now_playing();
+        #[cfg(all(feature = "smtc", windows))]
+        daemon.serve_smtc();
+        print_player_errors(&daemon.player);
+        match daemon.player.poll_device() {
+            Some(DeviceEvent::Lost) => {
+                println!("Output device lost, waiting for it to come back...")
+            }
+            Some(DeviceEvent::Recovered { device }) => println!("Output resumed on {}", device),
+            None => {}
+        }
+        daemon.track_history();
+        std::thread::sleep(DAEMON_INTERVAL);
+    }
+
+    let position = daemon.player.position();
+    daemon.player.stop();
+    if let Some(history) = &mut daemon.history {
+        history.stop_track(position);
+    }
+    if let Some(mut scrobbler) = daemon.scrobbler.take() {
+        scrobbler.stop_track(position);
+        scrobbler.close();
+    }
+    #[cfg(unix)]
+    let _ = std::fs::remove_file(&socket);
+}
+
+impl CtlAction {
+    /// The request that carries out the action, paths made absolute since the
+    /// daemon may run elsewhere; URLs are passed on as they are
+    fn request(&self) -> Result<Vec<String>, String> {
+        let absolute = |paths: &[std::path::PathBuf]| {
+            paths
+                .iter()
+                .map(|path| {
+                    if http::is_url(path) {
+                        return Ok(path.to_string_lossy().into_owned());
+                    }
+                    std::path::absolute(path)
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .map_err(|e| format!("failed to resolve {:?}: {}", path, e))
+                })
+                .collect::<Result<Vec<String>, String>>()
+        };
+        let (command, args) = match self {
+            CtlAction::Play { paths } => ("play", absolute(paths)?),
+            CtlAction::Pause => ("pause", Vec::new()),
+            CtlAction::Toggle => ("toggle", Vec::new()),
+            CtlAction::Stop => ("stop", Vec::new()),
+            CtlAction::Next => ("next", Vec::new()),
+            CtlAction::Previous => ("previous", Vec::new()),
+            CtlAction::Seek { position } => {
+                ("seek", vec![format!("{:.3}", position.as_secs_f64())])
+            }
+            CtlAction::Volume { volume } => ("volume", vec![format!("{}", volume * 100.0)]),
+            CtlAction::Add { paths } => ("add", absolute(paths)?),
+            CtlAction::Clear => ("clear", Vec::new()),
+            CtlAction::Queue => ("queue", Vec::new()),
+            CtlAction::Status => ("status", Vec::new()),
+            CtlAction::Shutdown => ("shutdown", Vec::new()),
+        };
+        Ok(std::iter::once(command.to_string()).chain(args).collect())
+    }
+}
+
+pub(crate) fn handle_ctl(args: CtlArgs, format: OutputFormat) {
+    let Some(socket) = args.socket.or_else(default_socket) else {
+        eprintln!("Error: no socket path available, pass --socket");
+        return;
+    };
+    let reply = args
+        .action
+        .request()
+        .and_then(|request| send_daemon_request(&socket, &request));
+    match (reply, &args.action) {
+        (Ok(body), CtlAction::Status) => print_daemon_status(&body, format),
+        (Ok(body), CtlAction::Queue) => print_daemon_queue(&body, format),
+        (Ok(body), _) => print_status(format, body.trim_end()),
+        (Err(e), _) => eprintln!("Error: {}", e),
+    }
+}
+
+/// The reply of the daemon to `status`
+pub(crate) struct DaemonStatus<'a> {
+    fields: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> DaemonStatus<'a> {
+    pub(crate) fn parse(body: &'a str) -> Self {
+        DaemonStatus {
+            fields: body
+                .lines()
+                .filter_map(|line| line.split_once('\t'))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn field(&self, key: &str) -> Option<&'a str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| *value)
+            .filter(|value| !value.is_empty())
+    }
+
+    pub(crate) fn seconds(&self, key: &str) -> Option<std::time::Duration> {
+        self.field(key)
+            .and_then(|value| value.parse::<f64>().ok())
+            .and_then(|seconds| std::time::Duration::try_from_secs_f64(seconds).ok())
+    }
+
+    pub(crate) fn number(&self, key: &str) -> Option<u64> {
+        self.field(key).and_then(|value| value.parse().ok())
+    }
+
+    pub(crate) fn state(&self) -> &'a str {
+        self.field("state").unwrap_or("stopped")
+    }
+
+    pub(crate) fn shuffle(&self) -> bool {
+        self.field("shuffle") == Some("true")
+    }
+
+    pub(crate) fn to_json(&self) -> Json {
+        Json::object(self.members())
+    }
+
+    pub(crate) fn members(&self) -> [(&'static str, Json); 12] {
+        [
+            ("state", Json::from(self.state())),
+            ("track", Json::from(self.number("track"))),
+            ("tracks", Json::from(self.number("tracks"))),
+            ("path", Json::from(self.field("path"))),
+            ("artist", Json::from(self.field("artist"))),
+            ("title", Json::from(self.field("title"))),
+            ("album", Json::from(self.field("album"))),
+            (
+                "position",
+                self.seconds("position").map_or(Json::Null, Json::seconds),
+            ),
+            (
+                "duration",
+                self.seconds("duration").map_or(Json::Null, Json::seconds),
+            ),
+            ("volume", Json::from(self.number("volume"))),
+            ("repeat", Json::from(self.field("repeat"))),
+            ("shuffle", Json::from(self.shuffle())),
+        ]
+    }
+}
+
+fn print_daemon_status(body: &str, format: OutputFormat) {
+    let status = DaemonStatus::parse(body);
+    if format == OutputFormat::Json {
+        println!("{}", status.to_json());
+        return;
+    }
+    println!("State: {}", status.state());
+    if let (Some(track), Some(path)) = (status.field("track"), status.field("path")) {
+        println!(
+            "Track: {}/{} {}",
+            track,
+            status.field("tracks").unwrap_or("?"),
+            path
+        );
+        match (status.field("artist"), status.field("title")) {
+            (Some(artist), Some(title)) => println!("Title: {} \u{2013} {}", artist, title),
+            (None, Some(title)) => println!("Title: {}", title),
+            _ => {}
+        }
+        if let Some(album) = status.field("album") {
+            println!("Album: {}", album);
+        }
+        let position = status.seconds("position").unwrap_or_default();
+        match status.seconds("duration") {
+            Some(duration) => println!(
+                "Position: {} / {}",
+                format_clock(position),
+                format_clock(duration)
+            ),
+            None => println!("Position: {}", format_clock(position)),
+        }
+    } else {
+        println!("Tracks: {}", status.field("tracks").unwrap_or("0"));
+    }
+    println!("Volume: {}%", status.field("volume").unwrap_or("?"));
+    println!(
+        "Repeat: {}, shuffle: {}",
+        status.field("repeat").unwrap_or("off"),
+        if status.shuffle() { "on" } else { "off" }
+    );
+}
+
+/// The tracks in the daemon's reply to `queue`, with whether each is the
+/// current one
+pub(crate) fn daemon_queue(body: &str) -> Vec<(bool, &str)> {
+    body.lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(marker, path)| (marker == "*", path))
+        .collect()
+}
+
+pub(crate) fn daemon_queue_json(body: &str) -> Json {
+    let tracks: Vec<Json> = daemon_queue(body)
+        .into_iter()
+        .map(|(current, path)| {
+            Json::object([("path", Json::from(path)), ("current", Json::from(current))])
+        })
+        .collect();
+    Json::from(tracks)
+}
+
+fn print_daemon_queue(body: &str, format: OutputFormat) {
+    let tracks = daemon_queue(body);
+    match format {
+        OutputFormat::Json => println!("{}", daemon_queue_json(body)),
+        OutputFormat::Text if tracks.is_empty() => println!("The queue is empty"),
+        OutputFormat::Text => {
+            for (index, (current, path)) in tracks.iter().enumerate() {
+                let marker = if *current { "*" } else { " " };
+                println!("{} {}. {}", marker, index + 1, path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::*;
+
+    const STATUS: &str = "state\tplaying\ntracks\t3\nvolume\t80\nrepeat\tall\nshuffle\tfalse\n\
+        track\t2\npath\t/music/b.flac\nartist\tArtist\ntitle\tTitle\nalbum\t\n\
+        position\t12.500\nduration\t200.000\n";
+
+    #[test]
+    fn status_fields() {
+        let status = DaemonStatus::parse(STATUS);
+        assert_eq!(status.state(), "playing");
+        assert_eq!(status.field("title"), Some("Title"));
+        assert_eq!(status.field("album"), None);
+        assert_eq!(status.number("track"), Some(2));
+        assert_eq!(
+            status.seconds("position"),
+            Some(Duration::from_millis(12_500))
+        );
+        assert!(!status.shuffle());
+        assert_eq!(DaemonStatus::parse("").state(), "stopped");
+    }
+
+    #[test]
+    fn status_json() {
+        assert_eq!(
+            DaemonStatus::parse(STATUS).to_json().to_string(),
+            concat!(
+                r#"{"state":"playing","track":2,"tracks":3,"path":"/music/b.flac","#,
+                r#""artist":"Artist","title":"Title","album":null,"position":12.500,"#,
+                r#""duration":200.000,"volume":80,"repeat":"all","shuffle":false}"#
+            )
+        );
+    }
+
+    #[test]
+    fn queue_marks_the_current_track() {
+        let body = "\t/music/a.flac\n*\t/music/b.flac\n";
+        assert_eq!(
+            daemon_queue(body),
+            [(false, "/music/a.flac"), (true, "/music/b.flac")]
+        );
+        assert_eq!(
+            daemon_queue_json(body).to_string(),
+            r#"[{"path":"/music/a.flac","current":false},{"path":"/music/b.flac","current":true}]"#
+        );
+    }
+
+    #[test]
+    fn ctl_requests() {
+        let request = |action: CtlAction| action.request().unwrap();
+        assert_eq!(request(CtlAction::Pause), ["pause"]);
+        assert_eq!(
+            request(CtlAction::Seek {
+                position: Duration::from_millis(1500)
+            }),
+            ["seek", "1.500"]
+        );
+        assert_eq!(request(CtlAction::Volume { volume: 0.5 }), ["volume", "50"]);
+        let add = request(CtlAction::Add {
+            paths: vec![
+                PathBuf::from("a.flac"),
+                PathBuf::from("https://example.com/b.mp3"),
+            ],
+        });
+        assert_eq!(add[0], "add");
+        assert_eq!(
+            PathBuf::from(&add[1]),
+            std::env::current_dir().unwrap().join("a.flac")
+        );
+        assert_eq!(add[2], "https://example.com/b.mp3");
+    }
+
+    #[test]
+    fn urls_are_not_opened_again() {
+        // A server that never answers, which a second download would wait on
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        server.set_nonblocking(true).unwrap();
+        let url = format!("http://{}/stream.mp3", server.local_addr().unwrap());
+        let (sender, tags) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(TrackTags::read(&PathBuf::from(url)));
+        });
+        let tags = tags
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(tags.title, None);
+        assert_eq!(tags.duration, None);
+        assert!(server.accept().is_err());
+    }
+
+    #[test]
+    fn requests_with_tabs_are_refused() {
+        let request = ["add".to_string(), "a\tb.flac".to_string()];
+        assert!(send_daemon_request(std::path::Path::new("unused"), &request).is_err());
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        use std::io::{BufRead, Write};
+
+        #[cfg(unix)]
+        let socket =
+            std::env::temp_dir().join(format!("mogbox-daemon-{}.sock", std::process::id()));
+        #[cfg(windows)]
+        let socket =
+            std::path::PathBuf::from(format!(r"\\.\pipe\mogbox-daemon-{}", std::process::id()));
+        let connections = listen_daemon(&socket).unwrap();
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for reply in ["ok\nVolume 50%\n", "error\nunknown command: dance\n"] {
+                let mut stream = connections.recv().unwrap();
+                let mut line = String::new();
+                std::io::BufReader::new(&stream)
+                    .read_line(&mut line)
+                    .unwrap();
+                requests.push(line);
+                stream.write_all(reply.as_bytes()).unwrap();
+                finish_reply(&stream);
+            }
+            requests
+        });
+        let request = |fields: &[&str]| {
+            let fields: Vec<String> = fields.iter().map(|field| field.to_string()).collect();
+            send_daemon_request(&socket, &fields)
+        };
+        assert_eq!(request(&["volume", "50"]), Ok("Volume 50%\n".to_string()));
+        assert_eq!(
+            request(&["dance"]),
+            Err("unknown command: dance".to_string())
+        );
+        assert_eq!(server.join().unwrap(), ["volume\t50\n", "dance\n"]);
+        assert!(listen_daemon(&socket).is_err());
+        #[cfg(unix)]
+        std::fs::remove_file(&socket).unwrap();
+    }
+}
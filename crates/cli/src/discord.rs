@@ -0,0 +1,374 @@
+// Discord presence
+//
+// With a Discord application's client ID in the config, the daemon shows the
+// track it plays in the user's Discord status, with the time elapsed. The
+// Discord client takes this over a local socket (a named pipe on Windows) as
+// frames of an opcode, a length and a JSON payload. It's fed from a thread of
+// its own, which connects again whenever Discord starts or restarts.
+
+use crate::daemon::{connect_daemon, Daemon, IpcStream};
+use crate::json::Json;
+use crate::track_name;
+
+/// How often a Discord that isn't running is looked for again
+const PRESENCE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+/// Shortest time between updates, as Discord takes at most 5 every 20 seconds
+const PRESENCE_MIN_GAP: std::time::Duration = std::time::Duration::from_secs(4);
+/// How far the elapsed time shown may be off before it's corrected
+const PRESENCE_TOLERANCE: std::time::Duration = std::time::Duration::from_secs(2);
+/// Longest text Discord shows for the details and state of an activity
+const PRESENCE_MAX_TEXT: usize = 128;
+/// Frame opcodes of Discord's IPC
+const DISCORD_HANDSHAKE: u32 = 0;
+const DISCORD_FRAME: u32 = 1;
+const DISCORD_CLOSE: u32 = 2;
+const DISCORD_PING: u32 = 3;
+const DISCORD_PONG: u32 = 4;
+
+/// What the presence shows about the track playing
+#[derive(Clone, Debug, PartialEq)]
+struct PresenceActivity {
+    title: String,
+    artist: Option<String>,
+    /// When the track would have started had it played without pausing, and
+    /// when it ends, in milliseconds since the Unix epoch
+    start: u64,
+    end: Option<u64>,
+}
+
+impl PresenceActivity {
+    /// The same track shown with nearly the same elapsed time
+    fn matches(&self, other: &PresenceActivity) -> bool {
+        let tolerance = PRESENCE_TOLERANCE.as_millis() as u64;
+        self.title == other.title
+            && self.artist == other.artist
+            && self.start.abs_diff(other.start) <= tolerance
+    }
+
+    /// The activity of a `SET_ACTIVITY` command
+    fn to_json(&self) -> Json {
+        let text = |text: &str| -> Json {
+            text.chars()
+                .take(PRESENCE_MAX_TEXT)
+                .collect::<String>()
+                .into()
+        };
+        let mut timestamps = vec![("start", self.start.into())];
+        if let Some(end) = self.end {
+            timestamps.push(("end", end.into()));
+        }
+        let mut activity = vec![
+            // Listening to
+            ("type", 2u32.into()),
+            ("details", text(&self.title)),
+        ];
+        if let Some(artist) = &self.artist {
+            activity.push(("state", text(artist)));
+        }
+        activity.push(("timestamps", Json::object(timestamps)));
+        Json::object(activity)
+    }
+}
+
+/// Keeps the daemon's Discord presence in step with what it plays
+pub(crate) struct Presence {
+    activities: std::sync::mpsc::Sender<Option<PresenceActivity>>,
+    /// Activity last handed over
+    last: Option<Option<PresenceActivity>>,
+}
+
+impl Presence {
+    /// Starts showing activity for the Discord application `client_id`
+    pub(crate) fn start(client_id: String) -> Self {
+        let (activities, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || run_presence(&client_id, receiver));
+        Presence {
+            activities,
+            last: None,
+        }
+    }
+}
+
+/// Shows each activity received in Discord, the latest one when several came in
+/// at once, and clears it for `None`
+fn run_presence(client_id: &str, activities: std::sync::mpsc::Receiver<Option<PresenceActivity>>) {
+    let mut connection: Option<IpcStream> = None;
+    let mut wanted: Option<PresenceActivity> = None;
+    // What Discord shows, when connected and known
+    let mut shown: Option<Option<PresenceActivity>> = None;
+    let mut nonce: u64 = 0;
+    loop {
+        match activities.recv_timeout(PRESENCE_INTERVAL) {
+            Ok(activity) => wanted = activity,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        while let Ok(activity) = activities.try_recv() {
+            wanted = activity;
+        }
+        if connection.is_none() && wanted.is_some() {
+            match discord_connect(client_id) {
+                Ok(stream) => {
+                    connection = Some(stream);
+                    shown = Some(None);
+                }
+                // Not running; tried again later
+                Err(None) => {}
+                Err(Some(e)) => eprintln!("Error updating Discord presence: {}", e),
+            }
+        }
+        let Some(stream) = &mut connection else {
+            continue;
+        };
+        if shown.as_ref() == Some(&wanted) {
+            continue;
+        }
+        nonce += 1;
+        let command = Json::object([
+            ("cmd", "SET_ACTIVITY".into()),
+            (
+                "args",
+                Json::object([
+                    ("pid", std::process::id().into()),
+                    (
+                        "activity",
+                        wanted.as_ref().map(PresenceActivity::to_json).into(),
+                    ),
+                ]),
+            ),
+            ("nonce", nonce.to_string().into()),
+        ]);
+        match discord_request(stream, DISCORD_FRAME, &command.to_string()) {
+            Ok(reply) if reply.contains("\"evt\":\"ERROR\"") => {
+                eprintln!("Error updating Discord presence: {}", reply);
+                shown = Some(wanted.clone());
+            }
+            Ok(_) => shown = Some(wanted.clone()),
+            Err(e) => {
+                eprintln!("Error updating Discord presence: {}", e);
+                connection = None;
+                shown = None;
+            }
+        }
+        std::thread::sleep(PRESENCE_MIN_GAP);
+    }
+}
+
+/// Where the Discord client may be listening; it takes the first free one of
+/// ten numbered sockets
+#[cfg(unix)]
+fn discord_sockets() -> Vec<std::path::PathBuf> {
+    let dirs: Vec<std::path::PathBuf> = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .iter()
+        .filter_map(|var| std::env::var_os(var).map(std::path::PathBuf::from))
+        .chain([std::path::PathBuf::from("/tmp")])
+        .collect();
+    let mut sockets = Vec::new();
+    for dir in dirs {
+        // Also inside the Flatpak and Snap sandboxes
+        for sandbox in ["", "app/com.discordapp.Discord", "snap.discord"] {
+            for number in 0..10 {
+                sockets.push(dir.join(sandbox).join(format!("discord-ipc-{}", number)));
+            }
+        }
+    }
+    sockets
+}
+
+#[cfg(windows)]
+fn discord_sockets() -> Vec<std::path::PathBuf> {
+    (0..10)
+        .map(|number| format!(r"\\.\pipe\discord-ipc-{}", number).into())
+        .collect()
+}
+
+/// Connects to the Discord client and introduces the application. Fails with
+/// `None` when no Discord client is running.
+fn discord_connect(client_id: &str) -> Result<IpcStream, Option<String>> {
+    let mut stream = discord_sockets()
+        .iter()
+        .find_map(|socket| connect_daemon(socket).ok())
+        .ok_or(None)?;
+    #[cfg(unix)]
+    let _ = stream.set_read_timeout(Some(PRESENCE_INTERVAL));
+    let handshake = Json::object([("v", 1u32.into()), ("client_id", client_id.into())]);
+    discord_request(&mut stream, DISCORD_HANDSHAKE, &handshake.to_string())
+        .map_err(|e| Some(format!("failed to connect to Discord: {}", e)))?;
+    Ok(stream)
+}
+
+/// Sends a frame and returns the payload of Discord's reply, answering pings
+/// on the way
+fn discord_request(stream: &mut IpcStream, opcode: u32, payload: &str) -> Result<String, String> {
+    use std::io::{Read, Write};
+
+    let write_frame = |stream: &mut IpcStream, opcode: u32, payload: &[u8]| {
+        let mut frame = Vec::with_capacity(8 + payload.len());
+        frame.extend_from_slice(&opcode.to_le_bytes());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(payload);
+        stream.write_all(&frame)
+    };
+    write_frame(stream, opcode, payload.as_bytes()).map_err(|e| format!("{}", e))?;
+    loop {
+        let mut header = [0u8; 8];
+        stream
+            .read_exact(&mut header)
+            .map_err(|e| format!("{}", e))?;
+        let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let mut reply = vec![0u8; length as usize];
+        stream
+            .read_exact(&mut reply)
+            .map_err(|e| format!("{}", e))?;
+        let reply = String::from_utf8_lossy(&reply).into_owned();
+        match opcode {
+            DISCORD_PING => {
+                write_frame(stream, DISCORD_PONG, reply.as_bytes()).map_err(|e| format!("{}", e))?
+            }
+            DISCORD_CLOSE => return Err(format!("Discord closed the connection: {}", reply)),
+            _ => return Ok(reply),
+        }
+    }
+}
+
+impl Daemon {
+    /// Hands the presence what is playing when it changes, or when the
+    /// elapsed time shown drifted
+    pub(crate) fn serve_presence(&mut self) {
+        let Some(mut presence) = self.presence.take() else {
+            return;
+        };
+        let path = self
+            .player
+            .current_path()
+            .filter(|_| self.player.is_playing() && !self.player.is_paused());
+        let activity = path.map(|path| {
+            let position = self.player.position();
+            let tags = self.track_tags(&path);
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default();
+            let start = now.saturating_sub(position);
+            PresenceActivity {
+                title: tags.title.clone().unwrap_or_else(|| track_name(&path)),
+                artist: tags.artist.clone(),
+                start: start.as_millis() as u64,
+                end: tags
+                    .duration
+                    .map(|duration| (start + duration).as_millis() as u64),
+            }
+        });
+        let changed = match (&presence.last, &activity) {
+            (Some(Some(last)), Some(activity)) => !last.matches(activity),
+            (Some(last), activity) => last.is_some() != activity.is_some(),
+            (None, _) => true,
+        };
+        if changed {
+            let _ = presence.activities.send(activity.clone());
+            presence.last = Some(activity);
+        }
+        self.presence = Some(presence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity() -> PresenceActivity {
+        PresenceActivity {
+            title: "Title".to_string(),
+            artist: Some("Artist".to_string()),
+            start: 1_700_000_000_000,
+            end: Some(1_700_000_200_000),
+        }
+    }
+
+    #[test]
+    fn activities_match_within_tolerance() {
+        let shown = activity();
+        let drifted = PresenceActivity {
+            start: shown.start + PRESENCE_TOLERANCE.as_millis() as u64,
+            ..activity()
+        };
+        assert!(shown.matches(&drifted));
+        let seeked = PresenceActivity {
+            start: drifted.start + 1,
+            ..activity()
+        };
+        assert!(!shown.matches(&seeked));
+        let other = PresenceActivity {
+            title: "Other".to_string(),
+            ..activity()
+        };
+        assert!(!shown.matches(&other));
+    }
+
+    #[test]
+    fn activity_json() {
+        assert_eq!(
+            activity().to_json().to_string(),
+            "{\"type\":2,\"details\":\"Title\",\"state\":\"Artist\",\
+             \"timestamps\":{\"start\":1700000000000,\"end\":1700000200000}}"
+        );
+        let long = PresenceActivity {
+            title: "x".repeat(PRESENCE_MAX_TEXT + 10),
+            artist: None,
+            end: None,
+            ..activity()
+        };
+        assert_eq!(
+            long.to_json().to_string(),
+            format!(
+                "{{\"type\":2,\"details\":\"{}\",\"timestamps\":{{\"start\":1700000000000}}}}",
+                "x".repeat(PRESENCE_MAX_TEXT)
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn discord_frames() {
+        use std::io::{Read, Write};
+
+        fn read_frame(stream: &mut IpcStream) -> (u32, String) {
+            let mut header = [0u8; 8];
+            stream.read_exact(&mut header).unwrap();
+            let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            let mut payload = vec![0u8; length as usize];
+            stream.read_exact(&mut payload).unwrap();
+            (opcode, String::from_utf8(payload).unwrap())
+        }
+        fn write_frame(stream: &mut IpcStream, opcode: u32, payload: &str) {
+            let mut frame = opcode.to_le_bytes().to_vec();
+            frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            frame.extend_from_slice(payload.as_bytes());
+            stream.write_all(&frame).unwrap();
+        }
+
+        let (mut client, mut discord) = IpcStream::pair().unwrap();
+        let server = std::thread::spawn(move || {
+            assert_eq!(
+                read_frame(&mut discord),
+                (DISCORD_HANDSHAKE, "{\"v\":1}".to_string())
+            );
+            // Pinged before the reply, which must be answered in kind
+            write_frame(&mut discord, DISCORD_PING, "ping");
+            assert_eq!(read_frame(&mut discord), (DISCORD_PONG, "ping".to_string()));
+            write_frame(&mut discord, DISCORD_FRAME, "{\"evt\":\"READY\"}");
+            assert_eq!(read_frame(&mut discord), (DISCORD_FRAME, "{}".to_string()));
+            write_frame(&mut discord, DISCORD_CLOSE, "bye");
+        });
+        assert_eq!(
+            discord_request(&mut client, DISCORD_HANDSHAKE, "{\"v\":1}").unwrap(),
+            "{\"evt\":\"READY\"}"
+        );
+        assert_eq!(
+            discord_request(&mut client, DISCORD_FRAME, "{}").unwrap_err(),
+            "Discord closed the connection: bye"
+        );
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+// gRPC service
+//
+// `mogbox daemon --grpc ADDRESS` serves the `mogbox.v1.Player` service of
+// `proto/mogbox.proto` for programs that already speak gRPC. tonic serves it
+// from a thread of its own: each call is handed to the daemon as the `mogbox
+// ctl` request it stands for, and `WatchStatus` streams follow the status the
+// daemon publishes as it changes. Browsers can't make gRPC calls, so unlike
+// the REST API there are no cross-site requests to refuse.
+
+use std::pin::Pin;
+
+use tokio::sync::{oneshot, watch};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::daemon::{daemon_queue, Daemon, DaemonStatus};
+
+mod proto {
+    tonic::include_proto!("mogbox.v1");
+}
+
+use proto::player_server::{Player, PlayerServer};
+
+/// How often watchers get the status while only the position changes
+const GRPC_STATUS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// A ctl request and where its reply goes
+type GrpcRequest = (Vec<String>, oneshot::Sender<Result<String, String>>);
+
+/// The daemon's end of the gRPC service
+pub(crate) struct GrpcServer {
+    requests: std::sync::mpsc::Receiver<GrpcRequest>,
+    /// `status` lines for `WatchStatus` streams
+    status: watch::Sender<String>,
+    /// Status last published without the position, and when
+    last_status: Option<(String, std::time::Instant)>,
+}
+
+/// Starts serving the gRPC service on `address`
+pub(crate) fn listen_grpc(address: std::net::SocketAddr) -> Result<GrpcServer, String> {
+    let listener = std::net::TcpListener::bind(address)
+        .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+        .map_err(|e| format!("failed to listen on {}: {}", address, e))?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("failed to start the gRPC server: {}", e))?;
+    let (sender, requests) = std::sync::mpsc::channel();
+    let (status, _) = watch::channel(String::new());
+    let service = PlayerService {
+        requests: sender,
+        status: status.clone(),
+    };
+    std::thread::spawn(move || {
+        runtime.block_on(async move {
+            let incoming = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => tonic::transport::server::TcpIncoming::from(listener),
+                Err(e) => return eprintln!("gRPC server stopped: {}", e),
+            };
+            let served = tonic::transport::Server::builder()
+                .add_service(PlayerServer::new(service))
+                .serve_with_incoming(incoming)
+                .await;
+            if let Err(e) = served {
+                eprintln!("gRPC server stopped: {}", e);
+            }
+        })
+    });
+    Ok(GrpcServer {
+        requests,
+        status,
+        last_status: None,
+    })
+}
+
+/// The service tonic calls, which passes requests on to the daemon
+struct PlayerService {
+    requests: std::sync::mpsc::Sender<GrpcRequest>,
+    status: watch::Sender<String>,
+}
+
+impl PlayerService {
+    /// Waits for the daemon to carry out `request`, returning the body of its reply
+    async fn call(&self, request: Vec<String>) -> Result<String, tonic::Status> {
+        let stopped = || tonic::Status::unavailable("the daemon is shutting down");
+        let (sender, reply) = oneshot::channel();
+        self.requests
+            .send((request, sender))
+            .map_err(|_| stopped())?;
+        // As the REST API answers 400
+        reply
+            .await
+            .map_err(|_| stopped())?
+            .map_err(tonic::Status::invalid_argument)
+    }
+
+    /// Carries out a request that replies with a message, such as "Paused"
+    async fn reply(
+        &self,
+        command: &str,
+        args: Vec<String>,
+    ) -> Result<tonic::Response<proto::Reply>, tonic::Status> {
+        let request = std::iter::once(command.to_string()).chain(args).collect();
+        let body = self.call(request).await?;
+        Ok(tonic::Response::new(proto::Reply {
+            message: body.trim_end().to_string(),
+        }))
+    }
+}
+
+type Request<T> = tonic::Request<T>;
+type Reply = Result<tonic::Response<proto::Reply>, tonic::Status>;
+type StatusStream = Pin<Box<dyn Stream<Item = Result<proto::Status, tonic::Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Player for PlayerService {
+    async fn play(&self, request: Request<proto::PlayRequest>) -> Reply {
+        self.reply("play", request.into_inner().paths).await
+    }
+
+    async fn pause(&self, _: Request<proto::Empty>) -> Reply {
+        self.reply("pause", Vec::new()).await
+    }
+
+    async fn toggle(&self, _: Request<proto::Empty>) -> Reply {
+        self.reply("toggle", Vec::new()).await
+    }
+
+    async fn stop(&self, _: Request<proto::Empty>) -> Reply {
+        self.reply("stop", Vec::new()).await
+    }
+
+    async fn next(&self, _: Request<proto::Empty>) -> Reply {
+        self.reply("next", Vec::new()).await
+    }
+
+    async fn previous(&self, _: Request<proto::Empty>) -> Reply {
+        self.reply("previous", Vec::new()).await
+    }
+
+    async fn seek(&self, request: Request<proto::SeekRequest>) -> Reply {
+        let position = request.into_inner().position_seconds;
+        self.reply("seek", vec![position.to_string()]).await
+    }
+
+    async fn set_volume(&self, request: Request<proto::VolumeRequest>) -> Reply {
+        let percent = request.into_inner().percent;
+        self.reply("volume", vec![percent.to_string()]).await
+    }
+
+    async fn get_queue(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<tonic::Response<proto::Queue>, tonic::Status> {
+        let body = self.call(vec!["queue".to_string()]).await?;
+        Ok(tonic::Response::new(queue_message(&body)))
+    }
+
+    async fn add_to_queue(&self, request: Request<proto::PathsRequest>) -> Reply {
+        self.reply("add", request.into_inner().paths).await
+    }
+
+    async fn clear_queue(&self, _: Request<proto::Empty>) -> Reply {
+        self.reply("clear", Vec::new()).await
+    }
+
+    async fn get_status(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<tonic::Response<proto::Status>, tonic::Status> {
+        let body = self.call(vec!["status".to_string()]).await?;
+        Ok(tonic::Response::new(status_message(&body)))
+    }
+
+    type WatchStatusStream = StatusStream;
+
+    async fn watch_status(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<tonic::Response<StatusStream>, tonic::Status> {
+        // Empty until the daemon first publishes the status
+        let status = tokio_stream::wrappers::WatchStream::new(self.status.subscribe())
+            .filter(|body| !body.is_empty())
+            .map(|body| Ok(status_message(&body)));
+        Ok(tonic::Response::new(Box::pin(status)))
+    }
+
+    async fn shutdown(&self, _: Request<proto::Empty>) -> Reply {
+        self.reply("shutdown", Vec::new()).await
+    }
+}
+
+/// The daemon's reply to `status` as a message
+fn status_message(body: &str) -> proto::Status {
+    use proto::status::{Repeat, State};
+
+    let status = DaemonStatus::parse(body);
+    let number = |key: &str| status.number(key).map(|number| number as u32);
+    let text = |key: &str| status.field(key).map(String::from);
+    let seconds = |key: &str| status.seconds(key).map(|time| time.as_secs_f64());
+    let state = match status.state() {
+        "playing" => State::Playing,
+        "paused" => State::Paused,
+        _ => State::Stopped,
+    };
+    let repeat = match status.field("repeat") {
+        Some("track") => Repeat::Track,
+        Some("queue") => Repeat::Queue,
+        _ => Repeat::Off,
+    };
+    proto::Status {
+        state: state as i32,
+        track: number("track"),
+        tracks: number("tracks").unwrap_or_default(),
+        path: text("path"),
+        artist: text("artist"),
+        title: text("title"),
+        album: text("album"),
+        position_seconds: seconds("position"),
+        duration_seconds: seconds("duration"),
+        volume: number("volume").unwrap_or_default(),
+        repeat: repeat as i32,
+        shuffle: status.shuffle(),
+    }
+}
+
+/// The daemon's reply to `queue` as a message
+fn queue_message(body: &str) -> proto::Queue {
+    let tracks = daemon_queue(body);
+    proto::Queue {
+        current: tracks
+            .iter()
+            .position(|(current, _)| *current)
+            .map(|index| index as u32),
+        paths: tracks
+            .into_iter()
+            .map(|(_, path)| path.to_string())
+            .collect(),
+    }
+}
+
+impl Daemon {
+    /// Carries out calls from gRPC clients and publishes the status to
+    /// their `WatchStatus` streams
+    pub(crate) fn serve_grpc(&mut self) {
+        let Some(mut grpc) = self.grpc.take() else {
+            return;
+        };
+        while let Ok((request, reply)) = grpc.requests.try_recv() {
+            let request: Vec<&str> = request.iter().map(String::as_str).collect();
+            let _ = reply.send(self.handle(&request));
+        }
+        if grpc.status.receiver_count() > 0 {
+            let body = self.status_lines();
+            // The position moves on all the time, so alone it only counts now and then
+            let status: String = body
+                .lines()
+                .filter(|line| !line.starts_with("position\t"))
+                .collect();
+            let due = grpc.last_status.as_ref().is_none_or(|(last, sent)| {
+                *last != status || sent.elapsed() >= GRPC_STATUS_INTERVAL
+            });
+            if due {
+                grpc.last_status = Some((status, std::time::Instant::now()));
+                grpc.status.send_replace(body);
+            }
+        }
+        self.grpc = Some(grpc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::player_client::PlayerClient;
+
+    const STATUS: &str = "state\tplaying\ntracks\t3\nvolume\t80\nrepeat\tqueue\n\
+                          shuffle\ttrue\ntrack\t2\npath\t/music/b.flac\nartist\t\n\
+                          title\tB\nalbum\t\nposition\t1.500\nduration\t\n";
+
+    /// A service on a free port, and a client connected to it
+    fn serve(
+        runtime: &tokio::runtime::Runtime,
+    ) -> (GrpcServer, PlayerClient<tonic::transport::Channel>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let server = listen_grpc(address).unwrap();
+        let _guard = runtime.enter();
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect_lazy();
+        (server, PlayerClient::new(channel))
+    }
+
+    /// Answers `count` requests as the daemon would, returning them
+    fn answer(
+        requests: std::sync::mpsc::Receiver<GrpcRequest>,
+        count: usize,
+    ) -> std::thread::JoinHandle<Vec<Vec<String>>> {
+        std::thread::spawn(move || {
+            (0..count)
+                .map(|_| {
+                    let (request, reply) = requests
+                        .recv_timeout(std::time::Duration::from_secs(5))
+                        .unwrap();
+                    let body = match request[0].as_str() {
+                        "status" => Ok(STATUS.to_string()),
+                        "queue" => Ok("\t/music/a.flac\n*\t/music/b.flac\n".to_string()),
+                        "next" => Err("no next track".to_string()),
+                        _ => Ok("Done\n".to_string()),
+                    };
+                    let _ = reply.send(body);
+                    request
+                })
+                .collect()
+        })
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn calls_are_carried_out_by_the_daemon() {
+        let runtime = runtime();
+        let (server, mut client) = serve(&runtime);
+        let requests = answer(server.requests, 5);
+        runtime.block_on(async {
+            let paths = vec!["/music/a.flac".to_string(), "/music/b.flac".to_string()];
+            let reply = client.play(proto::PlayRequest { paths }).await.unwrap();
+            assert_eq!(reply.into_inner().message, "Done");
+            let request = proto::VolumeRequest { percent: 80.0 };
+            client.set_volume(request).await.unwrap();
+            let request = proto::SeekRequest {
+                position_seconds: 1.5,
+            };
+            client.seek(request).await.unwrap();
+
+            let error = client.next(proto::Empty {}).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::InvalidArgument);
+            assert_eq!(error.message(), "no next track");
+
+            let queue = client
+                .get_queue(proto::Empty {})
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(queue.paths, ["/music/a.flac", "/music/b.flac"]);
+            assert_eq!(queue.current, Some(1));
+        });
+        assert_eq!(
+            requests.join().unwrap(),
+            [
+                vec!["play", "/music/a.flac", "/music/b.flac"],
+                vec!["volume", "80"],
+                vec!["seek", "1.5"],
+                vec!["next"],
+                vec!["queue"],
+            ]
+        );
+    }
+
+    #[test]
+    fn status_is_read_from_the_reply() {
+        let runtime = runtime();
+        let (server, mut client) = serve(&runtime);
+        let requests = answer(server.requests, 1);
+        let status = runtime
+            .block_on(client.get_status(proto::Empty {}))
+            .unwrap()
+            .into_inner();
+        requests.join().unwrap();
+        assert_eq!(status.state(), proto::status::State::Playing);
+        assert_eq!(status.track, Some(2));
+        assert_eq!(status.tracks, 3);
+        assert_eq!(status.path.as_deref(), Some("/music/b.flac"));
+        assert_eq!(status.artist, None);
+        assert_eq!(status.title.as_deref(), Some("B"));
+        assert_eq!(status.position_seconds, Some(1.5));
+        assert_eq!(status.duration_seconds, None);
+        assert_eq!(status.volume, 80);
+        assert_eq!(status.repeat(), proto::status::Repeat::Queue);
+        assert!(status.shuffle);
+    }
+
+    #[test]
+    fn watchers_follow_the_published_status() {
+        let runtime = runtime();
+        let (server, mut client) = serve(&runtime);
+        runtime.block_on(async {
+            let mut watch = client
+                .watch_status(proto::Empty {})
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(server.status.receiver_count(), 1);
+            server.status.send_replace(STATUS.to_string());
+            let status = watch.message().await.unwrap().unwrap();
+            assert_eq!(status.title.as_deref(), Some("B"));
+            server
+                .status
+                .send_replace("state\tstopped\ntracks\t0\n".to_string());
+            let status = watch.message().await.unwrap().unwrap();
+            assert_eq!(status.state(), proto::status::State::Stopped);
+            assert_eq!(status.track, None);
+        });
+    }
+}
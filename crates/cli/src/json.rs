@@ -0,0 +1,464 @@
+// JSON output, for commands that report with `--format json`
+
+use mogbox_encode::{DynamicRange, LoudnessReport, StatsReport};
+use mogbox_engine::{linear_to_db, HISTOGRAM_STEP};
+use mogbox_io::AudioFile;
+use mogbox_runtime::HistoryEntry;
+use symphonia::core::meta::Value;
+
+use crate::format_timestamp;
+
+/// A JSON value, for commands that report with `--format json`
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    /// Already formatted, so values keep the precision they are reported at
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub(crate) fn object<'a>(members: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// A number with a fixed count of decimals, `null` when not finite
+    pub(crate) fn rounded(value: impl Into<f64>, decimals: usize) -> Json {
+        let value = value.into();
+        if value.is_finite() {
+            Json::Number(format!("{:.*}", decimals, value))
+        } else {
+            Json::Null
+        }
+    }
+
+    pub(crate) fn seconds(duration: std::time::Duration) -> Json {
+        Json::rounded(duration.as_secs_f64(), 3)
+    }
+
+    pub(crate) fn path(path: &std::path::Path) -> Json {
+        Json::String(path.to_string_lossy().into_owned())
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(values: Vec<T>) -> Self {
+        Json::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<f32> for Json {
+    fn from(value: f32) -> Self {
+        if value.is_finite() {
+            Json::Number(value.to_string())
+        } else {
+            Json::Null
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+macro_rules! json_from_integer {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Json {
+            fn from(value: $t) -> Self {
+                Json::Number(value.to_string())
+            }
+        })*
+    };
+}
+
+json_from_integer!(u8, u32, u64, usize);
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => f.write_str(value),
+            Json::String(value) => f.write_str(&json_string(value)),
+            Json::Array(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(members) => {
+                f.write_str("{")?;
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}:{}", json_string(name), value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// Quotes a string for JSON output
+fn json_string(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+pub(crate) fn info_json(path: &std::path::Path, audio_file: &AudioFile) -> Json {
+    let tags: Vec<Json> = audio_file
+        .tags
+        .iter()
+        .map(|tag| {
+            let value = match &tag.value {
+                Value::Binary(data) => Json::object([("bytes", data.len().into())]),
+                value => value.to_string().trim_end_matches('\0').into(),
+            };
+            Json::object([("key", tag.key.as_str().into()), ("value", value)])
+        })
+        .collect();
+    let cover_art: Vec<Json> = audio_file
+        .visuals
+        .iter()
+        .map(|visual| {
+            Json::object([
+                (
+                    "usage",
+                    visual.usage.map(|usage| format!("{:?}", usage)).into(),
+                ),
+                ("media_type", visual.media_type.as_str().into()),
+                ("width", visual.dimensions.map(|size| size.width).into()),
+                ("height", visual.dimensions.map(|size| size.height).into()),
+                ("bytes", visual.data.len().into()),
+            ])
+        })
+        .collect();
+    let audio_tracks: Vec<Json> = audio_file
+        .audio_tracks
+        .iter()
+        .map(|track| {
+            Json::object([
+                ("id", track.id.into()),
+                ("codec", track.codec.map(|codec| codec.short_name).into()),
+                ("sample_rate_hz", track.sample_rate.into()),
+                ("channels", track.channels.into()),
+                ("language", track.language.clone().into()),
+            ])
+        })
+        .collect();
+    let chapters: Vec<Json> = audio_file
+        .chapters
+        .iter()
+        .map(|chapter| {
+            Json::object([
+                ("start_seconds", Json::seconds(chapter.start)),
+                ("title", chapter.title.clone().into()),
+            ])
+        })
+        .collect();
+    Json::object([
+        ("path", Json::path(path)),
+        (
+            "codec",
+            audio_file.codec().map(|codec| codec.short_name).into(),
+        ),
+        (
+            "duration_seconds",
+            audio_file.duration().map(Json::seconds).into(),
+        ),
+        ("sample_rate_hz", audio_file.sample_rate.into()),
+        ("channels", audio_file.channels.into()),
+        ("bits_per_sample", audio_file.bits_per_sample.into()),
+        ("bitrate_bps", audio_file.average_bitrate().into()),
+        ("track_id", audio_file.track_id.into()),
+        ("audio_tracks", audio_tracks.into()),
+        ("tags", tags.into()),
+        ("cover_art", cover_art.into()),
+        ("chapters", chapters.into()),
+    ])
+}
+
+pub(crate) fn loudness_json(path: &std::path::Path, report: &LoudnessReport) -> Json {
+    let number = |value: Option<f32>| value.map_or(Json::Null, |value| Json::rounded(value, 1));
+    Json::object([
+        ("path", Json::path(path)),
+        ("integrated_lufs", number(report.integrated)),
+        ("loudness_range_lu", number(report.range)),
+        ("momentary_max_lufs", number(report.momentary_max)),
+        ("short_term_max_lufs", number(report.short_term_max)),
+        ("true_peak_dbtp", number(report.true_peak)),
+    ])
+}
+
+pub(crate) fn stats_json(path: &std::path::Path, report: &StatsReport) -> Json {
+    let seconds = report.duration().as_secs_f64();
+    let channels: Vec<Json> = report
+        .channels
+        .iter()
+        .map(|stats| {
+            let last = stats.histogram.len() - 1;
+            let histogram: Vec<Json> = stats
+                .histogram
+                .iter()
+                .enumerate()
+                .map(|(bin, &count)| {
+                    let high = 0.0 - bin as f32 * HISTOGRAM_STEP;
+                    let low = (bin != last).then_some(high - HISTOGRAM_STEP);
+                    Json::object([
+                        ("high_db", high.into()),
+                        ("low_db", low.into()),
+                        ("samples", count.into()),
+                    ])
+                })
+                .collect();
+            Json::object([
+                ("peak_dbfs", Json::rounded(linear_to_db(stats.peak), 2)),
+                ("rms_dbfs", Json::rounded(linear_to_db(stats.rms), 2)),
+                (
+                    "crest_factor_db",
+                    stats
+                        .crest_factor()
+                        .map(|crest| Json::rounded(crest, 2))
+                        .into(),
+                ),
+                ("zero_crossings", stats.zero_crossings.into()),
+                (
+                    "zero_crossings_per_second",
+                    Json::rounded(
+                        stats.zero_crossings as f64 / seconds.max(f64::MIN_POSITIVE),
+                        1,
+                    ),
+                ),
+                ("samples", stats.samples.into()),
+                ("histogram", histogram.into()),
+            ])
+        })
+        .collect();
+    Json::object([
+        ("path", Json::path(path)),
+        ("duration_seconds", Json::seconds(report.duration())),
+        ("frames", report.frames.into()),
+        ("sample_rate_hz", report.sample_rate.into()),
+        ("channels", channels.into()),
+    ])
+}
+
+/// A track's figures, or the album's without a path
+pub(crate) fn dr_json(path: Option<&std::path::Path>, dr: &DynamicRange) -> Json {
+    let mut members = vec![
+        ("dr", dr.score().into()),
+        ("value_db", Json::rounded(dr.value, 2)),
+        ("peak_dbfs", Json::rounded(dr.peak, 2)),
+        ("rms_dbfs", Json::rounded(dr.rms, 2)),
+    ];
+    if let Some(path) = path {
+        members.insert(0, ("path", Json::path(path)));
+    }
+    Json::object(members)
+}
+
+pub(crate) fn history_json(entry: &HistoryEntry) -> Json {
+    Json::object([
+        ("played_at", format_timestamp(entry.played_at).into()),
+        ("path", Json::path(&entry.path)),
+        ("artist", entry.artist.clone().into()),
+        ("title", entry.title.clone().into()),
+        ("album", entry.album.clone().into()),
+        ("duration", entry.duration.map(Json::seconds).into()),
+        ("position", Json::seconds(entry.position)),
+        (
+            "completion",
+            entry
+                .completion()
+                .map(|completion| Json::rounded(completion, 3))
+                .into(),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use mogbox_engine::ChannelStats;
+    use mogbox_io::{PcmEncoding, RawFormat};
+
+    use super::*;
+
+    #[test]
+    fn strings_are_escaped() {
+        let json = Json::from("a \"b\"\\\n\t\u{1}é");
+        assert_eq!(json.to_string(), r#""a \"b\"\\\n\t\u0001é""#);
+    }
+
+    #[test]
+    fn numbers_that_are_not_finite_are_null() {
+        assert_eq!(Json::rounded(f64::NAN, 2).to_string(), "null");
+        assert_eq!(Json::from(f32::INFINITY).to_string(), "null");
+        assert_eq!(Json::rounded(-1.005f32, 1).to_string(), "-1.0");
+        assert_eq!(
+            Json::seconds(Duration::from_millis(1500)).to_string(),
+            "1.500"
+        );
+    }
+
+    #[test]
+    fn objects_keep_their_order() {
+        let json = Json::object([
+            ("b", Json::from(vec![1u32, 2])),
+            ("a", Json::from(None::<bool>)),
+            ("c", Json::object([])),
+        ]);
+        assert_eq!(json.to_string(), r#"{"b":[1,2],"a":null,"c":{}}"#);
+    }
+
+    #[test]
+    fn info_report() {
+        let path = std::env::temp_dir().join(format!("mogbox-info-{}.raw", std::process::id()));
+        std::fs::write(&path, vec![0u8; 8000 * 2]).unwrap();
+        let format = RawFormat {
+            encoding: PcmEncoding::S16Le,
+            sample_rate: 8000,
+            channels: 1,
+        };
+        let audio_file = AudioFile::open_raw(&path, format);
+        std::fs::remove_file(&path).unwrap();
+        let json = info_json(Path::new("in.raw"), &audio_file.unwrap());
+        assert_eq!(
+            json.to_string(),
+            concat!(
+                r#"{"path":"in.raw","codec":"pcm_s16le","duration_seconds":1.000,"#,
+                r#""sample_rate_hz":8000,"channels":1,"bits_per_sample":16,"#,
+                r#""bitrate_bps":128000,"track_id":0,"audio_tracks":[],"tags":[],"#,
+                r#""cover_art":[],"chapters":[]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn loudness_report() {
+        let report = LoudnessReport {
+            integrated: Some(-14.04),
+            range: Some(5.96),
+            momentary_max: Some(-9.0),
+            short_term_max: Some(-11.55),
+            true_peak: None,
+        };
+        let json = loudness_json(Path::new("a.flac"), &report);
+        assert_eq!(
+            json.to_string(),
+            concat!(
+                r#"{"path":"a.flac","integrated_lufs":-14.0,"loudness_range_lu":6.0,"#,
+                r#""momentary_max_lufs":-9.0,"short_term_max_lufs":-11.6,"#,
+                r#""true_peak_dbtp":null}"#
+            )
+        );
+    }
+
+    #[test]
+    fn stats_report() {
+        let report = StatsReport {
+            sample_rate: 4,
+            frames: 8,
+            channels: vec![ChannelStats {
+                peak: 0.5,
+                rms: 0.25,
+                zero_crossings: 3,
+                histogram: vec![6, 2],
+                samples: 8,
+            }],
+        };
+        let json = stats_json(Path::new("a.flac"), &report);
+        assert_eq!(
+            json.to_string(),
+            concat!(
+                r#"{"path":"a.flac","duration_seconds":2.000,"frames":8,"sample_rate_hz":4,"#,
+                r#""channels":[{"peak_dbfs":-6.02,"rms_dbfs":-12.04,"crest_factor_db":6.02,"#,
+                r#""zero_crossings":3,"zero_crossings_per_second":1.5,"samples":8,"#,
+                r#""histogram":[{"high_db":0,"low_db":-6,"samples":6},"#,
+                r#"{"high_db":-6,"low_db":null,"samples":2}]}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn dr_report() {
+        let dr = DynamicRange {
+            value: 11.6,
+            peak: -0.1,
+            rms: -14.25,
+        };
+        assert_eq!(
+            dr_json(None, &dr).to_string(),
+            r#"{"dr":12,"value_db":11.60,"peak_dbfs":-0.10,"rms_dbfs":-14.25}"#
+        );
+        assert!(dr_json(Some(Path::new("a.flac")), &dr)
+            .to_string()
+            .starts_with(r#"{"path":"a.flac","dr":12,"#));
+    }
+
+    #[test]
+    fn history_report() {
+        let entry = HistoryEntry {
+            played_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            path: "a.flac".into(),
+            artist: Some("Artist".to_string()),
+            title: None,
+            album: None,
+            duration: Some(Duration::from_secs(200)),
+            position: Duration::from_secs(50),
+        };
+        assert_eq!(
+            history_json(&entry).to_string(),
+            concat!(
+                r#"{"played_at":"2023-11-14 22:13:20Z","path":"a.flac","artist":"Artist","#,
+                r#""title":null,"album":null,"duration":200.000,"position":50.000,"#,
+                r#""completion":0.250}"#
+            )
+        );
+    }
+}
@@ -1,56 +1,5416 @@
-use clap::{Parser, Subcommand};
-use mogbox_io::AudioFile;
+mod daemon;
+mod discord;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod json;
+mod mpd;
+#[cfg(all(feature = "mpris", unix, not(target_os = "macos")))]
+mod mpris;
+#[cfg(all(feature = "now-playing", target_os = "macos"))]
+mod now_playing;
+#[cfg(feature = "http")]
+mod rest;
+mod scrobble;
+#[cfg(all(feature = "smtc", windows))]
+mod smtc;
+#[cfg(feature = "http")]
+mod websocket;
+
+use clap::{ArgGroup, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
+use mogbox_analysis::{
+    correlation_report, fingerprint, key, lookup, parse_color, peaks, spectrogram, spectrum, tempo,
+    text_waveform_peaks, waveform_image, waveform_peaks, waveform_svg, waveform_text, Colormap,
+    CorrelationReport, Graphics, Image, LevelMeter, PeakBits, PhaseScope, SpectrogramOptions,
+    SpectrumBars, WaveformOptions, Window, CORRELATION_WINDOW,
+};
+use mogbox_encode::{
+    backend, backend_for_path, clipping_report, convert_batch, convert_with, cue_segments,
+    dc_offsets, dynamic_range, loudness_report, parse_bitrate, plan_batch, scan_gain, set_cover,
+    silence_report, silence_segments, stats_report, update_tags, write_gain_tags, CoverArt,
+    DynamicRange, Edits, EncodeOptions, MixInput, Remix, SilenceReport,
+};
+use mogbox_engine::{
+    linear_to_db, parse_matrix, parse_routing, ChannelMatrix, ChannelRouting, DcBlocker,
+    DitherMode, FadeCurve, ResamplerQuality, SkipSilence, TruePeakLimiter, HISTOGRAM_STEP,
+};
+use mogbox_io::{
+    cue::CueSheet,
+    http, playlist,
+    podcast::{Episode, Feed},
+    scan, stdin, AudioFile, Chapter, LoopPoints, Lyrics, PcmEncoding, RawFormat,
+};
+use mogbox_runtime::{
+    file_key, list_hosts, next_local_time, parse_host, read_history, AudioPlayer, Bookmark,
+    BookmarkStore, Chain, DeviceEvent, HistoryEntry, HistoryLog, HostId, LoopRegion,
+    PlaybackOptions, PlaybackQueue, PlayerConfig, PlayerStats, Podcast, PodcastEpisode,
+    PodcastStore, Processed, QueueSource, RepeatMode, ReplayGainConfig, ReplayGainMode,
+    ResumeStore, Tap, WavSink,
+};
+use symphonia::core::meta::{StandardTagKey, Value};
+
+use daemon::{handle_ctl, handle_daemon};
+use json::{dr_json, history_json, info_json, loudness_json, stats_json, Json};
+use scrobble::{LastfmConfig, ScrobbleService};
+
+/// How often playback health is checked for new underruns
+const STATS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// How long playback fades out before the sleep timer stops it
+const SLEEP_FADE: std::time::Duration = std::time::Duration::from_secs(10);
+/// How long playback fades out at the end of `--duration`, to stop without a click
+const WINDOW_FADE: std::time::Duration = std::time::Duration::from_millis(20);
+/// How far the arrow keys seek while playing
+const SEEK_STEP: std::time::Duration = std::time::Duration::from_secs(5);
+/// How much the arrow keys change the volume while playing
+const VOLUME_STEP: f32 = 0.1;
+/// How often a live visualization is redrawn
+const VISUALIZER_INTERVAL: std::time::Duration = std::time::Duration::from_millis(33);
+/// Height of a live visualization, in lines
+const VISUALIZER_ROWS: usize = 12;
+/// Stretch of output the live level meter takes its RMS over
+const METER_WINDOW: std::time::Duration = std::time::Duration::from_millis(300);
+/// Room taken by a level meter line besides the bar: channel number and levels
+const METER_LABELS: usize = 38;
+/// Audio fingerprinted for lookups, as much as AcoustID's own tools use
+const FINGERPRINT_LENGTH: std::time::Duration = std::time::Duration::from_secs(120);
+/// Width of cover art drawn in the terminal, in character cells
+const ART_COLUMNS: usize = 32;
+/// How often the TUI is redrawn
+const TUI_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+/// How long a message stays in the TUI's status line
+const TUI_MESSAGE_TIME: std::time::Duration = std::time::Duration::from_secs(4);
+/// Rows of the TUI's spectrum, shown when the terminal has room for it
+const TUI_VISUALIZER_ROWS: usize = 6;
+/// How often the daemon checks for requests and tracks changing
+const DAEMON_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+/// How long the daemon waits for a client to send its request; Windows pipes
+/// have no timeouts
+#[cfg(any(unix, feature = "http"))]
+const DAEMON_READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+/// How often `play --events` reports the position
+const PROGRESS_EVENT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
 
 #[derive(Parser)]
 #[command(name = "MogBox")]
 struct Cli {
+    /// Output of info and analysis commands: `text`, or `json` for scripts
+    #[arg(long, value_name = "FORMAT", value_parser = parse_output_format)]
+    format: Option<OutputFormat>,
     #[command(subcommand)]
     command: Commands,
 }
 
+/// `--format` of the commands that can report as JSON. Each of them takes it
+/// rather than it being global, since decode's `--format` is a sample format.
+#[derive(Args, Debug)]
+struct OutputArgs {
+    /// Output: `text`, or `json` for scripts
+    #[arg(long, global = true, value_name = "FORMAT", value_parser = parse_output_format)]
+    format: Option<OutputFormat>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    /// One JSON object on stdout, with units in the field names
+    Json,
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
-    // Print info and metadata for an audio file
+    /// Print info and metadata for an audio file
     Info {
         #[arg(value_name = "PATH")]
         path: std::path::PathBuf,
+        #[command(flatten)]
+        output: OutputArgs,
+        /// Draw an overview of the waveform in the terminal
+        #[arg(long)]
+        waveform: bool,
+        /// Show the cover art in the terminal, in graphics it supports or as text;
+        /// `--art=kitty`, `iterm`, `sixel` or `ascii` picks one
+        #[arg(
+            long,
+            value_name = "MODE",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "auto"
+        )]
+        art: Option<Graphics>,
+    },
+    /// Play one or more audio files back to back on the default output device
+    Play(PlayArgs),
+    /// List audio hosts, output devices and their supported configs
+    Devices,
+    /// Transcode an audio file; the output format follows the file extension
+    ///
+    /// With --to or --out-dir, transcode many files in parallel instead.
+    Convert(ConvertArgs),
+    /// Decode an audio file to headerless interleaved PCM, e.g. to pipe into ffmpeg or sox
+    Decode(DecodeArgs),
+    /// Cut the region between --start and --end out of an audio file
+    Trim(TrimArgs),
+    /// Join audio files end to end into one file
+    Join(JoinArgs),
+    /// Split a single-file album rip into tracks by cue sheet or at silent gaps
+    Split(SplitArgs),
+    /// Mix audio files together into one file, e.g. a voice over a music bed
+    Mix(MixArgs),
+    /// Measure EBU R128 loudness: integrated, range, maxima and true peak
+    Loudness {
+        #[arg(value_name = "PATH")]
+        path: std::path::PathBuf,
+        #[command(flatten)]
+        output: OutputArgs,
+        /// Same as `--format json`
+        #[arg(long)]
+        json: bool,
+    },
+    /// Inspect a file for problems such as clipping
+    Analyze(AnalyzeArgs),
+    /// Print per-channel peak, RMS, crest factor, zero crossings and a level histogram
+    Stats {
+        #[arg(value_name = "PATH")]
+        path: std::path::PathBuf,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Print the magnitude spectrum of a region of a file
+    Spectrum(SpectrumArgs),
+    /// Render a spectrogram of a file to a PNG image
+    Spectrogram(SpectrogramArgs),
+    /// Draw the waveform of a file to a PNG or SVG image
+    Waveform(WaveformArgs),
+    /// Write downsampled peak data for web waveform players, as audiowaveform .dat or JSON
+    Peaks(PeaksArgs),
+    /// Estimate the tempo of a track in BPM
+    Bpm(BpmArgs),
+    /// Estimate the musical key of a track
+    Key(KeyArgs),
+    /// Print the Chromaprint acoustic fingerprint of a track, as fpcalc does
+    Fingerprint(FingerprintArgs),
+    /// Look a track up on AcoustID and MusicBrainz by its fingerprint, and optionally tag it
+    Identify(IdentifyArgs),
+    /// Measure the DR14-style dynamic range of tracks and of them as an album
+    Dr {
+        /// Files, directories and glob patterns such as "album/*.flac"
+        #[arg(value_name = "PATH", required = true)]
+        paths: Vec<String>,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Measure and manage ReplayGain loudness values
+    Gain {
+        #[command(subcommand)]
+        action: GainAction,
+    },
+    /// Manage the playback queue of the last play session
+    Queue {
+        #[command(subcommand)]
+        action: QueueAction,
+    },
+    /// Embed cover art in audio files
+    Art {
+        #[command(subcommand)]
+        action: ArtAction,
+    },
+    /// Save named positions in files and play from them
+    Bookmark {
+        #[command(subcommand)]
+        action: BookmarkAction,
+    },
+    /// Subscribe to podcasts and play their episodes, picking up where they were left off
+    Podcast {
+        #[command(subcommand)]
+        action: PodcastAction,
+    },
+    /// Show the tracks played, most recent first
+    History(HistoryArgs),
+    /// Wait until a time of day, then start playing with a fade-in
+    Alarm(AlarmArgs),
+    /// Browse files, queue them up and play them in a full-screen terminal interface
+    ///
+    /// Only on Unix, where keys can be read as they are pressed.
+    Tui(TuiArgs),
+    /// Show the settings read from the config file, with defaults for the rest
+    Config {
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Print a completion script for bash, zsh, fish, powershell or elvish
+    ///
+    /// For example `mogbox completions bash > /etc/bash_completion.d/mogbox`.
+    Completions {
+        #[arg(value_name = "SHELL", value_parser = parse_shell)]
+        shell: Shell,
+    },
+    /// Keep a player and its queue running in the foreground, controlled with `mogbox ctl`
+    ///
+    /// The daemon listens on a Unix socket (a named pipe on Windows), so playback
+    /// outlives a single command.
+    Daemon(DaemonArgs),
+    /// Control a running daemon: play, pause, skip tracks and show what is playing
+    Ctl(CtlArgs),
+}
+
+#[derive(Args, Debug)]
+struct TuiArgs {
+    /// Files, directories and playlists to browse; the configured library or
+    /// else the current directory by default
+    #[arg(value_name = "PATH")]
+    paths: Vec<std::path::PathBuf>,
+    #[arg(long, value_name = "NAME|INDEX")]
+    device: Option<String>,
+    #[arg(long, value_name = "HOST", value_parser = parse_host)]
+    host: Option<HostId>,
+    /// Playback volume in percent, such as `80`
+    #[arg(long, value_name = "PERCENT", value_parser = parse_volume)]
+    volume: Option<f32>,
+}
+
+#[derive(Args, Debug)]
+struct DaemonArgs {
+    /// Files, directories and playlists to start playing
+    #[arg(value_name = "PATH")]
+    paths: Vec<std::path::PathBuf>,
+    #[arg(long, value_name = "NAME|INDEX")]
+    device: Option<String>,
+    #[arg(long, value_name = "HOST", value_parser = parse_host)]
+    host: Option<HostId>,
+    /// Playback volume in percent, such as `80`
+    #[arg(long, value_name = "PERCENT", value_parser = parse_volume)]
+    volume: Option<f32>,
+    /// Socket to listen on instead of `mogbox.sock` in the runtime directory, or the
+    /// name of the pipe on Windows
+    #[arg(long, value_name = "PATH")]
+    socket: Option<std::path::PathBuf>,
+    /// Also serve a REST API on this address, such as `127.0.0.1:6680`
+    #[cfg(feature = "http")]
+    #[arg(long, value_name = "ADDRESS")]
+    http: Option<std::net::SocketAddr>,
+    /// Also serve the gRPC service of `proto/mogbox.proto` on this address, such as
+    /// `127.0.0.1:6681`
+    #[cfg(feature = "grpc")]
+    #[arg(long, value_name = "ADDRESS")]
+    grpc: Option<std::net::SocketAddr>,
+    /// Also speak the MPD protocol on this address, such as `127.0.0.1:6600`, for MPD
+    /// clients; they browse the first library directory of the config
+    #[arg(long, value_name = "ADDRESS")]
+    mpd: Option<std::net::SocketAddr>,
+}
+
+#[derive(Args, Debug)]
+struct CtlArgs {
+    /// Socket of the daemon, when it was started with `--socket`
+    #[arg(long, value_name = "PATH")]
+    socket: Option<std::path::PathBuf>,
+    #[command(subcommand)]
+    action: CtlAction,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlAction {
+    /// Resume playback or start the queue; with paths, replace the queue with them first
+    Play {
+        /// Files, directories and playlists
+        #[arg(value_name = "PATH")]
+        paths: Vec<std::path::PathBuf>,
+    },
+    /// Pause playback, keeping the position
+    Pause,
+    /// Pause or resume playback
+    Toggle,
+    /// Stop playback
+    Stop,
+    /// Skip to the next track in the queue
+    Next,
+    /// Go back to the previous track in the queue
+    Previous,
+    /// Continue the current track at a position
+    Seek {
+        /// Position such as `1:23` or `90s`
+        #[arg(value_name = "TIME", value_parser = parse_duration)]
+        position: std::time::Duration,
+    },
+    /// Set the playback volume
+    Volume {
+        /// Volume in percent, such as `80`
+        #[arg(value_name = "PERCENT", value_parser = parse_volume)]
+        volume: f32,
+    },
+    /// Add tracks to the end of the queue
+    Add {
+        /// Files, directories and playlists
+        #[arg(value_name = "PATH", required = true)]
+        paths: Vec<std::path::PathBuf>,
+    },
+    /// Stop playback and empty the queue
+    Clear,
+    /// List the tracks in the queue
+    Queue,
+    /// Show what is playing
+    Status,
+    /// Stop playback and shut the daemon down
+    Shutdown,
+}
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("when").required(true)))]
+struct AlarmArgs {
+    /// Local time of day to start at, such as `07:00` or `6:45:30`
+    #[arg(long, value_name = "HH:MM", value_parser = parse_time_of_day, group = "when")]
+    at: Option<(u32, u32, u32)>,
+    /// Start after this long instead, such as `8h`
+    #[arg(long = "in", value_name = "TIME", value_parser = parse_duration, group = "when")]
+    after: Option<std::time::Duration>,
+    /// File, directory or playlist to play; repeat for more
+    #[arg(long = "file", value_name = "PATH", required = true)]
+    files: Vec<std::path::PathBuf>,
+    /// How long the volume takes to rise from silence
+    #[arg(long, value_name = "TIME", value_parser = parse_duration, default_value = "30s")]
+    fade_in: std::time::Duration,
+    #[arg(long, value_name = "MODE", default_value = "off")]
+    repeat: RepeatMode,
+    #[arg(long, value_name = "NAME|INDEX")]
+    device: Option<String>,
+    #[arg(long, value_name = "HOST", value_parser = parse_host)]
+    host: Option<HostId>,
+}
+
+#[derive(Args, Debug)]
+struct HistoryArgs {
+    /// Number of tracks to show, 0 for all
+    #[arg(short = 'n', long, default_value_t = 20)]
+    limit: usize,
+    /// Only tracks whose artist, title, album or path contain this, ignoring case
+    #[arg(long, value_name = "TEXT")]
+    search: Option<String>,
+    /// Only tracks played within this long, such as `12h` or `7d`
+    #[arg(long, value_name = "TIME", value_parser = parse_duration)]
+    since: Option<std::time::Duration>,
+    /// Delete the whole history
+    #[arg(long, conflicts_with_all = ["search", "since"])]
+    clear: bool,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+struct PlayArgs {
+    /// Files, directories, playlists and URLs to play; `-` reads from stdin
+    #[arg(value_name = "PATH", required = true)]
+    paths: Vec<std::path::PathBuf>,
+    /// Play the files as headerless PCM in this format, such as `f32le:48000:2`
+    /// or `s16le:44100:1`, instead of probing them
+    #[arg(long, value_name = "FORMAT:RATE:CHANNELS")]
+    raw: Option<RawFormat>,
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    crossfade: Option<std::time::Duration>,
+    #[arg(long)]
+    shuffle: bool,
+    #[arg(long, value_name = "MODE", default_value = "off")]
+    repeat: RepeatMode,
+    #[arg(long, value_name = "NAME|INDEX")]
+    device: Option<String>,
+    #[arg(long, value_name = "HOST", value_parser = parse_host)]
+    host: Option<HostId>,
+    /// Input channels for each output channel, e.g. `1,0` to swap or `0+1,-` for a mono sum on the left
+    #[arg(long, value_name = "MAP", value_parser = parse_routing)]
+    channels: Option<ChannelRouting>,
+    /// Output buffer as frames (`512`) or as a latency (`20ms`)
+    #[arg(long, value_name = "FRAMES|DURATION", value_parser = parse_buffer)]
+    buffer: Option<BufferSize>,
+    /// Bit-perfect output at the source rate, bypassing volume and effects
+    #[arg(long)]
+    bit_perfect: bool,
+    /// Level tracks by their ReplayGain tags: `track`, `album` or `off` (the default)
+    #[arg(long, value_name = "MODE", conflicts_with = "bit_perfect")]
+    replaygain: Option<ReplayGainMode>,
+    /// Playback volume in percent, such as `80`
+    #[arg(long, value_name = "PERCENT", value_parser = parse_volume, conflicts_with_all = ["bit_perfect", "output"])]
+    volume: Option<f32>,
+    /// Gain in dB added to the ReplayGain adjustment
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value = "0",
+        value_parser = parse_db,
+        allow_hyphen_values = true
+    )]
+    preamp: f32,
+    /// Remove DC offset with a 10 Hz high-pass
+    #[arg(long, conflicts_with = "bit_perfect")]
+    dc_block: bool,
+    /// Keep the true peak of the output under a ceiling, -1 dBTP by default
+    #[arg(
+        long,
+        value_name = "DBTP",
+        num_args = 0..=1,
+        default_missing_value = "-1dBTP",
+        allow_hyphen_values = true,
+        value_parser = parse_dbtp,
+        conflicts_with = "bit_perfect"
+    )]
+    limit: Option<f32>,
+    /// Shorten silent gaps, such as pauses in lectures and audiobooks, where the level
+    /// stays below a threshold: -50 dB by default, others as `--skip-silence -40dB`
+    #[arg(
+        long,
+        value_name = "DB",
+        num_args = 0..=1,
+        default_missing_value = "-50dB",
+        allow_hyphen_values = true,
+        value_parser = parse_db,
+        conflicts_with = "bit_perfect"
+    )]
+    skip_silence: Option<f32>,
+    /// Longest part of each silent gap that is still played; 0 skips gaps entirely
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "250ms", requires = "skip_silence")]
+    max_gap: std::time::Duration,
+    /// Write the queue to a WAV file instead of playing it
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["device", "host", "bit_perfect"])]
+    output: Option<std::path::PathBuf>,
+    /// Draw the output live in the terminal: `spectrum`, `meter` or `phase`
+    #[arg(long, value_name = "MODE", value_parser = parse_visualization, conflicts_with_all = ["bit_perfect", "output"])]
+    visualize: Option<Visualization>,
+    /// Show each track's cover art as it starts, like `info --art`
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto",
+        conflicts_with = "output"
+    )]
+    art: Option<Graphics>,
+    /// Print synced lyrics line by line as they are sung, from a `.lrc` file next to
+    /// each track or from its tags
+    #[arg(long, conflicts_with = "output")]
+    lyrics: bool,
+    /// Start the first track at chapter N, counting from 1, such as a chapter of an audiobook
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "output")]
+    chapter: Option<u32>,
+    /// Start where the track was left off last time, and keep track of the position while
+    /// playing, such as for audiobooks
+    #[arg(long, conflicts_with_all = ["output", "chapter"])]
+    resume: bool,
+    /// Start the first track at one of its bookmarks, by name or number
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["output", "chapter", "resume"])]
+    bookmark: Option<String>,
+    /// Don't record the tracks played in the history
+    #[arg(long)]
+    no_history: bool,
+    /// Stop playback after this long, such as `30m`, fading out at the end
+    #[arg(long, value_name = "TIME", value_parser = parse_duration, conflicts_with = "output")]
+    sleep: Option<std::time::Duration>,
+    /// Raise the volume from silence over this long when playback starts
+    #[arg(long, value_name = "TIME", value_parser = parse_duration, conflicts_with = "bit_perfect")]
+    fade_in: Option<std::time::Duration>,
+    /// Play a stretch of the first track over and over, such as `1:05-1:32` to practice a part
+    #[arg(long, value_name = "A-B", value_parser = parse_ab_loop, conflicts_with_all = ["output", "chapter", "resume", "bookmark"])]
+    ab_loop: Option<(std::time::Duration, std::time::Duration)>,
+    /// Play the first track over and over seamlessly, between its loop points if it has
+    /// them, from a WAV `smpl` chunk or `LOOPSTART` tags
+    #[arg(long = "loop", conflicts_with_all = ["output", "chapter", "resume", "bookmark", "ab_loop"])]
+    looping: bool,
+    /// Where the loop starts instead of the track's loop point
+    #[arg(long, value_name = "TIME", value_parser = parse_duration, requires = "looping")]
+    loop_start: Option<std::time::Duration>,
+    /// Where the loop goes back to its start instead of the track's loop point
+    #[arg(long, value_name = "TIME", value_parser = parse_duration, requires = "looping")]
+    loop_end: Option<std::time::Duration>,
+    /// Start the first track this far into it, such as `2:00`
+    #[arg(long, value_name = "TIME", value_parser = parse_duration, conflicts_with_all = ["chapter", "resume", "bookmark", "ab_loop"])]
+    start: Option<std::time::Duration>,
+    /// Stop after playing this long, such as `30s` to audition a section
+    #[arg(long, value_name = "TIME", value_parser = parse_duration, conflicts_with = "sleep")]
+    duration: Option<std::time::Duration>,
+    /// Report playback on stdout as `ndjson`, one JSON event per line, for programs that
+    /// use mogbox as a backend; other messages go to stderr
+    #[arg(long, value_name = "FORMAT", value_parser = parse_events, conflicts_with_all = ["output", "visualize", "art"])]
+    events: Option<EventFormat>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EventFormat {
+    Ndjson,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BufferSize {
+    Frames(u32),
+    Latency(std::time::Duration),
+}
+
+/// Colors of the TUI's bars and selection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Theme {
+    /// The terminal's own colors, inverted
+    #[default]
+    Default,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+impl Theme {
+    const ALL: [Theme; 7] = [
+        Theme::Default,
+        Theme::Red,
+        Theme::Green,
+        Theme::Yellow,
+        Theme::Blue,
+        Theme::Magenta,
+        Theme::Cyan,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Theme::Default => "default",
+            Theme::Red => "red",
+            Theme::Green => "green",
+            Theme::Yellow => "yellow",
+            Theme::Blue => "blue",
+            Theme::Magenta => "magenta",
+            Theme::Cyan => "cyan",
+        }
+    }
+
+    /// SGR parameters of highlighted text, with readable foregrounds
+    fn style(self) -> &'static str {
+        match self {
+            Theme::Default => "7",
+            Theme::Red => "97;41",
+            Theme::Green => "30;42",
+            Theme::Yellow => "30;43",
+            Theme::Blue => "97;44",
+            Theme::Magenta => "97;45",
+            Theme::Cyan => "30;46",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Visualization {
+    /// Log-spaced frequency bars
+    Spectrum,
+    /// Peak and RMS level per channel, with peak hold
+    Meter,
+    /// Goniometer and stereo phase correlation
+    Phase,
+}
+
+#[derive(Args, Debug)]
+struct ConvertArgs {
+    /// INPUT OUTPUT, or for batch conversion any number of files, directories and glob patterns such as "album/*.flac"
+    #[arg(value_name = "PATH", required = true)]
+    paths: Vec<String>,
+    /// Output format for batch conversion, e.g. `opus`; defaults to each input's own
+    #[arg(long, value_name = "FORMAT")]
+    to: Option<String>,
+    /// Directory for batch output; defaults to each input's directory
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<std::path::PathBuf>,
+    /// Number of files to convert at once; defaults to one per CPU core
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+    #[command(flatten)]
+    edits: EditArgs,
+    #[command(flatten)]
+    encoder: EncoderArgs,
+}
+
+#[derive(Args, Debug)]
+struct DecodeArgs {
+    /// File or URL to decode; `-` reads from stdin
+    #[arg(value_name = "INPUT")]
+    input: std::path::PathBuf,
+    /// Sample format to write, such as `s16le` or `f32le`
+    #[arg(long, value_name = "FORMAT")]
+    format: PcmEncoding,
+    /// Where the samples go; `-` writes them to stdout
+    #[arg(value_name = "OUTPUT")]
+    output: std::path::PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct TrimArgs {
+    #[arg(value_name = "INPUT")]
+    input: std::path::PathBuf,
+    #[arg(value_name = "OUTPUT")]
+    output: std::path::PathBuf,
+    /// Where to start, e.g. `1:23.5`; from the beginning by default
+    #[arg(long, value_name = "TIME", value_parser = parse_duration, required_unless_present = "end")]
+    start: Option<std::time::Duration>,
+    /// Where to stop; at the end by default
+    #[arg(long, value_name = "TIME", value_parser = parse_duration)]
+    end: Option<std::time::Duration>,
+    #[command(flatten)]
+    edits: EditArgs,
+    #[command(flatten)]
+    encoder: EncoderArgs,
+}
+
+#[derive(Args, Debug)]
+struct JoinArgs {
+    #[arg(value_name = "INPUT", required = true)]
+    inputs: Vec<std::path::PathBuf>,
+    #[arg(short, long, value_name = "OUTPUT")]
+    output: std::path::PathBuf,
+    /// Convert inputs whose sample rate or channels differ from the first one's
+    #[arg(long)]
+    resample: bool,
+    #[command(flatten)]
+    encoder: EncoderArgs,
+}
+
+#[derive(Args, Debug)]
+struct MixArgs {
+    #[arg(value_name = "INPUT", required = true)]
+    inputs: Vec<std::path::PathBuf>,
+    #[arg(short, long, value_name = "OUTPUT")]
+    output: std::path::PathBuf,
+    /// Level change per input in dB, in input order, e.g. `0,-8`
+    #[arg(
+        long,
+        value_name = "DB",
+        value_delimiter = ',',
+        value_parser = parse_db,
+        allow_hyphen_values = true
+    )]
+    gain: Vec<f32>,
+    /// Start time per input, in input order, e.g. `0,2.5s`
+    #[arg(long, value_name = "DURATION", value_delimiter = ',', value_parser = parse_duration)]
+    offset: Vec<std::time::Duration>,
+    /// Keep the true peak of the mix under a ceiling, -1 dBTP by default
+    #[arg(
+        long,
+        value_name = "DBTP",
+        num_args = 0..=1,
+        default_missing_value = "-1dBTP",
+        allow_hyphen_values = true,
+        value_parser = parse_dbtp
+    )]
+    limit: Option<f32>,
+    #[command(flatten)]
+    encoder: EncoderArgs,
+}
+
+#[derive(Args, Debug)]
+struct SplitArgs {
+    #[arg(value_name = "INPUT")]
+    input: std::path::PathBuf,
+    /// Cue sheet with the track boundaries and titles
+    #[arg(
+        long,
+        value_name = "PATH",
+        required_unless_present = "silence",
+        conflicts_with = "silence"
+    )]
+    cue: Option<std::path::PathBuf>,
+    /// Split where the level stays below this threshold, e.g. `-40dB`
+    #[arg(long, value_name = "DB", value_parser = parse_db, allow_hyphen_values = true)]
+    silence: Option<f32>,
+    /// Shortest silence that separates two tracks
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "2s", requires = "silence")]
+    min_gap: std::time::Duration,
+    /// Output format, e.g. `flac`; defaults to the input's
+    #[arg(long, value_name = "FORMAT")]
+    to: Option<String>,
+    /// Directory for the tracks; defaults to the input's directory
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<std::path::PathBuf>,
+    #[command(flatten)]
+    encoder: EncoderArgs,
+}
+
+// Processing shared by the commands that re-encode a file
+#[derive(Args, Debug)]
+struct EditArgs {
+    /// Fade in from silence over this long at the start
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    fade_in: Option<std::time::Duration>,
+    /// Fade out to silence over this long at the end
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    fade_out: Option<std::time::Duration>,
+    /// Fade shape, `lin` or `log`
+    #[arg(long, value_name = "CURVE", default_value = "lin")]
+    fade_curve: FadeCurve,
+    /// Remove DC offset with a 10 Hz high-pass
+    #[arg(long)]
+    dc_block: bool,
+    /// Bring the integrated loudness to this level, e.g. `-16LUFS`
+    #[arg(long, value_name = "LUFS", value_parser = parse_lufs, allow_hyphen_values = true)]
+    normalize: Option<f32>,
+    /// Keep the true peak under a ceiling, -1 dBTP by default, so
+    /// raising the level doesn't clip
+    #[arg(
+        long,
+        value_name = "DBTP",
+        num_args = 0..=1,
+        default_missing_value = "-1dBTP",
+        allow_hyphen_values = true,
+        value_parser = parse_dbtp
+    )]
+    limit: Option<f32>,
+    /// Fold the channels down to `stereo` or `mono`
+    #[arg(long, value_name = "LAYOUT", conflicts_with = "matrix")]
+    downmix: Option<Remix>,
+    /// Custom channel gains, one `;`-separated row per output channel,
+    /// e.g. `1,0;1,0` copies the left channel to both sides
+    #[arg(long, value_name = "GAINS", value_parser = parse_matrix, allow_hyphen_values = true)]
+    matrix: Option<ChannelMatrix>,
+}
+
+// Output settings shared by the commands that write audio files
+#[derive(Args, Debug)]
+struct EncoderArgs {
+    /// Bitrate for lossy output, such as `192k`; defaults to the encoder's
+    #[arg(long, value_name = "RATE", value_parser = parse_bitrate)]
+    bitrate: Option<u32>,
+    /// Bit depth for WAV and FLAC output; defaults to the source's
+    #[arg(long, value_name = "BITS")]
+    bits: Option<u16>,
+    /// FLAC compression level, 0 (fastest) to 8 (smallest)
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u8).range(0..=8))]
+    compression: Option<u8>,
+    /// Vorbis quality, -1 (smallest) to 10 (best), or MP3 VBR preset, 0 (best) to 9 (smallest)
+    #[arg(long, value_name = "QUALITY", allow_negative_numbers = true)]
+    quality: Option<f32>,
+    /// Constant bitrate instead of VBR
+    #[arg(long)]
+    cbr: bool,
+    /// Don't copy title, artist, album and cover art from the source
+    #[arg(long)]
+    strip_tags: bool,
+    /// Sample rate to convert to, e.g. 48000; defaults to the source's
+    #[arg(long, value_name = "HZ", value_parser = clap::value_parser!(u32).range(1000..=768000))]
+    rate: Option<u32>,
+    /// Sample-rate conversion quality: `sinc` (best), `fast` or `linear` (fastest)
+    #[arg(long, value_name = "KIND", default_value = "sinc")]
+    resampler: ResamplerQuality,
+    /// Dither when reducing to 16 or 24 bit: `tpdf`, `shaped` (noise shaped) or `none`
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "none",
+        num_args = 0..=1,
+        default_missing_value = "tpdf"
+    )]
+    dither: DitherMode,
+}
+
+impl PlayArgs {
+    fn replaygain(&self) -> ReplayGainConfig {
+        ReplayGainConfig {
+            mode: self.replaygain.unwrap_or_default(),
+            preamp: self.preamp,
+        }
+    }
+
+    fn skip_silence(&self) -> Option<SkipSilence> {
+        self.skip_silence.map(|threshold_db| SkipSilence {
+            threshold_db,
+            keep: self.max_gap,
+        })
+    }
+
+    /// Fills in what wasn't given on the command line from `config`
+    fn apply_config(&mut self, config: &Config) {
+        // Writing to a file doesn't use the device
+        if self.output.is_none() {
+            self.device = self.device.take().or(config.device.clone());
+            self.host = self.host.or(config.host);
+            self.volume = self.volume.or(config.volume);
+        }
+        if !self.bit_perfect {
+            self.replaygain = self.replaygain.or(config.replaygain);
+        }
+    }
+}
+
+impl EditArgs {
+    fn edits(&self) -> Edits {
+        Edits {
+            fade_in: self.fade_in,
+            fade_out: self.fade_out,
+            fade_curve: self.fade_curve,
+            dc_block: self.dc_block,
+            normalize: self.normalize,
+            limit: self.limit,
+            remix: self
+                .downmix
+                .clone()
+                .or_else(|| self.matrix.clone().map(Remix::Matrix)),
+            ..Edits::default()
+        }
+    }
+}
+
+impl EncoderArgs {
+    fn options(&self) -> EncodeOptions {
+        EncodeOptions {
+            bitrate: self.bitrate,
+            bits_per_sample: self.bits,
+            compression: self.compression,
+            quality: self.quality,
+            cbr: self.cbr,
+            strip_tags: self.strip_tags,
+            sample_rate: self.rate,
+            resampler: self.resampler,
+            dither: self.dither,
+            ..EncodeOptions::default()
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("report").required(true).multiple(true)))]
+struct AnalyzeArgs {
+    #[arg(value_name = "PATH")]
+    path: std::path::PathBuf,
+    /// Count clipped samples and runs of them per channel, with the longest runs
+    #[arg(long, group = "report")]
+    clipping: bool,
+    /// Measure the DC offset of each channel
+    #[arg(long, group = "report")]
+    dc: bool,
+    /// Measure the phase correlation between left and right, for mono compatibility
+    #[arg(long, group = "report")]
+    correlation: bool,
+    /// List the stretches that stay below this level, e.g. `-50dB`
+    #[arg(long, value_name = "DB", value_parser = parse_db, allow_hyphen_values = true, group = "report")]
+    silence: Option<f32>,
+    /// Shortest stretch listed as silence
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "1s", requires = "silence")]
+    min: std::time::Duration,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+struct SpectrumArgs {
+    #[arg(value_name = "PATH")]
+    path: std::path::PathBuf,
+    /// Where the region starts, e.g. `1:30`
+    #[arg(long, value_name = "TIME", value_parser = parse_duration, default_value = "0")]
+    at: std::time::Duration,
+    /// Average over this long instead of a single FFT block
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    duration: Option<std::time::Duration>,
+    /// Window function: `hann`, `hamming`, `blackman` or `rect`
+    #[arg(long, value_name = "WINDOW", default_value = "hann")]
+    window: Window,
+    /// FFT size in samples
+    #[arg(long, value_name = "SAMPLES", default_value = "4096", value_parser = clap::value_parser!(u32).range(16..=1 << 20))]
+    size: u32,
+    /// Number of peaks to list
+    #[arg(long, value_name = "N", default_value = "10")]
+    peaks: usize,
+    /// Write every bin to a CSV file as well
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<std::path::PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct SpectrogramArgs {
+    #[arg(value_name = "PATH")]
+    path: std::path::PathBuf,
+    #[arg(short, long, value_name = "OUTPUT")]
+    output: std::path::PathBuf,
+    /// FFT size in samples; the image is half this high
+    #[arg(long, value_name = "SAMPLES", default_value = "2048", value_parser = clap::value_parser!(u32).range(16..=65536))]
+    size: u32,
+    /// Samples between columns; smaller makes a wider image
+    #[arg(long, value_name = "SAMPLES", default_value = "512", value_parser = clap::value_parser!(u32).range(1..))]
+    hop: u32,
+    /// Window function: `hann`, `hamming`, `blackman` or `rect`
+    #[arg(long, value_name = "WINDOW", default_value = "hann")]
+    window: Window,
+    /// Colors: `magma`, `viridis` or `gray`
+    #[arg(long, value_name = "NAME", default_value = "magma")]
+    colormap: Colormap,
+    /// Level drawn as the quietest color, e.g. `-120dB`
+    #[arg(long, value_name = "DB", default_value = "-120", value_parser = parse_db, allow_hyphen_values = true)]
+    floor: f32,
+}
+
+#[derive(Args, Debug)]
+struct WaveformArgs {
+    #[arg(value_name = "PATH")]
+    path: std::path::PathBuf,
+    /// Image to write; `.svg` draws vector paths, anything else a PNG
+    #[arg(short, long, value_name = "OUTPUT")]
+    output: std::path::PathBuf,
+    /// Width in pixels, one min/max pair per column
+    #[arg(long, value_name = "PIXELS", default_value = "1800", value_parser = clap::value_parser!(u32).range(1..=65535))]
+    width: u32,
+    /// Height in pixels, shared between the channels
+    #[arg(long, value_name = "PIXELS", default_value = "280", value_parser = clap::value_parser!(u32).range(1..=65535))]
+    height: u32,
+    /// Waveform color as `#rrggbb`
+    #[arg(long, value_name = "COLOR", default_value = "#3b82f6", value_parser = parse_color)]
+    color: [u8; 3],
+    /// Background color as `#rrggbb`
+    #[arg(long, value_name = "COLOR", default_value = "#ffffff", value_parser = parse_color)]
+    background: [u8; 3],
+}
+
+#[derive(Args, Debug)]
+struct BpmArgs {
+    #[arg(value_name = "PATH")]
+    path: std::path::PathBuf,
+    /// Slowest tempo to consider
+    #[arg(long, value_name = "BPM", default_value = "60")]
+    min: f32,
+    /// Fastest tempo to consider
+    #[arg(long, value_name = "BPM", default_value = "200")]
+    max: f32,
+    /// Store the rounded tempo in the file's BPM tag
+    #[arg(long)]
+    write: bool,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+struct KeyArgs {
+    #[arg(value_name = "PATH")]
+    path: std::path::PathBuf,
+    /// Store the key in the file's KEY tag, e.g. `Am`
+    #[arg(long)]
+    write: bool,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+struct FingerprintArgs {
+    #[arg(value_name = "PATH")]
+    path: std::path::PathBuf,
+    /// Seconds of audio to fingerprint from the start, 0 for all of it
+    #[arg(long, value_name = "SECONDS", default_value = "120")]
+    length: u64,
+    /// Print the subfingerprints as integers instead of the compressed form
+    #[arg(long)]
+    raw: bool,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+struct IdentifyArgs {
+    #[arg(value_name = "PATH")]
+    path: std::path::PathBuf,
+    /// AcoustID application key; read from ACOUSTID_API_KEY when not given
+    #[arg(long, value_name = "KEY")]
+    api_key: Option<String>,
+    /// Write the matched title, artist, album and MusicBrainz ids to the file's tags
+    #[arg(long)]
+    apply: bool,
+    /// Candidate to apply, numbered as listed
+    #[arg(long, value_name = "N", default_value = "1", requires = "apply")]
+    pick: usize,
+}
+
+#[derive(Args, Debug)]
+struct PeaksArgs {
+    #[arg(value_name = "PATH")]
+    path: std::path::PathBuf,
+    /// Peak file to write; `.json` for JSON, anything else for binary `.dat`
+    #[arg(short, long, value_name = "OUTPUT")]
+    output: std::path::PathBuf,
+    /// Source frames per min/max pair
+    #[arg(long, value_name = "FRAMES", default_value = "256", value_parser = clap::value_parser!(u32).range(1..))]
+    samples_per_pixel: u32,
+    /// Resolution of the values, 8 or 16 bits
+    #[arg(long, value_name = "BITS", default_value = "8", value_parser = parse_peak_bits)]
+    bits: PeakBits,
+    /// Keep the channels apart instead of averaging them to mono
+    #[arg(long)]
+    split_channels: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum GainAction {
+    /// Compute ReplayGain 2.0 track and album gain and peak, taking the files as one album
+    Scan(GainScanArgs),
+}
+
+#[derive(Args, Debug)]
+struct GainScanArgs {
+    /// Files, directories and glob patterns such as "album/*.flac"
+    #[arg(value_name = "PATH", required = true)]
+    paths: Vec<String>,
+    /// Number of files to measure at once; defaults to one per CPU core
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+    /// Store the values in each file's REPLAYGAIN_* tags (FLAC, Ogg and MP3)
+    #[arg(long)]
+    write: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum QueueAction {
+    /// Save the queue, including the current track, to a playlist file
+    Save {
+        #[arg(value_name = "PATH")]
+        path: std::path::PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ArtAction {
+    /// Embed an image as the front cover of a FLAC, Ogg, MP3 or MP4 file, replacing the old one
+    Set(ArtSetArgs),
+}
+
+#[derive(Args, Debug)]
+struct ArtSetArgs {
+    #[arg(value_name = "FILE")]
+    path: std::path::PathBuf,
+    /// PNG, JPEG or GIF file to embed
+    #[arg(value_name = "IMAGE")]
+    image: std::path::PathBuf,
+    /// Scale a larger image down to fit within PIXELS on each side, re-encoding it as JPEG
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+    max_size: Option<u32>,
+    /// Re-encode the image as JPEG at this quality, 1 to 100 [default: 90 when resizing]
+    #[arg(long, value_name = "QUALITY", value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+}
+
+#[derive(Subcommand, Debug)]
+enum BookmarkAction {
+    /// Save a position in a file under a name, replacing any bookmark of the same name
+    Add {
+        #[arg(value_name = "FILE")]
+        path: std::path::PathBuf,
+        /// Position such as `1:23:45` or `90s`
+        #[arg(value_name = "TIME", value_parser = parse_duration)]
+        position: std::time::Duration,
+        /// Defaults to the position
+        #[arg(value_name = "NAME")]
+        name: Option<String>,
+    },
+    /// List the bookmarks of a file
+    List {
+        #[arg(value_name = "FILE")]
+        path: std::path::PathBuf,
+    },
+    /// Play a file from one of its bookmarks, like `play --bookmark`
+    Jump {
+        #[arg(value_name = "FILE")]
+        path: std::path::PathBuf,
+        /// Name or number of the bookmark
+        #[arg(value_name = "NAME")]
+        bookmark: String,
+    },
+    /// Delete a bookmark
+    Remove {
+        #[arg(value_name = "FILE")]
+        path: std::path::PathBuf,
+        #[arg(value_name = "NAME")]
+        bookmark: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum PodcastAction {
+    /// Subscribe to a podcast by the URL of its RSS or Atom feed
+    Add {
+        #[arg(value_name = "URL")]
+        url: String,
+    },
+    /// List the podcasts subscribed to, or the episodes of one, newest first
+    List {
+        /// Name or number of the podcast
+        #[arg(value_name = "PODCAST")]
+        podcast: Option<String>,
+    },
+    /// Fetch the feeds of the podcasts, or of one, for new episodes
+    Update {
+        #[arg(value_name = "PODCAST")]
+        podcast: Option<String>,
+    },
+    /// Play an episode from where it was left off, streaming it unless it was downloaded
+    Play {
+        #[arg(value_name = "PODCAST")]
+        podcast: String,
+        /// Title or number of the episode; the newest one not listened to yet by default
+        #[arg(value_name = "EPISODE")]
+        episode: Option<String>,
+        /// Download the episode first, keeping it for next time
+        #[arg(long)]
+        download: bool,
     },
 }
 
 fn main() {
     let args: Cli = Cli::parse();
-    print_intro(&args);
+    let output = output_args(&args.command);
+    let format = match args.command {
+        Commands::Loudness { json: true, .. } => OutputFormat::Json,
+        _ => output
+            .and_then(|output| output.format)
+            .or(args.format)
+            .unwrap_or(OutputFormat::Text),
+    };
+    if format == OutputFormat::Json && output.is_none() {
+        eprintln!("--format json is not supported by this command");
+        return;
+    }
+    print_intro(&args.command, format);
 
     match args.command {
-        Commands::Info { path } => handle_info(path),
+        Commands::Info {
+            path,
+            waveform,
+            art,
+            ..
+        } => handle_info(path, waveform, art, format),
+        Commands::Play(play_args) => {
+            handle_play(play_args);
+        }
+        Commands::Devices => handle_devices(),
+        Commands::Convert(convert_args) => handle_convert(convert_args),
+        Commands::Decode(decode_args) => handle_decode(decode_args),
+        Commands::Trim(trim_args) => handle_trim(trim_args),
+        Commands::Join(join_args) => handle_join(join_args),
+        Commands::Split(split_args) => handle_split(split_args),
+        Commands::Mix(mix_args) => handle_mix(mix_args),
+        Commands::Loudness { path, .. } => handle_loudness(path, format),
+        Commands::Analyze(analyze_args) => handle_analyze(analyze_args, format),
+        Commands::Stats { path, .. } => handle_stats(path, format),
+        Commands::Spectrum(spectrum_args) => handle_spectrum(spectrum_args),
+        Commands::Spectrogram(spectrogram_args) => handle_spectrogram(spectrogram_args),
+        Commands::Waveform(waveform_args) => handle_waveform(waveform_args),
+        Commands::Peaks(peaks_args) => handle_peaks(peaks_args),
+        Commands::Bpm(bpm_args) => handle_bpm(bpm_args, format),
+        Commands::Key(key_args) => handle_key(key_args, format),
+        Commands::Fingerprint(fingerprint_args) => handle_fingerprint(fingerprint_args, format),
+        Commands::Identify(identify_args) => handle_identify(identify_args),
+        Commands::Dr { paths, .. } => handle_dr(paths, format),
+        Commands::Gain { action } => match action {
+            GainAction::Scan(scan_args) => handle_gain_scan(scan_args),
+        },
+        Commands::Queue { action } => match action {
+            QueueAction::Save { path } => handle_queue_save(path),
+        },
+        Commands::Art { action } => match action {
+            ArtAction::Set(set_args) => handle_art_set(set_args),
+        },
+        Commands::Bookmark { action } => match action {
+            BookmarkAction::Add {
+                path,
+                position,
+                name,
+            } => handle_bookmark_add(path, position, name),
+            BookmarkAction::List { path } => handle_bookmark_list(path),
+            BookmarkAction::Jump { path, bookmark } => handle_bookmark_jump(path, bookmark),
+            BookmarkAction::Remove { path, bookmark } => handle_bookmark_remove(path, bookmark),
+        },
+        Commands::Podcast { action } => match action {
+            PodcastAction::Add { url } => handle_podcast_add(url),
+            PodcastAction::List { podcast } => handle_podcast_list(podcast),
+            PodcastAction::Update { podcast } => handle_podcast_update(podcast),
+            PodcastAction::Play {
+                podcast,
+                episode,
+                download,
+            } => handle_podcast_play(podcast, episode, download),
+        },
+        Commands::History(history_args) => handle_history(history_args, format),
+        Commands::Alarm(alarm_args) => handle_alarm(alarm_args),
+        Commands::Tui(tui_args) => handle_tui(tui_args),
+        Commands::Config { .. } => handle_config(format),
+        Commands::Completions { shell } => handle_completions(shell),
+        Commands::Daemon(daemon_args) => handle_daemon(daemon_args),
+        Commands::Ctl(ctl_args) => handle_ctl(ctl_args, format),
     }
 }
 
 // Command Handlers
-fn handle_info(path: std::path::PathBuf) {
-    print_read_file(&path);
+/// Rows of the terminal waveform preview
+const TEXT_WAVEFORM_ROWS: usize = 8;
 
-    match AudioFile::open(&path) {
-        Ok(audio_file) => {
-            println!("Sample Rate: {} Hz", audio_file.sample_rate);
-            println!("Channels: {}", audio_file.channels);
-            println!("Track ID: {}", audio_file.track_id);
-        }
+fn handle_info(
+    path: std::path::PathBuf,
+    waveform: bool,
+    art: Option<Graphics>,
+    format: OutputFormat,
+) {
+    if format == OutputFormat::Text {
+        print_read_file(&path);
+    }
+
+    let audio_file = match AudioFile::open(&path) {
+        Ok(audio_file) => audio_file,
         Err(e) => {
             eprintln!("Error opening audio file: {}", e);
+            return;
+        }
+    };
+    if format == OutputFormat::Json {
+        println!("{}", info_json(&path, &audio_file));
+        return;
+    }
+    if let Some(codec) = audio_file.codec() {
+        println!("Codec: {} ({})", codec.short_name, codec.long_name);
+    }
+    if let Some(duration) = audio_file.duration() {
+        println!("Duration: {}", format_time(duration));
+    }
+    println!("Sample Rate: {} Hz", audio_file.sample_rate);
+    println!("Channels: {}", audio_file.channels);
+    if let Some(bits) = audio_file.bits_per_sample {
+        println!("Bit Depth: {} bit", bits);
+    }
+    if let Some(bitrate) = audio_file.average_bitrate() {
+        println!(
+            "Bitrate: {} kbps average",
+            (bitrate as f64 / 1000.0).round()
+        );
+    }
+    println!("Track ID: {}", audio_file.track_id);
+    if audio_file.audio_tracks.len() > 1 {
+        println!("Audio Tracks:");
+        for track in &audio_file.audio_tracks {
+            let marker = if track.id == audio_file.track_id {
+                "*"
+            } else {
+                " "
+            };
+            let codec = track
+                .codec
+                .map_or("unsupported codec", |codec| codec.short_name);
+            let channels = track
+                .channels
+                .map(|channels| format!(", {} channels", channels))
+                .unwrap_or_default();
+            let language = track
+                .language
+                .as_ref()
+                .map(|language| format!(", {}", language))
+                .unwrap_or_default();
+            println!(
+                "{} {:>2}. {}, {} Hz{}{}",
+                marker, track.id, codec, track.sample_rate, channels, language
+            );
+        }
+    }
+
+    if !audio_file.tags.is_empty() {
+        println!("Tags:");
+        for tag in &audio_file.tags {
+            let value = match &tag.value {
+                Value::Binary(data) => format!("<{} bytes>", data.len()),
+                value => {
+                    // Long values such as embedded cue sheets span many lines
+                    let value = value.to_string();
+                    let mut lines = value.trim_end_matches('\0').lines();
+                    let first = lines.next().unwrap_or_default().to_string();
+                    match lines.next() {
+                        Some(_) => format!("{} [...]", first),
+                        None => first,
+                    }
+                }
+            };
+            println!("  {}: {}", tag.key, value);
+        }
+    }
+
+    if audio_file.visuals.is_empty() {
+        println!("Cover Art: none");
+    } else {
+        println!("Cover Art:");
+        for visual in &audio_file.visuals {
+            let usage = visual
+                .usage
+                .map(|usage| format!("{:?}", usage))
+                .unwrap_or_else(|| "Picture".to_string());
+            let size = visual
+                .dimensions
+                .map(|size| format!(", {}x{}", size.width, size.height))
+                .unwrap_or_default();
+            println!(
+                "  {}: {}{}, {:.1} KiB",
+                usage,
+                visual.media_type,
+                size,
+                visual.data.len() as f64 / 1024.0
+            );
+        }
+    }
+
+    if !audio_file.chapters.is_empty() {
+        println!("Chapters:");
+        for (i, chapter) in audio_file.chapters.iter().enumerate() {
+            match &chapter.title {
+                Some(title) => {
+                    println!("  {:>2}. {}  {}", i + 1, format_time(chapter.start), title)
+                }
+                None => println!("  {:>2}. {}", i + 1, format_time(chapter.start)),
+            }
+        }
+    }
+
+    if let Some(graphics) = art {
+        println!();
+        print_cover_art(&audio_file, graphics);
+    }
+
+    if waveform {
+        let columns = terminal_columns();
+        match text_waveform_peaks(&path, columns) {
+            Ok(peaks) => print!("\n{}", waveform_text(&peaks, columns, TEXT_WAVEFORM_ROWS)),
+            Err(e) => eprintln!("Error drawing waveform: {}", e),
         }
     }
 }
 
-// Display Utils
-fn print_intro(args: &Cli) {
-    println!("==================");
-    println!("<<< MogBox CLI >>>");
-    println!("==================\n");
+/// Draws the front cover of `file`, if it has any pictures
+fn print_cover_art(file: &AudioFile, graphics: Graphics) {
+    let Some(cover) = file.cover() else {
+        return;
+    };
+    match graphics.render(&cover.data, ART_COLUMNS.min(terminal_columns())) {
+        Ok(art) => print!("{}", art),
+        Err(e) => eprintln!("Error drawing cover art: {}", e),
+    }
+}
 
-    println!("Command: {:?}", args.command);
+/// Width of the terminal as the shell reports it in `COLUMNS`, or 80
+fn terminal_columns() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.trim().parse().ok())
+        .filter(|columns| *columns > 0)
+        .unwrap_or(80)
 }
 
-fn print_read_file(path: &std::path::PathBuf) {
-    println!("Reading File: {:?}", path)
+/// Prints a message about playback: to stdout, or to stderr when stdout carries
+/// `play --events`
+macro_rules! say {
+    ($events:expr, $($arg:tt)*) => {
+        if $events {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// Returns whether the queue played to the end, rather than being quit or
+/// failing to start
+fn handle_play(mut args: PlayArgs) -> bool {
+    args.apply_config(&load_config());
+    // Events take stdout, so everything else is printed to stderr
+    let stdout_events = args.events.is_some();
+    let mut queue = load_queue(args.paths.clone());
+    if queue.is_empty() {
+        eprintln!("Nothing to play");
+        return false;
+    }
+
+    queue.set_repeat(args.repeat);
+    queue.set_shuffle(args.shuffle);
+    let start = match queue.current_index() {
+        Some(index) => index,
+        None => queue.first().unwrap_or(0),
+    };
+
+    // Ask for the first track's rate so it can play without resampling
+    let first = queue.get(start).and_then(|path| match args.raw {
+        Some(format) => AudioFile::open_raw(path, format).ok(),
+        None => AudioFile::open(path).ok(),
+    });
+    let sample_rate = first.as_ref().map(|file| file.sample_rate);
+
+    let offset = match args.chapter {
+        Some(number) => {
+            let chapters = first.as_ref().map_or(&[][..], |file| &file.chapters[..]);
+            match chapters.get(number as usize - 1) {
+                Some(chapter) => chapter.start,
+                None => {
+                    eprintln!(
+                        "No chapter {}: the track has {} chapters",
+                        number,
+                        chapters.len()
+                    );
+                    return false;
+                }
+            }
+        }
+        None => args.start.unwrap_or_default(),
+    };
+    let length = first.as_ref().and_then(|file| file.duration());
+    if let (Some(start), Some(length)) = (args.start, length) {
+        if start >= length {
+            eprintln!(
+                "Can't start at {}: the track is {} long",
+                format_time(start),
+                format_time(length)
+            );
+            return false;
+        }
+    }
+    let offset = match (&args.bookmark, queue.get(start)) {
+        (Some(name), Some(path)) => match find_bookmark(path, name) {
+            Ok(bookmark) => {
+                say!(
+                    stdout_events,
+                    "Starting at bookmark \"{}\" ({})",
+                    bookmark.name,
+                    format_time(bookmark.position)
+                );
+                bookmark.position
+            }
+            Err(e) => {
+                eprintln!("Error finding bookmark: {}", e);
+                return false;
+            }
+        },
+        _ => offset,
+    };
+    let offset = args.ab_loop.map_or(offset, |(loop_start, _)| loop_start);
+    let looping = match (args.looping, args.ab_loop, queue.get(start)) {
+        (true, _, Some(path)) => Some(track_loop(&args, path, first.as_ref())),
+        (_, Some((loop_start, loop_end)), _) => Some((loop_start, loop_end)),
+        _ => None,
+    };
+    if let Some((loop_start, loop_end)) = looping.filter(|(start, end)| end <= start) {
+        eprintln!(
+            "Invalid loop: {} doesn't end after it starts at {}",
+            format_time(loop_end),
+            format_time(loop_start)
+        );
+        return false;
+    }
+    let mut resume = args.resume.then(ResumeTracker::open).flatten();
+    let mut history = (!args.no_history).then(HistoryTracker::open).flatten();
+    let offset = match (&resume, queue.get(start)) {
+        (Some(resume), Some(path)) => match resume.position(path) {
+            Some(position) => {
+                say!(stdout_events, "Resuming at {}", format_time(position));
+                position
+            }
+            None => offset,
+        },
+        _ => offset,
+    };
+
+    if let Some(output) = args.output.as_ref() {
+        let channels = match args.channels.as_ref() {
+            Some(routing) => routing.len(),
+            None => first
+                .as_ref()
+                .map(|file| file.channels as usize)
+                .unwrap_or(2),
+        };
+        render_queue(
+            queue,
+            (start, offset),
+            output,
+            sample_rate.unwrap_or(44100),
+            channels,
+            &args,
+            first,
+        );
+        return false;
+    }
+
+    let config = PlayerConfig {
+        host: args.host,
+        device: args.device.clone(),
+        sample_rate,
+        buffer_frames: match args.buffer {
+            Some(BufferSize::Frames(frames)) => Some(frames),
+            _ => None,
+        },
+        latency_ms: match args.buffer {
+            Some(BufferSize::Latency(latency)) => Some(latency.as_millis().max(1) as u32),
+            _ => None,
+        },
+        bit_perfect: args.bit_perfect,
+    };
+
+    let mut player = match AudioPlayer::with_config(&config) {
+        Ok(player) => player,
+        Err(e) => {
+            eprintln!("Error opening output device: {}", e);
+            return false;
+        }
+    };
+
+    if args.bit_perfect {
+        let mixer = player.mixer();
+        let bits = first.as_ref().and_then(|file| file.bits_per_sample);
+        if mixer.is_bit_perfect_for(bits) {
+            say!(
+                stdout_events,
+                "Bit-perfect output at {} Hz",
+                mixer.sample_rate()
+            );
+        } else if mixer.is_bit_perfect() {
+            say!(
+                stdout_events,
+                "Bit-perfect output not supported by the device's {} samples, playing at {} Hz",
+                format!("{:?}", mixer.sample_format()).to_lowercase(),
+                mixer.sample_rate()
+            );
+        } else {
+            say!(
+                stdout_events,
+                "Bit-perfect output not supported by the device, playing at {} Hz",
+                mixer.sample_rate()
+            );
+        }
+    }
+
+    if let Some(crossfade) = args.crossfade {
+        player.set_crossfade(crossfade);
+    }
+    player.set_channel_routing(args.channels.clone());
+    player.set_replaygain(args.replaygain());
+    player.set_skip_silence(args.skip_silence());
+    player.set_fade_in(args.fade_in.unwrap_or_default());
+    player.set_raw_format(args.raw);
+    player.set_volume(args.volume.unwrap_or(1.0));
+    {
+        let chain = player.mixer().chain();
+        let mut chain = chain.lock().unwrap();
+        if args.dc_block {
+            chain.push(DcBlocker::default());
+        }
+        if let Some(ceiling) = args.limit {
+            chain.push(TruePeakLimiter::new(ceiling));
+        }
+    }
+    // Tapped last, so it shows exactly what reaches the device
+    let mut visualizer = args.visualize.map(|mode| {
+        let visualizer = Visualizer::new(mode, player.mixer().sample_rate());
+        player
+            .mixer()
+            .chain()
+            .lock()
+            .unwrap()
+            .push(visualizer.tap.clone());
+        visualizer
+    });
+
+    *player.queue().lock().unwrap() = queue;
+    if let Some((loop_start, loop_end)) = looping {
+        player.set_loop(Some(LoopRegion {
+            track: start,
+            start: loop_start,
+            end: loop_end,
+        }));
+        match loop_end {
+            std::time::Duration::MAX => {
+                say!(
+                    stdout_events,
+                    "Looping from {} to the end",
+                    format_time(loop_start)
+                )
+            }
+            _ => say!(
+                stdout_events,
+                "Looping {} to {}",
+                format_time(loop_start),
+                format_time(loop_end)
+            ),
+        }
+    }
+    // Handed over rather than opened again, which stdin can't be
+    player.play_opened_at(start, offset, first);
+    let keyboard = Keyboard::open();
+    let progress = ProgressBar::open().filter(|_| !stdout_events);
+    let mut events = args.events.map(|_| EventStream::default());
+    if keyboard.is_some() {
+        say!(
+            stdout_events,
+            "Keys: space pause, \u{2190}/\u{2192} seek, \u{2191}/\u{2193} volume, n/p next/previous, \
+             [/] chapters, a/b/c A-B loop, m bookmark, 1-9 go to bookmark, q quit"
+        );
+    }
+    if progress.is_none() {
+        match keyboard {
+            Some(_) => say!(stdout_events, "Playing... Press q to stop"),
+            None => say!(stdout_events, "Playing... Press Ctrl+C to stop"),
+        }
+    }
+    if let Some(sleep) = args.sleep {
+        player.set_sleep_timer(sleep, SLEEP_FADE);
+        say!(stdout_events, "Stopping in {}", format_time(sleep));
+    }
+    if let Some(duration) = args.duration {
+        player.set_sleep_timer(duration, WINDOW_FADE);
+    }
+
+    let mut current = None;
+    // Length and average bitrate of the current track
+    let mut length = None;
+    let mut average_bitrate = None;
+    // Chapters and lyrics of the current track and the ones last printed
+    let mut chapters = Vec::new();
+    let mut chapter = None;
+    let mut lyrics: Option<Lyrics> = None;
+    let mut lyric_line = None;
+    // What the current radio stream last said it's playing
+    let mut stream_title = None;
+    let mut underruns = 0;
+    let mut last_report = std::time::Instant::now();
+    let mut quit = false;
+    while player.is_playing() {
+        // Messages are printed in place of the progress bar, which is drawn
+        // again below them
+        if let Some(progress) = &progress {
+            progress.clear();
+        }
+        report_player_errors(&player, stdout_events);
+        while let Some(key) = keyboard.as_ref().and_then(Keyboard::poll) {
+            if key == Key::Char('q') || key == Key::Char('\u{3}') {
+                quit = true;
+                break;
+            }
+            let (track, position) = (player.current_track(), player.position());
+            if let Some(message) = handle_play_key(&mut player, key) {
+                say!(stdout_events, "{}", message);
+                if let Some(visualizer) = &mut visualizer {
+                    visualizer.detach();
+                }
+            }
+            // Skipping a track partway doesn't count as playing it to the end
+            let skipped = key == Key::Char('n') || key == Key::Char('p');
+            if skipped && player.current_track() != track {
+                if let Some(resume) = &mut resume {
+                    resume.stop_track(position);
+                }
+                if let Some(history) = &mut history {
+                    history.stop_track(position);
+                }
+                if let Some(events) = &mut events {
+                    events.stop_track(position);
+                }
+            }
+        }
+        if quit {
+            break;
+        }
+        match player.poll_device() {
+            Some(DeviceEvent::Lost) => {
+                say!(
+                    stdout_events,
+                    "Output device lost, waiting for it to come back..."
+                );
+                if let Some(visualizer) = &mut visualizer {
+                    visualizer.detach();
+                }
+            }
+            Some(DeviceEvent::Recovered { device }) => {
+                say!(stdout_events, "Output resumed on {}", device);
+                if let Some(visualizer) = &mut visualizer {
+                    visualizer.detach();
+                }
+            }
+            None => {}
+        }
+        if last_report.elapsed() >= STATS_INTERVAL {
+            last_report = std::time::Instant::now();
+            let stats = player.stats();
+            if stats.underruns != underruns {
+                underruns = stats.underruns;
+                if !stdout_events {
+                    print_stats(&stats);
+                }
+                if let Some(visualizer) = &mut visualizer {
+                    visualizer.detach();
+                }
+            }
+        }
+        // Seeking restarts playback, during which there is no current track
+        if player.current_track().is_some() && player.current_track() != current {
+            current = player.current_track();
+            if let Some(path) = player.current_path() {
+                if !stdout_events {
+                    print_read_file(&path);
+                }
+                if let Some(resume) = &mut resume {
+                    resume.start_track(&path);
+                }
+                let file = reopen(&path);
+                length = file.as_ref().and_then(AudioFile::duration);
+                average_bitrate = file.as_ref().and_then(AudioFile::average_bitrate);
+                if let Some(history) = &mut history {
+                    history.start_track(&path, file.as_ref());
+                }
+                if let (Some(events), Some(track)) = (&mut events, current) {
+                    events.start_track(track, &path, file.as_ref());
+                }
+                chapters = file
+                    .as_ref()
+                    .map_or(Vec::new(), |file| file.chapters.clone());
+                chapter = None;
+                stream_title = None;
+                let station = file.as_ref().and_then(|file| file.stream.as_ref()?.name());
+                if let Some(station) = station {
+                    say!(stdout_events, "Station: {}", station);
+                }
+                if let (Some(graphics), Some(file)) = (args.art, &file) {
+                    print_cover_art(file, graphics);
+                }
+                if args.lyrics {
+                    let tags = file.as_ref().map_or(&[][..], |file| &file.tags[..]);
+                    lyrics = Lyrics::read(&path, tags);
+                    lyric_line = None;
+                    if lyrics.is_none() {
+                        say!(stdout_events, "No synced lyrics found");
+                    }
+                }
+                if let Some(visualizer) = &mut visualizer {
+                    visualizer.detach();
+                }
+            }
+            save_session_queue(&player.queue().lock().unwrap());
+        }
+        let position = player.position();
+        if let Some(resume) = &mut resume {
+            resume.update(position);
+        }
+        if let Some(history) = &mut history {
+            history.update(position);
+        }
+        if let Some(events) = &mut events {
+            events.update(position, player.is_paused());
+        }
+        let playing = chapters
+            .iter()
+            .rposition(|chapter: &Chapter| chapter.start <= position);
+        if playing != chapter {
+            chapter = playing;
+            if let Some(index) = playing {
+                match &chapters[index].title {
+                    Some(title) => say!(
+                        stdout_events,
+                        "Chapter {}/{}: {}",
+                        index + 1,
+                        chapters.len(),
+                        title
+                    ),
+                    None => say!(stdout_events, "Chapter {}/{}", index + 1, chapters.len()),
+                }
+                if let Some(visualizer) = &mut visualizer {
+                    visualizer.detach();
+                }
+            }
+        }
+        if let Some(lyrics) = &lyrics {
+            let line = lyrics.line_at(position);
+            if line != lyric_line {
+                lyric_line = line;
+                if let Some(line) = line {
+                    say!(stdout_events, "  {}", lyrics.lines[line].text);
+                    if let Some(visualizer) = &mut visualizer {
+                        visualizer.detach();
+                    }
+                }
+            }
+        }
+        let title = player.stream_title();
+        if title.is_some() && title != stream_title {
+            stream_title = title;
+            if let (Some(title), Some(track)) = (&stream_title, current) {
+                say!(stdout_events, "Now playing: {}", title);
+                if let Some(events) = &events {
+                    events.stream_title(track, title);
+                }
+                if let Some(visualizer) = &mut visualizer {
+                    visualizer.detach();
+                }
+            }
+        }
+        if let Some(visualizer) = &mut visualizer {
+            visualizer.draw();
+        }
+        if let Some(progress) = &progress {
+            let bitrate = player.bitrate().or(average_bitrate);
+            progress.draw(position, length, bitrate, player.is_paused());
+        }
+        match visualizer {
+            Some(_) => std::thread::sleep(VISUALIZER_INTERVAL),
+            None => std::thread::sleep(std::time::Duration::from_millis(100)),
+        }
+    }
+
+    if let Some(progress) = &progress {
+        progress.clear();
+    }
+    report_player_errors(&player, stdout_events);
+    if quit || player.slept() {
+        if player.slept() && args.sleep.is_some() {
+            say!(stdout_events, "Sleep timer ran out, playback stopped");
+        }
+        let position = player.position();
+        if let Some(resume) = &mut resume {
+            resume.stop_track(position);
+        }
+        if let Some(history) = &mut history {
+            history.stop_track(position);
+        }
+        if let Some(events) = &mut events {
+            events.stop_track(position);
+        }
+        return false;
+    }
+    if let Some(resume) = &mut resume {
+        resume.finish();
+    }
+    if let Some(history) = &mut history {
+        history.finish();
+    }
+    if let Some(events) = &mut events {
+        events.finish();
+    }
+    true
+}
+
+/// The track an [`EventStream`] reported starting
+struct EventTrack {
+    index: usize,
+    path: std::path::PathBuf,
+    duration: Option<std::time::Duration>,
+}
+
+/// Reports playback for `play --events ndjson`, one JSON object per line on
+/// stdout
+#[derive(Default)]
+struct EventStream {
+    current: Option<EventTrack>,
+    last_progress: Option<std::time::Instant>,
+}
+
+impl EventStream {
+    fn emit<'a>(event: &'a str, members: impl IntoIterator<Item = (&'a str, Json)>) {
+        let members = std::iter::once(("event", Json::from(event))).chain(members);
+        println!("{}", Json::object(members));
+    }
+
+    /// Moves on to track `index` at `path`; the one before was played to the end
+    fn start_track(&mut self, index: usize, path: &std::path::Path, file: Option<&AudioFile>) {
+        self.finish();
+        let tag = |key| file.and_then(|file| file.tag(key));
+        let duration = file.and_then(AudioFile::duration);
+        EventStream::emit(
+            "track-start",
+            [
+                ("index", Json::from(index)),
+                ("path", Json::path(path)),
+                ("title", Json::from(tag(StandardTagKey::TrackTitle))),
+                ("artist", Json::from(tag(StandardTagKey::Artist))),
+                ("album", Json::from(tag(StandardTagKey::Album))),
+                ("duration", duration.map_or(Json::Null, Json::seconds)),
+            ],
+        );
+        self.current = Some(EventTrack {
+            index,
+            path: path.to_path_buf(),
+            duration,
+        });
+        self.last_progress = Some(std::time::Instant::now());
+    }
+
+    /// Reports `position` in the current track now and then
+    fn update(&mut self, position: std::time::Duration, paused: bool) {
+        let Some(track) = &self.current else {
+            return;
+        };
+        if self
+            .last_progress
+            .is_some_and(|last| last.elapsed() < PROGRESS_EVENT_INTERVAL)
+        {
+            return;
+        }
+        self.last_progress = Some(std::time::Instant::now());
+        EventStream::emit(
+            "progress",
+            [
+                ("index", Json::from(track.index)),
+                ("position", Json::seconds(position)),
+                ("duration", track.duration.map_or(Json::Null, Json::seconds)),
+                ("paused", Json::from(paused)),
+            ],
+        );
+    }
+
+    /// Reports the current track ended at `position`, from `completed` playing
+    /// to its end or being stopped partway
+    fn end_track(&mut self, position: Option<std::time::Duration>, completed: bool) {
+        let Some(track) = self.current.take() else {
+            return;
+        };
+        let position = position.or(track.duration);
+        EventStream::emit(
+            "track-end",
+            [
+                ("index", Json::from(track.index)),
+                ("path", Json::path(&track.path)),
+                ("position", position.map_or(Json::Null, Json::seconds)),
+                ("completed", Json::from(completed)),
+            ],
+        );
+    }
+
+    /// Reports what radio stream `index` says it's now playing
+    fn stream_title(&self, index: usize, title: &str) {
+        EventStream::emit(
+            "stream-title",
+            [("index", Json::from(index)), ("title", Json::from(title))],
+        );
+    }
+
+    /// Reports the current track was stopped at `position`
+    fn stop_track(&mut self, position: std::time::Duration) {
+        self.end_track(Some(position), false);
+    }
+
+    fn finish(&mut self) {
+        self.end_track(None, true);
+    }
+
+    fn error(path: &std::path::Path, message: &str) {
+        EventStream::emit(
+            "error",
+            [("path", Json::path(path)), ("message", Json::from(message))],
+        );
+    }
+}
+
+/// Reports the player's errors as `error` events when stdout carries them, or
+/// else prints them
+fn report_player_errors(player: &AudioPlayer, stdout_events: bool) {
+    if !stdout_events {
+        print_player_errors(player);
+        return;
+    }
+    for (path, e) in player.take_errors() {
+        EventStream::error(&path, &e);
+    }
+}
+
+fn bookmarks_path() -> Option<std::path::PathBuf> {
+    Some(state_dir()?.join("mogbox").join("bookmarks.tsv"))
+}
+
+/// The bookmark store and the key of the file at `path` in it
+fn open_bookmarks(path: &std::path::Path) -> Result<(BookmarkStore, String), String> {
+    let key = file_key(path)?;
+    let store_path = bookmarks_path().ok_or("no state directory available")?;
+    Ok((BookmarkStore::load(&store_path)?, key))
+}
+
+/// A bookmark of the file at `path`, by name or number
+fn find_bookmark(path: &std::path::Path, name: &str) -> Result<Bookmark, String> {
+    let (store, key) = open_bookmarks(path)?;
+    store
+        .find(&key, name)
+        .ok_or_else(|| format!("no bookmark \"{}\" in {:?}", name, path))
+}
+
+fn handle_bookmark_add(
+    path: std::path::PathBuf,
+    position: std::time::Duration,
+    name: Option<String>,
+) {
+    let (mut store, key) = match open_bookmarks(&path) {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("Error adding bookmark: {}", e);
+            return;
+        }
+    };
+    let duration = reopen(&path).and_then(|file| file.duration());
+    if let Some(duration) = duration.filter(|duration| position > *duration) {
+        eprintln!(
+            "Error adding bookmark: {} is past the end of the track ({})",
+            format_time(position),
+            format_time(duration)
+        );
+        return;
+    }
+
+    let name = name.unwrap_or_else(|| format_time(position));
+    store.add(&key, &path, &name, position);
+    match store.save() {
+        Ok(()) => println!(
+            "Added bookmark \"{}\" at {} to {:?}",
+            name.trim(),
+            format_time(position),
+            path
+        ),
+        Err(e) => eprintln!("Error saving bookmarks: {}", e),
+    }
+}
+
+fn handle_bookmark_list(path: std::path::PathBuf) {
+    let (store, key) = match open_bookmarks(&path) {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("Error reading bookmarks: {}", e);
+            return;
+        }
+    };
+    let bookmarks = store.bookmarks(&key);
+    if bookmarks.is_empty() {
+        println!("No bookmarks in {:?}", path);
+        return;
+    }
+    println!("Bookmarks in {:?}:", path);
+    for (i, bookmark) in bookmarks.iter().enumerate() {
+        println!(
+            "  {:>2}. {}  {}",
+            i + 1,
+            format_time(bookmark.position),
+            bookmark.name
+        );
+    }
+}
+
+fn handle_bookmark_jump(path: std::path::PathBuf, bookmark: String) {
+    // The same as `play --bookmark NAME FILE`
+    let mut args = default_play_args(vec![path]);
+    args.bookmark = Some(bookmark);
+    handle_play(args);
+}
+
+fn handle_bookmark_remove(path: std::path::PathBuf, bookmark: String) {
+    let (mut store, key) = match open_bookmarks(&path) {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("Error removing bookmark: {}", e);
+            return;
+        }
+    };
+    // Numbers count in list order, so go by the name they stand for
+    let Some(found) = store.find(&key, &bookmark) else {
+        eprintln!(
+            "Error removing bookmark: no bookmark \"{}\" in {:?}",
+            bookmark, path
+        );
+        return;
+    };
+    store.remove(&key, &found.name);
+    match store.save() {
+        Ok(()) => println!("Removed bookmark \"{}\" from {:?}", found.name, path),
+        Err(e) => eprintln!("Error saving bookmarks: {}", e),
+    }
+}
+
+fn podcasts_path() -> Option<std::path::PathBuf> {
+    Some(state_dir()?.join("mogbox").join("podcasts.tsv"))
+}
+
+fn open_podcasts() -> Result<PodcastStore, String> {
+    PodcastStore::load(&podcasts_path().ok_or("no state directory available")?)
+}
+
+/// Where an episode of `podcast` is kept once downloaded, named after its
+/// date and title
+fn episode_download_path(podcast: &Podcast, episode: &Episode) -> Option<std::path::PathBuf> {
+    let date = episode
+        .published
+        .map(|published| format_timestamp(published)[..10].to_string() + " ");
+    let name = format!(
+        "{}{}.{}",
+        date.unwrap_or_default(),
+        file_name(&episode.title),
+        http::url_extension(&episode.url).unwrap_or("mp3")
+    );
+    Some(
+        data_dir()?
+            .join("mogbox")
+            .join("podcasts")
+            .join(file_name(&podcast.title))
+            .join(name),
+    )
+}
+
+/// `text` with the characters file systems don't take in names replaced
+fn file_name(text: &str) -> String {
+    let name: String = text
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(120)
+        .collect();
+    match name.trim().trim_start_matches('.') {
+        "" => "untitled".to_string(),
+        name => name.to_string(),
+    }
+}
+
+fn handle_podcast_add(url: String) {
+    let mut store = match open_podcasts() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Error reading podcasts: {}", e);
+            return;
+        }
+    };
+    let feed = match Feed::fetch(&url) {
+        Ok(feed) => feed,
+        Err(e) => {
+            eprintln!("Error reading feed: {}", e);
+            return;
+        }
+    };
+    let (title, count) = match store.subscribe(&url, &feed) {
+        Ok(podcast) => (podcast.title.clone(), podcast.episodes.len()),
+        Err(e) => {
+            eprintln!("Error subscribing: {}", e);
+            return;
+        }
+    };
+    match store.save() {
+        Ok(()) => println!("Subscribed to {} ({} episodes)", title, count),
+        Err(e) => eprintln!("Error saving podcasts: {}", e),
+    }
+}
+
+fn handle_podcast_list(podcast: Option<String>) {
+    let store = match open_podcasts() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Error reading podcasts: {}", e);
+            return;
+        }
+    };
+    let Some(name) = podcast else {
+        if store.podcasts().is_empty() {
+            println!("No podcasts subscribed to");
+            return;
+        }
+        println!("Podcasts:");
+        for (i, podcast) in store.podcasts().iter().enumerate() {
+            println!(
+                "  {:>2}. {}  ({} of {} not listened to)",
+                i + 1,
+                podcast.title,
+                podcast.unlistened(),
+                podcast.episodes.len()
+            );
+        }
+        return;
+    };
+    let Some(index) = store.find(&name) else {
+        eprintln!("No podcast \"{}\"", name);
+        return;
+    };
+    let podcast = &store.podcasts()[index];
+    if podcast.episodes.is_empty() {
+        println!("No episodes of {}", podcast.title);
+        return;
+    }
+    let resume = ResumeTracker::open();
+    println!("Episodes of {}:", podcast.title);
+    for (i, PodcastEpisode { episode, listened }) in podcast.episodes.iter().enumerate() {
+        let date = episode.published.map_or(" ".repeat(10), |published| {
+            format_timestamp(published)[..10].to_string()
+        });
+        let duration = episode
+            .duration
+            .map(|duration| format!("  ({})", format_clock(duration)));
+        let download = episode_download_path(podcast, episode).filter(|path| path.exists());
+        let position = resume.as_ref().and_then(|resume| {
+            let path = download
+                .clone()
+                .unwrap_or_else(|| std::path::PathBuf::from(&episode.url));
+            resume.position(&path)
+        });
+        let mut notes = Vec::new();
+        match (listened, position) {
+            (true, _) => notes.push("listened".to_string()),
+            (false, Some(position)) => notes.push(format!("left at {}", format_clock(position))),
+            (false, None) => {}
+        }
+        if download.is_some() {
+            notes.push("downloaded".to_string());
+        }
+        let notes = match notes.is_empty() {
+            true => String::new(),
+            false => format!("  [{}]", notes.join(", ")),
+        };
+        println!(
+            "  {:>3}. {}  {}{}{}",
+            i + 1,
+            date,
+            episode.title,
+            duration.unwrap_or_default(),
+            notes
+        );
+    }
+}
+
+fn handle_podcast_update(podcast: Option<String>) {
+    let mut store = match open_podcasts() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Error reading podcasts: {}", e);
+            return;
+        }
+    };
+    let indices = match &podcast {
+        Some(name) => match store.find(name) {
+            Some(index) => index..index + 1,
+            None => {
+                eprintln!("No podcast \"{}\"", name);
+                return;
+            }
+        },
+        None => 0..store.podcasts().len(),
+    };
+    if indices.is_empty() {
+        println!("No podcasts subscribed to");
+        return;
+    }
+    for index in indices {
+        let title = store.podcasts()[index].title.clone();
+        match Feed::fetch(&store.podcasts()[index].url) {
+            Ok(feed) => {
+                let new = store.update(index, &feed);
+                println!("{}: {} new episodes", store.podcasts()[index].title, new);
+            }
+            Err(e) => eprintln!("Error updating {}: {}", title, e),
+        }
+    }
+    if let Err(e) = store.save() {
+        eprintln!("Error saving podcasts: {}", e);
+    }
+}
+
+fn handle_podcast_play(podcast: String, episode: Option<String>, download: bool) {
+    let store = match open_podcasts() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Error reading podcasts: {}", e);
+            return;
+        }
+    };
+    let Some(index) = store.find(&podcast) else {
+        eprintln!("No podcast \"{}\"", podcast);
+        return;
+    };
+    let podcast = &store.podcasts()[index];
+    let found = match &episode {
+        Some(name) => podcast.find(name),
+        None => podcast.next_episode(),
+    };
+    let Some(found) = found else {
+        match episode {
+            Some(name) => eprintln!("No episode \"{}\" of {}", name, podcast.title),
+            None => eprintln!("No episodes of {} left to listen to", podcast.title),
+        }
+        return;
+    };
+    let episode = &found.episode;
+
+    let path = match episode_download_path(podcast, episode) {
+        Some(path) if path.exists() => path,
+        Some(path) if download => {
+            println!("Downloading {}", episode.title);
+            let downloaded = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .map_err(|e| format!("failed to create {}: {}", path.display(), e))
+                .and_then(|()| http::download(&episode.url, &path));
+            if let Err(e) = downloaded {
+                eprintln!("Error downloading episode: {}", e);
+                return;
+            }
+            path
+        }
+        None if download => {
+            eprintln!("Error downloading episode: no data directory available");
+            return;
+        }
+        _ => std::path::PathBuf::from(&episode.url),
+    };
+    println!("Playing {}: {}", podcast.title, episode.title);
+    let mut args = default_play_args(vec![path]);
+    args.resume = true;
+    if !handle_play(args) {
+        return;
+    }
+
+    // Played to the end; the store is read again, as it may have changed
+    // while playing
+    let (url, guid) = (podcast.url.clone(), episode.guid.clone());
+    let mut store = match open_podcasts() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Error reading podcasts: {}", e);
+            return;
+        }
+    };
+    if store.set_listened(&url, &guid, true) {
+        if let Err(e) = store.save() {
+            eprintln!("Error saving podcasts: {}", e);
+        }
+    }
+}
+
+/// How often the position is saved while playing with `--resume`
+const RESUME_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Keeps the resume store up to date with the track being played
+struct ResumeTracker {
+    store: ResumeStore,
+    /// Key and path of the track being played
+    current: Option<(String, std::path::PathBuf)>,
+    last_save: std::time::Instant,
+}
+
+impl ResumeTracker {
+    fn open() -> Option<Self> {
+        let Some(path) = state_dir().map(|dir| dir.join("mogbox").join("resume.tsv")) else {
+            eprintln!("Error loading resume positions: no state directory available");
+            return None;
+        };
+        match ResumeStore::load(&path) {
+            Ok(store) => Some(ResumeTracker {
+                store,
+                current: None,
+                last_save: std::time::Instant::now(),
+            }),
+            Err(e) => {
+                eprintln!("Error loading resume positions: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Where the file at `path` was left off
+    fn position(&self, path: &std::path::Path) -> Option<std::time::Duration> {
+        self.store.position(&file_key(path).ok()?)
+    }
+
+    /// Moves on to the track at `path`; the one before was played to the end
+    fn start_track(&mut self, path: &std::path::Path) {
+        self.finish();
+        self.current = file_key(path).ok().map(|key| (key, path.to_path_buf()));
+    }
+
+    /// Saves `position` in the current track now and then
+    fn update(&mut self, position: std::time::Duration) {
+        if self.last_save.elapsed() < RESUME_INTERVAL {
+            return;
+        }
+        self.last_save = std::time::Instant::now();
+        self.save_position(position);
+    }
+
+    /// Saves `position` in the current track right away
+    fn save_position(&mut self, position: std::time::Duration) {
+        if let Some((key, path)) = &self.current {
+            self.store.set_position(key, path, position);
+            self.save();
+        }
+    }
+
+    /// Saves `position` in the current track, which was left there, and
+    /// moves on from it
+    fn stop_track(&mut self, position: std::time::Duration) {
+        self.save_position(position);
+        self.current = None;
+    }
+
+    /// Forgets the current track, which was played to the end
+    fn finish(&mut self) {
+        if let Some((key, _)) = self.current.take() {
+            self.store.remove(&key);
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.store.save() {
+            eprintln!("Error saving resume positions: {}", e);
+        }
+    }
+}
+
+/// Options of `play` for `paths`, with everything else at its default
+fn default_play_args(paths: Vec<std::path::PathBuf>) -> PlayArgs {
+    let command = PlayArgs::augment_args(clap::Command::new("play"));
+    let args = std::iter::once("play".into()).chain(paths.into_iter().map(Into::into));
+    let matches = command.get_matches_from::<_, std::ffi::OsString>(args);
+    PlayArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+}
+
+/// How often the alarm checks the clock while waiting, so it stays on time
+/// after the system was suspended
+const ALARM_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+fn handle_alarm(args: AlarmArgs) {
+    let now = std::time::SystemTime::now();
+    let start = match (args.at, args.after) {
+        (Some((hour, minute, second)), _) => next_local_time(hour, minute, second, now),
+        (None, Some(after)) => now + after,
+        (None, None) => now,
+    };
+    let wait = start.duration_since(now).unwrap_or_default();
+    match args.at {
+        Some((hour, minute, second)) => println!(
+            "Alarm set for {:02}:{:02}:{:02}, in {}",
+            hour,
+            minute,
+            second,
+            format_time(wait)
+        ),
+        None => println!("Alarm set for {} from now", format_time(wait)),
+    }
+    while let Ok(left) = start.duration_since(std::time::SystemTime::now()) {
+        if left.is_zero() {
+            break;
+        }
+        std::thread::sleep(left.min(ALARM_CHECK_INTERVAL));
+    }
+
+    let mut play = default_play_args(args.files);
+    play.fade_in = Some(args.fade_in);
+    play.repeat = args.repeat;
+    play.device = args.device;
+    play.host = args.host;
+    handle_play(play);
+}
+
+fn history_path() -> Option<std::path::PathBuf> {
+    Some(state_dir()?.join("mogbox").join("history.tsv"))
+}
+
+/// How often the position in the history is brought up to date
+const HISTORY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Records the tracks played in the history
+struct HistoryTracker {
+    log: HistoryLog,
+    last_save: std::time::Instant,
+}
+
+impl HistoryTracker {
+    fn open() -> Option<Self> {
+        let Some(path) = history_path() else {
+            eprintln!("Error recording history: no state directory available");
+            return None;
+        };
+        Some(HistoryTracker {
+            log: HistoryLog::new(&path),
+            last_save: std::time::Instant::now(),
+        })
+    }
+
+    /// Moves on to the track at `path`; the one before was played to the end
+    fn start_track(&mut self, path: &std::path::Path, file: Option<&AudioFile>) {
+        self.finish();
+        let tag = |key| file.and_then(|file| file.tag(key));
+        let entry = HistoryEntry {
+            played_at: std::time::SystemTime::now(),
+            path: path.to_path_buf(),
+            artist: tag(StandardTagKey::Artist),
+            title: tag(StandardTagKey::TrackTitle),
+            album: tag(StandardTagKey::Album),
+            duration: file.and_then(|file| file.duration()),
+            position: std::time::Duration::ZERO,
+        };
+        self.last_save = std::time::Instant::now();
+        if let Err(e) = self.log.start_track(entry) {
+            eprintln!("Error recording history: {}", e);
+        }
+    }
+
+    /// Saves `position` in the current track now and then
+    fn update(&mut self, position: std::time::Duration) {
+        if self.last_save.elapsed() < HISTORY_INTERVAL {
+            return;
+        }
+        self.last_save = std::time::Instant::now();
+        self.save_position(position);
+    }
+
+    /// Saves `position` in the current track right away
+    fn save_position(&mut self, position: std::time::Duration) {
+        if let Err(e) = self.log.update(position) {
+            eprintln!("Error recording history: {}", e);
+        }
+    }
+
+    /// Records where the current track was left and moves on from it
+    fn stop_track(&mut self, position: std::time::Duration) {
+        if let Err(e) = self.log.stop_track(position) {
+            eprintln!("Error recording history: {}", e);
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Err(e) = self.log.finish() {
+            eprintln!("Error recording history: {}", e);
+        }
+    }
+}
+
+fn handle_history(args: HistoryArgs, format: OutputFormat) {
+    let Some(path) = history_path() else {
+        eprintln!("Error reading history: no state directory available");
+        return;
+    };
+    if args.clear {
+        match std::fs::remove_file(&path) {
+            Ok(()) => print_status(format, "History cleared"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                print_status(format, "History cleared")
+            }
+            Err(e) => eprintln!("Error clearing history: {}", e),
+        }
+        return;
+    }
+    let entries = match read_history(&path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Error reading history: {}", e);
+            return;
+        }
+    };
+
+    let since = args
+        .since
+        .and_then(|since| std::time::SystemTime::now().checked_sub(since));
+    let search = args.search.map(|text| text.to_lowercase());
+    let matches = |entry: &HistoryEntry| {
+        let Some(search) = &search else {
+            return true;
+        };
+        [&entry.artist, &entry.title, &entry.album]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .chain([entry.path.to_string_lossy().into_owned()])
+            .any(|text| text.to_lowercase().contains(search))
+    };
+    let limit = match args.limit {
+        0 => usize::MAX,
+        limit => limit,
+    };
+    let entries: Vec<&HistoryEntry> = entries
+        .iter()
+        .rev()
+        .filter(|entry| since.is_none_or(|since| entry.played_at >= since))
+        .filter(|entry| matches(entry))
+        .take(limit)
+        .collect();
+
+    if format == OutputFormat::Json {
+        let entries: Vec<Json> = entries.into_iter().map(history_json).collect();
+        println!("{}", Json::from(entries));
+        return;
+    }
+    if entries.is_empty() {
+        println!("No tracks played");
+        return;
+    }
+    for entry in entries {
+        let completion = entry.completion().map_or("   ?".to_string(), |completion| {
+            format!("{:>3.0}%", completion * 100.0)
+        });
+        let track = match (&entry.artist, &entry.title) {
+            (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, Some(title)) => title.clone(),
+            _ => entry.path.display().to_string(),
+        };
+        println!(
+            "{}  {}  {}",
+            format_timestamp(entry.played_at),
+            completion,
+            track
+        );
+    }
+}
+
+/// What a [`Visualizer`] draws, with the state it keeps between drawings
+enum View {
+    Spectrum(SpectrumBars),
+    Meter(LevelMeter),
+    Phase(PhaseScope),
+}
+
+/// A live display of the output, redrawn in place below the playback messages
+struct Visualizer {
+    view: View,
+    tap: Tap,
+    /// Characters across, one spare so a full-width line never wraps
+    columns: usize,
+    last_draw: std::time::Instant,
+    /// Lines drawn last time, to move back up over
+    lines: usize,
+}
+
+impl Visualizer {
+    fn new(mode: Visualization, sample_rate: u32) -> Self {
+        Visualizer::with_columns(mode, sample_rate, terminal_columns().saturating_sub(1))
+    }
+
+    /// A visualizer drawing `columns` characters across
+    fn with_columns(mode: Visualization, sample_rate: u32, columns: usize) -> Self {
+        let columns = columns.max(1);
+        let (view, frames) = match mode {
+            Visualization::Spectrum => {
+                let bars = SpectrumBars::new(sample_rate, columns);
+                let frames = bars.size();
+                (View::Spectrum(bars), frames)
+            }
+            Visualization::Meter => (
+                View::Meter(LevelMeter::new()),
+                (METER_WINDOW.as_secs_f64() * sample_rate as f64).ceil() as usize,
+            ),
+            Visualization::Phase => (
+                View::Phase(PhaseScope::new()),
+                (CORRELATION_WINDOW.as_secs_f64() * sample_rate as f64).ceil() as usize,
+            ),
+        };
+        Visualizer {
+            view,
+            tap: Tap::new(frames),
+            columns,
+            last_draw: std::time::Instant::now(),
+            lines: 0,
+        }
+    }
+
+    fn draw(&mut self) {
+        let text = self.render(VISUALIZER_ROWS);
+        let mut frame = String::with_capacity(text.len() + 8 * VISUALIZER_ROWS);
+        if self.lines > 0 {
+            frame.push_str(&format!("\x1b[{}A", self.lines));
+        }
+        for line in text.lines() {
+            frame.push_str("\x1b[2K");
+            frame.push_str(line);
+            frame.push('\n');
+        }
+        print!("{}", frame);
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        self.lines = text.lines().count();
+    }
+
+    /// The latest output as text, `rows` high where the view allows
+    fn render(&mut self, rows: usize) -> String {
+        let elapsed = self.last_draw.elapsed();
+        self.last_draw = std::time::Instant::now();
+        let channels = self.tap.channels();
+        match &mut self.view {
+            View::Spectrum(bars) => {
+                bars.update(&self.tap.latest(bars.size()), channels);
+                bars.render(rows)
+            }
+            View::Meter(meter) => {
+                let sample_rate = self.tap.sample_rate();
+                let frames = (METER_WINDOW.as_secs_f64() * sample_rate as f64).ceil() as usize;
+                meter.update(&self.tap.latest(frames), channels, sample_rate, elapsed);
+                meter.render(self.columns.saturating_sub(METER_LABELS).max(10))
+            }
+            View::Phase(scope) => {
+                let frames = (CORRELATION_WINDOW.as_secs_f64() * self.tap.sample_rate() as f64)
+                    .ceil() as usize;
+                scope.update(&self.tap.latest(frames), channels);
+                scope.render(rows)
+            }
+        }
+    }
+
+    /// Leaves the last drawing alone, so messages printed after it stay
+    /// visible and the next drawing starts below them
+    fn detach(&mut self) {
+        self.lines = 0;
+    }
+}
+
+/// The position in the current track as a bar with the elapsed and total
+/// time, redrawn in place on the last line
+struct ProgressBar {
+    columns: usize,
+}
+
+impl ProgressBar {
+    /// A progress bar if stdout is a terminal, which it needs to redraw in
+    /// place
+    fn open() -> Option<Self> {
+        use std::io::IsTerminal;
+        std::io::stdout().is_terminal().then(|| ProgressBar {
+            // One spare so the line never wraps
+            columns: terminal_columns().saturating_sub(1).max(1),
+        })
+    }
+
+    fn draw(
+        &self,
+        position: std::time::Duration,
+        length: Option<std::time::Duration>,
+        bitrate: Option<u64>,
+        paused: bool,
+    ) {
+        print!("\r\x1b[2K{}", self.line(position, length, bitrate, paused));
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+    }
+
+    /// The bar as e.g. `1:23 / 4:56 [======------]  28%  320 kbps`
+    fn line(
+        &self,
+        position: std::time::Duration,
+        length: Option<std::time::Duration>,
+        bitrate: Option<u64>,
+        paused: bool,
+    ) -> String {
+        let length = length.filter(|length| !length.is_zero());
+        let fraction =
+            length.map(|length| (position.as_secs_f64() / length.as_secs_f64()).min(1.0));
+        let mut text = format_clock(position);
+        if let Some(length) = length {
+            text.push_str(&format!(" / {}", format_clock(length)));
+        }
+        let mut tail = String::new();
+        if let Some(fraction) = fraction {
+            tail.push_str(&format!(" {:>3.0}%", fraction * 100.0));
+        }
+        if let Some(bitrate) = bitrate {
+            tail.push_str(&format!("  {} kbps", (bitrate as f64 / 1000.0).round()));
+        }
+        if paused {
+            tail.push_str("  paused");
+        }
+        // Whatever room is left goes to the bar
+        let width = self.columns.saturating_sub(text.len() + tail.len() + 3);
+        if let (Some(fraction), true) = (fraction, width >= 10) {
+            let filled = (fraction * width as f64).round() as usize;
+            text.push_str(&format!(
+                " [{}{}]",
+                "=".repeat(filled),
+                "-".repeat(width - filled)
+            ));
+        }
+        text.push_str(&tail);
+        text.chars().take(self.columns).collect()
+    }
+
+    /// Erases the bar, leaving the cursor at the start of its line
+    fn clear(&self) {
+        print!("\r\x1b[2K");
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+    }
+}
+
+/// A key pressed while playing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Enter,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Keys pressed in the terminal, read one at a time on a thread of their
+/// own. The terminal is put back as it was when this is dropped.
+struct Keyboard {
+    keys: std::sync::mpsc::Receiver<Key>,
+    #[cfg(unix)]
+    saved: nix::sys::termios::Termios,
+}
+
+impl Keyboard {
+    /// Starts reading keys, if stdin is a terminal
+    fn open() -> Option<Self> {
+        use std::io::{IsTerminal, Read};
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+        #[cfg(unix)]
+        let saved = {
+            use nix::sys::termios::{
+                tcgetattr, tcsetattr, LocalFlags, SetArg, SpecialCharacterIndices,
+            };
+            let saved = tcgetattr(0).ok()?;
+            let mut raw = saved.clone();
+            // Keys arrive as they are pressed and aren't echoed. Ctrl+C
+            // arrives as a key too, so the terminal is restored on the way out.
+            raw.local_flags
+                .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG);
+            raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+            raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+            tcsetattr(0, SetArg::TCSANOW, &raw).ok()?;
+            saved
+        };
+
+        let (sender, keys) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut bytes = std::io::stdin().lock().bytes().map_while(Result::ok);
+            while let Some(byte) = bytes.next() {
+                let key = match byte {
+                    // Arrow keys are sent as ESC [ A to ESC [ D
+                    0x1b => match (bytes.next(), bytes.next()) {
+                        (Some(b'[' | b'O'), Some(b'A')) => Key::Up,
+                        (Some(b'[' | b'O'), Some(b'B')) => Key::Down,
+                        (Some(b'[' | b'O'), Some(b'C')) => Key::Right,
+                        (Some(b'[' | b'O'), Some(b'D')) => Key::Left,
+                        _ => continue,
+                    },
+                    b'\n' | b'\r' => Key::Enter,
+                    _ => Key::Char(byte.to_ascii_lowercase() as char),
+                };
+                if sender.send(key).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(Keyboard {
+            keys,
+            #[cfg(unix)]
+            saved,
+        })
+    }
+
+    /// The next key pressed, if any
+    fn poll(&self) -> Option<Key> {
+        self.keys.try_recv().ok()
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        let _ = nix::sys::termios::tcsetattr(0, nix::sys::termios::SetArg::TCSANOW, &self.saved);
+    }
+}
+
+/// Opens the track at `path` again for what it says about itself. Stdin
+/// can't be, as the player is reading it.
+fn reopen(path: &std::path::PathBuf) -> Option<AudioFile> {
+    match stdin::is_stdin(path) {
+        true => None,
+        false => AudioFile::open(path).ok(),
+    }
+}
+
+/// Acts on a key pressed while playing, other than quitting. Returns what
+/// to tell the user, if anything.
+fn handle_play_key(player: &mut AudioPlayer, key: Key) -> Option<String> {
+    let position = player.position();
+    match key {
+        Key::Char(' ') => {
+            let (result, message) = match player.is_paused() {
+                true => (player.resume(), "Playing"),
+                false => (player.pause(), "Paused"),
+            };
+            Some(match result {
+                Ok(()) => message.to_string(),
+                Err(e) => format!("Error pausing: {}", e),
+            })
+        }
+        Key::Left => {
+            player.seek(position.saturating_sub(SEEK_STEP));
+            None
+        }
+        Key::Right => {
+            let duration = player
+                .current_path()
+                .and_then(|path| reopen(&path))
+                .and_then(|file| file.duration());
+            match duration {
+                // Seeking past the end moves on to the next track
+                Some(duration) if position + SEEK_STEP >= duration => {
+                    if !player.next_track() {
+                        player.stop();
+                    }
+                }
+                _ => {
+                    player.seek(position + SEEK_STEP);
+                }
+            }
+            None
+        }
+        Key::Up | Key::Down => {
+            let step = if key == Key::Up {
+                VOLUME_STEP
+            } else {
+                -VOLUME_STEP
+            };
+            let volume = ((player.volume() + step) / VOLUME_STEP).round() * VOLUME_STEP;
+            player.set_volume(volume.clamp(0.0, 1.0));
+            Some(format!("Volume {:.0}%", player.volume() * 100.0))
+        }
+        Key::Char('n') => (!player.next_track()).then(|| "Already at the last track".to_string()),
+        Key::Char('p') => {
+            (!player.previous_track()).then(|| "Already at the first track".to_string())
+        }
+        Key::Char(']') => (!player.next_chapter()).then(|| "No next chapter".to_string()),
+        Key::Char('[') => (!player.previous_chapter()).then(|| "No chapters".to_string()),
+        Key::Char('a') => {
+            player.set_loop_start();
+            Some(format!("Loop starts at {}", format_time(position)))
+        }
+        Key::Char('b') => match player.set_loop_end() {
+            true => Some(format!(
+                "Looping {} to {}",
+                format_time(player.loop_region()?.start),
+                format_time(position)
+            )),
+            false => Some("Press a to mark where the loop starts first".to_string()),
+        },
+        Key::Char('c') => {
+            player.set_loop(None);
+            Some("Loop cleared".to_string())
+        }
+        Key::Char('m') => {
+            let path = player.current_path()?;
+            let added = open_bookmarks(&path).and_then(|(mut store, key)| {
+                store.add(&key, &path, &format_time(position), position);
+                store.save()
+            });
+            Some(match added {
+                Ok(()) => format!("Added bookmark at {}", format_time(position)),
+                Err(e) => format!("Error adding bookmark: {}", e),
+            })
+        }
+        Key::Char(number @ '1'..='9') => {
+            let path = player.current_path()?;
+            match find_bookmark(&path, &number.to_string()) {
+                Ok(bookmark) => {
+                    player.seek(bookmark.position);
+                    Some(format!(
+                        "Bookmark \"{}\" at {}",
+                        bookmark.name,
+                        format_time(bookmark.position)
+                    ))
+                }
+                Err(e) => Some(format!("Error: {}", e)),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Which list of the TUI keys act on
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Library,
+    Queue,
+}
+
+/// A list in a TUI pane, with its selected row and the first row in view
+#[derive(Default)]
+struct ListView {
+    selected: usize,
+    top: usize,
+}
+
+impl ListView {
+    /// Moves the selection `step` rows, within a list of `len`
+    fn step(&mut self, step: isize, len: usize) {
+        let last = len.saturating_sub(1) as isize;
+        self.selected = (self.selected as isize + step).clamp(0, last) as usize;
+    }
+
+    /// `rows` lines of `width` showing `items`, scrolled to keep the
+    /// selection in view, with a mark on the row of the current track. The
+    /// selection is drawn with the SGR parameters `style` when focused.
+    fn render(
+        &mut self,
+        items: &[String],
+        current: Option<usize>,
+        focused: bool,
+        rows: usize,
+        width: usize,
+        style: &str,
+    ) -> Vec<String> {
+        self.selected = self.selected.min(items.len().saturating_sub(1));
+        self.top = self
+            .top
+            .min(self.selected)
+            .max((self.selected + 1).saturating_sub(rows));
+        (self.top..self.top + rows)
+            .map(|index| {
+                let Some(item) = items.get(index) else {
+                    return " ".repeat(width);
+                };
+                let mark = match Some(index) == current {
+                    true => "\u{25b6} ",
+                    false => "  ",
+                };
+                let line = fit(&format!("{}{}", mark, item), width);
+                match (index == self.selected, focused) {
+                    (true, true) => format!("\x1b[{}m{}\x1b[0m", style, line),
+                    (true, false) => format!("\x1b[1m{}\x1b[0m", line),
+                    (false, _) => line,
+                }
+            })
+            .collect()
+    }
+}
+
+/// What the TUI shows of the track being played
+struct NowPlaying {
+    title: String,
+    /// Album, codec and format
+    details: String,
+    length: Option<std::time::Duration>,
+    average_bitrate: Option<u64>,
+}
+
+impl NowPlaying {
+    fn read(path: &std::path::PathBuf) -> Self {
+        let Ok(file) = AudioFile::open(path) else {
+            return NowPlaying {
+                title: track_name(path),
+                details: String::new(),
+                length: None,
+                average_bitrate: None,
+            };
+        };
+        let title = match (
+            file.tag(StandardTagKey::Artist),
+            file.tag(StandardTagKey::TrackTitle),
+        ) {
+            (Some(artist), Some(title)) => format!("{} \u{2013} {}", artist, title),
+            (None, Some(title)) => title,
+            _ => track_name(path),
+        };
+        let mut details: Vec<String> = file.tag(StandardTagKey::Album).into_iter().collect();
+        if let Some(codec) = file.codec() {
+            details.push(codec.short_name.to_uppercase());
+        }
+        details.push(format!("{} Hz", file.sample_rate));
+        details.push(format!("{} ch", file.channels));
+        NowPlaying {
+            title,
+            details: details.join(" \u{b7} "),
+            length: file.duration(),
+            average_bitrate: file.average_bitrate(),
+        }
+    }
+}
+
+/// The terminal's alternate screen with the cursor hidden, left when this
+/// is dropped
+struct Screen;
+
+impl Screen {
+    fn enter() -> Self {
+        print!("\x1b[?1049h\x1b[?25l");
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        // A panic's message would go to the alternate screen and vanish with it
+        let report = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            print!("\x1b[?25h\x1b[?1049l");
+            let _ = std::io::Write::flush(&mut std::io::stdout());
+            report(info);
+        }));
+        Screen
+    }
+
+    /// Replaces what is on screen with `lines`
+    fn draw(&self, lines: &[String]) {
+        print!("\x1b[H{}", lines.join("\n"));
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            // Back to the default hook
+            let _ = std::panic::take_hook();
+        }
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+    }
+}
+
+/// The state of `mogbox tui`: a library of files, the player's queue and
+/// what is playing
+struct Tui {
+    player: AudioPlayer,
+    library: Vec<std::path::PathBuf>,
+    library_names: Vec<String>,
+    focus: Pane,
+    library_view: ListView,
+    queue_view: ListView,
+    now_playing: Option<NowPlaying>,
+    /// The last message and when it was shown
+    message: Option<(String, std::time::Instant)>,
+    visualizer: Visualizer,
+    theme: Theme,
+}
+
+impl Tui {
+    fn show(&mut self, message: String) {
+        self.message = Some((message, std::time::Instant::now()));
+    }
+
+    /// Acts on a key. Returns false once the user quits.
+    fn handle_key(&mut self, key: Key) -> bool {
+        let queue = self.player.queue();
+        let queue_len = queue.lock().unwrap().len();
+        match (key, self.focus) {
+            (Key::Char('q' | '\u{3}'), _) => return false,
+            (Key::Char('\t'), Pane::Library) => self.focus = Pane::Queue,
+            (Key::Char('\t'), Pane::Queue) => self.focus = Pane::Library,
+            (Key::Up, Pane::Library) => self.library_view.step(-1, self.library.len()),
+            (Key::Down, Pane::Library) => self.library_view.step(1, self.library.len()),
+            (Key::Up, Pane::Queue) => self.queue_view.step(-1, queue_len),
+            (Key::Down, Pane::Queue) => self.queue_view.step(1, queue_len),
+            (Key::Enter | Key::Char('a'), Pane::Library) => {
+                let path = self.library[self.library_view.selected].clone();
+                queue.lock().unwrap().enqueue(path);
+                match key {
+                    Key::Enter => self.player.play_from(queue_len),
+                    _ => self.show(format!(
+                        "Added {}",
+                        self.library_names[self.library_view.selected]
+                    )),
+                }
+            }
+            (Key::Enter, Pane::Queue) if queue_len > 0 => {
+                self.player.play_from(self.queue_view.selected)
+            }
+            (Key::Char('d'), Pane::Queue) if queue_len > 0 => {
+                self.remove_from_queue(self.queue_view.selected)
+            }
+            (Key::Char('s'), _) => {
+                let mut queue = queue.lock().unwrap();
+                let shuffle = !queue.shuffle();
+                queue.set_shuffle(shuffle);
+                drop(queue);
+                self.show(format!("Shuffle {}", if shuffle { "on" } else { "off" }));
+            }
+            (Key::Char('r'), _) => {
+                let mut queue = queue.lock().unwrap();
+                let repeat = match queue.repeat() {
+                    RepeatMode::Off => RepeatMode::Queue,
+                    RepeatMode::Queue => RepeatMode::Track,
+                    RepeatMode::Track => RepeatMode::Off,
+                };
+                queue.set_repeat(repeat);
+                drop(queue);
+                self.show(format!("Repeat {}", repeat_name(repeat)));
+            }
+            // Up and down move the selection, so these change the volume
+            (Key::Char('+' | '='), _) => self.play_key(Key::Up),
+            (Key::Char('-'), _) => self.play_key(Key::Down),
+            (Key::Char(' ' | 'n' | 'p' | '[' | ']' | 'm') | Key::Left | Key::Right, _) => {
+                self.play_key(key)
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Acts on a key the same way `mogbox play` does
+    fn play_key(&mut self, key: Key) {
+        if let Some(message) = handle_play_key(&mut self.player, key) {
+            self.show(message);
+        }
+    }
+
+    /// Takes the track at `index` off the queue, keeping the current track
+    /// playing
+    fn remove_from_queue(&mut self, index: usize) {
+        if let Some(removed) = remove_queued(&mut self.player, index) {
+            self.show(format!("Removed {}", track_name(&removed)));
+        }
+    }
+
+    /// The whole screen, `columns` by `rows`
+    fn render(&mut self, columns: usize, rows: usize) -> Vec<String> {
+        // One spare column so lines never wrap
+        let width = columns.saturating_sub(1).max(20);
+        let visualizer_rows = match rows >= 24 {
+            true => TUI_VISUALIZER_ROWS,
+            false => 0,
+        };
+        // Title, pane headings, separator, three lines of the current track
+        // and the status line around the lists
+        let list_rows = rows.saturating_sub(7 + visualizer_rows).max(1);
+        let left = (width - 1) / 2;
+        let right = width - 1 - left;
+
+        let queue = self.player.queue();
+        let (queue_names, shuffle, repeat) = {
+            let queue = queue.lock().unwrap();
+            let names: Vec<String> = queue.items().iter().map(|path| track_name(path)).collect();
+            (names, queue.shuffle(), queue.repeat())
+        };
+        let current = self
+            .player
+            .current_track()
+            .filter(|_| self.player.is_playing());
+
+        let style = self.theme.style();
+        let mut lines = vec![format!("\x1b[{}m{}\x1b[0m", style, fit(" MogBox", width))];
+        let heading = |text: String, width: usize, focused: bool| match focused {
+            true => format!("\x1b[1;4m{}\x1b[0m", fit(&text, width)),
+            false => fit(&text, width),
+        };
+        let mut modes = String::new();
+        if shuffle {
+            modes.push_str("  shuffle");
+        }
+        if repeat != RepeatMode::Off {
+            modes.push_str(&format!("  repeat {}", repeat_name(repeat)));
+        }
+        lines.push(format!(
+            "{}\u{2502}{}",
+            heading(
+                format!(" Library ({})", self.library.len()),
+                left,
+                self.focus == Pane::Library
+            ),
+            heading(
+                format!(" Queue ({}){}", queue_names.len(), modes),
+                right,
+                self.focus == Pane::Queue
+            ),
+        ));
+        let library_lines = self.library_view.render(
+            &self.library_names,
+            None,
+            self.focus == Pane::Library,
+            list_rows,
+            left,
+            self.theme.style(),
+        );
+        let queue_lines = self.queue_view.render(
+            &queue_names,
+            current,
+            self.focus == Pane::Queue,
+            list_rows,
+            right,
+            self.theme.style(),
+        );
+        for (library, queue) in library_lines.into_iter().zip(queue_lines) {
+            lines.push(format!("{}\u{2502}{}", library, queue));
+        }
+        lines.push("\u{2500}".repeat(width));
+
+        let paused = self.player.is_paused();
+        match (&self.now_playing, self.player.is_playing()) {
+            (Some(now_playing), true) => {
+                let icon = match paused {
+                    true => "\u{2759}\u{2759}",
+                    false => "\u{25b6}",
+                };
+                lines.push(fit(&format!(" {} {}", icon, now_playing.title), width));
+                lines.push(fit(
+                    &format!(
+                        "   {}  Volume {:.0}%",
+                        now_playing.details,
+                        self.player.volume() * 100.0
+                    ),
+                    width,
+                ));
+                let bar = ProgressBar {
+                    columns: width.saturating_sub(3),
+                };
+                let bitrate = self.player.bitrate().or(now_playing.average_bitrate);
+                let line = bar.line(self.player.position(), now_playing.length, bitrate, paused);
+                lines.push(fit(&format!("   {}", line), width));
+            }
+            _ => {
+                lines.push(fit(" \u{25a0} Stopped", width));
+                lines.push(" ".repeat(width));
+                lines.push(" ".repeat(width));
+            }
+        }
+
+        if visualizer_rows > 0 {
+            let text = self.visualizer.render(visualizer_rows);
+            let mut drawn: Vec<String> = text.lines().map(|line| fit(line, width)).collect();
+            drawn.resize(visualizer_rows, " ".repeat(width));
+            lines.extend(drawn);
+        }
+
+        let status = match &self.message {
+            Some((message, shown)) if shown.elapsed() < TUI_MESSAGE_TIME => message.clone(),
+            _ => "Tab pane  \u{2191}/\u{2193} select  Enter play  a add  d remove  space pause  \
+                  \u{2190}/\u{2192} seek  +/- volume  n/p track  s shuffle  r repeat  q quit"
+                .to_string(),
+        };
+        lines.push(format!(
+            "\x1b[{}m{}\x1b[0m",
+            style,
+            fit(&format!(" {}", status), width)
+        ));
+        lines
+    }
+}
+
+/// Takes the track at `index` off the queue, keeping the current track
+/// playing, and returns its path
+fn remove_queued(player: &mut AudioPlayer, index: usize) -> Option<std::path::PathBuf> {
+    let playing = player.current_track();
+    let position = player.position();
+    let queue = player.queue();
+    let (removed, len) = {
+        let mut queue = queue.lock().unwrap();
+        (queue.remove(index), queue.len())
+    };
+    // The player knows tracks by their place in the queue, which moved
+    match playing {
+        Some(track) if index < track => player.play_from_at(track - 1, position),
+        Some(track) if index == track && track < len => player.play_from(track),
+        Some(track) if index == track => player.stop(),
+        // The decoder may already have moved on to the next track
+        Some(track) if index == track + 1 => player.play_from_at(track, position),
+        _ => {}
+    }
+    removed
+}
+
+fn repeat_name(repeat: RepeatMode) -> &'static str {
+    match repeat {
+        RepeatMode::Off => "off",
+        RepeatMode::Track => "track",
+        RepeatMode::Queue => "queue",
+    }
+}
+
+/// How a track is listed: its file name
+fn track_name(path: &std::path::Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// `text` cut or padded with spaces to `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted: String = text.chars().take(width).collect();
+    let len = fitted.chars().count();
+    fitted.extend(std::iter::repeat_n(' ', width - len));
+    fitted
+}
+
+/// Size of the terminal in columns and rows, else `COLUMNS` by 24
+fn terminal_size() -> (usize, usize) {
+    #[cfg(unix)]
+    {
+        nix::ioctl_read_bad!(window_size, nix::libc::TIOCGWINSZ, nix::pty::Winsize);
+        let mut size = nix::pty::Winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: TIOCGWINSZ only writes a `winsize` to the pointer given
+        #[allow(unsafe_code)]
+        let result = unsafe { window_size(1, &mut size) };
+        if result.is_ok() && size.ws_col > 0 && size.ws_row > 0 {
+            return (size.ws_col as usize, size.ws_row as usize);
+        }
+    }
+    (terminal_columns(), 24)
+}
+
+/// How often the TUI checks whether the terminal was resized
+const TUI_RESIZE_CHECK: std::time::Duration = std::time::Duration::from_secs(1);
+
+fn handle_tui(args: TuiArgs) {
+    // Keys only arrive as they are pressed in a Unix terminal
+    if !cfg!(unix) {
+        eprintln!("Error: the TUI needs a Unix terminal");
+        return;
+    }
+    let config = load_config();
+    let paths = match (args.paths.is_empty(), config.library.is_empty()) {
+        (false, _) => args.paths,
+        (true, false) => config.library.clone(),
+        (true, true) => vec![".".into()],
+    };
+    let library = expand_paths(paths);
+    if library.is_empty() {
+        eprintln!("Error: no audio files found");
+        return;
+    }
+    let Some(keyboard) = Keyboard::open() else {
+        eprintln!("Error: the TUI needs a terminal");
+        return;
+    };
+    let player_config = PlayerConfig {
+        host: args.host.or(config.host),
+        device: args.device.or(config.device),
+        ..PlayerConfig::default()
+    };
+    let mut player = match AudioPlayer::with_config(&player_config) {
+        Ok(player) => player,
+        Err(e) => {
+            eprintln!("Error opening output device: {}", e);
+            return;
+        }
+    };
+
+    player.set_volume(args.volume.or(config.volume).unwrap_or(1.0));
+    player.set_replaygain(ReplayGainConfig {
+        mode: config.replaygain.unwrap_or_default(),
+        preamp: 0.0,
+    });
+
+    let (mut columns, mut rows) = terminal_size();
+    let visualizer = Visualizer::with_columns(
+        Visualization::Spectrum,
+        player.mixer().sample_rate(),
+        columns.saturating_sub(1),
+    );
+    player
+        .mixer()
+        .chain()
+        .lock()
+        .unwrap()
+        .push(visualizer.tap.clone());
+    let mut tui = Tui {
+        player,
+        library_names: library.iter().map(|path| track_name(path)).collect(),
+        library,
+        focus: Pane::Library,
+        library_view: ListView::default(),
+        queue_view: ListView::default(),
+        now_playing: None,
+        message: None,
+        visualizer,
+        theme: config.theme.unwrap_or_default(),
+    };
+    let mut history = HistoryTracker::open();
+
+    let screen = Screen::enter();
+    let mut last_resize_check = std::time::Instant::now();
+    let mut current = None;
+    'running: loop {
+        while let Some(key) = keyboard.poll() {
+            let (track, position) = (tui.player.current_track(), tui.player.position());
+            if !tui.handle_key(key) {
+                break 'running;
+            }
+            // Leaving a track partway doesn't count as playing it to the end
+            let jumped = matches!(key, Key::Enter | Key::Char('n' | 'p' | 'd'));
+            if jumped && tui.player.current_track() != track {
+                if let Some(history) = &mut history {
+                    history.stop_track(position);
+                }
+            }
+        }
+        for (path, e) in tui.player.take_errors() {
+            tui.show(format!("Error playing {}: {}", track_name(&path), e));
+        }
+
+        // The last track is kept as the current one once the queue ends
+        let playing = tui
+            .player
+            .current_track()
+            .filter(|_| tui.player.is_playing());
+        if playing.is_some() && playing != current {
+            current = playing;
+            if let Some(path) = tui.player.current_path() {
+                tui.now_playing = Some(NowPlaying::read(&path));
+                if let Some(history) = &mut history {
+                    let file = AudioFile::open(&path).ok();
+                    history.start_track(&path, file.as_ref());
+                }
+            }
+        } else if current.is_some() && !tui.player.is_playing() {
+            current = None;
+            tui.now_playing = None;
+            if let Some(history) = &mut history {
+                history.finish();
+            }
+        }
+        if let Some(history) = &mut history {
+            history.update(tui.player.position());
+        }
+
+        if last_resize_check.elapsed() >= TUI_RESIZE_CHECK {
+            last_resize_check = std::time::Instant::now();
+            (columns, rows) = terminal_size();
+        }
+        screen.draw(&tui.render(columns, rows));
+        std::thread::sleep(TUI_INTERVAL);
+    }
+
+    drop(screen);
+    if let Some(history) = &mut history {
+        history.stop_track(tui.player.position());
+    }
+}
+
+fn handle_convert(args: ConvertArgs) {
+    let options = args.encoder.options();
+
+    if args.to.is_some() || args.out_dir.is_some() {
+        convert_many(&args, &options);
+        return;
+    }
+
+    let [input, output] = args.paths.as_slice() else {
+        eprintln!("Expected an INPUT and an OUTPUT, or --to/--out-dir to convert several files");
+        return;
+    };
+    let input = std::path::PathBuf::from(input);
+    let output = std::path::PathBuf::from(output);
+    print_read_file(&input);
+    let result = backend_for_path(&output).and_then(|backend| {
+        convert_with(
+            &input,
+            &output,
+            backend.as_ref(),
+            &options,
+            &args.edits.edits(),
+        )
+    });
+    match result {
+        Ok(()) => println!("Wrote {:?}", output),
+        Err(e) => eprintln!("Error converting {:?}: {}", input, e),
+    }
+}
+
+fn convert_many(args: &ConvertArgs, options: &EncodeOptions) {
+    let inputs = expand_globs(&args.paths);
+    if inputs.is_empty() {
+        eprintln!("Nothing to convert");
+        return;
+    }
+
+    let jobs = match plan_batch(&inputs, args.to.as_deref(), args.out_dir.as_deref()) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return;
+        }
+    };
+
+    let total = jobs.len();
+    let done = std::sync::atomic::AtomicUsize::new(0);
+    println!("Converting {} files...", total);
+    let edits = args.edits.edits();
+    let results = convert_batch(
+        &jobs,
+        options,
+        &edits,
+        args.jobs.map(usize::from),
+        |job, result| {
+            let n = done.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+            match result {
+                Ok(()) => println!("[{}/{}] Wrote {:?}", n, total, job.output),
+                Err(e) => eprintln!("[{}/{}] Error converting {:?}: {}", n, total, job.input, e),
+            }
+        },
+    );
+    let results = match results {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return;
+        }
+    };
+
+    let failed: Vec<_> = jobs
+        .iter()
+        .zip(&results)
+        .filter_map(|(job, result)| result.as_ref().err().map(|e| (job, e)))
+        .collect();
+    println!("Converted {} of {} files", total - failed.len(), total);
+    if !failed.is_empty() {
+        eprintln!("Failed:");
+        for (job, e) in failed {
+            eprintln!("  {:?}: {}", job.input, e);
+        }
+    }
+}
+
+fn handle_decode(args: DecodeArgs) {
+    use std::io::Write;
+
+    let encoding = args.format;
+    let mut audio_file = match AudioFile::open(&args.input) {
+        Ok(audio_file) => audio_file,
+        Err(e) => {
+            eprintln!("Error opening audio file: {}", e);
+            return;
+        }
+    };
+    // What the samples are, since nothing in them says so
+    eprintln!(
+        "Decoding {:?}: {}, {} Hz, {} channels",
+        args.input,
+        encoding.name(),
+        audio_file.sample_rate,
+        audio_file.channels
+    );
+
+    let to_stdout = args.output.as_os_str() == "-";
+    let output: Box<dyn std::io::Write> = if to_stdout {
+        Box::new(std::io::stdout().lock())
+    } else {
+        match std::fs::File::create(&args.output) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("Error creating {:?}: {}", args.output, e);
+                return;
+            }
+        }
+    };
+    let mut output = std::io::BufWriter::new(output);
+    let mut bytes = Vec::new();
+    loop {
+        let samples = match audio_file.next_samples() {
+            Ok(Some(samples)) => samples,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Error decoding {:?}: {}", args.input, e);
+                break;
+            }
+        };
+        bytes.clear();
+        for sample in samples {
+            encoding.encode(sample, &mut bytes);
+        }
+        if let Err(e) = output.write_all(&bytes) {
+            // The reader of a pipe is done with it
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                eprintln!("Error writing {:?}: {}", args.output, e);
+            }
+            return;
+        }
+    }
+    match output.flush() {
+        Ok(()) => {
+            if !to_stdout {
+                eprintln!("Wrote {:?}", args.output);
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+        Err(e) => eprintln!("Error writing {:?}: {}", args.output, e),
+    }
+}
+
+fn handle_trim(args: TrimArgs) {
+    print_read_file(&args.input);
+
+    let edits = Edits {
+        start: args.start,
+        end: args.end,
+        ..args.edits.edits()
+    };
+    let result = backend_for_path(&args.output).and_then(|backend| {
+        convert_with(
+            &args.input,
+            &args.output,
+            backend.as_ref(),
+            &args.encoder.options(),
+            &edits,
+        )
+    });
+    match result {
+        Ok(()) => println!("Wrote {:?}", args.output),
+        Err(e) => eprintln!("Error trimming {:?}: {}", args.input, e),
+    }
+}
+
+fn handle_join(args: JoinArgs) {
+    for input in &args.inputs {
+        print_read_file(input);
+    }
+
+    match mogbox_encode::join(
+        &args.inputs,
+        &args.output,
+        &args.encoder.options(),
+        args.resample,
+    ) {
+        Ok(()) => println!("Wrote {:?}", args.output),
+        Err(e) => eprintln!("Error joining files: {}", e),
+    }
+}
+
+fn handle_split(args: SplitArgs) {
+    print_read_file(&args.input);
+
+    let backend = match args.to.as_deref() {
+        Some(name) => backend(name).ok_or_else(|| format!("unknown format: {}", name)),
+        None => backend_for_path(&args.input),
+    };
+    let result = backend.and_then(|backend| {
+        let options = args.encoder.options();
+        let out_dir = match args.out_dir.as_deref() {
+            Some(dir) => dir,
+            None => args.input.parent().unwrap_or(std::path::Path::new("")),
+        };
+        let file = AudioFile::open(&args.input)?;
+        let segments = match (args.cue.as_ref(), args.silence) {
+            (Some(cue), _) => {
+                let sheet = CueSheet::read(cue)?;
+                cue_segments(
+                    &sheet,
+                    &args.input,
+                    &file,
+                    out_dir,
+                    backend.as_ref(),
+                    &options,
+                )?
+            }
+            // Finding the silences takes a pass over the input of its own
+            (None, Some(_)) if stdin::is_stdin(&args.input) => {
+                return Err("stdin can't be split at silences".to_string())
+            }
+            (None, Some(threshold)) => silence_segments(
+                &args.input,
+                threshold,
+                args.min_gap,
+                out_dir,
+                backend.as_ref(),
+                &options,
+            )?,
+            (None, None) => return Err("either --cue or --silence is needed".to_string()),
+        };
+        if let Some(dir) = args.out_dir.as_ref() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+        }
+        mogbox_encode::split(file, &segments, backend.as_ref(), &options)?;
+        Ok(segments)
+    });
+
+    match result {
+        Ok(segments) => {
+            for segment in segments {
+                println!("Wrote {:?}", segment.output);
+            }
+        }
+        Err(e) => eprintln!("Error splitting {:?}: {}", args.input, e),
+    }
+}
+
+fn handle_mix(args: MixArgs) {
+    for input in &args.inputs {
+        print_read_file(input);
+    }
+    if args.gain.len() > args.inputs.len() || args.offset.len() > args.inputs.len() {
+        eprintln!("Error mixing files: more gains or offsets than inputs");
+        return;
+    }
+
+    let inputs: Vec<MixInput> = args
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, path)| MixInput {
+            gain: args.gain.get(i).copied().unwrap_or(0.0),
+            offset: args.offset.get(i).copied().unwrap_or_default(),
+            ..MixInput::new(path.clone())
+        })
+        .collect();
+    match mogbox_encode::mix(&inputs, &args.output, &args.encoder.options(), args.limit) {
+        Ok(()) => println!("Wrote {:?}", args.output),
+        Err(e) => eprintln!("Error mixing files: {}", e),
+    }
+}
+
+fn handle_loudness(path: std::path::PathBuf, format: OutputFormat) {
+    let report = match loudness_report(&path) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error measuring {:?}: {}", path, e);
+            return;
+        }
+    };
+
+    if format == OutputFormat::Json {
+        println!("{}", loudness_json(&path, &report));
+        return;
+    }
+
+    print_read_file(&path);
+    let value = |value: Option<f32>, unit: &str| match value {
+        Some(value) => format!("{:.1} {}", value, unit),
+        None => "-".to_string(),
+    };
+    println!("Integrated:      {}", value(report.integrated, "LUFS"));
+    println!("Loudness range:  {}", value(report.range, "LU"));
+    println!("Momentary max:   {}", value(report.momentary_max, "LUFS"));
+    println!("Short-term max:  {}", value(report.short_term_max, "LUFS"));
+    println!("True peak:       {}", value(report.true_peak, "dBTP"));
+}
+
+fn handle_analyze(args: AnalyzeArgs, format: OutputFormat) {
+    let json = format == OutputFormat::Json;
+    let mut members = vec![("path", Json::path(&args.path))];
+    if !json {
+        print_read_file(&args.path);
+    }
+
+    if args.clipping {
+        let report = match clipping_report(&args.path) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("Error analyzing {:?}: {}", args.path, e);
+                return;
+            }
+        };
+        if json {
+            let channels: Vec<Json> = report
+                .iter()
+                .map(|clipping| {
+                    let worst: Vec<Json> = clipping
+                        .worst
+                        .iter()
+                        .map(|run| {
+                            Json::object([
+                                ("start_seconds", Json::seconds(run.start)),
+                                ("samples", run.length.into()),
+                            ])
+                        })
+                        .collect();
+                    Json::object([
+                        ("samples", clipping.samples.into()),
+                        ("runs", clipping.runs.into()),
+                        ("worst", worst.into()),
+                    ])
+                })
+                .collect();
+            members.push(("clipping", channels.into()));
+        } else {
+            println!("Clipping:");
+            for (channel, clipping) in report.iter().enumerate() {
+                println!(
+                    "  Channel {}: {} clipped samples in {} runs",
+                    channel + 1,
+                    clipping.samples,
+                    clipping.runs
+                );
+                for run in &clipping.worst {
+                    println!("    {}  {} samples", format_time(run.start), run.length);
+                }
+            }
+        }
+    }
+
+    if args.dc {
+        let offsets = match dc_offsets(&args.path) {
+            Ok(offsets) => offsets,
+            Err(e) => {
+                eprintln!("Error analyzing {:?}: {}", args.path, e);
+                return;
+            }
+        };
+        if json {
+            let offsets: Vec<Json> = offsets
+                .iter()
+                .map(|offset| Json::rounded(*offset, 6))
+                .collect();
+            members.push(("dc_offset", offsets.into()));
+        } else {
+            println!("DC offset:");
+            for (channel, offset) in offsets.iter().enumerate() {
+                if *offset == 0.0 {
+                    println!("  Channel {}: none", channel + 1);
+                    continue;
+                }
+                println!(
+                    "  Channel {}: {:+.6} ({:.1} dBFS)",
+                    channel + 1,
+                    offset,
+                    linear_to_db(offset.abs())
+                );
+            }
+        }
+    }
+
+    if args.correlation {
+        let report = match correlation_report(&args.path) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("Error analyzing {:?}: {}", args.path, e);
+                return;
+            }
+        };
+        if json {
+            let value = |correlation: Option<f32>| {
+                correlation.map_or(Json::Null, |value| Json::rounded(value, 2))
+            };
+            members.push((
+                "correlation",
+                Json::object([
+                    ("overall", value(report.overall)),
+                    ("minimum", value(report.minimum)),
+                    ("window_seconds", Json::seconds(CORRELATION_WINDOW)),
+                    ("negative_seconds", Json::seconds(report.negative)),
+                    ("duration_seconds", Json::seconds(report.duration)),
+                ]),
+            ));
+        } else {
+            print_correlation(&report);
+        }
+    }
+
+    if let Some(threshold) = args.silence {
+        let report = match silence_report(&args.path, threshold, args.min) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("Error analyzing {:?}: {}", args.path, e);
+                return;
+            }
+        };
+        if json {
+            let regions: Vec<Json> = report
+                .regions
+                .iter()
+                .map(|region| {
+                    Json::object([
+                        ("start_seconds", Json::seconds(region.start)),
+                        ("end_seconds", Json::seconds(region.end)),
+                    ])
+                })
+                .collect();
+            members.push((
+                "silence",
+                Json::object([
+                    ("threshold_db", Json::rounded(threshold, 1)),
+                    ("min_length_seconds", Json::seconds(args.min)),
+                    ("regions", regions.into()),
+                    ("total_seconds", Json::seconds(report.total())),
+                    ("duration_seconds", Json::seconds(report.duration)),
+                ]),
+            ));
+        } else {
+            print_silence(&report, threshold, args.min);
+        }
+    }
+
+    if json {
+        println!("{}", Json::object(members));
+    }
+}
+
+fn print_correlation(report: &CorrelationReport) {
+    let value = |correlation: Option<f32>| match correlation {
+        Some(correlation) => format!("{:+.2}", correlation),
+        None => "n/a (silent channel)".to_string(),
+    };
+    println!("Phase correlation:");
+    println!("  Overall: {}", value(report.overall));
+    println!(
+        "  Lowest over {} ms: {}",
+        CORRELATION_WINDOW.as_millis(),
+        value(report.minimum)
+    );
+    let share = report.negative.as_secs_f64() / report.duration.as_secs_f64().max(f64::EPSILON);
+    println!(
+        "  Negative for: {} ({:.1}%)",
+        format_time(report.negative),
+        share * 100.0
+    );
+}
+
+fn print_silence(report: &SilenceReport, threshold: f32, min: std::time::Duration) {
+    println!(
+        "Silence below {:.1} dB for at least {}:",
+        threshold,
+        format_time(min)
+    );
+    for region in &report.regions {
+        println!(
+            "  {} - {}  ({})",
+            format_time(region.start),
+            format_time(region.end),
+            format_time(region.end - region.start)
+        );
+    }
+    let share = report.total().as_secs_f64() / report.duration.as_secs_f64().max(f64::EPSILON);
+    println!(
+        "  {} regions, {} in total ({:.1}%)",
+        report.regions.len(),
+        format_time(report.total()),
+        share * 100.0
+    );
+}
+
+fn handle_stats(path: std::path::PathBuf, format: OutputFormat) {
+    if format == OutputFormat::Text {
+        print_read_file(&path);
+    }
+    let report = match stats_report(&path) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error analyzing {:?}: {}", path, e);
+            return;
+        }
+    };
+    if format == OutputFormat::Json {
+        println!("{}", stats_json(&path, &report));
+        return;
+    }
+
+    let seconds = report.duration().as_secs_f64();
+    println!(
+        "Length: {} ({} frames at {} Hz)",
+        format_time(report.duration()),
+        report.frames,
+        report.sample_rate
+    );
+    let db = |linear: f32| {
+        if linear > 0.0 {
+            format!("{:.2} dBFS", linear_to_db(linear))
+        } else {
+            "-inf dBFS".to_string()
+        }
+    };
+    for (channel, stats) in report.channels.iter().enumerate() {
+        println!();
+        println!("Channel {}:", channel + 1);
+        println!("  Peak:           {}", db(stats.peak));
+        println!("  RMS:            {}", db(stats.rms));
+        match stats.crest_factor() {
+            Some(crest) => println!("  Crest factor:   {:.2} dB", crest),
+            None => println!("  Crest factor:   -"),
+        }
+        println!(
+            "  Zero crossings: {} ({:.1}/s)",
+            stats.zero_crossings,
+            stats.zero_crossings as f64 / seconds.max(f64::MIN_POSITIVE)
+        );
+
+        println!("  Level histogram:");
+        let total = stats.samples.max(1) as f64;
+        let last = stats.histogram.len() - 1;
+        for (bin, &count) in stats.histogram.iter().enumerate() {
+            let high = 0.0 - bin as f32 * HISTOGRAM_STEP;
+            let range = if bin == last {
+                format!("below {:>4.0} dB", high)
+            } else {
+                format!("{:>4.0} to {:>4.0} dB", high, high - HISTOGRAM_STEP)
+            };
+            let share = count as f64 / total;
+            println!(
+                "    {:<16} {:<40} {:>5.1}%",
+                range,
+                "#".repeat((share * 40.0).round() as usize),
+                share * 100.0
+            );
+        }
+    }
+}
+
+fn handle_spectrum(args: SpectrumArgs) {
+    print_read_file(&args.path);
+    let spectrum = match spectrum(
+        &args.path,
+        args.at,
+        args.duration,
+        args.size as usize,
+        args.window,
+    ) {
+        Ok(spectrum) => spectrum,
+        Err(e) => {
+            eprintln!("Error analyzing {:?}: {}", args.path, e);
+            return;
+        }
+    };
+    println!(
+        "{} point FFT, {:.2} Hz per bin",
+        spectrum.size,
+        spectrum.frequency(1)
+    );
+
+    println!("Third-octave bands:");
+    for (centre, level) in spectrum.bands() {
+        // Bars span 96 dB below full scale
+        let bar = ((level + 96.0) / 2.0).clamp(0.0, 48.0).round() as usize;
+        println!(
+            "  {:>7.0} Hz  {:>7.1} dB  {}",
+            centre,
+            level,
+            "#".repeat(bar)
+        );
+    }
+
+    println!("Peaks:");
+    for (frequency, level) in spectrum.peaks(args.peaks) {
+        println!("  {:>9.1} Hz  {:>7.1} dB", frequency, level);
+    }
+
+    if let Some(output) = args.output.as_ref() {
+        match spectrum.write_csv(output) {
+            Ok(()) => println!("Wrote {:?}", output),
+            Err(e) => eprintln!("Error writing {:?}: {}", output, e),
+        }
+    }
+}
+
+fn handle_spectrogram(args: SpectrogramArgs) {
+    print_read_file(&args.path);
+    let options = SpectrogramOptions {
+        size: args.size as usize,
+        hop: args.hop as usize,
+        window: args.window,
+        colormap: args.colormap,
+        floor: args.floor,
+    };
+    let image = match spectrogram(&args.path, &options) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("Error analyzing {:?}: {}", args.path, e);
+            return;
+        }
+    };
+    match image.write_png(&args.output) {
+        Ok(()) => println!(
+            "Wrote {}x{} spectrogram to {:?}",
+            image.width, image.height, args.output
+        ),
+        Err(e) => eprintln!("Error writing {:?}: {}", args.output, e),
+    }
+}
+
+fn handle_waveform(args: WaveformArgs) {
+    print_read_file(&args.path);
+    let options = WaveformOptions {
+        width: args.width as usize,
+        height: args.height as usize,
+        color: args.color,
+        background: args.background,
+    };
+    let peaks = match waveform_peaks(&args.path, options.width) {
+        Ok(peaks) => peaks,
+        Err(e) => {
+            eprintln!("Error analyzing {:?}: {}", args.path, e);
+            return;
+        }
+    };
+
+    let is_svg = args
+        .output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+    let result = if is_svg {
+        std::fs::write(&args.output, waveform_svg(&peaks, &options))
+            .map_err(|e| format!("failed to write file: {}", e))
+    } else {
+        waveform_image(&peaks, &options).write_png(&args.output)
+    };
+    match result {
+        Ok(()) => println!(
+            "Wrote {}x{} waveform to {:?}",
+            options.width, options.height, args.output
+        ),
+        Err(e) => eprintln!("Error writing {:?}: {}", args.output, e),
+    }
+}
+
+fn handle_peaks(args: PeaksArgs) {
+    print_read_file(&args.path);
+    let peaks = match peaks(
+        &args.path,
+        args.samples_per_pixel as usize,
+        args.split_channels,
+    ) {
+        Ok(peaks) => peaks,
+        Err(e) => {
+            eprintln!("Error analyzing {:?}: {}", args.path, e);
+            return;
+        }
+    };
+    match peaks.write(&args.output, args.bits) {
+        Ok(()) => println!(
+            "Wrote {} peaks of {} channels to {:?}",
+            peaks.len(),
+            peaks.channels.len(),
+            args.output
+        ),
+        Err(e) => eprintln!("Error writing {:?}: {}", args.output, e),
+    }
+}
+
+fn handle_bpm(args: BpmArgs, format: OutputFormat) {
+    if format == OutputFormat::Text {
+        print_read_file(&args.path);
+    }
+    let estimate = match tempo(&args.path, args.min, args.max) {
+        Ok(estimate) => estimate,
+        Err(e) => {
+            eprintln!("Error analyzing {:?}: {}", args.path, e);
+            return;
+        }
+    };
+    match format {
+        OutputFormat::Text => println!(
+            "Tempo: {:.1} BPM (confidence {:.0}%)",
+            estimate.bpm,
+            estimate.confidence * 100.0
+        ),
+        OutputFormat::Json => println!(
+            "{}",
+            Json::object([
+                ("path", Json::path(&args.path)),
+                ("bpm", Json::rounded(estimate.bpm, 1)),
+                ("confidence", Json::rounded(estimate.confidence, 2)),
+            ])
+        ),
+    }
+
+    if args.write {
+        let bpm = estimate.bpm.round().to_string();
+        match update_tags(&args.path, &[("BPM", bpm.clone())]) {
+            Ok(()) => print_status(format, &format!("Tagged {:?} with BPM {}", args.path, bpm)),
+            Err(e) => eprintln!("Error tagging {:?}: {}", args.path, e),
+        }
+    }
+}
+
+fn handle_key(args: KeyArgs, format: OutputFormat) {
+    if format == OutputFormat::Text {
+        print_read_file(&args.path);
+    }
+    let estimate = match key(&args.path) {
+        Ok(estimate) => estimate,
+        Err(e) => {
+            eprintln!("Error analyzing {:?}: {}", args.path, e);
+            return;
+        }
+    };
+    match format {
+        OutputFormat::Text => println!(
+            "Key: {} ({}, confidence {:.0}%)",
+            estimate.key,
+            estimate.key.camelot(),
+            estimate.confidence * 100.0
+        ),
+        OutputFormat::Json => println!(
+            "{}",
+            Json::object([
+                ("path", Json::path(&args.path)),
+                ("key", estimate.key.to_string().into()),
+                ("short_name", estimate.key.short_name().into()),
+                ("camelot", estimate.key.camelot().into()),
+                ("confidence", Json::rounded(estimate.confidence, 2)),
+            ])
+        ),
+    }
+
+    if args.write {
+        let name = estimate.key.short_name();
+        match update_tags(&args.path, &[("KEY", name.clone())]) {
+            Ok(()) => print_status(format, &format!("Tagged {:?} with key {}", args.path, name)),
+            Err(e) => eprintln!("Error tagging {:?}: {}", args.path, e),
+        }
+    }
+}
+
+fn handle_fingerprint(args: FingerprintArgs, format: OutputFormat) {
+    let length = (args.length > 0).then(|| std::time::Duration::from_secs(args.length));
+    let fingerprint = match fingerprint(&args.path, length) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+            eprintln!("Error fingerprinting {:?}: {}", args.path, e);
+            return;
+        }
+    };
+    if format == OutputFormat::Json {
+        let data = if args.raw {
+            fingerprint.data.clone().into()
+        } else {
+            fingerprint.encode().into()
+        };
+        let fingerprint = Json::object([
+            ("path", Json::path(&args.path)),
+            ("duration_seconds", fingerprint.duration.as_secs().into()),
+            ("fingerprint", data),
+        ]);
+        println!("{}", fingerprint);
+        return;
+    }
+    println!("DURATION={}", fingerprint.duration.as_secs());
+    if args.raw {
+        let values: Vec<String> = fingerprint.data.iter().map(u32::to_string).collect();
+        println!("FINGERPRINT={}", values.join(","));
+    } else {
+        println!("FINGERPRINT={}", fingerprint.encode());
+    }
+}
+
+fn handle_identify(args: IdentifyArgs) {
+    let Some(api_key) = args
+        .api_key
+        .or_else(|| std::env::var("ACOUSTID_API_KEY").ok())
+    else {
+        eprintln!(
+            "An AcoustID API key is needed: pass --api-key or set ACOUSTID_API_KEY \
+             (register one at https://acoustid.org/new-application)"
+        );
+        return;
+    };
+    print_read_file(&args.path);
+    let candidates = match fingerprint(&args.path, Some(FINGERPRINT_LENGTH))
+        .and_then(|fingerprint| lookup(&fingerprint, &api_key))
+    {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            eprintln!("Error identifying {:?}: {}", args.path, e);
+            return;
+        }
+    };
+    if candidates.is_empty() {
+        println!("No matching recordings found");
+        return;
+    }
+
+    for (i, candidate) in candidates.iter().enumerate() {
+        println!(
+            "{:>2}. {:>3.0}%  {} - {}{}{}",
+            i + 1,
+            candidate.score * 100.0,
+            candidate.artist.as_deref().unwrap_or("[unknown artist]"),
+            candidate.title.as_deref().unwrap_or("[untitled]"),
+            candidate
+                .album
+                .as_ref()
+                .map(|(album, _)| format!(" ({})", album))
+                .unwrap_or_default(),
+            candidate
+                .duration
+                .map(|duration| format!(" [{}]", format_time(duration)))
+                .unwrap_or_default(),
+        );
+        println!(
+            "     https://musicbrainz.org/recording/{}",
+            candidate.recording
+        );
+    }
+
+    if args.apply {
+        let Some(candidate) = args.pick.checked_sub(1).and_then(|i| candidates.get(i)) else {
+            eprintln!("No candidate {} to apply", args.pick);
+            return;
+        };
+        match update_tags(&args.path, &candidate.tags()) {
+            Ok(()) => println!("Tagged {:?} with candidate {}", args.path, args.pick),
+            Err(e) => eprintln!("Error tagging {:?}: {}", args.path, e),
+        }
+    }
+}
+
+fn handle_dr(patterns: Vec<String>, format: OutputFormat) {
+    let paths = expand_globs(&patterns);
+    if paths.is_empty() {
+        eprintln!("Nothing to measure");
+        return;
+    }
+
+    let json = format == OutputFormat::Json;
+    if !json {
+        println!("{:>4}  {:>9}  {:>9}  File", "DR", "Peak", "RMS");
+    }
+    let mut tracks = Vec::with_capacity(paths.len());
+    let mut measured = Vec::with_capacity(paths.len());
+    for path in &paths {
+        match dynamic_range(path) {
+            Ok(dr) => {
+                if json {
+                    measured.push(dr_json(Some(path), &dr));
+                } else {
+                    println!(
+                        "DR{:<2}  {:>6.2} dB  {:>6.2} dB  {}",
+                        dr.score(),
+                        dr.peak,
+                        dr.rms,
+                        path.display()
+                    );
+                }
+                tracks.push(dr);
+            }
+            Err(e) => eprintln!("Error measuring {:?}: {}", path, e),
+        }
+    }
+    if json {
+        let album = DynamicRange::album(&tracks).map(|album| dr_json(None, &album));
+        println!(
+            "{}",
+            Json::object([("tracks", measured.into()), ("album", album.into())])
+        );
+    } else if let Some(album) = DynamicRange::album(&tracks) {
+        println!(
+            "DR{:<2}  {:>6.2} dB  {:>6.2} dB  (album)",
+            album.score(),
+            album.peak,
+            album.rms
+        );
+    }
+}
+
+fn handle_gain_scan(args: GainScanArgs) {
+    let paths = expand_globs(&args.paths);
+    if paths.is_empty() {
+        eprintln!("Nothing to scan");
+        return;
+    }
+
+    let total = paths.len();
+    let done = std::sync::atomic::AtomicUsize::new(0);
+    println!("Scanning {} files...", total);
+    let scan = scan_gain(&paths, args.jobs.map(usize::from), |path| {
+        let n = done.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+        println!("[{}/{}] Scanned {:?}", n, total, path);
+    });
+    let scan = match scan {
+        Ok(scan) => scan,
+        Err(e) => {
+            eprintln!("Error scanning files: {}", e);
+            return;
+        }
+    };
+
+    println!();
+    println!("{:>10}  {:>8}  File", "Gain", "Peak");
+    for (path, gain) in &scan.tracks {
+        println!(
+            "{:>+7.2} dB  {:>8.6}  {}",
+            gain.gain,
+            gain.peak,
+            path.display()
+        );
+    }
+    println!(
+        "{:>+7.2} dB  {:>8.6}  (album)",
+        scan.album.gain, scan.album.peak
+    );
+
+    if args.write {
+        let mut written = 0;
+        for (path, gain) in &scan.tracks {
+            match write_gain_tags(path, gain, &scan.album) {
+                Ok(()) => written += 1,
+                Err(e) => eprintln!("Error tagging {:?}: {}", path, e),
+            }
+        }
+        println!("Tagged {} of {} files", written, scan.tracks.len());
+    }
+}
+
+fn render_queue(
+    queue: PlaybackQueue,
+    start: (usize, std::time::Duration),
+    output: &std::path::Path,
+    sample_rate: u32,
+    channels: usize,
+    args: &PlayArgs,
+    first: Option<AudioFile>,
+) {
+    if queue.repeat() != RepeatMode::Off {
+        eprintln!("Repeat can not be used with --output");
+        return;
+    }
+
+    let mut sink = match WavSink::create(output, sample_rate, channels) {
+        Ok(sink) => sink,
+        Err(e) => {
+            eprintln!("Error creating output file: {}", e);
+            return;
+        }
+    };
+    let source = QueueSource::new(
+        queue.into_shared(),
+        start,
+        sample_rate,
+        channels,
+        PlaybackOptions {
+            crossfade: args.crossfade.unwrap_or_default(),
+            routing: args.channels.clone(),
+            replaygain: args.replaygain(),
+            skip_silence: args.skip_silence(),
+            fade_in: args.fade_in.unwrap_or_default(),
+            raw: args.raw,
+        },
+        first,
+    );
+    if let Some(duration) = args.duration {
+        source.set_sleep_timer(duration, WINDOW_FADE);
+    }
+    let mut chain = Chain::new();
+    if args.dc_block {
+        chain.push(DcBlocker::default());
+    }
+    if let Some(ceiling) = args.limit {
+        chain.push(TruePeakLimiter::new(ceiling));
+    }
+    let mut source = Processed::new(source, chain.into_shared());
+
+    let result = sink.render(&mut source);
+    for (path, e) in source.source().take_errors() {
+        eprintln!("Error playing {:?}: {}", path, e);
+    }
+    let duration = sink.duration();
+    match result.and_then(|()| sink.finish()) {
+        Ok(()) => println!("Wrote {:.1}s to {:?}", duration.as_secs_f64(), output),
+        Err(e) => eprintln!("Error writing {:?}: {}", output, e),
+    }
+}
+
+fn handle_devices() {
+    for host in list_hosts() {
+        let default = if host.is_default { " (default)" } else { "" };
+        println!("Host: {}{}", host.name, default);
+
+        if host.devices.is_empty() {
+            println!("  No output devices");
+        }
+        for device in host.devices {
+            let default = if device.is_default { " (default)" } else { "" };
+            println!("  [{}] {}{}", device.index, device.name, default);
+            for config in device.configs {
+                let buffer = match config.buffer_frames {
+                    Some((min, max)) => format!(", buffer {}-{} frames", min, max),
+                    None => String::new(),
+                };
+                println!(
+                    "      {} ch, {}-{} Hz, {:?}{}",
+                    config.channels,
+                    config.min_sample_rate,
+                    config.max_sample_rate,
+                    config.sample_format,
+                    buffer
+                );
+            }
+        }
+    }
+}
+
+fn handle_queue_save(path: std::path::PathBuf) {
+    let Some(session) = session_queue_path() else {
+        eprintln!("Error saving queue: no state directory available");
+        return;
+    };
+
+    let queue = match PlaybackQueue::load(&session) {
+        Ok(queue) => queue,
+        Err(e) => {
+            eprintln!("Error loading the last play session: {}", e);
+            return;
+        }
+    };
+
+    match queue.save(&path) {
+        Ok(()) => println!("Saved {} tracks to {:?}", queue.len(), path),
+        Err(e) => eprintln!("Error saving queue: {}", e),
+    }
+}
+
+/// JPEG quality of cover art re-encoded for resizing
+const ART_QUALITY: u8 = 90;
+
+fn handle_art_set(args: ArtSetArgs) {
+    let data = match std::fs::read(&args.image) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error reading {:?}: {}", args.image, e);
+            return;
+        }
+    };
+
+    let data = if args.max_size.is_some() || args.quality.is_some() {
+        let image = match Image::decode(&data) {
+            Ok(image) => image,
+            Err(e) => {
+                eprintln!("Error decoding {:?}: {}", args.image, e);
+                return;
+            }
+        };
+        let max_size = args.max_size.map_or(usize::MAX, |size| size as usize);
+        let largest = image.width.max(image.height);
+        if largest > max_size {
+            let scale = |side: usize| (side * max_size / largest).max(1);
+            let resized = image.resize(scale(image.width), scale(image.height));
+            println!(
+                "Resized from {}x{} to {}x{}",
+                image.width, image.height, resized.width, resized.height
+            );
+            resized.to_jpeg(args.quality.unwrap_or(ART_QUALITY))
+        } else if let Some(quality) = args.quality {
+            image.to_jpeg(quality)
+        } else {
+            // Small enough already; keep the original file
+            data
+        }
+    } else {
+        data
+    };
+
+    let cover = match CoverArt::from_image(data) {
+        Ok(cover) => cover,
+        Err(e) => {
+            eprintln!("Error reading {:?}: {}", args.image, e);
+            return;
+        }
+    };
+    match set_cover(&args.path, &cover) {
+        Ok(()) => println!(
+            "Embedded {} cover ({} bytes) in {:?}",
+            cover.media_type,
+            cover.data.len(),
+            args.path
+        ),
+        Err(e) => eprintln!("Error embedding cover art in {:?}: {}", args.path, e),
+    }
+}
+
+/// Builds the queue for the play command. A single playlist argument keeps its
+/// saved current index, so a saved session resumes where it left off.
+fn load_queue(paths: Vec<std::path::PathBuf>) -> PlaybackQueue {
+    if let [path] = paths.as_slice() {
+        if playlist::is_playlist(path) {
+            return match PlaybackQueue::load(path) {
+                Ok(queue) => queue,
+                Err(e) => {
+                    eprintln!("Error reading playlist {:?}: {}", path, e);
+                    PlaybackQueue::new()
+                }
+            };
+        }
+    }
+    PlaybackQueue::from_paths(expand_paths(paths))
+}
+
+/// Replaces playlist and directory arguments with the tracks they contain
+fn expand_paths(paths: Vec<std::path::PathBuf>) -> Vec<std::path::PathBuf> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            match scan::scan_directory(&path) {
+                Ok(files) => expanded.extend(files),
+                Err(e) => eprintln!("Error scanning directory {:?}: {}", path, e),
+            }
+        } else if playlist::is_playlist(&path) {
+            match playlist::read_playlist(&path) {
+                Ok(entries) => expanded.extend(entries),
+                Err(e) => eprintln!("Error reading playlist {:?}: {}", path, e),
+            }
+        } else {
+            expanded.push(path);
+        }
+    }
+    expanded
+}
+
+/// Expands glob patterns and directories into the decodable files they match.
+/// Patterns are expanded here so they work when quoted or on shells that
+/// leave them alone.
+fn expand_globs(patterns: &[String]) -> Vec<std::path::PathBuf> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let path = std::path::Path::new(pattern);
+        if path.exists() {
+            paths.push(path.to_path_buf());
+            continue;
+        }
+        match glob::glob(pattern) {
+            Ok(matches) => {
+                let before = paths.len();
+                paths.extend(matches.flatten());
+                if paths.len() == before {
+                    eprintln!("No files match {:?}", pattern);
+                }
+            }
+            Err(e) => eprintln!("Invalid pattern {:?}: {}", pattern, e),
+        }
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            match scan::scan_directory(&path) {
+                Ok(found) => files.extend(found),
+                Err(e) => eprintln!("Error scanning directory {:?}: {}", path, e),
+            }
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    files
+}
+
+// Shell Completions
+
+fn handle_completions(shell: Shell) {
+    clap_complete::generate(shell, &mut Cli::command(), "mogbox", &mut std::io::stdout());
+}
+
+// Configuration
+
+/// Defaults from `config.toml` in the config directory, which command line
+/// options override
+#[derive(Debug, Default)]
+struct Config {
+    device: Option<String>,
+    host: Option<HostId>,
+    /// Playback volume from 0 to 1
+    volume: Option<f32>,
+    replaygain: Option<ReplayGainMode>,
+    theme: Option<Theme>,
+    /// What `tui` browses when given no paths
+    library: Vec<std::path::PathBuf>,
+    /// Last.fm credentials for the daemon to scrobble with, from `[lastfm]`
+    lastfm: Option<LastfmConfig>,
+    /// ListenBrainz user token for the daemon to scrobble with, from
+    /// `[listenbrainz]`
+    listenbrainz: Option<String>,
+    /// Client ID of the Discord application the daemon shows what it plays
+    /// as, from `[discord]`
+    discord: Option<String>,
+}
+
+impl Config {
+    /// Reads the config file at `path`; a file that doesn't exist yet
+    /// leaves everything at its default
+    fn load(path: &std::path::Path) -> Result<Config, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                Config::parse(&text).map_err(|e| format!("invalid {}: {}", path.display(), e))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Config, String> {
+        let document: toml_edit::Document = text.parse().map_err(|e| format!("{}", e))?;
+        let mut config = Config::default();
+        for (key, item) in document.iter() {
+            let string = || {
+                item.as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))
+            };
+            match key {
+                "device" => config.device = Some(string()?.to_string()),
+                "host" => config.host = Some(parse_host(string()?)?),
+                "volume" => {
+                    let percent = item
+                        .as_integer()
+                        .map(|percent| percent as f64)
+                        .or_else(|| item.as_float())
+                        .filter(|percent| (0.0..=100.0).contains(percent))
+                        .ok_or("volume must be a number from 0 to 100")?;
+                    config.volume = Some(percent as f32 / 100.0);
+                }
+                "replaygain" => config.replaygain = Some(string()?.parse()?),
+                "theme" => config.theme = Some(parse_theme(string()?)?),
+                "library" => {
+                    let paths = item.as_array().ok_or("library must be a list of paths")?;
+                    for path in paths {
+                        let path = path.as_str().ok_or("library must be a list of paths")?;
+                        config.library.push(expand_home(path));
+                    }
+                }
+                "lastfm" => {
+                    config.lastfm = Some(LastfmConfig {
+                        api_key: table_string(item, "lastfm", "api_key")?,
+                        secret: table_string(item, "lastfm", "secret")?,
+                        session_key: table_string(item, "lastfm", "session_key")?,
+                    })
+                }
+                "listenbrainz" => {
+                    config.listenbrainz = Some(table_string(item, "listenbrainz", "token")?)
+                }
+                "discord" => config.discord = Some(table_string(item, "discord", "client_id")?),
+                _ => return Err(format!("unknown setting: {}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// The string `key` of table `table`
+fn table_string(item: &toml_edit::Item, table: &str, key: &str) -> Result<String, String> {
+    let table_like = item
+        .as_table_like()
+        .ok_or_else(|| format!("{} must be a table", table))?;
+    let value = table_like
+        .get(key)
+        .ok_or_else(|| format!("{} needs {}", table, key))?;
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| format!("{}.{} must be a string", table, key))
+}
+
+/// Where mogbox looks for its settings, under a `mogbox` directory
+fn config_dir() -> Option<std::path::PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config"))
+        })
+        .or_else(|| std::env::var_os("APPDATA").map(std::path::PathBuf::from))
+}
+
+fn config_path() -> Option<std::path::PathBuf> {
+    Some(config_dir()?.join("mogbox").join("config.toml"))
+}
+
+/// The config file's settings, or the defaults when it can't be read
+fn load_config() -> Config {
+    let Some(path) = config_path() else {
+        return Config::default();
+    };
+    Config::load(&path).unwrap_or_else(|e| {
+        eprintln!("Error loading config: {}", e);
+        Config::default()
+    })
+}
+
+/// `path` with a leading `~` standing for the home directory
+fn expand_home(path: &str) -> std::path::PathBuf {
+    match (path.strip_prefix('~'), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            std::path::Path::new(&home).join(rest.trim_start_matches('/'))
+        }
+        _ => path.into(),
+    }
+}
+
+fn handle_config(format: OutputFormat) {
+    let Some(path) = config_path() else {
+        eprintln!("Error loading config: no config directory available");
+        return;
+    };
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error loading config: {}", e);
+            return;
+        }
+    };
+    let volume = config.volume.unwrap_or(1.0);
+    let replaygain = match config.replaygain.unwrap_or_default() {
+        ReplayGainMode::Off => "off",
+        ReplayGainMode::Track => "track",
+        ReplayGainMode::Album => "album",
+    };
+    let theme = config.theme.unwrap_or_default();
+    let mut scrobble = Vec::new();
+    if config.lastfm.is_some() {
+        scrobble.push(ScrobbleService::Lastfm.name());
+    }
+    if config.listenbrainz.is_some() {
+        scrobble.push(ScrobbleService::ListenBrainz.name());
+    }
+
+    if format == OutputFormat::Json {
+        let json = Json::object([
+            ("path", Json::path(&path)),
+            ("exists", path.is_file().into()),
+            ("device", config.device.into()),
+            ("host", config.host.map(|host| host.name()).into()),
+            ("volume", Json::rounded(volume, 2)),
+            ("replaygain", replaygain.into()),
+            ("theme", theme.name().into()),
+            (
+                "library",
+                Json::Array(config.library.iter().map(|path| Json::path(path)).collect()),
+            ),
+            ("scrobble", scrobble.into()),
+            ("discord", config.discord.is_some().into()),
+        ]);
+        println!("{}", json);
+        return;
+    }
+    match path.is_file() {
+        true => println!("Config file: {}", path.display()),
+        false => println!(
+            "Config file: {} (not found, using defaults)",
+            path.display()
+        ),
+    }
+    println!(
+        "  device      {}",
+        config.device.as_deref().unwrap_or("(default)")
+    );
+    println!(
+        "  host        {}",
+        config.host.map_or("(default)", |host| host.name())
+    );
+    println!("  volume      {:.0}%", volume * 100.0);
+    println!("  replaygain  {}", replaygain);
+    println!("  theme       {}", theme.name());
+    match config.library.as_slice() {
+        [] => println!("  library     (current directory)"),
+        library => {
+            let paths: Vec<String> = library
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            println!("  library     {}", paths.join(", "));
+        }
+    }
+    match scrobble.as_slice() {
+        [] => println!("  scrobble    (off)"),
+        services => println!("  scrobble    {}", services.join(", ")),
+    }
+    match config.discord.is_some() {
+        true => println!("  discord     on"),
+        false => println!("  discord     (off)"),
+    }
+}
+
+// Session State
+
+/// Where mogbox keeps what it remembers between runs, under a `mogbox` directory
+fn state_dir() -> Option<std::path::PathBuf> {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".local/state"))
+        })
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(std::path::PathBuf::from))
+}
+
+/// Where mogbox keeps files it downloads, under a `mogbox` directory
+fn data_dir() -> Option<std::path::PathBuf> {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".local/share"))
+        })
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(std::path::PathBuf::from))
+}
+
+/// Where the queue of the running (or last) play session is kept
+fn session_queue_path() -> Option<std::path::PathBuf> {
+    Some(state_dir()?.join("mogbox").join("queue.m3u8"))
+}
+
+fn save_session_queue(queue: &PlaybackQueue) {
+    let Some(path) = session_queue_path() else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Err(e) = queue.save(&path) {
+        eprintln!("Error saving session queue: {}", e);
+    }
+}
+
+// Argument Parsers
+
+/// Parses durations like `90`, `5s`, `250ms`, `30m`, `1h`, `1:23.5` or `1:02:03`
+fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let value = value.trim();
+    let invalid = || format!("invalid duration: {}", value);
+
+    let seconds = if value.contains(':') {
+        value.split(':').try_fold(0.0f64, |acc, part| {
+            part.parse::<f64>()
+                .map(|part| acc * 60.0 + part)
+                .map_err(|_| invalid())
+        })?
+    } else if let Some(ms) = value.strip_suffix("ms") {
+        ms.parse::<f64>().map_err(|_| invalid())? / 1000.0
+    } else if let Some(s) = value.strip_suffix('s') {
+        s.parse::<f64>().map_err(|_| invalid())?
+    } else if let Some(m) = value.strip_suffix('m') {
+        m.parse::<f64>().map_err(|_| invalid())? * 60.0
+    } else if let Some(h) = value.strip_suffix('h') {
+        h.parse::<f64>().map_err(|_| invalid())? * 3600.0
+    } else if let Some(d) = value.strip_suffix('d') {
+        d.parse::<f64>().map_err(|_| invalid())? * 86_400.0
+    } else {
+        value.parse::<f64>().map_err(|_| invalid())?
+    };
+
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(invalid());
+    }
+    Ok(std::time::Duration::from_secs_f64(seconds))
+}
+
+/// Parses a time of day such as `07:00` or `6:45:30` into hours, minutes
+/// and seconds
+fn parse_time_of_day(value: &str) -> Result<(u32, u32, u32), String> {
+    let invalid = || format!("invalid time of day: {}", value);
+    let fields: Vec<u32> = value
+        .trim()
+        .split(':')
+        .map(|field| field.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    match fields[..] {
+        [hour, minute] if hour < 24 && minute < 60 => Ok((hour, minute, 0)),
+        [hour, minute, second] if hour < 24 && minute < 60 && second < 60 => {
+            Ok((hour, minute, second))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// The stretch `play --loop` repeats: the track's loop points unless given,
+/// with `Duration::MAX` for its end
+fn track_loop(
+    args: &PlayArgs,
+    path: &std::path::Path,
+    file: Option<&AudioFile>,
+) -> (std::time::Duration, std::time::Duration) {
+    let points = file.and_then(|file| {
+        let points = LoopPoints::read(path, &file.tags)?;
+        let time = |frame: u64| {
+            std::time::Duration::from_secs_f64(frame as f64 / file.sample_rate.max(1) as f64)
+        };
+        Some((time(points.start), points.end.map(time)))
+    });
+    let (start, end) = points.unwrap_or_default();
+    (
+        args.loop_start.unwrap_or(start),
+        args.loop_end.or(end).unwrap_or(std::time::Duration::MAX),
+    )
+}
+
+/// Parses a stretch of a track such as `1:05-1:32`
+fn parse_ab_loop(value: &str) -> Result<(std::time::Duration, std::time::Duration), String> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| format!("invalid loop: {}, expected A-B", value))?;
+    let (start, end) = (parse_duration(start)?, parse_duration(end)?);
+    if end <= start {
+        return Err(format!(
+            "invalid loop: {} doesn't end after it starts",
+            value
+        ));
+    }
+    Ok((start, end))
+}
+
+/// Parses a level such as `-40dB` or `-40`
+fn parse_db(value: &str) -> Result<f32, String> {
+    let number = value.trim();
+    let number = number
+        .strip_suffix("dB")
+        .or(number.strip_suffix("db"))
+        .unwrap_or(number);
+    number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid level: {}", value))
+}
+
+/// Parses a true-peak level such as `-1dBTP`, `-1dB` or `-1`
+fn parse_dbtp(value: &str) -> Result<f32, String> {
+    let number = value.trim();
+    let number = number
+        .strip_suffix("dBTP")
+        .or(number.strip_suffix("dbtp"))
+        .unwrap_or(number);
+    parse_db(number).map_err(|_| format!("invalid true-peak level: {}", value))
+}
+
+fn parse_peak_bits(value: &str) -> Result<PeakBits, String> {
+    let bits: u8 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid peak resolution: {}", value))?;
+    PeakBits::try_from(bits)
+}
+
+/// Parses a loudness such as `-16LUFS` or `-16`
+fn parse_lufs(value: &str) -> Result<f32, String> {
+    let number = value.trim();
+    let number = number
+        .strip_suffix("LUFS")
+        .or(number.strip_suffix("lufs"))
+        .unwrap_or(number);
+    number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid loudness: {}", value))
+}
+
+/// Parses `--format`, `text` or `json`
+fn parse_output_format(value: &str) -> Result<OutputFormat, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "text" => Ok(OutputFormat::Text),
+        "json" => Ok(OutputFormat::Json),
+        _ => Err(format!(
+            "invalid output format: {} (expected text or json)",
+            value
+        )),
+    }
+}
+
+/// Parses a volume in percent such as `80` or `80%` into a factor
+fn parse_volume(value: &str) -> Result<f32, String> {
+    let number = value.trim();
+    let number = number.strip_suffix('%').unwrap_or(number);
+    number
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|percent| (0.0..=100.0).contains(percent))
+        .map(|percent| percent / 100.0)
+        .ok_or_else(|| format!("invalid volume: {} (expected 0 to 100)", value))
+}
+
+fn parse_theme(value: &str) -> Result<Theme, String> {
+    let names: Vec<&str> = Theme::ALL.iter().map(|theme| theme.name()).collect();
+    Theme::ALL
+        .into_iter()
+        .find(|theme| theme.name().eq_ignore_ascii_case(value.trim()))
+        .ok_or_else(|| format!("invalid theme: {} (expected {})", value, names.join(", ")))
+}
+
+fn parse_shell(value: &str) -> Result<Shell, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "bash" => Ok(Shell::Bash),
+        "zsh" => Ok(Shell::Zsh),
+        "fish" => Ok(Shell::Fish),
+        "powershell" | "pwsh" => Ok(Shell::PowerShell),
+        "elvish" => Ok(Shell::Elvish),
+        _ => Err(format!(
+            "unsupported shell: {} (expected bash, zsh, fish, powershell or elvish)",
+            value
+        )),
+    }
+}
+
+fn parse_events(value: &str) -> Result<EventFormat, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "ndjson" => Ok(EventFormat::Ndjson),
+        _ => Err(format!(
+            "unsupported event format: {} (expected ndjson)",
+            value
+        )),
+    }
+}
+
+fn parse_visualization(value: &str) -> Result<Visualization, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "spectrum" => Ok(Visualization::Spectrum),
+        "meter" | "vu" => Ok(Visualization::Meter),
+        "phase" | "correlation" | "goniometer" => Ok(Visualization::Phase),
+        _ => Err(format!(
+            "invalid visualization: {} (expected spectrum, meter or phase)",
+            value
+        )),
+    }
+}
+
+/// A bare number is a frame count, anything with a unit is a latency
+fn parse_buffer(value: &str) -> Result<BufferSize, String> {
+    match value.trim().parse::<u32>() {
+        Ok(0) => Err(format!("invalid buffer size: {}", value)),
+        Ok(frames) => Ok(BufferSize::Frames(frames)),
+        Err(_) => parse_duration(value).map(BufferSize::Latency),
+    }
+}
+
+/// The `--format` of the commands that can report with `--format json`
+fn output_args(command: &Commands) -> Option<&OutputArgs> {
+    match command {
+        Commands::Info { output, .. }
+        | Commands::Loudness { output, .. }
+        | Commands::Stats { output, .. }
+        | Commands::Dr { output, .. }
+        | Commands::Config { output } => Some(output),
+        Commands::Analyze(args) => Some(&args.output),
+        Commands::Bpm(args) => Some(&args.output),
+        Commands::Key(args) => Some(&args.output),
+        Commands::Fingerprint(args) => Some(&args.output),
+        Commands::History(args) => Some(&args.output),
+        Commands::Ctl(args) => Some(&args.output),
+        _ => None,
+    }
+}
+
+/// Decodes `%XX` escapes, as in URLs
+#[cfg(any(
+    feature = "http",
+    all(feature = "mpris", unix, not(target_os = "macos"))
+))]
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Display Utils
+/// Prints a status line, on stderr when stdout carries JSON
+fn print_status(format: OutputFormat, message: &str) {
+    match format {
+        OutputFormat::Text => println!("{}", message),
+        OutputFormat::Json => eprintln!("{}", message),
+    }
+}
+
+fn print_intro(command: &Commands, format: OutputFormat) {
+    // Machine-readable output goes to stdout untouched
+    if format == OutputFormat::Json
+        || matches!(
+            command,
+            Commands::Decode(_)
+                | Commands::Fingerprint(_)
+                | Commands::Completions { .. }
+                | Commands::Ctl(_)
+        )
+        || matches!(command, Commands::Play(args) if args.events.is_some())
+    {
+        return;
+    }
+    println!("==================");
+    println!("<<< MogBox CLI >>>");
+    println!("==================\n");
+
+    println!("Command: {:?}", command);
+}
+
+fn print_stats(stats: &PlayerStats) {
+    println!(
+        "Underruns: {} ({:.1} ms of silence), buffer {:.0}/{:.0} ms",
+        stats.underruns,
+        stats.underrun_time.as_secs_f64() * 1000.0,
+        stats.buffered.as_secs_f64() * 1000.0,
+        stats.buffer_capacity.as_secs_f64() * 1000.0
+    );
+}
+
+/// Formats a position as `m:ss.mmm`
+fn format_time(time: std::time::Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// A time such as `4:05`, or `1:02:03` from an hour on
+fn format_clock(time: std::time::Duration) -> String {
+    let seconds = time.as_secs();
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+/// A UTC date and time such as `2024-03-09 18:04:12Z`
+fn format_timestamp(time: std::time::SystemTime) -> String {
+    let seconds = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // Days to a civil date, from Howard Hinnant's `civil_from_days`
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn print_read_file(path: &std::path::PathBuf) {
+    println!("Reading File: {:?}", path)
+}
+
+fn print_player_errors(player: &AudioPlayer) {
+    for (path, e) in player.take_errors() {
+        eprintln!("Error playing {:?}: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(args: &[&str]) -> Result<PlayArgs, clap::Error> {
+        let args = ["mogbox", "play"].iter().chain(args);
+        match Cli::try_parse_from(args)?.command {
+            Commands::Play(args) => Ok(args),
+            command => panic!("parsed as {:?}", command),
+        }
+    }
+
+    #[test]
+    fn arguments_are_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn levels_take_negative_values() {
+        let args = play(&["--limit", "-3dBTP", "a.flac"]).unwrap();
+        assert_eq!(args.limit, Some(-3.0));
+        assert_eq!(args.paths, [std::path::PathBuf::from("a.flac")]);
+        let args = play(&["--skip-silence", "-40dB", "a.flac"]).unwrap();
+        assert_eq!(args.skip_silence, Some(-40.0));
+        let args = play(&["--limit=-0.5", "a.flac", "--skip-silence"]).unwrap();
+        assert_eq!(args.limit, Some(-0.5));
+        assert_eq!(args.skip_silence, Some(-50.0));
+    }
+
+    #[test]
+    fn levels_are_optional() {
+        let args = play(&["a.flac", "--limit"]).unwrap();
+        assert_eq!(args.limit, Some(-1.0));
+        let args = play(&["--skip-silence=-45", "a.flac", "--limit"]).unwrap();
+        assert_eq!(args.skip_silence, Some(-45.0));
+        // Anything right after the flag is taken for its level
+        let error = play(&["--limit", "--shuffle", "a.flac"]).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn history_limit_stays_a_count() {
+        let history = |limit| Cli::try_parse_from(["mogbox", "history", "--limit", limit]);
+        assert!(history("5").is_ok());
+        assert!(history("-5").is_err());
+    }
 }
@@ -0,0 +1,1155 @@
+// MPD protocol
+//
+// With `daemon --mpd ADDRESS`, the daemon also speaks enough of the Music
+// Player Daemon protocol for MPD clients such as ncmpcpp and MALP to control
+// playback, edit the queue and browse the library. Songs are known by their
+// place in the queue, which doubles as their ID, and files by their path under
+// the first library directory of the config. Clients are served from the
+// daemon's loop, without threads of their own.
+
+use mogbox_io::scan;
+use mogbox_runtime::{AudioPlayer, RepeatMode, ReplayGainConfig, ReplayGainMode};
+
+use crate::daemon::{Daemon, TrackTags};
+use crate::{expand_paths, remove_queued};
+
+/// Protocol version MPD clients are greeted with
+const MPD_VERSION: &str = "0.23.0";
+/// Longest command line taken from an MPD client
+const MPD_MAX_LINE: usize = 64 * 1024;
+/// `ACK` code for bad arguments
+const MPD_ERROR_ARG: u32 = 2;
+/// `ACK` code for unknown commands
+const MPD_ERROR_UNKNOWN: u32 = 5;
+/// `ACK` code for songs and files that don't exist
+const MPD_ERROR_NO_EXIST: u32 = 50;
+/// `ACK` code for the player failing
+const MPD_ERROR_SYSTEM: u32 = 52;
+/// `ACK` code for commands that need something playing
+const MPD_ERROR_PLAYER_SYNC: u32 = 55;
+/// Tags given in song info, as `tagtypes` lists them
+const MPD_TAG_TYPES: [&str; 7] = [
+    "Artist",
+    "AlbumArtist",
+    "Title",
+    "Album",
+    "Track",
+    "Date",
+    "Genre",
+];
+/// Commands understood, as `commands` lists them
+const MPD_COMMANDS: &[&str] = &[
+    "add",
+    "addid",
+    "clear",
+    "clearerror",
+    "close",
+    "command_list_begin",
+    "command_list_end",
+    "command_list_ok_begin",
+    "commands",
+    "consume",
+    "crossfade",
+    "currentsong",
+    "decoders",
+    "delete",
+    "deleteid",
+    "getvol",
+    "idle",
+    "listall",
+    "listallinfo",
+    "listplaylists",
+    "lsinfo",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "password",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlist",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "plchangesposid",
+    "previous",
+    "random",
+    "repeat",
+    "replay_gain_mode",
+    "replay_gain_status",
+    "rescan",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "update",
+    "urlhandlers",
+    "volume",
+];
+
+/// Why an MPD command failed, sent back in an `ACK` line
+struct MpdError {
+    code: u32,
+    message: String,
+}
+
+impl MpdError {
+    pub(crate) fn new(code: u32, message: impl Into<String>) -> Self {
+        MpdError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// A connection from an MPD client, read from and written to without blocking
+struct MpdClient {
+    stream: std::net::TcpStream,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    /// Subsystems the client waits on with `idle`, any of them when empty
+    idle: Option<Vec<String>>,
+    /// Subsystems changed since the client last heard about them
+    changed: std::collections::BTreeSet<&'static str>,
+    /// Commands of an unfinished command list, and whether each gets a `list_OK`
+    list: Option<(Vec<String>, bool)>,
+    closed: bool,
+}
+
+impl MpdClient {
+    pub(crate) fn new(stream: std::net::TcpStream) -> Option<Self> {
+        stream.set_nonblocking(true).ok()?;
+        Some(MpdClient {
+            stream,
+            input: Vec::new(),
+            output: format!("OK MPD {}\n", MPD_VERSION).into_bytes(),
+            idle: None,
+            changed: std::collections::BTreeSet::new(),
+            list: None,
+            closed: false,
+        })
+    }
+
+    /// Takes in whatever the client sent since the last call
+    fn read(&mut self) {
+        use std::io::Read;
+
+        let mut buffer = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(count) => {
+                    self.input.extend_from_slice(&buffer[..count]);
+                    continue;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(_) => self.closed = true,
+            }
+            break;
+        }
+        if self.input.len() > MPD_MAX_LINE && !self.input.contains(&b'\n') {
+            self.closed = true;
+        }
+    }
+
+    /// The next complete line the client sent, without the line ending
+    fn next_line(&mut self) -> Option<String> {
+        let end = self.input.iter().position(|&byte| byte == b'\n')?;
+        let line: Vec<u8> = self.input.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line[..end]);
+        Some(line.trim_end_matches('\r').to_string())
+    }
+
+    /// Sends as much of the output as the connection takes
+    fn write(&mut self) {
+        use std::io::Write;
+
+        while !self.output.is_empty() {
+            match self.stream.write(&self.output) {
+                Ok(0) => self.closed = true,
+                Ok(count) => {
+                    self.output.drain(..count);
+                    continue;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(_) => self.closed = true,
+            }
+            break;
+        }
+    }
+
+    /// Adds the reply to the command at `index` of a list, without the final
+    /// `OK`; false when it failed, which ends the list
+    fn reply(&mut self, index: usize, command: &str, reply: Result<String, MpdError>) -> bool {
+        match reply {
+            Ok(body) => {
+                self.output.extend_from_slice(body.as_bytes());
+                true
+            }
+            Err(e) => {
+                let ack = format!("ACK [{}@{}] {{{}}} {}\n", e.code, index, command, e.message);
+                self.output.extend_from_slice(ack.as_bytes());
+                false
+            }
+        }
+    }
+
+    /// Ends `idle` once a subsystem it waits on changed, or right away with
+    /// `now`, as for `noidle`
+    fn answer_idle(&mut self, now: bool) {
+        let Some(wanted) = &self.idle else {
+            return;
+        };
+        let changed: Vec<&'static str> = self
+            .changed
+            .iter()
+            .copied()
+            .filter(|subsystem| wanted.is_empty() || wanted.iter().any(|name| name == subsystem))
+            .collect();
+        if changed.is_empty() && !now {
+            return;
+        }
+        for subsystem in changed {
+            self.changed.remove(subsystem);
+            self.output
+                .extend_from_slice(format!("changed: {}\n", subsystem).as_bytes());
+        }
+        self.output.extend_from_slice(b"OK\n");
+        self.idle = None;
+    }
+}
+
+/// What MPD clients are told about through `idle` when it changes
+#[derive(PartialEq)]
+struct MpdSnapshot {
+    /// State and current track
+    player: (&'static str, Option<usize>),
+    volume: u32,
+    /// Repeat, shuffle, single, crossfade and ReplayGain
+    options: (RepeatMode, bool, bool, std::time::Duration, ReplayGainMode),
+    queue: Vec<std::path::PathBuf>,
+}
+
+/// The MPD side of the daemon
+pub(crate) struct MpdServer {
+    connections: std::sync::mpsc::Receiver<std::net::TcpStream>,
+    clients: Vec<MpdClient>,
+    /// Where relative paths from clients start
+    music_dir: std::path::PathBuf,
+    last: Option<MpdSnapshot>,
+    /// Counts changes to the queue, as MPD's playlist version
+    playlist_version: u32,
+    /// Single mode, which repeats the current track while repeat is on
+    single: bool,
+    /// Tags of the files clients were told about
+    tags: std::collections::HashMap<std::path::PathBuf, TrackTags>,
+    started: std::time::Instant,
+}
+
+impl MpdServer {
+    pub(crate) fn new(
+        connections: std::sync::mpsc::Receiver<std::net::TcpStream>,
+        music_dir: std::path::PathBuf,
+    ) -> Self {
+        MpdServer {
+            connections,
+            clients: Vec::new(),
+            music_dir,
+            last: None,
+            playlist_version: 1,
+            single: false,
+            tags: std::collections::HashMap::new(),
+            started: std::time::Instant::now(),
+        }
+    }
+
+    /// Notes the subsystems that changed since the last call for every client
+    fn notice_changes(&mut self, player: &AudioPlayer) {
+        let snapshot = {
+            let queue = player.queue();
+            let queue = queue.lock().unwrap();
+            MpdSnapshot {
+                player: (mpd_state(player), player.current_track()),
+                volume: (player.volume() * 100.0).round() as u32,
+                options: (
+                    queue.repeat(),
+                    queue.shuffle(),
+                    self.single,
+                    player.crossfade(),
+                    player.replaygain().mode,
+                ),
+                queue: queue.items().to_vec(),
+            }
+        };
+        if let Some(last) = &self.last {
+            let mut changed = Vec::new();
+            if last.player != snapshot.player {
+                changed.push("player");
+            }
+            if last.volume != snapshot.volume {
+                changed.push("mixer");
+            }
+            if last.options != snapshot.options {
+                changed.push("options");
+            }
+            if last.queue != snapshot.queue {
+                changed.push("playlist");
+                self.playlist_version += 1;
+            }
+            for client in &mut self.clients {
+                client.changed.extend(&changed);
+            }
+        }
+        self.last = Some(snapshot);
+    }
+
+    /// The file or directory a client means by `uri`: a path under the music
+    /// directory, or an absolute one
+    fn path(&self, uri: &str) -> Result<std::path::PathBuf, MpdError> {
+        let path = std::path::Path::new(uri.strip_prefix("file://").unwrap_or(uri));
+        if path.is_absolute() {
+            return Ok(path.to_path_buf());
+        }
+        let inside = path.components().all(|component| {
+            matches!(
+                component,
+                std::path::Component::Normal(_) | std::path::Component::CurDir
+            )
+        });
+        match inside {
+            true => Ok(self.music_dir.join(path)),
+            false => Err(MpdError::new(
+                MPD_ERROR_NO_EXIST,
+                format!("no such file or directory: {}", uri),
+            )),
+        }
+    }
+
+    /// How clients are shown `path`: relative to the music directory when
+    /// it's in there
+    fn uri(&self, path: &std::path::Path) -> String {
+        let uri = match path.strip_prefix(&self.music_dir) {
+            Ok(relative) => relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            Err(_) => path.to_string_lossy().into_owned(),
+        };
+        mpd_value(&uri)
+    }
+
+    /// Song info lines for `path`, with its place in the queue when it has one
+    fn song(&mut self, path: &std::path::PathBuf, position: Option<usize>) -> String {
+        let mut lines = format!("file: {}\n", self.uri(path));
+        let tags = self
+            .tags
+            .entry(path.clone())
+            .or_insert_with(|| TrackTags::read(path));
+        let values = [
+            tags.artist.clone(),
+            tags.album_artist.clone(),
+            tags.title.clone(),
+            tags.album.clone(),
+            tags.track.map(|track| track.to_string()),
+            tags.date.clone(),
+            tags.genre.clone(),
+        ];
+        for (name, value) in MPD_TAG_TYPES.iter().zip(values) {
+            if let Some(value) = value {
+                lines.push_str(&format!("{}: {}\n", name, mpd_value(&value)));
+            }
+        }
+        if let Some(duration) = tags.duration {
+            let seconds = duration.as_secs_f64();
+            lines.push_str(&format!(
+                "Time: {}\nduration: {:.3}\n",
+                seconds.round(),
+                seconds
+            ));
+        }
+        if let Some(position) = position {
+            lines.push_str(&format!("Pos: {}\nId: {}\n", position, position));
+        }
+        lines
+    }
+
+    /// `lsinfo` of `uri`: the subdirectories and songs of a directory, or a song
+    fn lsinfo(&mut self, uri: &str) -> Result<String, MpdError> {
+        let path = self.path(uri)?;
+        if path.is_file() {
+            return Ok(self.song(&path, None));
+        }
+        let mut lines = String::new();
+        for (path, directory) in mpd_entries(&path)? {
+            match directory {
+                true => lines.push_str(&format!("directory: {}\n", self.uri(&path))),
+                false => lines.push_str(&self.song(&path, None)),
+            }
+        }
+        Ok(lines)
+    }
+
+    /// `listall` of a directory, with song info for `listallinfo`
+    fn list_all(
+        &mut self,
+        dir: &std::path::Path,
+        info: bool,
+        lines: &mut String,
+    ) -> Result<(), MpdError> {
+        for (path, directory) in mpd_entries(dir)? {
+            match (directory, info) {
+                (true, _) => {
+                    lines.push_str(&format!("directory: {}\n", self.uri(&path)));
+                    // An unreadable directory doesn't spoil the rest of the listing
+                    let _ = self.list_all(&path, info, lines);
+                }
+                (false, true) => lines.push_str(&self.song(&path, None)),
+                (false, false) => lines.push_str(&format!("file: {}\n", self.uri(&path))),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Daemon {
+    /// Takes in new MPD clients, carries out what they sent and tells those
+    /// waiting in `idle` what changed
+    pub(crate) fn serve_mpd(&mut self) {
+        let Some(mut mpd) = self.mpd.take() else {
+            return;
+        };
+        while let Ok(stream) = mpd.connections.try_recv() {
+            mpd.clients.extend(MpdClient::new(stream));
+        }
+        let (track, position) = (self.player.current_track(), self.player.position());
+        let mut clients = std::mem::take(&mut mpd.clients);
+        for client in &mut clients {
+            client.read();
+            while !client.closed {
+                let Some(line) = client.next_line() else {
+                    break;
+                };
+                self.mpd_line(&mut mpd, client, &line);
+            }
+        }
+        self.left_track(track, position);
+        mpd.clients = clients;
+        mpd.notice_changes(&self.player);
+        for client in &mut mpd.clients {
+            client.answer_idle(false);
+            client.write();
+        }
+        mpd.clients.retain(|client| !client.closed);
+        self.mpd = Some(mpd);
+    }
+
+    /// Carries out a line from an MPD client, or adds it to a command list
+    fn mpd_line(&mut self, mpd: &mut MpdServer, client: &mut MpdClient, line: &str) {
+        // A client waiting in `idle` may only end it
+        if client.idle.is_some() {
+            match line.trim() {
+                "noidle" => client.answer_idle(true),
+                _ => client.closed = true,
+            }
+            return;
+        }
+        if let Some((commands, _)) = &mut client.list {
+            if line.trim() != "command_list_end" {
+                commands.push(line.to_string());
+                return;
+            }
+        }
+        if let Some((commands, list_ok)) = client.list.take() {
+            for (index, line) in commands.iter().enumerate() {
+                let args = mpd_args(line);
+                let command = args.as_ref().ok().and_then(|args| args.first().cloned());
+                let reply = args.and_then(|args| self.mpd_command(mpd, &args));
+                if !client.reply(index, &command.unwrap_or_default(), reply) {
+                    return;
+                }
+                if list_ok {
+                    client.output.extend_from_slice(b"list_OK\n");
+                }
+            }
+            client.output.extend_from_slice(b"OK\n");
+            return;
+        }
+        let args = match mpd_args(line) {
+            Ok(args) => args,
+            Err(e) => {
+                client.reply(0, "", Err(e));
+                return;
+            }
+        };
+        match args.first().map(String::as_str) {
+            Some("command_list_begin") => client.list = Some((Vec::new(), false)),
+            Some("command_list_ok_begin") => client.list = Some((Vec::new(), true)),
+            Some("idle") => client.idle = Some(args[1..].to_vec()),
+            // Outside `idle` there's nothing to end
+            Some("noidle") => {}
+            Some("close") => client.closed = true,
+            _ => {
+                let reply = self.mpd_command(mpd, &args);
+                if client.reply(0, &args.first().cloned().unwrap_or_default(), reply) {
+                    client.output.extend_from_slice(b"OK\n");
+                }
+            }
+        }
+    }
+
+    /// Carries out an MPD command, returning the lines of the reply
+    fn mpd_command(&mut self, mpd: &mut MpdServer, args: &[String]) -> Result<String, MpdError> {
+        let Some((command, _)) = args.split_first() else {
+            return Err(MpdError::new(MPD_ERROR_UNKNOWN, "no command given"));
+        };
+        let system = |e: String| MpdError::new(MPD_ERROR_SYSTEM, e);
+        let queue = self.player.queue();
+        let len = queue.lock().unwrap().len();
+        match command.as_str() {
+            "status" => return Ok(self.mpd_status(mpd)),
+            "currentsong" => {
+                let current = self
+                    .player
+                    .current_track()
+                    .filter(|_| self.player.is_playing());
+                let path = current.and_then(|index| queue.lock().unwrap().get(index).cloned());
+                if let Some(path) = path {
+                    return Ok(mpd.song(&path, current));
+                }
+            }
+            "stats" => return Ok(format!("uptime: {}\n", mpd.started.elapsed().as_secs())),
+            "play" | "playid" => match args.get(1) {
+                Some(_) => {
+                    let index = mpd_queue_index(args, 1, len)?;
+                    self.player.play_from(index);
+                    if self.player.is_paused() {
+                        self.player.resume().map_err(system)?;
+                    }
+                }
+                None => {
+                    self.play(&[]).map_err(system)?;
+                }
+            },
+            "pause" => {
+                let pause = match args.get(1) {
+                    Some(_) => mpd_flag(args, 1)?,
+                    None => !self.player.is_paused(),
+                };
+                match (pause, self.player.is_paused()) {
+                    (true, false) if self.player.is_playing() => {
+                        self.player.pause().map_err(system)?
+                    }
+                    (false, true) => self.player.resume().map_err(system)?,
+                    _ => {}
+                }
+            }
+            "stop" => self.player.stop(),
+            "next" => {
+                self.player.next_track();
+            }
+            "previous" => {
+                self.player.previous_track();
+            }
+            "seek" | "seekid" => {
+                let index = mpd_queue_index(args, 1, len)?;
+                let position = mpd_time(args, 2)?;
+                self.mpd_seek(mpd, index, position)?;
+            }
+            "seekcur" => {
+                let index = self
+                    .player
+                    .current_track()
+                    .filter(|_| self.player.is_playing())
+                    .ok_or_else(|| MpdError::new(MPD_ERROR_PLAYER_SYNC, "not playing"))?;
+                let arg = args.get(1).map(String::as_str).unwrap_or_default();
+                let current = self.player.position();
+                let position = match arg.chars().next() {
+                    Some('+') => current + mpd_time(args, 1)?,
+                    Some('-') => {
+                        let back = mpd_time(&[String::new(), arg[1..].to_string()], 1)?;
+                        current.saturating_sub(back)
+                    }
+                    _ => mpd_time(args, 1)?,
+                };
+                self.mpd_seek(mpd, index, position)?;
+            }
+            "setvol" => {
+                let volume: u32 = mpd_arg(args, 1)?;
+                if volume > 100 {
+                    return Err(MpdError::new(MPD_ERROR_ARG, "invalid volume value"));
+                }
+                self.player.set_volume(volume as f32 / 100.0);
+            }
+            "volume" => {
+                let change: i32 = mpd_arg(args, 1)?;
+                let volume = (self.player.volume() * 100.0).round() as i32 + change;
+                self.player.set_volume(volume.clamp(0, 100) as f32 / 100.0);
+            }
+            "getvol" => return Ok(format!("volume: {:.0}\n", self.player.volume() * 100.0)),
+            "repeat" => {
+                let repeat = match (mpd_flag(args, 1)?, mpd.single) {
+                    (false, _) => RepeatMode::Off,
+                    (true, false) => RepeatMode::Queue,
+                    (true, true) => RepeatMode::Track,
+                };
+                queue.lock().unwrap().set_repeat(repeat);
+            }
+            "single" => {
+                if args.get(1).is_some_and(|arg| arg == "oneshot") {
+                    return Err(MpdError::new(MPD_ERROR_ARG, "oneshot is not supported"));
+                }
+                mpd.single = mpd_flag(args, 1)?;
+                let mut queue = queue.lock().unwrap();
+                match (queue.repeat(), mpd.single) {
+                    (RepeatMode::Queue, true) => queue.set_repeat(RepeatMode::Track),
+                    (RepeatMode::Track, false) => queue.set_repeat(RepeatMode::Queue),
+                    _ => {}
+                }
+            }
+            "random" => {
+                let shuffle = mpd_flag(args, 1)?;
+                queue.lock().unwrap().set_shuffle(shuffle);
+            }
+            "consume" => {
+                if mpd_flag(args, 1)? {
+                    return Err(MpdError::new(MPD_ERROR_ARG, "consume is not supported"));
+                }
+            }
+            "crossfade" => {
+                let seconds: u64 = mpd_arg(args, 1)?;
+                self.player
+                    .set_crossfade(std::time::Duration::from_secs(seconds));
+            }
+            "replay_gain_mode" => {
+                let mode = match args.get(1).map(String::as_str) {
+                    Some("off") => ReplayGainMode::Off,
+                    Some("track") => ReplayGainMode::Track,
+                    Some("album") => ReplayGainMode::Album,
+                    _ => return Err(MpdError::new(MPD_ERROR_ARG, "expected off, track or album")),
+                };
+                self.player.set_replaygain(ReplayGainConfig {
+                    mode,
+                    ..self.player.replaygain()
+                });
+            }
+            "replay_gain_status" => {
+                let mode = match self.player.replaygain().mode {
+                    ReplayGainMode::Off => "off",
+                    ReplayGainMode::Track => "track",
+                    ReplayGainMode::Album => "album",
+                };
+                return Ok(format!("replay_gain_mode: {}\n", mode));
+            }
+            "add" | "addid" => {
+                let uri = args
+                    .get(1)
+                    .ok_or_else(|| MpdError::new(MPD_ERROR_ARG, "add needs a URI"))?;
+                let path = mpd.path(uri)?;
+                if !path.exists() {
+                    return Err(MpdError::new(
+                        MPD_ERROR_NO_EXIST,
+                        format!("no such file or directory: {}", uri),
+                    ));
+                }
+                if command == "add" {
+                    queue.lock().unwrap().enqueue_all(expand_paths(vec![path]));
+                    return Ok(String::new());
+                }
+                if !path.is_file() {
+                    return Err(MpdError::new(MPD_ERROR_ARG, "addid takes a single song"));
+                }
+                if args.get(2).is_some() {
+                    return Err(MpdError::new(
+                        MPD_ERROR_ARG,
+                        "adding at a position is not supported",
+                    ));
+                }
+                queue.lock().unwrap().enqueue_all(vec![path]);
+                return Ok(format!("Id: {}\n", len));
+            }
+            "delete" => {
+                let range = mpd_queue_range(args.get(1), len)?;
+                for index in range.rev() {
+                    remove_queued(&mut self.player, index);
+                }
+            }
+            "deleteid" => {
+                let index = mpd_queue_index(args, 1, len)?;
+                remove_queued(&mut self.player, index);
+            }
+            "clear" => {
+                self.player.stop();
+                queue.lock().unwrap().clear();
+            }
+            "playlist" => {
+                let items = queue.lock().unwrap().items().to_vec();
+                let lines = items
+                    .iter()
+                    .enumerate()
+                    .map(|(index, path)| format!("{}:file: {}\n", index, mpd.uri(path)));
+                return Ok(lines.collect());
+            }
+            "playlistinfo" | "playlistid" | "plchanges" => {
+                let range = match command.as_str() {
+                    "playlistid" if args.get(1).is_some() => {
+                        let index = mpd_queue_index(args, 1, len)?;
+                        index..index + 1
+                    }
+                    "playlistid" => 0..len,
+                    "playlistinfo" => mpd_queue_range(args.get(1), len)?,
+                    _ => {
+                        if mpd_arg::<u32>(args, 1)? == mpd.playlist_version {
+                            return Ok(String::new());
+                        }
+                        mpd_queue_range(args.get(2), len)?
+                    }
+                };
+                let items = queue.lock().unwrap().items().to_vec();
+                let songs = range.map(|index| mpd.song(&items[index], Some(index)));
+                return Ok(songs.collect());
+            }
+            "plchangesposid" => {
+                if mpd_arg::<u32>(args, 1)? == mpd.playlist_version {
+                    return Ok(String::new());
+                }
+                let range = mpd_queue_range(args.get(2), len)?;
+                return Ok(range
+                    .map(|index| format!("cpos: {}\nId: {}\n", index, index))
+                    .collect());
+            }
+            "lsinfo" => return mpd.lsinfo(args.get(1).map(String::as_str).unwrap_or_default()),
+            "listall" | "listallinfo" => {
+                let uri = args.get(1).map(String::as_str).unwrap_or_default();
+                let path = mpd.path(uri)?;
+                if path.is_file() {
+                    return Ok(match command == "listall" {
+                        true => format!("file: {}\n", mpd.uri(&path)),
+                        false => mpd.song(&path, None),
+                    });
+                }
+                let mut lines = String::new();
+                mpd.list_all(&path, command == "listallinfo", &mut lines)?;
+                return Ok(lines);
+            }
+            // Files are read again as clients look at them
+            "update" | "rescan" => {
+                mpd.tags.clear();
+                return Ok("updating_db: 1\n".to_string());
+            }
+            "outputs" => {
+                return Ok(
+                    "outputid: 0\noutputname: mogbox\nplugin: mogbox\noutputenabled: 1\n"
+                        .to_string(),
+                )
+            }
+            "commands" => {
+                let lines = MPD_COMMANDS
+                    .iter()
+                    .map(|name| format!("command: {}\n", name));
+                return Ok(lines.collect());
+            }
+            "tagtypes" if args.len() == 1 => {
+                let lines = MPD_TAG_TYPES
+                    .iter()
+                    .map(|name| format!("tagtype: {}\n", name));
+                return Ok(lines.collect());
+            }
+            "decoders" => {
+                let suffixes = scan::SUPPORTED_EXTENSIONS
+                    .iter()
+                    .chain(scan::FFMPEG_EXTENSIONS)
+                    .map(|suffix| format!("suffix: {}\n", suffix));
+                return Ok(std::iter::once("plugin: mogbox\n".to_string())
+                    .chain(suffixes)
+                    .collect());
+            }
+            "urlhandlers" => return Ok("handler: file://\n".to_string()),
+            // Every tag is always sent, whichever ones a client asks for
+            "tagtypes" | "listplaylists" | "notcommands" | "ping" | "password" | "clearerror" => {}
+            "idle" | "noidle" | "close" | "command_list_begin" | "command_list_ok_begin" => {
+                return Err(MpdError::new(
+                    MPD_ERROR_ARG,
+                    format!("{} is not allowed in a command list", command),
+                ))
+            }
+            _ => {
+                return Err(MpdError::new(
+                    MPD_ERROR_UNKNOWN,
+                    format!("unknown command \"{}\"", command),
+                ))
+            }
+        }
+        Ok(String::new())
+    }
+
+    /// The reply to `status`
+    fn mpd_status(&mut self, mpd: &mut MpdServer) -> String {
+        let (repeat, shuffle, len, next) = {
+            let queue = self.player.queue();
+            let queue = queue.lock().unwrap();
+            let next = self
+                .player
+                .current_track()
+                .and_then(|index| queue.index_after(index));
+            (queue.repeat(), queue.shuffle(), queue.len(), next)
+        };
+        let mut lines = vec![
+            format!("volume: {:.0}", self.player.volume() * 100.0),
+            format!("repeat: {}", (repeat != RepeatMode::Off) as u8),
+            format!("random: {}", shuffle as u8),
+            format!(
+                "single: {}",
+                (repeat == RepeatMode::Track || mpd.single) as u8
+            ),
+            "consume: 0".to_string(),
+            format!("playlist: {}", mpd.playlist_version),
+            format!("playlistlength: {}", len),
+            format!("state: {}", mpd_state(&self.player)),
+        ];
+        let crossfade = self.player.crossfade();
+        if !crossfade.is_zero() {
+            lines.push(format!("xfade: {}", crossfade.as_secs_f64().round()));
+        }
+        let current = self
+            .player
+            .current_track()
+            .filter(|_| self.player.is_playing());
+        if let (Some(index), Some(path)) = (current, self.player.current_path()) {
+            let elapsed = self.player.position().as_secs_f64();
+            let duration = mpd
+                .tags
+                .entry(path.clone())
+                .or_insert_with(|| TrackTags::read(&path))
+                .duration
+                .map_or(0.0, |duration| duration.as_secs_f64());
+            lines.extend([
+                format!("song: {}", index),
+                format!("songid: {}", index),
+                format!("time: {}:{}", elapsed.round(), duration.round()),
+                format!("elapsed: {:.3}", elapsed),
+                format!("duration: {:.3}", duration),
+            ]);
+            if let Some(bitrate) = self.player.bitrate() {
+                lines.push(format!("bitrate: {}", bitrate / 1000));
+            }
+            let mixer = self.player.mixer();
+            lines.push(format!(
+                "audio: {}:f:{}",
+                mixer.sample_rate(),
+                mixer.channels()
+            ));
+            if let Some(next) = next {
+                lines.extend([
+                    format!("nextsong: {}", next),
+                    format!("nextsongid: {}", next),
+                ]);
+            }
+        }
+        lines.into_iter().map(|line| line + "\n").collect()
+    }
+
+    /// Plays the track at `index` from `position`, seeking within it when it's
+    /// the one playing
+    fn mpd_seek(
+        &mut self,
+        mpd: &mut MpdServer,
+        index: usize,
+        position: std::time::Duration,
+    ) -> Result<(), MpdError> {
+        let path = self.player.queue().lock().unwrap().get(index).cloned();
+        let length = path.and_then(|path| {
+            mpd.tags
+                .entry(path.clone())
+                .or_insert_with(|| TrackTags::read(&path))
+                .duration
+        });
+        if length.is_some_and(|length| position >= length) {
+            return Err(MpdError::new(
+                MPD_ERROR_ARG,
+                "can't seek past the end of the track",
+            ));
+        }
+        if self.player.is_playing() && self.player.current_track() == Some(index) {
+            self.player.seek(position);
+            return Ok(());
+        }
+        self.player.play_from_at(index, position);
+        if self.player.is_paused() {
+            self.player
+                .resume()
+                .map_err(|e| MpdError::new(MPD_ERROR_SYSTEM, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// `play`, `pause` or `stop`, as MPD names the player's state
+fn mpd_state(player: &AudioPlayer) -> &'static str {
+    match (player.is_playing(), player.is_paused()) {
+        (false, _) => "stop",
+        (true, true) => "pause",
+        (true, false) => "play",
+    }
+}
+
+/// Splits an MPD command line into the command and its arguments, which may
+/// be quoted with backslash escapes
+fn mpd_args(line: &str) -> Result<Vec<String>, MpdError> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            return Ok(args);
+        };
+        let mut arg = String::new();
+        if first == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => arg.extend(chars.next()),
+                    Some(c) => arg.push(c),
+                    None => return Err(MpdError::new(MPD_ERROR_ARG, "missing closing quote")),
+                }
+            }
+        } else {
+            arg.push(first);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Argument `index` of an MPD command, parsed
+fn mpd_arg<T: std::str::FromStr>(args: &[String], index: usize) -> Result<T, MpdError> {
+    let arg = args
+        .get(index)
+        .ok_or_else(|| MpdError::new(MPD_ERROR_ARG, "too few arguments"))?;
+    arg.parse()
+        .map_err(|_| MpdError::new(MPD_ERROR_ARG, format!("invalid argument: {}", arg)))
+}
+
+/// A `0` or `1` argument of an MPD command
+fn mpd_flag(args: &[String], index: usize) -> Result<bool, MpdError> {
+    match mpd_arg::<u8>(args, index)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(MpdError::new(MPD_ERROR_ARG, "expected 0 or 1")),
+    }
+}
+
+/// A time argument of an MPD command, in seconds
+fn mpd_time(args: &[String], index: usize) -> Result<std::time::Duration, MpdError> {
+    let seconds: f64 = mpd_arg(args, index)?;
+    std::time::Duration::try_from_secs_f64(seconds)
+        .map_err(|_| MpdError::new(MPD_ERROR_ARG, format!("invalid time: {}", seconds)))
+}
+
+/// A song argument of an MPD command, given by its place in a queue of `len`
+fn mpd_queue_index(args: &[String], index: usize, len: usize) -> Result<usize, MpdError> {
+    match mpd_arg(args, index)? {
+        song if song < len => Ok(song),
+        song => Err(MpdError::new(
+            MPD_ERROR_NO_EXIST,
+            format!("no such song: {}", song),
+        )),
+    }
+}
+
+/// A `START:END` range of a queue of `len`, END left out for the rest of it;
+/// a single place, or the whole queue without `arg`
+fn mpd_queue_range(arg: Option<&String>, len: usize) -> Result<std::ops::Range<usize>, MpdError> {
+    let Some(arg) = arg else {
+        return Ok(0..len);
+    };
+    let bad = || MpdError::new(MPD_ERROR_ARG, format!("bad song index: {}", arg));
+    let parse = |number: &str| number.parse::<usize>().map_err(|_| bad());
+    let range = match arg.split_once(':') {
+        Some((start, "")) => parse(start)?..len.max(parse(start)? + 1),
+        Some((start, end)) => parse(start)?..parse(end)?,
+        None => parse(arg)?..parse(arg)? + 1,
+    };
+    match range.start < range.end && range.end <= len {
+        true => Ok(range),
+        false => Err(bad()),
+    }
+}
+
+/// Subdirectories and supported files of `dir`, directories first, leaving
+/// out hidden ones
+fn mpd_entries(dir: &std::path::Path) -> Result<Vec<(std::path::PathBuf, bool)>, MpdError> {
+    let entries = std::fs::read_dir(dir).map_err(|e| {
+        MpdError::new(
+            MPD_ERROR_NO_EXIST,
+            format!("failed to read {}: {}", dir.display(), e),
+        )
+    })?;
+    let mut entries: Vec<(std::path::PathBuf, bool)> = entries
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|entry| {
+            let path = entry.path();
+            // Not following links to directories keeps loops out of `listall`
+            match entry.file_type().ok()?.is_dir() {
+                true => Some((path, true)),
+                false if path.is_file() && scan::is_supported(&path) => Some((path, false)),
+                false => None,
+            }
+        })
+        .collect();
+    entries.sort_by(|(a, a_dir), (b, b_dir)| {
+        b_dir
+            .cmp(a_dir)
+            .then_with(|| scan::natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()))
+    });
+    Ok(entries)
+}
+
+/// `text` fit for a `key: value` line, control characters turned to spaces
+fn mpd_value(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn command_lines_split_into_arguments() {
+        assert_eq!(
+            mpd_args(r#"add "Some Album/01 \"Intro\".flac""#).ok(),
+            Some(args(&["add", r#"Some Album/01 "Intro".flac"#]))
+        );
+        assert_eq!(
+            mpd_args("  seek  3 12.5 ").ok(),
+            Some(args(&["seek", "3", "12.5"]))
+        );
+        assert_eq!(mpd_args("").ok(), Some(Vec::new()));
+        let error = mpd_args(r#"add "open"#).err().unwrap();
+        assert_eq!(error.code, MPD_ERROR_ARG);
+    }
+
+    #[test]
+    fn arguments_are_checked() {
+        let line = args(&["cmd", "1", "2", "-1", "x"]);
+        assert_eq!(mpd_arg::<u32>(&line, 1).ok(), Some(1));
+        assert!(mpd_arg::<u32>(&line, 4).is_err());
+        assert!(mpd_arg::<u32>(&line, 5).is_err());
+        assert_eq!(mpd_flag(&line, 1).ok(), Some(true));
+        assert!(mpd_flag(&line, 2).is_err());
+        assert_eq!(
+            mpd_time(&args(&["seekcur", "1.5"]), 1).ok(),
+            Some(std::time::Duration::from_millis(1500))
+        );
+        assert!(mpd_time(&line, 3).is_err());
+        assert_eq!(mpd_queue_index(&line, 1, 2).ok(), Some(1));
+        let error = mpd_queue_index(&line, 2, 2).err().unwrap();
+        assert_eq!(error.code, MPD_ERROR_NO_EXIST);
+    }
+
+    #[test]
+    fn queue_ranges() {
+        let range = |arg: Option<&str>, len| mpd_queue_range(arg.map(String::from).as_ref(), len);
+        assert_eq!(range(None, 4).ok(), Some(0..4));
+        assert_eq!(range(Some("2"), 4).ok(), Some(2..3));
+        assert_eq!(range(Some("1:3"), 4).ok(), Some(1..3));
+        assert_eq!(range(Some("1:"), 4).ok(), Some(1..4));
+        assert!(range(Some("3:1"), 4).is_err());
+        assert!(range(Some("4"), 4).is_err());
+        assert!(range(Some("a:b"), 4).is_err());
+    }
+
+    #[test]
+    fn values_lose_control_characters() {
+        assert_eq!(mpd_value("a\nb\tc"), "a b c");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn library_paths() {
+        use std::path::{Path, PathBuf};
+
+        let dir = std::env::temp_dir().join(format!("mogbox-mpd-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Disc 2")).unwrap();
+        for name in ["track 10.flac", "track 2.mp3", ".hidden.flac", "notes.txt"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let (_, connections) = std::sync::mpsc::channel();
+        let server = MpdServer::new(connections, dir.clone());
+        let entries = mpd_entries(&dir).ok().unwrap();
+        let names: Vec<String> = entries.iter().map(|(path, _)| server.uri(path)).collect();
+        assert_eq!(names, ["Disc 2", "track 2.mp3", "track 10.flac"]);
+        let directories: Vec<bool> = entries.iter().map(|(_, directory)| *directory).collect();
+        assert_eq!(directories, [true, false, false]);
+        assert!(mpd_entries(&dir.join("missing")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            server.path("Disc 2/a.flac").ok(),
+            Some(dir.join("Disc 2/a.flac"))
+        );
+        assert_eq!(
+            server.path("file:///elsewhere/a.flac").ok(),
+            Some(PathBuf::from("/elsewhere/a.flac"))
+        );
+        assert!(server.path("../outside.flac").is_err());
+        assert_eq!(
+            server.uri(Path::new("/elsewhere/a.flac")),
+            "/elsewhere/a.flac"
+        );
+    }
+
+    #[test]
+    fn clients_are_greeted_and_answered() {
+        use std::io::{Read, Write};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut remote = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut client = MpdClient::new(listener.accept().unwrap().0).unwrap();
+        remote.write_all(b"status\r\nping").unwrap();
+        // The client is read without blocking, so wait for the bytes to arrive
+        while client.input.len() < 12 {
+            client.read();
+        }
+        assert_eq!(client.next_line().as_deref(), Some("status"));
+        assert_eq!(client.next_line(), None);
+        assert!(client.reply(0, "status", Ok("volume: 50\n".to_string())));
+        let error = MpdError::new(MPD_ERROR_UNKNOWN, "unknown command \"dance\"");
+        assert!(!client.reply(1, "dance", Err(error)));
+        client.changed.extend(["mixer", "player"]);
+        client.idle = Some(vec!["player".to_string()]);
+        client.answer_idle(false);
+        client.write();
+        drop(client);
+        let mut output = String::new();
+        remote.read_to_string(&mut output).unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "OK MPD {}\nvolume: 50\nACK [5@1] {{dance}} unknown command \"dance\"\n\
+                 changed: player\nOK\n",
+                MPD_VERSION
+            )
+        );
+    }
+}
@@ -0,0 +1,905 @@
+// MPRIS
+//
+// On Linux and the BSDs, the daemon shows up on the session bus as an MPRIS
+// player, so desktop media keys, playerctl and the media applets of GNOME and
+// KDE control it and show what is playing. zbus serves the player from a
+// thread of its own: properties are read from what the daemon last saw of
+// the player, and calls are handed to the daemon, which replies once it has
+// carried them out.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use mogbox_io::http;
+use mogbox_runtime::RepeatMode;
+use zbus::fdo;
+use zbus::names::InterfaceName;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, Value};
+
+use crate::daemon::{Daemon, MediaKey};
+use crate::{percent_decode, track_name};
+
+/// Bus name of the daemon's player, with `.instance<PID>` added when taken
+const MPRIS_NAME: &str = "org.mpris.MediaPlayer2.mogbox";
+/// Object the player is served at
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const MPRIS_PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+/// Track ID of no track, for the metadata while stopped
+const MPRIS_NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+/// How far the position may stray from where the clock says it should be
+/// before it counts as a seek
+const MPRIS_SEEK_TOLERANCE: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Something an MPRIS client asked of the player
+#[derive(Debug, PartialEq)]
+enum MprisCall {
+    Quit,
+    Key(MediaKey),
+    /// Move this many microseconds along
+    Seek(i64),
+    /// Go to a position in microseconds, if the track with this ID is playing
+    SetPosition(String, i64),
+    /// Play a path or URL
+    Open(String),
+    Volume(f64),
+    Repeat(RepeatMode),
+    Shuffle(bool),
+}
+
+/// A call and where its result goes
+type MprisRequest = (MprisCall, std::sync::mpsc::Sender<Result<(), String>>);
+
+/// Hands calls over to the daemon
+struct MprisCalls(std::sync::mpsc::Sender<MprisRequest>);
+
+impl MprisCalls {
+    /// Waits for the daemon to carry out `call`
+    fn call(&self, call: MprisCall) -> fdo::Result<()> {
+        let stopped = || fdo::Error::Failed("the daemon is shutting down".to_string());
+        let (sender, result) = std::sync::mpsc::channel();
+        self.0.send((call, sender)).map_err(|_| stopped())?;
+        result
+            .recv()
+            .map_err(|_| stopped())?
+            .map_err(fdo::Error::Failed)
+    }
+}
+
+/// Metadata of the current track
+#[derive(Clone, Debug, PartialEq)]
+struct MprisMetadata {
+    track_id: OwnedObjectPath,
+    length: Option<std::time::Duration>,
+    url: Option<String>,
+    title: Option<String>,
+    artist: Option<String>,
+    album_artist: Option<String>,
+    album: Option<String>,
+    genre: Option<String>,
+    track: Option<u32>,
+}
+
+impl MprisMetadata {
+    /// Metadata while nothing is playing
+    fn none() -> Self {
+        MprisMetadata {
+            track_id: ObjectPath::from_static_str_unchecked(MPRIS_NO_TRACK).into(),
+            length: None,
+            url: None,
+            title: None,
+            artist: None,
+            album_artist: None,
+            album: None,
+            genre: None,
+            track: None,
+        }
+    }
+
+    /// The `a{sv}` dictionary clients read
+    fn to_dict(&self) -> HashMap<&'static str, Value<'static>> {
+        let text = |text: &Option<String>| text.clone().map(Value::from);
+        let list = |text: &Option<String>| text.clone().map(|text| Value::from(vec![text]));
+        let entries = [
+            ("mpris:trackid", Some(Value::from(self.track_id.clone()))),
+            (
+                "mpris:length",
+                self.length
+                    .map(|length| Value::from(length.as_micros() as i64)),
+            ),
+            ("xesam:url", text(&self.url)),
+            ("xesam:title", text(&self.title)),
+            ("xesam:artist", list(&self.artist)),
+            ("xesam:albumArtist", list(&self.album_artist)),
+            ("xesam:album", text(&self.album)),
+            ("xesam:genre", list(&self.genre)),
+            (
+                "xesam:trackNumber",
+                self.track.map(|track| Value::from(track as i32)),
+            ),
+        ];
+        entries
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .collect()
+    }
+}
+
+/// What clients are told about the player, as of the daemon's last look
+#[derive(Clone, Debug, PartialEq)]
+struct MprisState {
+    status: &'static str,
+    loop_status: &'static str,
+    shuffle: bool,
+    metadata: MprisMetadata,
+    volume: f64,
+    /// In microseconds
+    position: i64,
+    has_next: bool,
+    has_previous: bool,
+    has_tracks: bool,
+    can_seek: bool,
+}
+
+impl MprisState {
+    fn stopped() -> Self {
+        MprisState {
+            status: "Stopped",
+            loop_status: "None",
+            shuffle: false,
+            metadata: MprisMetadata::none(),
+            volume: 1.0,
+            position: 0,
+            has_next: false,
+            has_previous: false,
+            has_tracks: false,
+            can_seek: false,
+        }
+    }
+
+    /// The player properties besides `Position`, which clients don't hear
+    /// about as it moves
+    fn properties(&self) -> Vec<(&'static str, Value<'static>)> {
+        vec![
+            ("PlaybackStatus", Value::from(self.status)),
+            ("LoopStatus", Value::from(self.loop_status)),
+            ("Rate", Value::from(1.0)),
+            ("Shuffle", Value::from(self.shuffle)),
+            ("Metadata", Value::from(self.metadata.to_dict())),
+            ("Volume", Value::from(self.volume)),
+            ("MinimumRate", Value::from(1.0)),
+            ("MaximumRate", Value::from(1.0)),
+            ("CanGoNext", Value::from(self.has_next)),
+            ("CanGoPrevious", Value::from(self.has_previous)),
+            ("CanPlay", Value::from(self.has_tracks)),
+            ("CanPause", Value::from(self.has_tracks)),
+            ("CanSeek", Value::from(self.can_seek)),
+            ("CanControl", Value::from(true)),
+        ]
+    }
+}
+
+/// The `org.mpris.MediaPlayer2` interface
+struct MprisRoot {
+    calls: MprisCalls,
+}
+
+#[zbus::interface(name = "org.mpris.MediaPlayer2")]
+impl MprisRoot {
+    /// There is no window to raise
+    fn raise(&self) {}
+
+    fn quit(&self) -> fdo::Result<()> {
+        self.calls.call(MprisCall::Quit)
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> &str {
+        "mogbox"
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<&str> {
+        vec!["file", "http", "https"]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<&str> {
+        vec![
+            "audio/flac",
+            "audio/x-wav",
+            "audio/ogg",
+            "audio/mpeg",
+            "audio/x-matroska",
+        ]
+    }
+}
+
+/// The `org.mpris.MediaPlayer2.Player` interface
+struct MprisPlayer {
+    state: Arc<Mutex<MprisState>>,
+    calls: MprisCalls,
+}
+
+impl MprisPlayer {
+    fn state(&self) -> std::sync::MutexGuard<'_, MprisState> {
+        self.state.lock().unwrap()
+    }
+}
+
+#[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+impl MprisPlayer {
+    fn next(&self) -> fdo::Result<()> {
+        self.calls.call(MprisCall::Key(MediaKey::Next))
+    }
+
+    fn previous(&self) -> fdo::Result<()> {
+        self.calls.call(MprisCall::Key(MediaKey::Previous))
+    }
+
+    fn pause(&self) -> fdo::Result<()> {
+        self.calls.call(MprisCall::Key(MediaKey::Pause))
+    }
+
+    fn play_pause(&self) -> fdo::Result<()> {
+        self.calls.call(MprisCall::Key(MediaKey::PlayPause))
+    }
+
+    fn stop(&self) -> fdo::Result<()> {
+        self.calls.call(MprisCall::Key(MediaKey::Stop))
+    }
+
+    fn play(&self) -> fdo::Result<()> {
+        self.calls.call(MprisCall::Key(MediaKey::Play))
+    }
+
+    fn seek(&self, offset: i64) -> fdo::Result<()> {
+        self.calls.call(MprisCall::Seek(offset))
+    }
+
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        self.calls
+            .call(MprisCall::SetPosition(track_id.to_string(), position))
+    }
+
+    fn open_uri(&self, uri: &str) -> fdo::Result<()> {
+        let path = match uri.strip_prefix("file://") {
+            Some(path) => percent_decode(path),
+            None if http::is_url(std::path::Path::new(uri)) => uri.to_string(),
+            None => {
+                let error = "only file and HTTP URIs can be opened";
+                return Err(fdo::Error::InvalidArgs(error.to_string()));
+            }
+        };
+        self.calls.call(MprisCall::Open(path))
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &str {
+        self.state().status
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> &str {
+        self.state().loop_status
+    }
+
+    #[zbus(property)]
+    fn set_loop_status(&mut self, status: String) -> fdo::Result<()> {
+        let (status, repeat) = match status.as_str() {
+            "None" => ("None", RepeatMode::Off),
+            "Track" => ("Track", RepeatMode::Track),
+            "Playlist" => ("Playlist", RepeatMode::Queue),
+            _ => {
+                let error = "expected None, Track or Playlist";
+                return Err(fdo::Error::InvalidArgs(error.to_string()));
+            }
+        };
+        self.calls.call(MprisCall::Repeat(repeat))?;
+        // Up to date for the change signal that follows
+        self.state().loop_status = status;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    /// Playback only goes at its own speed
+    #[zbus(property)]
+    fn set_rate(&mut self, _rate: f64) {}
+
+    #[zbus(property)]
+    fn shuffle(&self) -> bool {
+        self.state().shuffle
+    }
+
+    #[zbus(property)]
+    fn set_shuffle(&mut self, shuffle: bool) -> fdo::Result<()> {
+        self.calls.call(MprisCall::Shuffle(shuffle))?;
+        self.state().shuffle = shuffle;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<&'static str, Value<'static>> {
+        self.state().metadata.to_dict()
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.state().volume
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, volume: f64) -> fdo::Result<()> {
+        let volume = volume.clamp(0.0, 1.0);
+        self.calls.call(MprisCall::Volume(volume))?;
+        self.state().volume = volume;
+        Ok(())
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        self.state().position
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        self.state().has_next
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        self.state().has_previous
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        self.state().has_tracks
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        self.state().has_tracks
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.state().can_seek
+    }
+
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// The daemon's MPRIS player on the session bus
+pub(crate) struct Mpris {
+    bus: zbus::blocking::Connection,
+    calls: std::sync::mpsc::Receiver<MprisRequest>,
+    /// What the properties are read from
+    state: Arc<Mutex<MprisState>>,
+    /// State last signalled, to signal only what changed
+    last: Option<MprisState>,
+    /// Track, position and when it was seen, to notice seeks
+    last_position: Option<(usize, std::time::Duration, std::time::Instant)>,
+}
+
+impl Mpris {
+    /// Connects to the session bus and takes a name there, returning it too
+    pub(crate) fn connect() -> Result<(Self, String), String> {
+        let mpris = zbus::blocking::connection::Builder::session()
+            .and_then(Mpris::serve)
+            .map_err(|e| format!("failed to connect to the session bus: {}", e))?;
+        let names = [
+            MPRIS_NAME.to_string(),
+            format!("{}.instance{}", MPRIS_NAME, std::process::id()),
+        ];
+        for name in names {
+            let reply = mpris
+                .bus
+                .request_name_with_flags(name.as_str(), fdo::RequestNameFlags::DoNotQueue.into())
+                .map_err(|e| format!("failed to take {}: {}", name, e))?;
+            if let fdo::RequestNameReply::PrimaryOwner | fdo::RequestNameReply::AlreadyOwner = reply
+            {
+                return Ok((mpris, name));
+            }
+        }
+        Err(format!("{} is taken", MPRIS_NAME))
+    }
+
+    /// Serves the player on the connection `builder` makes
+    fn serve(builder: zbus::blocking::connection::Builder<'_>) -> zbus::Result<Self> {
+        let (sender, calls) = std::sync::mpsc::channel();
+        let state = Arc::new(Mutex::new(MprisState::stopped()));
+        let root = MprisRoot {
+            calls: MprisCalls(sender.clone()),
+        };
+        let player = MprisPlayer {
+            state: state.clone(),
+            calls: MprisCalls(sender),
+        };
+        let bus = builder
+            .serve_at(MPRIS_PATH, root)?
+            .serve_at(MPRIS_PATH, player)?
+            .build()?;
+        Ok(Mpris {
+            bus,
+            calls,
+            state,
+            last: None,
+            last_position: None,
+        })
+    }
+}
+
+impl Daemon {
+    /// Carries out calls from MPRIS clients and signals them what changed
+    pub(crate) fn serve_mpris(&mut self) {
+        let Some(mut mpris) = self.mpris.take() else {
+            return;
+        };
+        if mpris.bus.is_closed() {
+            eprintln!("Lost the session bus, media controls are off");
+            return;
+        }
+        let (track, position) = (self.player.current_track(), self.player.position());
+        while let Ok((call, reply)) = mpris.calls.try_recv() {
+            let _ = reply.send(self.mpris_call(call));
+        }
+        self.left_track(track, position);
+        self.mpris_signals(&mut mpris);
+        self.mpris = Some(mpris);
+    }
+
+    /// Carries out a call
+    fn mpris_call(&mut self, call: MprisCall) -> Result<(), String> {
+        match call {
+            MprisCall::Quit => self.running = false,
+            MprisCall::Key(key) => self.media_key(key)?,
+            MprisCall::Seek(offset) => {
+                if !self.player.is_playing() {
+                    return Ok(());
+                }
+                let position = self.player.position().as_micros() as i64 + offset;
+                let position = std::time::Duration::from_micros(position.max(0) as u64);
+                // Seeking past the end goes on to the next track
+                match self.mpris_length().is_some_and(|length| position >= length) {
+                    true => {
+                        self.player.next_track();
+                    }
+                    false => {
+                        self.player.seek(position);
+                    }
+                }
+            }
+            MprisCall::SetPosition(track, position) => {
+                let current = self
+                    .player
+                    .current_track()
+                    .filter(|_| self.player.is_playing());
+                // Calls meant for a track that has since ended are ignored
+                if current.map(mpris_track_id) != Some(track) || position < 0 {
+                    return Ok(());
+                }
+                let position = std::time::Duration::from_micros(position as u64);
+                if self.mpris_length().is_none_or(|length| position < length) {
+                    self.player.seek(position);
+                }
+            }
+            MprisCall::Open(path) => {
+                self.play(&[path.as_str()])?;
+            }
+            MprisCall::Volume(volume) => self.player.set_volume(volume as f32),
+            MprisCall::Repeat(repeat) => self.player.queue().lock().unwrap().set_repeat(repeat),
+            MprisCall::Shuffle(shuffle) => self.player.queue().lock().unwrap().set_shuffle(shuffle),
+        }
+        Ok(())
+    }
+
+    /// What clients are to be told about the player now
+    fn mpris_state(&mut self) -> MprisState {
+        let status = match (self.player.is_playing(), self.player.is_paused()) {
+            (false, _) => "Stopped",
+            (true, true) => "Paused",
+            (true, false) => "Playing",
+        };
+        let (repeat, shuffle, has_next, has_previous, tracks) = {
+            let queue = self.player.queue();
+            let queue = queue.lock().unwrap();
+            let current = self.player.current_track();
+            (
+                queue.repeat(),
+                queue.shuffle(),
+                current.is_some_and(|index| queue.index_after(index).is_some()),
+                current.is_some_and(|index| queue.index_before(index).is_some()),
+                queue.len(),
+            )
+        };
+        MprisState {
+            status,
+            loop_status: match repeat {
+                RepeatMode::Off => "None",
+                RepeatMode::Track => "Track",
+                RepeatMode::Queue => "Playlist",
+            },
+            shuffle,
+            metadata: self.mpris_metadata(),
+            volume: self.player.volume() as f64,
+            position: self.player.position().as_micros() as i64,
+            has_next,
+            has_previous,
+            has_tracks: tracks > 0,
+            can_seek: self.player.is_playing(),
+        }
+    }
+
+    /// MPRIS metadata of the current track
+    fn mpris_metadata(&mut self) -> MprisMetadata {
+        let current = self
+            .player
+            .current_track()
+            .filter(|_| self.player.is_playing());
+        let (Some(index), Some(path)) = (current, self.player.current_path()) else {
+            return MprisMetadata::none();
+        };
+        let tags = self.track_tags(&path);
+        MprisMetadata {
+            track_id: ObjectPath::from_string_unchecked(mpris_track_id(index)).into(),
+            length: tags.duration,
+            url: Some(file_url(&path)),
+            title: Some(tags.title.clone().unwrap_or_else(|| track_name(&path))),
+            artist: tags.artist.clone(),
+            album_artist: tags.album_artist.clone(),
+            album: tags.album.clone(),
+            genre: tags.genre.clone(),
+            track: tags.track,
+        }
+    }
+
+    /// Length of the current track, if known
+    fn mpris_length(&mut self) -> Option<std::time::Duration> {
+        let path = self.player.current_path()?;
+        self.track_tags(&path).duration
+    }
+
+    /// Updates what the properties are read from, and signals the properties
+    /// that changed since the last call, and seeks
+    fn mpris_signals(&mut self, mpris: &mut Mpris) {
+        let state = self.mpris_state();
+        *mpris.state.lock().unwrap() = state.clone();
+        let Ok(emitter) = SignalEmitter::new(mpris.bus.inner(), MPRIS_PATH) else {
+            return;
+        };
+        let last = mpris
+            .last
+            .as_ref()
+            .map(MprisState::properties)
+            .unwrap_or_default();
+        let changed: HashMap<&str, Value> = state
+            .properties()
+            .into_iter()
+            .filter(|property| !last.contains(property))
+            .collect();
+        if !changed.is_empty() {
+            let _ = zbus::block_on(fdo::Properties::properties_changed(
+                &emitter,
+                InterfaceName::from_static_str_unchecked(MPRIS_PLAYER_INTERFACE),
+                changed,
+                (&[]).into(),
+            ));
+        }
+        mpris.last = Some(state);
+
+        // Clients move their position along by the clock, so they only need
+        // to hear about jumps
+        let playing = self
+            .player
+            .current_track()
+            .filter(|_| self.player.is_playing());
+        let position = self.player.position();
+        if let (Some(track), Some((last_track, last_position, seen))) =
+            (playing, mpris.last_position)
+        {
+            let expected = match self.player.is_paused() {
+                true => last_position,
+                false => last_position + seen.elapsed(),
+            };
+            if track == last_track && position.abs_diff(expected) > MPRIS_SEEK_TOLERANCE {
+                let position = position.as_micros() as i64;
+                let _ = zbus::block_on(MprisPlayer::seeked(&emitter, position));
+            }
+        }
+        mpris.last_position = playing.map(|track| (track, position, std::time::Instant::now()));
+    }
+}
+
+/// MPRIS track ID of the track at `index` in the queue
+fn mpris_track_id(index: usize) -> String {
+    format!("/org/mpris/MediaPlayer2/Track/{}", index)
+}
+
+/// A `file://` URL of `path`, with the bytes URLs can't hold escaped; a path
+/// that is a URL already stays as it is
+fn file_url(path: &std::path::Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    if http::is_url(path) {
+        return path.to_string_lossy().into_owned();
+    }
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut url = "file://".to_string();
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A player served to a client over a socket pair, without a bus
+    fn serve() -> (Mpris, zbus::blocking::Connection) {
+        let (server, client) = std::os::unix::net::UnixStream::pair().unwrap();
+        let server = std::thread::spawn(move || {
+            let builder = zbus::blocking::connection::Builder::async_io_unix_stream(server)
+                .server(zbus::Guid::generate())
+                .unwrap()
+                .p2p();
+            Mpris::serve(builder).unwrap()
+        });
+        let client = zbus::blocking::connection::Builder::async_io_unix_stream(client)
+            .p2p()
+            .build()
+            .unwrap();
+        (server.join().unwrap(), client)
+    }
+
+    /// Calls `member` from another thread, carrying the call out with
+    /// `result` once it reaches the daemon
+    fn call<B>(
+        mpris: &Mpris,
+        client: &zbus::blocking::Connection,
+        interface: &'static str,
+        member: &'static str,
+        body: B,
+        result: Result<(), String>,
+    ) -> (Option<MprisCall>, zbus::Result<zbus::Message>)
+    where
+        B: zbus::export::serde::Serialize + zbus::zvariant::DynamicType + Send + 'static,
+    {
+        let client = client.clone();
+        let reply = std::thread::spawn(move || {
+            client.call_method(None::<&str>, MPRIS_PATH, Some(interface), member, &body)
+        });
+        let call = mpris
+            .calls
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .ok()
+            .map(|(call, reply)| {
+                reply.send(result).unwrap();
+                call
+            });
+        (call, reply.join().unwrap())
+    }
+
+    fn get(client: &zbus::blocking::Connection, name: &str) -> zbus::zvariant::OwnedValue {
+        let properties = "org.freedesktop.DBus.Properties";
+        let reply = client
+            .call_method(
+                None::<&str>,
+                MPRIS_PATH,
+                Some(properties),
+                "Get",
+                &(MPRIS_PLAYER_INTERFACE, name),
+            )
+            .unwrap();
+        reply.body().deserialize().unwrap()
+    }
+
+    #[test]
+    fn calls_are_carried_out_by_the_daemon() {
+        let (mpris, client) = serve();
+        let (next, reply) = call(&mpris, &client, MPRIS_PLAYER_INTERFACE, "Next", (), Ok(()));
+        assert_eq!(next, Some(MprisCall::Key(MediaKey::Next)));
+        assert!(reply.is_ok());
+
+        let failed = Err("nothing to play".to_string());
+        let (play, reply) = call(&mpris, &client, MPRIS_PLAYER_INTERFACE, "Play", (), failed);
+        assert_eq!(play, Some(MprisCall::Key(MediaKey::Play)));
+        let Err(zbus::Error::MethodError(name, message, _)) = reply else {
+            panic!("expected an error reply");
+        };
+        assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.Failed");
+        assert_eq!(message.as_deref(), Some("nothing to play"));
+
+        let (quit, _) = call(
+            &mpris,
+            &client,
+            "org.mpris.MediaPlayer2",
+            "Quit",
+            (),
+            Ok(()),
+        );
+        assert_eq!(quit, Some(MprisCall::Quit));
+    }
+
+    #[test]
+    fn uris_are_opened_as_paths() {
+        let (mpris, client) = serve();
+        let uri = "file:///music/a%20b.flac";
+        let (open, _) = call(
+            &mpris,
+            &client,
+            MPRIS_PLAYER_INTERFACE,
+            "OpenUri",
+            (uri,),
+            Ok(()),
+        );
+        assert_eq!(open, Some(MprisCall::Open("/music/a b.flac".to_string())));
+
+        let uri = "https://example.com/radio.mp3";
+        let (open, _) = call(
+            &mpris,
+            &client,
+            MPRIS_PLAYER_INTERFACE,
+            "OpenUri",
+            (uri,),
+            Ok(()),
+        );
+        assert_eq!(open, Some(MprisCall::Open(uri.to_string())));
+
+        // Refused without bothering the daemon
+        let uri = "ftp://example.com/a.flac";
+        let reply = client.call_method(
+            None::<&str>,
+            MPRIS_PATH,
+            Some(MPRIS_PLAYER_INTERFACE),
+            "OpenUri",
+            &(uri,),
+        );
+        assert!(matches!(reply, Err(zbus::Error::MethodError(..))));
+        assert!(mpris.calls.try_recv().is_err());
+    }
+
+    #[test]
+    fn properties_are_read_from_the_state() {
+        let (mpris, client) = serve();
+        {
+            let mut state = mpris.state.lock().unwrap();
+            state.status = "Playing";
+            state.volume = 0.25;
+            state.position = 1_500_000;
+            state.metadata.title = Some("Song".to_string());
+        }
+        assert_eq!(
+            get(&client, "PlaybackStatus"),
+            Value::from("Playing").try_into().unwrap()
+        );
+        assert_eq!(
+            get(&client, "Volume"),
+            Value::from(0.25).try_into().unwrap()
+        );
+        assert_eq!(
+            get(&client, "Position"),
+            Value::from(1_500_000i64).try_into().unwrap()
+        );
+        let metadata: HashMap<String, zbus::zvariant::OwnedValue> =
+            get(&client, "Metadata").try_into().unwrap();
+        assert_eq!(
+            metadata["xesam:title"],
+            Value::from("Song").try_into().unwrap()
+        );
+        assert_eq!(
+            metadata["mpris:trackid"],
+            Value::from(ObjectPath::from_static_str_unchecked(MPRIS_NO_TRACK))
+                .try_into()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn properties_are_set_through_the_daemon() {
+        let (mpris, client) = serve();
+        let set = |name: &'static str, value: Value<'static>| {
+            call(
+                &mpris,
+                &client,
+                "org.freedesktop.DBus.Properties",
+                "Set",
+                (MPRIS_PLAYER_INTERFACE, name, value),
+                Ok(()),
+            )
+        };
+        let (repeat, _) = set("LoopStatus", Value::from("Track"));
+        assert_eq!(repeat, Some(MprisCall::Repeat(RepeatMode::Track)));
+        assert_eq!(
+            get(&client, "LoopStatus"),
+            Value::from("Track").try_into().unwrap()
+        );
+        let (volume, _) = set("Volume", Value::from(1.5));
+        assert_eq!(volume, Some(MprisCall::Volume(1.0)));
+        let (shuffle, _) = set("Shuffle", Value::from(true));
+        assert_eq!(shuffle, Some(MprisCall::Shuffle(true)));
+        assert_eq!(
+            get(&client, "Shuffle"),
+            Value::from(true).try_into().unwrap()
+        );
+    }
+
+    #[test]
+    fn metadata_leaves_out_what_is_unknown() {
+        let metadata = MprisMetadata {
+            track_id: ObjectPath::from_string_unchecked(mpris_track_id(3)).into(),
+            length: Some(std::time::Duration::from_secs(2)),
+            url: Some("file:///a.flac".to_string()),
+            title: Some("A".to_string()),
+            artist: Some("B".to_string()),
+            ..MprisMetadata::none()
+        };
+        let dict = metadata.to_dict();
+        let mut keys: Vec<&str> = dict.keys().copied().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "mpris:length",
+                "mpris:trackid",
+                "xesam:artist",
+                "xesam:title",
+                "xesam:url"
+            ]
+        );
+        assert_eq!(dict["mpris:length"], Value::from(2_000_000i64));
+        assert_eq!(dict["xesam:artist"], Value::from(vec!["B".to_string()]));
+    }
+
+    #[test]
+    fn file_urls_escape_what_urls_cant_hold() {
+        let url = file_url(std::path::Path::new("/music/a b&\u{e9}.flac"));
+        assert_eq!(url, "file:///music/a%20b%26%C3%A9.flac");
+        assert_eq!(
+            percent_decode(url.strip_prefix("file://").unwrap()),
+            "/music/a b&\u{e9}.flac"
+        );
+        let stream = "https://example.com/radio.mp3";
+        assert_eq!(file_url(std::path::Path::new(stream)), stream);
+    }
+}
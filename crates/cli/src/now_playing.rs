@@ -0,0 +1,181 @@
+// Now Playing
+//
+// On macOS, the daemon fills in the Now Playing info, so Control Center and
+// the menu bar show the track, and takes the remote commands of the media
+// keys, Control Center and headphones. The commands arrive on the main
+// thread's run loop, which the daemon, running on the main thread, turns
+// each pass, and are handed to it through a channel.
+//
+// Every call into the MediaPlayer framework is unsafe to objc2, so the
+// module allows unsafe code.
+#![allow(unsafe_code)]
+
+use block2::RcBlock;
+use objc2::rc::Retained;
+use objc2::runtime::AnyObject;
+use objc2_core_foundation::{kCFRunLoopDefaultMode, CFRunLoop};
+use objc2_foundation::{NSDictionary, NSNumber, NSString};
+use objc2_media_player::{
+    MPMediaItemPropertyAlbumTitle, MPMediaItemPropertyArtist, MPMediaItemPropertyPlaybackDuration,
+    MPMediaItemPropertyTitle, MPNowPlayingInfoCenter, MPNowPlayingInfoPropertyElapsedPlaybackTime,
+    MPNowPlayingInfoPropertyPlaybackRate, MPNowPlayingPlaybackState, MPRemoteCommand,
+    MPRemoteCommandCenter, MPRemoteCommandHandlerStatus,
+};
+
+use crate::daemon::{Daemon, MediaKey};
+use crate::track_name;
+
+/// How far the position may stray from where the clock says it should be
+/// before the elapsed time is shown again
+const NOW_PLAYING_SEEK_TOLERANCE: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The daemon's Now Playing info and remote commands
+pub(crate) struct NowPlaying {
+    info: Retained<MPNowPlayingInfoCenter>,
+    keys: std::sync::mpsc::Receiver<MediaKey>,
+    /// What is shown, to update it only when it changes
+    last: Option<NowPlayingState>,
+    /// Position last shown and when, to notice seeks
+    last_position: Option<(std::time::Duration, std::time::Instant)>,
+}
+
+/// What Now Playing shows, besides the elapsed time
+#[derive(Clone, Debug, PartialEq)]
+struct NowPlayingState {
+    playing: bool,
+    paused: bool,
+    /// The track playing, if any
+    path: Option<std::path::PathBuf>,
+}
+
+impl NowPlaying {
+    /// Takes the remote commands
+    pub(crate) fn start() -> Self {
+        let (sender, keys) = std::sync::mpsc::channel();
+        let commands = unsafe { MPRemoteCommandCenter::sharedCommandCenter() };
+        let handle = |command: Retained<MPRemoteCommand>, key: MediaKey| {
+            let sender = sender.clone();
+            let handler = RcBlock::new(move |_| {
+                let _ = sender.send(key);
+                MPRemoteCommandHandlerStatus::Success
+            });
+            unsafe {
+                command.setEnabled(true);
+                command.addTargetWithHandler(&handler);
+            }
+        };
+        unsafe {
+            handle(commands.playCommand(), MediaKey::Play);
+            handle(commands.pauseCommand(), MediaKey::Pause);
+            handle(commands.togglePlayPauseCommand(), MediaKey::PlayPause);
+            handle(commands.stopCommand(), MediaKey::Stop);
+            handle(commands.nextTrackCommand(), MediaKey::Next);
+            handle(commands.previousTrackCommand(), MediaKey::Previous);
+        }
+        NowPlaying {
+            info: unsafe { MPNowPlayingInfoCenter::defaultCenter() },
+            keys,
+            last: None,
+            last_position: None,
+        }
+    }
+}
+
+impl Daemon {
+    /// Acts on the remote commands, and shows what changed since the last call
+    pub(crate) fn serve_now_playing(&mut self) {
+        let Some(mut now_playing) = self.now_playing.take() else {
+            return;
+        };
+        // Delivers the commands waiting
+        CFRunLoop::run_in_mode(unsafe { kCFRunLoopDefaultMode }, 0.0, false);
+        let (track, position) = (self.player.current_track(), self.player.position());
+        while let Ok(key) = now_playing.keys.try_recv() {
+            if let Err(e) = self.media_key(key) {
+                eprintln!("Error: {}", e);
+            }
+        }
+        self.left_track(track, position);
+        self.now_playing_update(&mut now_playing);
+        self.now_playing = Some(now_playing);
+    }
+
+    /// Shows the track, whether it plays and how far along it is, if any of
+    /// it changed
+    fn now_playing_update(&mut self, now_playing: &mut NowPlaying) {
+        let state = NowPlayingState {
+            playing: self.player.is_playing(),
+            paused: self.player.is_paused(),
+            path: self
+                .player
+                .current_path()
+                .filter(|_| self.player.is_playing()),
+        };
+        // Now Playing moves the elapsed time along by the clock, so it only
+        // needs to hear about jumps
+        let position = self.player.position();
+        let seeked = now_playing.last_position.is_some_and(|(last, seen)| {
+            let expected = match state.paused {
+                true => last,
+                false => last + seen.elapsed(),
+            };
+            position.abs_diff(expected) > NOW_PLAYING_SEEK_TOLERANCE
+        });
+        if now_playing.last.as_ref() == Some(&state) && !seeked {
+            return;
+        }
+        now_playing.last_position = state
+            .path
+            .is_some()
+            .then(|| (position, std::time::Instant::now()));
+
+        let playback = match (state.playing, state.paused) {
+            (false, _) => MPNowPlayingPlaybackState::Stopped,
+            (true, true) => MPNowPlayingPlaybackState::Paused,
+            (true, false) => MPNowPlayingPlaybackState::Playing,
+        };
+        let info = state.path.as_ref().map(|path| {
+            let tags = self.track_tags(path);
+            let title = tags.title.clone().unwrap_or_else(|| track_name(path));
+            let mut entries = vec![(unsafe { MPMediaItemPropertyTitle }, text_value(&title))];
+            if let Some(artist) = &tags.artist {
+                entries.push((unsafe { MPMediaItemPropertyArtist }, text_value(artist)));
+            }
+            if let Some(album) = &tags.album {
+                entries.push((unsafe { MPMediaItemPropertyAlbumTitle }, text_value(album)));
+            }
+            if let Some(duration) = tags.duration {
+                let duration = number_value(duration.as_secs_f64());
+                entries.push((unsafe { MPMediaItemPropertyPlaybackDuration }, duration));
+            }
+            let rate = if state.paused { 0.0 } else { 1.0 };
+            entries.extend([
+                (
+                    unsafe { MPNowPlayingInfoPropertyElapsedPlaybackTime },
+                    number_value(position.as_secs_f64()),
+                ),
+                (
+                    unsafe { MPNowPlayingInfoPropertyPlaybackRate },
+                    number_value(rate),
+                ),
+            ]);
+            let (keys, values): (Vec<&NSString>, Vec<Retained<AnyObject>>) =
+                entries.into_iter().unzip();
+            NSDictionary::from_retained_objects(&keys, &values)
+        });
+        unsafe {
+            now_playing.info.setNowPlayingInfo(info.as_deref());
+            now_playing.info.setPlaybackState(playback);
+        }
+        now_playing.last = Some(state);
+    }
+}
+
+fn text_value(text: &str) -> Retained<AnyObject> {
+    Retained::into_super(Retained::into_super(NSString::from_str(text)))
+}
+
+fn number_value(number: f64) -> Retained<AnyObject> {
+    let number = NSNumber::new_f64(number);
+    Retained::into_super(Retained::into_super(Retained::into_super(number)))
+}
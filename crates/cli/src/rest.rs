@@ -0,0 +1,392 @@
+// REST API
+//
+// With `daemon --http ADDRESS`, the daemon also takes requests over HTTP,
+// replying with JSON:
+//
+//   GET    /status                      what is playing, as `ctl status`
+//   GET    /queue                       the tracks in the queue
+//   POST   /queue?path=PATH...          add tracks to the queue
+//   DELETE /queue                       stop playback and empty the queue
+//   POST   /play[?path=PATH...]         resume, or replace the queue and play
+//   POST   /pause, /toggle, /stop, /next, /previous
+//   POST   /seek?position=SECONDS
+//   POST   /volume?percent=PERCENT
+//   GET    /events                      a WebSocket of `status`, `queue` and
+//                                       `levels` events as JSON text messages
+//
+// There is no authentication, so requests web pages could have made are
+// refused: the Host must be `localhost` or an address, as a name pointing
+// here (DNS rebinding) would let pages read the replies, and an Origin, which
+// browsers send with POSTs and WebSocket handshakes, must be the Host's own.
+// Requests are read on threads of their own and handed to the daemon whole,
+// so a slow client doesn't hold up the others.
+
+use crate::daemon::{daemon_queue_json, listen_tcp, Daemon, DaemonStatus};
+use crate::json::Json;
+use crate::{percent_decode, DAEMON_READ_TIMEOUT};
+
+/// Longest request body read, which the API ignores
+const HTTP_MAX_BODY: usize = 64 * 1024;
+
+/// A request to the REST API, read off the daemon thread, and the
+/// connection to reply on
+pub(crate) struct HttpConnection {
+    stream: std::net::TcpStream,
+    request: Result<HttpRequest, String>,
+}
+
+/// Starts accepting REST API connections on `address`, each handed over once
+/// its request has been read
+pub(crate) fn listen_http(
+    address: std::net::SocketAddr,
+) -> Result<std::sync::mpsc::Receiver<HttpConnection>, String> {
+    let streams = listen_tcp(address)?;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in streams {
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                let request = HttpRequest::read(&stream);
+                let _ = sender.send(HttpConnection { stream, request });
+            });
+        }
+    });
+    Ok(receiver)
+}
+
+/// Whether `host`, a Host header, is `localhost` or an address, with or
+/// without a port
+fn is_local_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((address, port)) if port.is_empty() || port.starts_with(':') => {
+                return address.parse::<std::net::Ipv6Addr>().is_ok();
+            }
+            _ => return false,
+        },
+        None => host.split_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<std::net::Ipv4Addr>().is_ok()
+}
+
+/// A request to the REST API
+struct HttpRequest {
+    method: String,
+    path: String,
+    /// Query parameters, decoded
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    /// Reads the request line and headers, then skips the body
+    fn read(stream: &std::net::TcpStream) -> Result<HttpRequest, String> {
+        use std::io::{BufRead, Read};
+
+        let read_error = |e: std::io::Error| format!("failed to read request: {}", e);
+        let _ = stream.set_read_timeout(Some(DAEMON_READ_TIMEOUT));
+        let mut reader = std::io::BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).map_err(read_error)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err("malformed request line".to_string());
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                let decode = |text: &str| percent_decode(&text.replace('+', " "));
+                (decode(name), decode(value))
+            })
+            .collect();
+        let mut headers = Vec::new();
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).map_err(read_error)? == 0 {
+                break;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        let length: usize = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(0);
+        // Left unread, the body could make closing the connection reset it
+        let _ = std::io::copy(
+            &mut reader.take(length.min(HTTP_MAX_BODY) as u64),
+            &mut std::io::sink(),
+        );
+        Ok(HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            query,
+            headers,
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether a web page on another site could have sent the request, or
+    /// read the reply through a rebound name
+    fn is_cross_site(&self) -> bool {
+        let Some(host) = self.header("host").filter(|host| is_local_host(host)) else {
+            return true;
+        };
+        self.header("origin").is_some_and(|origin| {
+            let origin = origin.strip_prefix("http://").unwrap_or_default();
+            !origin.eq_ignore_ascii_case(host)
+        })
+    }
+
+    /// Values of the query parameter `name`, in order
+    fn params(&self, name: &str) -> Vec<String> {
+        self.query
+            .iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+
+    /// The daemon command the request maps to and its arguments, or the
+    /// status code and message of the error
+    fn command(&self) -> Result<(&'static str, Vec<String>), (u16, String)> {
+        let method = self.method.as_str();
+        Ok(match (self.path.trim_end_matches('/'), method) {
+            ("/status", "GET") => ("status", Vec::new()),
+            ("/queue", "GET") => ("queue", Vec::new()),
+            ("/queue", "POST") => ("add", self.params("path")),
+            ("/queue", "DELETE") => ("clear", Vec::new()),
+            ("/play", "POST") => ("play", self.params("path")),
+            ("/pause", "POST") => ("pause", Vec::new()),
+            ("/toggle", "POST") => ("toggle", Vec::new()),
+            ("/stop", "POST") => ("stop", Vec::new()),
+            ("/next", "POST") => ("next", Vec::new()),
+            ("/previous", "POST") => ("previous", Vec::new()),
+            ("/seek", "POST") => ("seek", self.params("position")),
+            ("/volume", "POST") => ("volume", self.params("percent")),
+            (
+                "/status" | "/queue" | "/play" | "/pause" | "/toggle" | "/stop" | "/next"
+                | "/previous" | "/seek" | "/volume" | "/events",
+                _,
+            ) => {
+                let error = format!("{} is not allowed on {}", method, self.path);
+                return Err((405, error));
+            }
+            _ => return Err((404, format!("no such endpoint: {}", self.path))),
+        })
+    }
+}
+
+impl Daemon {
+    /// Replies to a request read by `listen_http`
+    pub(crate) fn serve_http(&mut self, connection: HttpConnection) {
+        use std::io::Write;
+
+        let HttpConnection {
+            mut stream,
+            request,
+        } = connection;
+        let (status, body) = match request {
+            // Before `/events`, since browsers open WebSockets from any page
+            Ok(request) if request.is_cross_site() => {
+                let error = "requests from web pages on other sites are refused";
+                (403, Json::object([("error", Json::from(error))]))
+            }
+            Ok(request) if request.path == "/events" && request.method == "GET" => {
+                match request.header("sec-websocket-key") {
+                    Some(key) => {
+                        self.events.accept(stream, key);
+                        return;
+                    }
+                    None => {
+                        let error = "/events takes WebSocket connections";
+                        (400, Json::object([("error", Json::from(error))]))
+                    }
+                }
+            }
+            Ok(request) => self.handle_http(&request),
+            Err(e) => (400, Json::object([("error", Json::from(e))])),
+        };
+        let reason = match status {
+            200 => "OK",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            _ => "Method Not Allowed",
+        };
+        let body = format!("{}\n", body);
+        let response = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            status,
+            reason,
+            body.len(),
+            body
+        );
+        let _ = stream.write_all(response.as_bytes());
+    }
+
+    /// Carries out an HTTP request through the same commands as `ctl`,
+    /// returning the status code and body of the response
+    fn handle_http(&mut self, request: &HttpRequest) -> (u16, Json) {
+        let (command, args) = match request.command() {
+            Ok(command) => command,
+            Err((status, error)) => return (status, Json::object([("error", Json::from(error))])),
+        };
+        let fields: Vec<&str> = std::iter::once(command)
+            .chain(args.iter().map(String::as_str))
+            .collect();
+        match self.handle(&fields) {
+            Ok(body) => match command {
+                "status" => (200, DaemonStatus::parse(&body).to_json()),
+                "queue" => (200, daemon_queue_json(&body)),
+                _ => (
+                    200,
+                    Json::object([("message", Json::from(body.trim_end()))]),
+                ),
+            },
+            Err(e) => (400, Json::object([("error", Json::from(e))])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    /// Reads `raw` as a client would have sent it
+    fn read_request(raw: &[u8]) -> Result<HttpRequest, String> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(raw).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        HttpRequest::read(&server)
+    }
+
+    fn request(method: &str, target: &str) -> HttpRequest {
+        read_request(format!("{} {} HTTP/1.1\r\n\r\n", method, target).as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn requests_are_read() {
+        let request = read_request(
+            b"POST /queue?path=%2Fmusic%2Fa+b.flac&path=c%20d.flac&empty HTTP/1.1\r\n\
+              Host: localhost\r\nContent-Length: 5\r\n\r\nhello",
+        )
+        .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/queue");
+        assert_eq!(request.params("path"), ["/music/a b.flac", "c d.flac"]);
+        assert_eq!(request.params("empty"), [""]);
+        assert_eq!(request.header("content-length"), Some("5"));
+        assert_eq!(request.header("Accept"), None);
+    }
+
+    #[test]
+    fn requests_from_other_sites_are_refused() {
+        let is_cross_site = |headers: &str| {
+            let raw = format!("POST /stop HTTP/1.1\r\n{}\r\n", headers);
+            read_request(raw.as_bytes()).unwrap().is_cross_site()
+        };
+        assert!(!is_cross_site("Host: localhost:6680\r\n"));
+        assert!(!is_cross_site("Host: 127.0.0.1:6680\r\n"));
+        assert!(!is_cross_site("Host: [::1]:6680\r\n"));
+        assert!(!is_cross_site("Host: 192.168.1.20\r\n"));
+        assert!(!is_cross_site(
+            "Host: localhost:6680\r\nOrigin: http://localhost:6680\r\n"
+        ));
+        // Pages elsewhere, including other ports of this machine
+        assert!(is_cross_site(
+            "Host: 127.0.0.1:6680\r\nOrigin: https://example.com\r\n"
+        ));
+        assert!(is_cross_site(
+            "Host: localhost:6680\r\nOrigin: http://localhost:8080\r\n"
+        ));
+        assert!(is_cross_site("Host: localhost:6680\r\nOrigin: null\r\n"));
+        assert!(is_cross_site(
+            "Host: localhost:6680\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nOrigin: https://example.com\r\n"
+        ));
+        // A name rebound to this machine, or none at all
+        assert!(is_cross_site("Host: attacker.example:6680\r\n"));
+        assert!(is_cross_site("Host: [::1]x\r\n"));
+        assert!(is_cross_site(""));
+    }
+
+    #[test]
+    fn slow_clients_do_not_hold_up_others() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let connections = listen_http(address).unwrap();
+        let mut slow = TcpStream::connect(address).unwrap();
+        slow.write_all(b"GET /sta").unwrap();
+        let mut fast = TcpStream::connect(address).unwrap();
+        fast.write_all(b"GET /queue HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let connection = connections
+            .recv_timeout(std::time::Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(connection.request.unwrap().path, "/queue");
+    }
+
+    #[test]
+    fn malformed_requests_are_refused() {
+        assert!(read_request(b"nonsense\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn endpoints_map_to_commands() {
+        let command = |method, target| request(method, target).command();
+        assert_eq!(command("GET", "/status"), Ok(("status", Vec::new())));
+        assert_eq!(command("GET", "/queue/"), Ok(("queue", Vec::new())));
+        assert_eq!(
+            command("POST", "/queue?path=a.flac&path=b.flac"),
+            Ok(("add", vec!["a.flac".to_string(), "b.flac".to_string()]))
+        );
+        assert_eq!(command("DELETE", "/queue"), Ok(("clear", Vec::new())));
+        assert_eq!(
+            command("POST", "/seek?position=12.5"),
+            Ok(("seek", vec!["12.5".to_string()]))
+        );
+        assert_eq!(
+            command("POST", "/volume?percent=40"),
+            Ok(("volume", vec!["40".to_string()]))
+        );
+    }
+
+    #[test]
+    fn unknown_endpoints_and_methods_are_refused() {
+        assert_eq!(
+            request("GET", "/pause").command(),
+            Err((405, "GET is not allowed on /pause".to_string()))
+        );
+        assert_eq!(
+            request("POST", "/events").command(),
+            Err((405, "POST is not allowed on /events".to_string()))
+        );
+        assert_eq!(
+            request("GET", "/nowhere").command(),
+            Err((404, "no such endpoint: /nowhere".to_string()))
+        );
+    }
+}
@@ -0,0 +1,774 @@
+// Scrobbling
+//
+// With credentials in the config, the daemon tells Last.fm and ListenBrainz
+// what it starts playing and submits each track once enough of it has played.
+// Submissions go through the `curl` command on a thread of their own, so a
+// slow or unreachable service doesn't hold up playback. Listens that can't be
+// submitted yet wait in the state directory and are tried again later.
+
+use mogbox_io::http;
+
+use crate::daemon::TrackTags;
+use crate::json::Json;
+use crate::state_dir;
+
+/// Last.fm's API endpoint
+const LASTFM_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+/// ListenBrainz's endpoint for listens and now playing
+const LISTENBRAINZ_URL: &str = "https://api.listenbrainz.org/1/submit-listens";
+/// Longest a submission may take, in seconds
+const SCROBBLE_TIMEOUT: u32 = 10;
+/// How often listens waiting to be submitted are tried again
+const SCROBBLE_RETRY: std::time::Duration = std::time::Duration::from_secs(300);
+/// Tracks shorter than this aren't scrobbled
+const SCROBBLE_MIN_LENGTH: std::time::Duration = std::time::Duration::from_secs(30);
+/// A track counts as listened to once half of it, or this much, has played
+const SCROBBLE_MIN_PLAYED: std::time::Duration = std::time::Duration::from_secs(240);
+
+/// Last.fm credentials: the API account's key and secret, and the session key
+/// a user gave it through Last.fm's authentication
+#[derive(Clone, Debug)]
+pub(crate) struct LastfmConfig {
+    pub(crate) api_key: String,
+    pub(crate) secret: String,
+    pub(crate) session_key: String,
+}
+
+/// Where listens are submitted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ScrobbleService {
+    Lastfm,
+    ListenBrainz,
+}
+
+impl ScrobbleService {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            ScrobbleService::Lastfm => "lastfm",
+            ScrobbleService::ListenBrainz => "listenbrainz",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lastfm" => Some(ScrobbleService::Lastfm),
+            "listenbrainz" => Some(ScrobbleService::ListenBrainz),
+            _ => None,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ScrobbleService::Lastfm => "Last.fm",
+            ScrobbleService::ListenBrainz => "ListenBrainz",
+        }
+    }
+}
+
+/// A track being listened to, or listened to
+#[derive(Clone, Debug)]
+struct Listen {
+    /// When playback started, in seconds since the Unix epoch
+    started: u64,
+    artist: String,
+    title: String,
+    album: Option<String>,
+    duration: Option<std::time::Duration>,
+}
+
+impl Listen {
+    /// The listen of `tags` starting now; tracks without artist and title
+    /// can't be scrobbled, nor ones that are too short
+    fn new(tags: &TrackTags) -> Option<Self> {
+        if tags
+            .duration
+            .is_some_and(|duration| duration < SCROBBLE_MIN_LENGTH)
+        {
+            return None;
+        }
+        Some(Listen {
+            started: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            artist: tags.artist.clone()?,
+            title: tags.title.clone()?,
+            album: tags.album.clone(),
+            duration: tags.duration,
+        })
+    }
+
+    /// Whether `played` of the track is enough to scrobble it
+    fn counts(&self, played: std::time::Duration) -> bool {
+        let needed = self.duration.map_or(SCROBBLE_MIN_PLAYED, |duration| {
+            (duration / 2).min(SCROBBLE_MIN_PLAYED)
+        });
+        played >= needed
+    }
+
+    /// One `service<TAB>started<TAB>duration<TAB>artist<TAB>title<TAB>album`
+    /// line of the listens waiting to be submitted
+    fn to_line(&self, service: ScrobbleService) -> String {
+        let fields = [
+            service.name().to_string(),
+            self.started.to_string(),
+            self.duration
+                .map(|duration| duration.as_secs().to_string())
+                .unwrap_or_default(),
+            self.artist.clone(),
+            self.title.clone(),
+            self.album.clone().unwrap_or_default(),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| {
+                field
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .collect()
+            })
+            .collect();
+        format!("{}\n", fields.join("\t"))
+    }
+
+    fn from_line(line: &str) -> Option<(ScrobbleService, Listen)> {
+        let mut fields = line.splitn(6, '\t');
+        let service = ScrobbleService::from_name(fields.next()?)?;
+        let started = fields.next()?.parse().ok()?;
+        let duration = fields
+            .next()?
+            .parse()
+            .ok()
+            .map(std::time::Duration::from_secs);
+        let listen = Listen {
+            started,
+            duration,
+            artist: fields.next()?.to_string(),
+            title: fields.next()?.to_string(),
+            album: fields
+                .next()
+                .filter(|album| !album.is_empty())
+                .map(str::to_string),
+        };
+        Some((service, listen))
+    }
+}
+
+/// What the daemon tells the submitting thread
+enum ScrobbleEvent {
+    NowPlaying(Listen),
+    Listened(Listen),
+}
+
+/// Why a submission didn't go through
+enum ScrobbleError {
+    /// The service couldn't be reached or was busy, so it's worth trying again
+    Retry(String),
+    /// The service turned the submission down
+    Rejected(String),
+}
+
+fn scrobble_queue_path() -> Option<std::path::PathBuf> {
+    Some(state_dir()?.join("mogbox").join("scrobbles.tsv"))
+}
+
+/// Follows what the daemon plays and hands listens to the submitting thread
+pub(crate) struct Scrobbler {
+    events: std::sync::mpsc::Sender<ScrobbleEvent>,
+    submitter: std::thread::JoinHandle<()>,
+    /// The track playing, with how far playback got
+    current: Option<(Listen, std::time::Duration)>,
+}
+
+impl Scrobbler {
+    /// Starts submitting to the services there are credentials for; `None`
+    /// when there are none
+    pub(crate) fn start(
+        lastfm: Option<LastfmConfig>,
+        listenbrainz: Option<String>,
+    ) -> Option<Self> {
+        let mut submitter = ScrobbleSubmitter {
+            lastfm,
+            listenbrainz,
+            path: scrobble_queue_path(),
+            pending: Vec::new(),
+        };
+        if submitter.services().is_empty() {
+            return None;
+        }
+        if let Some(path) = &submitter.path {
+            match std::fs::read_to_string(path) {
+                Ok(text) => {
+                    submitter.pending = text.lines().filter_map(Listen::from_line).collect()
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("Error scrobbling: failed to read {}: {}", path.display(), e),
+            }
+        }
+        let names: Vec<&str> = submitter
+            .services()
+            .iter()
+            .map(|service| service.title())
+            .collect();
+        println!("Scrobbling to {}", names.join(" and "));
+        let (events, receiver) = std::sync::mpsc::channel();
+        let submitter = std::thread::spawn(move || submitter.run(receiver));
+        Some(Scrobbler {
+            events,
+            submitter,
+            current: None,
+        })
+    }
+
+    /// Moves on to the track of `tags`; the one before was played to the end
+    pub(crate) fn start_track(&mut self, tags: &TrackTags) {
+        self.finish();
+        if let Some(listen) = Listen::new(tags) {
+            let _ = self.events.send(ScrobbleEvent::NowPlaying(listen.clone()));
+            self.current = Some((listen, std::time::Duration::ZERO));
+        }
+    }
+
+    /// Notes that playback of the current track got to `position`
+    pub(crate) fn update(&mut self, position: std::time::Duration) {
+        if let Some((_, played)) = &mut self.current {
+            *played = (*played).max(position);
+        }
+    }
+
+    /// Submits the current track if it played far enough before being left
+    /// at `position`
+    pub(crate) fn stop_track(&mut self, position: std::time::Duration) {
+        self.update(position);
+        if let Some((listen, played)) = self.current.take() {
+            if listen.counts(played) {
+                let _ = self.events.send(ScrobbleEvent::Listened(listen));
+            }
+        }
+    }
+
+    /// Submits the current track, which was played to the end
+    pub(crate) fn finish(&mut self) {
+        let end = self
+            .current
+            .as_ref()
+            .and_then(|(listen, _)| listen.duration);
+        self.stop_track(end.unwrap_or_default());
+    }
+
+    /// Waits for the listens handed over so far to be submitted or saved
+    pub(crate) fn close(self) {
+        drop(self.events);
+        let _ = self.submitter.join();
+    }
+}
+
+/// Submits listens on its own thread, keeping those that didn't go through
+struct ScrobbleSubmitter {
+    lastfm: Option<LastfmConfig>,
+    /// ListenBrainz user token
+    listenbrainz: Option<String>,
+    /// Where listens waiting to be submitted are kept
+    path: Option<std::path::PathBuf>,
+    pending: Vec<(ScrobbleService, Listen)>,
+}
+
+impl ScrobbleSubmitter {
+    fn services(&self) -> Vec<ScrobbleService> {
+        let mut services = Vec::new();
+        if self.lastfm.is_some() {
+            services.push(ScrobbleService::Lastfm);
+        }
+        if self.listenbrainz.is_some() {
+            services.push(ScrobbleService::ListenBrainz);
+        }
+        services
+    }
+
+    fn run(mut self, events: std::sync::mpsc::Receiver<ScrobbleEvent>) {
+        self.submit_pending();
+        loop {
+            match events.recv_timeout(SCROBBLE_RETRY) {
+                Ok(ScrobbleEvent::NowPlaying(listen)) => {
+                    for service in self.services() {
+                        match self.submit(service, &listen, true) {
+                            Ok(()) => {}
+                            Err(ScrobbleError::Retry(e) | ScrobbleError::Rejected(e)) => {
+                                eprintln!("Error scrobbling: {}", e)
+                            }
+                        }
+                    }
+                }
+                Ok(ScrobbleEvent::Listened(listen)) => {
+                    for service in self.services() {
+                        self.pending.push((service, listen.clone()));
+                    }
+                    self.save();
+                    self.submit_pending();
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => self.submit_pending(),
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    /// Submits the listens waiting, oldest first, until a service can't be
+    /// reached; listens it turns down are dropped
+    fn submit_pending(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let mut unreachable: Vec<ScrobbleService> = Vec::new();
+        let mut kept = Vec::new();
+        for (service, listen) in std::mem::take(&mut self.pending) {
+            if unreachable.contains(&service) {
+                kept.push((service, listen));
+                continue;
+            }
+            match self.submit(service, &listen, false) {
+                Ok(()) => {}
+                Err(ScrobbleError::Retry(e)) => {
+                    eprintln!("Error scrobbling: {}, trying again later", e);
+                    unreachable.push(service);
+                    kept.push((service, listen));
+                }
+                Err(ScrobbleError::Rejected(e)) => eprintln!("Error scrobbling: {}", e),
+            }
+        }
+        self.pending = kept;
+        self.save();
+    }
+
+    /// Writes out the listens waiting, removing the file once there are none
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = match self.pending.is_empty() {
+            true => match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            false => {
+                let text: String = self
+                    .pending
+                    .iter()
+                    .map(|(service, listen)| listen.to_line(*service))
+                    .collect();
+                if let Some(dir) = path.parent() {
+                    let _ = std::fs::create_dir_all(dir);
+                }
+                std::fs::write(path, text)
+            }
+        };
+        if let Err(e) = result {
+            eprintln!(
+                "Error scrobbling: failed to write {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    /// Submits `listen` to `service`, as the track playing now or as one
+    /// listened to
+    fn submit(
+        &self,
+        service: ScrobbleService,
+        listen: &Listen,
+        now_playing: bool,
+    ) -> Result<(), ScrobbleError> {
+        let (url, headers, form, body) = match (service, &self.lastfm, &self.listenbrainz) {
+            (ScrobbleService::Lastfm, Some(lastfm), _) => {
+                let form = lastfm_params(lastfm, listen, now_playing);
+                (LASTFM_URL, Vec::new(), form, None)
+            }
+            (ScrobbleService::ListenBrainz, _, Some(token)) => {
+                let headers = vec![
+                    format!("Authorization: Token {}", token),
+                    "Content-Type: application/json".to_string(),
+                ];
+                let body = listenbrainz_body(listen, now_playing).to_string();
+                (LISTENBRAINZ_URL, headers, Vec::new(), Some(body))
+            }
+            _ => return Ok(()),
+        };
+
+        // The request goes in through standard input, keeping the credentials
+        // off the command line
+        let mut request = format!("url = {}\n", curl_quote(url));
+        for header in &headers {
+            request.push_str(&format!("header = {}\n", curl_quote(header)));
+        }
+        for (name, value) in &form {
+            let param = format!("{}={}", name, value);
+            request.push_str(&format!("data-urlencode = {}\n", curl_quote(&param)));
+        }
+        if let Some(body) = &body {
+            request.push_str(&format!("data-raw = {}\n", curl_quote(body)));
+        }
+        let output = run_curl_config(&request).map_err(|e| {
+            ScrobbleError::Retry(format!("{} request failed: {}", service.title(), e))
+        })?;
+        let (response, status) = output.rsplit_once('\n').unwrap_or(("", &output));
+        let status: u16 = status.trim().parse().unwrap_or(0);
+        match status {
+            200..=299 => Ok(()),
+            // Rate limited or down for now
+            429 | 500..=599 => Err(ScrobbleError::Retry(format!(
+                "{} is unavailable (HTTP {})",
+                service.title(),
+                status
+            ))),
+            _ => Err(ScrobbleError::Rejected(format!(
+                "{} rejected the listen of {} - {} (HTTP {}): {}",
+                service.title(),
+                listen.artist,
+                listen.title,
+                status,
+                response.trim()
+            ))),
+        }
+    }
+}
+
+/// Parameters of a signed Last.fm `track.updateNowPlaying` or `track.scrobble`
+/// request
+fn lastfm_params(
+    lastfm: &LastfmConfig,
+    listen: &Listen,
+    now_playing: bool,
+) -> Vec<(String, String)> {
+    let method = match now_playing {
+        true => "track.updateNowPlaying",
+        false => "track.scrobble",
+    };
+    let mut params: Vec<(String, String)> = vec![
+        ("method".to_string(), method.to_string()),
+        ("api_key".to_string(), lastfm.api_key.clone()),
+        ("sk".to_string(), lastfm.session_key.clone()),
+        ("artist".to_string(), listen.artist.clone()),
+        ("track".to_string(), listen.title.clone()),
+    ];
+    if let Some(album) = &listen.album {
+        params.push(("album".to_string(), album.clone()));
+    }
+    if let Some(duration) = listen.duration {
+        params.push(("duration".to_string(), duration.as_secs().to_string()));
+    }
+    if !now_playing {
+        params.push(("timestamp".to_string(), listen.started.to_string()));
+    }
+    // Signed with every parameter, sorted by name, then the secret
+    params.sort();
+    let mut signed = String::new();
+    for (name, value) in &params {
+        signed.push_str(name);
+        signed.push_str(value);
+    }
+    signed.push_str(&lastfm.secret);
+    let signature: String = md5(signed.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    params.push(("api_sig".to_string(), signature));
+    params.push(("format".to_string(), "json".to_string()));
+    params
+}
+
+/// Body of a ListenBrainz `playing_now` or `single` submission
+fn listenbrainz_body(listen: &Listen, now_playing: bool) -> Json {
+    let mut info = vec![
+        ("media_player", "mogbox".into()),
+        ("submission_client", "mogbox".into()),
+        (
+            "submission_client_version",
+            env!("CARGO_PKG_VERSION").into(),
+        ),
+    ];
+    if let Some(duration) = listen.duration {
+        info.push(("duration_ms", (duration.as_millis() as u64).into()));
+    }
+    let mut metadata = vec![
+        ("artist_name", listen.artist.as_str().into()),
+        ("track_name", listen.title.as_str().into()),
+    ];
+    if let Some(album) = &listen.album {
+        metadata.push(("release_name", album.as_str().into()));
+    }
+    metadata.push(("additional_info", Json::object(info)));
+    let mut payload = Vec::new();
+    if !now_playing {
+        payload.push(("listened_at", listen.started.into()));
+    }
+    payload.push(("track_metadata", Json::object(metadata)));
+    let listen_type = match now_playing {
+        true => "playing_now",
+        false => "single",
+    };
+    Json::object([
+        ("listen_type", listen_type.into()),
+        ("payload", Json::Array(vec![Json::object(payload)])),
+    ])
+}
+
+/// Quotes `text` as a value in a curl config file
+fn curl_quote(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Runs curl with `config` as its config file, returning the response body
+/// followed by a line with the HTTP status
+fn run_curl_config(config: &str) -> Result<String, String> {
+    use std::io::Write;
+
+    let mut child = http::curl()?
+        .args(["--silent", "--show-error", "--compressed"])
+        .args(["--max-time", &SCROBBLE_TIMEOUT.to_string()])
+        .args(["--write-out", "\\n%{http_code}"])
+        .args(["--config", "-"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(http::curl_error)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(config.as_bytes())
+            .map_err(|e| format!("failed to run curl: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// MD5 digest of `data`, for signing Last.fm requests
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [[u32; 4]; 4] = [
+        [7, 12, 17, 22],
+        [5, 9, 14, 20],
+        [4, 11, 16, 23],
+        [6, 10, 15, 21],
+    ];
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    // Padded like SHA-1, but with the length little-endian
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_le_bytes());
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 16];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i {
+                0..=15 => ((b & c) | (!b & d), i),
+                16..=31 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                32..=47 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            // The integer part of 2^32 times |sin(i + 1)|
+            let k = (((i + 1) as f64).sin().abs() * 4294967296.0) as u32;
+            let next = a
+                .wrapping_add(f)
+                .wrapping_add(k)
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[i / 16][i % 4]);
+            (a, b, c, d) = (d, b.wrapping_add(next), b, c);
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+    let mut digest = [0u8; 16];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: [u8; 16]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn listen() -> Listen {
+        Listen {
+            started: 1_700_000_000,
+            artist: "Artist".to_string(),
+            title: "Title".to_string(),
+            album: Some("Album".to_string()),
+            duration: Some(std::time::Duration::from_secs(200)),
+        }
+    }
+
+    fn lastfm() -> LastfmConfig {
+        LastfmConfig {
+            api_key: "key".to_string(),
+            secret: "secret".to_string(),
+            session_key: "session".to_string(),
+        }
+    }
+
+    #[test]
+    fn md5_known_answers() {
+        assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            hex(md5(b"message digest")),
+            "f96b697d7cb7938d525a2f31aaf161d0"
+        );
+        // Long enough to need more than one block
+        assert_eq!(hex(md5(&[b'a'; 1000])), "cabe45dcc9ae5b66ba86600cca6b8ba8");
+    }
+
+    #[test]
+    fn service_names() {
+        for service in [ScrobbleService::Lastfm, ScrobbleService::ListenBrainz] {
+            assert_eq!(ScrobbleService::from_name(service.name()), Some(service));
+        }
+        assert_eq!(ScrobbleService::from_name("librefm"), None);
+    }
+
+    #[test]
+    fn listens_count_after_half_or_four_minutes() {
+        let short = listen();
+        assert!(!short.counts(std::time::Duration::from_secs(99)));
+        assert!(short.counts(std::time::Duration::from_secs(100)));
+        let long = Listen {
+            duration: Some(std::time::Duration::from_secs(3600)),
+            ..listen()
+        };
+        assert!(!long.counts(std::time::Duration::from_secs(239)));
+        assert!(long.counts(SCROBBLE_MIN_PLAYED));
+        let unknown = Listen {
+            duration: None,
+            ..listen()
+        };
+        assert!(unknown.counts(SCROBBLE_MIN_PLAYED));
+    }
+
+    #[test]
+    fn listen_lines_round_trip() {
+        let line = listen().to_line(ScrobbleService::ListenBrainz);
+        assert_eq!(
+            line,
+            "listenbrainz\t1700000000\t200\tArtist\tTitle\tAlbum\n"
+        );
+        let (service, parsed) = Listen::from_line(line.trim_end()).unwrap();
+        assert_eq!(service, ScrobbleService::ListenBrainz);
+        assert_eq!(parsed.started, 1_700_000_000);
+        assert_eq!(parsed.artist, "Artist");
+        assert_eq!(parsed.title, "Title");
+        assert_eq!(parsed.album.as_deref(), Some("Album"));
+        assert_eq!(parsed.duration, Some(std::time::Duration::from_secs(200)));
+
+        // Tabs and newlines in tags can't break the line apart
+        let odd = Listen {
+            title: "A\tB\nC".to_string(),
+            album: None,
+            duration: None,
+            ..listen()
+        };
+        let line = odd.to_line(ScrobbleService::Lastfm);
+        assert_eq!(line, "lastfm\t1700000000\t\tArtist\tA B C\t\n");
+        let (_, parsed) = Listen::from_line(line.trim_end_matches('\n')).unwrap();
+        assert_eq!(parsed.title, "A B C");
+        assert_eq!(parsed.album, None);
+        assert_eq!(parsed.duration, None);
+
+        assert!(Listen::from_line("spotify\t1\t2\tArtist\tTitle\t").is_none());
+        assert!(Listen::from_line("lastfm\tsoon\t2\tArtist\tTitle\t").is_none());
+        assert!(Listen::from_line("lastfm\t1\t2\tArtist").is_none());
+    }
+
+    #[test]
+    fn lastfm_params_are_sorted_and_signed() {
+        let params = lastfm_params(&lastfm(), &listen(), false);
+        let names: Vec<&str> = params.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "album",
+                "api_key",
+                "artist",
+                "duration",
+                "method",
+                "sk",
+                "timestamp",
+                "track",
+                "api_sig",
+                "format"
+            ]
+        );
+        let value = |name: &str| {
+            params
+                .iter()
+                .find(|(param, _)| param == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(value("method"), Some("track.scrobble"));
+        assert_eq!(value("api_sig"), Some("2fa93f11726e304a4d24c0d1be3c2040"));
+
+        let params = lastfm_params(&lastfm(), &listen(), true);
+        assert!(params
+            .iter()
+            .any(|(name, value)| name == "method" && value == "track.updateNowPlaying"));
+        assert!(!params.iter().any(|(name, _)| name == "timestamp"));
+    }
+
+    #[test]
+    fn listenbrainz_bodies() {
+        assert_eq!(
+            listenbrainz_body(&listen(), false).to_string(),
+            format!(
+                "{{\"listen_type\":\"single\",\"payload\":[{{\"listened_at\":1700000000,\
+                 \"track_metadata\":{{\"artist_name\":\"Artist\",\"track_name\":\"Title\",\
+                 \"release_name\":\"Album\",\"additional_info\":{{\"media_player\":\"mogbox\",\
+                 \"submission_client\":\"mogbox\",\"submission_client_version\":\"{}\",\
+                 \"duration_ms\":200000}}}}}}]}}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        let body = listenbrainz_body(&listen(), true).to_string();
+        assert!(body.starts_with("{\"listen_type\":\"playing_now\""));
+        assert!(!body.contains("listened_at"));
+    }
+
+    #[test]
+    fn curl_quoting() {
+        assert_eq!(curl_quote("plain"), "\"plain\"");
+        assert_eq!(
+            curl_quote("say \"hi\"\\\n\r\t"),
+            "\"say \\\"hi\\\"\\\\\\n\\r\\t\""
+        );
+    }
+
+    #[test]
+    fn no_credentials_no_scrobbler() {
+        assert!(Scrobbler::start(None, None).is_none());
+    }
+}
@@ -0,0 +1,202 @@
+// System Media Transport Controls
+//
+// On Windows, the daemon registers with the System Media Transport Controls,
+// so the media keys control it and the media flyout shows the track, its
+// artist and cover. A console program has no window to register the controls
+// for, so they come from a `MediaPlayer` that plays nothing and has its own
+// handling of the buttons turned off. Button presses arrive on a thread of
+// the system's and are handed to the daemon.
+
+use windows::core::{Ref, HSTRING};
+use windows::Foundation::TypedEventHandler;
+use windows::Media::Playback::MediaPlayer;
+use windows::Media::{
+    MediaPlaybackStatus, MediaPlaybackType, SystemMediaTransportControls,
+    SystemMediaTransportControlsButton, SystemMediaTransportControlsButtonPressedEventArgs,
+};
+use windows::Storage::Streams::{
+    DataWriter, InMemoryRandomAccessStream, RandomAccessStreamReference,
+};
+
+use crate::daemon::{Daemon, MediaKey};
+use crate::track_name;
+
+/// The daemon's media controls
+pub(crate) struct Smtc {
+    /// Owner of the controls, which go away with it
+    _player: MediaPlayer,
+    controls: SystemMediaTransportControls,
+    keys: std::sync::mpsc::Receiver<MediaKey>,
+    /// What the controls show, to update them only when it changes
+    last: Option<SmtcState>,
+}
+
+/// What the controls show
+#[derive(Clone, Debug, PartialEq)]
+struct SmtcState {
+    status: MediaPlaybackStatus,
+    /// The track playing, if any
+    path: Option<std::path::PathBuf>,
+    has_next: bool,
+    has_previous: bool,
+}
+
+impl Smtc {
+    /// Registers with the controls
+    pub(crate) fn start() -> Result<Self, String> {
+        let (sender, keys) = std::sync::mpsc::channel();
+        let player = MediaPlayer::new()
+            .map_err(|e| format!("failed to register the media controls: {}", e))?;
+        let controls = (|| {
+            player.CommandManager()?.SetIsEnabled(false)?;
+            let controls = player.SystemMediaTransportControls()?;
+            controls.SetIsEnabled(true)?;
+            controls.SetIsPlayEnabled(true)?;
+            controls.SetIsPauseEnabled(true)?;
+            controls.SetIsStopEnabled(true)?;
+            let handler = TypedEventHandler::new(
+                move |_, args: Ref<SystemMediaTransportControlsButtonPressedEventArgs>| {
+                    if let Some(key) = media_key(args.ok()?.Button()?) {
+                        let _ = sender.send(key);
+                    }
+                    Ok(())
+                },
+            );
+            controls.ButtonPressed(&handler)?;
+            Ok(controls)
+        })()
+        .map_err(|e: windows::core::Error| {
+            format!("failed to register the media controls: {}", e)
+        })?;
+        Ok(Smtc {
+            _player: player,
+            controls,
+            keys,
+            last: None,
+        })
+    }
+}
+
+/// The media key a button of the controls stands for
+fn media_key(button: SystemMediaTransportControlsButton) -> Option<MediaKey> {
+    match button {
+        SystemMediaTransportControlsButton::Play => Some(MediaKey::Play),
+        SystemMediaTransportControlsButton::Pause => Some(MediaKey::Pause),
+        SystemMediaTransportControlsButton::Stop => Some(MediaKey::Stop),
+        SystemMediaTransportControlsButton::Next => Some(MediaKey::Next),
+        SystemMediaTransportControlsButton::Previous => Some(MediaKey::Previous),
+        _ => None,
+    }
+}
+
+/// A stream of `data` for the flyout to read the cover from
+fn thumbnail(data: &[u8]) -> windows::core::Result<RandomAccessStreamReference> {
+    let stream = InMemoryRandomAccessStream::new()?;
+    let writer = DataWriter::CreateDataWriter(&stream)?;
+    writer.WriteBytes(data)?;
+    writer.StoreAsync()?.join()?;
+    // Leaves the stream open once the writer goes
+    writer.DetachStream()?;
+    stream.Seek(0)?;
+    RandomAccessStreamReference::CreateFromStream(&stream)
+}
+
+impl Daemon {
+    /// Acts on the buttons pressed, and shows what changed since the last call
+    pub(crate) fn serve_smtc(&mut self) {
+        let Some(mut smtc) = self.smtc.take() else {
+            return;
+        };
+        let (track, position) = (self.player.current_track(), self.player.position());
+        while let Ok(key) = smtc.keys.try_recv() {
+            if let Err(e) = self.media_key(key) {
+                eprintln!("Error: {}", e);
+            }
+        }
+        self.left_track(track, position);
+        if let Err(e) = self.smtc_update(&mut smtc) {
+            eprintln!("Error updating the media controls: {}", e);
+        }
+        self.smtc = Some(smtc);
+    }
+
+    /// What the controls are to show now
+    fn smtc_state(&self) -> SmtcState {
+        let status = match (self.player.is_playing(), self.player.is_paused()) {
+            (false, _) => MediaPlaybackStatus::Stopped,
+            (true, true) => MediaPlaybackStatus::Paused,
+            (true, false) => MediaPlaybackStatus::Playing,
+        };
+        let current = self.player.current_track();
+        let queue = self.player.queue();
+        let queue = queue.lock().unwrap();
+        SmtcState {
+            status,
+            path: self
+                .player
+                .current_path()
+                .filter(|_| self.player.is_playing()),
+            has_next: current.is_some_and(|index| queue.index_after(index).is_some()),
+            has_previous: current.is_some_and(|index| queue.index_before(index).is_some()),
+        }
+    }
+
+    /// Shows the track and whether it plays, if either changed
+    fn smtc_update(&mut self, smtc: &mut Smtc) -> windows::core::Result<()> {
+        let state = self.smtc_state();
+        if smtc.last.as_ref() == Some(&state) {
+            return Ok(());
+        }
+        // Not tried again until something else changes, should it fail
+        let last = smtc.last.replace(state.clone());
+        let controls = &smtc.controls;
+        controls.SetPlaybackStatus(state.status)?;
+        controls.SetIsNextEnabled(state.has_next)?;
+        controls.SetIsPreviousEnabled(state.has_previous)?;
+        if last.map(|last| last.path) != Some(state.path.clone()) {
+            let display = controls.DisplayUpdater()?;
+            display.ClearAll()?;
+            if let Some(path) = &state.path {
+                display.SetType(MediaPlaybackType::Music)?;
+                let tags = self.track_tags(path);
+                let music = display.MusicProperties()?;
+                let title = tags.title.clone().unwrap_or_else(|| track_name(path));
+                music.SetTitle(&HSTRING::from(title))?;
+                if let Some(artist) = &tags.artist {
+                    music.SetArtist(&HSTRING::from(artist))?;
+                }
+                if let Some(album_artist) = &tags.album_artist {
+                    music.SetAlbumArtist(&HSTRING::from(album_artist))?;
+                }
+                if let Some(album) = &tags.album {
+                    music.SetAlbumTitle(&HSTRING::from(album))?;
+                }
+                if let Some(track) = tags.track {
+                    music.SetTrackNumber(track)?;
+                }
+                if let Some(cover) = &tags.cover {
+                    display.SetThumbnail(&thumbnail(cover)?)?;
+                }
+            }
+            display.Update()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_map_to_media_keys() {
+        let next = SystemMediaTransportControlsButton::Next;
+        assert_eq!(media_key(next), Some(MediaKey::Next));
+        let pause = SystemMediaTransportControlsButton::Pause;
+        assert_eq!(media_key(pause), Some(MediaKey::Pause));
+        // Nothing to record, and no channels to change
+        assert_eq!(media_key(SystemMediaTransportControlsButton::Record), None);
+        let channel = SystemMediaTransportControlsButton::ChannelUp;
+        assert_eq!(media_key(channel), None);
+    }
+}
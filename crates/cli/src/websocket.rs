@@ -0,0 +1,387 @@
+// WebSocket events
+//
+// `GET /events` on the REST API upgrades to a WebSocket carrying JSON text
+// messages: `status` and `queue` events as they change, and `levels` of the
+// output ten times a second. Clients only ever send pings and closes.
+// Handshakes from pages on other sites are refused as other REST requests are.
+
+use mogbox_analysis::LevelMeter;
+use mogbox_runtime::Tap;
+
+use crate::daemon::{daemon_queue_json, Daemon, DaemonStatus};
+use crate::json::Json;
+use crate::METER_WINDOW;
+
+/// How often `/events` clients get the levels of the output
+const EVENTS_LEVELS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+/// How often `/events` clients get the status while only the position changes
+const EVENTS_STATUS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// What a WebSocket handshake appends to the client's key before hashing it
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Longest message taken from a WebSocket client, which only sends pings and closes
+const WEBSOCKET_MAX_MESSAGE: u64 = 64 * 1024;
+
+/// WebSocket clients following playback on `/events`. Each is written to by
+/// a thread of its own, so a slow one doesn't hold the daemon up.
+pub(crate) struct EventClients {
+    clients: Vec<std::sync::mpsc::Sender<Vec<u8>>>,
+    /// The output, for the levels
+    tap: Option<Tap>,
+    meter: LevelMeter,
+    last_levels: std::time::Instant,
+    /// Status sent last, without the position, and when
+    last_status: Option<(String, std::time::Instant)>,
+    last_queue: Option<String>,
+}
+
+impl EventClients {
+    pub(crate) fn new(tap: Option<Tap>) -> Self {
+        EventClients {
+            clients: Vec::new(),
+            tap,
+            meter: LevelMeter::new(),
+            last_levels: std::time::Instant::now(),
+            last_status: None,
+            last_queue: None,
+        }
+    }
+
+    /// Completes the handshake of a client that sent `key` and starts sending
+    /// it events
+    pub(crate) fn accept(&mut self, mut stream: std::net::TcpStream, key: &str) {
+        use base64::Engine;
+        use std::io::Write;
+
+        let digest = sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes());
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            base64::engine::general_purpose::STANDARD.encode(digest)
+        );
+        if stream.write_all(response.as_bytes()).is_err() {
+            return;
+        }
+        let Ok(reader) = stream.try_clone() else {
+            return;
+        };
+        let _ = reader.set_read_timeout(None);
+        let (sender, frames) = std::sync::mpsc::channel::<Vec<u8>>();
+        let replies = sender.clone();
+        std::thread::spawn(move || read_websocket(reader, replies));
+        std::thread::spawn(move || {
+            for frame in frames {
+                let close = frame.first() == Some(&0x88);
+                if stream.write_all(&frame).is_err() || close {
+                    break;
+                }
+            }
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        });
+        self.clients.push(sender);
+        // Everyone gets the status and queue again, so the new client has them
+        self.last_status = None;
+        self.last_queue = None;
+    }
+
+    /// Sends `event` as a text message to every client still connected
+    fn send(&mut self, event: &Json) {
+        let frame = websocket_frame(0x1, event.to_string().as_bytes());
+        self.clients
+            .retain(|client| client.send(frame.clone()).is_ok());
+    }
+
+    /// Sends the levels of the output now and then; they fall back to the
+    /// floor while `playing` is false
+    fn push_levels(&mut self, playing: bool) {
+        let Some(tap) = &self.tap else {
+            return;
+        };
+        let elapsed = self.last_levels.elapsed();
+        if elapsed < EVENTS_LEVELS_INTERVAL {
+            return;
+        }
+        self.last_levels = std::time::Instant::now();
+        let samples = match playing {
+            true => {
+                let frames = METER_WINDOW.as_secs_f64() * tap.sample_rate() as f64;
+                tap.latest(frames.ceil() as usize)
+            }
+            false => Vec::new(),
+        };
+        self.meter
+            .update(&samples, tap.channels(), tap.sample_rate(), elapsed);
+        let channels: Vec<Json> = self
+            .meter
+            .levels()
+            .iter()
+            .map(|level| {
+                Json::object([
+                    ("peak", Json::rounded(level.peak, 1)),
+                    ("rms", Json::rounded(level.rms, 1)),
+                    ("hold", Json::rounded(level.hold, 1)),
+                ])
+            })
+            .collect();
+        self.send(&Json::object([
+            ("event", Json::from("levels")),
+            ("channels", Json::from(channels)),
+        ]));
+    }
+}
+
+impl Daemon {
+    /// Sends `/events` clients the status and queue when they change, the
+    /// position now and then, and the levels
+    pub(crate) fn push_events(&mut self) {
+        if self.events.clients.is_empty() {
+            return;
+        }
+        let body = self.status_lines();
+        // The position moves on all the time, so alone it only counts now and then
+        let status: String = body
+            .lines()
+            .filter(|line| !line.starts_with("position\t"))
+            .collect();
+        let due =
+            self.events.last_status.as_ref().is_none_or(|(last, sent)| {
+                *last != status || sent.elapsed() >= EVENTS_STATUS_INTERVAL
+            });
+        if due {
+            self.events.last_status = Some((status, std::time::Instant::now()));
+            let members = DaemonStatus::parse(&body).members();
+            let event = std::iter::once(("event", Json::from("status"))).chain(members);
+            self.events.send(&Json::object(event));
+        }
+        let queue = self.queue_lines();
+        if self.events.last_queue.as_ref() != Some(&queue) {
+            self.events.send(&Json::object([
+                ("event", Json::from("queue")),
+                ("tracks", daemon_queue_json(&queue)),
+            ]));
+            self.events.last_queue = Some(queue);
+        }
+        let playing = self.player.is_playing() && !self.player.is_paused();
+        self.events.push_levels(playing);
+    }
+}
+
+/// Reads a WebSocket client's messages, answering pings and closing
+/// handshakes through `replies`
+fn read_websocket(mut stream: std::net::TcpStream, replies: std::sync::mpsc::Sender<Vec<u8>>) {
+    use std::io::Read;
+
+    let mut read = |count: usize| {
+        let mut bytes = vec![0u8; count];
+        stream.read_exact(&mut bytes).ok().map(|_| bytes)
+    };
+    while let Some(header) = read(2) {
+        let opcode = header[0] & 0x0f;
+        let length = match header[1] & 0x7f {
+            126 => read(2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as u64),
+            127 => read(8).map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap_or_default())),
+            length => Some(length as u64),
+        };
+        let Some(length) = length else {
+            return;
+        };
+        if length > WEBSOCKET_MAX_MESSAGE {
+            // 1009: message too big
+            let _ = replies.send(websocket_frame(0x8, &1009u16.to_be_bytes()));
+            return;
+        }
+        // Clients always mask what they send
+        let mask = match header[1] & 0x80 {
+            0 => vec![0; 4],
+            _ => match read(4) {
+                Some(mask) => mask,
+                None => return,
+            },
+        };
+        let Some(mut payload) = read(length as usize) else {
+            return;
+        };
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        let reply = match opcode {
+            // Closing echoes the status code back
+            0x8 => {
+                let _ = replies.send(websocket_frame(0x8, &payload[..payload.len().min(2)]));
+                return;
+            }
+            0x9 => websocket_frame(0xa, &payload),
+            _ => continue,
+        };
+        if replies.send(reply).is_err() {
+            return;
+        }
+    }
+}
+
+/// A final, unmasked WebSocket frame as a server sends it
+fn websocket_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length if length < 126 => frame.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// SHA-1 digest of `data`, for the WebSocket handshake
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    // Padded with a 1 bit and zeros to 8 bytes short of a block, then the
+    // length in bits
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let next = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, next);
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn sha1_known_answers() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(
+            hex(&sha1(&[b'a'; 1_000_000])),
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+        );
+    }
+
+    #[test]
+    fn handshake_accepts_the_rfc_example() {
+        use base64::Engine;
+
+        // RFC 6455, section 1.3
+        let digest = sha1(format!("{}{}", "dGhlIHNhbXBsZSBub25jZQ==", WEBSOCKET_GUID).as_bytes());
+        assert_eq!(
+            base64::engine::general_purpose::STANDARD.encode(digest),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn frames_carry_their_length() {
+        assert_eq!(websocket_frame(0x1, b"hi"), [0x81, 2, b'h', b'i']);
+        let frame = websocket_frame(0x2, &[0; 300]);
+        assert_eq!(frame[..4], [0x82, 126, 1, 44]);
+        assert_eq!(frame.len(), 4 + 300);
+        let frame = websocket_frame(0x2, &[0; 70_000]);
+        assert_eq!(frame[..10], [0x82, 127, 0, 0, 0, 0, 0, 1, 0x11, 0x70]);
+        assert_eq!(frame.len(), 10 + 70_000);
+    }
+
+    /// A frame as a client sends it, masked
+    fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    #[test]
+    fn pings_and_closes_are_answered() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let (replies, received) = std::sync::mpsc::channel();
+        let reader = std::thread::spawn(move || read_websocket(server, replies));
+        client.write_all(&client_frame(0x9, b"hi")).unwrap();
+        client.write_all(&client_frame(0x1, b"ignored")).unwrap();
+        client
+            .write_all(&client_frame(0x8, &1000u16.to_be_bytes()))
+            .unwrap();
+        reader.join().unwrap();
+        let replies: Vec<Vec<u8>> = received.iter().collect();
+        assert_eq!(
+            replies,
+            [vec![0x8a, 2, b'h', b'i'], vec![0x88, 2, 0x03, 0xe8]]
+        );
+    }
+
+    #[test]
+    fn oversized_messages_close_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let (replies, received) = std::sync::mpsc::channel();
+        let reader = std::thread::spawn(move || read_websocket(server, replies));
+        let mut header = vec![0x81, 0x80 | 127];
+        header.extend_from_slice(&(WEBSOCKET_MAX_MESSAGE + 1).to_be_bytes());
+        client.write_all(&header).unwrap();
+        reader.join().unwrap();
+        let replies: Vec<Vec<u8>> = received.iter().collect();
+        assert_eq!(replies, [vec![0x88, 2, 0x03, 0xf1]]);
+    }
+}
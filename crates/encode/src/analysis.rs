@@ -0,0 +1,290 @@
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+
+use mogbox_engine::{
+    linear_to_db, ChannelStats, DynamicRangeMeter, LoudnessMeter, SilenceDetector, StatsMeter,
+    TruePeakMeter,
+};
+use mogbox_io::AudioFile;
+
+use crate::convert::FrameReader;
+
+/// EBU R128 loudness figures of a file. Values are `None` when the audio
+/// is too short or too quiet to measure.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LoudnessReport {
+    /// Integrated loudness in LUFS
+    pub integrated: Option<f32>,
+    /// Loudness range in LU
+    pub range: Option<f32>,
+    /// Highest momentary (400 ms) loudness in LUFS
+    pub momentary_max: Option<f32>,
+    /// Highest short-term (3 s) loudness in LUFS
+    pub short_term_max: Option<f32>,
+    /// True peak in dBTP
+    pub true_peak: Option<f32>,
+}
+
+/// Measures the loudness of a whole file
+pub fn loudness_report(path: &Path) -> Result<LoudnessReport, String> {
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let channels = file.channels as usize;
+    let mut meter = LoudnessMeter::new(file.sample_rate, channels);
+    let mut peak = TruePeakMeter::new(channels);
+    let mut reader = FrameReader::new(&mut file);
+    while let Some(samples) = reader.next(None)? {
+        meter.process(&samples);
+        peak.process(&samples);
+    }
+
+    Ok(LoudnessReport {
+        integrated: meter.integrated(),
+        range: meter.loudness_range(),
+        momentary_max: meter.momentary_max(),
+        short_term_max: meter.short_term_max(),
+        true_peak: (peak.peak() > 0.0).then(|| linear_to_db(peak.peak())),
+    })
+}
+
+/// DR14-style dynamic range of a file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DynamicRange {
+    /// Dynamic range in dB; the DR score is this rounded
+    pub value: f32,
+    /// Sample peak in dBFS
+    pub peak: f32,
+    /// RMS in dBFS, a full-scale sine reading 0
+    pub rms: f32,
+}
+
+impl DynamicRange {
+    /// The DR score, e.g. 12 for DR12
+    pub fn score(&self) -> u32 {
+        self.value.round().max(0.0) as u32
+    }
+
+    /// Dynamic range of an album: the mean of its tracks'
+    pub fn album(tracks: &[DynamicRange]) -> Option<DynamicRange> {
+        if tracks.is_empty() {
+            return None;
+        }
+        let count = tracks.len() as f32;
+        Some(DynamicRange {
+            value: tracks.iter().map(|track| track.value).sum::<f32>() / count,
+            peak: tracks
+                .iter()
+                .map(|track| track.peak)
+                .fold(f32::MIN, f32::max),
+            rms: linear_to_db(
+                (tracks
+                    .iter()
+                    .map(|track| 10f32.powf(track.rms / 10.0))
+                    .sum::<f32>()
+                    / count)
+                    .sqrt(),
+            ),
+        })
+    }
+}
+
+/// Measures the dynamic range of a whole file
+pub fn dynamic_range(path: &Path) -> Result<DynamicRange, String> {
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let mut meter = DynamicRangeMeter::new(file.sample_rate, file.channels as usize);
+    let mut reader = FrameReader::new(&mut file);
+    while let Some(samples) = reader.next(None)? {
+        meter.process(&samples);
+    }
+
+    let value = meter.dynamic_range().ok_or("silent, nothing to measure")?;
+    Ok(DynamicRange {
+        value,
+        peak: linear_to_db(meter.peak()),
+        rms: linear_to_db(meter.rms()),
+    })
+}
+
+/// Level from which a sample counts as clipped: the highest 16-bit value
+const CLIP_LEVEL: f32 = 32767.0 / 32768.0;
+/// Clipped samples in a row that make a run; a single sample at full scale
+/// is more likely a peak that just fits
+const MIN_RUN: u64 = 2;
+/// Longest runs kept per channel
+const WORST_RUNS: usize = 5;
+
+/// Consecutive clipped samples in one channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClipRun {
+    /// Where the run starts
+    pub start: Duration,
+    /// Length in samples
+    pub length: u64,
+}
+
+/// Clipping found in one channel
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChannelClipping {
+    /// Samples at or beyond full scale
+    pub samples: u64,
+    /// Runs of two or more clipped samples
+    pub runs: u64,
+    /// The longest runs, longest first
+    pub worst: Vec<ClipRun>,
+}
+
+/// Finds the clipped samples of a whole file, per channel
+pub fn clipping_report(path: &Path) -> Result<Vec<ChannelClipping>, String> {
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let channels = file.channels.max(1) as usize;
+    let sample_rate = file.sample_rate.max(1) as f64;
+    let mut report = vec![ChannelClipping::default(); channels];
+    // Frame where the current run of each channel started, and its length
+    let mut current = vec![(0u64, 0u64); channels];
+    let mut frame = 0u64;
+
+    let end_run = |clipping: &mut ChannelClipping, (start, length): (u64, u64)| {
+        if length < MIN_RUN {
+            return;
+        }
+        clipping.runs += 1;
+        let run = ClipRun {
+            start: Duration::from_secs_f64(start as f64 / sample_rate),
+            length,
+        };
+        let index = clipping
+            .worst
+            .partition_point(|worst| worst.length >= length);
+        if index < WORST_RUNS {
+            clipping.worst.insert(index, run);
+            clipping.worst.truncate(WORST_RUNS);
+        }
+    };
+
+    let mut reader = FrameReader::new(&mut file);
+    while let Some(samples) = reader.next(None)? {
+        for samples in samples.chunks_exact(channels) {
+            for (channel, sample) in samples.iter().enumerate() {
+                let run = &mut current[channel];
+                if sample.abs() >= CLIP_LEVEL {
+                    report[channel].samples += 1;
+                    if run.1 == 0 {
+                        run.0 = frame;
+                    }
+                    run.1 += 1;
+                } else if run.1 > 0 {
+                    end_run(&mut report[channel], *run);
+                    run.1 = 0;
+                }
+            }
+            frame += 1;
+        }
+    }
+    for (clipping, run) in report.iter_mut().zip(current) {
+        end_run(clipping, run);
+    }
+    Ok(report)
+}
+
+/// Mean sample value of each channel of a whole file, the DC offset the
+/// recording carries. 0.0 is none, 1.0 full scale.
+pub fn dc_offsets(path: &Path) -> Result<Vec<f32>, String> {
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let channels = file.channels.max(1) as usize;
+    let mut sums = vec![0.0f64; channels];
+    let mut frames = 0u64;
+    let mut reader = FrameReader::new(&mut file);
+    while let Some(samples) = reader.next(None)? {
+        for frame in samples.chunks_exact(channels) {
+            for (sum, sample) in sums.iter_mut().zip(frame) {
+                *sum += *sample as f64;
+            }
+            frames += 1;
+        }
+    }
+    Ok(sums
+        .into_iter()
+        .map(|sum| (sum / frames.max(1) as f64) as f32)
+        .collect())
+}
+
+/// Silent stretches of a file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SilenceReport {
+    /// Start and end of each stretch, in order
+    pub regions: Vec<Range<Duration>>,
+    pub duration: Duration,
+}
+
+impl SilenceReport {
+    /// Combined length of the silent stretches
+    pub fn total(&self) -> Duration {
+        self.regions
+            .iter()
+            .map(|region| region.end - region.start)
+            .sum()
+    }
+}
+
+/// Finds the stretches of a whole file that stay below `threshold_db` for
+/// at least `min_length`, measured over all channels in 10 ms windows
+pub fn silence_report(
+    path: &Path,
+    threshold_db: f32,
+    min_length: Duration,
+) -> Result<SilenceReport, String> {
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let sample_rate = file.sample_rate.max(1) as f64;
+    let mut detector = SilenceDetector::new(
+        file.sample_rate,
+        file.channels as usize,
+        threshold_db,
+        min_length,
+    );
+    let mut reader = FrameReader::new(&mut file);
+    while let Some(samples) = reader.next(None)? {
+        detector.process(&samples);
+    }
+    let time = |frame: u64| Duration::from_secs_f64(frame as f64 / sample_rate);
+    let duration = time(detector.frames());
+    Ok(SilenceReport {
+        regions: detector
+            .finish()
+            .into_iter()
+            .map(|gap| time(gap.start)..time(gap.end))
+            .collect(),
+        duration,
+    })
+}
+
+/// Level statistics of a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsReport {
+    pub sample_rate: u32,
+    /// Length in frames
+    pub frames: u64,
+    pub channels: Vec<ChannelStats>,
+}
+
+impl StatsReport {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+/// Gathers peak, RMS, crest factor, zero crossings and a level histogram
+/// for every channel of a whole file
+pub fn stats_report(path: &Path) -> Result<StatsReport, String> {
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let sample_rate = file.sample_rate;
+    let mut meter = StatsMeter::new(file.channels as usize);
+    let mut reader = FrameReader::new(&mut file);
+    while let Some(samples) = reader.next(None)? {
+        meter.process(&samples);
+    }
+    Ok(StatsReport {
+        sample_rate,
+        frames: meter.frames(),
+        channels: meter.stats(),
+    })
+}
@@ -0,0 +1,103 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::{backend, backend_for_path, convert_with, Edits, EncodeOptions};
+
+/// One file of a batch conversion
+#[derive(Clone, Debug)]
+pub struct BatchJob {
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+/// Works out where each input is written: into `out_dir`, or next to the
+/// input, named after it with the extension of `format`. Without a format
+/// each file keeps its own. Fails before anything is converted if two
+/// inputs would end up at the same output or an input would be overwritten.
+pub fn plan_batch(
+    inputs: &[PathBuf],
+    format: Option<&str>,
+    out_dir: Option<&Path>,
+) -> Result<Vec<BatchJob>, String> {
+    let format = match format {
+        Some(name) => Some(backend(name).ok_or_else(|| format!("unknown format: {}", name))?),
+        None => None,
+    };
+
+    let mut outputs = HashSet::new();
+    let mut jobs = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let extension = match format.as_ref() {
+            Some(backend) => backend.extensions()[0],
+            None => backend_for_path(input)?.extensions()[0],
+        };
+        let name = input
+            .file_stem()
+            .ok_or_else(|| format!("not a file: {}", input.display()))?;
+        let dir = match out_dir {
+            Some(dir) => dir,
+            None => input.parent().unwrap_or(Path::new("")),
+        };
+        let output = dir.join(name).with_extension(extension);
+
+        if output == *input {
+            return Err(format!(
+                "{} would be overwritten; use --to or --out-dir",
+                input.display()
+            ));
+        }
+        if !outputs.insert(output.clone()) {
+            return Err(format!(
+                "more than one input would be written to {}",
+                output.display()
+            ));
+        }
+        jobs.push(BatchJob {
+            input: input.clone(),
+            output,
+        });
+    }
+    Ok(jobs)
+}
+
+/// Converts every job, applying `edits` to each, on a worker pool of
+/// `threads` threads (one per core by default). `on_done` is called from
+/// the workers as each file finishes. Returns the result of each job in
+/// the order given.
+pub fn convert_batch<F>(
+    jobs: &[BatchJob],
+    options: &EncodeOptions,
+    edits: &Edits,
+    threads: Option<usize>,
+    on_done: F,
+) -> Result<Vec<Result<(), String>>, String>
+where
+    F: Fn(&BatchJob, &Result<(), String>) + Sync,
+{
+    for dir in jobs.iter().filter_map(|job| job.output.parent()) {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(0))
+        .build()
+        .map_err(|e| format!("failed to start worker threads: {}", e))?;
+
+    Ok(pool.install(|| {
+        jobs.par_iter()
+            .map(|job| {
+                let result = backend_for_path(&job.output).and_then(|backend| {
+                    convert_with(&job.input, &job.output, backend.as_ref(), options, edits)
+                });
+                on_done(job, &result);
+                result
+            })
+            .collect()
+    }))
+}
@@ -0,0 +1,594 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use mogbox_engine::{
+    ChannelMapper, ChannelMatrix, DcBlocker, FadeCurve, Fader, Gain, LoudnessMeter, Processor,
+    Resampler, ResamplerQuality, TruePeakLimiter,
+};
+use mogbox_io::AudioFile;
+
+use crate::{backend_for_path, EncodeOptions, Encoder, EncoderBackend, TrackTags};
+
+/// Changes made to the audio on its way from the source to the encoder
+#[derive(Clone, Debug, Default)]
+pub struct Edits {
+    /// Where in the source to start; from the beginning when unset
+    pub start: Option<Duration>,
+    /// Where in the source to stop; at the end when unset
+    pub end: Option<Duration>,
+    /// Length of a fade from silence at the start
+    pub fade_in: Option<Duration>,
+    /// Length of a fade to silence at the end
+    pub fade_out: Option<Duration>,
+    pub fade_curve: FadeCurve,
+    /// Remove DC offset with a high-pass at [`DC_BLOCK_CUTOFF`] before
+    /// anything else
+    pub dc_block: bool,
+    /// Integrated loudness to bring the audio to, in LUFS. The source is
+    /// measured in a first pass.
+    pub normalize: Option<f32>,
+    /// Ceiling in dBTP of a true-peak limiter run last, after every
+    /// other change, e.g. [`LIMIT_CEILING`]
+    pub limit: Option<f32>,
+    /// New channel layout for the output; the source's when unset
+    pub remix: Option<Remix>,
+}
+
+/// A change of channel layout
+#[derive(Clone, Debug, PartialEq)]
+pub enum Remix {
+    /// Standard fold-down (or up) to two channels
+    Stereo,
+    /// Average of the stereo fold-down
+    Mono,
+    /// Explicit gains, one row per output channel
+    Matrix(ChannelMatrix),
+}
+
+impl Remix {
+    /// The mapper taking `channels` source channels to the new layout
+    pub fn mapper(&self, channels: usize) -> Result<ChannelMapper, String> {
+        match self {
+            Remix::Stereo => Ok(ChannelMapper::new(channels, 2)),
+            Remix::Mono => Ok(ChannelMapper::new(channels, 1)),
+            Remix::Matrix(matrix) => {
+                let columns = matrix.iter().map(Vec::len).max().unwrap_or(0);
+                if columns > channels {
+                    return Err(format!(
+                        "the channel matrix has {} columns but the source only has {} channels",
+                        columns, channels
+                    ));
+                }
+                let matrix = matrix
+                    .iter()
+                    .map(|row| {
+                        let mut row = row.clone();
+                        row.resize(channels, 0.0);
+                        row
+                    })
+                    .collect();
+                Ok(ChannelMapper::from_matrix(matrix))
+            }
+        }
+    }
+}
+
+impl FromStr for Remix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "stereo" => Ok(Remix::Stereo),
+            "mono" => Ok(Remix::Mono),
+            _ => Err(format!("invalid downmix: {} (expected stereo or mono)", s)),
+        }
+    }
+}
+
+/// Cutoff of the DC-blocking high-pass, in Hz
+pub const DC_BLOCK_CUTOFF: f32 = 10.0;
+
+/// Usual ceiling of the limiter, in dBTP
+pub const LIMIT_CEILING: f32 = -1.0;
+
+/// A true-peak limiter at the very end of an export, hiding its lookahead
+/// delay so the output lines up with the source
+pub(crate) struct OutputLimiter {
+    limiter: TruePeakLimiter,
+    channels: usize,
+    /// Samples still to drop from the start of the output
+    delayed: usize,
+}
+
+impl OutputLimiter {
+    pub(crate) fn new(ceiling: f32, sample_rate: u32, channels: usize) -> Self {
+        let mut limiter = TruePeakLimiter::new(ceiling);
+        limiter.prepare(sample_rate, channels);
+        OutputLimiter {
+            delayed: limiter.latency() * channels,
+            limiter,
+            channels,
+        }
+    }
+
+    /// Limits `samples` and writes what comes out of the delay to `encoder`
+    pub(crate) fn write(
+        &mut self,
+        encoder: &mut dyn Encoder,
+        samples: &mut [f32],
+    ) -> Result<(), String> {
+        self.limiter.process(samples);
+        let skipped = self.delayed.min(samples.len());
+        self.delayed -= skipped;
+        encoder.write(&samples[skipped..])
+    }
+
+    /// Writes the audio still held back by the lookahead
+    pub(crate) fn finish(&mut self, encoder: &mut dyn Encoder) -> Result<(), String> {
+        let mut tail = vec![0.0; self.limiter.latency() * self.channels];
+        self.write(encoder, &mut tail)
+    }
+}
+
+/// Processing applied in the source's sample rate and channel layout,
+/// before any conversion
+#[derive(Default)]
+pub(crate) struct Effects {
+    processors: Vec<Box<dyn Processor>>,
+    fader: Option<Fader>,
+    /// Ceiling of the limiter that runs on the converted output
+    limit: Option<f32>,
+}
+
+impl Effects {
+    fn add(&mut self, mut processor: Box<dyn Processor>, sample_rate: u32, channels: usize) {
+        processor.prepare(sample_rate, channels);
+        self.processors.push(processor);
+    }
+}
+
+impl Edits {
+    /// The effects for these edits. `loudness` is the measured loudness of
+    /// the source when normalizing.
+    fn effects(&self, sample_rate: u32, channels: usize, loudness: Option<f32>) -> Effects {
+        let mut effects = Effects::default();
+        if self.dc_block {
+            effects.add(
+                Box::new(DcBlocker::new(DC_BLOCK_CUTOFF)),
+                sample_rate,
+                channels,
+            );
+        }
+        if let (Some(target), Some(loudness)) = (self.normalize, loudness) {
+            effects.add(
+                Box::new(Gain::from_db(target - loudness)),
+                sample_rate,
+                channels,
+            );
+        }
+        if self.fade_in.is_some() || self.fade_out.is_some() {
+            effects.fader = Some(Fader::new(
+                sample_rate,
+                channels,
+                self.fade_in.unwrap_or_default(),
+                self.fade_out.unwrap_or_default(),
+                self.fade_curve,
+            ));
+        }
+        effects.limit = self.limit;
+        effects
+    }
+}
+
+/// Decodes `input` and re-encodes it to `output`, in the format given by
+/// the output's extension
+pub fn convert(input: &Path, output: &Path, options: &EncodeOptions) -> Result<(), String> {
+    let backend = backend_for_path(output)?;
+    convert_with(input, output, backend.as_ref(), options, &Edits::default())
+}
+
+/// Like [`convert`] with an explicit output format, applying `edits`
+pub fn convert_with(
+    input: &Path,
+    output: &Path,
+    backend: &dyn EncoderBackend,
+    options: &EncodeOptions,
+    edits: &Edits,
+) -> Result<(), String> {
+    let mut file = AudioFile::open(&PathBuf::from(input))?;
+
+    // The region to keep, in source frames
+    let start = edits
+        .start
+        .map(|start| frames_at(start, file.sample_rate))
+        .unwrap_or(0);
+    let remaining = match edits.end {
+        Some(end) if end <= edits.start.unwrap_or_default() => {
+            return Err("the end must be after the start".to_string())
+        }
+        Some(end) => Some(frames_at(end, file.sample_rate) - start),
+        None => None,
+    };
+    let loudness = match edits.normalize {
+        Some(_) => Some(measure_loudness(input, start, remaining)?),
+        None => None,
+    };
+    if start > 0 {
+        file.seek(start)?;
+    }
+
+    // Keep the source's bit depth unless asked otherwise, so lossless stays lossless
+    let options = EncodeOptions {
+        bits_per_sample: options.bits_per_sample.or(file
+            .bits_per_sample
+            .map(|bits| backend.bits_per_sample(bits as u16))),
+        tags: if options.strip_tags {
+            TrackTags::default()
+        } else {
+            TrackTags::from_file(&file)
+        },
+        ..options.clone()
+    };
+
+    let channels = file.channels as usize;
+    let sample_rate = backend.sample_rate(options.sample_rate.unwrap_or(file.sample_rate));
+    let remix = match &edits.remix {
+        Some(remix) => remix.mapper(channels)?,
+        None => ChannelMapper::identity(channels),
+    };
+    let out_channels = backend.channels(remix.outputs());
+    let mapper = remix.then(&ChannelMapper::new(remix.outputs(), out_channels));
+    let mut encoder = backend.create(output, sample_rate, out_channels, &options)?;
+    let effects = edits.effects(file.sample_rate, channels, loudness);
+    let mut reader = FrameReader::new(&mut file);
+    encode_frames(
+        &mut reader,
+        encoder.as_mut(),
+        sample_rate,
+        &mapper,
+        remaining,
+        effects,
+        options.resampler,
+    )?;
+    encoder.finish()
+}
+
+/// Decodes `inputs` one after another into a single `output`, in the format
+/// given by its extension. The first input sets the sample rate and channel
+/// layout; other inputs must match it unless `resample` is set, in which
+/// case they are converted. Tags that describe the whole album are taken
+/// from the first input.
+pub fn join(
+    inputs: &[PathBuf],
+    output: &Path,
+    options: &EncodeOptions,
+    resample: bool,
+) -> Result<(), String> {
+    let backend = backend_for_path(output)?;
+    let mut files = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let file = AudioFile::open(input).map_err(|e| format!("{}: {}", input.display(), e))?;
+        files.push(file);
+    }
+    let first = files.first().ok_or("nothing to join")?;
+
+    for (input, file) in inputs.iter().zip(&files).skip(1) {
+        if !resample && (file.sample_rate, file.channels) != (first.sample_rate, first.channels) {
+            return Err(format!(
+                "{} is {} Hz with {} channels, but {} is {} Hz with {} channels",
+                input.display(),
+                file.sample_rate,
+                file.channels,
+                inputs[0].display(),
+                first.sample_rate,
+                first.channels
+            ));
+        }
+    }
+
+    let tags = if options.strip_tags {
+        TrackTags::default()
+    } else {
+        TrackTags {
+            title: None,
+            track_number: None,
+            ..TrackTags::from_file(first)
+        }
+    };
+    let options = EncodeOptions {
+        bits_per_sample: options.bits_per_sample.or(first
+            .bits_per_sample
+            .map(|bits| backend.bits_per_sample(bits as u16))),
+        tags,
+        ..options.clone()
+    };
+
+    let sample_rate = backend.sample_rate(options.sample_rate.unwrap_or(first.sample_rate));
+    let channels = backend.channels(first.channels as usize);
+    let mut encoder = backend.create(output, sample_rate, channels, &options)?;
+    for (input, file) in inputs.iter().zip(files.iter_mut()) {
+        let mapper = ChannelMapper::new(file.channels as usize, channels);
+        encode_frames(
+            &mut FrameReader::new(file),
+            encoder.as_mut(),
+            sample_rate,
+            &mapper,
+            None,
+            Effects::default(),
+            options.resampler,
+        )
+        .map_err(|e| format!("{}: {}", input.display(), e))?;
+    }
+    encoder.finish()
+}
+
+/// Reads a file in runs of frames, keeping the rest of a packet that
+/// crosses the end of a run for the next one
+pub(crate) struct FrameReader<'a> {
+    file: &'a mut AudioFile,
+    pending: Vec<f32>,
+}
+
+impl<'a> FrameReader<'a> {
+    pub(crate) fn new(file: &'a mut AudioFile) -> Self {
+        FrameReader {
+            file,
+            pending: Vec::new(),
+        }
+    }
+
+    pub(crate) fn file(&self) -> &AudioFile {
+        self.file
+    }
+
+    /// The next decoded samples, at most `limit` frames of them
+    pub(crate) fn next(&mut self, limit: Option<u64>) -> Result<Option<Vec<f32>>, String> {
+        if limit == Some(0) {
+            return Ok(None);
+        }
+        let mut samples = if self.pending.is_empty() {
+            match self.file.next_samples()? {
+                Some(samples) => samples,
+                None => return Ok(None),
+            }
+        } else {
+            std::mem::take(&mut self.pending)
+        };
+
+        let channels = self.file.channels as usize;
+        if let Some(limit) = limit {
+            if (samples.len() / channels) as u64 > limit {
+                self.pending = samples.split_off(limit as usize * channels);
+            }
+        }
+        Ok(Some(samples))
+    }
+
+    /// Drops the next `frames` frames
+    pub(crate) fn skip(&mut self, mut frames: u64) -> Result<(), String> {
+        let channels = self.file.channels as usize;
+        while let Some(samples) = self.next(Some(frames))? {
+            frames -= (samples.len() / channels) as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the rest of the file, or the next `frames` frames of it, and
+/// writes it to `encoder` at `sample_rate` through `mapper`, applying
+/// `effects` first and converting the sample rate with `quality`. The
+/// limiter of `effects` runs last, on the converted audio.
+pub(crate) fn encode_frames(
+    reader: &mut FrameReader,
+    encoder: &mut dyn Encoder,
+    sample_rate: u32,
+    mapper: &ChannelMapper,
+    frames: Option<u64>,
+    mut effects: Effects,
+    quality: ResamplerQuality,
+) -> Result<(), String> {
+    let channels = reader.file().channels as usize;
+    let source_rate = reader.file().sample_rate;
+    let mut remaining = frames;
+    let mut resampler = if sample_rate != source_rate {
+        Some(Resampler::with_quality(
+            source_rate,
+            sample_rate,
+            channels,
+            quality,
+        )?)
+    } else {
+        None
+    };
+
+    let mut limiter = effects
+        .limit
+        .map(|ceiling| OutputLimiter::new(ceiling, sample_rate, mapper.outputs()));
+    let mut write = |samples: &mut Vec<f32>| match limiter.as_mut() {
+        Some(limiter) => limiter.write(encoder, samples),
+        None => encoder.write(samples),
+    };
+
+    let mut faded = Vec::new();
+    let mut mapped = Vec::new();
+    while let Some(mut samples) = reader.next(remaining)? {
+        if let Some(remaining) = remaining.as_mut() {
+            *remaining -= (samples.len() / channels) as u64;
+        }
+        for processor in effects.processors.iter_mut() {
+            processor.process(&mut samples);
+        }
+        let samples = match effects.fader.as_mut() {
+            Some(fader) => {
+                fader.process(&samples, &mut faded);
+                &faded[..]
+            }
+            None => &samples[..],
+        };
+        let resampled;
+        let samples = match resampler.as_mut() {
+            Some(resampler) => {
+                resampled = resampler.process(samples);
+                &resampled[..]
+            }
+            None => samples,
+        };
+        mapper.map_into(samples, &mut mapped);
+        write(&mut mapped)?;
+    }
+
+    // Release the held back end of a fade and what the resampler still holds
+    let mut tail = Vec::new();
+    if let Some(fader) = effects.fader.as_mut() {
+        fader.finish(&mut tail);
+    }
+    if let Some(resampler) = resampler.as_mut() {
+        let mut resampled = resampler.process(&tail);
+        resampled.extend(resampler.flush());
+        tail = resampled;
+    }
+    mapper.map_into(&tail, &mut mapped);
+    write(&mut mapped)?;
+    match limiter.as_mut() {
+        Some(limiter) => limiter.finish(encoder),
+        None => Ok(()),
+    }
+}
+
+/// Integrated loudness of `frames` frames of `input` from `start`, or of
+/// the rest of it, in LUFS
+fn measure_loudness(input: &Path, start: u64, frames: Option<u64>) -> Result<f32, String> {
+    let mut file = AudioFile::open(&input.to_path_buf())?;
+    if start > 0 {
+        file.seek(start)?;
+    }
+    let mut meter = LoudnessMeter::new(file.sample_rate, file.channels as usize);
+    let mut reader = FrameReader::new(&mut file);
+    let mut remaining = frames;
+    while let Some(samples) = reader.next(remaining)? {
+        if let Some(remaining) = remaining.as_mut() {
+            *remaining -= (samples.len() / reader.file().channels as usize) as u64;
+        }
+        meter.process(&samples);
+    }
+    meter
+        .integrated()
+        .ok_or_else(|| "too short or too quiet to measure its loudness".to_string())
+}
+
+/// The frame at `time` into a stream at `sample_rate`
+fn frames_at(time: Duration, sample_rate: u32) -> u64 {
+    (time.as_secs_f64() * sample_rate as f64).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Interleaved stereo samples that reach both ends of the range
+    fn samples(bits: u16) -> Vec<i32> {
+        let max = (1i32 << (bits - 1)) - 1;
+        let mut state = 0x2545_f491u32;
+        let mut samples = vec![-max - 1, max, 0, -1, 1, max - 1];
+        samples.extend((0..20000).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state as i32) >> (32 - bits)
+        }));
+        samples
+    }
+
+    fn write_wav(path: &Path, bits: u16, samples: &[i32]) {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: bits,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    /// Decodes a file back to integers of `bits` bits
+    fn read_ints(path: &Path, bits: u16) -> Vec<i32> {
+        let mut file = AudioFile::open(&path.to_path_buf()).unwrap();
+        assert_eq!(file.bits_per_sample, Some(bits as u32));
+        let scale = (1i64 << (bits - 1)) as f32;
+        file.read_all()
+            .unwrap()
+            .into_iter()
+            .map(|sample| (sample * scale) as i32)
+            .collect()
+    }
+
+    #[test]
+    fn lossless_conversion_keeps_every_sample() {
+        let dir = std::env::temp_dir().join(format!("mogbox-convert-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for bits in [16, 24] {
+            let source = samples(bits);
+            let input = dir.join(format!("in{}.wav", bits));
+            write_wav(&input, bits, &source);
+            for ext in ["wav", "flac"] {
+                let output = dir.join(format!("out{}.{}", bits, ext));
+                convert(&input, &output, &EncodeOptions::default()).unwrap();
+                assert!(
+                    read_ints(&output, bits) == source,
+                    "{}-bit {} changed samples",
+                    bits,
+                    ext
+                );
+
+                // And once more from the converted file
+                let again = dir.join(format!("again{}.wav", bits));
+                convert(&output, &again, &EncodeOptions::default()).unwrap();
+                assert!(read_ints(&again, bits) == source);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_depths_the_format_cannot_write() {
+        let dir = std::env::temp_dir().join(format!("mogbox-depth-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.wav");
+        write_wav(&input, 16, &samples(16));
+
+        let bits = |bits| EncodeOptions {
+            bits_per_sample: Some(bits),
+            ..EncodeOptions::default()
+        };
+        assert_eq!(
+            convert(&input, &dir.join("out.wav"), &bits(12))
+                .err()
+                .as_deref(),
+            Some("WAV can't be written at 12 bits (supported: 8, 16, 24, 32)")
+        );
+        assert_eq!(
+            convert(&input, &dir.join("out.flac"), &bits(32))
+                .err()
+                .as_deref(),
+            Some("FLAC can't be written at 32 bits (supported: 8, 16, 24)")
+        );
+        for ext in ["wav", "flac"] {
+            let output = dir.join(format!("out8.{}", ext));
+            convert(&input, &output, &bits(8)).unwrap();
+            assert_eq!(AudioFile::open(&output).unwrap().bits_per_sample, Some(8));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn source_depths_round_up_to_the_format() {
+        assert_eq!(crate::WavBackend.bits_per_sample(12), 16);
+        assert_eq!(crate::WavBackend.bits_per_sample(20), 24);
+        assert_eq!(crate::FlacBackend.bits_per_sample(24), 24);
+        assert_eq!(crate::FlacBackend.bits_per_sample(32), 24);
+    }
+}
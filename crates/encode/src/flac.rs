@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+
+use flacenc::component::{BitRepr, MetadataBlockData};
+use flacenc::error::Verify;
+
+use crate::tags::{comment_block, TrackTags};
+use mogbox_engine::{Dither, Processor};
+
+use crate::{check_depth, depth_for, dither_for, quantize, EncodeOptions, Encoder, EncoderBackend};
+
+/// Depths the encoder writes
+const DEPTHS: &[u16] = &[8, 16, 24];
+
+/// Compression level used when none is given, as in the reference encoder
+const DEFAULT_LEVEL: u8 = 5;
+
+/// Metadata block types
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+
+/// Lossless FLAC, 8, 16 or 24 bit, with compression levels 0 (fastest) to 8 (smallest)
+pub struct FlacBackend;
+
+impl EncoderBackend for FlacBackend {
+    fn name(&self) -> &'static str {
+        "flac"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["flac"]
+    }
+
+    fn bits_per_sample(&self, source_bits: u16) -> u16 {
+        depth_for(source_bits, DEPTHS)
+    }
+
+    fn create(
+        &self,
+        path: &Path,
+        sample_rate: u32,
+        channels: usize,
+        options: &EncodeOptions,
+    ) -> Result<Box<dyn Encoder>, String> {
+        let bits = check_depth(options.bits_per_sample.unwrap_or(16), DEPTHS, "FLAC")?;
+        let level = options.compression.unwrap_or(DEFAULT_LEVEL);
+        Ok(Box::new(FlacEncoder {
+            path: path.to_path_buf(),
+            config: flac_config(level)?,
+            sample_rate,
+            channels,
+            bits,
+            tags: options.tags.clone(),
+            dither: dither_for(bits, channels, options.dither),
+            samples: Vec::new(),
+        }))
+    }
+}
+
+/// Collects the whole stream and encodes it on `finish`, since the encoder
+/// works from an in-memory source
+pub struct FlacEncoder {
+    path: PathBuf,
+    config: flacenc::config::Encoder,
+    sample_rate: u32,
+    channels: usize,
+    bits: u16,
+    tags: TrackTags,
+    dither: Option<Dither>,
+    samples: Vec<i32>,
+}
+
+impl Encoder for FlacEncoder {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let mut dithered;
+        let samples = match self.dither.as_mut() {
+            Some(dither) => {
+                dithered = samples.to_vec();
+                dither.process(&mut dithered);
+                &dithered[..]
+            }
+            None => samples,
+        };
+        self.samples
+            .extend(samples.iter().map(|&sample| quantize(sample, self.bits)));
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), String> {
+        let config = self
+            .config
+            .into_verified()
+            .map_err(|(_, e)| format!("invalid FLAC settings: {}", e))?;
+        let block_size = config.block_size;
+        let source = flacenc::source::MemSource::from_samples(
+            &self.samples,
+            self.channels,
+            self.bits as usize,
+            self.sample_rate as usize,
+        );
+        let mut stream = flacenc::encode_with_fixed_block_size(&config, source, block_size)
+            .map_err(|e| format!("failed to encode FLAC: {}", e))?;
+        // A shorter last frame must not show up as the minimum block size,
+        // or decoders take the stream for a variable block size one
+        stream
+            .stream_info_mut()
+            .set_block_sizes(block_size, block_size)
+            .map_err(|e| format!("failed to encode FLAC: {}", e))?;
+
+        let comments = self.tags.vorbis_comments();
+        if !comments.is_empty() {
+            stream.add_metadata_block(metadata_block(VORBIS_COMMENT, comment_block(&comments))?);
+        }
+        if let Some(picture) = self.tags.picture_block() {
+            stream.add_metadata_block(metadata_block(PICTURE, picture)?);
+        }
+
+        let mut sink = flacenc::bitsink::ByteSink::new();
+        stream
+            .write(&mut sink)
+            .map_err(|e| format!("failed to encode FLAC: {:?}", e))?;
+        std::fs::write(&self.path, sink.as_slice())
+            .map_err(|e| format!("failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+fn metadata_block(typetag: u8, data: Vec<u8>) -> Result<MetadataBlockData, String> {
+    MetadataBlockData::new_unknown(typetag, &data)
+        .map_err(|e| format!("failed to write FLAC metadata: {}", e))
+}
+
+/// Encoder settings for a compression level, following the presets of the
+/// reference encoder: larger levels search longer predictors
+fn flac_config(level: u8) -> Result<flacenc::config::Encoder, String> {
+    if level > 8 {
+        return Err(format!("invalid FLAC compression level: {} (0-8)", level));
+    }
+
+    let mut config = flacenc::config::Encoder::default();
+    config.block_size = if level <= 2 { 1152 } else { 4096 };
+    let mid_side = !matches!(level, 0 | 3);
+    config.stereo_coding.use_leftside = mid_side;
+    config.stereo_coding.use_rightside = mid_side;
+    config.stereo_coding.use_midside = mid_side;
+    config.subframe_coding.use_lpc = level >= 3;
+    config.subframe_coding.qlpc.lpc_order = match level {
+        0..=3 => 6,
+        4..=6 => 8,
+        _ => 12,
+    };
+    config.subframe_coding.prc.max_parameter = if level >= 6 { 14 } else { 8 };
+    Ok(config)
+}
@@ -0,0 +1,218 @@
+// Encode crate
+
+pub mod analysis;
+pub mod batch;
+pub mod convert;
+pub mod flac;
+pub mod mix;
+#[cfg(feature = "mp3")]
+pub mod mp3;
+pub mod opus;
+pub mod replaygain;
+pub mod retag;
+pub mod split;
+pub mod tags;
+pub mod vorbis;
+pub mod wav;
+
+use std::path::Path;
+
+use mogbox_engine::{Dither, DitherMode, Processor, ResamplerQuality};
+
+pub use analysis::{
+    clipping_report, dc_offsets, dynamic_range, loudness_report, silence_report, stats_report,
+    ChannelClipping, ClipRun, DynamicRange, LoudnessReport, SilenceReport, StatsReport,
+};
+pub use batch::{convert_batch, plan_batch, BatchJob};
+pub use convert::{convert, convert_with, join, Edits, Remix};
+pub use flac::FlacBackend;
+pub use mix::{mix, MixInput};
+#[cfg(feature = "mp3")]
+pub use mp3::Mp3Backend;
+pub use opus::OpusBackend;
+pub use replaygain::{scan_gain, write_gain_tags, GainScan, ReplayGain};
+pub use retag::{set_cover, update_tags};
+pub use split::{cue_segments, silence_segments, split, Segment};
+pub use tags::{CoverArt, TrackTags};
+pub use vorbis::VorbisBackend;
+pub use wav::WavBackend;
+
+/// Writes interleaved f32 samples to an encoded file
+pub trait Encoder {
+    /// Encodes a buffer of interleaved samples
+    fn write(&mut self, samples: &[f32]) -> Result<(), String>;
+
+    /// Flushes anything buffered and finalizes the file
+    fn finish(self: Box<Self>) -> Result<(), String>;
+}
+
+/// An output format that encoders can be created for
+pub trait EncoderBackend: Send + Sync {
+    /// Short name used to pick the format explicitly, e.g. `wav`
+    fn name(&self) -> &'static str;
+
+    /// File extensions that select this format
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Sample rate to encode a source at `source_rate` with; input is
+    /// resampled when the format does not support the source's rate
+    fn sample_rate(&self, source_rate: u32) -> u32 {
+        source_rate
+    }
+
+    /// Bit depth to store a source of `source_bits` in when none is asked
+    /// for; formats with fixed depths use the next one up
+    fn bits_per_sample(&self, source_bits: u16) -> u16 {
+        source_bits
+    }
+
+    /// Channel count to encode a source with `source_channels` with; input
+    /// is downmixed when the format supports fewer channels
+    fn channels(&self, source_channels: usize) -> usize {
+        source_channels
+    }
+
+    fn create(
+        &self,
+        path: &Path,
+        sample_rate: u32,
+        channels: usize,
+        options: &EncodeOptions,
+    ) -> Result<Box<dyn Encoder>, String>;
+}
+
+/// User settings shared by all encoders; each backend uses what applies to it
+#[derive(Clone, Debug, Default)]
+pub struct EncodeOptions {
+    /// Target bitrate in bits per second for lossy formats
+    pub bitrate: Option<u32>,
+    /// Bit depth for PCM and lossless formats; the source's when converting
+    pub bits_per_sample: Option<u16>,
+    /// Compression level for lossless formats, e.g. 0-8 for FLAC
+    pub compression: Option<u8>,
+    /// Quality level for lossy formats, e.g. -1 to 10 for Vorbis or the LAME
+    /// VBR preset 0 (best) to 9 for MP3. A bitrate takes precedence where both are given.
+    pub quality: Option<f32>,
+    /// Constant instead of variable bitrate for lossy formats that support both
+    pub cbr: bool,
+    /// Tags to write to the output; the source's when converting
+    pub tags: TrackTags,
+    /// Leave out the source's tags when converting
+    pub strip_tags: bool,
+    /// Sample rate to convert to; the source's when not given. Formats that
+    /// don't support it use the closest rate they do.
+    pub sample_rate: Option<u32>,
+    /// Quality of the conversion when the sample rate changes
+    pub resampler: ResamplerQuality,
+    /// Dither applied when PCM and lossless formats reduce samples to 16 or 24 bit
+    pub dither: DitherMode,
+}
+
+/// Every output format compiled into this build
+pub fn backends() -> Vec<Box<dyn EncoderBackend>> {
+    vec![
+        Box::new(WavBackend),
+        Box::new(FlacBackend),
+        Box::new(VorbisBackend),
+        Box::new(OpusBackend),
+        #[cfg(feature = "mp3")]
+        Box::new(Mp3Backend),
+    ]
+}
+
+/// Finds a backend by name or extension, case-insensitively
+pub fn backend(name: &str) -> Option<Box<dyn EncoderBackend>> {
+    backends().into_iter().find(|backend| {
+        backend.name().eq_ignore_ascii_case(name)
+            || backend
+                .extensions()
+                .iter()
+                .any(|ext| ext.eq_ignore_ascii_case(name))
+    })
+}
+
+/// Picks the backend for an output file from its extension
+pub fn backend_for_path(path: &Path) -> Result<Box<dyn EncoderBackend>, String> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .ok_or_else(|| {
+            format!(
+                "no file extension to pick a format from: {}",
+                path.display()
+            )
+        })?;
+    backend(ext).ok_or_else(|| {
+        let known: Vec<&str> = backends().iter().map(|backend| backend.name()).collect();
+        format!(
+            "unsupported output format: {} (available: {})",
+            ext,
+            known.join(", ")
+        )
+    })
+}
+
+/// Converts a float sample to a signed integer of `bits` bits. Full scale
+/// is 2^(bits - 1), as decoders use, so integer sources come back exactly.
+pub(crate) fn quantize(sample: f32, bits: u16) -> i32 {
+    let scale = (1i64 << (bits - 1)) as f64;
+    (sample as f64 * scale).round().clamp(-scale, scale - 1.0) as i32
+}
+
+/// The first of `depths`, in ascending order, deep enough for `bits`, or
+/// the deepest one
+pub(crate) fn depth_for(bits: u16, depths: &[u16]) -> u16 {
+    let deepest = depths.last().copied().unwrap_or(bits);
+    depths
+        .iter()
+        .copied()
+        .find(|&depth| depth >= bits)
+        .unwrap_or(deepest)
+}
+
+/// Checks that `format` can write samples of `bits` bits
+pub(crate) fn check_depth(bits: u16, depths: &[u16], format: &str) -> Result<u16, String> {
+    if depths.contains(&bits) {
+        return Ok(bits);
+    }
+    let depths: Vec<String> = depths.iter().map(u16::to_string).collect();
+    Err(format!(
+        "{} can't be written at {} bits (supported: {})",
+        format,
+        bits,
+        depths.join(", ")
+    ))
+}
+
+/// A ditherer for output reduced to `bits` bits, or `None` when the mode
+/// is off or the depth is high enough not to need one
+pub(crate) fn dither_for(bits: u16, channels: usize, mode: DitherMode) -> Option<Dither> {
+    if mode == DitherMode::None || bits > 24 {
+        return None;
+    }
+    let mut dither = Dither::new(bits, mode);
+    dither.prepare(0, channels);
+    Some(dither)
+}
+
+/// Parses a bitrate such as `192k`, `1.5M` or `128000`
+pub fn parse_bitrate(value: &str) -> Result<u32, String> {
+    let value = value.trim();
+    let invalid = || format!("invalid bitrate: {}", value);
+
+    let lower = value.to_ascii_lowercase();
+    let lower = lower.strip_suffix("bps").unwrap_or(&lower);
+    let (number, scale) = if let Some(k) = lower.strip_suffix('k') {
+        (k, 1_000.0)
+    } else if let Some(m) = lower.strip_suffix('m') {
+        (m, 1_000_000.0)
+    } else {
+        (lower, 1.0)
+    };
+
+    let bitrate = number.parse::<f64>().map_err(|_| invalid())? * scale;
+    if !bitrate.is_finite() || bitrate < 1.0 || bitrate > u32::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(bitrate.round() as u32)
+}
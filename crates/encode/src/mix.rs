@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use mogbox_engine::{ChannelMapper, Gain, Processor, Resampler};
+use mogbox_io::AudioFile;
+
+use crate::convert::{FrameReader, OutputLimiter};
+use crate::{backend_for_path, EncodeOptions, TrackTags};
+
+/// Output frames mixed per round
+const MIX_FRAMES: usize = 4096;
+
+/// One input of a mix, with its own level and start time
+#[derive(Clone, Debug)]
+pub struct MixInput {
+    pub path: PathBuf,
+    /// Level change in dB
+    pub gain: f32,
+    /// Silence before the input starts
+    pub offset: Duration,
+}
+
+impl MixInput {
+    pub fn new(path: PathBuf) -> Self {
+        MixInput {
+            path,
+            gain: 0.0,
+            offset: Duration::ZERO,
+        }
+    }
+}
+
+/// An input on its way to the mix: gain at its own rate and layout, then
+/// converted to the mix's, queued up behind its offset
+struct Track<'a> {
+    reader: FrameReader<'a>,
+    gain: Gain,
+    resampler: Option<Resampler>,
+    mapper: ChannelMapper,
+    queue: VecDeque<f32>,
+    mapped: Vec<f32>,
+    done: bool,
+}
+
+impl Track<'_> {
+    /// Decodes until at least `samples` output samples are queued or the
+    /// input runs out
+    fn fill(&mut self, samples: usize) -> Result<(), String> {
+        while !self.done && self.queue.len() < samples {
+            let converted = match self.reader.next(None)? {
+                Some(mut decoded) => {
+                    self.gain.process(&mut decoded);
+                    match self.resampler.as_mut() {
+                        Some(resampler) => resampler.process(&decoded),
+                        None => decoded,
+                    }
+                }
+                None => {
+                    self.done = true;
+                    match self.resampler.as_mut() {
+                        Some(resampler) => resampler.flush(),
+                        None => Vec::new(),
+                    }
+                }
+            };
+            self.mapper.map_into(&converted, &mut self.mapped);
+            self.queue.extend(self.mapped.iter());
+        }
+        Ok(())
+    }
+}
+
+/// Sums `inputs` into a single `output`, in the format given by its
+/// extension. The mix takes the highest sample rate and the most channels
+/// of the inputs and runs until the longest one ends. With `limit`, a
+/// true-peak limiter keeps the sum under that ceiling in dBTP, such as
+/// [`LIMIT_CEILING`](crate::convert::LIMIT_CEILING).
+pub fn mix(
+    inputs: &[MixInput],
+    output: &Path,
+    options: &EncodeOptions,
+    limit: Option<f32>,
+) -> Result<(), String> {
+    let backend = backend_for_path(output)?;
+    let mut files = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let file =
+            AudioFile::open(&input.path).map_err(|e| format!("{}: {}", input.path.display(), e))?;
+        files.push(file);
+    }
+    let first = files.first().ok_or("nothing to mix")?;
+
+    let source_rate = files.iter().map(|file| file.sample_rate).max().unwrap_or(0);
+    let source_channels = files.iter().map(|file| file.channels).max().unwrap_or(0) as usize;
+    let bits = files.iter().filter_map(|file| file.bits_per_sample).max();
+    let options = EncodeOptions {
+        bits_per_sample: options
+            .bits_per_sample
+            .or(bits.map(|bits| backend.bits_per_sample(bits as u16))),
+        tags: if options.strip_tags {
+            TrackTags::default()
+        } else {
+            TrackTags::from_file(first)
+        },
+        ..options.clone()
+    };
+    let sample_rate = backend.sample_rate(options.sample_rate.unwrap_or(source_rate));
+    let channels = backend.channels(source_channels);
+    let mut encoder = backend.create(output, sample_rate, channels, &options)?;
+
+    let mut tracks = Vec::with_capacity(files.len());
+    for (input, file) in inputs.iter().zip(files.iter_mut()) {
+        let resampler = if file.sample_rate != sample_rate {
+            Some(Resampler::with_quality(
+                file.sample_rate,
+                sample_rate,
+                file.channels as usize,
+                options.resampler,
+            )?)
+        } else {
+            None
+        };
+        let offset = (input.offset.as_secs_f64() * sample_rate as f64).round() as usize;
+        tracks.push(Track {
+            mapper: ChannelMapper::new(file.channels as usize, channels),
+            reader: FrameReader::new(file),
+            gain: Gain::from_db(input.gain),
+            resampler,
+            queue: VecDeque::from(vec![0.0; offset * channels]),
+            mapped: Vec::new(),
+            done: false,
+        });
+    }
+
+    let mut limiter = limit.map(|ceiling| OutputLimiter::new(ceiling, sample_rate, channels));
+    let mut mixed = Vec::with_capacity(MIX_FRAMES * channels);
+    loop {
+        for (input, track) in inputs.iter().zip(tracks.iter_mut()) {
+            track
+                .fill(MIX_FRAMES * channels)
+                .map_err(|e| format!("{}: {}", input.path.display(), e))?;
+        }
+        let samples = tracks
+            .iter()
+            .map(|track| track.queue.len())
+            .max()
+            .unwrap_or(0);
+        let samples = samples.min(MIX_FRAMES * channels);
+        if samples == 0 {
+            break;
+        }
+
+        mixed.clear();
+        mixed.resize(samples, 0.0);
+        for track in tracks.iter_mut() {
+            let available = track.queue.len().min(samples);
+            for (sum, sample) in mixed.iter_mut().zip(track.queue.drain(..available)) {
+                *sum += sample;
+            }
+        }
+        match limiter.as_mut() {
+            Some(limiter) => limiter.write(encoder.as_mut(), &mut mixed)?,
+            None => encoder.write(&mixed)?,
+        }
+    }
+    if let Some(limiter) = limiter.as_mut() {
+        limiter.finish(encoder.as_mut())?;
+    }
+    encoder.finish()
+}
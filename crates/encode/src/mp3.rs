@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use mp3lame_encoder::{
+    Bitrate, Builder, FlushNoGap, Id3Tag, InterleavedPcm, MonoPcm, Quality, VbrMode,
+    MAX_ALBUM_ART_SIZE,
+};
+
+use crate::{EncodeOptions, Encoder, EncoderBackend};
+
+/// Sample rates MPEG audio can be stored at
+const MP3_RATES: [u32; 9] = [8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000];
+
+const DEFAULT_BITRATE: u32 = 192_000;
+
+/// LAME VBR preset used when neither a bitrate nor a quality is given (`-V2`)
+const DEFAULT_VBR_QUALITY: f32 = 2.0;
+
+/// MP3 through LAME. A bitrate or `cbr` selects constant bitrate, otherwise
+/// VBR at a LAME quality preset from 0 (best) to 9 (smallest)
+pub struct Mp3Backend;
+
+impl EncoderBackend for Mp3Backend {
+    fn name(&self) -> &'static str {
+        "mp3"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["mp3"]
+    }
+
+    fn sample_rate(&self, source_rate: u32) -> u32 {
+        if MP3_RATES.contains(&source_rate) {
+            source_rate
+        } else if source_rate > 48000 && source_rate.is_multiple_of(44100) {
+            44100
+        } else {
+            MP3_RATES
+                .iter()
+                .copied()
+                .find(|&rate| rate >= source_rate)
+                .unwrap_or(48000)
+        }
+    }
+
+    fn channels(&self, source_channels: usize) -> usize {
+        source_channels.clamp(1, 2)
+    }
+
+    fn create(
+        &self,
+        path: &Path,
+        sample_rate: u32,
+        channels: usize,
+        options: &EncodeOptions,
+    ) -> Result<Box<dyn Encoder>, String> {
+        let build_error =
+            |e: mp3lame_encoder::BuildError| format!("failed to start MP3 encoder: {}", e);
+
+        let mut builder = Builder::new().ok_or("failed to start MP3 encoder")?;
+        builder.set_sample_rate(sample_rate).map_err(build_error)?;
+        builder
+            .set_num_channels(channels as u8)
+            .map_err(build_error)?;
+        builder.set_quality(Quality::Best).map_err(build_error)?;
+
+        if options.cbr || options.bitrate.is_some() {
+            let bitrate = closest_bitrate(options.bitrate.unwrap_or(DEFAULT_BITRATE));
+            builder.set_vbr_mode(VbrMode::Off).map_err(build_error)?;
+            builder.set_brate(bitrate).map_err(build_error)?;
+        } else {
+            let quality = options.quality.unwrap_or(DEFAULT_VBR_QUALITY);
+            if !(0.0..=9.0).contains(&quality) {
+                return Err(format!("invalid MP3 VBR quality: {} (0-9)", quality));
+            }
+            builder.set_vbr_mode(VbrMode::Mtrh).map_err(build_error)?;
+            builder
+                .set_vbr_quality(vbr_quality(quality.round() as u8))
+                .map_err(build_error)?;
+        }
+
+        let tags = &options.tags;
+        let title = latin1(tags.title.as_deref());
+        let artist = latin1(tags.artist.as_deref());
+        let album = latin1(tags.album.as_deref());
+        let year = tags.year().unwrap_or_default();
+        let id3 = Id3Tag {
+            title: &title,
+            artist: &artist,
+            album: &album,
+            // LAME only embeds JPEG, PNG and GIF pictures up to 128 KiB
+            album_art: match tags.cover.as_ref() {
+                Some(cover) if cover.data.len() <= MAX_ALBUM_ART_SIZE => &cover.data,
+                _ => &[],
+            },
+            year: year.as_bytes(),
+            comment: &[],
+        };
+        if id3.is_any_set() {
+            builder
+                .set_id3_tag(id3)
+                .map_err(|e| format!("failed to write MP3 tags: {:?}", e))?;
+        }
+
+        let encoder = builder.build().map_err(build_error)?;
+        let file = File::create(path)
+            .map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+        Ok(Box::new(Mp3FileEncoder {
+            encoder,
+            writer: BufWriter::new(file),
+            channels,
+            buffer: Vec::new(),
+        }))
+    }
+}
+
+pub struct Mp3FileEncoder {
+    encoder: mp3lame_encoder::Encoder,
+    writer: BufWriter<File>,
+    channels: usize,
+    buffer: Vec<u8>,
+}
+
+impl Mp3FileEncoder {
+    fn write_buffer(&mut self) -> Result<(), String> {
+        self.writer
+            .write_all(&self.buffer)
+            .map_err(|e| format!("failed to write MP3: {}", e))?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl Encoder for Mp3FileEncoder {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let frames = samples.len() / self.channels;
+        self.buffer
+            .reserve(mp3lame_encoder::max_required_buffer_size(frames));
+        let result = if self.channels == 1 {
+            self.encoder
+                .encode_to_vec(MonoPcm(samples), &mut self.buffer)
+        } else {
+            self.encoder
+                .encode_to_vec(InterleavedPcm(samples), &mut self.buffer)
+        };
+        result.map_err(|e| format!("failed to encode MP3: {}", e))?;
+        self.write_buffer()
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), String> {
+        self.buffer.reserve(7200);
+        self.encoder
+            .flush_to_vec::<FlushNoGap>(&mut self.buffer)
+            .map_err(|e| format!("failed to encode MP3: {}", e))?;
+        self.write_buffer()?;
+
+        // The first frame was reserved for the LAME/Xing header, which needs
+        // the final frame count so players can seek and show the duration
+        let mut tag = Vec::with_capacity(self.encoder.lame_tag_size());
+        if self.encoder.lame_tag_encode_to_vec(&mut tag).is_some() {
+            let offset = self.encoder.id3v2_tag_size() as u64;
+            self.writer
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| self.writer.write_all(&tag))
+                .map_err(|e| format!("failed to write MP3 header: {}", e))?;
+        }
+        self.writer
+            .flush()
+            .map_err(|e| format!("failed to write MP3: {}", e))
+    }
+}
+
+/// LAME writes ID3 text as Latin-1; anything outside it becomes `?`
+fn latin1(value: Option<&str>) -> Vec<u8> {
+    value
+        .unwrap_or_default()
+        .chars()
+        .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+        .collect()
+}
+
+fn closest_bitrate(bitrate: u32) -> Bitrate {
+    const BITRATES: [(u32, Bitrate); 16] = [
+        (8, Bitrate::Kbps8),
+        (16, Bitrate::Kbps16),
+        (24, Bitrate::Kbps24),
+        (32, Bitrate::Kbps32),
+        (40, Bitrate::Kbps40),
+        (48, Bitrate::Kbps48),
+        (64, Bitrate::Kbps64),
+        (80, Bitrate::Kbps80),
+        (96, Bitrate::Kbps96),
+        (112, Bitrate::Kbps112),
+        (128, Bitrate::Kbps128),
+        (160, Bitrate::Kbps160),
+        (192, Bitrate::Kbps192),
+        (224, Bitrate::Kbps224),
+        (256, Bitrate::Kbps256),
+        (320, Bitrate::Kbps320),
+    ];
+    let kbps = bitrate / 1000;
+    BITRATES
+        .iter()
+        .min_by_key(|(rate, _)| rate.abs_diff(kbps))
+        .map(|(_, bitrate)| *bitrate)
+        .unwrap_or(Bitrate::Kbps192)
+}
+
+fn vbr_quality(preset: u8) -> Quality {
+    match preset {
+        0 => Quality::Best,
+        1 => Quality::SecondBest,
+        2 => Quality::NearBest,
+        3 => Quality::VeryNice,
+        4 => Quality::Nice,
+        5 => Quality::Good,
+        6 => Quality::Decent,
+        7 => Quality::Ok,
+        8 => Quality::SecondWorst,
+        _ => Quality::Worst,
+    }
+}
@@ -0,0 +1,202 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use opus_rs::{Application, OpusEncoder};
+
+use crate::tags::{comment_block, TrackTags};
+use crate::{EncodeOptions, Encoder, EncoderBackend};
+
+/// Opus always runs at 48 kHz; other rates are resampled before encoding
+const OPUS_RATE: u32 = 48000;
+
+/// 20 ms, the usual Opus frame size
+const FRAME_SIZE: usize = 960;
+
+/// Encoder lookahead that decoders skip at the start of the stream
+const PRE_SKIP: u16 = 312;
+
+/// Largest packet the encoder may produce
+const MAX_PACKET: usize = 4000;
+
+const DEFAULT_BITRATE: u32 = 96_000;
+
+/// Ogg Opus, mono or stereo, VBR by default
+pub struct OpusBackend;
+
+impl EncoderBackend for OpusBackend {
+    fn name(&self) -> &'static str {
+        "opus"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["opus"]
+    }
+
+    fn sample_rate(&self, _source_rate: u32) -> u32 {
+        OPUS_RATE
+    }
+
+    fn channels(&self, source_channels: usize) -> usize {
+        source_channels.clamp(1, 2)
+    }
+
+    fn create(
+        &self,
+        path: &Path,
+        sample_rate: u32,
+        channels: usize,
+        options: &EncodeOptions,
+    ) -> Result<Box<dyn Encoder>, String> {
+        if sample_rate != OPUS_RATE {
+            return Err(format!(
+                "Opus needs {} Hz input, got {} Hz",
+                OPUS_RATE, sample_rate
+            ));
+        }
+        let bitrate = options.bitrate.unwrap_or(DEFAULT_BITRATE);
+        if !(6_000..=510_000).contains(&bitrate) {
+            return Err(format!("invalid Opus bitrate: {} (6k-510k)", bitrate));
+        }
+
+        let mut encoder = OpusEncoder::new(OPUS_RATE as i32, channels, Application::Audio)
+            .map_err(|e| format!("failed to start Opus encoder: {}", e))?;
+        encoder.bitrate_bps = bitrate as i32;
+        encoder.use_cbr = options.cbr;
+
+        let file = File::create(path)
+            .map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+        let mut writer = OggOpusWriter {
+            packets: PacketWriter::new(BufWriter::new(file)),
+            serial: stream_serial(),
+        };
+        writer.write_headers(channels, sample_rate, &options.tags)?;
+
+        Ok(Box::new(OpusFileEncoder {
+            encoder,
+            writer,
+            channels,
+            pending: Vec::with_capacity(FRAME_SIZE * channels),
+            frames_in: 0,
+            granule: PRE_SKIP as u64,
+            packet: vec![0; MAX_PACKET],
+        }))
+    }
+}
+
+struct OggOpusWriter {
+    packets: PacketWriter<'static, BufWriter<File>>,
+    serial: u32,
+}
+
+impl OggOpusWriter {
+    /// Writes the identification and comment headers, each on its own page
+    fn write_headers(
+        &mut self,
+        channels: usize,
+        input_rate: u32,
+        tags: &TrackTags,
+    ) -> Result<(), String> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(channels as u8);
+        head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&input_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes());
+        head.push(0);
+        self.write(head, PacketWriteEndInfo::EndPage, 0)?;
+
+        let mut comments = b"OpusTags".to_vec();
+        comments.extend(comment_block(&tags.ogg_comments()));
+        self.write(comments, PacketWriteEndInfo::EndPage, 0)
+    }
+
+    fn write(
+        &mut self,
+        packet: Vec<u8>,
+        end: PacketWriteEndInfo,
+        granule: u64,
+    ) -> Result<(), String> {
+        self.packets
+            .write_packet(packet, self.serial, end, granule)
+            .map_err(|e| format!("failed to write Ogg page: {}", e))
+    }
+}
+
+pub struct OpusFileEncoder {
+    encoder: OpusEncoder,
+    writer: OggOpusWriter,
+    channels: usize,
+    /// Interleaved samples waiting for a full frame
+    pending: Vec<f32>,
+    /// Source frames received, to trim the padding of the last packet
+    frames_in: u64,
+    /// Granule position of the last packet written
+    granule: u64,
+    packet: Vec<u8>,
+}
+
+impl OpusFileEncoder {
+    fn encode_frame(&mut self, end: PacketWriteEndInfo, granule: u64) -> Result<(), String> {
+        let len = self
+            .encoder
+            .encode(&self.pending, FRAME_SIZE, &mut self.packet)
+            .map_err(|e| format!("failed to encode Opus: {}", e))?;
+        self.pending.clear();
+        self.writer.write(self.packet[..len].to_vec(), end, granule)
+    }
+}
+
+impl Encoder for OpusFileEncoder {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let frame_len = FRAME_SIZE * self.channels;
+        self.frames_in += (samples.len() / self.channels) as u64;
+        for chunk in samples.chunks(frame_len) {
+            let take = (frame_len - self.pending.len()).min(chunk.len());
+            self.pending.extend_from_slice(&chunk[..take]);
+            if self.pending.len() == frame_len {
+                self.granule += FRAME_SIZE as u64;
+                self.encode_frame(PacketWriteEndInfo::NormalPacket, self.granule)?;
+            }
+            self.pending.extend_from_slice(&chunk[take..]);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), String> {
+        // Pad with silence until the lookahead is flushed out as well; the
+        // final granule position tells decoders where the real audio ends
+        let end = PRE_SKIP as u64 + self.frames_in;
+        let frame_len = FRAME_SIZE * self.channels;
+        loop {
+            self.pending.resize(frame_len, 0.0);
+            self.granule += FRAME_SIZE as u64;
+            if self.granule >= end {
+                self.encode_frame(PacketWriteEndInfo::EndStream, end)?;
+                break;
+            }
+            self.encode_frame(PacketWriteEndInfo::NormalPacket, self.granule)?;
+        }
+        self.writer
+            .packets
+            .inner_mut()
+            .flush()
+            .map_err(|e| format!("failed to write Opus: {}", e))
+    }
+}
+
+/// Ogg streams should carry a random serial number
+fn stream_serial() -> u32 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0),
+    );
+    hasher.finish() as u32
+}
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use mogbox_engine::LoudnessMeter;
+use mogbox_io::AudioFile;
+use rayon::prelude::*;
+
+use crate::convert::FrameReader;
+use crate::retag::update_tags;
+
+/// Loudness that ReplayGain 2.0 brings playback to, in LUFS
+pub const REFERENCE_LOUDNESS: f32 = -18.0;
+
+/// A gain adjustment and the peak it has to respect
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayGain {
+    /// Gain in dB that brings the audio to [`REFERENCE_LOUDNESS`]
+    pub gain: f32,
+    /// Highest absolute sample value, 1.0 being full scale
+    pub peak: f32,
+}
+
+impl ReplayGain {
+    fn from_loudness(loudness: f32, peak: f32) -> Self {
+        ReplayGain {
+            gain: REFERENCE_LOUDNESS - loudness,
+            peak,
+        }
+    }
+}
+
+/// Track gains of a set of files along with the album gain for all of them
+#[derive(Clone, Debug)]
+pub struct GainScan {
+    pub tracks: Vec<(PathBuf, ReplayGain)>,
+    pub album: ReplayGain,
+}
+
+/// Stores track and album values in the tags of `path` as `REPLAYGAIN_*`
+/// fields, for other players to pick up
+pub fn write_gain_tags(path: &Path, track: &ReplayGain, album: &ReplayGain) -> Result<(), String> {
+    let gain = |gain: f32| format!("{:+.2} dB", gain);
+    let peak = |peak: f32| format!("{:.6}", peak);
+    update_tags(
+        path,
+        &[
+            ("REPLAYGAIN_TRACK_GAIN", gain(track.gain)),
+            ("REPLAYGAIN_TRACK_PEAK", peak(track.peak)),
+            ("REPLAYGAIN_ALBUM_GAIN", gain(album.gain)),
+            ("REPLAYGAIN_ALBUM_PEAK", peak(album.peak)),
+        ],
+    )
+}
+
+/// Measures `paths` on a worker pool of `threads` threads (one per core by
+/// default) and works out their ReplayGain 2.0 track and album values.
+/// `on_done` is called from the workers as each file finishes.
+pub fn scan_gain<F>(
+    paths: &[PathBuf],
+    threads: Option<usize>,
+    on_done: F,
+) -> Result<GainScan, String>
+where
+    F: Fn(&Path) + Sync,
+{
+    if paths.is_empty() {
+        return Err("nothing to scan".to_string());
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(0))
+        .build()
+        .map_err(|e| format!("failed to start worker threads: {}", e))?;
+
+    let measured = pool.install(|| {
+        paths
+            .par_iter()
+            .map(|path| {
+                let result = measure(path).map_err(|e| format!("{}: {}", path.display(), e));
+                on_done(path);
+                result
+            })
+            .collect::<Result<Vec<_>, String>>()
+    })?;
+
+    let mut tracks = Vec::with_capacity(paths.len());
+    for (path, (meter, peak)) in paths.iter().zip(&measured) {
+        let loudness = meter
+            .integrated()
+            .ok_or_else(|| format!("{}: too short or too quiet to measure", path.display()))?;
+        tracks.push((path.clone(), ReplayGain::from_loudness(loudness, *peak)));
+    }
+    let loudness = LoudnessMeter::integrated_all(measured.iter().map(|(meter, _)| meter))
+        .ok_or("too short or too quiet to measure")?;
+    let peak = measured.iter().map(|(_, peak)| *peak).fold(0.0, f32::max);
+    Ok(GainScan {
+        tracks,
+        album: ReplayGain::from_loudness(loudness, peak),
+    })
+}
+
+/// Runs a whole file through a loudness meter, also finding its sample peak
+fn measure(path: &Path) -> Result<(LoudnessMeter, f32), String> {
+    let mut file = AudioFile::open(&path.to_path_buf())?;
+    let mut meter = LoudnessMeter::new(file.sample_rate, file.channels as usize);
+    let mut peak = 0.0f32;
+    let mut reader = FrameReader::new(&mut file);
+    while let Some(samples) = reader.next(None)? {
+        meter.process(&samples);
+        peak = samples
+            .iter()
+            .fold(peak, |peak, sample| peak.max(sample.abs()));
+    }
+    Ok((meter, peak))
+}
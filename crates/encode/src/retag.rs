@@ -0,0 +1,674 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use ogg::reading::PacketReader;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+use base64::Engine;
+
+use crate::tags::{push_string, CoverArt, VENDOR};
+
+/// FLAC metadata block type of the Vorbis comment block
+const VORBIS_COMMENT: u8 = 4;
+/// FLAC metadata block type of picture blocks
+const PICTURE: u8 = 6;
+/// Picture type of front covers, in FLAC and ID3v2 alike
+const FRONT_COVER: u8 = 3;
+/// Handler box an iTunes-style `meta` box needs before its `ilst`
+const ITUNES_HANDLER: [u8; 25] = *b"\0\0\0\0\0\0\0\0mdirappl\0\0\0\0\0\0\0\0\0";
+
+/// Sets text fields in the tags of an existing file, replacing any values
+/// already stored under the same names and keeping everything else. Names
+/// are Vorbis comment field names such as `REPLAYGAIN_TRACK_GAIN`; in MP3
+/// files they go in ID3v2 `TXXX` frames, or the standard frame of fields
+/// that have one, such as `TBPM` for `BPM`. The file is rewritten through a
+/// temporary copy, so it is left alone if anything fails.
+pub fn update_tags(path: &Path, fields: &[(&str, String)]) -> Result<(), String> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let read = || fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e));
+    let updated = match ext.as_str() {
+        "flac" => update_flac(&read()?, fields)?,
+        "ogg" | "oga" | "opus" => update_ogg(path, fields)?,
+        "mp3" => update_id3(&read()?, fields)?,
+        _ => return Err(format!("can't write tags to .{} files", ext)),
+    };
+    replace_file(path, &updated)
+}
+
+/// Embeds `cover` as the front cover of an existing FLAC, Ogg, MP3 or MP4
+/// file, replacing any front cover already there. Ogg files keep only the
+/// new picture, since they store all of them in one list of comments. The
+/// file is rewritten through a temporary copy, like [`update_tags`].
+pub fn set_cover(path: &Path, cover: &CoverArt) -> Result<(), String> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let read = || fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e));
+    let updated = match ext.as_str() {
+        "flac" => rewrite_flac(&read()?, |blocks| {
+            blocks.retain(|(kind, body)| {
+                *kind != PICTURE || body.get(..4) != Some(&[0, 0, 0, FRONT_COVER])
+            });
+            // After the comments if there are any, else after STREAMINFO
+            let index = blocks
+                .iter()
+                .position(|(kind, _)| *kind == VORBIS_COMMENT)
+                .unwrap_or(0);
+            blocks.insert(index + 1, (PICTURE, cover.picture_block()));
+            Ok(())
+        })?,
+        "ogg" | "oga" | "opus" => update_ogg(
+            path,
+            &[(
+                "METADATA_BLOCK_PICTURE",
+                base64::engine::general_purpose::STANDARD.encode(cover.picture_block()),
+            )],
+        )?,
+        "mp3" => rewrite_id3(
+            &read()?,
+            |id, data| id == b"APIC" && apic_picture_type(data) == Some(FRONT_COVER),
+            |_| {
+                // Latin-1 media type, then the picture type and an empty description
+                let mut data = vec![0];
+                data.extend_from_slice(cover.media_type.as_bytes());
+                data.extend_from_slice(&[0, FRONT_COVER, 0]);
+                data.extend_from_slice(&cover.data);
+                vec![(*b"APIC", data)]
+            },
+        )?,
+        "m4a" | "m4b" | "mp4" => update_mp4_items(&read()?, &[(*b"covr", mp4_cover_item(cover))])?,
+        _ => return Err(format!("can't embed cover art in .{} files", ext)),
+    };
+    replace_file(path, &updated)
+}
+
+/// Writes `bytes` next to `path` and moves them over it
+fn replace_file(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    fs::write(&temp, bytes)
+        .and_then(|()| fs::rename(&temp, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp);
+            format!("failed to write {}: {}", path.display(), e)
+        })
+}
+
+/// Replaces or adds `fields` in a Vorbis comment header body, keeping its
+/// vendor string. Anything after the comments, such as the framing bit of
+/// a Vorbis header, is kept too.
+fn update_comments(block: &[u8], fields: &[(&str, String)]) -> Result<Vec<u8>, String> {
+    let invalid = || "invalid Vorbis comment block".to_string();
+    let mut pos = 0;
+    let read_u32 = |pos: &mut usize| -> Result<usize, String> {
+        let bytes = block.get(*pos..*pos + 4).ok_or_else(invalid)?;
+        *pos += 4;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+    let read_string = |pos: &mut usize, len: usize| -> Result<Vec<u8>, String> {
+        let value = block.get(*pos..*pos + len).ok_or_else(invalid)?.to_vec();
+        *pos += len;
+        Ok(value)
+    };
+
+    let len = read_u32(&mut pos)?;
+    let vendor = read_string(&mut pos, len)?;
+    let count = read_u32(&mut pos)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let len = read_u32(&mut pos)?;
+        entries.push(read_string(&mut pos, len)?);
+    }
+    let rest = &block[pos..];
+
+    let replaced = |entry: &[u8]| {
+        let key = entry.split(|&b| b == b'=').next().unwrap_or_default();
+        fields
+            .iter()
+            .any(|(name, _)| name.as_bytes().eq_ignore_ascii_case(key))
+    };
+    let entries: Vec<Vec<u8>> = entries
+        .into_iter()
+        .filter(|entry| !replaced(entry))
+        .chain(
+            fields
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value).into_bytes()),
+        )
+        .collect();
+
+    let mut updated = Vec::new();
+    push_string(&mut updated, &vendor);
+    updated.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for entry in entries {
+        push_string(&mut updated, &entry);
+    }
+    updated.extend_from_slice(rest);
+    Ok(updated)
+}
+
+/// An empty Vorbis comment header body
+fn empty_comments() -> Vec<u8> {
+    let mut block = Vec::new();
+    push_string(&mut block, VENDOR.as_bytes());
+    block.extend_from_slice(&0u32.to_le_bytes());
+    block
+}
+
+fn update_flac(bytes: &[u8], fields: &[(&str, String)]) -> Result<Vec<u8>, String> {
+    rewrite_flac(bytes, |blocks| {
+        match blocks.iter_mut().find(|(kind, _)| *kind == VORBIS_COMMENT) {
+            Some((_, body)) => *body = update_comments(body, fields)?,
+            // Right after STREAMINFO, which has to come first
+            None => blocks.insert(
+                1,
+                (VORBIS_COMMENT, update_comments(&empty_comments(), fields)?),
+            ),
+        }
+        Ok(())
+    })
+}
+
+/// Rewrites a FLAC file with its metadata blocks, as (type, body) in file
+/// order, changed by `edit`
+fn rewrite_flac(
+    bytes: &[u8],
+    edit: impl FnOnce(&mut Vec<(u8, Vec<u8>)>) -> Result<(), String>,
+) -> Result<Vec<u8>, String> {
+    let invalid = || "not a FLAC file".to_string();
+    if !bytes.starts_with(b"fLaC") {
+        return Err(invalid());
+    }
+
+    // Metadata blocks as (type, body) up to the first audio frame
+    let mut blocks = Vec::new();
+    let mut pos = 4;
+    loop {
+        let header = bytes.get(pos..pos + 4).ok_or_else(invalid)?;
+        let last = header[0] & 0x80 != 0;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let body = bytes.get(pos + 4..pos + 4 + len).ok_or_else(invalid)?;
+        blocks.push((header[0] & 0x7f, body.to_vec()));
+        pos += 4 + len;
+        if last {
+            break;
+        }
+    }
+    let audio = &bytes[pos..];
+    edit(&mut blocks)?;
+
+    let mut updated = b"fLaC".to_vec();
+    let count = blocks.len();
+    for (i, (kind, body)) in blocks.into_iter().enumerate() {
+        if body.len() >= 1 << 24 {
+            return Err("FLAC metadata block too large".to_string());
+        }
+        let last = if i + 1 == count { 0x80 } else { 0 };
+        updated.push(kind | last);
+        updated.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        updated.extend(body);
+    }
+    updated.extend_from_slice(audio);
+    Ok(updated)
+}
+
+/// Rewrites an Ogg Vorbis or Opus file with new comment headers. Packets
+/// are written back with their original granule positions and page ends,
+/// only the pages around the comment header change.
+fn update_ogg(path: &Path, fields: &[(&str, String)]) -> Result<Vec<u8>, String> {
+    let read_error = |e: ogg::OggReadError| format!("failed to read {}: {}", path.display(), e);
+    let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+    let mut reader = PacketReader::new(BufReader::new(file));
+    let mut writer = PacketWriter::new(Vec::new());
+    // Packets seen so far in each logical stream
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+
+    while let Some(packet) = reader.read_packet().map_err(read_error)? {
+        let serial = packet.stream_serial();
+        let index = counts.entry(serial).or_default();
+        *index += 1;
+
+        let end = if packet.last_in_stream() {
+            PacketWriteEndInfo::EndStream
+        } else if packet.last_in_page() {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        let absgp = packet.absgp_page();
+        let mut data = packet.data;
+        if *index == 2 {
+            for magic in [&b"\x03vorbis"[..], b"OpusTags"] {
+                if let Some(block) = data.strip_prefix(magic) {
+                    data = [magic, &update_comments(block, fields)?].concat();
+                    break;
+                }
+            }
+        }
+        writer
+            .write_packet(data, serial, end, absgp)
+            .map_err(|e| format!("failed to write Ogg page: {}", e))?;
+    }
+    Ok(writer.into_inner())
+}
+
+/// Fields written to a standard ID3v2 text frame instead of a `TXXX` one
+const ID3_TEXT_FRAMES: [(&str, &[u8; 4]); 5] = [
+    ("TITLE", b"TIT2"),
+    ("ARTIST", b"TPE1"),
+    ("ALBUM", b"TALB"),
+    ("BPM", b"TBPM"),
+    ("KEY", b"TKEY"),
+];
+
+fn id3_text_frame(name: &str) -> Option<&'static [u8; 4]> {
+    ID3_TEXT_FRAMES
+        .iter()
+        .find(|(field, _)| field.eq_ignore_ascii_case(name))
+        .map(|(_, id)| *id)
+}
+
+/// Replaces or adds text frames in the file's ID3v2 tag, creating an
+/// ID3v2.3 tag if there is none
+fn update_id3(bytes: &[u8], fields: &[(&str, String)]) -> Result<Vec<u8>, String> {
+    let replaced = |id: &[u8; 4], data: &[u8]| {
+        fields.iter().any(|(name, _)| match id3_text_frame(name) {
+            Some(frame) => frame == id,
+            None => {
+                id == b"TXXX"
+                    && txxx_description(data)
+                        .is_some_and(|description| name.eq_ignore_ascii_case(&description))
+            }
+        })
+    };
+    rewrite_id3(bytes, replaced, |version| {
+        fields
+            .iter()
+            .map(|(name, value)| {
+                // Latin-1 in 2.3, which has no UTF-8; UTF-8 in 2.4
+                let mut data = vec![if version == 4 { 3 } else { 0 }];
+                let id = match id3_text_frame(name) {
+                    Some(id) => id,
+                    None => {
+                        data.extend(latin1_or_utf8(name, version));
+                        data.push(0);
+                        b"TXXX"
+                    }
+                };
+                data.extend(latin1_or_utf8(value, version));
+                (*id, data)
+            })
+            .collect()
+    })
+}
+
+/// Rewrites the file's ID3v2 tag without the frames `replaced` picks, as
+/// (id, data), and with the frames `added` makes for the tag's version
+/// appended, creating an ID3v2.3 tag if there is none
+fn rewrite_id3(
+    bytes: &[u8],
+    replaced: impl Fn(&[u8; 4], &[u8]) -> bool,
+    added: impl FnOnce(u8) -> Vec<([u8; 4], Vec<u8>)>,
+) -> Result<Vec<u8>, String> {
+    let (version, frames, audio) = if bytes.starts_with(b"ID3") && bytes.len() >= 10 {
+        let version = bytes[3];
+        let flags = bytes[5];
+        if !(3..=4).contains(&version) {
+            return Err(format!("unsupported ID3v2.{} tag", version));
+        }
+        if flags & 0x80 != 0 {
+            return Err("unsynchronised ID3 tags are not supported".to_string());
+        }
+        let size = syncsafe(&bytes[6..10]);
+        let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+        let tag = bytes
+            .get(10..10 + size)
+            .ok_or("ID3 tag runs past the end of the file")?;
+        let tag = if flags & 0x40 != 0 {
+            // Skip the extended header, whose size counts itself in 2.4 only
+            let header = tag.get(..4).ok_or("invalid ID3 tag")?;
+            let len = match version {
+                4 => syncsafe(header),
+                _ => 4 + u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize,
+            };
+            tag.get(len..).ok_or("invalid ID3 tag")?
+        } else {
+            tag
+        };
+        (
+            version,
+            id3_frames(tag, version),
+            bytes.get(10 + size + footer..).unwrap_or_default(),
+        )
+    } else {
+        (3, Vec::new(), bytes)
+    };
+
+    let mut body = Vec::new();
+    for (id, flags, data) in frames {
+        if !replaced(&id, &data) {
+            push_id3_frame(&mut body, version, &id, flags, &data);
+        }
+    }
+    for (id, data) in added(version) {
+        push_id3_frame(&mut body, version, &id, [0, 0], &data);
+    }
+    if body.len() >= 1 << 28 {
+        return Err("ID3 tag too large".to_string());
+    }
+
+    let mut updated = b"ID3".to_vec();
+    updated.extend_from_slice(&[version, 0, 0]);
+    updated.extend_from_slice(&to_syncsafe(body.len()));
+    updated.extend(body);
+    updated.extend_from_slice(audio);
+    Ok(updated)
+}
+
+/// The frames of an ID3v2 tag body as (id, flags, data), up to the padding
+fn id3_frames(mut tag: &[u8], version: u8) -> Vec<([u8; 4], [u8; 2], Vec<u8>)> {
+    let mut frames = Vec::new();
+    while tag.len() >= 10 && tag[0] != 0 {
+        let size = match version {
+            4 => syncsafe(&tag[4..8]),
+            _ => u32::from_be_bytes([tag[4], tag[5], tag[6], tag[7]]) as usize,
+        };
+        let Some(data) = tag.get(10..10 + size) else {
+            break;
+        };
+        frames.push((
+            [tag[0], tag[1], tag[2], tag[3]],
+            [tag[8], tag[9]],
+            data.to_vec(),
+        ));
+        tag = &tag[10 + size..];
+    }
+    frames
+}
+
+fn push_id3_frame(body: &mut Vec<u8>, version: u8, id: &[u8; 4], flags: [u8; 2], data: &[u8]) {
+    body.extend_from_slice(id);
+    match version {
+        4 => body.extend_from_slice(&to_syncsafe(data.len())),
+        _ => body.extend_from_slice(&(data.len() as u32).to_be_bytes()),
+    }
+    body.extend_from_slice(&flags);
+    body.extend_from_slice(data);
+}
+
+/// The picture type of an `APIC` frame, which follows its text encoding
+/// and media type
+fn apic_picture_type(data: &[u8]) -> Option<u8> {
+    let media_type = data.get(1..)?;
+    let end = media_type.iter().position(|&b| b == 0)?;
+    media_type.get(end + 1).copied()
+}
+
+/// The description of a `TXXX` frame, which comes before its value
+fn txxx_description(data: &[u8]) -> Option<String> {
+    let (&encoding, text) = data.split_first()?;
+    match encoding {
+        0 => Some(
+            text.iter()
+                .take_while(|&&b| b != 0)
+                .map(|&b| b as char)
+                .collect(),
+        ),
+        3 => {
+            let end = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+            String::from_utf8(text[..end].to_vec()).ok()
+        }
+        1 | 2 => {
+            let units: Vec<[u8; 2]> = text
+                .chunks_exact(2)
+                .map(|unit| [unit[0], unit[1]])
+                .take_while(|&unit| unit != [0, 0])
+                .collect();
+            let (little_endian, units) = match units.first() {
+                Some([0xff, 0xfe]) => (true, &units[1..]),
+                Some([0xfe, 0xff]) => (false, &units[1..]),
+                _ => (encoding == 1, &units[..]),
+            };
+            let units: Vec<u16> = units
+                .iter()
+                .map(|&unit| match little_endian {
+                    true => u16::from_le_bytes(unit),
+                    false => u16::from_be_bytes(unit),
+                })
+                .collect();
+            String::from_utf16(&units).ok()
+        }
+        _ => None,
+    }
+}
+
+fn latin1_or_utf8(text: &str, version: u8) -> Vec<u8> {
+    match version {
+        4 => text.as_bytes().to_vec(),
+        _ => text
+            .chars()
+            .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+            .collect(),
+    }
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, &b| (size << 7) | (b & 0x7f) as usize)
+}
+
+fn to_syncsafe(size: usize) -> [u8; 4] {
+    [
+        (size >> 21) as u8 & 0x7f,
+        (size >> 14) as u8 & 0x7f,
+        (size >> 7) as u8 & 0x7f,
+        size as u8 & 0x7f,
+    ]
+}
+
+/// An MP4 box within a buffer, by offsets into it
+struct Mp4Box {
+    kind: [u8; 4],
+    start: usize,
+    /// Where the body starts, after the size, type and any 64-bit size
+    body: usize,
+    end: usize,
+}
+
+/// The boxes laid end to end in `data`
+fn mp4_boxes(data: &[u8]) -> Result<Vec<Mp4Box>, String> {
+    let invalid = || "invalid MP4 box".to_string();
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let size = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+        let (header, size) = match size {
+            // A 64-bit size follows the type
+            1 => {
+                let bytes = data.get(pos + 8..pos + 16).ok_or_else(invalid)?;
+                let size = u64::from_be_bytes(bytes.try_into().map_err(|_| invalid())?);
+                (16, usize::try_from(size).map_err(|_| invalid())?)
+            }
+            // The box runs to the end
+            0 => (8, data.len() - pos),
+            size => (8, size as usize),
+        };
+        if size < header || pos + size > data.len() {
+            return Err(invalid());
+        }
+        boxes.push(Mp4Box {
+            kind: [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]],
+            start: pos,
+            body: pos + header,
+            end: pos + size,
+        });
+        pos += size;
+    }
+    Ok(boxes)
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8 + body.len());
+    data.extend_from_slice(&(8 + body.len() as u32).to_be_bytes());
+    data.extend_from_slice(kind);
+    data.extend_from_slice(body);
+    data
+}
+
+/// The children in `body` with the first `kind` box's body replaced by
+/// what `edit` makes of it, adding the box at the end if there is none
+fn edit_mp4_child(
+    body: &[u8],
+    kind: &[u8; 4],
+    edit: impl FnOnce(&[u8]) -> Result<Vec<u8>, String>,
+) -> Result<Vec<u8>, String> {
+    let mut edited = Vec::with_capacity(body.len());
+    let mut edit = Some(edit);
+    for child in mp4_boxes(body)? {
+        match edit.take_if(|_| &child.kind == kind) {
+            Some(edit) => edited.extend(mp4_box(kind, &edit(&body[child.body..child.end])?)),
+            None => edited.extend_from_slice(&body[child.start..child.end]),
+        }
+    }
+    if let Some(edit) = edit {
+        edited.extend(mp4_box(kind, &edit(&[])?));
+    }
+    Ok(edited)
+}
+
+/// The `covr` item of an iTunes-style tag, as a `data` box of the type
+/// that marks JPEG or PNG
+fn mp4_cover_item(cover: &CoverArt) -> Vec<u8> {
+    let kind: u32 = if cover.media_type == "image/png" {
+        14
+    } else {
+        13
+    };
+    let mut data = kind.to_be_bytes().to_vec();
+    // Locale, unused
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&cover.data);
+    mp4_box(b"data", &data)
+}
+
+/// Replaces or adds `items`, as (name, body), in the iTunes-style tag at
+/// `moov/udta/meta/ilst`, creating the boxes on the way if needed. When
+/// `moov` comes before the media data, the chunk offsets of every track
+/// are moved by however much it grew or shrank.
+fn update_mp4_items(bytes: &[u8], items: &[([u8; 4], Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let boxes = mp4_boxes(bytes)?;
+    if boxes.first().map(|first| &first.kind) != Some(b"ftyp") {
+        return Err("not an MP4 file".to_string());
+    }
+    let moov = boxes
+        .iter()
+        .find(|child| &child.kind == b"moov")
+        .ok_or("MP4 file has no moov box")?;
+
+    let body = edit_mp4_child(&bytes[moov.body..moov.end], b"udta", |udta| {
+        edit_mp4_child(udta, b"meta", |meta| {
+            // A full box, with a version and flags before its children
+            let (header, children) = match meta.len() {
+                0 => (&[0u8; 4][..], &[][..]),
+                _ => meta.split_at(4.min(meta.len())),
+            };
+            let mut children = children.to_vec();
+            if !mp4_boxes(&children)?
+                .iter()
+                .any(|child| &child.kind == b"hdlr")
+            {
+                children.splice(0..0, mp4_box(b"hdlr", &ITUNES_HANDLER));
+            }
+            let children = edit_mp4_child(&children, b"ilst", |ilst| {
+                let mut edited = Vec::with_capacity(ilst.len());
+                for item in mp4_boxes(ilst)? {
+                    if !items.iter().any(|(name, _)| name == &item.kind) {
+                        edited.extend_from_slice(&ilst[item.start..item.end]);
+                    }
+                }
+                for (name, item) in items {
+                    edited.extend(mp4_box(name, item));
+                }
+                Ok(edited)
+            })?;
+            Ok([header, &children].concat())
+        })
+    })?;
+    if body.len() + 8 > u32::MAX as usize {
+        return Err("MP4 moov box too large".to_string());
+    }
+    let mut moov_box = mp4_box(b"moov", &body);
+
+    let delta = moov_box.len() as i64 - (moov.end - moov.start) as i64;
+    if delta != 0 {
+        let mut tables = Vec::new();
+        chunk_offset_tables(&moov_box[8..], 8, &mut tables)?;
+        for (kind, range) in tables {
+            shift_chunk_offsets(&mut moov_box[range], &kind, moov.start as u64, delta)?;
+        }
+    }
+
+    let mut updated = Vec::with_capacity(bytes.len() + moov_box.len());
+    updated.extend_from_slice(&bytes[..moov.start]);
+    updated.extend(moov_box);
+    updated.extend_from_slice(&bytes[moov.end..]);
+    Ok(updated)
+}
+
+/// The `stco` and `co64` bodies of every track in a `moov` body, as (type,
+/// range), with `base` added to the ranges
+fn chunk_offset_tables(
+    data: &[u8],
+    base: usize,
+    tables: &mut Vec<([u8; 4], std::ops::Range<usize>)>,
+) -> Result<(), String> {
+    for child in mp4_boxes(data)? {
+        match &child.kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => {
+                chunk_offset_tables(&data[child.body..child.end], base + child.body, tables)?
+            }
+            b"stco" | b"co64" => tables.push((child.kind, base + child.body..base + child.end)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Moves the file offsets in a chunk offset table that point past `from`
+/// by `delta` bytes
+fn shift_chunk_offsets(
+    table: &mut [u8],
+    kind: &[u8; 4],
+    from: u64,
+    delta: i64,
+) -> Result<(), String> {
+    let width = if kind == b"co64" { 8 } else { 4 };
+    let entries = table.get_mut(8..).ok_or("invalid MP4 chunk offset table")?;
+    for entry in entries.chunks_exact_mut(width) {
+        let offset = entry.iter().fold(0u64, |offset, &b| offset << 8 | b as u64);
+        if offset <= from {
+            continue;
+        }
+        let shifted = offset
+            .checked_add_signed(delta)
+            .ok_or("invalid MP4 chunk offset")?;
+        if width == 4 {
+            let shifted = u32::try_from(shifted)
+                .map_err(|_| "MP4 chunk offsets no longer fit in 32 bits".to_string())?;
+            entry.copy_from_slice(&shifted.to_be_bytes());
+        } else {
+            entry.copy_from_slice(&shifted.to_be_bytes());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use mogbox_engine::{ChannelMapper, SilenceDetector};
+use mogbox_io::cue::CueSheet;
+use mogbox_io::AudioFile;
+
+use crate::convert::{encode_frames, Effects, FrameReader};
+use crate::{EncodeOptions, EncoderBackend, TrackTags};
+
+/// A region of the source written to its own file
+#[derive(Clone, Debug)]
+pub struct Segment {
+    /// First frame, at the source's sample rate
+    pub start: u64,
+    /// Frame after the last one; the end of the source when unset
+    pub end: Option<u64>,
+    pub output: PathBuf,
+    pub tags: TrackTags,
+}
+
+/// Writes each segment of `file` to its own output in one pass over the
+/// source. Segments must be in order and must not overlap; audio between
+/// them is dropped.
+pub fn split(
+    mut file: AudioFile,
+    segments: &[Segment],
+    backend: &dyn EncoderBackend,
+    options: &EncodeOptions,
+) -> Result<(), String> {
+    let bits_per_sample = options.bits_per_sample.or(file
+        .bits_per_sample
+        .map(|bits| backend.bits_per_sample(bits as u16)));
+    let sample_rate = backend.sample_rate(options.sample_rate.unwrap_or(file.sample_rate));
+    let channels = backend.channels(file.channels as usize);
+    let mapper = ChannelMapper::new(file.channels as usize, channels);
+
+    let mut reader = FrameReader::new(&mut file);
+    let mut position = 0;
+    for segment in segments {
+        if segment.start < position {
+            return Err(format!(
+                "{} overlaps the segment before it",
+                segment.output.display()
+            ));
+        }
+        reader.skip(segment.start - position)?;
+
+        let options = EncodeOptions {
+            bits_per_sample,
+            tags: segment.tags.clone(),
+            ..options.clone()
+        };
+        let frames = segment.end.map(|end| end.saturating_sub(segment.start));
+        let mut encoder = backend.create(&segment.output, sample_rate, channels, &options)?;
+        encode_frames(
+            &mut reader,
+            encoder.as_mut(),
+            sample_rate,
+            &mapper,
+            frames,
+            Effects::default(),
+            options.resampler,
+        )?;
+        encoder
+            .finish()
+            .map_err(|e| format!("{}: {}", segment.output.display(), e))?;
+
+        match segment.end {
+            Some(end) => position = end,
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// Segments for the tracks of a cue sheet that are in `input`, named
+/// `NN - Title` in `out_dir` and tagged from the sheet. Other tags and
+/// the cover are kept from the source unless `options.strip_tags` is set.
+pub fn cue_segments(
+    sheet: &CueSheet,
+    input: &Path,
+    file: &AudioFile,
+    out_dir: &Path,
+    backend: &dyn EncoderBackend,
+    options: &EncodeOptions,
+) -> Result<Vec<Segment>, String> {
+    // A sheet for a single file still applies when the rip was converted
+    // or renamed afterwards
+    let files = sheet.files();
+    let tracks: Vec<_> = if files.len() == 1 {
+        sheet.tracks.iter().collect()
+    } else {
+        sheet
+            .tracks
+            .iter()
+            .filter(|track| track.file.file_name() == input.file_name())
+            .collect()
+    };
+    if tracks.is_empty() {
+        return Err(format!(
+            "the cue sheet has no tracks for {}",
+            input.display()
+        ));
+    }
+
+    let base = if options.strip_tags {
+        TrackTags::default()
+    } else {
+        TrackTags::from_file(file)
+    };
+    let extension = backend.extensions()[0];
+
+    let mut segments = Vec::with_capacity(tracks.len());
+    for (i, track) in tracks.iter().enumerate() {
+        let title = track
+            .title
+            .clone()
+            .unwrap_or_else(|| format!("Track {}", track.number));
+        let name = format!("{:02} - {}.{}", track.number, file_name(&title), extension);
+        segments.push(Segment {
+            start: track.start_frame(file.sample_rate),
+            end: tracks
+                .get(i + 1)
+                .map(|next| next.start_frame(file.sample_rate)),
+            output: out_dir.join(name),
+            tags: TrackTags {
+                title: Some(title),
+                artist: track
+                    .performer
+                    .clone()
+                    .or(sheet.performer.clone())
+                    .or(base.artist.clone()),
+                album: sheet.title.clone().or(base.album.clone()),
+                album_artist: sheet.performer.clone().or(base.album_artist.clone()),
+                track_number: Some(track.number.to_string()),
+                date: sheet.date.clone().or(base.date.clone()),
+                genre: sheet.genre.clone().or(base.genre.clone()),
+                ..base.clone()
+            },
+        });
+    }
+    Ok(segments)
+}
+
+/// Segments for the parts of `input` between stretches of at least
+/// `min_gap` below `threshold_db`, which are left out. They are named after
+/// the input with a track number, in `out_dir`. Decodes the whole input
+/// once to find the gaps.
+pub fn silence_segments(
+    input: &Path,
+    threshold_db: f32,
+    min_gap: Duration,
+    out_dir: &Path,
+    backend: &dyn EncoderBackend,
+    options: &EncodeOptions,
+) -> Result<Vec<Segment>, String> {
+    let mut file = AudioFile::open(&input.to_path_buf())?;
+    let mut detector = SilenceDetector::new(
+        file.sample_rate,
+        file.channels as usize,
+        threshold_db,
+        min_gap,
+    );
+    while let Some(samples) = file.next_samples()? {
+        detector.process(&samples);
+    }
+    let frames = detector.frames();
+    let gaps = detector.finish();
+
+    // The sounding parts are what lies between the gaps
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for gap in gaps.iter().chain(std::iter::once(&(frames..frames))) {
+        if gap.start > start {
+            parts.push(start..gap.start);
+        }
+        start = gap.end;
+    }
+    if parts.is_empty() {
+        return Err("nothing but silence".to_string());
+    }
+
+    let base = if options.strip_tags {
+        TrackTags::default()
+    } else {
+        TrackTags {
+            title: None,
+            ..TrackTags::from_file(&file)
+        }
+    };
+    let stem = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = backend.extensions()[0];
+    Ok(parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| Segment {
+            start: part.start,
+            end: Some(part.end),
+            output: out_dir.join(format!("{} {:02}.{}", stem, i + 1, extension)),
+            tags: TrackTags {
+                track_number: Some((i + 1).to_string()),
+                ..base.clone()
+            },
+        })
+        .collect())
+}
+
+/// Replaces characters that are not allowed in file names on common systems
+fn file_name(title: &str) -> String {
+    title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim()
+        .trim_end_matches('.')
+        .to_string()
+}
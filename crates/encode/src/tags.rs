@@ -0,0 +1,162 @@
+use std::fmt;
+
+use base64::Engine;
+use mogbox_io::AudioFile;
+use symphonia::core::meta::StandardTagKey;
+
+/// Written as the encoder name in Vorbis comment headers
+pub(crate) const VENDOR: &str = concat!("mogbox ", env!("CARGO_PKG_VERSION"));
+
+/// Tags copied from the source to the converted file
+#[derive(Clone, Debug, Default)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<String>,
+    pub disc_number: Option<String>,
+    pub date: Option<String>,
+    pub genre: Option<String>,
+    pub cover: Option<CoverArt>,
+}
+
+/// An embedded picture, stored as the original image file
+#[derive(Clone)]
+pub struct CoverArt {
+    /// MIME type such as `image/jpeg`
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+impl fmt::Debug for CoverArt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoverArt")
+            .field("media_type", &self.media_type)
+            .field("bytes", &self.data.len())
+            .finish()
+    }
+}
+
+impl CoverArt {
+    /// Cover art from the contents of a PNG, JPEG or GIF file, with the
+    /// media type read from its signature
+    pub fn from_image(data: Vec<u8>) -> Result<Self, String> {
+        let media_type = if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            "image/png"
+        } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            "image/jpeg"
+        } else if data.starts_with(b"GIF8") {
+            "image/gif"
+        } else {
+            return Err("unsupported image format (expected PNG, JPEG or GIF)".to_string());
+        };
+        Ok(CoverArt {
+            media_type: media_type.to_string(),
+            data,
+        })
+    }
+
+    /// The picture as a front cover FLAC `PICTURE` block body. Dimensions
+    /// are left at 0, which readers take as unknown.
+    pub(crate) fn picture_block(&self) -> Vec<u8> {
+        let mut block = Vec::new();
+        // Picture type 3 is the front cover
+        block.extend_from_slice(&3u32.to_be_bytes());
+        block.extend_from_slice(&(self.media_type.len() as u32).to_be_bytes());
+        block.extend_from_slice(self.media_type.as_bytes());
+        // Empty description, then width, height, depth and palette size
+        block.extend_from_slice(&[0; 20]);
+        block.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        block.extend_from_slice(&self.data);
+        block
+    }
+}
+
+impl TrackTags {
+    /// Reads the tags and front cover (or else the first picture) of an opened file
+    pub fn from_file(file: &AudioFile) -> Self {
+        let cover = file.cover().map(|visual| CoverArt {
+            media_type: visual.media_type.clone(),
+            data: visual.data.to_vec(),
+        });
+
+        TrackTags {
+            title: file.tag(StandardTagKey::TrackTitle),
+            artist: file.tag(StandardTagKey::Artist),
+            album: file.tag(StandardTagKey::Album),
+            album_artist: file.tag(StandardTagKey::AlbumArtist),
+            track_number: file.tag(StandardTagKey::TrackNumber),
+            disc_number: file.tag(StandardTagKey::DiscNumber),
+            date: file.tag(StandardTagKey::Date),
+            genre: file.tag(StandardTagKey::Genre),
+            cover,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vorbis_comments().is_empty() && self.cover.is_none()
+    }
+
+    /// The year at the start of the date, for formats that only store a year
+    pub fn year(&self) -> Option<&str> {
+        let date = self.date.as_deref()?;
+        date.get(..4)
+            .filter(|year| year.chars().all(|c| c.is_ascii_digit()))
+    }
+
+    /// Text fields under their Vorbis comment names, as used by FLAC, Vorbis and Opus
+    pub(crate) fn vorbis_comments(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("TITLE", &self.title),
+            ("ARTIST", &self.artist),
+            ("ALBUM", &self.album),
+            ("ALBUMARTIST", &self.album_artist),
+            ("TRACKNUMBER", &self.track_number),
+            ("DISCNUMBER", &self.disc_number),
+            ("DATE", &self.date),
+            ("GENRE", &self.genre),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.as_deref()?)))
+        .collect()
+    }
+
+    /// Vorbis comments plus the cover as a base64 `METADATA_BLOCK_PICTURE`,
+    /// the form Ogg streams carry pictures in
+    pub(crate) fn ogg_comments(&self) -> Vec<(&'static str, String)> {
+        let mut comments: Vec<(&'static str, String)> = self
+            .vorbis_comments()
+            .into_iter()
+            .map(|(key, value)| (key, value.to_string()))
+            .collect();
+        if let Some(picture) = self.picture_block() {
+            comments.push((
+                "METADATA_BLOCK_PICTURE",
+                base64::engine::general_purpose::STANDARD.encode(picture),
+            ));
+        }
+        comments
+    }
+
+    /// The cover as a FLAC `PICTURE` block body
+    pub(crate) fn picture_block(&self) -> Option<Vec<u8>> {
+        self.cover.as_ref().map(CoverArt::picture_block)
+    }
+}
+
+/// A Vorbis comment header body: vendor string followed by `KEY=value` entries
+pub(crate) fn comment_block<V: AsRef<str>>(comments: &[(&str, V)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    push_string(&mut block, VENDOR.as_bytes());
+    block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for (key, value) in comments {
+        push_string(&mut block, format!("{}={}", key, value.as_ref()).as_bytes());
+    }
+    block
+}
+
+pub(crate) fn push_string(block: &mut Vec<u8>, value: &[u8]) {
+    block.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    block.extend_from_slice(value);
+}
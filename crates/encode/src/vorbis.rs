@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::num::{NonZeroU32, NonZeroU8};
+use std::path::Path;
+
+use vorbis_rs::{VorbisBitrateManagementStrategy, VorbisEncoder, VorbisEncoderBuilder};
+
+use crate::{EncodeOptions, Encoder, EncoderBackend};
+
+/// Frames handed to libvorbis per block, as its documentation suggests
+const BLOCK_FRAMES: usize = 1024;
+
+/// Ogg Vorbis, either at a quality level (-1 to 10, default 5) or a target bitrate
+pub struct VorbisBackend;
+
+impl EncoderBackend for VorbisBackend {
+    fn name(&self) -> &'static str {
+        "vorbis"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ogg", "oga"]
+    }
+
+    fn create(
+        &self,
+        path: &Path,
+        sample_rate: u32,
+        channels: usize,
+        options: &EncodeOptions,
+    ) -> Result<Box<dyn Encoder>, String> {
+        let strategy = match (options.bitrate, options.quality) {
+            (Some(bitrate), _) => VorbisBitrateManagementStrategy::Vbr {
+                target_bitrate: NonZeroU32::new(bitrate).ok_or("bitrate must not be 0")?,
+            },
+            (None, Some(quality)) if (-1.0..=10.0).contains(&quality) => {
+                VorbisBitrateManagementStrategy::QualityVbr {
+                    target_quality: quality / 10.0,
+                }
+            }
+            (None, Some(quality)) => {
+                return Err(format!("invalid Vorbis quality: {} (-1 to 10)", quality))
+            }
+            (None, None) => VorbisBitrateManagementStrategy::default(),
+        };
+
+        let sample_rate = NonZeroU32::new(sample_rate).ok_or("sample rate must not be 0")?;
+        let channel_count = u8::try_from(channels)
+            .ok()
+            .and_then(NonZeroU8::new)
+            .ok_or_else(|| format!("unsupported channel count for Vorbis: {}", channels))?;
+        let file = File::create(path)
+            .map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+
+        let encoder = VorbisEncoderBuilder::new(sample_rate, channel_count, BufWriter::new(file))
+            .and_then(|mut builder| {
+                builder
+                    .bitrate_management_strategy(strategy)
+                    .comment_tags(options.tags.ogg_comments())?
+                    .build()
+            })
+            .map_err(|e| format!("failed to start Vorbis encoder: {}", e))?;
+        Ok(Box::new(VorbisFileEncoder {
+            encoder,
+            planar: vec![Vec::with_capacity(BLOCK_FRAMES); channels],
+        }))
+    }
+}
+
+pub struct VorbisFileEncoder {
+    encoder: VorbisEncoder<BufWriter<File>>,
+    /// Deinterleaved samples waiting for a full block
+    planar: Vec<Vec<f32>>,
+}
+
+impl VorbisFileEncoder {
+    fn flush_block(&mut self) -> Result<(), String> {
+        self.encoder
+            .encode_audio_block(&self.planar)
+            .map_err(|e| format!("failed to encode Vorbis: {}", e))?;
+        for channel in self.planar.iter_mut() {
+            channel.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Encoder for VorbisFileEncoder {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let channels = self.planar.len();
+        for frame in samples.chunks_exact(channels) {
+            for (channel, &sample) in self.planar.iter_mut().zip(frame) {
+                channel.push(sample);
+            }
+            if self.planar[0].len() >= BLOCK_FRAMES {
+                self.flush_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), String> {
+        if !self.planar[0].is_empty() {
+            self.flush_block()?;
+        }
+        let mut writer = self
+            .encoder
+            .finish()
+            .map_err(|e| format!("failed to finish Vorbis stream: {}", e))?;
+        std::io::Write::flush(&mut writer).map_err(|e| format!("failed to write Vorbis: {}", e))
+    }
+}
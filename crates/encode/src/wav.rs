@@ -0,0 +1,187 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use mogbox_engine::{Dither, DitherMode, Processor};
+
+use crate::{
+    check_depth, depth_for, dither_for, quantize, EncodeOptions, Encoder, EncoderBackend, TrackTags,
+};
+
+/// Integer depths PCM WAV is written at
+const DEPTHS: &[u16] = &[8, 16, 24, 32];
+
+/// PCM WAV, 16 bit unless 8, 24 or 32 bit is asked for. Tags go in a
+/// `LIST INFO` chunk, which has no place for cover art.
+pub struct WavBackend;
+
+impl EncoderBackend for WavBackend {
+    fn name(&self) -> &'static str {
+        "wav"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["wav", "wave"]
+    }
+
+    fn bits_per_sample(&self, source_bits: u16) -> u16 {
+        depth_for(source_bits, DEPTHS)
+    }
+
+    fn create(
+        &self,
+        path: &Path,
+        sample_rate: u32,
+        channels: usize,
+        options: &EncodeOptions,
+    ) -> Result<Box<dyn Encoder>, String> {
+        let bits = check_depth(options.bits_per_sample.unwrap_or(16), DEPTHS, "WAV")?;
+        Ok(Box::new(
+            WavEncoder::with_tags(path, sample_rate, channels, bits, &options.tags)?
+                .with_dither(options.dither),
+        ))
+    }
+}
+
+pub struct WavEncoder {
+    writer: hound::WavWriter<BufWriter<File>>,
+    bits: u16,
+    dither: Option<Dither>,
+    buffer: Vec<f32>,
+}
+
+impl WavEncoder {
+    /// Creates a 16-bit WAV file
+    pub fn create(path: &Path, sample_rate: u32, channels: usize) -> Result<Self, String> {
+        WavEncoder::with_bits(path, sample_rate, channels, 16)
+    }
+
+    pub fn with_bits(
+        path: &Path,
+        sample_rate: u32,
+        channels: usize,
+        bits: u16,
+    ) -> Result<Self, String> {
+        WavEncoder::with_tags(path, sample_rate, channels, bits, &TrackTags::default())
+    }
+
+    /// Creates a WAV file with the tags in a `LIST INFO` chunk ahead of the
+    /// samples, where readers that stop at the data chunk still find them
+    pub fn with_tags(
+        path: &Path,
+        sample_rate: u32,
+        channels: usize,
+        bits: u16,
+        tags: &TrackTags,
+    ) -> Result<Self, String> {
+        let spec = hound::WavSpec {
+            channels: channels as u16,
+            sample_rate,
+            bits_per_sample: bits,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let create_error = |e: hound::Error| format!("failed to create {}: {}", path.display(), e);
+        let writer = hound::WavWriter::create(path, spec).map_err(create_error)?;
+
+        let Some(chunk) = info_chunk(tags) else {
+            return Ok(WavEncoder::new(writer, bits));
+        };
+        // Write out the empty file, slot the chunk in before the data chunk
+        // and carry on appending samples to it
+        writer.finalize().map_err(create_error)?;
+        insert_chunk(path, &chunk).map_err(|e| format!("failed to write WAV tags: {}", e))?;
+        let writer = hound::WavWriter::append(path).map_err(create_error)?;
+        Ok(WavEncoder::new(writer, bits))
+    }
+
+    fn new(writer: hound::WavWriter<BufWriter<File>>, bits: u16) -> Self {
+        WavEncoder {
+            writer,
+            bits,
+            dither: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Dithers samples before they are reduced to 16 or 24 bit
+    pub fn with_dither(mut self, mode: DitherMode) -> Self {
+        self.dither = dither_for(self.bits, self.writer.spec().channels as usize, mode);
+        self
+    }
+}
+
+impl Encoder for WavEncoder {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let samples = match self.dither.as_mut() {
+            Some(dither) => {
+                self.buffer.clear();
+                self.buffer.extend_from_slice(samples);
+                dither.process(&mut self.buffer);
+                &self.buffer[..]
+            }
+            None => samples,
+        };
+        for &sample in samples {
+            self.writer
+                .write_sample(quantize(sample, self.bits))
+                .map_err(|e| format!("failed to write WAV data: {}", e))?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), String> {
+        self.writer
+            .finalize()
+            .map_err(|e| format!("failed to finalize WAV file: {}", e))
+    }
+}
+
+/// A `LIST` chunk of `INFO` text fields, or `None` without any tags
+fn info_chunk(tags: &TrackTags) -> Option<Vec<u8>> {
+    let fields = [
+        (b"INAM", tags.title.as_deref()),
+        (b"IART", tags.artist.as_deref()),
+        (b"IPRD", tags.album.as_deref()),
+        (b"IPRT", tags.track_number.as_deref()),
+        (b"ICRD", tags.date.as_deref()),
+        (b"IGNR", tags.genre.as_deref()),
+    ];
+
+    let mut body = b"INFO".to_vec();
+    for (id, value) in fields {
+        let Some(value) = value else {
+            continue;
+        };
+        // NUL-terminated, padded to an even length
+        let size = value.len() + 1;
+        body.extend_from_slice(id);
+        body.extend_from_slice(&(size as u32).to_le_bytes());
+        body.extend_from_slice(value.as_bytes());
+        body.push(0);
+        if size % 2 == 1 {
+            body.push(0);
+        }
+    }
+    if body.len() == 4 {
+        return None;
+    }
+
+    let mut chunk = b"LIST".to_vec();
+    chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    chunk.extend(body);
+    Some(chunk)
+}
+
+/// Inserts a chunk before the data chunk of a WAV file without samples,
+/// updating the RIFF size
+fn insert_chunk(path: &Path, chunk: &[u8]) -> std::io::Result<()> {
+    let mut bytes = fs::read(path)?;
+    let data = bytes
+        .windows(4)
+        .rposition(|id| id == b"data")
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "no data chunk"))?;
+    bytes.splice(data..data, chunk.iter().copied());
+    let riff_size = (bytes.len() - 8) as u32;
+    bytes[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    fs::write(path, bytes)
+}
@@ -0,0 +1,248 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+/// Which inputs feed each output channel, e.g. `[[1], [0]]` swaps a stereo pair.
+/// An empty entry leaves that output silent.
+pub type ChannelRouting = Vec<Vec<usize>>;
+
+/// Gains from each input to each output channel, one row per output
+pub type ChannelMatrix = Vec<Vec<f32>>;
+
+/// Maps interleaved audio between channel layouts with a gain matrix:
+/// `out[o] = sum(matrix[o][i] * in[i])`.
+///
+/// Inputs are assumed to be in the usual WAV/SMPTE order
+/// (FL, FR, FC, LFE, BL, BR, SL, SR).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelMapper {
+    matrix: Vec<Vec<f32>>,
+    inputs: usize,
+}
+
+impl ChannelMapper {
+    /// Standard up/downmix between two channel counts. Mono is copied to the
+    /// front pair, surround layouts fold down to stereo with ITU-R BS.775
+    /// coefficients (normalized so a full-scale signal can not clip), and
+    /// anything else is mapped one to one.
+    pub fn new(inputs: usize, outputs: usize) -> Self {
+        let inputs = inputs.max(1);
+        let outputs = outputs.max(1);
+
+        if inputs == outputs {
+            return ChannelMapper::identity(inputs);
+        }
+        if inputs == 1 {
+            let mut matrix = vec![vec![0.0]; outputs];
+            for row in matrix.iter_mut().take(2) {
+                row[0] = 1.0;
+            }
+            return ChannelMapper { matrix, inputs };
+        }
+        if outputs == 1 {
+            let stereo = ChannelMapper::new(inputs, 2);
+            return stereo.then(&ChannelMapper::from_matrix(vec![vec![0.5, 0.5]]));
+        }
+        if outputs == 2 {
+            if let Some(matrix) = stereo_downmix(inputs) {
+                return ChannelMapper { matrix, inputs };
+            }
+        }
+
+        let matrix = (0..outputs)
+            .map(|o| {
+                (0..inputs)
+                    .map(|i| if i == o { 1.0 } else { 0.0 })
+                    .collect()
+            })
+            .collect();
+        ChannelMapper { matrix, inputs }
+    }
+
+    pub fn identity(channels: usize) -> Self {
+        ChannelMapper::new_unchecked(
+            (0..channels)
+                .map(|o| {
+                    (0..channels)
+                        .map(|i| if i == o { 1.0 } else { 0.0 })
+                        .collect()
+                })
+                .collect(),
+            channels,
+        )
+    }
+
+    /// Builds a mapper from a gain matrix with one row per output channel
+    pub fn from_matrix(matrix: Vec<Vec<f32>>) -> Self {
+        let inputs = matrix.iter().map(Vec::len).max().unwrap_or(1).max(1);
+        let matrix = matrix
+            .into_iter()
+            .map(|mut row| {
+                row.resize(inputs, 0.0);
+                row
+            })
+            .collect();
+        ChannelMapper::new_unchecked(matrix, inputs)
+    }
+
+    /// Builds a mapper from an explicit routing. Outputs fed by several
+    /// inputs take their average; inputs that do not exist are ignored.
+    pub fn from_routing(routing: &ChannelRouting, inputs: usize) -> Self {
+        let inputs = inputs.max(1);
+        let matrix = routing
+            .iter()
+            .map(|sources| {
+                let valid: Vec<usize> = sources.iter().copied().filter(|&i| i < inputs).collect();
+                let mut row = vec![0.0; inputs];
+                for &i in valid.iter() {
+                    row[i] += 1.0 / valid.len() as f32;
+                }
+                row
+            })
+            .collect();
+        ChannelMapper::new_unchecked(matrix, inputs)
+    }
+
+    fn new_unchecked(matrix: Vec<Vec<f32>>, inputs: usize) -> Self {
+        ChannelMapper { matrix, inputs }
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.matrix.len()
+    }
+
+    pub fn matrix(&self) -> &[Vec<f32>] {
+        &self.matrix
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == ChannelMapper::identity(self.inputs)
+    }
+
+    /// Chains two mappers: the result applies `self` first, then `next`
+    pub fn then(&self, next: &ChannelMapper) -> ChannelMapper {
+        let adapted;
+        let next = if next.inputs == self.outputs() {
+            next
+        } else {
+            adapted = ChannelMapper::new(self.outputs(), next.inputs).then(next);
+            &adapted
+        };
+
+        let matrix = next
+            .matrix
+            .iter()
+            .map(|row| {
+                (0..self.inputs)
+                    .map(|i| {
+                        row.iter()
+                            .zip(self.matrix.iter())
+                            .map(|(gain, inner)| gain * inner[i])
+                            .sum()
+                    })
+                    .collect()
+            })
+            .collect();
+        ChannelMapper::new_unchecked(matrix, self.inputs)
+    }
+
+    /// Maps interleaved input into `out`, replacing its contents
+    pub fn map_into(&self, input: &[f32], out: &mut Vec<f32>) {
+        out.clear();
+        if self.is_identity() {
+            out.extend_from_slice(input);
+            return;
+        }
+        out.reserve(input.len() / self.inputs * self.outputs());
+        for frame in input.chunks_exact(self.inputs) {
+            for row in self.matrix.iter() {
+                out.push(
+                    row.iter()
+                        .zip(frame)
+                        .map(|(gain, sample)| gain * sample)
+                        .sum(),
+                );
+            }
+        }
+    }
+
+    pub fn map(&self, input: &[f32]) -> Vec<f32> {
+        let mut out = Vec::new();
+        self.map_into(input, &mut out);
+        out
+    }
+}
+
+/// Parses a routing such as `1,0` (swap), `0,0` (left to both) or `0+1,-`
+/// (mono sum on the left, silent right). Each comma separated entry is an
+/// output channel listing its input channels.
+pub fn parse_routing(spec: &str) -> Result<ChannelRouting, String> {
+    spec.split(',')
+        .map(|entry| {
+            let entry = entry.trim();
+            if entry == "-" {
+                return Ok(Vec::new());
+            }
+            entry
+                .split('+')
+                .map(|input| {
+                    input
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid channel routing: {}", spec))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Parses a gain matrix such as `0.5,0.5` (mono sum) or `1,0,0.7;0,1,0.7`
+/// (L R C to stereo). Rows are output channels separated by `;`, each
+/// listing the gain of every input channel.
+pub fn parse_matrix(spec: &str) -> Result<ChannelMatrix, String> {
+    spec.split(';')
+        .map(|row| {
+            row.split(',')
+                .map(|gain| {
+                    gain.trim()
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|gain| gain.is_finite())
+                        .ok_or_else(|| format!("invalid channel matrix: {}", spec))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Stereo fold-down matrices for the common surround layouts
+fn stereo_downmix(inputs: usize) -> Option<Vec<Vec<f32>>> {
+    const C: f32 = FRAC_1_SQRT_2;
+    let (left, right): (Vec<f32>, Vec<f32>) = match inputs {
+        // L R C
+        3 => (vec![1.0, 0.0, C], vec![0.0, 1.0, C]),
+        // L R BL BR
+        4 => (vec![1.0, 0.0, C, 0.0], vec![0.0, 1.0, 0.0, C]),
+        // L R C BL BR
+        5 => (vec![1.0, 0.0, C, C, 0.0], vec![0.0, 1.0, C, 0.0, C]),
+        // 5.1: L R C LFE BL BR, the LFE is dropped
+        6 => (
+            vec![1.0, 0.0, C, 0.0, C, 0.0],
+            vec![0.0, 1.0, C, 0.0, 0.0, C],
+        ),
+        // 7.1: L R C LFE BL BR SL SR
+        8 => (
+            vec![1.0, 0.0, C, 0.0, C, 0.0, C, 0.0],
+            vec![0.0, 1.0, C, 0.0, 0.0, C, 0.0, C],
+        ),
+        _ => return None,
+    };
+
+    let normalize = |row: Vec<f32>| {
+        let sum: f32 = row.iter().sum();
+        row.into_iter().map(|gain| gain / sum).collect()
+    };
+    Some(vec![normalize(left), normalize(right)])
+}
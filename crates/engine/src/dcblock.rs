@@ -0,0 +1,61 @@
+use std::f32::consts::PI;
+
+use crate::Processor;
+
+/// A one-pole high-pass that removes DC offset while leaving the audible
+/// range alone
+pub struct DcBlocker {
+    cutoff: f32,
+    /// Pole of the filter, derived from the cutoff and sample rate
+    pole: f32,
+    channels: usize,
+    /// Previous input and output, per channel
+    state: Vec<(f32, f32)>,
+}
+
+impl DcBlocker {
+    /// Creates a blocker with the cutoff given in Hz
+    pub fn new(cutoff: f32) -> Self {
+        let mut blocker = DcBlocker {
+            cutoff,
+            pole: 0.0,
+            channels: 2,
+            state: Vec::new(),
+        };
+        blocker.prepare(44100, 2);
+        blocker
+    }
+
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+}
+
+impl Default for DcBlocker {
+    fn default() -> Self {
+        DcBlocker::new(10.0)
+    }
+}
+
+impl Processor for DcBlocker {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.channels = channels.max(1);
+        self.pole = (-2.0 * PI * self.cutoff / sample_rate.max(1) as f32).exp();
+        self.state = vec![(0.0, 0.0); self.channels];
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            for (sample, (x1, y1)) in frame.iter_mut().zip(self.state.iter_mut()) {
+                let y = *sample - *x1 + self.pole * *y1;
+                *x1 = *sample;
+                *y1 = y;
+                *sample = y;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state.fill((0.0, 0.0));
+    }
+}
@@ -0,0 +1,115 @@
+use std::str::FromStr;
+
+use crate::Processor;
+
+/// Error feedback filter for noise shaping (Lipshitz's E-weighted 3 tap
+/// filter), which moves the noise up to where hearing is least sensitive
+const SHAPING: [f32; 3] = [1.623, -0.982, 0.109];
+
+/// How samples are dithered before they are reduced to a lower bit depth
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DitherMode {
+    /// Plain rounding
+    #[default]
+    None,
+    /// Triangular noise of one step peak, which decorrelates the
+    /// rounding error from the signal
+    Tpdf,
+    /// TPDF with the noise shaped towards high frequencies
+    Shaped,
+}
+
+impl FromStr for DitherMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(DitherMode::None),
+            "tpdf" => Ok(DitherMode::Tpdf),
+            "shaped" => Ok(DitherMode::Shaped),
+            _ => Err(format!(
+                "invalid dither mode: {} (expected tpdf, shaped or none)",
+                s
+            )),
+        }
+    }
+}
+
+/// Dithers and rounds samples to the steps of a `bits` deep signed integer
+/// format, so that converting them afterwards is exact
+pub struct Dither {
+    mode: DitherMode,
+    scale: f32,
+    channels: usize,
+    /// Last rounding errors per channel, newest first
+    errors: Vec<[f32; 3]>,
+    state: u32,
+}
+
+impl Dither {
+    pub fn new(bits: u16, mode: DitherMode) -> Self {
+        let mut dither = Dither {
+            mode,
+            scale: (1i64 << (bits.clamp(2, 32) - 1)) as f32,
+            channels: 2,
+            errors: Vec::new(),
+            state: 0x9e37_79b9,
+        };
+        dither.prepare(44100, 2);
+        dither
+    }
+
+    pub fn mode(&self) -> DitherMode {
+        self.mode
+    }
+
+    /// Uniform noise in [-0.5, 0.5) steps from a xorshift generator
+    fn noise(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f32 / 4_294_967_296.0 - 0.5
+    }
+}
+
+impl Processor for Dither {
+    fn prepare(&mut self, _sample_rate: u32, channels: usize) {
+        self.channels = channels.max(1);
+        self.errors = vec![[0.0; 3]; self.channels];
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        if self.mode == DitherMode::None {
+            return;
+        }
+        for frame in samples.chunks_mut(self.channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let mut value = *sample * self.scale;
+                if self.mode == DitherMode::Shaped {
+                    let errors = self.errors[channel];
+                    value -= SHAPING
+                        .iter()
+                        .zip(errors.iter())
+                        .map(|(k, e)| k * e)
+                        .sum::<f32>();
+                }
+                let noise = self.noise() + self.noise();
+                let rounded = (value + noise).round().clamp(-self.scale, self.scale - 1.0);
+                if self.mode == DitherMode::Shaped {
+                    // Bounded so that clipping cannot make the feedback run away
+                    let error = (rounded - value).clamp(-2.0, 2.0);
+                    let errors = &mut self.errors[channel];
+                    errors.rotate_right(1);
+                    errors[0] = error;
+                }
+                *sample = rounded / self.scale;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for errors in self.errors.iter_mut() {
+            *errors = [0.0; 3];
+        }
+    }
+}
@@ -0,0 +1,111 @@
+/// Blocks are 3 s long
+const BLOCK_SECONDS: u64 = 3;
+/// Share of the loudest blocks the RMS is taken over
+const LOUDEST_SHARE: f64 = 0.2;
+
+/// Measures the DR14-style dynamic range of a recording: per channel, the
+/// second highest block peak over the RMS of the loudest 20% of 3 s blocks,
+/// averaged over the channels
+pub struct DynamicRangeMeter {
+    channels: usize,
+    block_frames: u64,
+    count: u64,
+    /// Sum of squares and peak of the block being filled, per channel
+    sums: Vec<f64>,
+    peaks: Vec<f32>,
+    /// Mean square and peak of every finished block, per channel
+    blocks: Vec<Vec<(f64, f32)>>,
+    /// Sum of squares of everything processed, per channel
+    totals: Vec<f64>,
+    frames: u64,
+}
+
+impl DynamicRangeMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        DynamicRangeMeter {
+            channels,
+            block_frames: (sample_rate as u64 * BLOCK_SECONDS).max(1),
+            count: 0,
+            sums: vec![0.0; channels],
+            peaks: vec![0.0; channels],
+            blocks: vec![Vec::new(); channels],
+            totals: vec![0.0; channels],
+            frames: 0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let square = sample as f64 * sample as f64;
+                self.sums[channel] += square;
+                self.totals[channel] += square;
+                self.peaks[channel] = self.peaks[channel].max(sample.abs());
+            }
+            self.count += 1;
+            self.frames += 1;
+            if self.count == self.block_frames {
+                for channel in 0..self.channels {
+                    let block = (self.sums[channel] / self.count as f64, self.peaks[channel]);
+                    self.blocks[channel].push(block);
+                }
+                self.sums.fill(0.0);
+                self.peaks.fill(0.0);
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Dynamic range in dB, or `None` if nothing but silence was processed.
+    /// The DR score is this rounded to a whole number.
+    pub fn dynamic_range(&self) -> Option<f32> {
+        let ranges: Vec<f64> = (0..self.channels)
+            .filter_map(|channel| self.channel_range(channel))
+            .collect();
+        if ranges.is_empty() {
+            return None;
+        }
+        Some((ranges.iter().sum::<f64>() / ranges.len() as f64) as f32)
+    }
+
+    /// Highest sample of any channel, 1.0 being full scale
+    pub fn peak(&self) -> f32 {
+        (0..self.channels)
+            .flat_map(|channel| self.channel_blocks(channel))
+            .fold(0.0, |peak, (_, block)| peak.max(block))
+    }
+
+    /// RMS over everything processed and every channel, scaled the DR14 way
+    /// so a full-scale sine reads 1.0
+    pub fn rms(&self) -> f32 {
+        let frames = (self.frames * self.channels as u64).max(1) as f64;
+        (2.0 * self.totals.iter().sum::<f64>() / frames).sqrt() as f32
+    }
+
+    fn channel_range(&self, channel: usize) -> Option<f64> {
+        let blocks = self.channel_blocks(channel);
+        let mut squares: Vec<f64> = blocks.iter().map(|(square, _)| *square).collect();
+        let mut peaks: Vec<f32> = blocks.iter().map(|(_, peak)| *peak).collect();
+        squares.sort_by(|a, b| b.total_cmp(a));
+        peaks.sort_by(|a, b| b.total_cmp(a));
+
+        let loudest = ((squares.len() as f64 * LOUDEST_SHARE).round() as usize).max(1);
+        let square = squares.iter().take(loudest).sum::<f64>() / loudest as f64;
+        let rms = (2.0 * square).sqrt();
+        let peak = *peaks.get(1).or(peaks.first())? as f64;
+        if rms <= 0.0 || peak <= 0.0 {
+            return None;
+        }
+        Some(20.0 * (peak / rms).log10())
+    }
+
+    /// Finished blocks of a channel along with the one being filled
+    fn channel_blocks(&self, channel: usize) -> Vec<(f64, f32)> {
+        let mut blocks = self.blocks[channel].clone();
+        if self.count > 0 {
+            blocks.push((self.sums[channel] / self.count as f64, self.peaks[channel]));
+        }
+        blocks
+    }
+}
@@ -0,0 +1,215 @@
+use std::f32::consts::PI;
+
+use crate::Processor;
+
+/// Filter shape of an equalizer band
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BandKind {
+    LowShelf,
+    Peak,
+    HighShelf,
+}
+
+/// A single equalizer band
+#[derive(Clone, Copy, Debug)]
+pub struct Band {
+    pub kind: BandKind,
+    pub frequency: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+impl Band {
+    pub fn low_shelf(frequency: f32, gain_db: f32) -> Self {
+        Band {
+            kind: BandKind::LowShelf,
+            frequency,
+            gain_db,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+        }
+    }
+
+    pub fn peak(frequency: f32, gain_db: f32, q: f32) -> Self {
+        Band {
+            kind: BandKind::Peak,
+            frequency,
+            gain_db,
+            q,
+        }
+    }
+
+    pub fn high_shelf(frequency: f32, gain_db: f32) -> Self {
+        Band {
+            kind: BandKind::HighShelf,
+            frequency,
+            gain_db,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+        }
+    }
+}
+
+/// Normalized biquad coefficients (a0 = 1)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Coefficients {
+    pub b0: f32,
+    pub b1: f32,
+    pub b2: f32,
+    pub a1: f32,
+    pub a2: f32,
+}
+
+impl Coefficients {
+    /// Computes the coefficients for a band using the RBJ audio EQ cookbook formulas
+    pub fn for_band(band: &Band, sample_rate: u32) -> Self {
+        let a = 10f32.powf(band.gain_db / 40.0);
+        let w0 = 2.0 * PI * band.frequency / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q);
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            BandKind::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            BandKind::LowShelf => {
+                let k = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + k),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - k),
+                    (a + 1.0) + (a - 1.0) * cos + k,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - k,
+                )
+            }
+            BandKind::HighShelf => {
+                let k = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + k),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - k),
+                    (a + 1.0) - (a - 1.0) * cos + k,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - k,
+                )
+            }
+        };
+
+        Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+/// Per-channel filter history (transposed direct form II)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BiquadState {
+    z1: f32,
+    z2: f32,
+}
+
+impl BiquadState {
+    #[inline]
+    pub fn tick(&mut self, c: &Coefficients, x: f32) -> f32 {
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        y
+    }
+}
+
+/// A multi-band parametric equalizer built from cascaded biquads
+pub struct Eq {
+    bands: Vec<Band>,
+    coefficients: Vec<Coefficients>,
+    // One state per band per channel, laid out band-major
+    states: Vec<BiquadState>,
+    sample_rate: u32,
+    channels: usize,
+}
+
+impl Eq {
+    pub fn new() -> Self {
+        Eq {
+            bands: Vec::new(),
+            coefficients: Vec::new(),
+            states: Vec::new(),
+            sample_rate: 44100,
+            channels: 2,
+        }
+    }
+
+    /// Adds a band, builder style
+    pub fn band(mut self, band: Band) -> Self {
+        self.add_band(band);
+        self
+    }
+
+    pub fn add_band(&mut self, band: Band) {
+        self.bands.push(band);
+        self.rebuild();
+    }
+
+    pub fn bands(&self) -> &[Band] {
+        &self.bands
+    }
+
+    /// Replaces the band at `index`, keeping the filter history intact
+    pub fn set_band(&mut self, index: usize, band: Band) {
+        if let Some(slot) = self.bands.get_mut(index) {
+            *slot = band;
+            self.coefficients[index] = Coefficients::for_band(&band, self.sample_rate);
+        }
+    }
+
+    fn rebuild(&mut self) {
+        self.coefficients = self
+            .bands
+            .iter()
+            .map(|band| Coefficients::for_band(band, self.sample_rate))
+            .collect();
+        self.states = vec![BiquadState::default(); self.bands.len() * self.channels];
+    }
+}
+
+impl Default for Eq {
+    fn default() -> Self {
+        Eq::new()
+    }
+}
+
+impl Processor for Eq {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.sample_rate = sample_rate;
+        self.channels = channels.max(1);
+        self.rebuild();
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        if self.bands.is_empty() {
+            return;
+        }
+        let channels = self.channels;
+        for frame in samples.chunks_mut(channels) {
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let mut x = *sample;
+                for (band, c) in self.coefficients.iter().enumerate() {
+                    x = self.states[band * channels + ch].tick(c, x);
+                }
+                *sample = x;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.states.fill(BiquadState::default());
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::db_to_linear;
+
+/// Level a logarithmic fade starts from, below which it jumps to silence
+const LOG_FLOOR_DB: f32 = -60.0;
+
+/// Shape of a fade
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FadeCurve {
+    #[default]
+    Linear,
+    /// Even steps in dB, which sounds smoother over long fades
+    Logarithmic,
+}
+
+impl FadeCurve {
+    /// Gain at `progress` through a fade, from 0 (silent) to 1 (full level)
+    pub fn gain(self, progress: f32) -> f32 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => progress,
+            FadeCurve::Logarithmic if progress == 0.0 => 0.0,
+            FadeCurve::Logarithmic => db_to_linear(LOG_FLOOR_DB * (1.0 - progress)),
+        }
+    }
+}
+
+impl FromStr for FadeCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lin" | "linear" => Ok(FadeCurve::Linear),
+            "log" | "logarithmic" => Ok(FadeCurve::Logarithmic),
+            _ => Err(format!("invalid fade curve: {} (expected lin or log)", s)),
+        }
+    }
+}
+
+/// Fades a stream in at its start and out at its end. The end is not known
+/// until the stream finishes, so the last `fade_out` worth of samples is
+/// held back and only faded and released by `finish`.
+pub struct Fader {
+    curve: FadeCurve,
+    channels: usize,
+    fade_in: u64,
+    fade_out: u64,
+    position: u64,
+    tail: VecDeque<f32>,
+}
+
+impl Fader {
+    pub fn new(
+        sample_rate: u32,
+        channels: usize,
+        fade_in: Duration,
+        fade_out: Duration,
+        curve: FadeCurve,
+    ) -> Self {
+        let frames = |time: Duration| (time.as_secs_f64() * sample_rate as f64).round() as u64;
+        Fader {
+            curve,
+            channels: channels.max(1),
+            fade_in: frames(fade_in),
+            fade_out: frames(fade_out),
+            position: 0,
+            tail: VecDeque::new(),
+        }
+    }
+
+    /// Fades in `samples` as needed and replaces `out` with whatever is
+    /// ready to be released
+    pub fn process(&mut self, samples: &[f32], out: &mut Vec<f32>) {
+        out.clear();
+        for frame in samples.chunks(self.channels) {
+            let gain = if self.position < self.fade_in {
+                self.curve.gain(self.position as f32 / self.fade_in as f32)
+            } else {
+                1.0
+            };
+            self.position += 1;
+            self.tail.extend(frame.iter().map(|&sample| sample * gain));
+        }
+
+        let hold = self.fade_out as usize * self.channels;
+        if self.tail.len() > hold {
+            out.extend(self.tail.drain(..self.tail.len() - hold));
+        }
+    }
+
+    /// Ends the stream, replacing `out` with the held back samples faded out
+    pub fn finish(&mut self, out: &mut Vec<f32>) {
+        out.clear();
+        out.extend(self.tail.drain(..));
+        let frames = out.len() / self.channels;
+        for (i, frame) in out.chunks_mut(self.channels).enumerate() {
+            // Frames left after this one, so the very last one is silent
+            let left = (frames - i - 1) as f32;
+            let gain = self.curve.gain(left / self.fade_out.max(1) as f32);
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+}
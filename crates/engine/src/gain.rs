@@ -0,0 +1,47 @@
+use crate::{db_to_linear, Processor};
+
+/// Applies a constant gain to every sample
+pub struct Gain {
+    factor: f32,
+}
+
+impl Gain {
+    /// Creates a gain stage from a linear factor (1.0 = unity)
+    pub fn new(factor: f32) -> Self {
+        Gain { factor }
+    }
+
+    /// Creates a gain stage from a value in decibels
+    pub fn from_db(db: f32) -> Self {
+        Gain::new(db_to_linear(db))
+    }
+
+    pub fn factor(&self) -> f32 {
+        self.factor
+    }
+
+    pub fn set_factor(&mut self, factor: f32) {
+        self.factor = factor;
+    }
+
+    pub fn set_db(&mut self, db: f32) {
+        self.factor = db_to_linear(db);
+    }
+}
+
+impl Default for Gain {
+    fn default() -> Self {
+        Gain::new(1.0)
+    }
+}
+
+impl Processor for Gain {
+    fn process(&mut self, samples: &mut [f32]) {
+        if self.factor == 1.0 {
+            return;
+        }
+        for sample in samples.iter_mut() {
+            *sample *= self.factor;
+        }
+    }
+}
@@ -1 +1,51 @@
 // Engine crate
+
+pub mod channels;
+pub mod dcblock;
+pub mod dither;
+pub mod dynamics;
+pub mod eq;
+pub mod fade;
+pub mod gain;
+pub mod limiter;
+pub mod loudness;
+pub mod resample;
+pub mod silence;
+pub mod stats;
+pub mod truepeak;
+
+pub use channels::{parse_matrix, parse_routing, ChannelMapper, ChannelMatrix, ChannelRouting};
+pub use dcblock::DcBlocker;
+pub use dither::{Dither, DitherMode};
+pub use dynamics::DynamicRangeMeter;
+pub use eq::{Band, BandKind, Eq};
+pub use fade::{FadeCurve, Fader};
+pub use gain::Gain;
+pub use limiter::{Limiter, TruePeakLimiter};
+pub use loudness::LoudnessMeter;
+pub use resample::{Resampler, ResamplerQuality};
+pub use silence::{SilenceDetector, SilenceSkipper, SkipSilence};
+pub use stats::{ChannelStats, StatsMeter, HISTOGRAM_BINS, HISTOGRAM_STEP};
+pub use truepeak::{Oversampler, TruePeakMeter};
+
+/// A DSP node that processes interleaved f32 samples in place
+pub trait Processor: Send {
+    /// Called before processing starts and whenever the stream format changes
+    fn prepare(&mut self, _sample_rate: u32, _channels: usize) {}
+
+    /// Processes a buffer of interleaved samples in place
+    fn process(&mut self, samples: &mut [f32]);
+
+    /// Clears internal state (filter history, envelopes) without touching parameters
+    fn reset(&mut self) {}
+}
+
+/// Converts a decibel value to a linear amplitude factor
+pub fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Converts a linear amplitude factor to decibels
+pub fn linear_to_db(linear: f32) -> f32 {
+    20.0 * linear.max(f32::MIN_POSITIVE).log10()
+}
@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+
+use crate::truepeak::Oversampler;
+use crate::{db_to_linear, Processor};
+
+/// A simple feed-forward peak limiter with instant attack and exponential release.
+/// All channels share one gain envelope so the stereo image is preserved.
+pub struct Limiter {
+    threshold: f32,
+    release_ms: f32,
+    release_coeff: f32,
+    envelope: f32,
+    channels: usize,
+}
+
+impl Limiter {
+    /// Creates a limiter with the ceiling given in dBFS
+    pub fn new(threshold_db: f32) -> Self {
+        let mut limiter = Limiter {
+            threshold: db_to_linear(threshold_db),
+            release_ms: 50.0,
+            release_coeff: 0.0,
+            envelope: 1.0,
+            channels: 2,
+        };
+        limiter.prepare(44100, 2);
+        limiter
+    }
+
+    pub fn with_release(mut self, release_ms: f32) -> Self {
+        self.release_ms = release_ms;
+        self
+    }
+
+    pub fn set_threshold_db(&mut self, threshold_db: f32) {
+        self.threshold = db_to_linear(threshold_db);
+    }
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Limiter::new(-0.1)
+    }
+}
+
+impl Processor for Limiter {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.channels = channels.max(1);
+        let release_samples = self.release_ms * 0.001 * sample_rate as f32;
+        self.release_coeff = (-1.0 / release_samples.max(1.0)).exp();
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_mut(self.channels) {
+            let peak = frame.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+            let target = if peak > self.threshold {
+                self.threshold / peak
+            } else {
+                1.0
+            };
+
+            // Clamp down immediately, recover slowly
+            if target < self.envelope {
+                self.envelope = target;
+            } else {
+                self.envelope = target + (self.envelope - target) * self.release_coeff;
+            }
+
+            for sample in frame.iter_mut() {
+                *sample *= self.envelope;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 1.0;
+    }
+}
+
+/// Frames the true-peak limiter looks ahead: enough for the oversampler to
+/// report the peaks around a frame before that frame is let through
+const LOOKAHEAD: usize = 8;
+
+/// A limiter that keeps the true peak, the level of the signal between
+/// samples as well as at them, under a ceiling. Peaks are found on a
+/// four times oversampled copy and the audio is delayed by
+/// [`TruePeakLimiter::latency`] frames so the gain is down before they pass.
+pub struct TruePeakLimiter {
+    ceiling: f32,
+    release_ms: f32,
+    release_coeff: f32,
+    envelope: f32,
+    channels: usize,
+    oversamplers: Vec<Oversampler>,
+    /// Gain each frame of the lookahead window needs, oldest first
+    targets: VecDeque<f32>,
+    /// Audio waiting to be let through, oldest first
+    delay: VecDeque<f32>,
+}
+
+impl TruePeakLimiter {
+    /// Creates a limiter with the ceiling given in dBTP
+    pub fn new(ceiling_db: f32) -> Self {
+        let mut limiter = TruePeakLimiter {
+            ceiling: db_to_linear(ceiling_db),
+            release_ms: 50.0,
+            release_coeff: 0.0,
+            envelope: 1.0,
+            channels: 2,
+            oversamplers: Vec::new(),
+            targets: VecDeque::new(),
+            delay: VecDeque::new(),
+        };
+        limiter.prepare(44100, 2);
+        limiter
+    }
+
+    pub fn with_release(mut self, release_ms: f32) -> Self {
+        self.release_ms = release_ms;
+        self
+    }
+
+    pub fn set_ceiling_db(&mut self, ceiling_db: f32) {
+        self.ceiling = db_to_linear(ceiling_db);
+    }
+
+    /// Frames of delay the lookahead adds
+    pub fn latency(&self) -> usize {
+        LOOKAHEAD
+    }
+}
+
+impl Default for TruePeakLimiter {
+    fn default() -> Self {
+        TruePeakLimiter::new(-1.0)
+    }
+}
+
+impl Processor for TruePeakLimiter {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.channels = channels.max(1);
+        let release_samples = self.release_ms * 0.001 * sample_rate as f32;
+        self.release_coeff = (-1.0 / release_samples.max(1.0)).exp();
+        self.oversamplers = vec![Oversampler::new(); self.channels];
+        self.reset();
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            let mut peak = 0.0f32;
+            for (sample, oversampler) in frame.iter().zip(self.oversamplers.iter_mut()) {
+                for value in oversampler.process(*sample) {
+                    peak = peak.max(value.abs());
+                }
+                peak = peak.max(sample.abs());
+            }
+            self.targets.pop_front();
+            self.targets.push_back(if peak > self.ceiling {
+                self.ceiling / peak
+            } else {
+                1.0
+            });
+
+            // Clamp down to what any frame in the window needs, recover slowly
+            let target = self.targets.iter().fold(1.0f32, |acc, t| acc.min(*t));
+            if target < self.envelope {
+                self.envelope = target;
+            } else {
+                self.envelope = target + (self.envelope - target) * self.release_coeff;
+            }
+
+            self.delay.extend(frame.iter());
+            for sample in frame.iter_mut() {
+                *sample = self.delay.pop_front().unwrap_or(0.0) * self.envelope;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 1.0;
+        for oversampler in self.oversamplers.iter_mut() {
+            oversampler.reset();
+        }
+        self.targets = vec![1.0; LOOKAHEAD + 1].into();
+        self.delay = vec![0.0; LOOKAHEAD * self.channels].into();
+    }
+}
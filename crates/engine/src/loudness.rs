@@ -0,0 +1,354 @@
+use std::f64::consts::PI;
+
+/// Gating blocks are 400 ms long and start every 100 ms
+const STEP_MS: u64 = 100;
+const STEPS_PER_BLOCK: usize = 4;
+/// Short-term loudness is measured over 3 s
+const STEPS_PER_SHORT_TERM: usize = 30;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+/// Relative gate of the loudness range, per EBU Tech 3342
+const RANGE_GATE: f64 = -20.0;
+
+/// Measures loudness as specified by ITU-R BS.1770 and EBU R128:
+/// K-weighted, channel-weighted mean square over gated 400 ms blocks
+pub struct LoudnessMeter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<KWeighting>,
+    step_frames: u64,
+    /// Weighted sum of squares of the step being filled
+    sum: f64,
+    count: u64,
+    /// Weighted sum of squares of every finished 100 ms step
+    steps: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        LoudnessMeter {
+            channels,
+            weights: channel_weights(channels),
+            filters: vec![KWeighting::new(sample_rate); channels],
+            step_frames: (sample_rate as u64 * STEP_MS / 1000).max(1),
+            sum: 0.0,
+            count: 0,
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let filtered = self.filters[channel].tick(sample as f64);
+                self.sum += self.weights[channel] * filtered * filtered;
+            }
+            self.count += 1;
+            if self.count == self.step_frames {
+                self.steps.push(self.sum);
+                self.sum = 0.0;
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Integrated loudness in LUFS over everything processed so far, or
+    /// `None` if it was too short or silent to measure
+    pub fn integrated(&self) -> Option<f32> {
+        gated_loudness(self.blocks().collect())
+    }
+
+    /// Integrated loudness of several meters' audio taken as one
+    /// programme, such as the tracks of an album
+    pub fn integrated_all<'a, I>(meters: I) -> Option<f32>
+    where
+        I: IntoIterator<Item = &'a LoudnessMeter>,
+    {
+        gated_loudness(
+            meters
+                .into_iter()
+                .flat_map(|meter| meter.blocks())
+                .collect(),
+        )
+    }
+
+    /// Highest momentary (400 ms) loudness in LUFS
+    pub fn momentary_max(&self) -> Option<f32> {
+        max_loudness(self.blocks())
+    }
+
+    /// Highest short-term (3 s) loudness in LUFS
+    pub fn short_term_max(&self) -> Option<f32> {
+        max_loudness(self.windows(STEPS_PER_SHORT_TERM))
+    }
+
+    /// Loudness range in LU as specified by EBU Tech 3342: the spread
+    /// between the 10th and 95th percentile of the gated short-term loudness
+    pub fn loudness_range(&self) -> Option<f32> {
+        let blocks: Vec<f64> = self
+            .windows(STEPS_PER_SHORT_TERM)
+            .filter(|&power| loudness(power) > ABSOLUTE_GATE)
+            .collect();
+        if blocks.is_empty() {
+            return None;
+        }
+
+        let threshold = loudness(mean(&blocks)) + RANGE_GATE;
+        let mut gated: Vec<f64> = blocks
+            .into_iter()
+            .map(loudness)
+            .filter(|&loudness| loudness > threshold)
+            .collect();
+        gated.sort_by(f64::total_cmp);
+        let percentile = |p: f64| gated[((gated.len() - 1) as f64 * p).round() as usize];
+        Some((percentile(0.95) - percentile(0.10)) as f32)
+    }
+
+    /// Mean square of every 400 ms block
+    fn blocks(&self) -> impl Iterator<Item = f64> + '_ {
+        self.windows(STEPS_PER_BLOCK)
+    }
+
+    /// Mean square of every window of `steps` steps, one per step
+    fn windows(&self, steps: usize) -> impl Iterator<Item = f64> + '_ {
+        let frames = (steps as u64 * self.step_frames) as f64;
+        self.steps
+            .windows(steps)
+            .map(move |steps| steps.iter().sum::<f64>() / frames)
+    }
+}
+
+fn max_loudness(powers: impl Iterator<Item = f64>) -> Option<f32> {
+    powers
+        .max_by(f64::total_cmp)
+        .map(|power| loudness(power) as f32)
+}
+
+/// Applies the absolute and relative gates to block powers
+fn gated_loudness(blocks: Vec<f64>) -> Option<f32> {
+    let blocks: Vec<f64> = blocks
+        .into_iter()
+        .filter(|&power| loudness(power) > ABSOLUTE_GATE)
+        .collect();
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let threshold = loudness(mean(&blocks)) + RELATIVE_GATE;
+    let gated: Vec<f64> = blocks
+        .into_iter()
+        .filter(|&power| loudness(power) > threshold)
+        .collect();
+    Some(loudness(mean(&gated)) as f32)
+}
+
+fn loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.max(f64::MIN_POSITIVE).log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len().max(1) as f64
+}
+
+/// Surround channels count 1.41 times, the LFE not at all. Layouts follow
+/// the usual WAV and FLAC channel orders.
+fn channel_weights(channels: usize) -> Vec<f64> {
+    (0..channels)
+        .map(|channel| match (channels, channel) {
+            (6 | 8, 3) => 0.0,
+            (4, 2..) | (5, 3..) | (6 | 8, 4..) => 1.41,
+            _ => 1.0,
+        })
+        .collect()
+}
+
+/// The K-weighting pre-filter: a high shelf modelling the head followed by
+/// a high-pass, with coefficients derived for any sample rate
+#[derive(Clone)]
+struct KWeighting {
+    shelf: [f64; 5],
+    highpass: [f64; 5],
+    state: [[f64; 2]; 2],
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+
+        let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        ];
+
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let highpass = [
+            1.0,
+            -2.0,
+            1.0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        ];
+
+        KWeighting {
+            shelf,
+            highpass,
+            state: [[0.0; 2]; 2],
+        }
+    }
+
+    fn tick(&mut self, x: f64) -> f64 {
+        let y = biquad(&self.shelf, &mut self.state[0], x);
+        biquad(&self.highpass, &mut self.state[1], y)
+    }
+}
+
+/// One step of a biquad in transposed direct form II; `c` is b0, b1, b2, a1, a2
+fn biquad(c: &[f64; 5], z: &mut [f64; 2], x: f64) -> f64 {
+    let y = c[0] * x + z[0];
+    z[0] = c[1] * x - c[3] * y + z[1];
+    z[1] = c[2] * x - c[4] * y;
+    y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    /// Stereo 1 kHz sine at `dbfs` peak for `seconds`, the EBU Tech 3341
+    /// and 3342 test signal
+    fn sine(dbfs: f64, seconds: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        (0..(seconds * RATE as f64) as usize)
+            .flat_map(|i| {
+                let sample = amplitude * (2.0 * PI * 1000.0 * i as f64 / RATE as f64).sin();
+                [sample as f32; 2]
+            })
+            .collect()
+    }
+
+    fn meter(segments: &[(f64, f64)]) -> LoudnessMeter {
+        let mut meter = LoudnessMeter::new(RATE, 2);
+        for &(dbfs, seconds) in segments {
+            meter.process(&sine(dbfs, seconds));
+        }
+        meter
+    }
+
+    fn assert_near(value: Option<f32>, expected: f32, tolerance: f32) {
+        let value = value.expect("no loudness measured");
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            value,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn k_weighting_matches_bs1770_at_48khz() {
+        // The coefficients tabled in ITU-R BS.1770-4
+        let filter = KWeighting::new(48000);
+        let shelf = [
+            1.53512485958697,
+            -2.69169618940638,
+            1.19839281085285,
+            -1.69065929318241,
+            0.73248077421585,
+        ];
+        let highpass = [1.0, -2.0, 1.0, -1.99004745483398, 0.99007225036621];
+        for (actual, expected) in filter.shelf.iter().zip(shelf) {
+            assert!(
+                (actual - expected).abs() < 1e-8,
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+        for (actual, expected) in filter.highpass.iter().zip(highpass) {
+            assert!(
+                (actual - expected).abs() < 1e-8,
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn measures_tech_3341_steady_tones() {
+        // Cases 1 and 2: -23 and -33 dBFS stereo sines read the same in LUFS
+        let quiet = meter(&[(-23.0, 20.0)]);
+        assert_near(quiet.integrated(), -23.0, 0.1);
+        assert_near(quiet.momentary_max(), -23.0, 0.1);
+        assert_near(quiet.short_term_max(), -23.0, 0.1);
+        assert_near(meter(&[(-33.0, 20.0)]).integrated(), -33.0, 0.1);
+    }
+
+    #[test]
+    fn gates_tech_3341_quiet_parts() {
+        // Case 3: the relative gate drops the -36 dBFS parts
+        let relative = meter(&[(-36.0, 10.0), (-23.0, 60.0), (-36.0, 10.0)]);
+        assert_near(relative.integrated(), -23.0, 0.1);
+        // Case 4: the absolute gate drops the -72 dBFS parts too
+        let absolute = meter(&[
+            (-72.0, 10.0),
+            (-36.0, 10.0),
+            (-23.0, 60.0),
+            (-36.0, 10.0),
+            (-72.0, 10.0),
+        ]);
+        assert_near(absolute.integrated(), -23.0, 0.1);
+    }
+
+    #[test]
+    fn measures_tech_3342_loudness_range() {
+        // Cases 1 and 2: 20 s each of two levels 10 and 5 LU apart
+        assert_near(
+            meter(&[(-20.0, 20.0), (-30.0, 20.0)]).loudness_range(),
+            10.0,
+            1.0,
+        );
+        assert_near(
+            meter(&[(-20.0, 20.0), (-15.0, 20.0)]).loudness_range(),
+            5.0,
+            1.0,
+        );
+    }
+
+    #[test]
+    fn measures_albums_as_one_programme() {
+        let tracks = [meter(&[(-20.0, 10.0)]), meter(&[(-20.0, 10.0)])];
+        assert_near(LoudnessMeter::integrated_all(&tracks), -20.0, 0.1);
+    }
+
+    #[test]
+    fn silence_and_short_audio_are_unmeasured() {
+        let mut silent = LoudnessMeter::new(RATE, 2);
+        silent.process(&vec![0.0; RATE as usize * 2 * 5]);
+        assert_eq!(silent.integrated(), None);
+        assert_eq!(silent.loudness_range(), None);
+        assert_eq!(meter(&[(-23.0, 0.3)]).integrated(), None);
+    }
+
+    #[test]
+    fn weights_surround_channels() {
+        assert_eq!(channel_weights(2), [1.0, 1.0]);
+        assert_eq!(channel_weights(6), [1.0, 1.0, 1.0, 0.0, 1.41, 1.41]);
+        assert_eq!(channel_weights(5), [1.0, 1.0, 1.0, 1.41, 1.41]);
+    }
+}
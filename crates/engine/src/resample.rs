@@ -0,0 +1,170 @@
+use std::str::FromStr;
+
+use rubato::{
+    FastFixedIn, PolynomialDegree, SincFixedIn, SincInterpolationParameters, SincInterpolationType,
+    VecResampler, WindowFunction,
+};
+
+/// Input frames handed to the resampler per call
+const CHUNK_FRAMES: usize = 1024;
+
+/// Trade-off between conversion quality and speed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResamplerQuality {
+    /// Long windowed sinc filter, transparent for mastering
+    #[default]
+    Sinc,
+    /// Plain linear interpolation; fastest, but aliases audibly
+    Linear,
+    /// Short sinc filter with a wider transition band
+    Fast,
+}
+
+impl FromStr for ResamplerQuality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sinc" => Ok(ResamplerQuality::Sinc),
+            "linear" => Ok(ResamplerQuality::Linear),
+            "fast" => Ok(ResamplerQuality::Fast),
+            _ => Err(format!(
+                "invalid resampler: {} (expected sinc, linear or fast)",
+                s
+            )),
+        }
+    }
+}
+
+/// Streaming sample-rate converter for interleaved audio, built on rubato.
+/// Output lines up with the input and `flush` trims it to exactly the input
+/// duration, so tracks stay sample-accurate end to end.
+pub struct Resampler {
+    inner: Box<dyn VecResampler<f32>>,
+    channels: usize,
+    ratio: f64,
+    /// Planar input waiting for a full chunk
+    pending: Vec<Vec<f32>>,
+    frames_in: u64,
+    frames_out: u64,
+}
+
+impl Resampler {
+    /// Creates a converter with the best quality
+    pub fn new(from: u32, to: u32, channels: usize) -> Result<Self, String> {
+        Resampler::with_quality(from, to, channels, ResamplerQuality::Sinc)
+    }
+
+    pub fn with_quality(
+        from: u32,
+        to: u32,
+        channels: usize,
+        quality: ResamplerQuality,
+    ) -> Result<Self, String> {
+        let channels = channels.max(1);
+        let ratio = to as f64 / from as f64;
+        let create_error =
+            |e: rubato::ResamplerConstructionError| format!("failed to create resampler: {}", e);
+        let sinc = |sinc_len, oversampling_factor, interpolation| {
+            let parameters = SincInterpolationParameters {
+                sinc_len,
+                f_cutoff: 0.95,
+                oversampling_factor,
+                interpolation,
+                window: WindowFunction::BlackmanHarris2,
+            };
+            SincFixedIn::<f32>::new(ratio, 1.0, parameters, CHUNK_FRAMES, channels)
+        };
+        let inner: Box<dyn VecResampler<f32>> = match quality {
+            ResamplerQuality::Sinc => {
+                Box::new(sinc(256, 128, SincInterpolationType::Cubic).map_err(create_error)?)
+            }
+            ResamplerQuality::Fast => {
+                Box::new(sinc(64, 32, SincInterpolationType::Linear).map_err(create_error)?)
+            }
+            ResamplerQuality::Linear => Box::new(
+                FastFixedIn::<f32>::new(
+                    ratio,
+                    1.0,
+                    PolynomialDegree::Linear,
+                    CHUNK_FRAMES,
+                    channels,
+                )
+                .map_err(create_error)?,
+            ),
+        };
+
+        Ok(Resampler {
+            inner,
+            channels,
+            ratio,
+            pending: vec![Vec::new(); channels],
+            frames_in: 0,
+            frames_out: 0,
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Feeds interleaved input and returns whatever output is ready
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        for frame in input.chunks_exact(self.channels) {
+            for (channel, sample) in self.pending.iter_mut().zip(frame) {
+                channel.push(*sample);
+            }
+        }
+        self.frames_in += (input.len() / self.channels) as u64;
+
+        let mut out = Vec::new();
+        while self.pending[0].len() >= self.inner.input_frames_next() {
+            let needed = self.inner.input_frames_next();
+            let chunk: Vec<Vec<f32>> = self
+                .pending
+                .iter_mut()
+                .map(|channel| channel.drain(..needed).collect())
+                .collect();
+            match self.inner.process(&chunk, None) {
+                Ok(planar) => self.emit(&planar, &mut out),
+                Err(_) => break,
+            }
+        }
+        out
+    }
+
+    /// Pushes the remaining input through at the end of a stream and returns
+    /// the tail, so the total output length matches the input duration
+    pub fn flush(&mut self) -> Vec<f32> {
+        let expected = (self.frames_in as f64 * self.ratio).round() as u64;
+        let mut out = Vec::new();
+
+        if !self.pending[0].is_empty() {
+            let pending = std::mem::replace(&mut self.pending, vec![Vec::new(); self.channels]);
+            if let Ok(planar) = self.inner.process_partial(Some(&pending), None) {
+                self.emit(&planar, &mut out);
+            }
+        }
+        while self.frames_out < expected {
+            match self.inner.process_partial(None, None) {
+                Ok(planar) if !planar[0].is_empty() => self.emit(&planar, &mut out),
+                _ => break,
+            }
+        }
+
+        let excess = self.frames_out.saturating_sub(expected) as usize;
+        out.truncate(out.len().saturating_sub(excess * self.channels));
+        self.frames_out -= excess as u64;
+        out
+    }
+
+    fn emit(&mut self, planar: &[Vec<f32>], out: &mut Vec<f32>) {
+        let frames = planar[0].len();
+        for frame in 0..frames {
+            for channel in planar.iter() {
+                out.push(channel[frame]);
+            }
+        }
+        self.frames_out += frames as u64;
+    }
+}
@@ -0,0 +1,175 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::db_to_linear;
+
+/// Length of the windows the level is measured over
+const WINDOW_MS: u64 = 10;
+
+/// Finds silent stretches in a stream: runs of 10 ms windows whose RMS
+/// level, over all channels, stays under a threshold for a minimum time
+pub struct SilenceDetector {
+    /// Mean square level below which a window counts as silent
+    threshold: f64,
+    min_frames: u64,
+    window: u64,
+    channels: usize,
+    sum: f64,
+    count: u64,
+    position: u64,
+    silent_since: Option<u64>,
+    gaps: Vec<Range<u64>>,
+}
+
+impl SilenceDetector {
+    pub fn new(sample_rate: u32, channels: usize, threshold_db: f32, min_gap: Duration) -> Self {
+        let threshold = db_to_linear(threshold_db) as f64;
+        SilenceDetector {
+            threshold: threshold * threshold,
+            min_frames: (min_gap.as_secs_f64() * sample_rate as f64).round() as u64,
+            window: (sample_rate as u64 * WINDOW_MS / 1000).max(1),
+            channels: channels.max(1),
+            sum: 0.0,
+            count: 0,
+            position: 0,
+            silent_since: None,
+            gaps: Vec::new(),
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks(self.channels) {
+            self.sum += frame.iter().map(|&s| (s as f64) * (s as f64)).sum::<f64>();
+            self.count += 1;
+            self.position += 1;
+            if self.count == self.window {
+                self.end_window();
+            }
+        }
+    }
+
+    /// Frames seen so far
+    pub fn frames(&self) -> u64 {
+        self.position
+    }
+
+    /// Ends the stream, counting silence that runs to the end, and returns
+    /// every silent stretch as a frame range
+    pub fn finish(mut self) -> Vec<Range<u64>> {
+        if self.count > 0 {
+            self.end_window();
+        }
+        if let Some(start) = self.silent_since.take() {
+            self.push_gap(start, self.position);
+        }
+        self.gaps
+    }
+
+    fn end_window(&mut self) {
+        let start = self.position - self.count;
+        let mean_square = self.sum / (self.count as usize * self.channels) as f64;
+        if mean_square < self.threshold {
+            self.silent_since.get_or_insert(start);
+        } else if let Some(since) = self.silent_since.take() {
+            self.push_gap(since, start);
+        }
+        self.sum = 0.0;
+        self.count = 0;
+    }
+
+    fn push_gap(&mut self, start: u64, end: u64) {
+        if end - start >= self.min_frames {
+            self.gaps.push(start..end);
+        }
+    }
+}
+
+/// Settings for shortening silent gaps during playback
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkipSilence {
+    /// RMS level, over all channels, below which audio counts as silent
+    pub threshold_db: f32,
+    /// Longest stretch of a gap that is kept; zero drops gaps entirely
+    pub keep: Duration,
+}
+
+impl Default for SkipSilence {
+    fn default() -> Self {
+        SkipSilence {
+            threshold_db: -50.0,
+            keep: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Shortens silent gaps in a stream as it passes through, keeping the
+/// first part of each gap and dropping the rest. Works on 10 ms windows,
+/// so it holds back at most one window.
+pub struct SilenceSkipper {
+    threshold: f64,
+    /// Frames of each gap let through before dropping starts
+    keep_frames: u64,
+    window: usize,
+    channels: usize,
+    pending: Vec<f32>,
+    /// Frames of the current gap so far
+    silent_frames: u64,
+}
+
+impl SilenceSkipper {
+    pub fn new(sample_rate: u32, channels: usize, settings: SkipSilence) -> Self {
+        let threshold = db_to_linear(settings.threshold_db) as f64;
+        let channels = channels.max(1);
+        let window = (sample_rate as usize * WINDOW_MS as usize / 1000).max(1) * channels;
+        SilenceSkipper {
+            threshold: threshold * threshold,
+            keep_frames: (settings.keep.as_secs_f64() * sample_rate as f64).round() as u64,
+            window,
+            channels,
+            pending: Vec::with_capacity(window),
+            silent_frames: 0,
+        }
+    }
+
+    /// Appends the part of `samples` that is kept to `out`
+    pub fn process(&mut self, samples: &[f32], out: &mut Vec<f32>) {
+        let mut samples = samples;
+        while !samples.is_empty() {
+            let count = (self.window - self.pending.len()).min(samples.len());
+            self.pending.extend_from_slice(&samples[..count]);
+            samples = &samples[count..];
+            if self.pending.len() == self.window {
+                self.end_window(out);
+            }
+        }
+    }
+
+    /// Lets the held back samples through, at the end of the stream
+    pub fn flush(&mut self, out: &mut Vec<f32>) {
+        out.append(&mut self.pending);
+        self.silent_frames = 0;
+    }
+
+    fn end_window(&mut self, out: &mut Vec<f32>) {
+        let mean_square = self
+            .pending
+            .iter()
+            .map(|&s| (s as f64) * (s as f64))
+            .sum::<f64>()
+            / self.pending.len() as f64;
+        let frames = (self.pending.len() / self.channels) as u64;
+        if mean_square >= self.threshold {
+            self.silent_frames = 0;
+            out.append(&mut self.pending);
+            return;
+        }
+
+        let kept = self
+            .keep_frames
+            .saturating_sub(self.silent_frames)
+            .min(frames) as usize;
+        out.extend_from_slice(&self.pending[..kept * self.channels]);
+        self.silent_frames += frames;
+        self.pending.clear();
+    }
+}
@@ -0,0 +1,91 @@
+use crate::linear_to_db;
+
+/// Width of a level histogram bin, in dB
+pub const HISTOGRAM_STEP: f32 = 6.0;
+/// Bins of the level histogram; the last one takes everything quieter,
+/// silence included
+pub const HISTOGRAM_BINS: usize = 16;
+
+/// Level statistics of one channel
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelStats {
+    /// Highest absolute sample, 1.0 being full scale
+    pub peak: f32,
+    /// Root mean square of the samples
+    pub rms: f32,
+    /// Sign changes between neighbouring samples
+    pub zero_crossings: u64,
+    /// Samples per [`HISTOGRAM_STEP`] dB band below full scale, loudest first
+    pub histogram: Vec<u64>,
+    pub samples: u64,
+}
+
+impl ChannelStats {
+    /// Peak to RMS ratio in dB, or `None` for silence
+    pub fn crest_factor(&self) -> Option<f32> {
+        (self.rms > 0.0).then(|| linear_to_db(self.peak) - linear_to_db(self.rms))
+    }
+}
+
+/// Gathers per-channel peak, RMS, zero crossings and a level histogram
+pub struct StatsMeter {
+    channels: usize,
+    peaks: Vec<f32>,
+    sums: Vec<f64>,
+    crossings: Vec<u64>,
+    /// Last sample of each channel, for spotting sign changes
+    last: Vec<f32>,
+    histograms: Vec<Vec<u64>>,
+    frames: u64,
+}
+
+impl StatsMeter {
+    pub fn new(channels: usize) -> Self {
+        let channels = channels.max(1);
+        StatsMeter {
+            channels,
+            peaks: vec![0.0; channels],
+            sums: vec![0.0; channels],
+            crossings: vec![0; channels],
+            last: vec![0.0; channels],
+            histograms: vec![vec![0; HISTOGRAM_BINS]; channels],
+            frames: 0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let level = sample.abs();
+                self.peaks[channel] = self.peaks[channel].max(level);
+                self.sums[channel] += sample as f64 * sample as f64;
+                if self.frames > 0 && (sample < 0.0) != (self.last[channel] < 0.0) {
+                    self.crossings[channel] += 1;
+                }
+                self.last[channel] = sample;
+
+                let bin = (-linear_to_db(level) / HISTOGRAM_STEP).max(0.0) as usize;
+                self.histograms[channel][bin.min(HISTOGRAM_BINS - 1)] += 1;
+            }
+            self.frames += 1;
+        }
+    }
+
+    /// Frames processed so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The statistics of every channel so far
+    pub fn stats(&self) -> Vec<ChannelStats> {
+        (0..self.channels)
+            .map(|channel| ChannelStats {
+                peak: self.peaks[channel],
+                rms: (self.sums[channel] / self.frames.max(1) as f64).sqrt() as f32,
+                zero_crossings: self.crossings[channel],
+                histogram: self.histograms[channel].clone(),
+                samples: self.frames,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,157 @@
+use std::f64::consts::PI;
+
+/// Oversampling factor, as ITU-R BS.1770 asks for at 48 kHz
+pub const OVERSAMPLING: usize = 4;
+
+/// Taps of the interpolation filter per output phase
+const TAPS_PER_PHASE: usize = 12;
+
+/// Interpolates a single channel to [`OVERSAMPLING`] times its rate with a
+/// windowed-sinc polyphase filter, to see the peaks between samples
+#[derive(Clone)]
+pub struct Oversampler {
+    phases: Vec<[f32; TAPS_PER_PHASE]>,
+    /// Last input samples, newest first
+    history: [f32; TAPS_PER_PHASE],
+}
+
+impl Oversampler {
+    pub fn new() -> Self {
+        let len = OVERSAMPLING * TAPS_PER_PHASE;
+        let center = (len - 1) as f64 / 2.0;
+        let taps: Vec<f64> = (0..len)
+            .map(|n| {
+                let x = (n as f64 - center) / OVERSAMPLING as f64;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                // Blackman window
+                let w = 2.0 * PI * n as f64 / (len - 1) as f64;
+                sinc * (0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos())
+            })
+            .collect();
+
+        let phases = (0..OVERSAMPLING)
+            .map(|phase| {
+                let mut coeffs = [0.0; TAPS_PER_PHASE];
+                for (k, coeff) in coeffs.iter_mut().enumerate() {
+                    *coeff = taps[k * OVERSAMPLING + phase] as f32;
+                }
+                coeffs
+            })
+            .collect();
+        Oversampler {
+            phases,
+            history: [0.0; TAPS_PER_PHASE],
+        }
+    }
+
+    /// Feeds one sample and returns the interpolated values that follow it
+    pub fn process(&mut self, sample: f32) -> [f32; OVERSAMPLING] {
+        self.history.rotate_right(1);
+        self.history[0] = sample;
+        let mut out = [0.0; OVERSAMPLING];
+        for (value, coeffs) in out.iter_mut().zip(self.phases.iter()) {
+            *value = coeffs
+                .iter()
+                .zip(self.history.iter())
+                .map(|(c, x)| c * x)
+                .sum();
+        }
+        out
+    }
+
+    /// Samples of delay the filter adds, at the input rate
+    pub fn latency() -> usize {
+        TAPS_PER_PHASE / 2
+    }
+
+    pub fn reset(&mut self) {
+        self.history = [0.0; TAPS_PER_PHASE];
+    }
+}
+
+impl Default for Oversampler {
+    fn default() -> Self {
+        Oversampler::new()
+    }
+}
+
+/// Finds the true peak of interleaved audio: the highest level of the
+/// signal between samples as well as at them
+pub struct TruePeakMeter {
+    channels: usize,
+    oversamplers: Vec<Oversampler>,
+    peak: f32,
+}
+
+impl TruePeakMeter {
+    pub fn new(channels: usize) -> Self {
+        let channels = channels.max(1);
+        TruePeakMeter {
+            channels,
+            oversamplers: vec![Oversampler::new(); channels],
+            peak: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (sample, oversampler) in frame.iter().zip(self.oversamplers.iter_mut()) {
+                for value in oversampler.process(*sample) {
+                    self.peak = self.peak.max(value.abs());
+                }
+                self.peak = self.peak.max(sample.abs());
+            }
+        }
+    }
+
+    /// Highest absolute level seen, 1.0 being full scale
+    pub fn peak(&self) -> f32 {
+        self.peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// A sine at a quarter of the sample rate, `phase` off its peaks
+    fn quarter_rate_sine(amplitude: f32, phase: f32) -> Vec<f32> {
+        (0..4800)
+            .map(|i| amplitude * (PI / 2.0 * i as f32 + phase).sin())
+            .collect()
+    }
+
+    fn dbtp(peak: f32) -> f32 {
+        20.0 * peak.log10()
+    }
+
+    #[test]
+    fn finds_peaks_between_samples() {
+        // EBU Tech 3341 allows +0.2 / -0.4 dB around the true peak
+        let mut meter = TruePeakMeter::new(1);
+        meter.process(&quarter_rate_sine(0.5, PI / 4.0));
+        let peak = dbtp(meter.peak());
+        assert!((-6.42..=-5.82).contains(&peak), "{} dBTP", peak);
+
+        let mut meter = TruePeakMeter::new(1);
+        meter.process(&quarter_rate_sine(0.5, 0.0));
+        let peak = dbtp(meter.peak());
+        assert!((-6.42..=-5.82).contains(&peak), "{} dBTP", peak);
+    }
+
+    #[test]
+    fn keeps_channels_apart() {
+        let mut meter = TruePeakMeter::new(2);
+        let frames: Vec<f32> = quarter_rate_sine(0.25, 0.0)
+            .into_iter()
+            .flat_map(|sample| [sample, 0.0])
+            .collect();
+        meter.process(&frames);
+        assert!((0.24..=0.26).contains(&meter.peak()), "{}", meter.peak());
+    }
+}
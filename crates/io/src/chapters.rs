@@ -0,0 +1,220 @@
+// Chapter markers embedded in audio files
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use symphonia::core::formats::Cue;
+use symphonia::core::meta::Tag;
+
+use crate::cue::{CueSheet, CD_FRAMES_PER_SECOND};
+use crate::id3::{read_frames, read_text, Id3Tag};
+
+/// Track numbers FLAC cue sheets use for the lead-out, which only marks
+/// where the audio ends
+const LEAD_OUT_TRACKS: [u32; 2] = [170, 255];
+/// Nero `chpl` chapter starts count 100 ns units
+const CHPL_UNITS_PER_SECOND: u64 = 10_000_000;
+
+/// A marked position within a file, such as an audiobook chapter or a
+/// track of a single-file album rip
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: Option<String>,
+}
+
+/// Chapters from `CHAPTERxxx` comments (Vorbis, FLAC and Opus), an embedded
+/// `CUESHEET`, ID3v2 `CHAP` frames, an MP4 `chpl` box or the container's
+/// cue points, whichever comes first, in order of their start
+pub(crate) fn read_chapters(
+    path: &Path,
+    tags: &[Tag],
+    cues: &[Cue],
+    sample_rate: u32,
+) -> Vec<Chapter> {
+    let mut chapters = comment_chapters(tags);
+    if chapters.is_empty() {
+        chapters = embedded_cue_sheet(tags);
+    }
+    if chapters.is_empty() {
+        chapters = id3_chapters(path);
+    }
+    if chapters.is_empty() {
+        chapters = mp4_chapters(path).unwrap_or_default();
+    }
+    if chapters.is_empty() {
+        chapters = cues
+            .iter()
+            .filter(|cue| !LEAD_OUT_TRACKS.contains(&cue.index))
+            .map(|cue| Chapter {
+                start: Duration::from_secs_f64(cue.start_ts as f64 / sample_rate.max(1) as f64),
+                title: None,
+            })
+            .collect();
+    }
+    chapters.sort_by_key(|chapter| chapter.start);
+    chapters
+}
+
+/// Chapters in the Vorbis comment convention: `CHAPTER001=00:01:30.000`
+/// with the title in `CHAPTER001NAME`
+fn comment_chapters(tags: &[Tag]) -> Vec<Chapter> {
+    let value = |key: &str| {
+        tags.iter()
+            .find(|tag| tag.key.eq_ignore_ascii_case(key))
+            .map(|tag| tag.value.to_string())
+    };
+    tags.iter()
+        .filter_map(|tag| {
+            let key = tag.key.to_ascii_uppercase();
+            let number = key.strip_prefix("CHAPTER")?;
+            if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            Some(Chapter {
+                start: parse_timestamp(&tag.value.to_string())?,
+                title: value(&format!("CHAPTER{}NAME", number)),
+            })
+        })
+        .collect()
+}
+
+/// Parses `HH:MM:SS.mmm`, with as many hour digits as needed
+pub(crate) fn parse_timestamp(value: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    for part in value.trim().split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    (seconds.is_finite() && seconds >= 0.0).then(|| Duration::from_secs_f64(seconds))
+}
+
+/// Tracks of a cue sheet stored in a `CUESHEET` comment, as rippers embed
+/// in single-file FLAC albums
+fn embedded_cue_sheet(tags: &[Tag]) -> Vec<Chapter> {
+    let Some(tag) = tags
+        .iter()
+        .find(|tag| tag.key.eq_ignore_ascii_case("CUESHEET"))
+    else {
+        return Vec::new();
+    };
+    let Ok(sheet) = CueSheet::parse(&tag.value.to_string(), Path::new(".")) else {
+        return Vec::new();
+    };
+    sheet
+        .tracks
+        .iter()
+        .map(|track| Chapter {
+            start: Duration::from_secs_f64(track.start as f64 / CD_FRAMES_PER_SECOND as f64),
+            title: track.title.clone(),
+        })
+        .collect()
+}
+
+/// Chapters from the `CHAP` frames of an ID3v2 tag, titled by their `TIT2`
+/// subframes
+fn id3_chapters(path: &Path) -> Vec<Chapter> {
+    let Some(tag) = Id3Tag::read(path) else {
+        return Vec::new();
+    };
+    tag.frames
+        .iter()
+        .filter(|(id, _)| id == b"CHAP")
+        .filter_map(|(_, data)| {
+            // Element ID, then start and end times in ms and byte offsets
+            let (_, rest) = read_text(data, 0)?;
+            let start = rest.get(..4)?;
+            let start = u32::from_be_bytes([start[0], start[1], start[2], start[3]]);
+            let title = read_frames(rest.get(16..)?, tag.version)
+                .into_iter()
+                .find(|(id, _)| id == b"TIT2")
+                .and_then(|(_, text)| {
+                    let (&encoding, text) = text.split_first()?;
+                    Some(read_text(text, encoding)?.0)
+                });
+            Some(Chapter {
+                start: Duration::from_millis(start as u64),
+                title,
+            })
+        })
+        .collect()
+}
+
+/// Chapters from the Nero `moov/udta/chpl` box of an MP4 file, as M4B
+/// audiobooks carry them
+fn mp4_chapters(path: &Path) -> Option<Vec<Chapter>> {
+    let mut file = File::open(path).ok()?;
+    let mut header = [0u8; 8];
+    file.read_exact(&mut header).ok()?;
+    if &header[4..] != b"ftyp" {
+        return None;
+    }
+
+    // Top-level boxes can be huge, so only moov is read in
+    let mut start = 0u64;
+    let moov = loop {
+        file.seek(SeekFrom::Start(start)).ok()?;
+        file.read_exact(&mut header).ok()?;
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let (header_len, size) = match size {
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large).ok()?;
+                (16, u64::from_be_bytes(large))
+            }
+            0 => return None,
+            size => (8, size),
+        };
+        if size < header_len {
+            return None;
+        }
+        if &header[4..] == b"moov" {
+            let mut moov = vec![0u8; usize::try_from(size - header_len).ok()?];
+            file.read_exact(&mut moov).ok()?;
+            break moov;
+        }
+        start += size;
+    };
+
+    let udta = mp4_child(&moov, b"udta")?;
+    let chpl = mp4_child(udta, b"chpl")?;
+    let (&version, rest) = chpl.split_first()?;
+    // Flags, and in version 1 four more reserved bytes
+    let mut rest = rest.get(if version == 1 { 7 } else { 3 }..)?;
+    let (&count, entries) = rest.split_first()?;
+    rest = entries;
+    let mut chapters = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let start = u64::from_be_bytes(rest.get(..8)?.try_into().ok()?);
+        let len = *rest.get(8)? as usize;
+        let title = String::from_utf8_lossy(rest.get(9..9 + len)?).into_owned();
+        chapters.push(Chapter {
+            start: Duration::from_secs_f64(start as f64 / CHPL_UNITS_PER_SECOND as f64),
+            title: (!title.is_empty()).then_some(title),
+        });
+        rest = &rest[9 + len..];
+    }
+    Some(chapters)
+}
+
+/// The body of the first `kind` box among the boxes laid end to end in `data`
+fn mp4_child<'a>(mut data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let (header_len, size) = match size {
+            1 => (
+                16,
+                usize::try_from(u64::from_be_bytes(data.get(8..16)?.try_into().ok()?)).ok()?,
+            ),
+            0 => (8, data.len()),
+            size => (8, size),
+        };
+        let body = data.get(header_len..size)?;
+        if &data[4..8] == kind {
+            return Some(body);
+        }
+        data = &data[size..];
+    }
+    None
+}
@@ -0,0 +1,258 @@
+// Cue sheet parsing
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Cue times are `mm:ss:ff`, counting CD frames of 1/75 s
+pub const CD_FRAMES_PER_SECOND: u64 = 75;
+
+/// The album described by a cue sheet
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// From `REM DATE`
+    pub date: Option<String>,
+    /// From `REM GENRE`
+    pub genre: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    /// The audio file the track is in, resolved against the sheet's directory
+    pub file: PathBuf,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Start of the track (`INDEX 01`) in CD frames. Any pregap before it
+    /// stays with the previous track.
+    pub start: u64,
+}
+
+impl CueTrack {
+    /// The start as a frame offset into audio at `sample_rate`
+    pub fn start_frame(&self, sample_rate: u32) -> u64 {
+        self.start * sample_rate as u64 / CD_FRAMES_PER_SECOND
+    }
+}
+
+impl CueSheet {
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("failed to read cue sheet: {}", e))?;
+        let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+        // Older rippers write the system code page; Latin-1 is the usual one
+        let content = match std::str::from_utf8(bytes) {
+            Ok(text) => text.to_string(),
+            Err(_) => bytes.iter().map(|&b| b as char).collect(),
+        };
+        let base = path.parent().unwrap_or(Path::new("."));
+        CueSheet::parse(&content, base)
+    }
+
+    /// Parses cue sheet text, resolving file names against `base`
+    pub fn parse(content: &str, base: &Path) -> Result<Self, String> {
+        let mut sheet = CueSheet::default();
+        let mut file: Option<PathBuf> = None;
+        let mut track: Option<CueTrack> = None;
+        let mut has_start = false;
+
+        for (number, line) in content.lines().enumerate() {
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+            let line = line.trim();
+            let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+
+            match command.to_ascii_uppercase().as_str() {
+                "FILE" => {
+                    let name = unquote(
+                        rest.rsplit_once(char::is_whitespace)
+                            .map_or(rest, |(name, _)| name),
+                    );
+                    file = Some(base.join(name.replace('\\', std::path::MAIN_SEPARATOR_STR)));
+                }
+                "TRACK" => {
+                    if let Some(track) = track.take() {
+                        if !has_start {
+                            return Err(error(&format!("track {} has no INDEX 01", track.number)));
+                        }
+                        sheet.tracks.push(track);
+                    }
+                    let file = file.clone().ok_or_else(|| error("TRACK before FILE"))?;
+                    let number = rest
+                        .split_whitespace()
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or_else(|| error("invalid track number"))?;
+                    track = Some(CueTrack {
+                        number,
+                        file,
+                        ..CueTrack::default()
+                    });
+                    has_start = false;
+                }
+                "INDEX" => {
+                    let mut parts = rest.split_whitespace();
+                    let index = parts.next().and_then(|n| n.parse::<u32>().ok());
+                    let time = parts.next().and_then(parse_time);
+                    let (Some(index), Some(time)) = (index, time) else {
+                        return Err(error("invalid INDEX"));
+                    };
+                    if let (1, Some(track)) = (index, track.as_mut()) {
+                        track.start = time;
+                        has_start = true;
+                    }
+                }
+                "TITLE" => match track.as_mut() {
+                    Some(track) => track.title = Some(unquote(rest)),
+                    None => sheet.title = Some(unquote(rest)),
+                },
+                "PERFORMER" => match track.as_mut() {
+                    Some(track) => track.performer = Some(unquote(rest)),
+                    None => sheet.performer = Some(unquote(rest)),
+                },
+                "REM" => {
+                    let (key, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    match key.to_ascii_uppercase().as_str() {
+                        "DATE" => sheet.date = Some(unquote(value.trim())),
+                        "GENRE" => sheet.genre = Some(unquote(value.trim())),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(track) = track {
+            if !has_start {
+                return Err(format!("track {} has no INDEX 01", track.number));
+            }
+            sheet.tracks.push(track);
+        }
+        if sheet.tracks.is_empty() {
+            return Err("no tracks in cue sheet".to_string());
+        }
+        Ok(sheet)
+    }
+
+    /// Every audio file the sheet refers to, in order of appearance
+    pub fn files(&self) -> Vec<&Path> {
+        let mut files: Vec<&Path> = Vec::new();
+        for track in &self.tracks {
+            if !files.contains(&track.file.as_path()) {
+                files.push(&track.file);
+            }
+        }
+        files
+    }
+}
+
+/// Parses `mm:ss:ff` into CD frames
+fn parse_time(value: &str) -> Option<u64> {
+    let mut parts = value.split(':').map(|part| part.parse::<u64>().ok());
+    let (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if seconds >= 60 || frames >= CD_FRAMES_PER_SECOND {
+        return None;
+    }
+    Some((minutes * 60 + seconds) * CD_FRAMES_PER_SECOND + frames)
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "REM GENRE Ambient\n\
+        REM DATE 1994\n\
+        PERFORMER \"Some Artist\"\n\
+        TITLE \"Some Album\"\n\
+        FILE \"CD1\\album.flac\" WAVE\n\
+        \x20 TRACK 01 AUDIO\n\
+        \x20   TITLE \"First\"\n\
+        \x20   INDEX 01 00:00:00\n\
+        \x20 TRACK 02 AUDIO\n\
+        \x20   TITLE \"Second\"\n\
+        \x20   PERFORMER \"Guest\"\n\
+        \x20   INDEX 00 03:58:40\n\
+        \x20   INDEX 01 04:00:15\n\
+        FILE \"bonus.wav\" WAVE\n\
+        \x20 TRACK 03 AUDIO\n\
+        \x20   INDEX 01 00:00:00\n";
+
+    #[test]
+    fn parses_album_and_tracks() {
+        let sheet = CueSheet::parse(SHEET, Path::new("/music")).unwrap();
+        assert_eq!(sheet.title.as_deref(), Some("Some Album"));
+        assert_eq!(sheet.performer.as_deref(), Some("Some Artist"));
+        assert_eq!(sheet.date.as_deref(), Some("1994"));
+        assert_eq!(sheet.genre.as_deref(), Some("Ambient"));
+
+        let album = Path::new("/music").join("CD1").join("album.flac");
+        let numbers: Vec<u32> = sheet.tracks.iter().map(|track| track.number).collect();
+        assert_eq!(numbers, [1, 2, 3]);
+        assert_eq!(sheet.tracks[0].file, album);
+        assert_eq!(sheet.tracks[0].title.as_deref(), Some("First"));
+        assert_eq!(sheet.tracks[0].performer, None);
+        assert_eq!(sheet.tracks[1].performer.as_deref(), Some("Guest"));
+        // INDEX 01, not the pregap
+        assert_eq!(sheet.tracks[1].start, 240 * 75 + 15);
+        assert_eq!(sheet.tracks[2].start, 0);
+        assert_eq!(
+            sheet.files(),
+            [album.as_path(), Path::new("/music/bonus.wav")]
+        );
+    }
+
+    #[test]
+    fn converts_start_to_sample_frames() {
+        let track = CueTrack {
+            start: 240 * 75 + 15,
+            ..CueTrack::default()
+        };
+        assert_eq!(track.start_frame(44100), 240 * 44100 + 8820);
+        assert_eq!(track.start_frame(48000), 240 * 48000 + 9600);
+    }
+
+    #[test]
+    fn rejects_broken_sheets() {
+        let base = Path::new(".");
+        assert_eq!(
+            CueSheet::parse("TRACK 01 AUDIO\n", base),
+            Err("line 1: TRACK before FILE".to_string())
+        );
+        assert_eq!(
+            CueSheet::parse("FILE a.wav WAVE\nTRACK 01 AUDIO\n", base),
+            Err("track 1 has no INDEX 01".to_string())
+        );
+        assert_eq!(
+            CueSheet::parse("FILE a.wav WAVE\nTRACK 01 AUDIO\nINDEX 01 00:60:00\n", base),
+            Err("line 3: invalid INDEX".to_string())
+        );
+        assert_eq!(
+            CueSheet::parse("TITLE \"Nothing\"\n", base),
+            Err("no tracks in cue sheet".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_cd_times() {
+        assert_eq!(parse_time("00:00:00"), Some(0));
+        assert_eq!(parse_time("01:02:03"), Some((62 * 75) + 3));
+        assert_eq!(parse_time("99:59:74"), Some((99 * 60 + 59) * 75 + 74));
+        assert_eq!(parse_time("00:00:75"), None);
+        assert_eq!(parse_time("00:00"), None);
+        assert_eq!(parse_time("00:00:00:00"), None);
+        assert_eq!(parse_time("aa:00:00"), None);
+    }
+}
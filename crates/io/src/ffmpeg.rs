@@ -0,0 +1,196 @@
+// Decoding through ffmpeg, for what symphonia can't read
+
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use symphonia::core::io::MediaSource;
+use symphonia::core::meta::{StandardTagKey, Tag, Value};
+
+use crate::raw::{PcmEncoding, RawFormat};
+
+/// What ffprobe says about a file's first audio stream
+pub(crate) struct Probed {
+    /// The samples ffmpeg is asked to write: f32 at the stream's own rate
+    /// and channel count
+    pub(crate) format: RawFormat,
+    pub(crate) duration: Option<Duration>,
+    pub(crate) tags: Vec<Tag>,
+}
+
+/// Asks ffprobe about `input`, a path or URL
+pub(crate) fn probe(input: &str) -> Result<Probed, String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "a:0"])
+        .args([
+            "-show_entries",
+            "stream=sample_rate,channels:format=duration:format_tags",
+        ])
+        .args(["-of", "default=noprint_wrappers=1"])
+        .arg(input)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to run ffprobe: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    // `key=value` lines, tags as `TAG:key=value`
+    let mut sample_rate = None;
+    let mut channels = None;
+    let mut duration = None;
+    let mut tags = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match key {
+            "sample_rate" => sample_rate = value.parse().ok().filter(|&rate| rate > 0),
+            "channels" => channels = value.parse().ok().filter(|&channels| channels > 0),
+            "duration" => {
+                duration = value
+                    .parse()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            }
+            _ => {
+                if let Some(key) = key.strip_prefix("TAG:") {
+                    tags.push(Tag::new(
+                        standard_key(key),
+                        key,
+                        Value::String(value.to_string()),
+                    ));
+                }
+            }
+        }
+    }
+    let (Some(sample_rate), Some(channels)) = (sample_rate, channels) else {
+        return Err("ffprobe found no audio stream".to_string());
+    };
+    Ok(Probed {
+        format: RawFormat {
+            encoding: PcmEncoding::F32Le,
+            sample_rate,
+            channels,
+        },
+        duration,
+        tags,
+    })
+}
+
+/// The standard key of a tag ffmpeg names `key`
+fn standard_key(key: &str) -> Option<StandardTagKey> {
+    match key.to_ascii_lowercase().as_str() {
+        "title" => Some(StandardTagKey::TrackTitle),
+        "artist" => Some(StandardTagKey::Artist),
+        "album" => Some(StandardTagKey::Album),
+        "album_artist" => Some(StandardTagKey::AlbumArtist),
+        "composer" => Some(StandardTagKey::Composer),
+        "genre" => Some(StandardTagKey::Genre),
+        "date" => Some(StandardTagKey::Date),
+        "track" => Some(StandardTagKey::TrackNumber),
+        "disc" => Some(StandardTagKey::DiscNumber),
+        "comment" => Some(StandardTagKey::Comment),
+        _ => None,
+    }
+}
+
+/// The samples an ffmpeg process decodes, as headerless PCM of a
+/// [`Probed::format`]. Seeking starts a new process at that point.
+pub(crate) struct FfmpegSource {
+    input: String,
+    format: RawFormat,
+    seekable: bool,
+    child: Child,
+    /// Bytes into the samples
+    position: u64,
+}
+
+impl FfmpegSource {
+    /// Starts decoding `input` into `format`. Only files can seek, since a
+    /// URL would be fetched again from the start.
+    pub(crate) fn open(input: &str, format: RawFormat) -> Result<Self, String> {
+        Ok(FfmpegSource {
+            input: input.to_string(),
+            format,
+            seekable: Path::new(input).is_file(),
+            child: spawn(input, format, Duration::ZERO)?,
+            position: 0,
+        })
+    }
+}
+
+fn spawn(input: &str, format: RawFormat, start: Duration) -> Result<Child, String> {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-v", "error", "-nostdin"]);
+    if !start.is_zero() {
+        command.args(["-ss", &format!("{:.6}", start.as_secs_f64())]);
+    }
+    command
+        .arg("-i")
+        .arg(input)
+        .args(["-map", "0:a:0", "-f", format.encoding.name()])
+        .args(["-ac", &format.channels.to_string()])
+        .args(["-ar", &format.sample_rate.to_string()])
+        .arg("-")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("failed to run ffmpeg: {}", e))
+}
+
+impl Read for FfmpegSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(stdout) = self.child.stdout.as_mut() else {
+            return Ok(0);
+        };
+        let count = stdout.read(buf)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl std::io::Seek for FfmpegSource {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let byte = match pos {
+            std::io::SeekFrom::Start(byte) if self.seekable => byte,
+            std::io::SeekFrom::Current(0) => return Ok(self.position),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "ffmpeg output can only seek in files",
+                ))
+            }
+        };
+        let frame = byte / self.format.frame_bytes() as u64;
+        let start = Duration::from_secs_f64(frame as f64 / self.format.sample_rate as f64);
+        let child = spawn(&self.input, self.format, start).map_err(std::io::Error::other)?;
+        let mut old = std::mem::replace(&mut self.child, child);
+        let _ = old.kill();
+        let _ = old.wait();
+        self.position = frame * self.format.frame_bytes() as u64;
+        Ok(self.position)
+    }
+}
+
+impl MediaSource for FfmpegSource {
+    fn is_seekable(&self) -> bool {
+        self.seekable
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl Drop for FfmpegSource {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
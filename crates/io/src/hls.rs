@@ -0,0 +1,755 @@
+// HTTP Live Streaming (HLS) playback
+
+use std::io::Read;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use symphonia::core::io::MediaSource;
+
+use crate::http::{fetch, resolve_url};
+
+/// Longest a playlist or segment may take to fetch, in seconds
+const FETCH_TIMEOUT: u32 = 30;
+/// Times a segment is fetched before giving up on it
+const FETCH_TRIES: usize = 3;
+/// Segments fetched ahead of playback
+const READ_AHEAD_SEGMENTS: usize = 3;
+/// How far from the end of a live playlist playback starts, in segments
+const LIVE_START_SEGMENTS: usize = 3;
+/// Size of an MPEG transport stream packet
+const TS_PACKET: usize = 188;
+/// Video codecs named in `CODECS`, to tell audio-only variants apart
+const VIDEO_CODECS: &[&str] = &["avc1", "avc3", "hvc1", "hev1", "vp09", "av01", "dvh1"];
+
+/// Whether `url` names an HLS playlist
+pub fn is_hls(url: &str) -> bool {
+    crate::http::url_extension(url).is_some_and(|ext| ext.eq_ignore_ascii_case("m3u8"))
+}
+
+/// Audio of an HLS stream, live or on demand: the segments of its media
+/// playlist one after the other, fetched on a thread of their own. Transport
+/// stream segments are demuxed to their audio, so the decoder sees a plain
+/// stream of ADTS or MPEG audio frames.
+pub struct HlsSource {
+    segments: Mutex<Receiver<Result<Segment, String>>>,
+    /// Data of the segment being read, and how much of it was
+    data: Vec<u8>,
+    consumed: usize,
+    /// Extension of the audio format, for picking the decoder
+    extension: Option<&'static str>,
+}
+
+/// Audio data of a segment
+struct Segment {
+    data: Vec<u8>,
+    extension: Option<&'static str>,
+}
+
+impl HlsSource {
+    /// Starts playing the stream of the playlist at `url`, which may be a
+    /// master playlist listing several variants
+    pub fn open(url: &str) -> Result<Self, String> {
+        let text = fetch_text(url)?;
+        let media_url = match text.contains("#EXT-X-STREAM-INF") {
+            true => pick_variant(url, &text).ok_or("no playable stream in the HLS playlist")?,
+            false => url.to_string(),
+        };
+        let (sender, segments) = sync_channel(READ_AHEAD_SEGMENTS);
+        std::thread::spawn(move || fetch_segments(&media_url, &sender));
+        // The first segment tells what the audio is
+        let first = segments
+            .recv()
+            .map_err(|_| "the HLS stream has no segments".to_string())??;
+        Ok(HlsSource {
+            segments: Mutex::new(segments),
+            extension: first.extension,
+            data: first.data,
+            consumed: 0,
+        })
+    }
+
+    /// Extension of the stream's audio format, such as `aac`
+    pub fn extension(&self) -> Option<&'static str> {
+        self.extension
+    }
+}
+
+impl Read for HlsSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.consumed == self.data.len() {
+            match self.segments.get_mut().unwrap().recv() {
+                Ok(Ok(segment)) => {
+                    self.data = segment.data;
+                    self.consumed = 0;
+                }
+                Ok(Err(e)) => return Err(std::io::Error::other(e)),
+                // The stream ended
+                Err(_) => return Ok(0),
+            }
+        }
+        let count = buf.len().min(self.data.len() - self.consumed);
+        buf[..count].copy_from_slice(&self.data[self.consumed..self.consumed + count]);
+        self.consumed += count;
+        Ok(count)
+    }
+}
+
+impl std::io::Seek for HlsSource {
+    fn seek(&mut self, _pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "HLS streams can't be seeked",
+        ))
+    }
+}
+
+impl MediaSource for HlsSource {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+fn fetch_text(url: &str) -> Result<String, String> {
+    let data = fetch(url, FETCH_TIMEOUT)?;
+    let text = String::from_utf8_lossy(&data).into_owned();
+    if !text.trim_start().starts_with("#EXTM3U") {
+        return Err(format!("{} is not an HLS playlist", url));
+    }
+    Ok(text)
+}
+
+/// The `NAME=value` attributes of a tag, with quotes taken off the values
+fn attributes(list: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = list;
+    while let Some((name, after)) = rest.split_once('=') {
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let next = quoted[end..].trim_start_matches('"');
+                (&quoted[..end], next.trim_start_matches(','))
+            }
+            None => match after.split_once(',') {
+                Some((value, next)) => (value, next),
+                None => (after, ""),
+            },
+        };
+        attributes.push((name.trim().to_string(), value.to_string()));
+        rest = next;
+    }
+    attributes
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// The media playlist to play from a master playlist: its default audio
+/// rendition if it has separate ones, else the best audio-only variant, else
+/// the variant with the least to download
+fn pick_variant(url: &str, master: &str) -> Option<String> {
+    let mut renditions = Vec::new();
+    let mut variants: Vec<(u64, bool, String)> = Vec::new();
+    let mut lines = master.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if let Some(list) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let list = attributes(list);
+            if let (Some("AUDIO"), Some(uri)) = (attribute(&list, "TYPE"), attribute(&list, "URI"))
+            {
+                let default = attribute(&list, "DEFAULT") == Some("YES");
+                renditions.push((default, uri.to_string()));
+            }
+        } else if let Some(list) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            let list = attributes(list);
+            let bandwidth = attribute(&list, "BANDWIDTH")
+                .and_then(|bandwidth| bandwidth.parse().ok())
+                .unwrap_or(0);
+            let audio_only = attribute(&list, "CODECS").is_some_and(|codecs| {
+                !codecs.split(',').any(|codec| {
+                    VIDEO_CODECS
+                        .iter()
+                        .any(|video| codec.trim().starts_with(video))
+                })
+            });
+            if let Some(uri) = lines
+                .by_ref()
+                .find(|line| !line.is_empty() && !line.starts_with('#'))
+            {
+                variants.push((bandwidth, audio_only, uri.to_string()));
+            }
+        }
+    }
+    let rendition = renditions
+        .iter()
+        .find(|(default, _)| *default)
+        .or(renditions.first())
+        .map(|(_, uri)| uri);
+    let audio_only = variants
+        .iter()
+        .filter(|(_, audio_only, _)| *audio_only)
+        .max_by_key(|(bandwidth, _, _)| *bandwidth);
+    let smallest = variants.iter().min_by_key(|(bandwidth, _, _)| *bandwidth);
+    let uri = rendition.or(audio_only.or(smallest).map(|(_, _, uri)| uri))?;
+    Some(resolve_url(url, uri))
+}
+
+/// A segment listed in a media playlist
+struct MediaSegment {
+    sequence: u64,
+    url: String,
+    /// Initialization section to play first, for fragmented MP4 segments
+    map: Option<String>,
+    /// Whether the encoding may change from the segment before
+    discontinuity: bool,
+}
+
+/// What a media playlist says
+struct MediaPlaylist {
+    /// How long segments last at most
+    target_duration: Duration,
+    segments: Vec<MediaSegment>,
+    /// Set once the stream has all its segments, as on demand ones do
+    ended: bool,
+}
+
+fn parse_media_playlist(url: &str, text: &str) -> Result<MediaPlaylist, String> {
+    let mut playlist = MediaPlaylist {
+        target_duration: Duration::from_secs(6),
+        segments: Vec::new(),
+        ended: false,
+    };
+    let mut sequence = 0;
+    let mut map = None;
+    let mut discontinuity = false;
+    for line in text.lines().map(str::trim) {
+        if let Some(seconds) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            if let Ok(seconds) = seconds.trim().parse::<f64>() {
+                playlist.target_duration = Duration::from_secs_f64(seconds.max(1.0));
+            }
+        } else if let Some(number) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            sequence = number.trim().parse().unwrap_or(0);
+        } else if let Some(list) = line.strip_prefix("#EXT-X-MAP:") {
+            map = attribute(&attributes(list), "URI").map(|uri| resolve_url(url, uri));
+        } else if let Some(list) = line.strip_prefix("#EXT-X-KEY:") {
+            let list = attributes(list);
+            if attribute(&list, "METHOD").is_some_and(|method| method != "NONE") {
+                return Err("encrypted HLS streams aren't supported".to_string());
+            }
+        } else if line == "#EXT-X-DISCONTINUITY" {
+            discontinuity = true;
+        } else if line == "#EXT-X-ENDLIST" {
+            playlist.ended = true;
+        } else if !line.is_empty() && !line.starts_with('#') {
+            playlist.segments.push(MediaSegment {
+                sequence,
+                url: resolve_url(url, line),
+                map: map.clone(),
+                discontinuity,
+            });
+            sequence += 1;
+            discontinuity = false;
+        }
+    }
+    Ok(playlist)
+}
+
+/// Fetches the segments of the media playlist at `url` in order and hands
+/// them over, reloading the playlist of a live stream as it grows
+fn fetch_segments(url: &str, segments: &SyncSender<Result<Segment, String>>) {
+    let mut next: Option<u64> = None;
+    let mut map: Option<String> = None;
+    let mut demuxer = TsDemuxer::default();
+    loop {
+        let playlist = match fetch_text(url).and_then(|text| parse_media_playlist(url, &text)) {
+            Ok(playlist) => playlist,
+            Err(e) => {
+                let _ = segments.send(Err(e));
+                return;
+            }
+        };
+        let start = first_sequence(&playlist, next);
+        let mut fetched = false;
+        for segment in playlist
+            .segments
+            .iter()
+            .filter(|segment| segment.sequence >= start)
+        {
+            // Decoders need the encoding to stay the same, so a transport
+            // stream is followed afresh from a discontinuity on
+            if segment.discontinuity || next.is_some_and(|next| segment.sequence > next) {
+                demuxer = TsDemuxer::default();
+            }
+            let mut data = Vec::new();
+            if segment.map != map {
+                map = segment.map.clone();
+                if let Some(map) = &map {
+                    match fetch_segment(map) {
+                        Ok(init) => data = init,
+                        Err(e) => {
+                            let _ = segments.send(Err(e));
+                            return;
+                        }
+                    }
+                }
+            }
+            let body = match fetch_segment(&segment.url) {
+                Ok(body) => body,
+                Err(e) => {
+                    let _ = segments.send(Err(e));
+                    return;
+                }
+            };
+            let extension = match is_transport_stream(&body) {
+                true => {
+                    demuxer.push(&body, &mut data);
+                    demuxer.extension
+                }
+                false => {
+                    data.extend_from_slice(skip_id3(&body));
+                    let extension = crate::http::url_extension(&segment.url);
+                    segment_extension(extension, map.is_some())
+                }
+            };
+            if segments.send(Ok(Segment { data, extension })).is_err() {
+                return;
+            }
+            next = Some(segment.sequence + 1);
+            fetched = true;
+        }
+        if playlist.ended {
+            return;
+        }
+        // A live playlist is reloaded once it may have a new segment, or
+        // sooner when it had none
+        let wait = match fetched {
+            true => playlist.target_duration,
+            false => playlist.target_duration / 2,
+        };
+        std::thread::sleep(wait);
+    }
+}
+
+/// Sequence number of the first segment to fetch from a playlist that was
+/// just loaded, `next` following the last one fetched from earlier loads.
+/// Live streams start near their end, as they would on the air.
+fn first_sequence(playlist: &MediaPlaylist, next: Option<u64>) -> u64 {
+    match (next, playlist.ended) {
+        (Some(next), _) => next,
+        (None, true) => 0,
+        (None, false) => {
+            let first = playlist.segments.len().saturating_sub(LIVE_START_SEGMENTS);
+            playlist
+                .segments
+                .get(first)
+                .map_or(0, |segment| segment.sequence)
+        }
+    }
+}
+
+fn fetch_segment(url: &str) -> Result<Vec<u8>, String> {
+    let mut tries = 0;
+    loop {
+        match fetch(url, FETCH_TIMEOUT) {
+            Err(_) if tries + 1 < FETCH_TRIES => tries += 1,
+            result => return result,
+        }
+    }
+}
+
+/// The format of a packed audio or fragmented MP4 segment, from its
+/// extension
+fn segment_extension(extension: Option<&str>, fragmented: bool) -> Option<&'static str> {
+    if fragmented {
+        return Some("mp4");
+    }
+    match extension?.to_ascii_lowercase().as_str() {
+        "aac" | "adts" => Some("aac"),
+        "mp3" => Some("mp3"),
+        "ac3" => Some("ac3"),
+        "m4s" | "mp4" | "m4a" => Some("mp4"),
+        _ => None,
+    }
+}
+
+/// `data` without the ID3 tag packed audio segments start with
+fn skip_id3(data: &[u8]) -> &[u8] {
+    if data.len() < 10 || &data[..3] != b"ID3" {
+        return data;
+    }
+    // Synchsafe: 7 bits a byte
+    let size = data[6..10]
+        .iter()
+        .fold(0usize, |size, &byte| (size << 7) | (byte & 0x7f) as usize);
+    let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+    data.get(10 + size + footer..).unwrap_or_default()
+}
+
+fn is_transport_stream(data: &[u8]) -> bool {
+    data.len() >= TS_PACKET
+        && data[0] == 0x47
+        && data.get(TS_PACKET).is_none_or(|&sync| sync == 0x47)
+}
+
+/// Pulls the first audio stream out of an MPEG transport stream
+#[derive(Default)]
+struct TsDemuxer {
+    /// Packet id of the program map table, from the program association table
+    pmt_pid: Option<u16>,
+    audio_pid: Option<u16>,
+    /// Format of the audio stream
+    extension: Option<&'static str>,
+    /// PES packet of the audio stream being gathered
+    pes: Vec<u8>,
+}
+
+impl TsDemuxer {
+    /// Demuxes the packets of `data`, adding the audio they carry to `out`
+    fn push(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        for packet in data.chunks_exact(TS_PACKET) {
+            if packet[0] != 0x47 {
+                continue;
+            }
+            let unit_start = packet[1] & 0x40 != 0;
+            let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+            let adaptation = packet[3] & 0x20 != 0;
+            let has_payload = packet[3] & 0x10 != 0;
+            let mut payload = &packet[4..];
+            if adaptation {
+                let length = payload[0] as usize;
+                payload = payload.get(1 + length..).unwrap_or_default();
+            }
+            if !has_payload {
+                continue;
+            }
+            if pid == 0 {
+                self.read_pat(unit_start, payload);
+            } else if Some(pid) == self.pmt_pid {
+                self.read_pmt(unit_start, payload);
+            } else if Some(pid) == self.audio_pid {
+                if unit_start {
+                    self.flush(out);
+                }
+                self.pes.extend_from_slice(payload);
+            }
+        }
+        // Segments begin with a new packet, so what's gathered is complete
+        self.flush(out);
+    }
+
+    /// The section of a table, after its pointer field
+    fn section(unit_start: bool, payload: &[u8]) -> Option<&[u8]> {
+        if !unit_start {
+            return None;
+        }
+        let pointer = *payload.first()? as usize;
+        let section = payload.get(1 + pointer..)?;
+        let length = (u16::from_be_bytes([*section.get(1)? & 0x0f, *section.get(2)?])) as usize;
+        // Without the CRC at the end
+        section.get(..(3 + length).checked_sub(4)?)
+    }
+
+    fn read_pat(&mut self, unit_start: bool, payload: &[u8]) {
+        let Some(section) = TsDemuxer::section(unit_start, payload) else {
+            return;
+        };
+        for program in section.get(8..).unwrap_or_default().chunks_exact(4) {
+            let number = u16::from_be_bytes([program[0], program[1]]);
+            // Program 0 points to the network information table instead
+            if number != 0 {
+                self.pmt_pid = Some(u16::from_be_bytes([program[2] & 0x1f, program[3]]));
+                return;
+            }
+        }
+    }
+
+    fn read_pmt(&mut self, unit_start: bool, payload: &[u8]) {
+        let Some(section) = TsDemuxer::section(unit_start, payload) else {
+            return;
+        };
+        let Some(info) = section.get(10..12) else {
+            return;
+        };
+        let info_length = u16::from_be_bytes([info[0] & 0x0f, info[1]]) as usize;
+        let mut streams = section.get(12 + info_length..).unwrap_or_default();
+        while streams.len() >= 5 {
+            let stream_type = streams[0];
+            let pid = u16::from_be_bytes([streams[1] & 0x1f, streams[2]]);
+            let es_info_length = u16::from_be_bytes([streams[3] & 0x0f, streams[4]]) as usize;
+            let extension = match stream_type {
+                0x03 | 0x04 => Some("mp3"),
+                0x0f => Some("aac"),
+                0x81 => Some("ac3"),
+                _ => None,
+            };
+            if extension.is_some() {
+                if self.audio_pid != Some(pid) {
+                    self.pes.clear();
+                }
+                self.audio_pid = Some(pid);
+                self.extension = extension;
+                return;
+            }
+            streams = streams.get(5 + es_info_length..).unwrap_or_default();
+        }
+    }
+
+    /// Adds the payload of the PES packet gathered so far to `out`
+    fn flush(&mut self, out: &mut Vec<u8>) {
+        let pes = std::mem::take(&mut self.pes);
+        if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+            return;
+        }
+        let header = 9 + pes[8] as usize;
+        out.extend_from_slice(pes.get(header..).unwrap_or_default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "https://example.com/live/index.m3u8?token=1";
+
+    fn media_playlist(text: &str) -> MediaPlaylist {
+        parse_media_playlist(BASE, text).unwrap()
+    }
+
+    fn sequences(playlist: &MediaPlaylist) -> Vec<u64> {
+        playlist
+            .segments
+            .iter()
+            .map(|segment| segment.sequence)
+            .collect()
+    }
+
+    #[test]
+    fn reads_attributes() {
+        let list = attributes(r#"BANDWIDTH=128000,CODECS="mp4a.40.2,avc1.4d401f",NAME="A, B""#);
+        assert_eq!(attribute(&list, "BANDWIDTH"), Some("128000"));
+        assert_eq!(attribute(&list, "CODECS"), Some("mp4a.40.2,avc1.4d401f"));
+        assert_eq!(attribute(&list, "NAME"), Some("A, B"));
+        assert_eq!(attribute(&list, "URI"), None);
+    }
+
+    #[test]
+    fn picks_the_default_audio_rendition() {
+        let master = "#EXTM3U\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"Other\",URI=\"other/audio.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"Main\",DEFAULT=YES,URI=\"main/audio.m3u8\"\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS=\"avc1.4d401f,mp4a.40.2\",AUDIO=\"aac\"\n\
+            video.m3u8\n";
+        assert_eq!(
+            pick_variant(BASE, master).as_deref(),
+            Some("https://example.com/live/main/audio.m3u8")
+        );
+    }
+
+    #[test]
+    fn picks_the_best_audio_only_variant() {
+        let master = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS=\"mp4a.40.5\"\n\
+            low/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS=\"avc1.4d401f,mp4a.40.2\"\n\
+            video/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=192000,CODECS=\"mp4a.40.2\"\n\
+            \n\
+            /high/index.m3u8\n";
+        assert_eq!(
+            pick_variant(BASE, master).as_deref(),
+            Some("https://example.com/high/index.m3u8")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_smallest_variant() {
+        let master = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=1500000\n\
+            https://cdn.example/hd.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=400000\n\
+            https://cdn.example/sd.m3u8\n";
+        assert_eq!(
+            pick_variant(BASE, master).as_deref(),
+            Some("https://cdn.example/sd.m3u8")
+        );
+        assert_eq!(
+            pick_variant(BASE, "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=1\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_media_playlists() {
+        let playlist = media_playlist(
+            "#EXTM3U\n\
+             #EXT-X-VERSION:7\n\
+             #EXT-X-TARGETDURATION:10\n\
+             #EXT-X-MEDIA-SEQUENCE:100\n\
+             #EXT-X-MAP:URI=\"init.mp4\"\n\
+             #EXTINF:9.8,\n\
+             seg100.m4s\n\
+             #EXT-X-DISCONTINUITY\n\
+             #EXTINF:10.0,\n\
+             /other/seg101.m4s\n\
+             #EXTINF:10.0,\n\
+             https://cdn.example/seg102.m4s\n\
+             #EXT-X-ENDLIST\n",
+        );
+        assert_eq!(playlist.target_duration, Duration::from_secs(10));
+        assert!(playlist.ended);
+        assert_eq!(sequences(&playlist), [100, 101, 102]);
+        let urls: Vec<&str> = playlist.segments.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/live/seg100.m4s",
+                "https://example.com/other/seg101.m4s",
+                "https://cdn.example/seg102.m4s",
+            ]
+        );
+        let discontinuities: Vec<bool> =
+            playlist.segments.iter().map(|s| s.discontinuity).collect();
+        assert_eq!(discontinuities, [false, true, false]);
+        assert!(playlist
+            .segments
+            .iter()
+            .all(|s| s.map.as_deref() == Some("https://example.com/live/init.mp4")));
+    }
+
+    #[test]
+    fn refuses_encrypted_playlists() {
+        let text = "#EXTM3U\n#EXT-X-KEY:METHOD=AES-128,URI=\"key\"\nseg.ts\n";
+        assert_eq!(
+            parse_media_playlist(BASE, text).err().as_deref(),
+            Some("encrypted HLS streams aren't supported")
+        );
+        let clear = media_playlist("#EXTM3U\n#EXT-X-KEY:METHOD=NONE\nseg.ts\n");
+        assert_eq!(sequences(&clear), [0]);
+        assert!(!clear.ended);
+    }
+
+    fn live(first: u64, count: u64) -> MediaPlaylist {
+        let mut text = format!(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+            first
+        );
+        for sequence in first..first + count {
+            text.push_str(&format!("#EXTINF:4.0,\nseg{}.ts\n", sequence));
+        }
+        media_playlist(&text)
+    }
+
+    #[test]
+    fn follows_live_playlists_across_reloads() {
+        // Live streams start a few segments from the end
+        let playlist = live(50, 6);
+        assert_eq!(first_sequence(&playlist, None), 53);
+        // A reload picks up after the last segment fetched
+        let reloaded = live(52, 6);
+        assert_eq!(first_sequence(&reloaded, Some(56)), 56);
+        let new: Vec<u64> = sequences(&reloaded)
+            .into_iter()
+            .filter(|&sequence| sequence >= 56)
+            .collect();
+        assert_eq!(new, [56, 57]);
+        // Nothing new yet
+        assert_eq!(first_sequence(&reloaded, Some(58)), 58);
+        // Short live playlists and on demand ones play from the start
+        assert_eq!(first_sequence(&live(7, 2), None), 7);
+        let ended =
+            media_playlist("#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:9\na.ts\nb.ts\n#EXT-X-ENDLIST\n");
+        assert_eq!(first_sequence(&ended, None), 0);
+        assert_eq!(sequences(&ended), [9, 10]);
+    }
+
+    #[test]
+    fn names_segment_formats() {
+        assert!(is_hls("https://example.com/live.M3U8?x=1"));
+        assert!(!is_hls("https://example.com/live.mp3"));
+        assert_eq!(segment_extension(Some("AAC"), false), Some("aac"));
+        assert_eq!(segment_extension(Some("m4s"), false), Some("mp4"));
+        assert_eq!(segment_extension(Some("ts"), true), Some("mp4"));
+        assert_eq!(segment_extension(Some("ts"), false), None);
+        assert_eq!(segment_extension(None, false), None);
+    }
+
+    #[test]
+    fn skips_id3_tags() {
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x01\x00".to_vec();
+        data.extend([0u8; 128]);
+        data.extend(b"\xff\xf1audio");
+        assert_eq!(skip_id3(&data), b"\xff\xf1audio");
+        assert_eq!(skip_id3(b"\xff\xf1audio"), b"\xff\xf1audio");
+    }
+
+    /// A transport stream packet, padded with adaptation field stuffing
+    fn packet(pid: u16, unit_start: bool, payload: &[u8]) -> Vec<u8> {
+        let start = if unit_start { 0x40 } else { 0 };
+        let stuffing = TS_PACKET - 4 - payload.len();
+        let adaptation = if stuffing > 0 { 0x20 } else { 0 };
+        let mut packet = vec![0x47, start | (pid >> 8) as u8, pid as u8, adaptation | 0x10];
+        if stuffing > 0 {
+            packet.push((stuffing - 1) as u8);
+        }
+        if stuffing > 1 {
+            packet.push(0);
+            packet.resize(packet.len() + stuffing - 2, 0xff);
+        }
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// A table section after its pointer field, with a dummy CRC
+    fn section(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let length = body.len() + 4;
+        let mut section = vec![0, table_id, 0xb0 | (length >> 8) as u8, length as u8];
+        section.extend_from_slice(body);
+        section.extend([0; 4]);
+        section
+    }
+
+    fn pes(payload: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0, 0, 1, 0xc0, 0, 0, 0x80, 0x80, 5, 0x21, 0, 1, 0, 1];
+        pes.extend_from_slice(payload);
+        pes
+    }
+
+    #[test]
+    fn demuxes_audio_from_transport_streams() {
+        // Program 1 has its map on PID 0x100
+        let pat = section(0, &[0, 1, 0xc1, 0, 0, 0, 1, 0xe1, 0x00]);
+        // Video on 0x101, AAC on 0x102
+        let pmt = section(
+            2,
+            &[
+                0, 1, 0xc1, 0, 0, 0xe1, 0x01, 0xf0, 0, 0x1b, 0xe1, 0x01, 0xf0, 0, 0x0f, 0xe1, 0x02,
+                0xf0, 0,
+            ],
+        );
+        let first = pes(&[1; 300]);
+        let mut stream = Vec::new();
+        stream.extend(packet(0, true, &pat));
+        stream.extend(packet(0x100, true, &pmt));
+        stream.extend(packet(0x101, true, &pes(b"video")));
+        stream.extend(packet(0x102, true, &first[..184]));
+        stream.extend(packet(0x102, false, &first[184..]));
+        stream.extend(packet(0x102, true, &pes(&[2; 20])));
+        assert!(is_transport_stream(&stream));
+
+        let mut demuxer = TsDemuxer::default();
+        let mut out = Vec::new();
+        demuxer.push(&stream, &mut out);
+        assert_eq!(demuxer.extension, Some("aac"));
+        assert_eq!(out.len(), 320);
+        assert!(out[..300].iter().all(|&byte| byte == 1));
+        assert!(out[300..].iter().all(|&byte| byte == 2));
+        assert!(!is_transport_stream(b"\xff\xf1audio"));
+    }
+}
@@ -0,0 +1,730 @@
+// Media streamed over HTTP(S), including Icecast and SHOUTcast radio
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
+
+use symphonia::core::io::MediaSource;
+
+/// Bytes the read-ahead thread reads at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks read ahead of playback, 4 MiB in all
+const READ_AHEAD_CHUNKS: usize = 64;
+/// How far ahead a seek may land and still be read through rather than
+/// starting a new transfer
+const SKIP_LIMIT: u64 = 512 * 1024;
+/// Longest the server may take to answer, in seconds
+const CONNECT_TIMEOUT: u32 = 15;
+/// Audio buffered before an endless stream starts playing, and again after
+/// the network stalled, to ride out hiccups
+const JITTER_SECS: u64 = 3;
+/// Bitrate assumed for the jitter buffer when a stream doesn't state one
+const DEFAULT_BITRATE_KBPS: u64 = 128;
+
+/// Whether `path` is an `http://` or `https://` URL rather than a file
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| {
+        let scheme = path.split_once("://").map(|(scheme, _)| scheme);
+        scheme.is_some_and(|scheme| {
+            scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+        })
+    })
+}
+
+/// Extension of the file a URL names, ignoring its query, for picking the
+/// decoder
+pub fn url_extension(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    let (_, name) = path.split_once("://")?.1.split_once('/')?;
+    let name = name.rsplit('/').next()?;
+    name.rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .filter(|ext| !ext.is_empty())
+}
+
+/// `reference` resolved against the URL `base` it was found at
+pub fn resolve_url(base: &str, reference: &str) -> String {
+    if reference.contains("://") {
+        return reference.to_string();
+    }
+    let (scheme, rest) = base.split_once("://").unwrap_or(("https", base));
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if let Some(path) = reference.strip_prefix("//") {
+        return format!("{}://{}", scheme, path);
+    }
+    if reference.starts_with('/') {
+        return format!("{}://{}{}", scheme, host, reference);
+    }
+    let path = base.split(['?', '#']).next().unwrap_or(base);
+    match path
+        .rfind('/')
+        .filter(|&slash| slash >= scheme.len() + 3 + host.len())
+    {
+        Some(slash) => format!("{}{}", &path[..=slash], reference),
+        None => format!("{}://{}/{}", scheme, host, reference),
+    }
+}
+
+/// The `curl` command, which everything fetched over HTTP(S) goes through.
+/// Fails when built without the `curl` feature.
+pub fn curl() -> Result<Command, String> {
+    match cfg!(feature = "curl") {
+        true => Ok(Command::new("curl")),
+        false => Err("mogbox was built without the `curl` feature".to_string()),
+    }
+}
+
+/// Why `curl` couldn't be run, saying so plainly when it isn't installed
+pub fn curl_error(e: std::io::Error) -> String {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => "curl not found; it has to be installed".to_string(),
+        _ => format!("failed to run curl: {}", e),
+    }
+}
+
+/// Fetches all of `url` through the `curl` command, giving up after `timeout`
+/// seconds
+pub(crate) fn fetch(url: &str, timeout: u32) -> Result<Vec<u8>, String> {
+    let output = curl()?
+        .args(["--silent", "--show-error", "--location", "--fail"])
+        .args(["--connect-timeout", &CONNECT_TIMEOUT.to_string()])
+        .args(["--max-time", &timeout.to_string()])
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(curl_error)?;
+    if !output.status.success() {
+        return Err(format!(
+            "failed to fetch {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Downloads `url` to the file at `path` through the `curl` command. The
+/// download goes to a temporary file first, so `path` only ever holds all of
+/// it.
+pub fn download(url: &str, path: &Path) -> Result<(), String> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".part");
+    let output = curl()?
+        .args(["--silent", "--show-error", "--location", "--fail"])
+        .args(["--connect-timeout", &CONNECT_TIMEOUT.to_string()])
+        .arg("--output")
+        .arg(&temp)
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(curl_error)?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(&temp);
+        return Err(format!(
+            "failed to download {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    std::fs::rename(&temp, path).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+/// What an Icecast or SHOUTcast station says about itself and what it plays.
+/// Clones share the title, which changes as the stream goes on.
+#[derive(Clone, Debug, Default)]
+pub struct StreamMetadata {
+    name: Option<String>,
+    genre: Option<String>,
+    title: Arc<Mutex<Option<String>>>,
+}
+
+impl StreamMetadata {
+    /// Name of the station
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn genre(&self) -> Option<&str> {
+        self.genre.as_deref()
+    }
+
+    /// What the station last said it's playing, usually `Artist - Title`
+    pub fn title(&self) -> Option<String> {
+        self.title.lock().unwrap().clone()
+    }
+}
+
+/// Media read over HTTP(S) through the `curl` command, which takes care of
+/// TLS and redirects. A thread reads ahead of the decoder; seeking starts a new
+/// transfer with a range request, when the server takes those. Radio streams
+/// have their ICY metadata taken out of the audio and kept in
+/// [`StreamMetadata`].
+pub struct HttpSource {
+    url: String,
+    /// Length of the media, when the server states it; endless streams have none
+    length: Option<u64>,
+    /// Whether the server answers range requests
+    ranges: bool,
+    /// Bytes of audio read so far, not counting metadata
+    position: u64,
+    transfer: Option<Transfer>,
+    /// Bytes of audio between ICY metadata blocks, for radio streams
+    metaint: Option<usize>,
+    /// Bytes of audio left before the next metadata block
+    until_metadata: usize,
+    metadata: Option<StreamMetadata>,
+}
+
+/// A running `curl` and the chunks read ahead from it
+struct Transfer {
+    child: Child,
+    chunks: Mutex<Receiver<std::io::Result<Vec<u8>>>>,
+    /// Chunks received and not yet read, and how much of the first one was
+    pending: VecDeque<Vec<u8>>,
+    consumed: usize,
+    /// Bytes in `pending` not yet read
+    buffered: usize,
+    /// Bytes to wait for whenever nothing is buffered
+    prebuffer: usize,
+    /// Set once curl is done, with the error it ended on, if any
+    done: bool,
+    error: Option<std::io::Error>,
+}
+
+/// What the server said about a transfer
+struct Response {
+    status: u32,
+    /// Total length, from `Content-Range` or else `Content-Length`
+    length: Option<u64>,
+    ranges: bool,
+    /// `icy-metaint`, `icy-name`, `icy-genre` and `icy-br` of radio streams
+    metaint: Option<usize>,
+    name: Option<String>,
+    genre: Option<String>,
+    bitrate_kbps: Option<u64>,
+}
+
+impl HttpSource {
+    /// Starts fetching `url`
+    pub fn open(url: &str) -> Result<Self, String> {
+        let (mut transfer, response) = Transfer::start(url, None)?;
+        if response.length.is_none() {
+            let kbps = response.bitrate_kbps.unwrap_or(DEFAULT_BITRATE_KBPS);
+            let jitter = (kbps * 1000 / 8 * JITTER_SECS) as usize;
+            transfer.prebuffer = jitter.min(READ_AHEAD_CHUNKS * CHUNK_SIZE / 2);
+        }
+        let metadata =
+            (response.metaint.is_some() || response.name.is_some()).then(|| StreamMetadata {
+                name: response.name.clone(),
+                genre: response.genre.clone(),
+                title: Arc::default(),
+            });
+        Ok(HttpSource {
+            url: url.to_string(),
+            length: response.length,
+            ranges: response.ranges || response.status == 206,
+            position: 0,
+            transfer: Some(transfer),
+            metaint: response.metaint,
+            until_metadata: response.metaint.unwrap_or(0),
+            metadata,
+        })
+    }
+
+    /// What the station says about itself, for radio streams
+    pub fn metadata(&self) -> Option<StreamMetadata> {
+        self.metadata.clone()
+    }
+
+    /// Reads the metadata block that comes after every `metaint` bytes of
+    /// audio: a byte giving its length in 16 byte units, then text such as
+    /// `StreamTitle='Artist - Title';`, padded with zeros
+    fn read_metadata(&mut self) -> std::io::Result<()> {
+        let Some(transfer) = &mut self.transfer else {
+            return Ok(());
+        };
+        let mut length = [0u8];
+        transfer.read_exact(&mut length)?;
+        let mut block = vec![0u8; length[0] as usize * 16];
+        transfer.read_exact(&mut block)?;
+        let end = block
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(block.len());
+        // Mostly UTF-8, but older stations send Latin-1
+        let text = match std::str::from_utf8(&block[..end]) {
+            Ok(text) => text.to_string(),
+            Err(_) => block[..end].iter().map(|&byte| byte as char).collect(),
+        };
+        if let (Some(title), Some(metadata)) = (stream_title(&text), &self.metadata) {
+            *metadata.title.lock().unwrap() = Some(title).filter(|title| !title.is_empty());
+        }
+        Ok(())
+    }
+}
+
+/// The `StreamTitle` of ICY metadata
+fn stream_title(metadata: &str) -> Option<String> {
+    let start = metadata.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &metadata[start..];
+    // Titles can hold quotes themselves, so the field ends at `';`
+    let end = rest.find("';").unwrap_or(rest.trim_end_matches('\'').len());
+    Some(rest[..end].trim().to_string())
+}
+
+impl Transfer {
+    /// Runs curl for `url` from `offset`, reading the response headers
+    fn start(url: &str, offset: Option<u64>) -> Result<(Transfer, Response), String> {
+        let mut command = curl()?;
+        command
+            .args(["--silent", "--show-error", "--location"])
+            .args(["--connect-timeout", &CONNECT_TIMEOUT.to_string()])
+            .args(["--dump-header", "-"])
+            // Radio stations interleave what they play with the audio when asked
+            .args(["--header", "Icy-MetaData: 1"]);
+        if let Some(offset) = offset {
+            command.args(["--range", &format!("{}-", offset)]);
+        }
+        let mut child = command
+            .arg(url)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(curl_error)?;
+        let stdout = child.stdout.take().ok_or("failed to run curl")?;
+        let mut reader = BufReader::with_capacity(CHUNK_SIZE, stdout);
+        let response = match read_response(&mut reader) {
+            Ok(response) => response,
+            Err(e) => {
+                let _ = child.kill();
+                let output = child.wait_with_output();
+                let stderr = output
+                    .map(|output| String::from_utf8_lossy(&output.stderr).trim().to_string())
+                    .unwrap_or_default();
+                return Err(match stderr.is_empty() {
+                    true => format!("failed to open {}: {}", url, e),
+                    false => format!("failed to open {}: {}", url, stderr),
+                });
+            }
+        };
+        if response.status >= 400 {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("failed to open {}: HTTP {}", url, response.status));
+        }
+        if offset.is_some_and(|offset| offset > 0) && response.status != 206 {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("{} can't be read from the middle", url));
+        }
+
+        let (sender, chunks) = sync_channel(READ_AHEAD_CHUNKS);
+        std::thread::spawn(move || read_ahead(reader, sender));
+        let transfer = Transfer {
+            child,
+            chunks: Mutex::new(chunks),
+            pending: VecDeque::new(),
+            consumed: 0,
+            buffered: 0,
+            prebuffer: 0,
+            done: false,
+            error: None,
+        };
+        Ok((transfer, response))
+    }
+
+    /// Waits until `target` bytes are buffered, or the transfer is done
+    fn fill(&mut self, target: usize) {
+        let chunks = self.chunks.get_mut().unwrap();
+        while !self.done && self.buffered < target {
+            match chunks.recv() {
+                Ok(Ok(chunk)) => {
+                    self.buffered += chunk.len();
+                    self.pending.push_back(chunk);
+                }
+                Ok(Err(e)) => {
+                    self.error = Some(e);
+                    self.done = true;
+                }
+                Err(_) => self.done = true,
+            }
+        }
+    }
+}
+
+impl Read for Transfer {
+    /// Reads from the chunks read ahead; 0 at the end of the transfer
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            self.fill(self.prebuffer.max(1));
+        }
+        let Some(chunk) = self.pending.front() else {
+            return match self.error.take() {
+                Some(e) => Err(e),
+                None => Ok(0),
+            };
+        };
+        let count = buf.len().min(chunk.len() - self.consumed);
+        buf[..count].copy_from_slice(&chunk[self.consumed..self.consumed + count]);
+        self.consumed += count;
+        self.buffered -= count;
+        if self.consumed == chunk.len() {
+            self.pending.pop_front();
+            self.consumed = 0;
+        }
+        Ok(count)
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Hands over what curl writes in chunks, until it's done or nobody reads
+fn read_ahead(
+    mut reader: BufReader<ChildStdout>,
+    chunks: std::sync::mpsc::SyncSender<std::io::Result<Vec<u8>>>,
+) {
+    loop {
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(count) => {
+                chunk.truncate(count);
+                if chunks.send(Ok(chunk)).is_err() {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                let _ = chunks.send(Err(e));
+                break;
+            }
+        }
+    }
+}
+
+/// Reads the headers curl writes before the body, skipping those of redirects
+fn read_response(reader: &mut impl BufRead) -> Result<Response, String> {
+    loop {
+        let mut response = Response {
+            status: 0,
+            length: None,
+            ranges: false,
+            metaint: None,
+            name: None,
+            genre: None,
+            bitrate_kbps: None,
+        };
+        let mut content_length = None;
+        let mut status_line = true;
+        loop {
+            let mut line = String::new();
+            let read = reader.read_line(&mut line).map_err(|e| format!("{}", e))?;
+            if read == 0 {
+                return Err("no response".to_string());
+            }
+            let line = line.trim_end();
+            if status_line {
+                // `HTTP/1.1 206 Partial Content`, or `ICY 200 OK` from SHOUTcast
+                response.status = line
+                    .split_whitespace()
+                    .nth(1)
+                    .and_then(|status| status.parse().ok())
+                    .ok_or_else(|| format!("invalid response: {}", line))?;
+                status_line = false;
+                continue;
+            }
+            if line.is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse().ok(),
+                // `bytes 0-999/1000`
+                "content-range" => {
+                    response.length = value
+                        .rsplit_once('/')
+                        .and_then(|(_, total)| total.parse().ok())
+                }
+                "accept-ranges" => response.ranges = value.eq_ignore_ascii_case("bytes"),
+                "icy-metaint" => response.metaint = value.parse().ok().filter(|&bytes| bytes > 0),
+                "icy-name" => {
+                    response.name = Some(value.to_string()).filter(|name| !name.is_empty())
+                }
+                "icy-genre" => {
+                    response.genre = Some(value.to_string()).filter(|genre| !genre.is_empty())
+                }
+                // Sometimes `128,128` for several qualities
+                "icy-br" => {
+                    response.bitrate_kbps =
+                        value.split(',').next().and_then(|kbps| kbps.parse().ok())
+                }
+                _ => {}
+            }
+        }
+        // Informational and redirect responses come before the real one
+        if (100..200).contains(&response.status) || (300..400).contains(&response.status) {
+            continue;
+        }
+        if response.status != 206 {
+            response.length = content_length;
+        }
+        // Interleaved metadata makes the length meaningless, and these are
+        // endless anyway
+        if response.metaint.is_some() {
+            response.length = None;
+            response.ranges = false;
+        }
+        return Ok(response);
+    }
+}
+
+impl Read for HttpSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.transfer.is_none() {
+            let (transfer, response) =
+                Transfer::start(&self.url, Some(self.position)).map_err(std::io::Error::other)?;
+            self.transfer = Some(transfer);
+            self.metaint = response.metaint;
+            self.until_metadata = response.metaint.unwrap_or(0);
+        }
+        if let Some(metaint) = self.metaint.filter(|_| self.until_metadata == 0) {
+            self.read_metadata()?;
+            self.until_metadata = metaint;
+        }
+        let limit = match self.metaint {
+            Some(_) => buf.len().min(self.until_metadata),
+            None => buf.len(),
+        };
+        let count = match &mut self.transfer {
+            Some(transfer) => transfer.read(&mut buf[..limit])?,
+            None => 0,
+        };
+        if self.metaint.is_some() {
+            self.until_metadata -= count;
+        }
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for HttpSource {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self
+                .length
+                .ok_or_else(|| std::io::Error::other("the length of the stream is unknown"))?
+                .checked_add_signed(offset),
+        };
+        let target = target.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before the start")
+        })?;
+        if target == self.position {
+            return Ok(target);
+        }
+        // A little ahead is read through, as that's quicker than starting over
+        if target > self.position && target - self.position <= SKIP_LIMIT && self.transfer.is_some()
+        {
+            let skip = target - self.position;
+            std::io::copy(&mut (&mut *self).take(skip), &mut std::io::sink())?;
+            if self.position == target {
+                return Ok(target);
+            }
+        }
+        if !self.ranges {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "the server doesn't allow seeking",
+            ));
+        }
+        // The next read starts a transfer from here
+        self.transfer = None;
+        self.position = target;
+        Ok(target)
+    }
+}
+
+impl MediaSource for HttpSource {
+    fn is_seekable(&self) -> bool {
+        self.ranges
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_curl_is_named() {
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert_eq!(
+            curl_error(missing),
+            "curl not found; it has to be installed"
+        );
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(curl_error(denied).starts_with("failed to run curl: "));
+    }
+
+    #[test]
+    fn curl_needs_the_feature() {
+        assert_eq!(curl().is_ok(), cfg!(feature = "curl"));
+    }
+
+    fn response(headers: &str) -> Result<Response, String> {
+        read_response(&mut headers.replace('\n', "\r\n").as_bytes())
+    }
+
+    #[test]
+    fn reads_the_final_response_after_redirects() {
+        let response = response(
+            "HTTP/1.1 302 Found\n\
+             Location: https://cdn.example/a.mp3\n\
+             Content-Length: 0\n\
+             \n\
+             HTTP/2 200\n\
+             content-length: 5000\n\
+             accept-ranges: bytes\n\
+             \n",
+        )
+        .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.length, Some(5000));
+        assert!(response.ranges);
+        assert_eq!(response.metaint, None);
+    }
+
+    #[test]
+    fn takes_the_length_of_ranges_from_content_range() {
+        let response = response(
+            "HTTP/1.1 100 Continue\n\
+             \n\
+             HTTP/1.1 206 Partial Content\n\
+             Content-Range: bytes 1000-4999/5000\n\
+             Content-Length: 4000\n\
+             \n",
+        )
+        .unwrap();
+        assert_eq!(response.status, 206);
+        assert_eq!(response.length, Some(5000));
+    }
+
+    #[test]
+    fn reads_icy_headers() {
+        let response = response(
+            "ICY 200 OK\n\
+             icy-name: Some Radio\n\
+             icy-genre:\n\
+             icy-br: 128,64\n\
+             icy-metaint: 16000\n\
+             Content-Length: 100\n\
+             Accept-Ranges: bytes\n\
+             \n",
+        )
+        .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.name.as_deref(), Some("Some Radio"));
+        assert_eq!(response.genre, None);
+        assert_eq!(response.bitrate_kbps, Some(128));
+        assert_eq!(response.metaint, Some(16000));
+        // Endless, with metadata in between
+        assert_eq!(response.length, None);
+        assert!(!response.ranges);
+    }
+
+    #[test]
+    fn refuses_broken_responses() {
+        assert_eq!(response("").err().as_deref(), Some("no response"));
+        assert_eq!(
+            response("garbage\n\n").err().as_deref(),
+            Some("invalid response: garbage")
+        );
+        assert_eq!(
+            response("HTTP/1.1 301 Moved\n\n").err().as_deref(),
+            Some("no response")
+        );
+    }
+
+    #[test]
+    fn reads_stream_titles() {
+        assert_eq!(
+            stream_title("StreamTitle='Artist - Title';StreamUrl='';").as_deref(),
+            Some("Artist - Title")
+        );
+        assert_eq!(
+            stream_title("StreamTitle='It's Here';").as_deref(),
+            Some("It's Here")
+        );
+        assert_eq!(
+            stream_title("StreamTitle='Cut off'").as_deref(),
+            Some("Cut off")
+        );
+        assert_eq!(stream_title("StreamUrl='x';"), None);
+    }
+
+    #[test]
+    fn resolves_urls() {
+        let base = "https://example.com/live/stream.m3u8?token=1";
+        assert_eq!(
+            resolve_url(base, "seg1.ts"),
+            "https://example.com/live/seg1.ts"
+        );
+        assert_eq!(
+            resolve_url(base, "/other/seg1.ts"),
+            "https://example.com/other/seg1.ts"
+        );
+        assert_eq!(
+            resolve_url(base, "//cdn.example/seg1.ts"),
+            "https://cdn.example/seg1.ts"
+        );
+        assert_eq!(
+            resolve_url(base, "http://elsewhere/seg1.ts"),
+            "http://elsewhere/seg1.ts"
+        );
+        assert_eq!(
+            resolve_url("http://example.com", "seg1.ts"),
+            "http://example.com/seg1.ts"
+        );
+        assert_eq!(
+            resolve_url("http://example.com/", "a/b.ts"),
+            "http://example.com/a/b.ts"
+        );
+    }
+
+    #[test]
+    fn finds_url_extensions() {
+        assert_eq!(url_extension("https://a.example/x/song.mp3"), Some("mp3"));
+        assert_eq!(
+            url_extension("https://a.example/list.M3U8?token=a.b#c.d"),
+            Some("M3U8")
+        );
+        assert_eq!(url_extension("https://a.example.com/stream"), None);
+        assert_eq!(url_extension("https://a.example.com"), None);
+        assert_eq!(url_extension("https://a.example/dir.d/file"), None);
+        assert_eq!(url_extension("https://a.example/file."), None);
+    }
+
+    #[test]
+    fn tells_urls_from_files() {
+        assert!(is_url(Path::new("https://example.com/a.mp3")));
+        assert!(is_url(Path::new("HTTP://example.com/a.mp3")));
+        assert!(!is_url(Path::new("ftp://example.com/a.mp3")));
+        assert!(!is_url(Path::new("/music/a.mp3")));
+    }
+}
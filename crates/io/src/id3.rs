@@ -0,0 +1,118 @@
+// Reading ID3v2 frames symphonia skips over, such as SYLT and CHAP
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// The frames of an ID3v2.3 or 2.4 tag
+pub(crate) struct Id3Tag {
+    pub version: u8,
+    /// Frames as (id, body), leaving out any the tag can't be read without
+    /// decompressing, decrypting or undoing unsynchronisation
+    pub frames: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl Id3Tag {
+    /// Reads the tag at the start of the file, if it has one
+    pub fn read(path: &Path) -> Option<Id3Tag> {
+        let mut file = File::open(path).ok()?;
+        let mut header = [0u8; 10];
+        file.read_exact(&mut header).ok()?;
+        let (version, flags) = (header[3], header[5]);
+        // Unsynchronised tags would need their bytes restored first
+        if &header[..3] != b"ID3" || !(3..=4).contains(&version) || flags & 0x80 != 0 {
+            return None;
+        }
+        let mut tag = vec![0u8; syncsafe(&header[6..10])];
+        file.read_exact(&mut tag).ok()?;
+        let mut frames = &tag[..];
+        if flags & 0x40 != 0 {
+            // The extended header's size counts itself in 2.4 only
+            let size = frames.get(..4)?;
+            let len = match version {
+                4 => syncsafe(size),
+                _ => 4 + u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize,
+            };
+            frames = frames.get(len..)?;
+        }
+        Some(Id3Tag {
+            version,
+            frames: read_frames(frames, version),
+        })
+    }
+}
+
+/// The frames laid end to end in `data`, up to the padding; also used for
+/// the subframes of `CHAP` frames
+pub(crate) fn read_frames(mut data: &[u8], version: u8) -> Vec<([u8; 4], Vec<u8>)> {
+    let mut frames = Vec::new();
+    while data.len() >= 10 && data[0] != 0 {
+        let size = match version {
+            4 => syncsafe(&data[4..8]),
+            _ => u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize,
+        };
+        let Some(body) = data.get(10..10 + size) else {
+            break;
+        };
+        let format_flags = data[9];
+        let skipped = match version {
+            4 => format_flags & 0x0e != 0,
+            _ => format_flags & 0xc0 != 0,
+        };
+        // 2.4 may put the data length before the body
+        let body = match version == 4 && format_flags & 0x01 != 0 {
+            true => body.get(4..),
+            false => Some(body),
+        };
+        if let (false, Some(body)) = (skipped, body) {
+            frames.push(([data[0], data[1], data[2], data[3]], body.to_vec()));
+        }
+        data = &data[10 + size..];
+    }
+    frames
+}
+
+/// A NUL-terminated string in an ID3v2 text encoding, and what follows it
+pub(crate) fn read_text(data: &[u8], encoding: u8) -> Option<(String, &[u8])> {
+    match encoding {
+        0 | 3 => {
+            let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+            let text = match encoding {
+                0 => data[..end].iter().map(|&b| b as char).collect(),
+                _ => String::from_utf8_lossy(&data[..end]).into_owned(),
+            };
+            Some((text, data.get(end + 1..).unwrap_or_default()))
+        }
+        1 | 2 => {
+            let end = data
+                .chunks_exact(2)
+                .position(|unit| unit == [0, 0])
+                .map_or(data.len() & !1, |units| units * 2);
+            let units = &data[..end];
+            // UTF-16 with a byte order mark, or big-endian without one
+            let (little_endian, units) = match units {
+                [0xff, 0xfe, rest @ ..] => (true, rest),
+                [0xfe, 0xff, rest @ ..] => (false, rest),
+                _ => (false, units),
+            };
+            let units: Vec<u16> = units
+                .chunks_exact(2)
+                .map(|unit| match little_endian {
+                    true => u16::from_le_bytes([unit[0], unit[1]]),
+                    false => u16::from_be_bytes([unit[0], unit[1]]),
+                })
+                .collect();
+            Some((
+                String::from_utf16_lossy(&units),
+                data.get(end + 2..).unwrap_or_default(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, &b| (size << 7) | (b & 0x7f) as usize)
+}
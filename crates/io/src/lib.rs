@@ -1,35 +1,183 @@
 // IO crate
 
+pub mod chapters;
+pub mod cue;
+#[cfg(feature = "ffmpeg")]
+mod ffmpeg;
+pub mod hls;
+pub mod http;
+mod id3;
+pub mod loops;
+pub mod lyrics;
+pub mod playlist;
+pub mod podcast;
+pub mod raw;
+pub mod scan;
+pub mod stdin;
+
 use std::fs::File;
+use std::time::Duration;
 
 use symphonia::core::{
-    codecs::{Decoder, DecoderOptions},
-    formats::{FormatOptions, FormatReader},
-    io::MediaSourceStream,
-    meta::MetadataOptions,
+    audio::SampleBuffer,
+    codecs::{CodecDescriptor, CodecParameters, Decoder, DecoderOptions},
+    errors::Error,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo, Track},
+    io::{MediaSource, MediaSourceStream},
+    meta::{MetadataOptions, StandardTagKey, StandardVisualKey, Tag, Visual},
     probe::Hint,
     units::TimeBase,
 };
 
+pub use chapters::Chapter;
+pub use loops::LoopPoints;
+pub use lyrics::{LyricLine, Lyrics};
+pub use raw::{PcmEncoding, RawFormat};
+
 /// Represents an opened audio file with all necessary information for playback and analysis
 pub struct AudioFile {
-    pub format: Box<dyn FormatReader>,
-    pub decoder: Box<dyn Decoder>,
+    reader: Reader,
     pub track_id: u32,
     pub time_base: TimeBase,
     pub sample_rate: u32,
     pub channels: u8,
+    /// Bit depth of the stored samples, when the codec has one
+    pub bits_per_sample: Option<u32>,
+    /// Length of the track in frames, when the container states it
+    pub frames: Option<u64>,
+    /// Size of the whole file in bytes
+    pub file_size: u64,
+    pub tags: Vec<Tag>,
+    /// The audio tracks of the file, of which the one with `track_id` plays.
+    /// Video containers can have several, one per language.
+    pub audio_tracks: Vec<AudioTrack>,
+    /// Embedded pictures such as cover art
+    pub visuals: Vec<Visual>,
+    pub chapters: Vec<Chapter>,
+    /// What a radio station says about itself and what it plays, for
+    /// Icecast and SHOUTcast streams
+    pub stream: Option<http::StreamMetadata>,
+    /// Frames still to drop after a seek landed before the requested one
+    skip_frames: u64,
+    /// Bytes of the packets decoded so far
+    bytes_decoded: u64,
+}
+
+/// An audio track of a container
+#[derive(Clone)]
+pub struct AudioTrack {
+    pub id: u32,
+    /// The codec, when symphonia knows it
+    pub codec: Option<&'static CodecDescriptor>,
+    pub sample_rate: u32,
+    pub channels: Option<u8>,
+    pub language: Option<String>,
+}
+
+impl AudioTrack {
+    /// The track, if it's an audio track
+    fn new(track: &Track) -> Option<Self> {
+        let codec_params = &track.codec_params;
+        Some(AudioTrack {
+            id: track.id,
+            codec: symphonia::default::get_codecs().get_codec(codec_params.codec),
+            sample_rate: codec_params.sample_rate?,
+            channels: channel_count(codec_params),
+            language: track.language.clone(),
+        })
+    }
+}
+
+/// Channels of a track, which Matroska only gives as a layout
+fn channel_count(codec_params: &CodecParameters) -> Option<u8> {
+    let channels = codec_params.channels.or_else(|| {
+        codec_params
+            .channel_layout
+            .map(|layout| layout.into_channels())
+    })?;
+    Some(channels.count() as u8)
+}
+
+/// Where the samples of an [`AudioFile`] come from
+enum Reader {
+    /// A container and codec symphonia reads
+    Symphonia {
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn Decoder>,
+    },
+    /// Headerless PCM, read as it was said to be
+    Raw(raw::RawReader),
+    /// What symphonia can't read, decoded by ffmpeg into headerless PCM
+    #[cfg(feature = "ffmpeg")]
+    Ffmpeg(raw::RawReader),
+}
+
+/// The bytes of a file, URL or stdin, ready to be read
+struct Opened {
+    source: Box<dyn MediaSource>,
+    /// Size of the whole file in bytes, or 0 when unknown
+    file_size: u64,
+    /// Extension of the format, for picking the decoder
+    extension: Option<String>,
+    stream: Option<http::StreamMetadata>,
+}
+
+impl Opened {
+    /// Opens a file. An `http://` or `https://` URL is streamed from the
+    /// server instead, and one of an `.m3u8` playlist played as an HLS
+    /// stream. `-` reads stdin.
+    fn open(path: &std::path::PathBuf) -> Result<Self, String> {
+        let url = path.to_str().filter(|_| http::is_url(path));
+        let mut stream = None;
+        let mut extension = match url {
+            Some(url) => http::url_extension(url),
+            None => path.extension().and_then(|e| e.to_str()),
+        };
+        let (source, file_size): (Box<dyn MediaSource>, u64) = match url {
+            Some(url) if hls::is_hls(url) => {
+                let source = hls::HlsSource::open(url)?;
+                extension = source.extension();
+                (Box::new(source), 0)
+            }
+            Some(url) => {
+                let source = http::HttpSource::open(url)?;
+                let length = source.byte_len().unwrap_or(0);
+                stream = source.metadata();
+                (Box::new(source), length)
+            }
+            None if stdin::is_stdin(path) => (Box::new(stdin::StdinSource::open()), 0),
+            None => {
+                let file: File =
+                    File::open(path).map_err(|e| format!("failed to open media: {}", e))?;
+                let file_size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+                (Box::new(file), file_size)
+            }
+        };
+        Ok(Opened {
+            source,
+            file_size,
+            extension: extension.map(str::to_string),
+            stream,
+        })
+    }
 }
 
 impl AudioFile {
-    /// Opens an audio file and returns an AudioFile struct containing decoder and format info
+    /// Opens an audio file and returns an AudioFile struct containing decoder and format info.
+    /// An `http://` or `https://` URL is streamed from the server instead, and
+    /// one of an `.m3u8` playlist played as an HLS stream. `-` reads stdin.
     pub fn open(path: &std::path::PathBuf) -> Result<Self, String> {
-        let file: File = File::open(path).map_err(|e| format!("failed to open media: {}", e))?;
-        let mss: MediaSourceStream = MediaSourceStream::new(Box::new(file), Default::default());
+        let Opened {
+            source,
+            file_size,
+            extension,
+            stream,
+        } = Opened::open(path)?;
+        let mss: MediaSourceStream = MediaSourceStream::new(source, Default::default());
 
         // Create a hint for which decoder to use based on the file's extension
         let mut hint: Hint = Hint::new();
-        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        if let Some(ext) = &extension {
             hint.with_extension(ext); // e.g., "mp3" or "wav"
         }
 
@@ -39,42 +187,333 @@ impl AudioFile {
         let decoder_opts: DecoderOptions = Default::default();
 
         // Probe the media source stream for a format.
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &format_opts, &metadata_opts)
-            .map_err(|e| format!("failed to probe media: {}", e))?;
+        let probed = match symphonia::default::get_probe().format(
+            &hint,
+            mss,
+            &format_opts,
+            &metadata_opts,
+        ) {
+            Ok(probed) => probed,
+            Err(e) => return AudioFile::fall_back(path, format!("failed to probe media: {}", e)),
+        };
 
         // Get the format reader yielded by the probe operation.
-        let format = probed.format;
+        let mut format = probed.format;
+
+        // Collect tags found before the container (e.g. ID3v2) and inside it.
+        let mut tags: Vec<Tag> = Vec::new();
+        let mut visuals: Vec<Visual> = Vec::new();
+        let mut probed_metadata = probed.metadata;
+        if let Some(revision) = probed_metadata.get().as_ref().and_then(|m| m.current()) {
+            tags.extend(revision.tags().iter().cloned());
+            visuals.extend(revision.visuals().iter().cloned());
+        }
+        if let Some(revision) = format.metadata().current() {
+            tags.extend(revision.tags().iter().cloned());
+            visuals.extend(revision.visuals().iter().cloned());
+        }
 
-        // Get the default track.
+        // Video containers have other tracks too, so take the first audio
+        // track symphonia can decode, or else the first audio track.
+        let audio_tracks: Vec<AudioTrack> =
+            format.tracks().iter().filter_map(AudioTrack::new).collect();
+        let track_id = audio_tracks
+            .iter()
+            .find(|track| track.codec.is_some())
+            .or(audio_tracks.first())
+            .ok_or("no audio tracks found in media")?
+            .id;
         let track = format
-            .default_track()
+            .tracks()
+            .iter()
+            .find(|track| track.id == track_id)
             .ok_or("no audio tracks found in media")?;
 
         // Get codec parameters
         let codec_params = &track.codec_params;
         let sample_rate = codec_params.sample_rate.ok_or("sample rate not found")?;
-        let channels = codec_params
-            .channels
-            .ok_or("channel count not found")?
-            .count() as u8;
+        let channels = channel_count(codec_params).ok_or("channel count not found")?;
         let time_base = codec_params.time_base.ok_or("time base not found")?;
+        let bits_per_sample = codec_params.bits_per_sample;
+        // Stated in the time base, which Matroska doesn't count in frames
+        let frames = codec_params.n_frames.map(|ts| {
+            (ts as u128 * time_base.numer as u128 * sample_rate as u128 / time_base.denom as u128)
+                as u64
+        });
 
         // Create a decoder for the track.
-        let decoder = symphonia::default::get_codecs()
-            .make(codec_params, &decoder_opts)
-            .map_err(|e| format!("failed to create decoder: {}", e))?;
+        let decoder = match symphonia::default::get_codecs().make(codec_params, &decoder_opts) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                return AudioFile::fall_back(path, format!("failed to create decoder: {}", e))
+            }
+        };
 
-        // Store the track identifier, we'll use it to filter packets.
-        let track_id = track.id;
+        let chapters = chapters::read_chapters(path, &tags, format.cues(), sample_rate);
 
         Ok(AudioFile {
-            format,
-            decoder,
+            reader: Reader::Symphonia { format, decoder },
             track_id,
             time_base,
             sample_rate,
             channels,
+            bits_per_sample,
+            frames,
+            file_size,
+            tags,
+            audio_tracks,
+            visuals,
+            chapters,
+            stream,
+            skip_frames: 0,
+            bytes_decoded: 0,
+        })
+    }
+
+    /// Opens headerless PCM, such as a `.pcm` file or piped-in samples, which
+    /// holds what `format` says without symphonia having to recognize it
+    pub fn open_raw(path: &std::path::PathBuf, format: RawFormat) -> Result<Self, String> {
+        let opened = Opened::open(path)?;
+        let frame_bytes = format.frame_bytes() as u64;
+        Ok(AudioFile {
+            reader: Reader::Raw(raw::RawReader::new(opened.source, format)),
+            track_id: 0,
+            time_base: TimeBase::new(1, format.sample_rate),
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            bits_per_sample: Some(format.encoding.bytes() as u32 * 8),
+            frames: (opened.file_size > 0).then(|| opened.file_size / frame_bytes),
+            file_size: opened.file_size,
+            tags: Vec::new(),
+            audio_tracks: Vec::new(),
+            visuals: Vec::new(),
+            chapters: Vec::new(),
+            stream: opened.stream,
+            skip_frames: 0,
+            bytes_decoded: 0,
         })
     }
+
+    /// Decodes `path` with ffmpeg, since symphonia couldn't and failed with
+    /// `error`. Stdin can't be read again from the start for it.
+    #[cfg(feature = "ffmpeg")]
+    fn fall_back(path: &std::path::PathBuf, error: String) -> Result<Self, String> {
+        let Some(input) = path.to_str().filter(|_| !stdin::is_stdin(path)) else {
+            return Err(error);
+        };
+        let probed = ffmpeg::probe(input).map_err(|e| format!("{}; {}", error, e))?;
+        let format = probed.format;
+        let source = ffmpeg::FfmpegSource::open(input, format)?;
+        let file_size = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+        let frames = probed
+            .duration
+            .map(|duration| (duration.as_secs_f64() * format.sample_rate as f64).round() as u64);
+        let chapters = chapters::read_chapters(path, &probed.tags, &[], format.sample_rate);
+        Ok(AudioFile {
+            reader: Reader::Ffmpeg(raw::RawReader::new(Box::new(source), format)),
+            track_id: 0,
+            time_base: TimeBase::new(1, format.sample_rate),
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            bits_per_sample: None,
+            frames,
+            file_size,
+            tags: probed.tags,
+            audio_tracks: Vec::new(),
+            visuals: Vec::new(),
+            chapters,
+            stream: None,
+            skip_frames: 0,
+            bytes_decoded: 0,
+        })
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    fn fall_back(_path: &std::path::PathBuf, error: String) -> Result<Self, String> {
+        Err(error)
+    }
+
+    /// What a radio stream last said it's playing
+    pub fn stream_title(&self) -> Option<String> {
+        self.stream.as_ref()?.title()
+    }
+
+    /// Length of the track, when the container states it
+    pub fn duration(&self) -> Option<Duration> {
+        self.frames
+            .map(|frames| Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64))
+    }
+
+    /// Names of the track's codec, e.g. `flac` and `Free Lossless Audio Codec`
+    pub fn codec(&self) -> Option<&'static CodecDescriptor> {
+        let codec = match &self.reader {
+            Reader::Symphonia { decoder, .. } => decoder.codec_params().codec,
+            Reader::Raw(reader) => reader.format().encoding.codec(),
+            // Whatever it was, ffmpeg knows it and symphonia doesn't
+            #[cfg(feature = "ffmpeg")]
+            Reader::Ffmpeg(_) => return None,
+        };
+        symphonia::default::get_codecs().get_codec(codec)
+    }
+
+    /// Bits per second over the whole file, tags and cover art included
+    pub fn average_bitrate(&self) -> Option<u64> {
+        let seconds = self.duration()?.as_secs_f64();
+        (seconds > 0.0).then(|| (self.file_size as f64 * 8.0 / seconds).round() as u64)
+    }
+
+    /// Bytes of compressed data decoded so far. Counting them over a stretch
+    /// of frames gives the bitrate of that stretch.
+    pub fn bytes_decoded(&self) -> u64 {
+        self.bytes_decoded
+    }
+
+    /// The symphonia format reader, unless the samples are headerless PCM or
+    /// come from ffmpeg
+    pub fn format(&self) -> Option<&dyn FormatReader> {
+        match &self.reader {
+            Reader::Symphonia { format, .. } => Some(format.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn format_mut(&mut self) -> Option<&mut (dyn FormatReader + 'static)> {
+        match &mut self.reader {
+            Reader::Symphonia { format, .. } => Some(format.as_mut()),
+            _ => None,
+        }
+    }
+
+    /// The symphonia decoder of the track, unless the samples are headerless
+    /// PCM or come from ffmpeg
+    pub fn decoder(&self) -> Option<&dyn Decoder> {
+        match &self.reader {
+            Reader::Symphonia { decoder, .. } => Some(decoder.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn decoder_mut(&mut self) -> Option<&mut (dyn Decoder + 'static)> {
+        match &mut self.reader {
+            Reader::Symphonia { decoder, .. } => Some(decoder.as_mut()),
+            _ => None,
+        }
+    }
+
+    /// The front cover, or else the first embedded picture
+    pub fn cover(&self) -> Option<&Visual> {
+        self.visuals
+            .iter()
+            .find(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+            .or(self.visuals.first())
+    }
+
+    /// Returns the value of the first tag with the given standard key
+    pub fn tag(&self, key: StandardTagKey) -> Option<String> {
+        self.tags
+            .iter()
+            .find(|tag| tag.std_key == Some(key))
+            // RIFF INFO strings keep their NUL terminator
+            .map(|tag| tag.value.to_string().trim_end_matches('\0').to_string())
+    }
+
+    /// Parses a numeric tag such as a track number, accepting `3` as well as `3/12`
+    pub fn tag_number(&self, key: StandardTagKey) -> Option<u32> {
+        let value = self.tag(key)?;
+        value.split('/').next()?.trim().parse().ok()
+    }
+    /// Seeks so that decoding resumes exactly at `frame`, counted at the
+    /// file's sample rate. Formats can only seek to packet boundaries, so
+    /// the frames between the one landed on and the one asked for are
+    /// decoded and dropped.
+    pub fn seek(&mut self, frame: u64) -> Result<(), String> {
+        let (format, decoder) = match &mut self.reader {
+            Reader::Symphonia { format, decoder } => (format, decoder),
+            Reader::Raw(reader) => return reader.seek(frame),
+            #[cfg(feature = "ffmpeg")]
+            Reader::Ffmpeg(reader) => return reader.seek(frame),
+        };
+        // Frames to the track's time base and back
+        let rate = self.sample_rate as u128;
+        let (numer, denom) = (self.time_base.numer as u128, self.time_base.denom as u128);
+        let ts = (frame as u128 * denom / (rate * numer)) as u64;
+
+        let seeked = format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::TimeStamp {
+                    ts,
+                    track_id: self.track_id,
+                },
+            )
+            .map_err(|e| format!("failed to seek: {}", e))?;
+        decoder.reset();
+
+        let actual = (seeked.actual_ts as u128 * numer * rate / denom) as u64;
+        self.skip_frames = frame.saturating_sub(actual);
+        Ok(())
+    }
+
+    /// Decodes the next packet of the selected track into interleaved f32 samples.
+    /// Returns `Ok(None)` once the end of the stream has been reached.
+    pub fn next_samples(&mut self) -> Result<Option<Vec<f32>>, String> {
+        let (format, decoder) = match &mut self.reader {
+            Reader::Symphonia { format, decoder } => (format, decoder),
+            Reader::Raw(reader) => {
+                let samples = reader.next_samples()?;
+                let bytes = reader.format().encoding.bytes();
+                self.bytes_decoded +=
+                    samples.as_ref().map_or(0, |samples| samples.len() * bytes) as u64;
+                return Ok(samples);
+            }
+            #[cfg(feature = "ffmpeg")]
+            Reader::Ffmpeg(reader) => return reader.next_samples(),
+        };
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(None);
+                }
+                Err(Error::ResetRequired) => return Ok(None),
+                Err(e) => return Err(format!("failed to read packet: {}", e)),
+            };
+
+            // Skip packets belonging to other tracks.
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            self.bytes_decoded += packet.data.len() as u64;
+            match decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let mut buffer: SampleBuffer<f32> =
+                        SampleBuffer::new(decoded.capacity() as u64, *decoded.spec());
+                    buffer.copy_interleaved_ref(decoded);
+                    let mut samples = buffer.samples();
+                    if self.skip_frames > 0 {
+                        let frames = samples.len() / self.channels as usize;
+                        let skip = self.skip_frames.min(frames as u64) as usize;
+                        self.skip_frames -= skip as u64;
+                        samples = &samples[skip * self.channels as usize..];
+                        if samples.is_empty() {
+                            continue;
+                        }
+                    }
+                    return Ok(Some(samples.to_vec()));
+                }
+                // A corrupt packet is not fatal, move on to the next one.
+                Err(Error::DecodeError(_)) => continue,
+                Err(e) => return Err(format!("failed to decode packet: {}", e)),
+            }
+        }
+    }
+    /// Decodes the rest of the track into one interleaved buffer
+    pub fn read_all(&mut self) -> Result<Vec<f32>, String> {
+        let mut samples = Vec::new();
+        while let Some(packet) = self.next_samples()? {
+            samples.extend_from_slice(&packet);
+        }
+        Ok(samples)
+    }
 }
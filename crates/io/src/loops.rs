@@ -0,0 +1,86 @@
+// Loop points of game music and ambience files
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use symphonia::core::meta::Tag;
+
+/// Bytes of a `smpl` chunk before its list of loops
+const SMPL_HEADER: usize = 36;
+/// Bytes of each loop in a `smpl` chunk
+const SMPL_LOOP: usize = 24;
+
+/// The stretch of a file meant to be played over and over, in frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoopPoints {
+    pub start: u64,
+    /// Frame at which playback goes back to the start, the end of the file
+    /// when `None`
+    pub end: Option<u64>,
+}
+
+impl LoopPoints {
+    /// Finds the loop points of the file at `path`: the first loop of a WAV
+    /// `smpl` chunk, else `LOOPSTART` with `LOOPLENGTH` or `LOOPEND` tags as
+    /// game engines such as RPG Maker use them
+    pub fn read(path: &Path, tags: &[Tag]) -> Option<LoopPoints> {
+        read_smpl(path).or_else(|| LoopPoints::from_tags(tags))
+    }
+
+    /// Loop points from `LOOPSTART` and `LOOPLENGTH` or `LOOPEND` tags,
+    /// counted in frames
+    pub fn from_tags(tags: &[Tag]) -> Option<LoopPoints> {
+        let frames = |key: &str| {
+            tags.iter()
+                .find(|tag| tag.key.eq_ignore_ascii_case(key))
+                .and_then(|tag| tag.value.to_string().trim().parse::<u64>().ok())
+        };
+        let start = frames("LOOPSTART")?;
+        let end = match frames("LOOPLENGTH") {
+            Some(length) => Some(start + length),
+            None => frames("LOOPEND"),
+        };
+        Some(LoopPoints {
+            start,
+            end: end.filter(|end| *end > start),
+        })
+    }
+}
+
+/// The first loop of the `smpl` chunk of a WAV file
+fn read_smpl(path: &Path) -> Option<LoopPoints> {
+    let mut file = File::open(path).ok()?;
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header).ok()?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+        return None;
+    }
+    let mut chunk = [0u8; 8];
+    while file.read_exact(&mut chunk).is_ok() {
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+        if &chunk[..4] != b"smpl" {
+            // Chunks are padded to an even length
+            file.seek(SeekFrom::Current((size + (size & 1)) as i64))
+                .ok()?;
+            continue;
+        }
+        let mut smpl = vec![0u8; (size as usize).min(SMPL_HEADER + SMPL_LOOP)];
+        file.read_exact(&mut smpl).ok()?;
+        let field = |offset: usize| {
+            let bytes = smpl.get(offset..offset + 4)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64)
+        };
+        if field(28)? == 0 {
+            return None;
+        }
+        // Each loop is a cue point ID, a type, then its first and last frames
+        let start = field(SMPL_HEADER + 8)?;
+        let last = field(SMPL_HEADER + 12)?;
+        return Some(LoopPoints {
+            start,
+            end: (last >= start).then_some(last + 1),
+        });
+    }
+    None
+}
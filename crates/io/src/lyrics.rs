@@ -0,0 +1,252 @@
+// Time-synced lyrics from LRC files and tags
+
+use std::path::Path;
+use std::time::Duration;
+
+use symphonia::core::meta::{StandardTagKey, Tag};
+
+use crate::chapters::parse_timestamp;
+use crate::id3::{read_text, Id3Tag};
+
+/// SYLT timestamps in milliseconds; the other format counts MPEG frames
+const SYLT_MILLISECONDS: u8 = 2;
+
+/// A line of lyrics and when it is sung
+#[derive(Clone, Debug, PartialEq)]
+pub struct LyricLine {
+    pub start: Duration,
+    pub text: String,
+}
+
+/// Lines of synced lyrics, in order of their start
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lyrics {
+    pub lines: Vec<LyricLine>,
+}
+
+impl Lyrics {
+    /// Finds the lyrics of the file at `path`: a `.lrc` file next to it with
+    /// the same name, else an ID3v2 `SYLT` frame, else LRC text in a
+    /// `LYRICS` tag. Lyrics without timestamps are left out.
+    pub fn read(path: &Path, tags: &[Tag]) -> Option<Lyrics> {
+        let sidecar = ["lrc", "LRC"]
+            .iter()
+            .find_map(|ext| std::fs::read(path.with_extension(ext)).ok());
+        let lyrics = match sidecar {
+            Some(bytes) => Lyrics::parse_lrc(&String::from_utf8_lossy(&bytes)),
+            None => read_sylt(path).unwrap_or_default(),
+        };
+        let lyrics = if lyrics.lines.is_empty() {
+            tags.iter()
+                .filter(|tag| {
+                    tag.std_key == Some(StandardTagKey::Lyrics)
+                        || ["LYRICS", "SYNCEDLYRICS", "UNSYNCEDLYRICS"]
+                            .iter()
+                            .any(|key| tag.key.eq_ignore_ascii_case(key))
+                })
+                .map(|tag| Lyrics::parse_lrc(&tag.value.to_string()))
+                .find(|lyrics| !lyrics.lines.is_empty())?
+        } else {
+            lyrics
+        };
+        Some(lyrics)
+    }
+
+    /// Parses LRC text: lines led by one or more `[mm:ss.xx]` timestamps,
+    /// moved by an `[offset:ms]` tag if there is one. Other tags such as
+    /// `[ar:Artist]` and enhanced LRC word timings `<mm:ss.xx>` are dropped.
+    pub fn parse_lrc(text: &str) -> Lyrics {
+        let mut offset_ms = 0i64;
+        let mut lines = Vec::new();
+        for line in text.trim_start_matches('\u{feff}').lines() {
+            let mut rest = line.trim();
+            let mut starts = Vec::new();
+            while let Some(tag) = rest.strip_prefix('[') {
+                let Some(end) = tag.find(']') else {
+                    break;
+                };
+                let (tag, after) = (&tag[..end], &tag[end + 1..]);
+                if let Some(value) = tag.strip_prefix("offset:") {
+                    offset_ms = value.trim().parse().unwrap_or(offset_ms);
+                } else if let Some(start) = parse_timestamp(tag) {
+                    starts.push(start);
+                }
+                rest = after;
+            }
+            let text = strip_word_timings(rest);
+            lines.extend(starts.into_iter().map(|start| LyricLine {
+                start,
+                text: text.clone(),
+            }));
+        }
+
+        // A positive offset shows lines sooner
+        let offset = Duration::from_millis(offset_ms.unsigned_abs());
+        for line in &mut lines {
+            line.start = match offset_ms > 0 {
+                true => line.start.saturating_sub(offset),
+                false => line.start + offset,
+            };
+        }
+        lines.sort_by_key(|line| line.start);
+        Lyrics { lines }
+    }
+
+    /// Index of the line being sung at `position`, the last one started
+    pub fn line_at(&self, position: Duration) -> Option<usize> {
+        self.lines
+            .partition_point(|line| line.start <= position)
+            .checked_sub(1)
+    }
+}
+
+/// Text without enhanced LRC `<mm:ss.xx>` word timings
+fn strip_word_timings(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        match rest[open..].find('>') {
+            Some(close) if parse_timestamp(&rest[open + 1..open + close]).is_some() => {
+                stripped.push_str(&rest[..open]);
+                rest = &rest[open + close + 1..];
+            }
+            _ => {
+                stripped.push_str(&rest[..=open]);
+                rest = &rest[open + 1..];
+            }
+        }
+    }
+    stripped.push_str(rest);
+    stripped.trim().to_string()
+}
+
+/// Lyrics from the first millisecond-timed `SYLT` frame of the file's
+/// ID3v2 tag
+fn read_sylt(path: &Path) -> Option<Lyrics> {
+    Id3Tag::read(path)?
+        .frames
+        .iter()
+        .filter(|(id, _)| id == b"SYLT")
+        .find_map(|(_, data)| parse_sylt(data))
+}
+
+/// Parses a `SYLT` frame body. Karaoke tags time each syllable and start
+/// lines with a line break; those are joined back into lines.
+fn parse_sylt(data: &[u8]) -> Option<Lyrics> {
+    let (&encoding, rest) = data.split_first()?;
+    let timestamp_format = *rest.get(3)?;
+    if timestamp_format != SYLT_MILLISECONDS {
+        return None;
+    }
+    // Language and content type come before the content descriptor
+    let (_, mut rest) = read_text(rest.get(5..)?, encoding)?;
+    let mut syllables = Vec::new();
+    while !rest.is_empty() {
+        let (text, after) = read_text(rest, encoding)?;
+        let time = after.get(..4)?;
+        let ms = u32::from_be_bytes([time[0], time[1], time[2], time[3]]);
+        syllables.push((Duration::from_millis(ms as u64), text));
+        rest = &after[4..];
+    }
+
+    let karaoke = syllables
+        .iter()
+        .skip(1)
+        .any(|(_, text)| text.starts_with(['\n', '\r']));
+    let mut lines: Vec<LyricLine> = Vec::new();
+    for (start, text) in syllables {
+        match lines.last_mut() {
+            Some(line) if karaoke && !text.starts_with(['\n', '\r']) => line.text.push_str(&text),
+            _ => lines.push(LyricLine { start, text }),
+        }
+    }
+    for line in &mut lines {
+        line.text = line.text.trim().to_string();
+    }
+    lines.sort_by_key(|line| line.start);
+    Some(Lyrics { lines })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(ms: u64, text: &str) -> LyricLine {
+        LyricLine {
+            start: Duration::from_millis(ms),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_lrc() {
+        let lyrics = Lyrics::parse_lrc(
+            "\u{feff}[ar:Artist]\n\
+             [ti:Title]\n\
+             [00:12.50]First line\n\
+             [00:05.00][01:00.25]Chorus\n\
+             \n\
+             [00:20.00]<00:20.00>Word <00:20.50>timed <b>\n\
+             no timestamp\n",
+        );
+        assert_eq!(
+            lyrics.lines,
+            [
+                line(5000, "Chorus"),
+                line(12500, "First line"),
+                line(20000, "Word timed <b>"),
+                line(60250, "Chorus"),
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_offset() {
+        let sooner = Lyrics::parse_lrc("[offset:+500]\n[00:00.20]a\n[00:01.00]b\n");
+        assert_eq!(sooner.lines, [line(0, "a"), line(500, "b")]);
+        let later = Lyrics::parse_lrc("[00:01.00]b\n[offset:-250]\n");
+        assert_eq!(later.lines, [line(1250, "b")]);
+    }
+
+    #[test]
+    fn finds_the_line_being_sung() {
+        let lyrics = Lyrics {
+            lines: vec![line(1000, "a"), line(2000, "b")],
+        };
+        assert_eq!(lyrics.line_at(Duration::from_millis(999)), None);
+        assert_eq!(lyrics.line_at(Duration::from_millis(1000)), Some(0));
+        assert_eq!(lyrics.line_at(Duration::from_millis(1999)), Some(0));
+        assert_eq!(lyrics.line_at(Duration::from_secs(60)), Some(1));
+    }
+
+    fn sylt(format: u8, syllables: &[(&str, u32)]) -> Vec<u8> {
+        // Latin-1, language, timestamp format, content type, empty descriptor
+        let mut data = vec![0, b'e', b'n', b'g', format, 1, 0];
+        for (text, ms) in syllables {
+            data.extend_from_slice(text.as_bytes());
+            data.push(0);
+            data.extend_from_slice(&ms.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn parses_sylt() {
+        let lines = sylt(SYLT_MILLISECONDS, &[("Second", 2000), ("First", 1000)]);
+        assert_eq!(
+            parse_sylt(&lines).unwrap().lines,
+            [line(1000, "First"), line(2000, "Second")]
+        );
+
+        let karaoke = sylt(
+            SYLT_MILLISECONDS,
+            &[("Hel", 0), ("lo", 300), ("\nWorld", 900), (" again", 1200)],
+        );
+        assert_eq!(
+            parse_sylt(&karaoke).unwrap().lines,
+            [line(0, "Hello"), line(900, "World again")]
+        );
+
+        assert_eq!(parse_sylt(&sylt(1, &[("Frames", 10)])), None);
+    }
+}
@@ -0,0 +1,412 @@
+// Playlist reading and writing (M3U, M3U8, PLS, XSPF)
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Supported playlist file formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    M3u8,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    /// Guesses the format from the file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "m3u" => Some(PlaylistFormat::M3u),
+            "m3u8" => Some(PlaylistFormat::M3u8),
+            "pls" => Some(PlaylistFormat::Pls),
+            "xspf" => Some(PlaylistFormat::Xspf),
+            _ => None,
+        }
+    }
+}
+
+/// A list of entries plus the index of the entry that was playing when it was saved
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Playlist {
+    pub entries: Vec<PathBuf>,
+    pub current: Option<usize>,
+}
+
+impl Playlist {
+    pub fn new(entries: Vec<PathBuf>, current: Option<usize>) -> Self {
+        Playlist { entries, current }
+    }
+
+    /// Reads a playlist, resolving relative entries against its directory
+    pub fn read(path: &PathBuf) -> Result<Self, String> {
+        let format = PlaylistFormat::from_path(path).ok_or("unrecognized playlist format")?;
+        let bytes = fs::read(path).map_err(|e| format!("failed to read playlist: {}", e))?;
+        let content = decode_text(&bytes, format);
+        let base = path.parent().unwrap_or(Path::new("."));
+        Ok(Playlist::parse(&content, format, base))
+    }
+
+    /// Parses playlist text, resolving relative entries against `base`
+    pub fn parse(content: &str, format: PlaylistFormat, base: &Path) -> Self {
+        let (entries, current) = match format {
+            PlaylistFormat::M3u | PlaylistFormat::M3u8 => parse_m3u(content),
+            PlaylistFormat::Pls => parse_pls(content),
+            PlaylistFormat::Xspf => parse_xspf(content),
+        };
+        let entries: Vec<PathBuf> = entries
+            .into_iter()
+            .map(|entry| resolve_entry(&entry, base))
+            .collect();
+        let current = current.filter(|&index| index < entries.len());
+        Playlist { entries, current }
+    }
+
+    /// Writes the playlist in the format given by the file extension.
+    /// Entries inside the playlist's directory are stored relative to it.
+    pub fn write(&self, path: &PathBuf) -> Result<(), String> {
+        let format = PlaylistFormat::from_path(path).ok_or("unrecognized playlist format")?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        fs::write(path, self.format(format, base))
+            .map_err(|e| format!("failed to write playlist: {}", e))
+    }
+
+    /// Renders the playlist as text. The current index is stored as a
+    /// `#MOGBOX-CURRENT` directive (M3U), a `Current` key (PLS) or a
+    /// mogbox `<extension>` element (XSPF), which other players ignore.
+    pub fn format(&self, format: PlaylistFormat, base: &Path) -> String {
+        let entries: Vec<PathBuf> = self
+            .entries
+            .iter()
+            .map(|entry| relative_to(entry, base))
+            .collect();
+
+        match format {
+            PlaylistFormat::M3u | PlaylistFormat::M3u8 => {
+                let mut out = String::from("#EXTM3U\n");
+                if let Some(current) = self.current {
+                    out.push_str(&format!("{}{}\n", M3U_CURRENT, current));
+                }
+                for entry in entries.iter() {
+                    out.push_str(&entry.to_string_lossy());
+                    out.push('\n');
+                }
+                out
+            }
+            PlaylistFormat::Pls => {
+                let mut out = String::from("[playlist]\n");
+                for (i, entry) in entries.iter().enumerate() {
+                    out.push_str(&format!("File{}={}\n", i + 1, entry.to_string_lossy()));
+                }
+                out.push_str(&format!("NumberOfEntries={}\n", entries.len()));
+                if let Some(current) = self.current {
+                    out.push_str(&format!("Current={}\n", current + 1));
+                }
+                out.push_str("Version=2\n");
+                out
+            }
+            PlaylistFormat::Xspf => {
+                let mut out = String::from(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                     <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+                );
+                if let Some(current) = self.current {
+                    out.push_str(&format!(
+                        "  <extension application=\"{}\"><current>{}</current></extension>\n",
+                        XSPF_APPLICATION, current
+                    ));
+                }
+                out.push_str("  <trackList>\n");
+                for entry in entries.iter() {
+                    out.push_str(&format!(
+                        "    <track><location>{}</location></track>\n",
+                        escape_xml(&path_to_uri(entry))
+                    ));
+                }
+                out.push_str("  </trackList>\n</playlist>\n");
+                out
+            }
+        }
+    }
+}
+
+const M3U_CURRENT: &str = "#MOGBOX-CURRENT:";
+const XSPF_APPLICATION: &str = "https://github.com/moghaus/mogbox-core";
+
+/// Returns true if the path looks like a playlist rather than an audio file.
+/// URLs never do, as an `.m3u8` one is played as an HLS stream.
+pub fn is_playlist(path: &Path) -> bool {
+    !crate::http::is_url(path) && PlaylistFormat::from_path(path).is_some()
+}
+
+/// Reads a playlist and returns its entries, with relative entries resolved
+/// against the playlist's own directory
+pub fn read_playlist(path: &PathBuf) -> Result<Vec<PathBuf>, String> {
+    Ok(Playlist::read(path)?.entries)
+}
+
+/// Writes entries to a playlist, in the format given by the file extension
+pub fn write_playlist(path: &PathBuf, entries: &[PathBuf]) -> Result<(), String> {
+    Playlist::new(entries.to_vec(), None).write(path)
+}
+
+// Parsers
+
+fn parse_m3u(content: &str) -> (Vec<String>, Option<usize>) {
+    let mut entries = Vec::new();
+    let mut current = None;
+    for line in content.lines().map(str::trim) {
+        if let Some(index) = line.strip_prefix(M3U_CURRENT) {
+            current = index.trim().parse().ok();
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entries.push(line.to_string());
+        }
+    }
+    (entries, current)
+}
+
+fn parse_pls(content: &str) -> (Vec<String>, Option<usize>) {
+    let mut entries: Vec<(u32, String)> = Vec::new();
+    let mut current = None;
+    for line in content.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let key = key.trim();
+        if key == "Current" {
+            current = value
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1));
+        } else if let Some(number) = key.strip_prefix("File").and_then(|n| n.parse().ok()) {
+            entries.push((number, value.trim().to_string()));
+        }
+    }
+    entries.sort_by_key(|(number, _)| *number);
+    (
+        entries.into_iter().map(|(_, entry)| entry).collect(),
+        current,
+    )
+}
+
+fn parse_xspf(content: &str) -> (Vec<String>, Option<usize>) {
+    let current = content
+        .split_once("<current>")
+        .and_then(|(_, rest)| rest.split_once("</current>"))
+        .and_then(|(index, _)| index.trim().parse().ok());
+
+    let mut entries = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("<location>") {
+        rest = &rest[start + "<location>".len()..];
+        let Some(end) = rest.find("</location>") else {
+            break;
+        };
+        let location = unescape_xml(rest[..end].trim());
+        // Relative locations are URI references too
+        if location.contains("://") {
+            entries.push(location);
+        } else {
+            entries.push(percent_decode(&location));
+        }
+        rest = &rest[end..];
+    }
+    (entries, current)
+}
+
+// Helpers
+
+/// M3U8 and the XML formats are UTF-8; plain M3U may also be Latin-1
+fn decode_text(bytes: &[u8], format: PlaylistFormat) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match String::from_utf8(bytes.to_vec()) {
+        Ok(text) => text,
+        Err(_) if format == PlaylistFormat::M3u => bytes.iter().map(|&b| b as char).collect(),
+        Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+fn resolve_entry(entry: &str, base: &Path) -> PathBuf {
+    let path = match entry.strip_prefix("file://") {
+        Some(uri) => PathBuf::from(percent_decode(uri)),
+        // Remote streams and other URLs are kept verbatim
+        None if entry.contains("://") => return PathBuf::from(entry),
+        None => PathBuf::from(entry.replace('\\', std::path::MAIN_SEPARATOR_STR)),
+    };
+    if path.is_absolute() {
+        path
+    } else {
+        base.join(path)
+    }
+}
+
+fn relative_to(entry: &Path, base: &Path) -> PathBuf {
+    if base.as_os_str().is_empty() {
+        return entry.to_path_buf();
+    }
+    entry
+        .strip_prefix(base)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| entry.to_path_buf())
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    if path.is_absolute() {
+        format!("file://{}", encoded)
+    } else {
+        encoded
+    }
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok());
+        if let (b'%', Some(hex)) = (bytes[i], hex) {
+            if hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                out.push(u8::from_str_radix(hex, 16).unwrap_or_default());
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist() -> Playlist {
+        Playlist::new(
+            vec![
+                PathBuf::from("/music/a b.flac"),
+                PathBuf::from("/music/sub/ü&.mp3"),
+                PathBuf::from("/elsewhere/c.ogg"),
+                PathBuf::from("http://radio.example/stream"),
+            ],
+            Some(2),
+        )
+    }
+
+    #[test]
+    fn round_trips_every_format() {
+        let base = Path::new("/music");
+        for format in [
+            PlaylistFormat::M3u,
+            PlaylistFormat::M3u8,
+            PlaylistFormat::Pls,
+            PlaylistFormat::Xspf,
+        ] {
+            let text = playlist().format(format, base);
+            assert_eq!(
+                Playlist::parse(&text, format, base),
+                playlist(),
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn writes_entries_relative_to_the_playlist() {
+        let text = playlist().format(PlaylistFormat::M3u, Path::new("/music"));
+        assert_eq!(
+            text,
+            "#EXTM3U\n#MOGBOX-CURRENT:2\na b.flac\nsub/ü&.mp3\n/elsewhere/c.ogg\n\
+             http://radio.example/stream\n"
+        );
+        let xspf = playlist().format(PlaylistFormat::Xspf, Path::new("/music"));
+        assert!(xspf.contains("<location>sub/%C3%BC%26.mp3</location>"));
+        assert!(xspf.contains("<location>file:///elsewhere/c.ogg</location>"));
+    }
+
+    #[test]
+    fn parses_pls() {
+        let text = "[playlist]\nFile2=b.mp3\nTitle1=A\nFile1=a.mp3\nNumberOfEntries=2\n\
+                    Current=2\nVersion=2\n";
+        let parsed = Playlist::parse(text, PlaylistFormat::Pls, Path::new("/p"));
+        assert_eq!(
+            parsed.entries,
+            [PathBuf::from("/p/a.mp3"), "/p/b.mp3".into()]
+        );
+        assert_eq!(parsed.current, Some(1));
+    }
+
+    #[test]
+    fn drops_a_current_index_past_the_end() {
+        let parsed = Playlist::parse(
+            "#MOGBOX-CURRENT:5\na.flac\n",
+            PlaylistFormat::M3u,
+            Path::new(""),
+        );
+        assert_eq!(parsed.entries, [PathBuf::from("a.flac")]);
+        assert_eq!(parsed.current, None);
+    }
+
+    #[test]
+    fn decodes_text() {
+        let latin1 = b"#EXTM3U\ncaf\xe9.mp3\n";
+        assert_eq!(
+            decode_text(latin1, PlaylistFormat::M3u),
+            "#EXTM3U\ncafé.mp3\n"
+        );
+        assert_eq!(
+            decode_text(latin1, PlaylistFormat::M3u8),
+            "#EXTM3U\ncaf\u{fffd}.mp3\n"
+        );
+        assert_eq!(
+            decode_text(b"\xEF\xBB\xBFa.mp3", PlaylistFormat::M3u8),
+            "a.mp3"
+        );
+    }
+
+    #[test]
+    fn percent_decodes() {
+        assert_eq!(percent_decode("a%20b%C3%BC"), "a bü");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+        assert_eq!(percent_decode("%aé%+1"), "%aé%+1");
+    }
+
+    #[test]
+    fn recognizes_playlists() {
+        assert!(is_playlist(Path::new("list.M3U8")));
+        assert!(is_playlist(Path::new("list.xspf")));
+        assert!(!is_playlist(Path::new("song.flac")));
+        assert!(!is_playlist(Path::new("https://example.com/live.m3u8")));
+    }
+}
@@ -0,0 +1,501 @@
+// Podcast feeds
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Longest a feed may take to fetch, in seconds
+const FEED_TIMEOUT: u32 = 60;
+
+/// What an RSS or Atom feed says about a podcast
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Feed {
+    pub title: Option<String>,
+    /// Episodes that have something to play, in the feed's order, which is
+    /// usually newest first
+    pub episodes: Vec<Episode>,
+}
+
+/// An item or entry of a feed with an enclosure
+#[derive(Clone, Debug, PartialEq)]
+pub struct Episode {
+    /// The item's `guid` or the entry's `id`, or else its enclosure URL
+    pub guid: String,
+    pub title: String,
+    /// URL of the audio
+    pub url: String,
+    pub published: Option<SystemTime>,
+    pub duration: Option<Duration>,
+}
+
+impl Feed {
+    /// Fetches and reads the feed at `url`
+    pub fn fetch(url: &str) -> Result<Feed, String> {
+        let data = crate::http::fetch(url, FEED_TIMEOUT)?;
+        Feed::parse(&String::from_utf8_lossy(&data))
+    }
+
+    /// Reads an RSS 2.0 or Atom feed
+    pub fn parse(xml: &str) -> Result<Feed, String> {
+        if let Some(channel) = element(xml, "channel") {
+            Ok(Feed::read(channel, "item", parse_item))
+        } else if let Some(feed) = element(xml, "feed") {
+            Ok(Feed::read(feed, "entry", parse_entry))
+        } else {
+            Err("not an RSS or Atom feed".to_string())
+        }
+    }
+
+    /// Reads the title of a channel or feed and the episodes of its `item`
+    /// elements, named `entry` in Atom
+    fn read(xml: &str, item: &str, parse: fn(&str) -> Option<Episode>) -> Feed {
+        // The feed's own title comes before its items
+        let head = &xml[..find_tag(xml, item).unwrap_or(xml.len())];
+        let mut feed = Feed {
+            title: element(head, "title")
+                .map(text)
+                .filter(|title| !title.is_empty()),
+            episodes: Vec::new(),
+        };
+        let close = format!("</{}>", item);
+        let mut rest = xml;
+        while let Some(start) = find_tag(rest, item) {
+            let item = &rest[start..];
+            let end = item.find(&close).unwrap_or(item.len());
+            if let Some(episode) = parse(&item[..end]) {
+                feed.episodes.push(episode);
+            }
+            rest = &item[end..];
+        }
+        feed
+    }
+}
+
+fn parse_item(item: &str) -> Option<Episode> {
+    let enclosure = start_tag(item, "enclosure")?;
+    let url = unescape(attribute(enclosure, "url")?);
+    let guid = element(item, "guid")
+        .map(text)
+        .filter(|guid| !guid.is_empty());
+    Some(Episode {
+        guid: guid.unwrap_or_else(|| url.clone()),
+        title: element(item, "title").map(text).unwrap_or_default(),
+        published: element(item, "pubDate").and_then(|date| parse_date(&text(date))),
+        duration: element(item, "itunes:duration").and_then(|time| parse_duration(&text(time))),
+        url,
+    })
+}
+
+/// An Atom entry, whose audio is a `link` with `rel="enclosure"`
+fn parse_entry(entry: &str) -> Option<Episode> {
+    let mut rest = entry;
+    let url = loop {
+        let link = start_tag(rest, "link")?;
+        if attribute(link, "rel") == Some("enclosure") {
+            break unescape(attribute(link, "href")?);
+        }
+        rest = &rest[find_tag(rest, "link")? + link.len()..];
+    };
+    let id = element(entry, "id").map(text).filter(|id| !id.is_empty());
+    let published = element(entry, "published").or(element(entry, "updated"));
+    Some(Episode {
+        guid: id.unwrap_or_else(|| url.clone()),
+        title: element(entry, "title").map(text).unwrap_or_default(),
+        published: published.and_then(|date| parse_timestamp(&text(date))),
+        duration: element(entry, "itunes:duration").and_then(|time| parse_duration(&text(time))),
+        url,
+    })
+}
+
+/// Where the start tag `<name>` or `<name ...>` begins in `xml`
+fn find_tag(xml: &str, name: &str) -> Option<usize> {
+    let open = format!("<{}", name);
+    let mut from = 0;
+    while let Some(found) = xml[from..].find(&open) {
+        let start = from + found;
+        match xml[start + open.len()..].chars().next() {
+            Some('>' | '/' | ' ' | '\t' | '\r' | '\n') => return Some(start),
+            _ => from = start + open.len(),
+        }
+    }
+    None
+}
+
+/// The start tag of the first `name` element, attributes and all
+fn start_tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = find_tag(xml, name)?;
+    let end = xml[start..].find('>')?;
+    Some(&xml[start..start + end])
+}
+
+/// The content of the first `name` element, as it is in the XML
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let tag = find_tag(xml, name)?;
+    let start = tag + xml[tag..].find('>')? + 1;
+    if xml[..start].ends_with("/>") {
+        return Some("");
+    }
+    let end = xml[start..].find(&format!("</{}>", name))?;
+    Some(&xml[start..start + end])
+}
+
+/// The value of attribute `name` of a start tag, still escaped
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(found) = rest.find(name) {
+        let before = rest[..found].chars().next_back();
+        let after = rest[found + name.len()..].trim_start();
+        rest = &rest[found + name.len()..];
+        if !before.is_some_and(char::is_whitespace) {
+            continue;
+        }
+        let Some(value) = after.strip_prefix('=').map(str::trim_start) else {
+            continue;
+        };
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let value = &value[1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+    None
+}
+
+/// The text of element content: CDATA sections as they are, tags dropped and
+/// references replaced everywhere else
+fn text(content: &str) -> String {
+    let mut out = String::new();
+    let mut rest = content;
+    while !rest.is_empty() {
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").unwrap_or(cdata.len());
+            out.push_str(&cdata[..end]);
+            rest = cdata.get(end + 3..).unwrap_or_default();
+        } else if rest.starts_with('<') {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            rest = &rest[end..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            out.push_str(&unescape(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Replaces the predefined entities and character references of XML
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(end) = rest.find(';').filter(|&end| end <= 10) else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let reference = &rest[1..end];
+        let replacement = match reference {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "amp" => Some('&'),
+            _ => reference
+                .strip_prefix("#x")
+                .or(reference.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| reference.strip_prefix('#')?.parse().ok())
+                .and_then(char::from_u32),
+        };
+        match replacement {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// An RFC 822 date as RSS has them, such as `Sat, 07 Sep 2002 09:42:31 GMT`
+fn parse_date(date: &str) -> Option<SystemTime> {
+    // The day of the week is optional
+    let date = date.split_once(',').map_or(date, |(_, date)| date);
+    let mut fields = date.split_whitespace();
+    let day: i64 = fields.next()?.parse().ok()?;
+    let month = fields.next()?.get(..3)?.to_ascii_lowercase();
+    let month = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ]
+    .iter()
+    .position(|name| *name == month)? as i64
+        + 1;
+    let year: i64 = match fields.next()?.parse().ok()? {
+        year @ 0..=49 => 2000 + year,
+        year @ 50..=99 => 1900 + year,
+        year => year,
+    };
+    let mut time = fields.next().unwrap_or("0:0").split(':');
+    let mut part = || -> Option<i64> { time.next().map_or(Some(0), |part| part.parse().ok()) };
+    let seconds = part()? * 3600 + part()? * 60 + part()?;
+    let offset = match fields.next().unwrap_or("GMT") {
+        "GMT" | "UT" | "UTC" | "Z" => 0,
+        "EST" => -5 * 3600,
+        "EDT" => -4 * 3600,
+        "CST" => -6 * 3600,
+        "CDT" => -5 * 3600,
+        "MST" => -7 * 3600,
+        "MDT" => -6 * 3600,
+        "PST" => -8 * 3600,
+        "PDT" => -7 * 3600,
+        zone => {
+            let sign = match zone.chars().next()? {
+                '+' => 1,
+                '-' => -1,
+                _ => return None,
+            };
+            let hhmm: i64 = zone.get(1..5)?.parse().ok()?;
+            sign * (hhmm / 100 * 3600 + hhmm % 100 * 60)
+        }
+    };
+    let time = days_from_civil(year, month, day) * 86_400 + seconds - offset;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(time).ok()?))
+}
+
+/// An RFC 3339 timestamp as Atom has them, such as `2003-12-13T18:30:02Z`
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let (date, time) = timestamp.trim().split_once(['T', 't', ' '])?;
+    let mut date = date.split('-').map(|part| part.parse::<i64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day))) =
+        (date.next(), date.next(), date.next())
+    else {
+        return None;
+    };
+    let zone = time.find(['Z', 'z', '+', '-']).unwrap_or(time.len());
+    let (time, zone) = time.split_at(zone);
+    let mut time = time.split(':');
+    let mut part = || -> Option<f64> { time.next()?.parse().ok() };
+    let seconds = part()? * 3600.0 + part()? * 60.0 + part()?;
+    let offset = match zone {
+        "" | "Z" | "z" => 0,
+        zone => {
+            let sign = if zone.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = zone[1..].split_once(':')?;
+            sign * (hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60)
+        }
+    };
+    let time = days_from_civil(year, month, day) * 86_400 + seconds as i64 - offset;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(time).ok()?))
+}
+
+/// Days since 1970-01-01 of a date, from Howard Hinnant's `days_from_civil`
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// An `itunes:duration`: seconds, `MM:SS` or `HH:MM:SS`
+fn parse_duration(time: &str) -> Option<Duration> {
+    let seconds = time.split(':').try_fold(0.0, |total: f64, part| {
+        Some(total * 60.0 + part.trim().parse::<f64>().ok()?)
+    })?;
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">
+  <channel>
+    <title>Tom &amp; Jerry&#8217;s Show</title>
+    <link>https://example.com/</link>
+    <item>
+      <title><![CDATA[Episode 2: <Cats> & dogs]]></title>
+      <guid isPermaLink="false">ep-2</guid>
+      <pubDate>Sat, 07 Sep 2002 09:42:31 GMT</pubDate>
+      <itunes:duration>1:02:03</itunes:duration>
+      <enclosure url="https://cdn.example.com/ep2.mp3?a=1&amp;b=2" length="1" type="audio/mpeg"/>
+    </item>
+    <item>
+      <title>Show notes only</title>
+      <guid>notes</guid>
+    </item>
+    <item>
+      <title>Episode 1</title>
+      <enclosure type='audio/mpeg' url='https://cdn.example.com/ep1.mp3' />
+    </item>
+  </channel>
+</rss>"#;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title type="text">Atom &lt;Cast&gt;</title>
+  <link rel="self" href="https://example.com/feed.atom"/>
+  <entry>
+    <title>First</title>
+    <id>urn:uuid:1225c695</id>
+    <link rel="alternate" href="https://example.com/first"/>
+    <link rel="enclosure" type="audio/ogg" href="https://example.com/first.ogg?x=1&amp;y=2"/>
+    <published>2003-12-13T18:30:02+01:00</published>
+    <updated>2003-12-14T00:00:00Z</updated>
+  </entry>
+  <entry>
+    <title>Text only</title>
+    <link href="https://example.com/text"/>
+  </entry>
+  <entry>
+    <title>Second</title>
+    <link rel="enclosure" href="https://example.com/second.ogg"/>
+    <updated>2003-12-13T18:30:02.25Z</updated>
+  </entry>
+</feed>"#;
+
+    fn time(seconds: u64) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn rss_items_with_enclosures_become_episodes() {
+        let feed = Feed::parse(RSS).unwrap();
+        assert_eq!(feed.title.as_deref(), Some("Tom & Jerry\u{2019}s Show"));
+        assert_eq!(
+            feed.episodes,
+            [
+                Episode {
+                    guid: "ep-2".to_string(),
+                    title: "Episode 2: <Cats> & dogs".to_string(),
+                    url: "https://cdn.example.com/ep2.mp3?a=1&b=2".to_string(),
+                    published: time(1_031_391_751),
+                    duration: Some(Duration::from_secs(3723)),
+                },
+                Episode {
+                    guid: "https://cdn.example.com/ep1.mp3".to_string(),
+                    title: "Episode 1".to_string(),
+                    url: "https://cdn.example.com/ep1.mp3".to_string(),
+                    published: None,
+                    duration: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn atom_entries_with_enclosure_links_become_episodes() {
+        let feed = Feed::parse(ATOM).unwrap();
+        assert_eq!(feed.title.as_deref(), Some("Atom <Cast>"));
+        assert_eq!(
+            feed.episodes,
+            [
+                Episode {
+                    guid: "urn:uuid:1225c695".to_string(),
+                    title: "First".to_string(),
+                    url: "https://example.com/first.ogg?x=1&y=2".to_string(),
+                    published: time(1_071_336_602),
+                    duration: None,
+                },
+                Episode {
+                    guid: "https://example.com/second.ogg".to_string(),
+                    title: "Second".to_string(),
+                    url: "https://example.com/second.ogg".to_string(),
+                    published: time(1_071_340_202),
+                    duration: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn feeds_without_enclosures_have_no_episodes() {
+        let feed = Feed::parse(
+            "<rss><channel><title>Blog</title><item><title>Post</title></item></channel></rss>",
+        )
+        .unwrap();
+        assert_eq!(feed.title.as_deref(), Some("Blog"));
+        assert!(feed.episodes.is_empty());
+        let feed = Feed::parse("<channel><title/></channel>").unwrap();
+        assert_eq!(feed.title, None);
+    }
+
+    #[test]
+    fn other_documents_are_not_feeds() {
+        assert!(Feed::parse("<html><body>Not found</body></html>").is_err());
+        assert!(Feed::parse("").is_err());
+        // `<channels>` is not `<channel>`
+        assert!(Feed::parse("<channels></channels>").is_err());
+    }
+
+    #[test]
+    fn references_are_replaced() {
+        assert_eq!(
+            unescape("a &lt;b&gt; &quot;c&quot; &apos;d&apos;"),
+            "a <b> \"c\" 'd'"
+        );
+        assert_eq!(unescape("&#233;&#xE9;&#XE9;"), "ééé");
+        assert_eq!(unescape("&amp;amp;"), "&amp;");
+        // Unknown or unterminated references stay as they are
+        assert_eq!(unescape("AT&T &nbsp; &#xZZ; &"), "AT&T &nbsp; &#xZZ; &");
+        assert_eq!(
+            text("  a <b>bold</b>\n &amp; <![CDATA[&amp;]]> "),
+            "a bold & &amp;"
+        );
+    }
+
+    #[test]
+    fn attributes_need_a_whole_name() {
+        let tag = r#"<enclosure data-url="no" url = 'yes' type="audio/mpeg""#;
+        assert_eq!(attribute(tag, "url"), Some("yes"));
+        assert_eq!(attribute(tag, "type"), Some("audio/mpeg"));
+        assert_eq!(attribute(tag, "length"), None);
+    }
+
+    #[test]
+    fn rfc_822_dates() {
+        assert_eq!(parse_date("Thu, 01 Jan 1970 00:00:00 GMT"), time(0));
+        assert_eq!(parse_date("01 Jan 70 01:00:00 +0100"), time(0));
+        assert_eq!(parse_date("Wed, 31 Dec 1969 19:00:00 EST"), time(0));
+        assert_eq!(
+            parse_date("Sat, 29 Feb 2020 12:30 PDT"),
+            time(1_583_004_600)
+        );
+        assert_eq!(
+            parse_date("Sat, 07 Sept 2002 09:42:31 -0000"),
+            time(1_031_391_751)
+        );
+        assert_eq!(parse_date("yesterday"), None);
+        assert_eq!(parse_date("01 Foo 2000"), None);
+    }
+
+    #[test]
+    fn rfc_3339_timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), time(0));
+        assert_eq!(parse_timestamp("1970-01-01t01:00:00+01:00"), time(0));
+        assert_eq!(parse_timestamp("1969-12-31 23:00:00-01:00"), time(0));
+        assert_eq!(
+            parse_timestamp("2020-02-29T12:30:00.5Z"),
+            time(1_582_979_400)
+        );
+        assert_eq!(parse_timestamp("2020-02-29"), None);
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59Z"), None);
+    }
+
+    #[test]
+    fn itunes_durations() {
+        assert_eq!(parse_duration("95"), Some(Duration::from_secs(95)));
+        assert_eq!(parse_duration("01:35"), Some(Duration::from_secs(95)));
+        assert_eq!(parse_duration("1:01:35"), Some(Duration::from_secs(3695)));
+        assert_eq!(parse_duration("12.5"), Some(Duration::from_millis(12_500)));
+        assert_eq!(parse_duration("0"), None);
+        assert_eq!(parse_duration("an hour"), None);
+    }
+}
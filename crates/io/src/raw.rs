@@ -0,0 +1,293 @@
+// Headerless PCM
+
+use std::io::Read;
+use std::str::FromStr;
+
+use symphonia::core::codecs::{self, CodecType};
+use symphonia::core::io::MediaSource;
+
+/// Frames read at a time
+const CHUNK_FRAMES: usize = 4096;
+
+/// How samples are stored in raw PCM, named as ffmpeg and sox name them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PcmEncoding {
+    S8,
+    U8,
+    S16Le,
+    S16Be,
+    S24Le,
+    S24Be,
+    S32Le,
+    S32Be,
+    F32Le,
+    F32Be,
+    F64Le,
+    F64Be,
+}
+
+impl PcmEncoding {
+    const ALL: [PcmEncoding; 12] = [
+        PcmEncoding::S8,
+        PcmEncoding::U8,
+        PcmEncoding::S16Le,
+        PcmEncoding::S16Be,
+        PcmEncoding::S24Le,
+        PcmEncoding::S24Be,
+        PcmEncoding::S32Le,
+        PcmEncoding::S32Be,
+        PcmEncoding::F32Le,
+        PcmEncoding::F32Be,
+        PcmEncoding::F64Le,
+        PcmEncoding::F64Be,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PcmEncoding::S8 => "s8",
+            PcmEncoding::U8 => "u8",
+            PcmEncoding::S16Le => "s16le",
+            PcmEncoding::S16Be => "s16be",
+            PcmEncoding::S24Le => "s24le",
+            PcmEncoding::S24Be => "s24be",
+            PcmEncoding::S32Le => "s32le",
+            PcmEncoding::S32Be => "s32be",
+            PcmEncoding::F32Le => "f32le",
+            PcmEncoding::F32Be => "f32be",
+            PcmEncoding::F64Le => "f64le",
+            PcmEncoding::F64Be => "f64be",
+        }
+    }
+
+    /// Bytes a sample takes
+    pub fn bytes(self) -> usize {
+        match self {
+            PcmEncoding::S8 | PcmEncoding::U8 => 1,
+            PcmEncoding::S16Le | PcmEncoding::S16Be => 2,
+            PcmEncoding::S24Le | PcmEncoding::S24Be => 3,
+            PcmEncoding::S32Le | PcmEncoding::S32Be | PcmEncoding::F32Le | PcmEncoding::F32Be => 4,
+            PcmEncoding::F64Le | PcmEncoding::F64Be => 8,
+        }
+    }
+
+    fn big_endian(self) -> bool {
+        matches!(
+            self,
+            PcmEncoding::S16Be
+                | PcmEncoding::S24Be
+                | PcmEncoding::S32Be
+                | PcmEncoding::F32Be
+                | PcmEncoding::F64Be
+        )
+    }
+
+    /// The symphonia codec of the same samples
+    pub fn codec(self) -> CodecType {
+        match self {
+            PcmEncoding::S8 => codecs::CODEC_TYPE_PCM_S8,
+            PcmEncoding::U8 => codecs::CODEC_TYPE_PCM_U8,
+            PcmEncoding::S16Le => codecs::CODEC_TYPE_PCM_S16LE,
+            PcmEncoding::S16Be => codecs::CODEC_TYPE_PCM_S16BE,
+            PcmEncoding::S24Le => codecs::CODEC_TYPE_PCM_S24LE,
+            PcmEncoding::S24Be => codecs::CODEC_TYPE_PCM_S24BE,
+            PcmEncoding::S32Le => codecs::CODEC_TYPE_PCM_S32LE,
+            PcmEncoding::S32Be => codecs::CODEC_TYPE_PCM_S32BE,
+            PcmEncoding::F32Le => codecs::CODEC_TYPE_PCM_F32LE,
+            PcmEncoding::F32Be => codecs::CODEC_TYPE_PCM_F32BE,
+            PcmEncoding::F64Le => codecs::CODEC_TYPE_PCM_F64LE,
+            PcmEncoding::F64Be => codecs::CODEC_TYPE_PCM_F64BE,
+        }
+    }
+
+    /// Reads the sample in `bytes`, which are [`bytes`](Self::bytes) long,
+    /// scaled to -1..1
+    pub fn decode(self, bytes: &[u8]) -> f32 {
+        // Little-endian from here on
+        let mut le = [0u8; 8];
+        le[..bytes.len()].copy_from_slice(bytes);
+        if self.big_endian() {
+            le[..bytes.len()].reverse();
+        }
+        match self {
+            PcmEncoding::S8 => le[0] as i8 as f32 / 128.0,
+            PcmEncoding::U8 => (le[0] as f32 - 128.0) / 128.0,
+            PcmEncoding::S16Le | PcmEncoding::S16Be => {
+                i16::from_le_bytes([le[0], le[1]]) as f32 / 32_768.0
+            }
+            PcmEncoding::S24Le | PcmEncoding::S24Be => {
+                // Into the top of an i32, which sign-extends it
+                (i32::from_le_bytes([0, le[0], le[1], le[2]]) >> 8) as f32 / 8_388_608.0
+            }
+            PcmEncoding::S32Le | PcmEncoding::S32Be => {
+                i32::from_le_bytes([le[0], le[1], le[2], le[3]]) as f32 / 2_147_483_648.0
+            }
+            PcmEncoding::F32Le | PcmEncoding::F32Be => {
+                f32::from_le_bytes([le[0], le[1], le[2], le[3]])
+            }
+            PcmEncoding::F64Le | PcmEncoding::F64Be => f64::from_le_bytes(le) as f32,
+        }
+    }
+
+    /// Appends `sample`, scaled to -1..1, to `out` the way
+    /// [`decode`](Self::decode) reads it back; integers are clipped
+    pub fn encode(self, sample: f32, out: &mut Vec<u8>) {
+        let integer = |bits: u32| {
+            let scale = (1i64 << (bits - 1)) as f64;
+            (sample as f64 * scale).round().clamp(-scale, scale - 1.0) as i64
+        };
+        let start = out.len();
+        match self {
+            PcmEncoding::S8 => out.push(integer(8) as i8 as u8),
+            PcmEncoding::U8 => out.push((integer(8) + 128) as u8),
+            PcmEncoding::S16Le | PcmEncoding::S16Be => {
+                out.extend_from_slice(&(integer(16) as i16).to_le_bytes())
+            }
+            PcmEncoding::S24Le | PcmEncoding::S24Be => {
+                out.extend_from_slice(&(integer(24) as i32).to_le_bytes()[..3])
+            }
+            PcmEncoding::S32Le | PcmEncoding::S32Be => {
+                out.extend_from_slice(&(integer(32) as i32).to_le_bytes())
+            }
+            PcmEncoding::F32Le | PcmEncoding::F32Be => out.extend_from_slice(&sample.to_le_bytes()),
+            PcmEncoding::F64Le | PcmEncoding::F64Be => {
+                out.extend_from_slice(&(sample as f64).to_le_bytes())
+            }
+        }
+        if self.big_endian() {
+            out[start..].reverse();
+        }
+    }
+}
+
+impl FromStr for PcmEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        PcmEncoding::ALL
+            .into_iter()
+            .find(|encoding| encoding.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = PcmEncoding::ALL.iter().map(|e| e.name()).collect();
+                format!(
+                    "unknown sample format '{}', expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// What headerless PCM holds, which has to be known to read it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawFormat {
+    pub encoding: PcmEncoding,
+    pub sample_rate: u32,
+    pub channels: u8,
+}
+
+impl RawFormat {
+    /// Bytes a frame takes
+    pub fn frame_bytes(&self) -> usize {
+        self.encoding.bytes() * self.channels as usize
+    }
+}
+
+impl FromStr for RawFormat {
+    type Err = String;
+
+    /// Parses `ENCODING:RATE:CHANNELS`, such as `f32le:48000:2`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let (Some(encoding), Some(rate), Some(channels), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!(
+                "invalid raw format '{}', expected FORMAT:RATE:CHANNELS such as f32le:48000:2",
+                s
+            ));
+        };
+        let sample_rate = rate
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&rate| rate > 0)
+            .ok_or_else(|| format!("invalid sample rate '{}'", rate))?;
+        let channels = channels
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&channels| channels > 0)
+            .ok_or_else(|| format!("invalid channel count '{}'", channels))?;
+        Ok(RawFormat {
+            encoding: encoding.parse()?,
+            sample_rate,
+            channels,
+        })
+    }
+}
+
+/// Reads frames of headerless PCM from a source
+pub(crate) struct RawReader {
+    source: Box<dyn MediaSource>,
+    format: RawFormat,
+    /// Bytes read past the last whole frame
+    partial: Vec<u8>,
+}
+
+impl RawReader {
+    pub(crate) fn new(source: Box<dyn MediaSource>, format: RawFormat) -> Self {
+        RawReader {
+            source,
+            format,
+            partial: Vec::new(),
+        }
+    }
+
+    pub(crate) fn format(&self) -> RawFormat {
+        self.format
+    }
+
+    /// Goes to `frame`, when the source can seek
+    pub(crate) fn seek(&mut self, frame: u64) -> Result<(), String> {
+        if !self.source.is_seekable() {
+            return Err("failed to seek: the source can't seek".to_string());
+        }
+        let byte = frame * self.format.frame_bytes() as u64;
+        self.source
+            .seek(std::io::SeekFrom::Start(byte))
+            .map_err(|e| format!("failed to seek: {}", e))?;
+        self.partial.clear();
+        Ok(())
+    }
+
+    /// The next frames as interleaved samples, or `None` at the end. A frame
+    /// cut short at the end is dropped.
+    pub(crate) fn next_samples(&mut self) -> Result<Option<Vec<f32>>, String> {
+        let frame_bytes = self.format.frame_bytes();
+        let want = CHUNK_FRAMES * frame_bytes;
+        let mut data = std::mem::take(&mut self.partial);
+        let mut filled = data.len();
+        data.resize(want, 0);
+        // Read until a whole chunk or the end
+        while filled < want {
+            match self.source.read(&mut data[filled..]) {
+                Ok(0) => break,
+                Ok(count) => filled += count,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(format!("failed to read samples: {}", e)),
+            }
+        }
+        let whole = filled / frame_bytes * frame_bytes;
+        self.partial = data[whole..filled].to_vec();
+        if whole == 0 {
+            return Ok(None);
+        }
+        let encoding = self.format.encoding;
+        let samples = data[..whole]
+            .chunks_exact(encoding.bytes())
+            .map(|sample| encoding.decode(sample))
+            .collect();
+        Ok(Some(samples))
+    }
+}
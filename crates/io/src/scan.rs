@@ -0,0 +1,143 @@
+// Directory scanning for playback
+
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use symphonia::core::meta::StandardTagKey;
+
+use crate::AudioFile;
+
+/// Extensions of the formats the default decoders can open, video
+/// containers included
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "wav", "wave", "flac", "ogg", "oga", "mp3", "mka", "mkv", "webm",
+];
+
+/// Extensions of the formats only the ffmpeg fallback opens
+#[cfg(feature = "ffmpeg")]
+pub const FFMPEG_EXTENSIONS: &[&str] = &[
+    "wma", "m4a", "aac", "opus", "aif", "aiff", "ape", "wv", "ac3", "mpc", "tta", "mp4", "m4v",
+    "mov", "avi",
+];
+#[cfg(not(feature = "ffmpeg"))]
+pub const FFMPEG_EXTENSIONS: &[&str] = &[];
+
+/// Returns true if the file extension belongs to a decodable format
+pub fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            SUPPORTED_EXTENSIONS
+                .iter()
+                .chain(FFMPEG_EXTENSIONS)
+                .any(|supported| supported.eq_ignore_ascii_case(ext))
+        })
+        .unwrap_or(false)
+}
+
+/// Recursively collects the decodable files below `dir`. Each directory is
+/// listed as a unit: by disc and track number when every file carries those
+/// tags, otherwise by natural file name order (`2 - x` before `10 - y`).
+pub fn scan_directory(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    scan_into(dir, &mut files)?;
+    Ok(files)
+}
+
+fn scan_into(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("failed to read directory {:?}: {}", dir, e))?;
+
+    let mut subdirs = Vec::new();
+    let mut tracks = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            subdirs.push(path);
+        } else if is_supported(&path) {
+            tracks.push(path);
+        }
+    }
+
+    sort_tracks(&mut tracks);
+    files.extend(tracks);
+
+    subdirs.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    for subdir in subdirs {
+        scan_into(&subdir, files)?;
+    }
+    Ok(())
+}
+
+fn sort_tracks(tracks: &mut [PathBuf]) {
+    let positions: Option<Vec<(u32, u32)>> = tracks.iter().map(track_position).collect();
+
+    match positions {
+        Some(positions) => {
+            let mut keyed: Vec<((u32, u32), PathBuf)> =
+                positions.into_iter().zip(tracks.iter().cloned()).collect();
+            keyed.sort_by(|(a, path_a), (b, path_b)| {
+                a.cmp(b)
+                    .then_with(|| natural_cmp(&path_a.to_string_lossy(), &path_b.to_string_lossy()))
+            });
+            for (slot, (_, path)) in tracks.iter_mut().zip(keyed) {
+                *slot = path;
+            }
+        }
+        None => tracks.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy())),
+    }
+}
+
+/// Disc and track number from the file's tags; the disc defaults to 1
+fn track_position(path: &PathBuf) -> Option<(u32, u32)> {
+    let file = AudioFile::open(path).ok()?;
+    let track = file.tag_number(StandardTagKey::TrackNumber)?;
+    let disc = file.tag_number(StandardTagKey::DiscNumber).unwrap_or(1);
+    Some((disc, track))
+}
+
+/// Compares strings treating runs of digits as numbers, case-insensitively
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                // Compare by magnitude first, then by length so "01" sorts before "1"
+                let ordering = x
+                    .trim_start_matches('0')
+                    .len()
+                    .cmp(&y.trim_start_matches('0').len())
+                    .then_with(|| x.trim_start_matches('0').cmp(y.trim_start_matches('0')))
+                    .then_with(|| y.len().cmp(&x.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+        number.push(c);
+        chars.next();
+    }
+    number
+}
@@ -0,0 +1,51 @@
+// Audio piped in through standard input
+
+use std::io::Read;
+use std::path::Path;
+
+use symphonia::core::io::MediaSource;
+
+/// Whether `path` stands for stdin, as `-` does
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Stdin as a source that can't seek. It can only be read through once, so
+/// whoever opens it has to hand the source, or the file opened on it, on to
+/// whatever plays it rather than open it again.
+pub struct StdinSource {
+    stdin: std::io::Stdin,
+}
+
+impl StdinSource {
+    pub fn open() -> Self {
+        StdinSource {
+            stdin: std::io::stdin(),
+        }
+    }
+}
+
+impl Read for StdinSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stdin.read(buf)
+    }
+}
+
+impl std::io::Seek for StdinSource {
+    fn seek(&mut self, _pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "stdin can't be seeked",
+        ))
+    }
+}
+
+impl MediaSource for StdinSource {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
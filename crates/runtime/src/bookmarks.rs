@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::resume::write_state_file;
+
+/// A named position in a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bookmark {
+    pub name: String,
+    pub position: Duration,
+}
+
+/// Named positions in files, saved across runs. Like [`ResumeStore`],
+/// files are known by the key [`file_key`] gives them.
+///
+/// [`ResumeStore`]: crate::ResumeStore
+/// [`file_key`]: crate::file_key
+#[derive(Clone, Debug, Default)]
+pub struct BookmarkStore {
+    path: PathBuf,
+    /// Bookmarks as (file key, last known location, bookmark)
+    entries: Vec<(String, PathBuf, Bookmark)>,
+}
+
+impl BookmarkStore {
+    /// Loads the store kept at `path`; a file that doesn't exist yet is an
+    /// empty store
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+        // One `key<TAB>seconds<TAB>name<TAB>path` line per bookmark
+        let entries = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(4, '\t');
+                let key = fields.next()?.to_string();
+                let seconds: f64 = fields.next()?.parse().ok()?;
+                let bookmark = Bookmark {
+                    position: Duration::try_from_secs_f64(seconds).ok()?,
+                    name: fields.next()?.to_string(),
+                };
+                let path = PathBuf::from(fields.next().unwrap_or_default());
+                Some((key, path, bookmark))
+            })
+            .collect();
+        Ok(BookmarkStore {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// Writes the store back through a temporary file
+    pub fn save(&self) -> Result<(), String> {
+        let text: String = self
+            .entries
+            .iter()
+            .map(|(key, path, bookmark)| {
+                format!(
+                    "{}\t{:.3}\t{}\t{}\n",
+                    key,
+                    bookmark.position.as_secs_f64(),
+                    bookmark.name,
+                    path.display()
+                )
+            })
+            .collect();
+        write_state_file(&self.path, &text)
+    }
+
+    /// The bookmarks of the file with `key`, in order of position
+    pub fn bookmarks(&self, key: &str) -> Vec<Bookmark> {
+        let mut bookmarks: Vec<Bookmark> = self
+            .entries
+            .iter()
+            .filter(|(entry, _, _)| entry == key)
+            .map(|(_, _, bookmark)| bookmark.clone())
+            .collect();
+        bookmarks.sort_by_key(|bookmark| bookmark.position);
+        bookmarks
+    }
+
+    /// Adds a bookmark to the file at `path`, whose key is `key`, replacing
+    /// any of the same name. Tabs and line breaks in the name become spaces.
+    pub fn add(&mut self, key: &str, path: &Path, name: &str, position: Duration) {
+        let name: String = name
+            .trim()
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        self.remove(key, &name);
+        self.entries.push((
+            key.to_string(),
+            path.to_path_buf(),
+            Bookmark { name, position },
+        ));
+    }
+
+    /// Finds a bookmark of the file with `key` by its name, ignoring case,
+    /// or by its number in [`bookmarks`](Self::bookmarks) counting from 1
+    pub fn find(&self, key: &str, name: &str) -> Option<Bookmark> {
+        let bookmarks = self.bookmarks(key);
+        let by_name = bookmarks
+            .iter()
+            .find(|bookmark| bookmark.name.eq_ignore_ascii_case(name.trim()));
+        let by_number = || {
+            let number: usize = name.trim().parse().ok()?;
+            bookmarks.get(number.checked_sub(1)?)
+        };
+        by_name.or_else(by_number).cloned()
+    }
+
+    /// Deletes the bookmark `name` of the file with `key`. Returns whether
+    /// there was one.
+    pub fn remove(&mut self, key: &str, name: &str) -> bool {
+        let count = self.entries.len();
+        self.entries.retain(|(entry, _, bookmark)| {
+            entry != key || !bookmark.name.eq_ignore_ascii_case(name.trim())
+        });
+        self.entries.len() != count
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::{Arc, Mutex};
+
+use mogbox_engine::Processor;
+
+/// A chain that can be shared between the control side and the audio thread,
+/// so nodes can be added or removed while playback is running
+pub type SharedChain = Arc<Mutex<Chain>>;
+
+/// An ordered list of processors applied one after the other
+pub struct Chain {
+    nodes: Vec<Box<dyn Processor>>,
+    sample_rate: u32,
+    channels: usize,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Chain {
+            nodes: Vec::new(),
+            sample_rate: 44100,
+            channels: 2,
+        }
+    }
+
+    /// Appends a node, builder style: `Chain::new().add(Gain::from_db(-3.0)).add(Limiter::default())`
+    #[allow(clippy::should_implement_trait)]
+    pub fn add<P: Processor + 'static>(mut self, node: P) -> Self {
+        self.push(node);
+        self
+    }
+
+    /// Appends a node at the end of the chain
+    pub fn push<P: Processor + 'static>(&mut self, node: P) {
+        self.push_boxed(Box::new(node));
+    }
+
+    pub fn push_boxed(&mut self, mut node: Box<dyn Processor>) {
+        node.prepare(self.sample_rate, self.channels);
+        self.nodes.push(node);
+    }
+
+    /// Inserts a node at `index`, shifting later nodes towards the end
+    pub fn insert<P: Processor + 'static>(&mut self, index: usize, node: P) {
+        let mut node: Box<dyn Processor> = Box::new(node);
+        node.prepare(self.sample_rate, self.channels);
+        self.nodes.insert(index.min(self.nodes.len()), node);
+    }
+
+    /// Removes and returns the node at `index`
+    pub fn remove(&mut self, index: usize) -> Option<Box<dyn Processor>> {
+        if index < self.nodes.len() {
+            Some(self.nodes.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Wraps the chain so it can be edited from another thread
+    pub fn into_shared(self) -> SharedChain {
+        Arc::new(Mutex::new(self))
+    }
+}
+
+impl Default for Chain {
+    fn default() -> Self {
+        Chain::new()
+    }
+}
+
+impl Processor for Chain {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        for node in self.nodes.iter_mut() {
+            node.prepare(sample_rate, channels);
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for node in self.nodes.iter_mut() {
+            node.process(samples);
+        }
+    }
+
+    fn reset(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.reset();
+        }
+    }
+}
@@ -0,0 +1,325 @@
+// Local wall-clock time, from the system's time zone database
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: i64 = 86_400;
+
+/// Length of a TZif header
+const HEADER_LEN: usize = 44;
+
+/// Where the time zone database is installed on Unix systems
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+/// Seconds local time is ahead of UTC at `time`, by the `TZ` variable or
+/// else `/etc/localtime`. Without either, local time is taken to be UTC.
+pub fn utc_offset(time: SystemTime) -> i64 {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    };
+    let zone = match std::env::var("TZ") {
+        Ok(tz) if !tz.is_empty() => {
+            let name = tz.strip_prefix(':').unwrap_or(&tz);
+            let path = match name.starts_with('/') {
+                true => name.into(),
+                false => std::path::Path::new(ZONEINFO).join(name),
+            };
+            match std::fs::read(path) {
+                Ok(data) => TimeZone::parse(&data),
+                Err(_) => PosixRule::parse(name).map(|rule| TimeZone {
+                    transitions: Vec::new(),
+                    offsets: Vec::new(),
+                    rule: Some(rule),
+                }),
+            }
+        }
+        _ => std::fs::read("/etc/localtime")
+            .ok()
+            .and_then(|data| TimeZone::parse(&data)),
+    };
+    zone.map_or(0, |zone| zone.offset_at(seconds))
+}
+
+/// The next moment after `after` at which the local clock reads
+/// `hour:minute:second`
+pub fn next_local_time(hour: u32, minute: u32, second: u32, after: SystemTime) -> SystemTime {
+    let now = after
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let local_now = now + utc_offset(after);
+    let time_of_day = (hour * 3600 + minute * 60 + second) as i64;
+    let mut local = local_now.div_euclid(DAY) * DAY + time_of_day;
+    if local <= local_now {
+        local += DAY;
+    }
+    // The offset may be different by then, across a daylight saving change
+    let guess = UNIX_EPOCH + Duration::from_secs((local - utc_offset(after)).max(0) as u64);
+    let target = (local - utc_offset(guess)).max(now + 1);
+    UNIX_EPOCH + Duration::from_secs(target as u64)
+}
+
+/// Days since 1970-01-01 of a date, from Howard Hinnant's `days_from_civil`
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// The parts of a TZif file needed to find the offset at a time
+struct TimeZone {
+    /// Times at which the offset changes, with the index of the new offset
+    transitions: Vec<(i64, usize)>,
+    offsets: Vec<i64>,
+    /// Rule for times after the last transition
+    rule: Option<PosixRule>,
+}
+
+impl TimeZone {
+    /// Parses a TZif file, using its 64-bit data and footer rule from
+    /// version 2 on
+    fn parse(data: &[u8]) -> Option<TimeZone> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let block = TimeZone::block_len(data, 4)?;
+        if data[4] < b'2' {
+            return TimeZone::read_block(data, 4, None);
+        }
+        // The 32-bit data is followed by a second header and 64-bit data
+        let data = data.get(HEADER_LEN + block..)?;
+        let footer = data.get(HEADER_LEN + TimeZone::block_len(data, 8)?..)?;
+        let rule = std::str::from_utf8(footer)
+            .ok()
+            .and_then(|footer| PosixRule::parse(footer.trim_matches('\n')));
+        TimeZone::read_block(data, 8, rule)
+    }
+
+    /// A count from the header at the start of `data`
+    fn count(data: &[u8], index: usize) -> Option<usize> {
+        let bytes = data.get(20 + index * 4..24 + index * 4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    /// Length of the data block after the header at the start of `data`,
+    /// whose times are `time_size` bytes long
+    fn block_len(data: &[u8], time_size: usize) -> Option<usize> {
+        let count = |index| TimeZone::count(data, index);
+        let (utc, standard, leap) = (count(0)?, count(1)?, count(2)?);
+        let (times, types, chars) = (count(3)?, count(4)?, count(5)?);
+        Some(times * (time_size + 1) + types * 6 + chars + leap * (time_size + 4) + standard + utc)
+    }
+
+    /// Reads the transitions and offsets from the header at the start of
+    /// `data` and the block after it
+    fn read_block(data: &[u8], time_size: usize, rule: Option<PosixRule>) -> Option<TimeZone> {
+        let (times, types) = (TimeZone::count(data, 3)?, TimeZone::count(data, 4)?);
+        let block = data.get(HEADER_LEN..)?;
+        let (starts, block) = block.split_at_checked(times * time_size)?;
+        let (indices, block) = block.split_at_checked(times)?;
+        let transitions = starts
+            .chunks_exact(time_size)
+            .zip(indices)
+            .map(|(start, &index)| {
+                let start = match time_size {
+                    4 => i32::from_be_bytes([start[0], start[1], start[2], start[3]]) as i64,
+                    _ => i64::from_be_bytes(start.try_into().unwrap_or_default()),
+                };
+                (start, index as usize)
+            })
+            .collect();
+        // Each local time type is a 32-bit offset, a DST flag and a name index
+        let offsets = block
+            .get(..types * 6)?
+            .chunks_exact(6)
+            .map(|info| i32::from_be_bytes([info[0], info[1], info[2], info[3]]) as i64)
+            .collect();
+        Some(TimeZone {
+            transitions,
+            offsets,
+            rule,
+        })
+    }
+
+    /// Seconds ahead of UTC at `time`, in seconds since the epoch
+    fn offset_at(&self, time: i64) -> i64 {
+        let after_last = self
+            .transitions
+            .last()
+            .is_none_or(|(last, _)| time >= *last);
+        if let (true, Some(rule)) = (after_last, &self.rule) {
+            return rule.offset_at(time);
+        }
+        let index = match self
+            .transitions
+            .partition_point(|(start, _)| *start <= time)
+        {
+            0 => 0,
+            count => self.transitions[count - 1].1,
+        };
+        self.offsets.get(index).copied().unwrap_or(0)
+    }
+}
+
+/// A POSIX `TZ` rule such as `CET-1CEST,M3.5.0,M10.5.0/3`
+struct PosixRule {
+    /// Seconds ahead of UTC outside daylight saving time
+    standard: i64,
+    /// Daylight saving offset and when it starts and ends, in local time
+    daylight: Option<(i64, RuleDate, RuleDate)>,
+}
+
+/// A day of the year in a POSIX rule and the time of day on it
+#[derive(Clone, Copy)]
+enum RuleDate {
+    /// Day `n` from 1 to 365, never counting February 29
+    Julian(i64, i64),
+    /// Day `n` from 0 to 365, counting February 29
+    Day(i64, i64),
+    /// Weekday `d` (0 for Sunday) of week `w` (5 for the last) of month `m`
+    Weekday(i64, i64, i64, i64),
+}
+
+impl PosixRule {
+    fn parse(text: &str) -> Option<PosixRule> {
+        let rest = skip_zone_name(text)?;
+        let (standard, rest) = parse_offset(rest)?;
+        // POSIX offsets count west of UTC
+        let standard = -standard;
+        if rest.is_empty() {
+            return Some(PosixRule {
+                standard,
+                daylight: None,
+            });
+        }
+        let rest = skip_zone_name(rest)?;
+        let (daylight, rest) = match rest.starts_with(',') || rest.is_empty() {
+            true => (standard + 3600, rest),
+            false => parse_offset(rest).map(|(offset, rest)| (-offset, rest))?,
+        };
+        // The US rules are the default when none are given
+        let rules = rest.strip_prefix(',').unwrap_or("M3.2.0,M11.1.0");
+        let (start, end) = rules.split_once(',')?;
+        Some(PosixRule {
+            standard,
+            daylight: Some((daylight, RuleDate::parse(start)?, RuleDate::parse(end)?)),
+        })
+    }
+
+    fn offset_at(&self, time: i64) -> i64 {
+        let Some((daylight, start, end)) = self.daylight else {
+            return self.standard;
+        };
+        let year = year_of(time + self.standard);
+        // Daylight saving starts in standard time and ends in daylight time
+        let starts = start.local_time(year) - self.standard;
+        let ends = end.local_time(year) - daylight;
+        let in_daylight = match starts < ends {
+            true => starts <= time && time < ends,
+            // Southern hemisphere, daylight saving time spans the new year
+            false => !(ends <= time && time < starts),
+        };
+        match in_daylight {
+            true => daylight,
+            false => self.standard,
+        }
+    }
+}
+
+impl RuleDate {
+    fn parse(text: &str) -> Option<RuleDate> {
+        let (date, time) = match text.split_once('/') {
+            Some((date, time)) => (
+                date,
+                parse_offset(time).filter(|(_, rest)| rest.is_empty())?.0,
+            ),
+            None => (text, 2 * 3600),
+        };
+        if let Some(day) = date.strip_prefix('J') {
+            return Some(RuleDate::Julian(day.parse().ok()?, time));
+        }
+        if let Some(fields) = date.strip_prefix('M') {
+            let mut fields = fields.split('.').map(|field| field.parse::<i64>().ok());
+            let (month, week, weekday) = (fields.next()??, fields.next()??, fields.next()??);
+            return Some(RuleDate::Weekday(month, week, weekday, time));
+        }
+        Some(RuleDate::Day(date.parse().ok()?, time))
+    }
+
+    /// Seconds since the epoch, in local time, at which the rule falls in `year`
+    fn local_time(self, year: i64) -> i64 {
+        let new_year = days_from_civil(year, 1, 1);
+        let (day, time) = match self {
+            RuleDate::Julian(day, time) => {
+                let leap_day = (is_leap_year(year) && day >= 60) as i64;
+                (new_year + day - 1 + leap_day, time)
+            }
+            RuleDate::Day(day, time) => (new_year + day, time),
+            RuleDate::Weekday(month, week, weekday, time) => {
+                let first = days_from_civil(year, month, 1);
+                // 1970-01-01 was a Thursday
+                let first_weekday = (first + 4).rem_euclid(7);
+                let mut day = first + (weekday - first_weekday).rem_euclid(7) + (week - 1) * 7;
+                let next_month = match month {
+                    12 => days_from_civil(year + 1, 1, 1),
+                    _ => days_from_civil(year, month + 1, 1),
+                };
+                while day >= next_month {
+                    day -= 7;
+                }
+                (day, time)
+            }
+        };
+        day * DAY + time
+    }
+}
+
+/// The year `time`, in seconds since the epoch, falls in
+fn year_of(time: i64) -> i64 {
+    let days = time.div_euclid(DAY);
+    let mut year = 1970 + days.div_euclid(365);
+    while days_from_civil(year, 1, 1) > days {
+        year -= 1;
+    }
+    while days_from_civil(year + 1, 1, 1) <= days {
+        year += 1;
+    }
+    year
+}
+
+/// What follows a zone name such as `CET` or `<+03>`
+fn skip_zone_name(text: &str) -> Option<&str> {
+    if let Some(quoted) = text.strip_prefix('<') {
+        return Some(&quoted[quoted.find('>')? + 1..]);
+    }
+    let end = text
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(text.len());
+    (end >= 3).then(|| &text[end..])
+}
+
+/// An `[+-]hh[:mm[:ss]]` offset in seconds, and what follows it
+fn parse_offset(text: &str) -> Option<(i64, &str)> {
+    let (sign, text) = match text.as_bytes().first()? {
+        b'-' => (-1, &text[1..]),
+        b'+' => (1, &text[1..]),
+        _ => (1, text),
+    };
+    let end = text
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(text.len());
+    let mut seconds = 0;
+    let mut unit = 3600;
+    for field in text[..end].split(':') {
+        seconds += field.parse::<i64>().ok()? * unit;
+        unit /= 60;
+    }
+    Some((sign * seconds, &text[end..]))
+}
@@ -0,0 +1,145 @@
+/// Settings used when opening the output stream
+#[derive(Clone, Debug, Default)]
+pub struct PlayerConfig {
+    /// Audio host to use; the platform default when `None`
+    pub host: Option<cpal::HostId>,
+    /// Output device name or index; the host's default device when `None`
+    pub device: Option<String>,
+    /// Preferred output rate, used when the device supports it so that
+    /// material at this rate plays without resampling
+    pub sample_rate: Option<u32>,
+    /// Frames per device callback; the host's default when `None`.
+    /// Smaller buffers lower latency, larger ones help machines that glitch.
+    pub buffer_frames: Option<u32>,
+    /// Target output latency, turned into a buffer size at the stream's
+    /// sample rate. Ignored when `buffer_frames` is set.
+    pub latency_ms: Option<u32>,
+    /// Bit-perfect output: the device must run at `sample_rate`, and the
+    /// master chain and voice gains are bypassed. Falls back to normal
+    /// output when the device can not do the rate, see [`Mixer::is_bit_perfect`],
+    /// and tracks only reach the device exactly when its sample format holds
+    /// all their bits, see [`Mixer::is_bit_perfect_for`].
+    /// The audio backend has no way to request exclusive access (WASAPI
+    /// exclusive, CoreAudio hog mode), so pick a hardware device such as
+    /// ALSA's `hw:` to keep other applications out of the mix.
+    ///
+    /// [`Mixer::is_bit_perfect`]: crate::Mixer::is_bit_perfect
+    /// [`Mixer::is_bit_perfect_for`]: crate::Mixer::is_bit_perfect_for
+    pub bit_perfect: bool,
+}
+
+impl PlayerConfig {
+    pub fn with_host(host: cpal::HostId) -> Self {
+        PlayerConfig {
+            host: Some(host),
+            ..PlayerConfig::default()
+        }
+    }
+
+    /// Picks the stream config and sample format for a device. Starts from the
+    /// device default and switches to the preferred sample rate when a
+    /// supported config allows it, preferring float over integer formats.
+    pub(crate) fn stream_config(
+        &self,
+        device: &cpal::Device,
+    ) -> Result<(cpal::StreamConfig, cpal::SampleFormat), String> {
+        use cpal::traits::DeviceTrait;
+
+        let default = device
+            .default_output_config()
+            .map_err(|e| format!("failed to query output config: {}", e))?;
+        let mut sample_format = default.sample_format();
+        let mut config = cpal::StreamConfig {
+            channels: default.channels(),
+            sample_rate: default.sample_rate(),
+            buffer_size: cpal::BufferSize::Default,
+        };
+
+        let ranges: Vec<cpal::SupportedStreamConfigRange> = device
+            .supported_output_configs()
+            .map(|configs| configs.collect())
+            .unwrap_or_default();
+        let rate = self.sample_rate.unwrap_or(config.sample_rate.0);
+        let formats = [
+            cpal::SampleFormat::F32,
+            cpal::SampleFormat::I16,
+            cpal::SampleFormat::U16,
+        ];
+
+        for format in formats {
+            let supported = ranges.iter().any(|range| {
+                range.channels() == config.channels
+                    && range.sample_format() == format
+                    && range.min_sample_rate().0 <= rate
+                    && rate <= range.max_sample_rate().0
+            });
+            if supported {
+                config.sample_rate = cpal::SampleRate(rate);
+                sample_format = format;
+                break;
+            }
+        }
+
+        if let Some(frames) = self.buffer_size(config.sample_rate.0) {
+            // Keep the request within what the device reports it can do
+            let limits = ranges.iter().find_map(|range| match range.buffer_size() {
+                cpal::SupportedBufferSize::Range { min, max }
+                    if range.channels() == config.channels
+                        && range.sample_format() == sample_format =>
+                {
+                    Some((*min, *max))
+                }
+                _ => None,
+            });
+            let frames = match limits {
+                Some((min, max)) => frames.clamp(min, max.max(min)),
+                None => frames,
+            };
+            config.buffer_size = cpal::BufferSize::Fixed(frames);
+        }
+        Ok((config, sample_format))
+    }
+
+    /// Requested frames per callback at `sample_rate`, if any
+    pub fn buffer_size(&self, sample_rate: u32) -> Option<u32> {
+        match (self.buffer_frames, self.latency_ms) {
+            (Some(frames), _) => Some(frames.max(1)),
+            (None, Some(ms)) => Some((sample_rate as u64 * ms as u64 / 1000).max(1) as u32),
+            (None, None) => None,
+        }
+    }
+
+    /// Opens the configured output device
+    pub(crate) fn output_device(&self) -> Result<cpal::Device, String> {
+        use cpal::traits::HostTrait;
+
+        let host = match self.host {
+            Some(id) => cpal::host_from_id(id)
+                .map_err(|e| format!("failed to open host {}: {}", id.name(), e))?,
+            None => cpal::default_host(),
+        };
+        match self.device.as_deref() {
+            Some(selector) => crate::device::find_output_device(&host, selector),
+            None => host
+                .default_output_device()
+                .ok_or_else(|| String::from("no output device available")),
+        }
+    }
+}
+
+/// Looks up a host by name (`alsa`, `jack`, `wasapi`, `asio`, `coreaudio`...).
+/// Only hosts compiled into this build can be found.
+pub fn parse_host(name: &str) -> Result<cpal::HostId, String> {
+    cpal::ALL_HOSTS
+        .iter()
+        .copied()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let known: Vec<&str> = cpal::ALL_HOSTS.iter().map(|id| id.name()).collect();
+            format!(
+                "unknown or unsupported host: {} (available: {})",
+                name,
+                known.join(", ")
+            )
+        })
+}
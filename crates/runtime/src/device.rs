@@ -0,0 +1,122 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+
+/// A supported output configuration range of a device
+#[derive(Clone, Debug)]
+pub struct ConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: cpal::SampleFormat,
+    /// Smallest and largest buffer size in frames, if the host reports them
+    pub buffer_frames: Option<(u32, u32)>,
+}
+
+/// An output device and what it supports
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub index: usize,
+    pub name: String,
+    pub is_default: bool,
+    pub configs: Vec<ConfigRange>,
+}
+
+/// An audio host (ALSA, JACK, WASAPI, CoreAudio...) and its output devices
+#[derive(Clone, Debug)]
+pub struct HostInfo {
+    pub id: cpal::HostId,
+    pub name: String,
+    pub is_default: bool,
+    pub devices: Vec<DeviceInfo>,
+}
+
+/// Lists every available host with its output devices and supported configs
+pub fn list_hosts() -> Vec<HostInfo> {
+    let default_id = cpal::default_host().id();
+    cpal::available_hosts()
+        .into_iter()
+        .filter_map(|id| {
+            let host = cpal::host_from_id(id).ok()?;
+            Some(HostInfo {
+                id,
+                name: id.name().to_string(),
+                is_default: id == default_id,
+                devices: list_output_devices(&host),
+            })
+        })
+        .collect()
+}
+
+/// Lists the output devices of a host, in the order used for index selection
+pub fn list_output_devices(host: &cpal::Host) -> Vec<DeviceInfo> {
+    let default_name = host
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+
+    let Ok(devices) = host.output_devices() else {
+        return Vec::new();
+    };
+
+    devices
+        .enumerate()
+        .map(|(index, device)| {
+            let name = device.name().unwrap_or_else(|_| String::from("<unknown>"));
+            let configs = device
+                .supported_output_configs()
+                .map(|configs| configs.map(config_range).collect())
+                .unwrap_or_default();
+            DeviceInfo {
+                index,
+                is_default: Some(&name) == default_name.as_ref(),
+                name,
+                configs,
+            }
+        })
+        .collect()
+}
+
+fn config_range(range: cpal::SupportedStreamConfigRange) -> ConfigRange {
+    let buffer_frames = match range.buffer_size() {
+        cpal::SupportedBufferSize::Range { min, max } => Some((*min, *max)),
+        cpal::SupportedBufferSize::Unknown => None,
+    };
+    ConfigRange {
+        channels: range.channels(),
+        min_sample_rate: range.min_sample_rate().0,
+        max_sample_rate: range.max_sample_rate().0,
+        sample_format: range.sample_format(),
+        buffer_frames,
+    }
+}
+
+/// Picks an output device by index (as listed by `list_output_devices`),
+/// by exact name, or by a case-insensitive part of its name
+pub fn find_output_device(host: &cpal::Host, selector: &str) -> Result<cpal::Device, String> {
+    let devices: Vec<cpal::Device> = host
+        .output_devices()
+        .map_err(|e| format!("failed to list output devices: {}", e))?
+        .collect();
+
+    if let Ok(index) = selector.parse::<usize>() {
+        return devices
+            .into_iter()
+            .nth(index)
+            .ok_or_else(|| format!("no output device with index {}", index));
+    }
+
+    let names: Vec<String> = devices
+        .iter()
+        .map(|device| device.name().unwrap_or_default())
+        .collect();
+    let needle = selector.to_lowercase();
+    let position = names
+        .iter()
+        .position(|name| name == selector)
+        .or_else(|| {
+            names
+                .iter()
+                .position(|name| name.to_lowercase().contains(&needle))
+        })
+        .ok_or_else(|| format!("no output device matching \"{}\"", selector))?;
+
+    Ok(devices.into_iter().nth(position).unwrap())
+}
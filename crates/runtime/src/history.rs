@@ -0,0 +1,170 @@
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A track that was played
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    /// When playback of the track started
+    pub played_at: SystemTime,
+    pub path: PathBuf,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+    /// How far into the track playback got
+    pub position: Duration,
+}
+
+impl HistoryEntry {
+    /// How much of the track was played, from 0 to 1
+    pub fn completion(&self) -> Option<f64> {
+        let duration = self.duration.filter(|duration| !duration.is_zero())?;
+        Some((self.position.as_secs_f64() / duration.as_secs_f64()).min(1.0))
+    }
+
+    /// One `started<TAB>position<TAB>duration<TAB>artist<TAB>title<TAB>album<TAB>path`
+    /// line, times in seconds and missing values empty
+    fn to_line(&self) -> String {
+        let seconds = |time: Duration| format!("{:.3}", time.as_secs_f64());
+        let started = self
+            .played_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let fields = [
+            started.to_string(),
+            seconds(self.position),
+            self.duration.map(seconds).unwrap_or_default(),
+            self.artist.clone().unwrap_or_default(),
+            self.title.clone().unwrap_or_default(),
+            self.album.clone().unwrap_or_default(),
+            self.path.display().to_string(),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| {
+                field
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .collect()
+            })
+            .collect();
+        format!("{}\n", fields.join("\t"))
+    }
+
+    fn from_line(line: &str) -> Option<HistoryEntry> {
+        let mut fields = line.splitn(7, '\t');
+        let started: u64 = fields.next()?.parse().ok()?;
+        let seconds = |field: &str| Duration::try_from_secs_f64(field.parse().ok()?).ok();
+        let position = seconds(fields.next()?)?;
+        let duration = seconds(fields.next()?);
+        let mut text = || Some(fields.next()?).filter(|text| !text.is_empty());
+        let (artist, title, album) = (text(), text(), text());
+        Some(HistoryEntry {
+            played_at: UNIX_EPOCH + Duration::from_secs(started),
+            artist: artist.map(str::to_string),
+            title: title.map(str::to_string),
+            album: album.map(str::to_string),
+            path: PathBuf::from(text()?),
+            duration,
+            position,
+        })
+    }
+}
+
+/// Reads every track in the history kept at `path`, oldest first; a file
+/// that doesn't exist yet is an empty history
+pub fn read_history(path: &Path) -> Result<Vec<HistoryEntry>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(text.lines().filter_map(HistoryEntry::from_line).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Records tracks as they play, one line per track appended to a file. The
+/// line of the track being played is rewritten as it goes on, so the history
+/// keeps how far it got even if playback is killed.
+#[derive(Debug)]
+pub struct HistoryLog {
+    path: PathBuf,
+    /// The track being played, with where its line starts and ends
+    current: Option<(HistoryEntry, u64, u64)>,
+}
+
+impl HistoryLog {
+    pub fn new(path: &Path) -> Self {
+        HistoryLog {
+            path: path.to_path_buf(),
+            current: None,
+        }
+    }
+
+    /// Adds a line for a track that just started, creating the file and its
+    /// directory if needed
+    pub fn start_track(&mut self, entry: HistoryEntry) -> Result<(), String> {
+        let write_error =
+            |e: std::io::Error| format!("failed to write {}: {}", self.path.display(), e);
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(write_error)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(write_error)?;
+        let start = file.metadata().map_err(write_error)?.len();
+        let line = entry.to_line();
+        file.write_all(line.as_bytes()).map_err(write_error)?;
+        self.current = Some((entry, start, start + line.len() as u64));
+        Ok(())
+    }
+
+    /// Updates how far into the current track playback got
+    pub fn update(&mut self, position: Duration) -> Result<(), String> {
+        let write_error =
+            |e: std::io::Error| format!("failed to write {}: {}", self.path.display(), e);
+        let Some((entry, start, end)) = &mut self.current else {
+            return Ok(());
+        };
+        entry.position = position;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .map_err(write_error)?;
+        // Another player may have added lines since, which are kept
+        if file.metadata().map_err(write_error)?.len() != *end {
+            self.current = None;
+            return Ok(());
+        }
+        let line = entry.to_line();
+        file.set_len(*start).map_err(write_error)?;
+        file.seek(SeekFrom::Start(*start)).map_err(write_error)?;
+        file.write_all(line.as_bytes()).map_err(write_error)?;
+        *end = *start + line.len() as u64;
+        Ok(())
+    }
+
+    /// Records that the current track was stopped at `position` and moves
+    /// on from it
+    pub fn stop_track(&mut self, position: Duration) -> Result<(), String> {
+        let result = self.update(position);
+        self.current = None;
+        result
+    }
+
+    /// Marks the current track as played to the end and moves on from it
+    pub fn finish(&mut self) -> Result<(), String> {
+        let end = self
+            .current
+            .as_ref()
+            .and_then(|(entry, _, _)| entry.duration);
+        if let Some(duration) = end {
+            self.update(duration)?;
+        }
+        self.current = None;
+        Ok(())
+    }
+}
@@ -1 +1,40 @@
 // Runtime crate
+
+pub mod bookmarks;
+pub mod chain;
+pub mod clock;
+pub mod config;
+pub mod device;
+pub mod history;
+pub mod mixer;
+pub mod player;
+pub mod podcasts;
+pub mod queue;
+pub mod replaygain;
+pub mod resample;
+pub mod resume;
+pub mod ring;
+pub mod sink;
+pub mod sound;
+pub mod source;
+pub mod tap;
+
+pub use bookmarks::{Bookmark, BookmarkStore};
+pub use chain::{Chain, SharedChain};
+pub use clock::{next_local_time, utc_offset};
+pub use config::{parse_host, PlayerConfig};
+pub use cpal::HostId;
+pub use device::{list_hosts, ConfigRange, DeviceInfo, HostInfo};
+pub use history::{read_history, HistoryEntry, HistoryLog};
+pub use mixer::{DeviceEvent, Mixer, SourceHandle, SourceId};
+pub use player::{AudioPlayer, LoopRegion, PlaybackOptions, PlayerStats, QueueSource};
+pub use podcasts::{Podcast, PodcastEpisode, PodcastStore};
+pub use queue::{PlaybackQueue, RepeatMode, SharedQueue};
+pub use replaygain::{ReplayGainConfig, ReplayGainMode};
+pub use resample::{Resampled, Resampler};
+pub use resume::{file_key, ResumeStore};
+pub use ring::RingBuffer;
+pub use sink::WavSink;
+pub use sound::{PlayParams, SoundHandle, SoundInstance};
+pub use source::{AudioSource, FileSource, Processed};
+pub use tap::Tap;
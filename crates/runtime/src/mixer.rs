@@ -0,0 +1,684 @@
+use std::collections::VecDeque;
+use std::f32::consts::{FRAC_PI_4, SQRT_2};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+use mogbox_engine::{ChannelMapper, Processor};
+
+use crate::chain::{Chain, SharedChain};
+use crate::config::PlayerConfig;
+use crate::resample::{Resampled, Resampler};
+use crate::source::AudioSource;
+
+/// Identifies a source that was handed to the mixer
+pub type SourceId = u64;
+
+/// Live parameters of a voice, shared between its handle and the audio thread
+struct VoiceParams {
+    gain: AtomicU32,
+    pan: AtomicU32,
+    stopped: AtomicBool,
+    finished: AtomicBool,
+}
+
+impl VoiceParams {
+    fn new() -> Self {
+        VoiceParams {
+            gain: AtomicU32::new(1.0f32.to_bits()),
+            pan: AtomicU32::new(0.0f32.to_bits()),
+            stopped: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    fn pan(&self) -> f32 {
+        f32::from_bits(self.pan.load(Ordering::Relaxed))
+    }
+}
+
+/// Constant-power pan law, scaled so a centered source keeps unity gain.
+/// `pan` goes from -1.0 (hard left) to 1.0 (hard right).
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+    (angle.cos() * SQRT_2, angle.sin() * SQRT_2)
+}
+
+struct Voice {
+    id: SourceId,
+    source: Box<dyn AudioSource>,
+    params: Arc<VoiceParams>,
+    mapper: ChannelMapper,
+    scratch: Vec<f32>,
+    mapped: Vec<f32>,
+}
+
+impl Voice {
+    fn is_done(&self) -> bool {
+        self.params.stopped.load(Ordering::Relaxed) || self.params.finished.load(Ordering::Relaxed)
+    }
+
+    /// Reads `frames` frames from the source and adds them onto `out`.
+    /// Gain and pan are left out in bit-perfect mode.
+    fn mix_into(&mut self, out: &mut [f32], out_channels: usize, bit_perfect: bool) {
+        let frames = out.len() / out_channels;
+        self.scratch.resize(frames * self.mapper.inputs(), 0.0);
+
+        let read = self.source.read(&mut self.scratch);
+        if read < self.scratch.len() {
+            self.params.finished.store(true, Ordering::Relaxed);
+        }
+        self.mapper
+            .map_into(&self.scratch[..read], &mut self.mapped);
+
+        let gain = if bit_perfect { 1.0 } else { self.params.gain() };
+        let (left, right) = if out_channels >= 2 && !bit_perfect {
+            pan_gains(self.params.pan())
+        } else {
+            (1.0, 1.0)
+        };
+
+        for (frame, src) in out
+            .chunks_mut(out_channels)
+            .zip(self.mapped.chunks(out_channels))
+        {
+            for (ch, (sample, value)) in frame.iter_mut().zip(src).enumerate() {
+                let pan = match ch {
+                    0 => left,
+                    1 => right,
+                    _ => 1.0,
+                };
+                *sample += value * gain * pan;
+            }
+        }
+    }
+}
+
+/// Controls a single source playing on the mixer
+#[derive(Clone)]
+pub struct SourceHandle {
+    id: SourceId,
+    params: Arc<VoiceParams>,
+}
+
+impl SourceHandle {
+    /// A handle for a source that could not be started
+    fn finished() -> Self {
+        let params = VoiceParams::new();
+        params.finished.store(true, Ordering::Relaxed);
+        SourceHandle {
+            id: SourceId::MAX,
+            params: Arc::new(params),
+        }
+    }
+
+    pub fn id(&self) -> SourceId {
+        self.id
+    }
+
+    /// Sets the linear gain of the source (1.0 = unity)
+    pub fn set_gain(&self, gain: f32) {
+        self.params
+            .gain
+            .store(gain.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_gain_db(&self, db: f32) {
+        self.set_gain(mogbox_engine::db_to_linear(db));
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.params.gain()
+    }
+
+    /// Sets the stereo position, from -1.0 (left) through 0.0 (center) to 1.0 (right)
+    pub fn set_pan(&self, pan: f32) {
+        self.params
+            .pan
+            .store(pan.clamp(-1.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn pan(&self) -> f32 {
+        self.params.pan()
+    }
+
+    /// Stops the source; the mixer drops it on the next callback
+    pub fn stop(&self) {
+        self.params.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the source is still producing audio
+    pub fn is_playing(&self) -> bool {
+        !self.params.stopped.load(Ordering::Relaxed)
+            && !self.params.finished.load(Ordering::Relaxed)
+    }
+}
+
+struct MixerState {
+    voices: Vec<Voice>,
+    next_id: SourceId,
+}
+
+/// Owns the output stream and sums any number of sources into it.
+/// Sources are dropped automatically once they run out of samples.
+pub struct Mixer {
+    stream: cpal::Stream,
+    state: Arc<Mutex<MixerState>>,
+    chain: SharedChain,
+    sample_rate: u32,
+    channels: usize,
+    sample_format: cpal::SampleFormat,
+    config: PlayerConfig,
+    /// Set by the stream when the device disappears
+    lost: Arc<AtomicBool>,
+    recovery: Option<Recovery>,
+    paused: bool,
+    bit_perfect: bool,
+}
+
+/// Progress of reopening a lost device
+struct Recovery {
+    reported: bool,
+    last_attempt: Option<Instant>,
+}
+
+/// Time between attempts to reopen a lost device
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Produces the mixed output for one callback. Every voice is read with the
+/// voices locked, and the master chain runs with its lock held, so mixer
+/// calls that take either lock wait on the callback, and the other way round.
+struct Renderer {
+    state: Arc<Mutex<MixerState>>,
+    chain: SharedChain,
+    channels: usize,
+    /// Skip the master chain and voice gains so samples reach the device untouched
+    bit_perfect: bool,
+}
+
+impl Renderer {
+    fn render(&mut self, data: &mut [f32]) {
+        data.fill(0.0);
+        if let Ok(mut state) = self.state.lock() {
+            state.voices.retain(|voice| !voice.is_done());
+            for voice in state.voices.iter_mut() {
+                voice.mix_into(data, self.channels, self.bit_perfect);
+            }
+        }
+        if self.bit_perfect {
+            return;
+        }
+        if let Ok(mut chain) = self.chain.lock() {
+            chain.process(data);
+        }
+    }
+}
+
+/// Adapts the mix to a device that came back from a reconnect with a
+/// different sample rate or channel count than the mixer runs at
+struct Conversion {
+    resampler: Option<Resampler>,
+    mapper: ChannelMapper,
+    chunk: Vec<f32>,
+    mapped: Vec<f32>,
+    pending: VecDeque<f32>,
+}
+
+impl Conversion {
+    /// Frames rendered at a time when converting
+    const CHUNK_FRAMES: usize = 512;
+
+    fn new(mix_rate: u32, mix_channels: usize, rate: u32, channels: usize) -> Result<Self, String> {
+        let resampler = if mix_rate != rate {
+            Some(Resampler::new(mix_rate, rate, mix_channels)?)
+        } else {
+            None
+        };
+        Ok(Conversion {
+            resampler,
+            mapper: ChannelMapper::new(mix_channels, channels),
+            chunk: Vec::new(),
+            mapped: Vec::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn render(&mut self, renderer: &mut Renderer, data: &mut [f32]) {
+        while self.pending.len() < data.len() {
+            self.chunk
+                .resize(Self::CHUNK_FRAMES * renderer.channels, 0.0);
+            renderer.render(&mut self.chunk);
+            match self.resampler.as_mut() {
+                Some(resampler) => {
+                    let resampled = resampler.process(&self.chunk);
+                    self.mapper.map_into(&resampled, &mut self.mapped);
+                }
+                None => self.mapper.map_into(&self.chunk, &mut self.mapped),
+            }
+            self.pending.extend(self.mapped.iter());
+        }
+        let len = data.len();
+        for (out, sample) in data.iter_mut().zip(self.pending.drain(..len)) {
+            *out = sample;
+        }
+    }
+}
+
+/// Builds an output stream in the device's sample format. Mixing always
+/// happens in f32 and is converted to `T` at the end of the callback.
+fn build_stream<T: DeviceSample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut renderer: Renderer,
+    mut conversion: Option<Conversion>,
+    lost: Arc<AtomicBool>,
+) -> Result<cpal::Stream, String> {
+    let mut mix: Vec<f32> = Vec::new();
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                mix.resize(data.len(), 0.0);
+                match conversion.as_mut() {
+                    Some(conversion) => conversion.render(&mut renderer, &mut mix),
+                    None => renderer.render(&mut mix),
+                }
+                for (out, &sample) in data.iter_mut().zip(mix.iter()) {
+                    *out = T::from_mix(sample);
+                }
+            },
+            move |err| {
+                eprintln!("an error occurred on the output stream: {}", err);
+                if let cpal::StreamError::DeviceNotAvailable = err {
+                    lost.store(true, Ordering::Relaxed);
+                }
+            },
+        )
+        .map_err(|e| format!("failed to build output stream: {}", e))
+}
+
+/// A sample format devices take. Integers are scaled the way decoders scale
+/// them to floats, by a power of two, so they come back out exactly.
+trait DeviceSample: cpal::Sample {
+    fn from_mix(sample: f32) -> Self;
+}
+
+impl DeviceSample for f32 {
+    fn from_mix(sample: f32) -> Self {
+        sample.clamp(-1.0, 1.0)
+    }
+}
+
+impl DeviceSample for i16 {
+    fn from_mix(sample: f32) -> Self {
+        (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16
+    }
+}
+
+impl DeviceSample for u16 {
+    fn from_mix(sample: f32) -> Self {
+        (i16::from_mix(sample) as i32 + 32768) as u16
+    }
+}
+
+/// Whether samples of `bits_per_sample` pass through `format` exactly
+fn holds_bits(format: cpal::SampleFormat, bits_per_sample: Option<u32>) -> bool {
+    match (format, bits_per_sample) {
+        // Integers of up to 24 bits are exact in an f32
+        (cpal::SampleFormat::F32, bits) => bits.is_none_or(|bits| bits <= 24),
+        (cpal::SampleFormat::I16 | cpal::SampleFormat::U16, Some(bits)) => bits <= 16,
+        _ => false,
+    }
+}
+
+/// Changes of the output device the UI may want to show
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceEvent {
+    /// The output device went away; playback is held until it can be reopened
+    Lost,
+    /// Output was reopened, on `device`, and playback carries on where it was
+    Recovered { device: String },
+}
+
+/// Builds and starts a stream in whichever sample format was negotiated
+fn open_stream(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sample_format: cpal::SampleFormat,
+    renderer: Renderer,
+    conversion: Option<Conversion>,
+    lost: Arc<AtomicBool>,
+) -> Result<cpal::Stream, String> {
+    let stream = match sample_format {
+        cpal::SampleFormat::F32 => build_stream::<f32>(device, config, renderer, conversion, lost),
+        cpal::SampleFormat::I16 => build_stream::<i16>(device, config, renderer, conversion, lost),
+        cpal::SampleFormat::U16 => build_stream::<u16>(device, config, renderer, conversion, lost),
+    }?;
+    stream
+        .play()
+        .map_err(|e| format!("failed to start output stream: {}", e))?;
+    Ok(stream)
+}
+
+impl Mixer {
+    /// Opens the default output device and starts an (initially silent) stream
+    pub fn new() -> Result<Self, String> {
+        Mixer::with_config(&PlayerConfig::default())
+    }
+
+    /// Opens the output device chosen by `config` and starts an (initially silent) stream
+    pub fn with_config(config: &PlayerConfig) -> Result<Self, String> {
+        let device = config.output_device()?;
+        let (stream_config, sample_format) = config.stream_config(&device)?;
+        let sample_rate = stream_config.sample_rate.0;
+        let channels = stream_config.channels as usize;
+
+        let state = Arc::new(Mutex::new(MixerState {
+            voices: Vec::new(),
+            next_id: 0,
+        }));
+        let chain = Chain::new().into_shared();
+        if let Ok(mut chain) = chain.lock() {
+            chain.prepare(sample_rate, channels);
+        }
+
+        // Bit-perfect only holds if the device really runs at the source rate,
+        // which can't be told without one
+        let bit_perfect = config.bit_perfect
+            && config
+                .sample_rate
+                .map(|rate| rate == sample_rate)
+                .unwrap_or(false);
+
+        let lost = Arc::new(AtomicBool::new(false));
+        let renderer = Renderer {
+            state: state.clone(),
+            chain: chain.clone(),
+            channels,
+            bit_perfect,
+        };
+        let stream = open_stream(
+            &device,
+            &stream_config,
+            sample_format,
+            renderer,
+            None,
+            lost.clone(),
+        )?;
+
+        Ok(Mixer {
+            stream,
+            state,
+            chain,
+            sample_rate,
+            channels,
+            sample_format,
+            config: config.clone(),
+            lost,
+            recovery: None,
+            paused: false,
+            bit_perfect,
+        })
+    }
+
+    /// Checks whether the output device was lost and tries to reopen it,
+    /// falling back to the host's default device if the configured one is
+    /// gone. Sources keep their position across the reconnect. Call this
+    /// regularly from the thread that owns the mixer.
+    pub fn poll_device(&mut self) -> Option<DeviceEvent> {
+        if !self.lost.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let recovery = self.recovery.get_or_insert(Recovery {
+            reported: false,
+            last_attempt: None,
+        });
+        if !recovery.reported {
+            recovery.reported = true;
+            return Some(DeviceEvent::Lost);
+        }
+        if let Some(last) = recovery.last_attempt {
+            if last.elapsed() < RETRY_INTERVAL {
+                return None;
+            }
+        }
+        recovery.last_attempt = Some(Instant::now());
+
+        let device = self.reopen().ok()?;
+        self.lost.store(false, Ordering::Relaxed);
+        self.recovery = None;
+        Some(DeviceEvent::Recovered { device })
+    }
+
+    /// Rebuilds the stream on the configured or default device, converting
+    /// the mix if the device no longer runs at the mixer's rate and layout
+    fn reopen(&mut self) -> Result<String, String> {
+        let config = PlayerConfig {
+            sample_rate: Some(self.sample_rate),
+            ..self.config.clone()
+        };
+        let device = match config.output_device() {
+            Ok(device) => device,
+            Err(_) => PlayerConfig {
+                device: None,
+                ..config.clone()
+            }
+            .output_device()?,
+        };
+
+        let (stream_config, sample_format) = config.stream_config(&device)?;
+        let rate = stream_config.sample_rate.0;
+        let channels = stream_config.channels as usize;
+        let conversion = if rate != self.sample_rate || channels != self.channels {
+            Some(Conversion::new(
+                self.sample_rate,
+                self.channels,
+                rate,
+                channels,
+            )?)
+        } else {
+            None
+        };
+
+        let bit_perfect = self.config.bit_perfect && conversion.is_none();
+        let renderer = Renderer {
+            state: self.state.clone(),
+            chain: self.chain.clone(),
+            channels: self.channels,
+            bit_perfect,
+        };
+        self.stream = open_stream(
+            &device,
+            &stream_config,
+            sample_format,
+            renderer,
+            conversion,
+            self.lost.clone(),
+        )?;
+        self.sample_format = sample_format;
+        self.bit_perfect = bit_perfect;
+        if self.paused {
+            self.pause()?;
+        }
+        Ok(device
+            .name()
+            .unwrap_or_else(|_| String::from("unknown device")))
+    }
+
+    /// Starts playing a source and returns a handle to control it with.
+    /// Sources at a different sample rate are resampled to the output rate,
+    /// and other channel layouts are up- or downmixed to the output's.
+    pub fn play<S: AudioSource + 'static>(&self, source: S) -> SourceHandle {
+        let mapper = ChannelMapper::new(source.channels(), self.channels);
+        self.play_mapped(source, mapper)
+    }
+
+    /// Like [`Mixer::play`], with an explicit mapping from the source's
+    /// channels to the output's
+    pub fn play_mapped<S: AudioSource + 'static>(
+        &self,
+        source: S,
+        mapper: ChannelMapper,
+    ) -> SourceHandle {
+        let mapper = if mapper.outputs() == self.channels {
+            mapper
+        } else {
+            mapper.then(&ChannelMapper::identity(self.channels))
+        };
+
+        let source: Box<dyn AudioSource> = if source.sample_rate() == self.sample_rate {
+            Box::new(source)
+        } else {
+            match Resampled::new(source, self.sample_rate) {
+                Ok(resampled) => Box::new(resampled),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return SourceHandle::finished();
+                }
+            }
+        };
+
+        let params = Arc::new(VoiceParams::new());
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.voices.push(Voice {
+            id,
+            source,
+            params: params.clone(),
+            mapper,
+            scratch: Vec::new(),
+            mapped: Vec::new(),
+        });
+        SourceHandle { id, params }
+    }
+
+    /// Stops and drops a source; does nothing if it already finished
+    pub fn stop(&self, id: SourceId) {
+        let mut state = self.state.lock().unwrap();
+        for voice in state.voices.iter().filter(|voice| voice.id == id) {
+            voice.params.stopped.store(true, Ordering::Relaxed);
+        }
+        state.voices.retain(|voice| voice.id != id);
+    }
+
+    /// Stops every source currently playing
+    pub fn stop_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        for voice in state.voices.iter() {
+            voice.params.stopped.store(true, Ordering::Relaxed);
+        }
+        state.voices.clear();
+    }
+
+    pub fn is_playing(&self, id: SourceId) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .voices
+            .iter()
+            .any(|voice| voice.id == id && !voice.is_done())
+    }
+
+    /// Number of sources currently playing
+    pub fn active(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .voices
+            .iter()
+            .filter(|voice| !voice.is_done())
+            .count()
+    }
+
+    /// Master chain applied to the mixed output
+    pub fn chain(&self) -> SharedChain {
+        self.chain.clone()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Sample format the device is driven with
+    pub fn sample_format(&self) -> cpal::SampleFormat {
+        self.sample_format
+    }
+
+    /// Whether the mix currently reaches the device untouched: bit-perfect
+    /// output was requested and the device runs at the source rate. Sources at
+    /// other rates or channel layouts are still converted.
+    pub fn is_bit_perfect(&self) -> bool {
+        self.bit_perfect
+    }
+
+    /// Whether samples of `bits_per_sample` reach the device exactly: the mix
+    /// is bit-perfect and the device's sample format holds every bit. Lossy
+    /// sources, without a bit depth, only do on float devices.
+    pub fn is_bit_perfect_for(&self, bits_per_sample: Option<u32>) -> bool {
+        self.bit_perfect && holds_bits(self.sample_format, bits_per_sample)
+    }
+
+    /// Pauses the output stream; sources keep their position
+    pub fn pause(&mut self) -> Result<(), String> {
+        self.paused = true;
+        self.stream
+            .pause()
+            .map_err(|e| format!("failed to pause output stream: {}", e))
+    }
+
+    pub fn resume(&mut self) -> Result<(), String> {
+        self.paused = false;
+        self.stream
+            .play()
+            .map_err(|e| format!("failed to resume output stream: {}", e))
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_samples_come_back_out_exactly() {
+        // Decoders turn 16 bit samples into floats by dividing by 2^15
+        for sample in i16::MIN..=i16::MAX {
+            let mixed = sample as f32 / 32768.0;
+            assert_eq!(i16::from_mix(mixed), sample);
+            assert_eq!(u16::from_mix(mixed), (sample as i32 + 32768) as u16);
+        }
+    }
+
+    #[test]
+    fn device_samples_clip() {
+        assert_eq!(i16::from_mix(1.5), i16::MAX);
+        assert_eq!(i16::from_mix(-1.5), i16::MIN);
+        assert_eq!(u16::from_mix(1.0), u16::MAX);
+        assert_eq!(u16::from_mix(-1.0), 0);
+        assert_eq!(f32::from_mix(2.0), 1.0);
+    }
+
+    #[test]
+    fn formats_holding_a_bit_depth() {
+        assert!(holds_bits(cpal::SampleFormat::F32, Some(24)));
+        assert!(holds_bits(cpal::SampleFormat::F32, None));
+        assert!(!holds_bits(cpal::SampleFormat::F32, Some(32)));
+        assert!(holds_bits(cpal::SampleFormat::I16, Some(16)));
+        assert!(!holds_bits(cpal::SampleFormat::I16, Some(24)));
+        assert!(!holds_bits(cpal::SampleFormat::U16, None));
+    }
+}
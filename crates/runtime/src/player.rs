@@ -0,0 +1,1269 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use mogbox_engine::{ChannelMapper, ChannelRouting, Gain, Processor, SilenceSkipper, SkipSilence};
+use mogbox_io::{stdin, AudioFile, Chapter, RawFormat};
+
+use crate::config::PlayerConfig;
+use crate::mixer::{DeviceEvent, Mixer, SourceHandle};
+use crate::queue::{PlaybackQueue, SharedQueue};
+use crate::replaygain::ReplayGainConfig;
+use crate::resample::Resampler;
+use crate::ring::RingBuffer;
+use crate::source::{AudioSource, FileSource};
+
+/// Seconds of decoded audio kept ahead of the output
+const READ_AHEAD_SECS: usize = 1;
+
+/// Samples decoded per read on the decoder thread
+const DECODE_CHUNK: usize = 4096;
+
+/// How far into a chapter going back restarts it instead of going to the
+/// previous one, as players do with tracks
+const CHAPTER_RESTART: Duration = Duration::from_secs(3);
+
+/// Stretch of a track the current bitrate is measured over
+const BITRATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Marks the output frame at which a track starts
+#[derive(Clone, Copy)]
+struct Boundary {
+    frame: u64,
+    track: usize,
+    /// Where in the track playback starts, when it doesn't from the beginning
+    offset: Duration,
+}
+
+struct PlayerShared {
+    ring: RingBuffer,
+    queue: SharedQueue,
+    /// Frames handed to the output since playback started
+    played: AtomicU64,
+    /// The audible track and the frame it started at
+    current: Mutex<Option<Boundary>>,
+    errors: Mutex<Vec<(PathBuf, String)>>,
+    /// Callbacks that found the ring empty before the end of the queue
+    underruns: AtomicU64,
+    /// Frames of silence played because of underruns
+    underrun_frames: AtomicU64,
+    /// Set once the sleep timer ran out and stopped playback
+    slept: AtomicBool,
+    looping: Mutex<Option<LoopRegion>>,
+    /// Bitrate of the file data last decoded, with the track it is from
+    bitrate: Mutex<Option<(usize, u64)>>,
+    /// Title a radio stream last announced, with the track it is from
+    stream_title: Mutex<Option<(usize, String)>>,
+}
+
+impl PlayerShared {
+    fn new(queue: SharedQueue, capacity: usize, looping: Option<LoopRegion>) -> Self {
+        PlayerShared {
+            ring: RingBuffer::new(capacity),
+            queue,
+            played: AtomicU64::new(0),
+            current: Mutex::new(None),
+            errors: Mutex::new(Vec::new()),
+            underruns: AtomicU64::new(0),
+            underrun_frames: AtomicU64::new(0),
+            slept: AtomicBool::new(false),
+            looping: Mutex::new(looping),
+            bitrate: Mutex::new(None),
+            stream_title: Mutex::new(None),
+        }
+    }
+}
+
+/// A stretch of a track that is played over and over, seamlessly
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoopRegion {
+    /// Index of the track in the queue
+    pub track: usize,
+    pub start: Duration,
+    /// Where playback goes back to the start; past the end of the track,
+    /// such as `Duration::MAX`, loops from its end
+    pub end: Duration,
+}
+
+/// Counts playback down to a stop, fading out over the last stretch
+#[derive(Clone, Copy, Debug)]
+struct SleepTimer {
+    /// Frames left to play
+    remaining: u64,
+    /// Frames the fade-out lasts
+    fade: u64,
+}
+
+/// Playback health counters, mostly useful to diagnose stuttering
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlayerStats {
+    /// Number of output callbacks the decoder could not keep up with
+    pub underruns: u64,
+    /// Total silence inserted because of underruns
+    pub underrun_time: Duration,
+    /// Decoded audio waiting to be played
+    pub buffered: Duration,
+    /// Most audio the read-ahead buffer can hold
+    pub buffer_capacity: Duration,
+}
+
+/// How the player turns tracks into its output
+#[derive(Clone, Debug, Default)]
+pub struct PlaybackOptions {
+    /// How long consecutive tracks overlap; zero means gapless playback
+    pub crossfade: Duration,
+    /// Explicit channel routing applied to every track instead of the standard mix
+    pub routing: Option<ChannelRouting>,
+    pub replaygain: ReplayGainConfig,
+    /// Shortens silent gaps within tracks, for speech such as lectures and
+    /// audiobooks
+    pub skip_silence: Option<SkipSilence>,
+    /// How long the volume takes to rise from silence when playback starts
+    pub fade_in: Duration,
+    /// Reads every track as headerless PCM in this format instead of
+    /// probing it
+    pub raw: Option<RawFormat>,
+}
+
+/// Output side of the player: drains the ring filled by the decoder thread.
+/// Reading runs in the device's real-time callback, so it never waits for
+/// the decoder to catch up and only `try_lock`s the current track, queue and
+/// sleep timer. The ring's own lock is still taken, but the decoder only
+/// holds it to copy samples in, never while decoding.
+struct PlayerSource {
+    shared: Arc<PlayerShared>,
+    /// Where tracks start, handed over by the decoder thread
+    boundaries: Receiver<Boundary>,
+    /// The next boundary, once received and until it's reached
+    next: Option<Boundary>,
+    /// A boundary reached but not yet published, while its locks were taken
+    reached: Option<Boundary>,
+    sample_rate: u32,
+    channels: usize,
+    /// Feeds a device: never wait for the decoder, play silence instead
+    realtime: bool,
+    /// Shared by the player's sessions, so an armed timer outlives skips
+    sleep: Arc<Mutex<Option<SleepTimer>>>,
+}
+
+impl PlayerSource {
+    /// Counts `frames` as played and moves on to the track they reached. The
+    /// current track is only published when its locks are free, else by a
+    /// later call.
+    fn advance(&mut self, frames: u64) {
+        let played = self.shared.played.fetch_add(frames, Ordering::Relaxed) + frames;
+        loop {
+            if self.next.is_none() {
+                self.next = self.boundaries.try_recv().ok();
+            }
+            match self.next {
+                Some(boundary) if boundary.frame <= played => {
+                    self.reached = self.next.take();
+                }
+                _ => break,
+            }
+        }
+        let Some(boundary) = self.reached else {
+            return;
+        };
+        let (Ok(mut current), Ok(mut queue)) =
+            (self.shared.current.try_lock(), self.shared.queue.try_lock())
+        else {
+            return;
+        };
+        *current = Some(boundary);
+        queue.set_current(boundary.track);
+        self.reached = None;
+    }
+
+    /// Fades `out` as the sleep timer runs down and returns how many of its
+    /// samples to play; once the timer runs out the session is cancelled.
+    /// While the timer is being set, it's left alone until the next call.
+    fn apply_sleep(&self, out: &mut [f32]) -> usize {
+        let Ok(mut sleep) = self.sleep.try_lock() else {
+            return out.len();
+        };
+        let Some(timer) = sleep.as_mut() else {
+            return out.len();
+        };
+        let frames = (out.len() / self.channels) as u64;
+        let played = frames.min(timer.remaining);
+        for (i, frame) in out
+            .chunks_exact_mut(self.channels)
+            .take(played as usize)
+            .enumerate()
+        {
+            let left = timer.remaining - i as u64;
+            if left < timer.fade {
+                let gain = left as f32 / timer.fade as f32;
+                frame.iter_mut().for_each(|sample| *sample *= gain);
+            }
+        }
+        timer.remaining -= played;
+        if timer.remaining == 0 {
+            *sleep = None;
+            self.shared.slept.store(true, Ordering::Relaxed);
+            self.shared.ring.cancel();
+        }
+        played as usize * self.channels
+    }
+}
+
+impl AudioSource for PlayerSource {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> usize {
+        if !self.realtime {
+            let count = self.shared.ring.pop_wait(out);
+            let count = self.apply_sleep(&mut out[..count]);
+            self.advance((count / self.channels) as u64);
+            return count;
+        }
+
+        let mut count = self.shared.ring.pop(out);
+        if count < out.len() && !self.shared.ring.is_drained() {
+            // The decoder fell behind, play silence rather than stopping
+            out[count..].fill(0.0);
+            self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+            self.shared.underrun_frames.fetch_add(
+                ((out.len() - count) / self.channels) as u64,
+                Ordering::Relaxed,
+            );
+            count = out.len();
+        }
+        let count = self.apply_sleep(&mut out[..count]);
+        self.advance((count / self.channels) as u64);
+        count
+    }
+}
+
+/// Pushes decoded samples into the ring, holding back the end of each track
+/// so it can be overlapped with the start of the next one
+struct Feeder {
+    shared: Arc<PlayerShared>,
+    /// Where tracks start, for the output side to tell when it reaches them
+    boundaries: Sender<Boundary>,
+    channels: usize,
+    sample_rate: u32,
+    /// Frames pushed into the ring so far
+    queued: u64,
+    /// Length of the crossfade in samples, 0 for plain gapless playback
+    fade_len: usize,
+    /// Most recent samples, not yet pushed, that may become a fade-out
+    tail: VecDeque<f32>,
+    /// The outgoing track's tail while it is being faded under the new track
+    fading: Vec<f32>,
+    fade_pos: usize,
+    /// Explicit channel routing applied to every track instead of the standard mix
+    routing: Option<ChannelRouting>,
+    replaygain: ReplayGainConfig,
+    skip_silence: Option<SkipSilence>,
+    /// Length of the fade-in at the start of the session in frames
+    fade_in: u64,
+    raw: Option<RawFormat>,
+    /// The first track, when whoever started playback opened it already
+    first: Option<AudioFile>,
+}
+
+impl Feeder {
+    fn new(
+        shared: Arc<PlayerShared>,
+        boundaries: Sender<Boundary>,
+        channels: usize,
+        sample_rate: u32,
+        options: PlaybackOptions,
+    ) -> Self {
+        let fade_frames = (options.crossfade.as_secs_f64() * sample_rate as f64) as usize;
+        Feeder {
+            shared,
+            boundaries,
+            channels,
+            sample_rate,
+            queued: 0,
+            fade_len: fade_frames * channels,
+            tail: VecDeque::new(),
+            fading: Vec::new(),
+            fade_pos: 0,
+            routing: options.routing,
+            replaygain: options.replaygain,
+            skip_silence: options.skip_silence,
+            fade_in: (options.fade_in.as_secs_f64() * sample_rate as f64) as u64,
+            raw: options.raw,
+            first: None,
+        }
+    }
+
+    /// Channel mapping from a track's layout to the output's
+    fn mapper(&self, channels: usize) -> ChannelMapper {
+        match &self.routing {
+            Some(routing) => ChannelMapper::from_routing(routing, channels)
+                .then(&ChannelMapper::identity(self.channels)),
+            None => ChannelMapper::new(channels, self.channels),
+        }
+    }
+
+    /// Marks that the track at `index` goes on at `offset` into it, once
+    /// everything pushed or held back so far was played
+    fn mark_loop(&mut self, index: usize, offset: Duration) {
+        let _ = self.boundaries.send(Boundary {
+            frame: self.queued + (self.tail.len() / self.channels) as u64,
+            track: index,
+            offset,
+        });
+    }
+
+    /// Marks the start of a new track, overlapping it with the held back
+    /// tail; `offset` is how far into the track it starts
+    fn start_track(&mut self, index: usize, offset: Duration) -> bool {
+        if !self.flush_fading() {
+            return false;
+        }
+        self.fading = self.tail.drain(..).collect();
+        self.fade_pos = 0;
+        let _ = self.boundaries.send(Boundary {
+            frame: self.queued,
+            track: index,
+            offset,
+        });
+        true
+    }
+
+    fn push(&mut self, samples: &[f32]) -> bool {
+        let mut out = Vec::with_capacity(samples.len());
+        let fade_frames = (self.fading.len() / self.channels).max(1) as f32;
+        for &sample in samples {
+            let mut value = sample;
+            if let Some(outgoing) = self.fading.get(self.fade_pos) {
+                // Equal-power curves keep the perceived loudness constant
+                let t = (self.fade_pos / self.channels) as f32 / fade_frames;
+                let angle = t * std::f32::consts::FRAC_PI_2;
+                value = outgoing * angle.cos() + sample * angle.sin();
+                self.fade_pos += 1;
+            }
+            self.hold(value, &mut out);
+        }
+        self.write(&out)
+    }
+
+    fn hold(&mut self, value: f32, out: &mut Vec<f32>) {
+        if self.fade_len == 0 {
+            out.push(value);
+            return;
+        }
+        self.tail.push_back(value);
+        if self.tail.len() > self.fade_len {
+            out.extend(self.tail.pop_front());
+        }
+    }
+
+    /// Fades out whatever is left of the outgoing tail when the new track was too short to cover it
+    fn flush_fading(&mut self) -> bool {
+        let fading = std::mem::take(&mut self.fading);
+        let fade_frames = (fading.len() / self.channels).max(1) as f32;
+        let mut out = Vec::new();
+        for (pos, outgoing) in fading.iter().enumerate().skip(self.fade_pos) {
+            let t = (pos / self.channels) as f32 / fade_frames;
+            self.hold(outgoing * (t * std::f32::consts::FRAC_PI_2).cos(), &mut out);
+        }
+        self.fade_pos = 0;
+        self.write(&out)
+    }
+
+    fn write(&mut self, samples: &[f32]) -> bool {
+        let faded;
+        let samples = if self.queued < self.fade_in {
+            // A squared curve, so the loudness rises at an even pace
+            let mut fading = samples.to_vec();
+            for (i, frame) in fading.chunks_exact_mut(self.channels).enumerate() {
+                let t = ((self.queued + i as u64) as f32 / self.fade_in as f32).min(1.0);
+                frame.iter_mut().for_each(|sample| *sample *= t * t);
+            }
+            faded = fading;
+            &faded[..]
+        } else {
+            samples
+        };
+        if !self.shared.ring.push(samples) {
+            return false;
+        }
+        self.queued += (samples.len() / self.channels) as u64;
+        true
+    }
+
+    /// Pushes everything held back once the last track has been decoded
+    fn finish(mut self) {
+        if !self.flush_fading() {
+            return;
+        }
+        let tail: Vec<f32> = self.tail.drain(..).collect();
+        if self.write(&tail) {
+            self.shared.ring.finish();
+        }
+    }
+}
+
+/// Decodes the queue back to back into the ring starting at `start`, so the
+/// next track is already buffered when the current one ends and no silence is
+/// inserted. The first track starts `offset` into it.
+fn decode_queue(mut feeder: Feeder, start: usize, mut offset: Duration) {
+    let shared = feeder.shared.clone();
+    let mut index = Some(start);
+    // Tracks in a row that produced no audio; stops repeat modes from spinning forever
+    let mut failures = 0;
+
+    while let Some(current) = index {
+        let Some(path) = shared.queue.lock().unwrap().get(current).cloned() else {
+            break;
+        };
+
+        match decode_track(&mut feeder, current, &path, std::mem::take(&mut offset)) {
+            Some(true) => failures = 0,
+            Some(false) => failures += 1,
+            None => return,
+        }
+        let queue = shared.queue.lock().unwrap();
+        if failures > queue.len() {
+            break;
+        }
+        index = queue.index_following(current);
+    }
+
+    feeder.finish();
+}
+
+/// Feeds one track into the ring from `offset` on. Returns whether any
+/// audio was produced, or `None` if playback was stopped.
+fn decode_track(
+    feeder: &mut Feeder,
+    index: usize,
+    path: &PathBuf,
+    offset: Duration,
+) -> Option<bool> {
+    let shared = feeder.shared.clone();
+    let opened = match (feeder.first.take(), feeder.raw) {
+        (Some(file), _) => Ok(FileSource::new(file)),
+        (None, Some(format)) => AudioFile::open_raw(path, format).map(FileSource::new),
+        (None, None) => FileSource::open(path),
+    };
+    let mut source = match opened {
+        Ok(source) => source,
+        Err(e) => {
+            shared.errors.lock().unwrap().push((path.clone(), e));
+            return Some(false);
+        }
+    };
+    let mut offset = offset;
+    if !offset.is_zero() {
+        let frame = (offset.as_secs_f64() * source.sample_rate() as f64) as u64;
+        if let Err(e) = source.seek(frame) {
+            // Play it from the start rather than not at all
+            shared.errors.lock().unwrap().push((path.clone(), e));
+            offset = Duration::ZERO;
+        }
+    }
+    if !feeder.start_track(index, offset) {
+        return None;
+    }
+
+    let src_channels = source.channels().max(1);
+    let mut resampler = if source.sample_rate() != feeder.sample_rate {
+        match Resampler::new(source.sample_rate(), feeder.sample_rate, src_channels) {
+            Ok(resampler) => Some(resampler),
+            Err(e) => {
+                shared.errors.lock().unwrap().push((path.clone(), e));
+                return Some(false);
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut skipper = feeder
+        .skip_silence
+        .map(|settings| SilenceSkipper::new(source.sample_rate(), src_channels, settings));
+    let mut gain = Gain::new(feeder.replaygain.factor(source.file()));
+    let mapper = feeder.mapper(src_channels);
+    let mut mapped = Vec::new();
+    let mut kept = Vec::new();
+    let mut buffer = vec![0.0f32; DECODE_CHUNK - DECODE_CHUNK % src_channels];
+    let mut produced = false;
+    let rate = source.sample_rate() as f64;
+    // Next frame of the file to be read
+    let mut frame = (offset.as_secs_f64() * rate) as u64;
+    // Set after looping back until anything is read, as a loop that plays
+    // nothing, such as one starting past the end, would spin forever
+    let mut just_looped = false;
+    // Frame and bytes decoded at the start of the bitrate measurement
+    let mut measured = (frame, source.file().bytes_decoded());
+    // Samples read past the end of the loop, left in `buffer` to be played on
+    // when going back to its start fails
+    let mut overshoot = 0..0;
+    loop {
+        let mut read = match overshoot.is_empty() {
+            true => source.read(&mut buffer),
+            false => {
+                let count = overshoot.len();
+                buffer.copy_within(std::mem::take(&mut overshoot), 0);
+                count
+            }
+        };
+        let region = *shared.looping.lock().unwrap();
+        let mut wrap = None;
+        if let Some(region) = region.filter(|region| region.track == index) {
+            // Rounded, so loop points given in frames come out exactly
+            let end = (region.end.as_secs_f64() * rate).round() as u64;
+            let left = (end.saturating_sub(frame) as usize).saturating_mul(src_channels);
+            if read == 0 || read >= left {
+                overshoot = read.min(left)..read;
+                read = read.min(left);
+                wrap = Some(region.start);
+            }
+        }
+        frame += (read / src_channels) as u64;
+        just_looped &= read == 0;
+        let frames = frame.saturating_sub(measured.0);
+        if frames as f64 >= rate * BITRATE_WINDOW.as_secs_f64() {
+            let bytes = source.file().bytes_decoded() - measured.1;
+            let bitrate = (bytes as f64 * 8.0 * rate / frames as f64).round() as u64;
+            *shared.bitrate.lock().unwrap() = Some((index, bitrate));
+            measured = (frame, source.file().bytes_decoded());
+        }
+        if let Some(title) = source.file().stream_title() {
+            *shared.stream_title.lock().unwrap() = Some((index, title));
+        }
+        let finished = read == 0 && wrap.is_none();
+
+        let decoded = match skipper.as_mut() {
+            Some(skipper) => {
+                kept.clear();
+                if finished {
+                    skipper.flush(&mut kept);
+                } else {
+                    skipper.process(&buffer[..read], &mut kept);
+                }
+                &mut kept[..]
+            }
+            None => &mut buffer[..read],
+        };
+        gain.process(decoded);
+        let resampled;
+        let samples = match resampler.as_mut() {
+            Some(resampler) if finished => {
+                resampled = [resampler.process(decoded), resampler.flush()].concat();
+                &resampled[..]
+            }
+            Some(resampler) => {
+                resampled = resampler.process(decoded);
+                &resampled[..]
+            }
+            None => &*decoded,
+        };
+
+        if !samples.is_empty() {
+            produced = true;
+            mapper.map_into(samples, &mut mapped);
+            if !feeder.push(&mapped) {
+                return None;
+            }
+        }
+        if let Some(start) = wrap {
+            let start_frame = (start.as_secs_f64() * rate).round() as u64;
+            let seeked = match just_looped {
+                true => Err("nothing to loop".to_string()),
+                false => source.seek(start_frame),
+            };
+            if let Err(e) = seeked {
+                shared.errors.lock().unwrap().push((path.clone(), e));
+                *shared.looping.lock().unwrap() = None;
+                continue;
+            }
+            frame = start_frame;
+            measured = (frame, source.file().bytes_decoded());
+            just_looped = true;
+            overshoot = 0..0;
+            feeder.mark_loop(index, start);
+            continue;
+        }
+        if finished {
+            break;
+        }
+    }
+
+    if let Some(e) = source.error() {
+        shared
+            .errors
+            .lock()
+            .unwrap()
+            .push((path.clone(), e.to_string()));
+    }
+    Some(produced)
+}
+
+/// Starts decoding the queue at `index`, `offset` into that track, into a
+/// fresh ring
+fn start_session(
+    queue: SharedQueue,
+    (index, offset): (usize, Duration),
+    (sample_rate, channels): (u32, usize),
+    options: PlaybackOptions,
+    looping: Option<LoopRegion>,
+    first: Option<AudioFile>,
+) -> (Arc<PlayerShared>, Receiver<Boundary>, JoinHandle<()>) {
+    let shared = Arc::new(PlayerShared::new(
+        queue,
+        sample_rate as usize * channels * READ_AHEAD_SECS,
+        looping,
+    ));
+    let (sender, boundaries) = channel();
+    let mut feeder = Feeder::new(shared.clone(), sender, channels, sample_rate, options);
+    feeder.first = first;
+    let decoder = std::thread::spawn(move || decode_queue(feeder, index, offset));
+    (shared, boundaries, decoder)
+}
+
+/// A whole queue as one continuous source, decoded exactly the way the player
+/// plays it (gapless or crossfaded, resampled and channel-mapped). Used to
+/// render playback to a file instead of a device.
+pub struct QueueSource {
+    source: PlayerSource,
+    decoder: Option<JoinHandle<()>>,
+}
+
+impl QueueSource {
+    /// Reads `queue` from the track at `start`, `offset` into it. `first` is
+    /// that track if it was opened already, which stdin has to be.
+    pub fn new(
+        queue: SharedQueue,
+        (start, offset): (usize, Duration),
+        sample_rate: u32,
+        channels: usize,
+        options: PlaybackOptions,
+        first: Option<AudioFile>,
+    ) -> Self {
+        queue.lock().unwrap().set_current(start);
+        let (shared, boundaries, decoder) = start_session(
+            queue,
+            (start, offset),
+            (sample_rate, channels),
+            options,
+            None,
+            first,
+        );
+        QueueSource {
+            source: PlayerSource {
+                shared,
+                boundaries,
+                next: None,
+                reached: None,
+                sample_rate,
+                channels,
+                realtime: false,
+                sleep: Arc::default(),
+            },
+            decoder: Some(decoder),
+        }
+    }
+
+    /// Index of the track being read
+    pub fn current_track(&self) -> Option<usize> {
+        let current = *self.source.shared.current.lock().unwrap();
+        current.map(|boundary| boundary.track)
+    }
+
+    /// Takes the errors of tracks that failed to open or decode
+    pub fn take_errors(&self) -> Vec<(PathBuf, String)> {
+        std::mem::take(&mut *self.source.shared.errors.lock().unwrap())
+    }
+
+    /// Ends the source after `after` of audio, fading out over the last
+    /// `fade`, like [`AudioPlayer::set_sleep_timer`]
+    pub fn set_sleep_timer(&self, after: Duration, fade: Duration) {
+        let rate = self.source.sample_rate as f64;
+        let remaining = (after.as_secs_f64() * rate) as u64;
+        let fade = ((fade.as_secs_f64() * rate) as u64).min(remaining);
+        *self.source.sleep.lock().unwrap() = Some(SleepTimer { remaining, fade });
+    }
+}
+
+impl AudioSource for QueueSource {
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.source.channels
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> usize {
+        self.source.read(out)
+    }
+}
+
+impl Drop for QueueSource {
+    fn drop(&mut self) {
+        self.source.shared.ring.cancel();
+        if let Some(decoder) = self.decoder.take() {
+            let _ = decoder.join();
+        }
+    }
+}
+
+struct Session {
+    shared: Arc<PlayerShared>,
+    voice: SourceHandle,
+    decoder: Option<JoinHandle<()>>,
+}
+
+/// Plays a playback queue on the mixer, either gapless or crossfaded
+pub struct AudioPlayer {
+    mixer: Mixer,
+    queue: SharedQueue,
+    session: Option<Session>,
+    options: PlaybackOptions,
+    sleep: Arc<Mutex<Option<SleepTimer>>>,
+    looping: Option<LoopRegion>,
+    /// Point A of an A-B loop being marked, waiting for point B
+    loop_start: Option<(usize, Duration)>,
+    /// Linear gain of the playback voice, kept across sessions
+    volume: f32,
+}
+
+impl AudioPlayer {
+    /// Opens the default output device
+    pub fn new() -> Result<Self, String> {
+        AudioPlayer::with_config(&PlayerConfig::default())
+    }
+
+    /// Opens the default device of a specific audio host, e.g. JACK or ASIO
+    pub fn with_host(host: cpal::HostId) -> Result<Self, String> {
+        AudioPlayer::with_config(&PlayerConfig::with_host(host))
+    }
+
+    /// Opens the output device chosen by `config`
+    pub fn with_config(config: &PlayerConfig) -> Result<Self, String> {
+        Ok(AudioPlayer {
+            mixer: Mixer::with_config(config)?,
+            queue: PlaybackQueue::new().into_shared(),
+            session: None,
+            options: PlaybackOptions::default(),
+            sleep: Arc::default(),
+            looping: None,
+            loop_start: None,
+            volume: 1.0,
+        })
+    }
+
+    /// Sets how long consecutive tracks overlap; zero means gapless playback.
+    /// Takes effect the next time `play` is called.
+    pub fn set_crossfade(&mut self, crossfade: Duration) {
+        self.options.crossfade = crossfade;
+    }
+
+    pub fn crossfade(&self) -> Duration {
+        self.options.crossfade
+    }
+
+    /// Routes track channels explicitly instead of using the standard up/downmix.
+    /// Takes effect the next time `play` is called.
+    pub fn set_channel_routing(&mut self, routing: Option<ChannelRouting>) {
+        self.options.routing = routing;
+    }
+
+    pub fn channel_routing(&self) -> Option<&ChannelRouting> {
+        self.options.routing.as_ref()
+    }
+
+    /// Levels tracks by their ReplayGain tags. Takes effect the next time
+    /// `play` is called.
+    pub fn set_replaygain(&mut self, replaygain: ReplayGainConfig) {
+        self.options.replaygain = replaygain;
+    }
+
+    pub fn replaygain(&self) -> ReplayGainConfig {
+        self.options.replaygain
+    }
+
+    /// Shortens silent gaps within tracks, or plays them in full with
+    /// `None`. Takes effect the next time `play` is called.
+    pub fn set_skip_silence(&mut self, skip_silence: Option<SkipSilence>) {
+        self.options.skip_silence = skip_silence;
+    }
+
+    pub fn skip_silence(&self) -> Option<SkipSilence> {
+        self.options.skip_silence
+    }
+
+    /// Raises the volume from silence over `fade_in` each time playback
+    /// starts. Takes effect the next time `play` is called.
+    pub fn set_fade_in(&mut self, fade_in: Duration) {
+        self.options.fade_in = fade_in;
+    }
+
+    pub fn fade_in(&self) -> Duration {
+        self.options.fade_in
+    }
+
+    /// Reads every track as headerless PCM in `format`, or probes tracks for
+    /// their format with `None`. Takes effect the next time `play` is called.
+    pub fn set_raw_format(&mut self, format: Option<RawFormat>) {
+        self.options.raw = format;
+    }
+
+    pub fn raw_format(&self) -> Option<RawFormat> {
+        self.options.raw
+    }
+
+    /// The mixer the player outputs to, for layering other sounds on top
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    /// Reopens the output if the device was unplugged; see [`Mixer::poll_device`]
+    pub fn poll_device(&mut self) -> Option<DeviceEvent> {
+        self.mixer.poll_device()
+    }
+
+    /// The queue the player reads from; edits apply to tracks not yet decoded
+    pub fn queue(&self) -> SharedQueue {
+        self.queue.clone()
+    }
+
+    /// Replaces the queue with the given tracks and starts from the first one
+    pub fn play(&mut self, tracks: Vec<PathBuf>) {
+        *self.queue.lock().unwrap() = PlaybackQueue::from_paths(tracks);
+        self.play_from(0);
+    }
+
+    /// Starts playing the queue at `index`, replacing whatever is playing
+    pub fn play_from(&mut self, index: usize) {
+        self.play_from_at(index, Duration::ZERO);
+    }
+
+    /// Starts playing the queue at `index`, `position` into that track
+    pub fn play_from_at(&mut self, index: usize, position: Duration) {
+        self.play_opened_at(index, position, None);
+    }
+
+    /// Starts playing the queue at `index`, `position` into that track, which
+    /// is `first` when it was opened already. Stdin can only be opened once,
+    /// so a file opened on it has to be handed over here.
+    pub fn play_opened_at(&mut self, index: usize, position: Duration, first: Option<AudioFile>) {
+        self.stop();
+        if self.queue.lock().unwrap().set_current(index).is_none() {
+            return;
+        }
+
+        let (shared, boundaries, decoder) = start_session(
+            self.queue.clone(),
+            (index, position),
+            (self.mixer.sample_rate(), self.mixer.channels()),
+            self.options.clone(),
+            self.looping,
+            first,
+        );
+        let voice = self.mixer.play(PlayerSource {
+            shared: shared.clone(),
+            boundaries,
+            next: None,
+            reached: None,
+            sample_rate: self.mixer.sample_rate(),
+            channels: self.mixer.channels(),
+            realtime: true,
+            sleep: self.sleep.clone(),
+        });
+        voice.set_gain(self.volume);
+
+        self.session = Some(Session {
+            shared,
+            voice,
+            decoder: Some(decoder),
+        });
+    }
+
+    /// Skips to the next track in the queue. Returns false at the end of the queue.
+    pub fn next_track(&mut self) -> bool {
+        let next = {
+            let queue = self.queue.lock().unwrap();
+            queue
+                .current_index()
+                .and_then(|index| queue.index_after(index))
+        };
+        match next {
+            Some(index) => {
+                self.play_from(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Goes back to the previous track in the queue. Returns false at the start of the queue.
+    pub fn previous_track(&mut self) -> bool {
+        let previous = {
+            let queue = self.queue.lock().unwrap();
+            queue
+                .current_index()
+                .and_then(|index| queue.index_before(index))
+        };
+        match previous {
+            Some(index) => {
+                self.play_from(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Jumps to the start of the next chapter of the current track. Returns
+    /// false in its last chapter or when it has none.
+    pub fn next_chapter(&mut self) -> bool {
+        let Some((index, chapters)) = self.current_chapters() else {
+            return false;
+        };
+        let position = self.position();
+        match chapters.iter().find(|chapter| chapter.start > position) {
+            Some(chapter) => {
+                self.play_from_at(index, chapter.start);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Goes back to the start of the current chapter, or to the previous one
+    /// early on in it. Returns false when the current track has no chapters.
+    pub fn previous_chapter(&mut self) -> bool {
+        let Some((index, chapters)) = self.current_chapters() else {
+            return false;
+        };
+        let position = self.position();
+        let current = chapters
+            .iter()
+            .rposition(|chapter| chapter.start <= position)
+            .unwrap_or(0);
+        let target = match position.saturating_sub(chapters[current].start) < CHAPTER_RESTART {
+            true => current.saturating_sub(1),
+            false => current,
+        };
+        self.play_from_at(index, chapters[target].start);
+        true
+    }
+
+    /// The current track's index and its chapters, if it has any
+    fn current_chapters(&self) -> Option<(usize, Vec<Chapter>)> {
+        let index = self.current_track()?;
+        let path = self.current_path().filter(|path| !stdin::is_stdin(path))?;
+        let chapters = AudioFile::open(&path).ok()?.chapters;
+        (!chapters.is_empty()).then_some((index, chapters))
+    }
+
+    /// Continues the current track at `position`. Returns false when
+    /// nothing is playing.
+    pub fn seek(&mut self, position: Duration) -> bool {
+        match self.current_track() {
+            Some(index) => {
+                self.play_from_at(index, position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pauses the output; the position is kept
+    pub fn pause(&mut self) -> Result<(), String> {
+        self.mixer.pause()
+    }
+
+    pub fn resume(&mut self) -> Result<(), String> {
+        self.mixer.resume()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mixer.is_paused()
+    }
+
+    /// Sets the linear playback volume (1.0 = unity). Has no effect on
+    /// bit-perfect output.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.max(0.0);
+        if let Some(session) = &self.session {
+            session.voice.set_gain(self.volume);
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Stops playback and shuts the decoder thread down
+    pub fn stop(&mut self) {
+        if let Some(mut session) = self.session.take() {
+            session.voice.stop();
+            session.shared.ring.cancel();
+            if let Some(decoder) = session.decoder.take() {
+                let _ = decoder.join();
+            }
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.session
+            .as_ref()
+            .map(|session| session.voice.is_playing())
+            .unwrap_or(false)
+    }
+
+    /// Index of the track currently audible
+    pub fn current_track(&self) -> Option<usize> {
+        let session = self.session.as_ref()?;
+        let current = *session.shared.current.lock().unwrap();
+        current.map(|boundary| boundary.track)
+    }
+
+    pub fn current_path(&self) -> Option<PathBuf> {
+        let index = self.current_track()?;
+        self.queue.lock().unwrap().get(index).cloned()
+    }
+
+    /// Playback position within the current track
+    pub fn position(&self) -> Duration {
+        let Some(session) = self.session.as_ref() else {
+            return Duration::ZERO;
+        };
+        let played = session.shared.played.load(Ordering::Relaxed);
+        let (start, offset) = session
+            .shared
+            .current
+            .lock()
+            .unwrap()
+            .map(|boundary| (boundary.frame, boundary.offset))
+            .unwrap_or((played, Duration::ZERO));
+        let frames = played.saturating_sub(start);
+        offset + Duration::from_secs_f64(frames as f64 / self.mixer.sample_rate() as f64)
+    }
+
+    /// Plays a stretch of a track over and over, or stops looping with
+    /// `None`. Looping the track being played jumps back to the start of
+    /// the loop if playback is outside of it or about to leave it.
+    pub fn set_loop(&mut self, region: Option<LoopRegion>) {
+        self.looping = region;
+        self.loop_start = None;
+        let Some(session) = self.session.as_ref() else {
+            return;
+        };
+        *session.shared.looping.lock().unwrap() = region;
+        let Some(region) = region.filter(|region| Some(region.track) == self.current_track())
+        else {
+            return;
+        };
+        // The decoder may already have read past the end of the loop
+        let position = self.position();
+        if position < region.start || position + self.stats().buffered >= region.end {
+            self.play_from_at(region.track, region.start);
+        }
+    }
+
+    pub fn loop_region(&self) -> Option<LoopRegion> {
+        self.looping
+    }
+
+    /// Marks point A of an A-B loop at the current position
+    pub fn set_loop_start(&mut self) {
+        self.loop_start = self.current_track().map(|track| (track, self.position()));
+    }
+
+    /// Marks point B of an A-B loop at the current position and starts
+    /// looping back to point A. Returns false when point A wasn't marked in
+    /// the current track, or isn't before point B.
+    pub fn set_loop_end(&mut self) -> bool {
+        let (Some((track, start)), Some(current)) = (self.loop_start, self.current_track()) else {
+            return false;
+        };
+        let end = self.position();
+        if track != current || end <= start {
+            return false;
+        }
+        self.set_loop(Some(LoopRegion { track, start, end }));
+        true
+    }
+
+    /// Arms the sleep timer to stop playback after `after` more of it,
+    /// fading out over the last `fade`. Replaces a timer already armed.
+    /// Time spent paused doesn't count.
+    pub fn set_sleep_timer(&self, after: Duration, fade: Duration) {
+        let rate = self.mixer.sample_rate() as f64;
+        let remaining = (after.as_secs_f64() * rate) as u64;
+        let fade = ((fade.as_secs_f64() * rate) as u64).min(remaining);
+        *self.sleep.lock().unwrap() = Some(SleepTimer { remaining, fade });
+    }
+
+    /// Disarms the sleep timer, bringing the volume back if it was fading out
+    pub fn cancel_sleep_timer(&self) {
+        *self.sleep.lock().unwrap() = None;
+    }
+
+    /// Playback left before the sleep timer stops it, if it is armed
+    pub fn sleep_timer(&self) -> Option<Duration> {
+        let timer = (*self.sleep.lock().unwrap())?;
+        Some(Duration::from_secs_f64(
+            timer.remaining as f64 / self.mixer.sample_rate() as f64,
+        ))
+    }
+
+    /// Whether the sleep timer ran out and stopped playback
+    pub fn slept(&self) -> bool {
+        self.session
+            .as_ref()
+            .is_some_and(|session| session.shared.slept.load(Ordering::Relaxed))
+    }
+
+    /// Bits per second of the current track's file data over the last
+    /// second decoded, which runs a little ahead of what is heard. `None`
+    /// early on in a track.
+    pub fn bitrate(&self) -> Option<u64> {
+        let session = self.session.as_ref()?;
+        let (track, bitrate) = (*session.shared.bitrate.lock().unwrap())?;
+        (Some(track) == self.current_track()).then_some(bitrate)
+    }
+
+    /// What the current track, a radio stream, last said it's playing. Like
+    /// the bitrate, it runs a little ahead of what is heard.
+    pub fn stream_title(&self) -> Option<String> {
+        let session = self.session.as_ref()?;
+        let announced = session.shared.stream_title.lock().unwrap();
+        let (track, title) = announced.as_ref()?;
+        (Some(*track) == self.current_track()).then(|| title.clone())
+    }
+
+    /// Underrun counters and buffer fill of the current session
+    pub fn stats(&self) -> PlayerStats {
+        let Some(session) = self.session.as_ref() else {
+            return PlayerStats::default();
+        };
+        let shared = &session.shared;
+        let samples_per_sec = (self.mixer.sample_rate() as usize * self.mixer.channels()) as f64;
+        let frames_per_sec = self.mixer.sample_rate() as f64;
+        PlayerStats {
+            underruns: shared.underruns.load(Ordering::Relaxed),
+            underrun_time: Duration::from_secs_f64(
+                shared.underrun_frames.load(Ordering::Relaxed) as f64 / frames_per_sec,
+            ),
+            buffered: Duration::from_secs_f64(shared.ring.len() as f64 / samples_per_sec),
+            buffer_capacity: Duration::from_secs_f64(
+                shared.ring.capacity() as f64 / samples_per_sec,
+            ),
+        }
+    }
+
+    /// Takes the errors of tracks that failed to open or decode
+    pub fn take_errors(&self) -> Vec<(PathBuf, String)> {
+        match self.session.as_ref() {
+            Some(session) => std::mem::take(&mut *session.shared.errors.lock().unwrap()),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Drop for AudioPlayer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player_source() -> (PlayerSource, Sender<Boundary>) {
+        let queue = PlaybackQueue::from_paths(["a.flac", "b.flac"].map(PathBuf::from));
+        let shared = PlayerShared::new(Arc::new(Mutex::new(queue)), 64, None);
+        let (sender, boundaries) = channel();
+        let source = PlayerSource {
+            shared: Arc::new(shared),
+            boundaries,
+            next: None,
+            reached: None,
+            sample_rate: 48000,
+            channels: 2,
+            realtime: true,
+            sleep: Arc::default(),
+        };
+        (source, sender)
+    }
+
+    fn boundary(frame: u64, track: usize) -> Boundary {
+        Boundary {
+            frame,
+            track,
+            offset: Duration::ZERO,
+        }
+    }
+
+    fn current(source: &PlayerSource) -> Option<(usize, u64)> {
+        let current = *source.shared.current.lock().unwrap();
+        current.map(|boundary| (boundary.track, boundary.frame))
+    }
+
+    #[test]
+    fn tracks_start_once_their_boundary_is_played() {
+        let (mut source, boundaries) = player_source();
+        boundaries.send(boundary(0, 0)).unwrap();
+        boundaries.send(boundary(100, 1)).unwrap();
+        source.advance(10);
+        assert_eq!(current(&source), Some((0, 0)));
+        source.advance(89);
+        assert_eq!(current(&source), Some((0, 0)));
+        source.advance(1);
+        assert_eq!(current(&source), Some((1, 100)));
+        assert_eq!(source.shared.queue.lock().unwrap().current_index(), Some(1));
+    }
+
+    #[test]
+    fn boundaries_passed_in_one_read_end_on_the_last() {
+        let (mut source, boundaries) = player_source();
+        boundaries.send(boundary(0, 0)).unwrap();
+        boundaries.send(boundary(5, 1)).unwrap();
+        boundaries.send(boundary(50, 0)).unwrap();
+        source.advance(20);
+        assert_eq!(current(&source), Some((1, 5)));
+    }
+
+    #[test]
+    fn a_held_lock_delays_the_track_change_without_waiting() {
+        let (mut source, boundaries) = player_source();
+        boundaries.send(boundary(0, 1)).unwrap();
+        let queue = source.shared.queue.clone();
+        let held = queue.lock().unwrap();
+        source.advance(10);
+        assert_eq!(current(&source), None);
+        drop(held);
+        source.advance(10);
+        assert_eq!(current(&source), Some((1, 0)));
+        assert_eq!(source.shared.played.load(Ordering::Relaxed), 20);
+    }
+
+    #[test]
+    fn a_held_sleep_timer_is_left_for_the_next_read() {
+        let (source, _) = player_source();
+        *source.sleep.lock().unwrap() = Some(SleepTimer {
+            remaining: 2,
+            fade: 0,
+        });
+        let mut out = [1.0f32; 8];
+        let held = source.sleep.lock().unwrap();
+        assert_eq!(source.apply_sleep(&mut out), 8);
+        drop(held);
+        assert_eq!(source.apply_sleep(&mut out), 4);
+        assert!(source.shared.slept.load(Ordering::Relaxed));
+    }
+}
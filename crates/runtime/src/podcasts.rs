@@ -0,0 +1,263 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use mogbox_io::podcast::{Episode, Feed};
+
+use crate::resume::write_state_file;
+
+/// A podcast subscribed to, with the episodes its feed had when last fetched
+#[derive(Clone, Debug, PartialEq)]
+pub struct Podcast {
+    /// URL of the RSS or Atom feed
+    pub url: String,
+    pub title: String,
+    /// Newest first
+    pub episodes: Vec<PodcastEpisode>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PodcastEpisode {
+    pub episode: Episode,
+    /// Set once the episode was played to the end
+    pub listened: bool,
+}
+
+impl Podcast {
+    /// Finds an episode by its number in [`episodes`](Self::episodes),
+    /// counting from 1, or by a part of its title, ignoring case
+    pub fn find(&self, name: &str) -> Option<&PodcastEpisode> {
+        let by_number = || {
+            let number: usize = name.trim().parse().ok()?;
+            self.episodes.get(number.checked_sub(1)?)
+        };
+        let search = name.trim().to_lowercase();
+        by_number().or_else(|| {
+            self.episodes
+                .iter()
+                .find(|episode| episode.episode.title.to_lowercase().contains(&search))
+        })
+    }
+
+    /// The newest episode not listened to yet
+    pub fn next_episode(&self) -> Option<&PodcastEpisode> {
+        self.episodes.iter().find(|episode| !episode.listened)
+    }
+
+    /// How many episodes weren't listened to yet
+    pub fn unlistened(&self) -> usize {
+        self.episodes
+            .iter()
+            .filter(|episode| !episode.listened)
+            .count()
+    }
+
+    /// Takes the episodes of `feed`, keeping what was listened to. Returns how
+    /// many episodes are new.
+    fn update(&mut self, feed: &Feed) -> usize {
+        if let Some(title) = &feed.title {
+            self.title = title.clone();
+        }
+        let mut new = 0;
+        let mut episodes: Vec<PodcastEpisode> = feed
+            .episodes
+            .iter()
+            .map(|episode| {
+                let known = self
+                    .episodes
+                    .iter()
+                    .find(|known| known.episode.guid == episode.guid);
+                new += known.is_none() as usize;
+                PodcastEpisode {
+                    episode: episode.clone(),
+                    listened: known.is_some_and(|known| known.listened),
+                }
+            })
+            .collect();
+        // Stable, so undated episodes keep their place in the feed
+        episodes.sort_by_key(|episode| std::cmp::Reverse(episode.episode.published));
+        self.episodes = episodes;
+        new
+    }
+}
+
+/// The podcasts subscribed to, saved across runs
+#[derive(Clone, Debug, Default)]
+pub struct PodcastStore {
+    path: PathBuf,
+    podcasts: Vec<Podcast>,
+}
+
+impl PodcastStore {
+    /// Loads the store kept at `path`; a file that doesn't exist yet is an
+    /// empty store
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+        // A `podcast<TAB>feed URL<TAB>title` line per podcast, followed by an
+        // `episode<TAB>listened<TAB>published<TAB>duration<TAB>guid<TAB>URL<TAB>title`
+        // line per episode, times in seconds and missing values empty
+        let mut podcasts: Vec<Podcast> = Vec::new();
+        for line in text.lines() {
+            let mut fields = line.split('\t');
+            match fields.next() {
+                Some("podcast") => {
+                    let (Some(url), Some(title)) = (fields.next(), fields.next()) else {
+                        continue;
+                    };
+                    podcasts.push(Podcast {
+                        url: url.to_string(),
+                        title: title.to_string(),
+                        episodes: Vec::new(),
+                    });
+                }
+                Some("episode") => {
+                    let (Some(podcast), Some(episode)) =
+                        (podcasts.last_mut(), parse_episode(fields))
+                    else {
+                        continue;
+                    };
+                    podcast.episodes.push(episode);
+                }
+                _ => {}
+            }
+        }
+        Ok(PodcastStore {
+            path: path.to_path_buf(),
+            podcasts,
+        })
+    }
+
+    /// Writes the store back through a temporary file
+    pub fn save(&self) -> Result<(), String> {
+        let mut text = String::new();
+        for podcast in &self.podcasts {
+            text.push_str(&line(&["podcast", &podcast.url, &podcast.title]));
+            for PodcastEpisode { episode, listened } in &podcast.episodes {
+                let published = episode.published.map(|time| {
+                    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                    seconds.as_secs().to_string()
+                });
+                let duration = episode
+                    .duration
+                    .map(|duration| format!("{:.3}", duration.as_secs_f64()));
+                text.push_str(&line(&[
+                    "episode",
+                    if *listened { "1" } else { "0" },
+                    &published.unwrap_or_default(),
+                    &duration.unwrap_or_default(),
+                    &episode.guid,
+                    &episode.url,
+                    &episode.title,
+                ]));
+            }
+        }
+        write_state_file(&self.path, &text)
+    }
+
+    pub fn podcasts(&self) -> &[Podcast] {
+        &self.podcasts
+    }
+
+    /// Finds a podcast by its number in [`podcasts`](Self::podcasts),
+    /// counting from 1, by its feed URL, or by a part of its title, ignoring
+    /// case
+    pub fn find(&self, name: &str) -> Option<usize> {
+        let name = name.trim();
+        let by_number = name
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| number.checked_sub(1))
+            .filter(|&index| index < self.podcasts.len());
+        let search = name.to_lowercase();
+        by_number
+            .or_else(|| self.podcasts.iter().position(|podcast| podcast.url == name))
+            .or_else(|| {
+                self.podcasts
+                    .iter()
+                    .position(|podcast| podcast.title.to_lowercase().contains(&search))
+            })
+    }
+
+    /// Subscribes to the podcast whose feed at `url` reads `feed`. Fails if
+    /// it's subscribed to already.
+    pub fn subscribe(&mut self, url: &str, feed: &Feed) -> Result<&Podcast, String> {
+        if let Some(podcast) = self.podcasts.iter().find(|podcast| podcast.url == url) {
+            return Err(format!("already subscribed to {}", podcast.title));
+        }
+        let mut podcast = Podcast {
+            url: url.to_string(),
+            title: url.to_string(),
+            episodes: Vec::new(),
+        };
+        podcast.update(feed);
+        self.podcasts.push(podcast);
+        Ok(&self.podcasts[self.podcasts.len() - 1])
+    }
+
+    /// Takes what the feed of podcast `index` reads now. Returns how many
+    /// episodes are new.
+    pub fn update(&mut self, index: usize, feed: &Feed) -> usize {
+        self.podcasts[index].update(feed)
+    }
+
+    /// Marks the episode `guid` of the podcast with feed `url` as listened
+    /// to or not. Returns whether there is such an episode.
+    pub fn set_listened(&mut self, url: &str, guid: &str, listened: bool) -> bool {
+        let episode = self
+            .podcasts
+            .iter_mut()
+            .filter(|podcast| podcast.url == url)
+            .flat_map(|podcast| podcast.episodes.iter_mut())
+            .find(|episode| episode.episode.guid == guid);
+        match episode {
+            Some(episode) => {
+                episode.listened = listened;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn parse_episode<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<PodcastEpisode> {
+    let listened = fields.next()? == "1";
+    let published = fields
+        .next()?
+        .parse()
+        .ok()
+        .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds));
+    let duration = fields
+        .next()?
+        .parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok());
+    let (guid, url, title) = (fields.next()?, fields.next()?, fields.next()?);
+    Some(PodcastEpisode {
+        episode: Episode {
+            guid: guid.to_string(),
+            title: title.to_string(),
+            url: url.to_string(),
+            published,
+            duration,
+        },
+        listened,
+    })
+}
+
+/// A line of tab-separated fields, with tabs and line breaks in them turned
+/// to spaces
+fn line(fields: &[&str]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            field
+                .chars()
+                .map(|c| if c.is_control() { ' ' } else { c })
+                .collect()
+        })
+        .collect();
+    format!("{}\n", fields.join("\t"))
+}
@@ -0,0 +1,414 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use mogbox_io::playlist::Playlist;
+
+/// A queue shared between the player's decoder thread and the control side
+pub type SharedQueue = Arc<Mutex<PlaybackQueue>>;
+
+/// What happens when the end of a track or of the queue is reached
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    /// Play the current track over and over
+    Track,
+    /// Start again from the beginning after the last track
+    Queue,
+}
+
+impl FromStr for RepeatMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(RepeatMode::Off),
+            "track" => Ok(RepeatMode::Track),
+            "queue" => Ok(RepeatMode::Queue),
+            _ => Err(format!(
+                "invalid repeat mode: {} (expected track, queue or off)",
+                s
+            )),
+        }
+    }
+}
+
+/// An ordered list of tracks with a cursor on the one currently playing.
+/// Tracks are visited in `order`, which is either the list order or a
+/// shuffled permutation that stays fixed until shuffle is toggled, so going
+/// back always returns to what actually played.
+#[derive(Clone, Debug, Default)]
+pub struct PlaybackQueue {
+    items: Vec<PathBuf>,
+    current: Option<usize>,
+    order: Vec<usize>,
+    shuffle: bool,
+    repeat: RepeatMode,
+}
+
+impl PlaybackQueue {
+    pub fn new() -> Self {
+        PlaybackQueue::default()
+    }
+
+    pub fn from_paths<I: IntoIterator<Item = PathBuf>>(paths: I) -> Self {
+        let items: Vec<PathBuf> = paths.into_iter().collect();
+        PlaybackQueue {
+            order: (0..items.len()).collect(),
+            items,
+            ..PlaybackQueue::default()
+        }
+    }
+
+    /// Appends a track at the end of the queue
+    pub fn enqueue(&mut self, path: PathBuf) {
+        self.items.push(path);
+        let index = self.items.len() - 1;
+        if self.shuffle {
+            // Slot new tracks somewhere after the current one so they are still ahead
+            let start = self.order_position().map(|pos| pos + 1).unwrap_or(0);
+            let slot = start + (random() as usize) % (self.order.len() - start + 1);
+            self.order.insert(slot, index);
+        } else {
+            self.order.push(index);
+        }
+    }
+
+    pub fn enqueue_all<I: IntoIterator<Item = PathBuf>>(&mut self, paths: I) {
+        for path in paths {
+            self.enqueue(path);
+        }
+    }
+
+    /// Removes the track at `index`, keeping the cursor on the same track where possible
+    pub fn remove(&mut self, index: usize) -> Option<PathBuf> {
+        if index >= self.items.len() {
+            return None;
+        }
+        let removed = self.items.remove(index);
+        self.order.retain(|&i| i != index);
+        for i in self.order.iter_mut() {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+        self.current = match self.current {
+            Some(current) if index < current => Some(current - 1),
+            Some(current) if current >= self.items.len() => None,
+            current => current,
+        };
+        Some(removed)
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.order.clear();
+        self.current = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn items(&self) -> &[PathBuf] {
+        &self.items
+    }
+
+    pub fn get(&self, index: usize) -> Option<&PathBuf> {
+        self.items.get(index)
+    }
+
+    /// Track indexes in the order they will be played
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    pub fn current_index(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn current(&self) -> Option<&PathBuf> {
+        self.items.get(self.current?)
+    }
+
+    /// Moves the cursor to `index`
+    pub fn set_current(&mut self, index: usize) -> Option<&PathBuf> {
+        if index < self.items.len() {
+            self.current = Some(index);
+        }
+        self.current()
+    }
+
+    pub fn shuffle(&self) -> bool {
+        self.shuffle
+    }
+
+    /// Turns shuffle on or off. When enabling, the tracks up to and including
+    /// the current one keep their order, so going back still returns to what
+    /// played, and only the ones after it are drawn in a new random order.
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+        if !shuffle {
+            self.order = (0..self.items.len()).collect();
+            return;
+        }
+
+        // Fisher-Yates over what hasn't played yet
+        let start = self.order_position().map(|pos| pos + 1).unwrap_or(0);
+        for i in (start + 1..self.order.len()).rev() {
+            let j = start + (random() as usize) % (i - start + 1);
+            self.order.swap(i, j);
+        }
+    }
+
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    /// Index of the first track in play order
+    pub fn first(&self) -> Option<usize> {
+        self.order.first().copied()
+    }
+
+    /// Index of the track that follows `index` in play order when skipping,
+    /// wrapping around when repeat is on
+    pub fn index_after(&self, index: usize) -> Option<usize> {
+        let pos = self.order.iter().position(|&i| i == index)?;
+        match self.order.get(pos + 1) {
+            Some(&next) => Some(next),
+            None if self.repeat != RepeatMode::Off => self.first(),
+            None => None,
+        }
+    }
+
+    /// Index of the track that precedes `index` in play order,
+    /// wrapping around when repeat is on
+    pub fn index_before(&self, index: usize) -> Option<usize> {
+        let pos = self.order.iter().position(|&i| i == index)?;
+        match pos.checked_sub(1) {
+            Some(prev) => self.order.get(prev).copied(),
+            None if self.repeat != RepeatMode::Off => self.order.last().copied(),
+            None => None,
+        }
+    }
+
+    /// Index of the track to play automatically once `index` ends
+    pub fn index_following(&self, index: usize) -> Option<usize> {
+        match self.repeat {
+            RepeatMode::Track if index < self.items.len() => Some(index),
+            _ => self.index_after(index),
+        }
+    }
+
+    /// Advances the cursor; starts at the first track if nothing is current yet.
+    /// Returns `None` and leaves the cursor alone at the end of the queue.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&PathBuf> {
+        let next = match self.current {
+            Some(current) => self.index_after(current)?,
+            None => self.first()?,
+        };
+        self.current = Some(next);
+        self.current()
+    }
+
+    /// Moves the cursor back one track
+    pub fn previous(&mut self) -> Option<&PathBuf> {
+        let previous = self.index_before(self.current?)?;
+        self.current = Some(previous);
+        self.current()
+    }
+
+    /// Loads a playlist, restoring the current index if it was saved with one
+    /// that is still inside the list
+    pub fn load(path: &PathBuf) -> Result<Self, String> {
+        let playlist = Playlist::read(path)?;
+        let mut queue = PlaybackQueue::from_paths(playlist.entries);
+        queue.current = playlist.current.filter(|&index| index < queue.len());
+        Ok(queue)
+    }
+
+    /// Saves the queue as a playlist (format chosen by extension), including the current index
+    pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+        Playlist::new(self.items.clone(), self.current).write(path)
+    }
+
+    pub fn into_shared(self) -> SharedQueue {
+        Arc::new(Mutex::new(self))
+    }
+
+    fn order_position(&self) -> Option<usize> {
+        let current = self.current?;
+        self.order.iter().position(|&i| i == current)
+    }
+}
+
+/// Cheap xorshift generator; shuffling does not need anything stronger
+fn random() -> u64 {
+    use std::cell::Cell;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0x2545_f491_4f6c_dd1d)
+                | 1,
+        );
+    }
+
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(len: usize) -> PlaybackQueue {
+        PlaybackQueue::from_paths((0..len).map(|i| PathBuf::from(format!("{}.flac", i))))
+    }
+
+    fn played(queue: &mut PlaybackQueue, count: usize) -> Vec<usize> {
+        (0..count)
+            .map_while(|_| {
+                queue.next()?;
+                queue.current_index()
+            })
+            .collect()
+    }
+
+    fn is_permutation(order: &[usize], len: usize) -> bool {
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        sorted == (0..len).collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn plays_in_list_order() {
+        let mut queue = queue(3);
+        assert_eq!(played(&mut queue, 5), [0, 1, 2]);
+        assert_eq!(queue.current_index(), Some(2));
+        assert_eq!(queue.previous(), Some(&PathBuf::from("1.flac")));
+        assert_eq!(queue.previous(), Some(&PathBuf::from("0.flac")));
+        assert_eq!(queue.previous(), None);
+        assert_eq!(queue.current_index(), Some(0));
+    }
+
+    #[test]
+    fn repeats() {
+        let mut queue = queue(3);
+        queue.set_repeat(RepeatMode::Queue);
+        assert_eq!(played(&mut queue, 5), [0, 1, 2, 0, 1]);
+        assert_eq!(queue.index_before(0), Some(2));
+
+        queue.set_repeat(RepeatMode::Track);
+        assert_eq!(queue.index_following(1), Some(1));
+        // Skipping still moves on, wrapping as with repeat queue
+        assert_eq!(queue.index_after(2), Some(0));
+        assert_eq!(queue.index_following(3), None);
+
+        queue.set_repeat(RepeatMode::Off);
+        assert_eq!(queue.index_following(2), None);
+        assert_eq!(queue.index_before(0), None);
+    }
+
+    #[test]
+    fn shuffles_every_track_once() {
+        let mut queue = queue(20);
+        queue.set_shuffle(true);
+        assert!(is_permutation(queue.order(), 20));
+        let order = queue.order().to_vec();
+        assert_eq!(played(&mut queue, 25), order);
+
+        queue.set_repeat(RepeatMode::Queue);
+        queue.next();
+        assert_eq!(queue.current_index(), Some(order[0]));
+    }
+
+    #[test]
+    fn shuffle_keeps_what_already_played() {
+        let mut queue = queue(20);
+        played(&mut queue, 3);
+        queue.set_shuffle(true);
+        assert_eq!(queue.order()[..3], [0, 1, 2]);
+        assert!(is_permutation(queue.order(), 20));
+
+        let ahead = queue.order()[3..].to_vec();
+        assert_eq!(played(&mut queue, 20), ahead);
+        for _ in 0..17 {
+            queue.previous();
+        }
+        assert_eq!(queue.current_index(), Some(2));
+        assert_eq!(queue.previous(), Some(&PathBuf::from("1.flac")));
+
+        queue.set_shuffle(false);
+        assert_eq!(queue.order(), (0..20).collect::<Vec<_>>());
+        assert_eq!(queue.current_index(), Some(1));
+    }
+
+    #[test]
+    fn enqueues_ahead_when_shuffled() {
+        let mut queue = queue(5);
+        played(&mut queue, 3);
+        queue.set_shuffle(true);
+        queue.enqueue_all((5..10).map(|i| PathBuf::from(format!("{}.flac", i))));
+        assert!(is_permutation(queue.order(), 10));
+        assert_eq!(queue.order()[..3], [0, 1, 2]);
+    }
+
+    #[test]
+    fn remove_keeps_the_cursor() {
+        let mut queue = queue(4);
+        queue.set_current(2);
+        assert_eq!(queue.remove(0), Some(PathBuf::from("0.flac")));
+        assert_eq!(queue.current(), Some(&PathBuf::from("2.flac")));
+        assert_eq!(queue.order(), [0, 1, 2]);
+        assert_eq!(queue.remove(2), Some(PathBuf::from("3.flac")));
+        assert_eq!(queue.current_index(), Some(1));
+        assert_eq!(queue.remove(1), Some(PathBuf::from("2.flac")));
+        assert_eq!(queue.current_index(), None);
+        assert_eq!(queue.remove(5), None);
+    }
+
+    #[test]
+    fn load_drops_a_stale_current_index() {
+        let dir = std::env::temp_dir().join(format!("mogbox-queue-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.m3u8");
+
+        std::fs::write(&path, "#MOGBOX-CURRENT:1\na.flac\nb.flac\n").unwrap();
+        let queue = PlaybackQueue::load(&path).unwrap();
+        assert_eq!(queue.current(), Some(&dir.join("b.flac")));
+
+        std::fs::write(&path, "#MOGBOX-CURRENT:7\na.flac\nb.flac\n").unwrap();
+        let queue = PlaybackQueue::load(&path).unwrap();
+        assert_eq!(queue.current_index(), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_repeat_modes() {
+        assert_eq!("Track".parse(), Ok(RepeatMode::Track));
+        assert_eq!("queue".parse(), Ok(RepeatMode::Queue));
+        assert_eq!("off".parse(), Ok(RepeatMode::Off));
+        assert!("all".parse::<RepeatMode>().is_err());
+    }
+}
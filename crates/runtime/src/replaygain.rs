@@ -0,0 +1,92 @@
+use std::str::FromStr;
+
+use mogbox_engine::db_to_linear;
+use mogbox_io::AudioFile;
+use symphonia::core::meta::StandardTagKey;
+
+/// Which ReplayGain value tracks are leveled with during playback
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    /// Every track at the same loudness
+    Track,
+    /// Albums at the same loudness, keeping the level differences between
+    /// their tracks. Tracks without album values use their track gain.
+    Album,
+}
+
+impl FromStr for ReplayGainMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(ReplayGainMode::Off),
+            "track" => Ok(ReplayGainMode::Track),
+            "album" => Ok(ReplayGainMode::Album),
+            _ => Err(format!(
+                "invalid ReplayGain mode: {} (expected track, album or off)",
+                s
+            )),
+        }
+    }
+}
+
+/// How playback applies the `REPLAYGAIN_*` tags of the files it plays
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayGainConfig {
+    pub mode: ReplayGainMode,
+    /// Extra gain in dB on top of the tagged one, since the ReplayGain
+    /// reference level is quieter than most modern masters
+    pub preamp: f32,
+}
+
+impl ReplayGainConfig {
+    /// Linear gain for a file from its tags and the pre-amp, lowered where
+    /// the tagged peak would otherwise clip. 1.0 when off or untagged.
+    pub fn factor(&self, file: &AudioFile) -> f32 {
+        let (gain, peak) = match self.mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => (
+                StandardTagKey::ReplayGainTrackGain,
+                StandardTagKey::ReplayGainTrackPeak,
+            ),
+            ReplayGainMode::Album => (
+                StandardTagKey::ReplayGainAlbumGain,
+                StandardTagKey::ReplayGainAlbumPeak,
+            ),
+        };
+        let tagged = |key| file.tag(key).as_deref().and_then(parse_value);
+        let (gain, peak) = match tagged(gain) {
+            Some(gain) => (gain, tagged(peak)),
+            None if self.mode == ReplayGainMode::Album => {
+                match tagged(StandardTagKey::ReplayGainTrackGain) {
+                    Some(gain) => (gain, tagged(StandardTagKey::ReplayGainTrackPeak)),
+                    None => return 1.0,
+                }
+            }
+            None => return 1.0,
+        };
+
+        let factor = db_to_linear(gain + self.preamp);
+        match peak {
+            Some(peak) if peak > 0.0 => factor.min(1.0 / peak),
+            _ => factor,
+        }
+    }
+}
+
+/// Parses a tag value such as `-6.52 dB` or `0.988525`
+fn parse_value(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let number = value
+        .strip_suffix("dB")
+        .or(value.strip_suffix("db"))
+        .or(value.strip_suffix("DB"))
+        .unwrap_or(value);
+    number
+        .trim()
+        .parse()
+        .ok()
+        .filter(|value: &f32| value.is_finite())
+}
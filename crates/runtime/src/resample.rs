@@ -0,0 +1,70 @@
+pub use mogbox_engine::Resampler;
+
+use crate::source::AudioSource;
+
+/// Input frames read from the source per resampler call
+const CHUNK_FRAMES: usize = 1024;
+
+/// Converts another source to a different sample rate on the fly
+pub struct Resampled<S: AudioSource> {
+    source: S,
+    resampler: Resampler,
+    sample_rate: u32,
+    input: Vec<f32>,
+    output: Vec<f32>,
+    position: usize,
+    finished: bool,
+}
+
+impl<S: AudioSource> Resampled<S> {
+    pub fn new(source: S, sample_rate: u32) -> Result<Self, String> {
+        let resampler = Resampler::new(source.sample_rate(), sample_rate, source.channels())?;
+        let channels = source.channels().max(1);
+        Ok(Resampled {
+            source,
+            resampler,
+            sample_rate,
+            input: vec![0.0; CHUNK_FRAMES * channels],
+            output: Vec::new(),
+            position: 0,
+            finished: false,
+        })
+    }
+}
+
+impl<S: AudioSource> AudioSource for Resampled<S> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.resampler.channels()
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> usize {
+        let mut written = 0;
+        while written < out.len() {
+            if self.position >= self.output.len() {
+                if self.finished {
+                    break;
+                }
+                let read = self.source.read(&mut self.input);
+                self.output = if read == 0 {
+                    self.finished = true;
+                    self.resampler.flush()
+                } else {
+                    self.resampler.process(&self.input[..read])
+                };
+                self.position = 0;
+                continue;
+            }
+
+            let count = (out.len() - written).min(self.output.len() - self.position);
+            out[written..written + count]
+                .copy_from_slice(&self.output[self.position..self.position + count]);
+            written += count;
+            self.position += count;
+        }
+        written
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Bytes hashed from each end of a file to identify it
+const KEY_SAMPLE: u64 = 64 * 1024;
+
+/// Where files were left off, saved across runs so long recordings such as
+/// audiobooks can be picked up again. Files are known by a hash of their
+/// contents, so positions follow them when they are moved or renamed.
+#[derive(Clone, Debug, Default)]
+pub struct ResumeStore {
+    path: PathBuf,
+    /// Position and last known location of each file, by key
+    entries: BTreeMap<String, (Duration, PathBuf)>,
+}
+
+impl ResumeStore {
+    /// Loads the store kept at `path`; a file that doesn't exist yet is an
+    /// empty store
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+        // One `key<TAB>seconds<TAB>path` line per file
+        let entries = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let key = fields.next()?.to_string();
+                let seconds: f64 = fields.next()?.parse().ok()?;
+                let position = Duration::try_from_secs_f64(seconds).ok()?;
+                Some((
+                    key,
+                    (position, PathBuf::from(fields.next().unwrap_or_default())),
+                ))
+            })
+            .collect();
+        Ok(ResumeStore {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// Writes the store back through a temporary file, creating its
+    /// directory if needed
+    pub fn save(&self) -> Result<(), String> {
+        let text: String = self
+            .entries
+            .iter()
+            .map(|(key, (position, path))| {
+                format!(
+                    "{}\t{:.3}\t{}\n",
+                    key,
+                    position.as_secs_f64(),
+                    path.display()
+                )
+            })
+            .collect();
+        write_state_file(&self.path, &text)
+    }
+
+    /// Where the file with `key` was left off
+    pub fn position(&self, key: &str) -> Option<Duration> {
+        self.entries.get(key).map(|(position, _)| *position)
+    }
+
+    /// Remembers `position` in the file at `path`, whose key is `key`
+    pub fn set_position(&mut self, key: &str, path: &Path, position: Duration) {
+        self.entries
+            .insert(key.to_string(), (position, path.to_path_buf()));
+    }
+
+    /// Forgets the file with `key`, such as once it was played to the end
+    pub fn remove(&mut self, key: &str) {
+        self.entries.remove(key);
+    }
+}
+
+/// Replaces the file at `path` with `text` through a temporary file,
+/// creating its directory if needed
+pub(crate) fn write_state_file(path: &Path, text: &str) -> Result<(), String> {
+    let write_error = |e: std::io::Error| format!("failed to write {}: {}", path.display(), e);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(write_error)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, text)
+        .and_then(|()| std::fs::rename(&temp, path))
+        .map_err(write_error)
+}
+
+/// Identifies a file by its contents: a 64-bit FNV-1a hash of its size and
+/// its first and last 64 KiB, as 16 hex digits. Reading only the ends keeps
+/// this quick for hour-long files. A URL, which can't be read that cheaply,
+/// is known by a hash of the URL instead.
+pub fn file_key(path: &Path) -> Result<String, String> {
+    if mogbox_io::http::is_url(path) {
+        return Ok(fnv_hash(path.as_os_str().as_encoded_bytes()));
+    }
+    let read_error = |e: std::io::Error| format!("failed to read {}: {}", path.display(), e);
+    let mut file = File::open(path).map_err(read_error)?;
+    let size = file.metadata().map_err(read_error)?.len();
+
+    let mut data = size.to_le_bytes().to_vec();
+    file.by_ref()
+        .take(KEY_SAMPLE)
+        .read_to_end(&mut data)
+        .map_err(read_error)?;
+    if size > KEY_SAMPLE {
+        file.seek(SeekFrom::Start(
+            size.saturating_sub(KEY_SAMPLE).max(KEY_SAMPLE),
+        ))
+        .map_err(read_error)?;
+        file.read_to_end(&mut data).map_err(read_error)?;
+    }
+
+    Ok(fnv_hash(&data))
+}
+
+/// 64-bit FNV-1a hash of `data`, as 16 hex digits
+fn fnv_hash(data: &[u8]) -> String {
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+struct RingState {
+    samples: VecDeque<f32>,
+    finished: bool,
+    cancelled: bool,
+}
+
+/// A bounded sample queue between a decoder thread (producer) and the audio
+/// callback (consumer). The producer blocks while the ring is full; the
+/// consumer never waits for samples unless it asks to with `pop_wait`. Both
+/// sides share one mutex, which is only held while samples are copied.
+pub struct RingBuffer {
+    state: Mutex<RingState>,
+    space: Condvar,
+    data: Condvar,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer {
+            state: Mutex::new(RingState {
+                samples: VecDeque::with_capacity(capacity),
+                finished: false,
+                cancelled: false,
+            }),
+            space: Condvar::new(),
+            data: Condvar::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Pushes all of `samples`, waiting for space as needed.
+    /// Returns false if the ring was cancelled before everything was written.
+    pub fn push(&self, mut samples: &[f32]) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !samples.is_empty() {
+            while state.samples.len() >= self.capacity && !state.cancelled {
+                state = self.space.wait(state).unwrap();
+            }
+            if state.cancelled {
+                return false;
+            }
+            let count = (self.capacity - state.samples.len()).min(samples.len());
+            state.samples.extend(&samples[..count]);
+            samples = &samples[count..];
+            self.data.notify_one();
+        }
+        true
+    }
+
+    /// Pops up to `out.len()` samples and returns how many were copied
+    pub fn pop(&self, out: &mut [f32]) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let count = out.len().min(state.samples.len());
+        for (slot, sample) in out.iter_mut().zip(state.samples.drain(..count)) {
+            *slot = sample;
+        }
+        drop(state);
+        self.space.notify_one();
+        count
+    }
+
+    /// Pops exactly `out.len()` samples, waiting for the producer as needed.
+    /// Returns fewer only once the producer finished or was cancelled.
+    pub fn pop_wait(&self, out: &mut [f32]) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let mut written = 0;
+        while written < out.len() {
+            while state.samples.is_empty() && !state.finished && !state.cancelled {
+                state = self.data.wait(state).unwrap();
+            }
+            if state.samples.is_empty() {
+                break;
+            }
+            let count = (out.len() - written).min(state.samples.len());
+            for (slot, sample) in out[written..].iter_mut().zip(state.samples.drain(..count)) {
+                *slot = sample;
+            }
+            written += count;
+            self.space.notify_one();
+        }
+        written
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Marks that the producer will not push any more samples
+    pub fn finish(&self) {
+        self.state.lock().unwrap().finished = true;
+        self.data.notify_all();
+    }
+
+    /// True once the producer finished and every sample has been consumed
+    pub fn is_drained(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        (state.finished || state.cancelled) && state.samples.is_empty()
+    }
+
+    /// Wakes up and aborts a producer blocked in `push`, dropping buffered samples
+    pub fn cancel(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.cancelled = true;
+        state.samples.clear();
+        drop(state);
+        self.space.notify_all();
+        self.data.notify_all();
+    }
+}
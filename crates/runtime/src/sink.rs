@@ -0,0 +1,70 @@
+use std::path::Path;
+use std::time::Duration;
+
+use mogbox_encode::wav::WavEncoder;
+use mogbox_encode::Encoder;
+
+use crate::source::AudioSource;
+
+/// Samples pulled from a source per write
+const RENDER_CHUNK: usize = 4096;
+
+/// Renders audio to a PCM WAV file instead of an output device, e.g. a
+/// `Processed` source to bounce a DSP chain or a `QueueSource` to capture playback
+pub struct WavSink {
+    encoder: Box<WavEncoder>,
+    sample_rate: u32,
+    channels: usize,
+    frames: u64,
+}
+
+impl WavSink {
+    pub fn create(path: &Path, sample_rate: u32, channels: usize) -> Result<Self, String> {
+        Ok(WavSink {
+            encoder: Box::new(WavEncoder::create(path, sample_rate, channels)?),
+            sample_rate,
+            channels: channels.max(1),
+            frames: 0,
+        })
+    }
+
+    /// Writes interleaved samples in the sink's format
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.encoder.write(samples)?;
+        self.frames += (samples.len() / self.channels) as u64;
+        Ok(())
+    }
+
+    /// Reads `source` until it is exhausted and writes everything to the file.
+    /// The source must already be in the sink's sample rate and channel count.
+    pub fn render<S: AudioSource>(&mut self, source: &mut S) -> Result<(), String> {
+        if source.sample_rate() != self.sample_rate || source.channels() != self.channels {
+            return Err(format!(
+                "source format {} Hz/{} ch does not match the sink's {} Hz/{} ch",
+                source.sample_rate(),
+                source.channels(),
+                self.sample_rate,
+                self.channels
+            ));
+        }
+
+        let mut buffer = vec![0.0f32; RENDER_CHUNK - RENDER_CHUNK % self.channels];
+        loop {
+            let read = source.read(&mut buffer);
+            if read == 0 {
+                return Ok(());
+            }
+            self.write(&buffer[..read])?;
+        }
+    }
+
+    /// Length of the audio written so far
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / self.sample_rate as f64)
+    }
+
+    /// Finalizes the WAV header; the file is incomplete until this is called
+    pub fn finish(self) -> Result<(), String> {
+        self.encoder.finish()
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use mogbox_io::AudioFile;
+
+use crate::mixer::{Mixer, SourceHandle};
+use crate::source::AudioSource;
+
+/// A short sound decoded once into memory. Cloning is cheap and every call to
+/// `play` starts an independent, overlapping instance on the mixer.
+#[derive(Clone)]
+pub struct SoundHandle {
+    samples: Arc<[f32]>,
+    sample_rate: u32,
+    channels: usize,
+}
+
+/// Per-instance settings for a triggered sound
+#[derive(Clone, Copy, Debug)]
+pub struct PlayParams {
+    /// Linear volume (1.0 = unity)
+    pub volume: f32,
+    /// Playback rate, 2.0 plays an octave up and twice as fast
+    pub pitch: f32,
+    /// Stereo position from -1.0 to 1.0
+    pub pan: f32,
+}
+
+impl Default for PlayParams {
+    fn default() -> Self {
+        PlayParams {
+            volume: 1.0,
+            pitch: 1.0,
+            pan: 0.0,
+        }
+    }
+}
+
+impl SoundHandle {
+    /// Decodes the whole file into memory
+    pub fn load(path: &std::path::PathBuf) -> Result<Self, String> {
+        let mut file = AudioFile::open(path)?;
+        let samples = file.read_all()?;
+        Ok(SoundHandle::from_samples(
+            samples,
+            file.sample_rate,
+            file.channels as usize,
+        ))
+    }
+
+    pub fn from_samples(samples: Vec<f32>, sample_rate: u32, channels: usize) -> Self {
+        SoundHandle {
+            samples: samples.into(),
+            sample_rate,
+            channels: channels.max(1),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn duration(&self) -> std::time::Duration {
+        let frames = self.samples.len() / self.channels;
+        std::time::Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+
+    /// Triggers the sound with default settings
+    pub fn play(&self, mixer: &Mixer) -> SourceHandle {
+        self.play_with(mixer, PlayParams::default())
+    }
+
+    /// Triggers the sound with the given volume, pitch and pan
+    pub fn play_with(&self, mixer: &Mixer, params: PlayParams) -> SourceHandle {
+        let handle = mixer.play(self.instance(params.pitch));
+        handle.set_gain(params.volume);
+        handle.set_pan(params.pan);
+        handle
+    }
+
+    /// Creates a standalone source for this sound, e.g. to run it through a chain first
+    pub fn instance(&self, pitch: f32) -> SoundInstance {
+        SoundInstance {
+            sound: self.clone(),
+            position: 0.0,
+            pitch: pitch.max(0.01) as f64,
+        }
+    }
+}
+
+/// One playback of a `SoundHandle`, resampled on the fly to apply pitch
+pub struct SoundInstance {
+    sound: SoundHandle,
+    position: f64,
+    pitch: f64,
+}
+
+impl AudioSource for SoundInstance {
+    fn sample_rate(&self) -> u32 {
+        self.sound.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.sound.channels
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> usize {
+        let channels = self.sound.channels;
+        let samples = &self.sound.samples;
+        let total_frames = samples.len() / channels;
+
+        let mut written = 0;
+        for frame in out.chunks_exact_mut(channels) {
+            let index = self.position as usize;
+            if index >= total_frames {
+                break;
+            }
+            // Linear interpolation between neighbouring frames
+            let frac = (self.position - index as f64) as f32;
+            let next = (index + 1).min(total_frames - 1);
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let a = samples[index * channels + ch];
+                let b = samples[next * channels + ch];
+                *sample = a + (b - a) * frac;
+            }
+            self.position += self.pitch;
+            written += channels;
+        }
+        written
+    }
+}
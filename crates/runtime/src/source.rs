@@ -0,0 +1,155 @@
+use mogbox_engine::Processor;
+use mogbox_io::AudioFile;
+
+use crate::chain::SharedChain;
+
+/// Anything that can produce interleaved f32 samples for playback
+pub trait AudioSource: Send {
+    fn sample_rate(&self) -> u32;
+
+    fn channels(&self) -> usize;
+
+    /// Fills `out` with interleaved samples and returns how many were written.
+    /// Returning 0 means the source is exhausted.
+    fn read(&mut self, out: &mut [f32]) -> usize;
+}
+
+impl AudioSource for Box<dyn AudioSource> {
+    fn sample_rate(&self) -> u32 {
+        (**self).sample_rate()
+    }
+
+    fn channels(&self) -> usize {
+        (**self).channels()
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> usize {
+        (**self).read(out)
+    }
+}
+
+/// Streams decoded samples from an opened audio file
+pub struct FileSource {
+    file: AudioFile,
+    pending: Vec<f32>,
+    position: usize,
+    finished: bool,
+    error: Option<String>,
+}
+
+impl FileSource {
+    pub fn new(file: AudioFile) -> Self {
+        FileSource {
+            file,
+            pending: Vec::new(),
+            position: 0,
+            finished: false,
+            error: None,
+        }
+    }
+
+    pub fn open(path: &std::path::PathBuf) -> Result<Self, String> {
+        Ok(FileSource::new(AudioFile::open(path)?))
+    }
+
+    pub fn file(&self) -> &AudioFile {
+        &self.file
+    }
+
+    /// Continues the stream at `frame` of the file
+    pub fn seek(&mut self, frame: u64) -> Result<(), String> {
+        self.file.seek(frame)?;
+        self.pending.clear();
+        self.position = 0;
+        self.finished = false;
+        Ok(())
+    }
+
+    /// The decode error that ended the stream early, if any
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+impl AudioSource for FileSource {
+    fn sample_rate(&self) -> u32 {
+        self.file.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.file.channels as usize
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> usize {
+        let mut written = 0;
+        while written < out.len() {
+            if self.position >= self.pending.len() {
+                if self.finished {
+                    break;
+                }
+                match self.file.next_samples() {
+                    Ok(Some(samples)) => {
+                        self.pending = samples;
+                        self.position = 0;
+                    }
+                    Ok(None) => self.finished = true,
+                    Err(e) => {
+                        self.error = Some(e);
+                        self.finished = true;
+                    }
+                }
+                continue;
+            }
+
+            let count = (out.len() - written).min(self.pending.len() - self.position);
+            out[written..written + count]
+                .copy_from_slice(&self.pending[self.position..self.position + count]);
+            written += count;
+            self.position += count;
+        }
+        written
+    }
+}
+
+/// A source whose output runs through a processing chain before reaching the sink
+pub struct Processed<S: AudioSource> {
+    source: S,
+    chain: SharedChain,
+}
+
+impl<S: AudioSource> Processed<S> {
+    pub fn new(source: S, chain: SharedChain) -> Self {
+        if let Ok(mut chain) = chain.lock() {
+            chain.prepare(source.sample_rate(), source.channels());
+        }
+        Processed { source, chain }
+    }
+
+    /// Handle to the chain, for editing it while the source is playing
+    pub fn chain(&self) -> SharedChain {
+        self.chain.clone()
+    }
+
+    /// The wrapped source
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+}
+
+impl<S: AudioSource> AudioSource for Processed<S> {
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn channels(&self) -> usize {
+        self.source.channels()
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> usize {
+        let count = self.source.read(out);
+        if let Ok(mut chain) = self.chain.lock() {
+            chain.process(&mut out[..count]);
+        }
+        count
+    }
+}
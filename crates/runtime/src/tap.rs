@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use mogbox_engine::Processor;
+
+struct TapState {
+    samples: VecDeque<f32>,
+    capacity: usize,
+    sample_rate: u32,
+    channels: usize,
+}
+
+/// A pass-through node that keeps the most recent samples it saw, for
+/// meters and visualizers to read from another thread. Clones share the
+/// same buffer, so one goes into a chain and another to the reader.
+#[derive(Clone)]
+pub struct Tap {
+    state: Arc<Mutex<TapState>>,
+}
+
+impl Tap {
+    /// A tap holding up to `frames` frames
+    pub fn new(frames: usize) -> Self {
+        Tap {
+            state: Arc::new(Mutex::new(TapState {
+                samples: VecDeque::with_capacity(frames * 2),
+                capacity: frames.max(1),
+                sample_rate: 44100,
+                channels: 2,
+            })),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.state.lock().unwrap().sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.state.lock().unwrap().channels
+    }
+
+    /// Up to `frames` of the latest interleaved frames, oldest first
+    pub fn latest(&self, frames: usize) -> Vec<f32> {
+        let state = self.state.lock().unwrap();
+        let count = (frames * state.channels).min(state.samples.len());
+        state
+            .samples
+            .range(state.samples.len() - count..)
+            .copied()
+            .collect()
+    }
+}
+
+impl Processor for Tap {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.sample_rate = sample_rate;
+        state.channels = channels.max(1);
+        state.samples.clear();
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        // Never make the audio thread wait on a reader; a missed buffer
+        // only shows up as a skipped display frame
+        let Ok(mut state) = self.state.try_lock() else {
+            return;
+        };
+        let limit = state.capacity * state.channels;
+        let skip = samples.len().saturating_sub(limit);
+        state.samples.extend(&samples[skip..]);
+        let excess = state.samples.len().saturating_sub(limit);
+        state.samples.drain(..excess);
+    }
+
+    fn reset(&mut self) {
+        self.state.lock().unwrap().samples.clear();
+    }
+}